@@ -23,6 +23,12 @@ pub struct BuildInfo {
     pub time: &'static str,
     /// The target triple of the platform.
     pub target_triple: &'static str,
+    /// The version of the Rust compiler that produced the build, as reported
+    /// by `rustc --version`.
+    pub rustc_version: &'static str,
+    /// The date, formatted as `YYYY-MM-DD`, on which the license of the build
+    /// changes, or the empty string if the license does not specify one.
+    pub license_change_date: &'static str,
 }
 
 /// Dummy build information.
@@ -34,6 +40,8 @@ pub const DUMMY_BUILD_INFO: BuildInfo = BuildInfo {
     sha: "",
     time: "",
     target_triple: "",
+    rustc_version: "",
+    license_change_date: "",
 };
 
 impl BuildInfo {
@@ -0,0 +1,148 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Tracking of whether statements are executing.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Tracks whether any statement is executing, across all sessions, and which
+/// statement each session is executing.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct StatementActivity {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The number of statements that are executing.
+    active: usize,
+    /// When the last statement to execute finished, or when tracking began,
+    /// if no statement has executed.
+    idle_since: Instant,
+    /// The statement that each session is executing, by connection ID.
+    running: HashMap<u32, Running>,
+    /// The ID to assign to the next statement to start.
+    next_id: u64,
+}
+
+/// A statement that a session is executing.
+#[derive(Debug)]
+struct Running {
+    /// Identifies the [`ActiveStatement`] that records the statement.
+    id: u64,
+    /// The statement's redacted SQL.
+    sql: String,
+    started: Instant,
+}
+
+impl StatementActivity {
+    /// Constructs a tracker in which no statement is executing.
+    pub fn new() -> StatementActivity {
+        StatementActivity {
+            inner: Arc::new(Mutex::new(Inner {
+                active: 0,
+                idle_since: Instant::now(),
+                running: HashMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Records that a statement is executing in the session with connection
+    /// ID `conn_id` until the returned guard is dropped.
+    ///
+    /// `sql` is the statement's SQL, which the caller must already have
+    /// redacted, or `None` if the statement is empty.
+    pub fn start(&self, conn_id: u32, sql: Option<String>) -> ActiveStatement {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.active += 1;
+        let id = inner.next_id;
+        inner.next_id += 1;
+        if let Some(sql) = sql {
+            let started = Instant::now();
+            inner.running.insert(conn_id, Running { id, sql, started });
+        }
+        ActiveStatement {
+            activity: self.clone(),
+            conn_id,
+            id,
+        }
+    }
+
+    /// Returns the statement that is executing in the session with
+    /// connection ID `conn_id`, if any.
+    pub fn current(&self, conn_id: u32) -> Option<CurrentStatement> {
+        let inner = self.inner.lock().expect("lock poisoned");
+        let running = inner.running.get(&conn_id)?;
+        Some(CurrentStatement {
+            sql: running.sql.clone(),
+            elapsed_ms: u64::try_from(running.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        })
+    }
+
+    /// Returns when the last statement to execute finished, or `None` if a
+    /// statement is executing.
+    pub fn idle_since(&self) -> Option<Instant> {
+        let inner = self.inner.lock().expect("lock poisoned");
+        if inner.active == 0 {
+            Some(inner.idle_since)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for StatementActivity {
+    fn default() -> StatementActivity {
+        StatementActivity::new()
+    }
+}
+
+/// Describes the statement that a session is executing, as reported in
+/// [`SessionInfo`](crate::SessionInfo).
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentStatement {
+    /// The statement's SQL, redacted as the slow query log redacts it.
+    pub sql: String,
+    /// How long the statement has been executing, in milliseconds.
+    pub elapsed_ms: u64,
+}
+
+/// Records that a statement is executing, until dropped.
+///
+/// Created by [`StatementActivity::start`].
+#[derive(Debug)]
+pub struct ActiveStatement {
+    activity: StatementActivity,
+    conn_id: u32,
+    id: u64,
+}
+
+impl Drop for ActiveStatement {
+    fn drop(&mut self) {
+        let mut inner = self.activity.inner.lock().expect("lock poisoned");
+        // A session's next statement may start before the guard of its
+        // previous statement is dropped, in which case the next statement
+        // must remain recorded.
+        if matches!(inner.running.get(&self.conn_id), Some(running) if running.id == self.id) {
+            inner.running.remove(&self.conn_id);
+        }
+        inner.active -= 1;
+        if inner.active == 0 {
+            inner.idle_since = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Authorization of statements at the server boundary.
+//!
+//! Frontends install an [`Authorizer`] on each session via
+//! [`SessionClient::set_authorizer`](crate::SessionClient::set_authorizer).
+//! Before a statement is submitted to the coordinator for execution, the
+//! authorizer is consulted with the session's user, the statement's coarse
+//! [`AuthzClass`], and the names of the objects that the statement refers to.
+//!
+//! Statements that affect only the session, like `SET`, `BEGIN`, or `FETCH`,
+//! are not subject to authorization. A cursor is authorized when it is
+//! declared, according to the statement that it runs.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use sql::ast::visit::{self, Visit};
+use sql::ast::{
+    CopyRelation, CopyStatement, DeclareStatement, ObjectType, Raw, RawName, Statement,
+    UnresolvedObjectName,
+};
+
+/// A coarse classification of statements for the purpose of authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AuthzClass {
+    /// Statements that read data or the catalog, like `SELECT`, `TAIL`,
+    /// `EXPLAIN`, or `SHOW`.
+    Read,
+    /// Statements that write data, like `INSERT`, `UPDATE`, `DELETE`, or
+    /// `COPY ... FROM`.
+    Write,
+    /// Statements that create, alter, or drop catalog objects.
+    Ddl,
+    /// Statements that manage roles.
+    Admin,
+}
+
+impl AuthzClass {
+    /// Classifies `stmt`, or returns `None` if `stmt` affects only the
+    /// session and so is not subject to authorization.
+    pub fn of(stmt: &Statement<Raw>) -> Option<AuthzClass> {
+        match stmt {
+            Statement::Select(_)
+            | Statement::Tail(_)
+            | Statement::Explain(_)
+            | Statement::ShowDatabases(_)
+            | Statement::ShowObjects(_)
+            | Statement::ShowIndexes(_)
+            | Statement::ShowColumns(_)
+            | Statement::ShowCreateView(_)
+            | Statement::ShowCreateSource(_)
+            | Statement::ShowCreateTable(_)
+            | Statement::ShowCreateSink(_)
+            | Statement::ShowCreateIndex(_) => Some(AuthzClass::Read),
+            Statement::Copy(CopyStatement { relation, .. }) => match relation {
+                CopyRelation::Select(_) | CopyRelation::Tail(_) => Some(AuthzClass::Read),
+                CopyRelation::Table { .. } => Some(AuthzClass::Write),
+            },
+            Statement::Insert(_) | Statement::Update(_) | Statement::Delete(_) => {
+                Some(AuthzClass::Write)
+            }
+            Statement::CreateDatabase(_)
+            | Statement::CreateSchema(_)
+            | Statement::CreateSource(_)
+            | Statement::CreateSink(_)
+            | Statement::CreateView(_)
+            | Statement::CreateViews(_)
+            | Statement::CreateTable(_)
+            | Statement::CreateIndex(_)
+            | Statement::CreateType(_)
+            | Statement::AlterObjectRename(_)
+            | Statement::AlterIndexOptions(_)
+            | Statement::DropDatabase(_) => Some(AuthzClass::Ddl),
+            Statement::DropObjects(stmt) => match stmt.object_type {
+                ObjectType::Role => Some(AuthzClass::Admin),
+                _ => Some(AuthzClass::Ddl),
+            },
+            Statement::CreateRole(_) => Some(AuthzClass::Admin),
+            Statement::Declare(DeclareStatement { stmt, .. }) => AuthzClass::of(stmt),
+            Statement::Discard(_)
+            | Statement::SetVariable(_)
+            | Statement::ShowVariable(_)
+            | Statement::StartTransaction(_)
+            | Statement::SetTransaction(_)
+            | Statement::Commit(_)
+            | Statement::Rollback(_)
+            | Statement::Fetch(_)
+            | Statement::Close(_) => None,
+        }
+    }
+
+    /// Returns the name of the class, as it appears in policy files.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthzClass::Read => "read",
+            AuthzClass::Write => "write",
+            AuthzClass::Ddl => "ddl",
+            AuthzClass::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for AuthzClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuthzClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AuthzClass, anyhow::Error> {
+        match s {
+            "read" => Ok(AuthzClass::Read),
+            "write" => Ok(AuthzClass::Write),
+            "ddl" => Ok(AuthzClass::Ddl),
+            "admin" => Ok(AuthzClass::Admin),
+            _ => Err(anyhow!(
+                "invalid statement class {}: expected read, write, ddl, or admin",
+                s
+            )),
+        }
+    }
+}
+
+/// A statement that is about to be submitted to the coordinator.
+#[derive(Debug)]
+pub struct AuthzRequest<'a> {
+    /// The user that owns the session that is executing the statement.
+    pub user: &'a str,
+    /// The class of the statement.
+    pub class: AuthzClass,
+    /// The names of the objects, like relations, types, and functions, that
+    /// the statement refers to, as written, in the order in which they first
+    /// appear.
+    pub objects: &'a [String],
+}
+
+/// The outcome of an authorization request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthzDecision {
+    /// The statement may execute.
+    Allow,
+    /// The statement may not execute, for the described reason.
+    Deny(String),
+}
+
+/// Decides which statements users may execute.
+pub trait Authorizer: fmt::Debug + Send + Sync {
+    /// Decides whether the statement described by `request` may execute.
+    fn authorize(&self, request: &AuthzRequest) -> AuthzDecision;
+}
+
+/// Decides whether `stmt` may execute on behalf of `user`.
+pub(crate) fn authorize(
+    authorizer: &dyn Authorizer,
+    user: &str,
+    stmt: &Statement<Raw>,
+) -> AuthzDecision {
+    let class = match AuthzClass::of(stmt) {
+        None => return AuthzDecision::Allow,
+        Some(class) => class,
+    };
+    let mut collector = ObjectCollector::default();
+    collector.visit_statement(stmt);
+    authorizer.authorize(&AuthzRequest {
+        user,
+        class,
+        objects: &collector.objects,
+    })
+}
+
+/// Collects the names of the objects that a statement refers to.
+#[derive(Default)]
+struct ObjectCollector {
+    objects: Vec<String>,
+}
+
+impl ObjectCollector {
+    fn collect(&mut self, name: &UnresolvedObjectName) {
+        let name = name.to_string();
+        if !self.objects.contains(&name) {
+            self.objects.push(name);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast, Raw> for ObjectCollector {
+    fn visit_object_name(&mut self, name: &'ast RawName) {
+        self.collect(name.name());
+    }
+
+    fn visit_unresolved_object_name(&mut self, name: &'ast UnresolvedObjectName) {
+        self.collect(name);
+        visit::visit_unresolved_object_name(self, name);
+    }
+}
+
+/// An [`Authorizer`] that grants each user the statement classes listed in a
+/// policy file.
+///
+/// The policy file is TOML. The `default` key lists the classes granted to
+/// users that the `users` table does not name, and defaults to none:
+///
+/// ```toml
+/// default = ["read"]
+///
+/// [users]
+/// materialize = ["read", "write", "ddl", "admin"]
+/// etl = ["read", "write"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyAuthorizer {
+    default: BTreeSet<AuthzClass>,
+    users: HashMap<String, BTreeSet<AuthzClass>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicy {
+    #[serde(default)]
+    default: Vec<String>,
+    #[serde(default)]
+    users: HashMap<String, Vec<String>>,
+}
+
+impl PolicyAuthorizer {
+    /// Loads a policy from the file at `path`.
+    pub fn load(path: &Path) -> Result<PolicyAuthorizer, anyhow::Error> {
+        let policy = fs::read_to_string(path)
+            .with_context(|| format!("reading authorization policy {}", path.display()))?;
+        policy
+            .parse()
+            .with_context(|| format!("parsing authorization policy {}", path.display()))
+    }
+
+    fn classes(&self, user: &str) -> &BTreeSet<AuthzClass> {
+        self.users.get(user).unwrap_or(&self.default)
+    }
+}
+
+impl FromStr for PolicyAuthorizer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<PolicyAuthorizer, anyhow::Error> {
+        fn parse_classes(classes: Vec<String>) -> Result<BTreeSet<AuthzClass>, anyhow::Error> {
+            classes.iter().map(|c| c.parse()).collect()
+        }
+
+        let raw: RawPolicy = toml::from_str(s)?;
+        let default = parse_classes(raw.default).context("in default")?;
+        let mut users = HashMap::new();
+        for (user, classes) in raw.users {
+            let classes = parse_classes(classes).with_context(|| format!("in user {}", user))?;
+            users.insert(user, classes);
+        }
+        Ok(PolicyAuthorizer { default, users })
+    }
+}
+
+impl Authorizer for PolicyAuthorizer {
+    fn authorize(&self, request: &AuthzRequest) -> AuthzDecision {
+        if self.classes(request.user).contains(&request.class) {
+            AuthzDecision::Allow
+        } else {
+            AuthzDecision::Deny(format!(
+                "user {} may not execute {} statements",
+                request.user, request.class
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::collections::CollectionExt;
+
+    use super::*;
+
+    fn check(authorizer: &dyn Authorizer, user: &str, sql: &str) -> AuthzDecision {
+        let stmt = sql::parse::parse(sql).unwrap().into_element();
+        authorize(authorizer, user, &stmt)
+    }
+
+    #[test]
+    fn test_policy_authorizer() -> Result<(), anyhow::Error> {
+        let policy: PolicyAuthorizer = r#"
+            default = ["read"]
+
+            [users]
+            admin = ["read", "write", "ddl", "admin"]
+            nobody = []
+        "#
+        .parse()?;
+
+        assert_eq!(
+            check(&policy, "analyst", "SELECT * FROM t"),
+            AuthzDecision::Allow
+        );
+        assert_eq!(
+            check(&policy, "analyst", "CREATE VIEW v AS SELECT 1"),
+            AuthzDecision::Deny("user analyst may not execute ddl statements".into())
+        );
+        assert_eq!(
+            check(&policy, "analyst", "COPY t FROM STDIN"),
+            AuthzDecision::Deny("user analyst may not execute write statements".into())
+        );
+        assert_eq!(check(&policy, "admin", "DROP ROLE r"), AuthzDecision::Allow);
+        // Statements that affect only the session are always allowed.
+        assert_eq!(check(&policy, "nobody", "SET x = 1"), AuthzDecision::Allow);
+        assert_eq!(
+            check(&policy, "nobody", "DECLARE c CURSOR FOR SELECT 1"),
+            AuthzDecision::Deny("user nobody may not execute read statements".into())
+        );
+
+        assert!("default = [\"select\"]"
+            .parse::<PolicyAuthorizer>()
+            .is_err());
+        assert!("unknown = []".parse::<PolicyAuthorizer>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_objects() {
+        #[derive(Debug)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl Authorizer for Recorder {
+            fn authorize(&self, request: &AuthzRequest) -> AuthzDecision {
+                *self.0.lock().unwrap() = request.objects.to_vec();
+                AuthzDecision::Allow
+            }
+        }
+
+        let recorder = Recorder(Default::default());
+        check(
+            &recorder,
+            "u",
+            "INSERT INTO db.s.t SELECT * FROM u JOIN t ON true",
+        );
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["db.s.t", "u", "t"]);
+    }
+}
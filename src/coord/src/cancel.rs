@@ -0,0 +1,109 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Secret keys for cancellation requests.
+//!
+//! A PostgreSQL cancellation request arrives on a new connection and names the
+//! connection to cancel only by its ID and secret key. When several instances
+//! share a load balancer, the request may reach an instance other than the one
+//! that serves the connection. The high bits of every secret key are therefore
+//! derived from the cluster ID, which partitions the key space by instance, so
+//! that an instance can recognize a request that was meant for another
+//! instance without consulting the coordinator. The cluster ID persists across
+//! restarts, and so does the partition.
+
+use rand::Rng;
+use uuid::Uuid;
+
+/// The number of high bits of a secret key that identify the instance that
+/// issued it.
+///
+/// Two instances whose cluster IDs agree in these bits cannot tell each
+/// other's requests apart, which, for randomly generated cluster IDs, happens
+/// for one pair of instances in 4096.
+const PARTITION_BITS: u32 = 12;
+
+/// Issues and recognizes the secret keys of one instance.
+#[derive(Debug, Clone, Copy)]
+pub struct CancelKeys {
+    partition: u32,
+}
+
+impl CancelKeys {
+    /// Constructs the keys of the instance with the specified cluster ID.
+    pub fn new(cluster_id: Uuid) -> CancelKeys {
+        let bytes = cluster_id.as_bytes();
+        let high = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        CancelKeys {
+            partition: high >> (32 - PARTITION_BITS),
+        }
+    }
+
+    /// Generates a random secret key in this instance's partition.
+    pub fn generate(&self) -> u32 {
+        let random: u32 = rand::thread_rng().gen();
+        (self.partition << (32 - PARTITION_BITS)) | (random >> PARTITION_BITS)
+    }
+
+    /// Reports whether `secret_key` is in this instance's partition, i.e.,
+    /// whether this instance could have issued it.
+    pub fn owns(&self, secret_key: u32) -> bool {
+        secret_key >> (32 - PARTITION_BITS) == self.partition
+    }
+}
+
+/// The outcome of a cancellation request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The request named an active connection, whose query, if any, was
+    /// canceled.
+    Matched,
+    /// The request was meant for this instance but did not name an active
+    /// connection, e.g., because the connection has since closed or because
+    /// the instance has restarted since issuing the key.
+    Unmatched,
+    /// The request was meant for another instance.
+    Foreign,
+}
+
+impl CancelOutcome {
+    /// Returns the name of the outcome, for use in metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelOutcome::Matched => "matched",
+            CancelOutcome::Unmatched => "unmatched",
+            CancelOutcome::Foreign => "foreign",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::CancelKeys;
+
+    #[test]
+    fn test_partitions() {
+        let a = CancelKeys::new(Uuid::parse_str("00000000-0000-4000-8000-000000000000").unwrap());
+        let b = CancelKeys::new(Uuid::parse_str("fff00000-0000-4000-8000-000000000000").unwrap());
+        for _ in 0..1000 {
+            let key = a.generate();
+            assert!(a.owns(key));
+            assert!(!b.owns(key));
+            let key = b.generate();
+            assert!(b.owns(key));
+            assert!(!a.owns(key));
+        }
+
+        // The partition depends only on the cluster ID.
+        let id = Uuid::new_v4();
+        assert!(CancelKeys::new(id).owns(CancelKeys::new(id).generate()));
+    }
+}
@@ -10,8 +10,9 @@
 //! Persistent metadata storage for the coordinator.
 
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use anyhow::bail;
@@ -20,12 +21,13 @@ use dataflow_types::{ExternalSourceConnector, MzOffset, SinkEnvelope};
 use expr::{Id, PartitionId};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use log::{info, trace};
+use log::{info, trace, warn};
 use ore::collections::CollectionExt;
 use ore::now::{to_datetime, EpochMillis, NowFn};
 use regex::Regex;
 use repr::Timestamp;
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 use build_info::DUMMY_BUILD_INFO;
 use dataflow_types::{SinkConnector, SinkConnectorBuilder, SourceConnector, Timeline};
@@ -55,16 +57,24 @@ use crate::session::Session;
 
 mod builtin_table_updates;
 mod config;
+mod encryption;
 mod error;
 mod migrate;
+mod sql_dump;
+mod version;
 
 pub mod builtin;
+pub mod check;
 pub mod storage;
+pub mod storage_directory;
 
 pub use crate::catalog::builtin_table_updates::BuiltinTableUpdate;
-pub use crate::catalog::config::Config;
+pub use crate::catalog::config::{Config, DurabilityLevel, ASYNC_SYNC_INTERVAL};
+pub use crate::catalog::encryption::EncryptionKey;
 pub use crate::catalog::error::Error;
 pub use crate::catalog::error::ErrorKind;
+pub use crate::catalog::sql_dump::SqlDumpOptions;
+pub use crate::catalog::version::CatalogVersion;
 
 const SYSTEM_CONN_ID: u32 = 0;
 const SYSTEM_USER: &str = "mz_system";
@@ -109,6 +119,34 @@ pub struct Catalog {
     config: sql::catalog::CatalogConfig,
 }
 
+/// The version of the catalog's contents produced by this version of the
+/// code, i.e., the number of content migrations.
+pub const CONTENT_VERSION: u32 = CONTENT_MIGRATIONS.len() as u32;
+
+/// Describes a snapshot of the catalog produced by [`Catalog::snapshot`].
+#[derive(Debug, Clone)]
+pub struct CatalogSnapshot {
+    /// The version of the catalog's schema.
+    pub schema_version: u32,
+    /// The version of the catalog's contents.
+    pub content_version: u32,
+    /// The ID of the cluster to which the catalog belongs.
+    pub cluster_id: Uuid,
+    /// The time for which the catalog was locked to take the snapshot.
+    pub duration: Duration,
+}
+
+/// Describes a compaction of the catalog performed by [`Catalog::compact`].
+#[derive(Debug, Clone)]
+pub struct CatalogCompaction {
+    /// The size of the catalog's on-disk state before compaction, in bytes.
+    pub size_before: u64,
+    /// The size of the catalog's on-disk state after compaction, in bytes.
+    pub size_after: u64,
+    /// The time for which the catalog was locked to compact it.
+    pub duration: Duration,
+}
+
 #[derive(Debug)]
 pub struct ConnCatalog<'a> {
     catalog: &'a Catalog,
@@ -513,9 +551,29 @@ impl Catalog {
                 num_workers: config.num_workers,
                 timestamp_frequency: config.timestamp_frequency,
                 now: config.now,
+                outbound_bind_addr: config.outbound_bind_addr,
             },
         };
 
+        // At `DurabilityLevel::Async`, commits are not synced as they are
+        // made, so sync them periodically for as long as the catalog lives.
+        if config.durability == Some(DurabilityLevel::Async) {
+            let storage = Arc::downgrade(&catalog.storage);
+            thread::Builder::new()
+                .name("catalog-sync".to_string())
+                .spawn(move || loop {
+                    thread::sleep(ASYNC_SYNC_INTERVAL);
+                    let storage = match storage.upgrade() {
+                        Some(storage) => storage,
+                        None => break,
+                    };
+                    if let Err(e) = storage.lock().expect("lock poisoned").sync() {
+                        warn!("unable to sync catalog: {}", e);
+                    }
+                })
+                .expect("failed to spawn catalog sync thread");
+        }
+
         catalog.create_temporary_schema(SYSTEM_CONN_ID)?;
 
         let databases = catalog.storage().load_databases()?;
@@ -733,8 +791,16 @@ impl Catalog {
 
         let mut catalog_content_version = catalog.storage().get_catalog_content_version()?;
 
+        if config.read_only && CONTENT_MIGRATIONS.len() > catalog_content_version {
+            return Err(Error::new(ErrorKind::ReadOnlyCatalog(format!(
+                "catalog content version {} must be migrated to version {}",
+                catalog_content_version,
+                CONTENT_MIGRATIONS.len()
+            ))));
+        }
+
         while CONTENT_MIGRATIONS.len() > catalog_content_version {
-            if let Err(e) = CONTENT_MIGRATIONS[catalog_content_version](&mut catalog) {
+            if let Err(e) = (CONTENT_MIGRATIONS[catalog_content_version].migrate)(&mut catalog) {
                 return Err(Error::new(ErrorKind::FailedMigration {
                     last_version: catalog_content_version,
                     cause: e.to_string(),
@@ -745,6 +811,11 @@ impl Catalog {
                 .storage()
                 .set_catalog_content_version(catalog_content_version)?;
         }
+        if !config.read_only {
+            catalog
+                .storage()
+                .set_build_version(config.build_info.version)?;
+        }
 
         let catalog = Self::load_catalog_items(catalog)?;
 
@@ -819,10 +890,14 @@ impl Catalog {
             enable_logging: true,
             experimental_mode: None,
             safe_mode: false,
+            read_only: false,
             build_info: &DUMMY_BUILD_INFO,
             num_workers: 0,
             timestamp_frequency: Duration::from_secs(1),
             now,
+            durability: None,
+            encryption_key: None,
+            outbound_bind_addr: None,
         })?;
         Ok(catalog)
     }
@@ -1589,6 +1664,7 @@ impl Catalog {
             });
         }
         tx.commit()?;
+        storage.note_change();
         drop(storage); // release immutable borrow on `self` so we can borrow mutably below
 
         for action in actions {
@@ -1975,6 +2051,52 @@ impl Catalog {
         }
     }
 
+    /// Returns a receiver that observes the number of changes to the
+    /// catalog's definitions committed since the catalog was opened.
+    ///
+    /// Changes to timestamp bindings are not counted.
+    pub fn subscribe_changes(&self) -> watch::Receiver<u64> {
+        self.storage().subscribe_changes()
+    }
+
+    /// Writes a consistent snapshot of the catalog's on-disk state to a new
+    /// database file at `path`.
+    ///
+    /// The catalog's storage is locked for the duration of the copy.
+    pub fn snapshot(&self, path: &str) -> Result<CatalogSnapshot, Error> {
+        let start = Instant::now();
+        let (schema_version, content_version) = self.storage().backup(path)?;
+        Ok(CatalogSnapshot {
+            schema_version,
+            content_version,
+            cluster_id: self.config.cluster_id,
+            duration: start.elapsed(),
+        })
+    }
+
+    /// Reencrypts the catalog's on-disk state with `key`, which replaces the
+    /// key with which the catalog is currently encrypted.
+    ///
+    /// The catalog's storage is locked while its contents are reencrypted.
+    pub fn reencrypt(&self, key: EncryptionKey) -> Result<(), Error> {
+        self.storage().reencrypt(key)
+    }
+
+    /// Rewrites the catalog's on-disk state to its minimal form, reclaiming
+    /// the space left behind by dropped objects and rewritten definitions.
+    ///
+    /// See [`storage::Connection::compact`] for how the rewrite is made safe
+    /// against crashes.
+    pub fn compact(&self) -> Result<CatalogCompaction, Error> {
+        let start = Instant::now();
+        let (size_before, size_after) = self.storage().compact()?;
+        Ok(CatalogCompaction {
+            size_before,
+            size_after,
+            duration: start.elapsed(),
+        })
+    }
+
     /// Serializes the catalog's in-memory state.
     ///
     /// There are no guarantees about the format of the serialized state, except
@@ -1984,6 +2106,43 @@ impl Catalog {
         serde_json::to_string(&self.by_name).expect("serialization cannot fail")
     }
 
+    /// Returns the paths of the local files that the catalog's sources read
+    /// from and that its sinks write to.
+    ///
+    /// Sinks that have not yet started are omitted, as the name of the file
+    /// they will write to is not determined until they start.
+    pub fn file_paths(&self) -> Vec<PathBuf> {
+        self.file_references()
+            .into_iter()
+            .map(|(_name, path)| path)
+            .collect()
+    }
+
+    /// Like [`Catalog::file_paths`], but pairs each path with the name of
+    /// the source or sink that refers to it.
+    pub fn file_references(&self) -> Vec<(String, PathBuf)> {
+        let mut refs = vec![];
+        for entry in self.by_id.values() {
+            match entry.item() {
+                CatalogItem::Source(Source {
+                    connector:
+                        SourceConnector::External {
+                            connector:
+                                ExternalSourceConnector::File(c) | ExternalSourceConnector::AvroOcf(c),
+                            ..
+                        },
+                    ..
+                }) => refs.push((entry.name().to_string(), c.path.clone())),
+                CatalogItem::Sink(Sink {
+                    connector: SinkConnectorState::Ready(SinkConnector::AvroOcf(c)),
+                    ..
+                }) => refs.push((entry.name().to_string(), c.path.clone())),
+                _ => (),
+            }
+        }
+        refs
+    }
+
     pub fn config(&self) -> &sql::catalog::CatalogConfig {
         &self.config
     }
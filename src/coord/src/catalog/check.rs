@@ -0,0 +1,544 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Integrity checks for the on-disk catalog.
+//!
+//! The checks open the catalog read-only and never modify it. They are run in
+//! two situations: in full, on demand, to vet a data directory after an
+//! unclean shutdown, and as a [quick](CheckLevel::Quick) subset whenever the
+//! coordinator boots, so that a damaged catalog produces an actionable error
+//! rather than a failure deep within [`Catalog::open`](super::Catalog::open).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{params, OpenFlags, OptionalExtension};
+use serde::Serialize;
+
+use expr::GlobalId;
+use ore::collections::CollectionExt;
+use sql::ast::visit::{self, Visit};
+use sql::ast::{Raw, UnresolvedObjectName};
+
+use crate::catalog::builtin::BUILTINS;
+use crate::catalog::encryption::EncryptionKey;
+use crate::catalog::error::Error;
+use crate::catalog::storage::{self, SqlVal};
+use crate::catalog::{SerializedCatalogItem, CONTENT_VERSION};
+
+/// The suggestion for findings that indicate that the catalog is damaged
+/// beyond what can be fixed by hand.
+const RESTORE_SUGGESTION: &str =
+    "restore the catalog from a backup by starting materialized with --restore-from";
+
+/// How thorough a catalog check is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckLevel {
+    /// Verifies that the catalog is a well-formed catalog database that this
+    /// version of the server can open. Fast enough to run on every boot.
+    Quick,
+    /// Additionally verifies the internal consistency of the catalog's
+    /// contents.
+    Full,
+}
+
+/// The severity of a [`Finding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The catalog cannot be used until the problem is fixed.
+    Error,
+    /// The catalog can be used, but something is amiss.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+/// A problem discovered by a catalog check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// The severity of the problem.
+    pub severity: Severity,
+    /// The name of the check that discovered the problem.
+    pub check: &'static str,
+    /// A description of the problem.
+    pub message: String,
+    /// A suggestion for how to repair the problem, if any.
+    pub suggestion: Option<String>,
+}
+
+/// The results of a catalog check.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckReport {
+    /// The problems discovered by the check, in the order they were
+    /// discovered.
+    pub findings: Vec<Finding>,
+}
+
+impl CheckReport {
+    /// Reports whether the check discovered no errors.
+    ///
+    /// Warnings do not prevent the catalog from being used.
+    pub fn is_ok(&self) -> bool {
+        self.findings.iter().all(|f| f.severity != Severity::Error)
+    }
+
+    fn error(&mut self, check: &'static str, message: String, suggestion: Option<&str>) {
+        self.push(Severity::Error, check, message, suggestion);
+    }
+
+    fn warning(&mut self, check: &'static str, message: String, suggestion: Option<&str>) {
+        self.push(Severity::Warning, check, message, suggestion);
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        check: &'static str,
+        message: String,
+        suggestion: Option<&str>,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            check,
+            message,
+            suggestion: suggestion.map(|s| s.into()),
+        });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", finding.severity, finding.message)?;
+            if let Some(suggestion) = &finding.suggestion {
+                write!(f, "\nhint: {}", suggestion)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the integrity of the catalog at `path`.
+///
+/// A missing catalog is not an error, as the catalog is created when the
+/// server first boots. An `Err` is returned only if the catalog cannot be
+/// read at all; problems with its contents are reported as findings.
+///
+/// If the catalog is encrypted, a full check uses `encryption_key` to
+/// decrypt the definitions of its items. Without the key, the definitions
+/// are not checked. A quick check never decrypts anything, and leaves
+/// verifying the key to [`Catalog::open`](super::Catalog::open).
+pub fn check(
+    path: &Path,
+    level: CheckLevel,
+    encryption_key: Option<&EncryptionKey>,
+) -> Result<CheckReport, Error> {
+    let mut report = CheckReport::default();
+    if !path.exists() {
+        if level == CheckLevel::Full {
+            report.warning(
+                "existence",
+                format!("catalog {} does not exist", path.display()),
+                Some("a new catalog will be created when materialized starts"),
+            );
+        }
+        return Ok(report);
+    }
+
+    let sqlite = rusqlite::Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    // Structural integrity. If the database itself is damaged, its contents
+    // cannot be trusted, so no further checks are run.
+    let pragma = match level {
+        CheckLevel::Quick => "PRAGMA quick_check",
+        CheckLevel::Full => "PRAGMA integrity_check",
+    };
+    let problems = || -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = sqlite.prepare(pragma)?;
+        let rows = stmt.query_map(params![], |row| row.get(0))?;
+        rows.collect()
+    };
+    match problems() {
+        Ok(problems) if problems == ["ok"] => (),
+        Ok(problems) => {
+            report.error(
+                "structure",
+                format!("catalog database is damaged: {}", problems.join("; ")),
+                Some(RESTORE_SUGGESTION),
+            );
+            return Ok(report);
+        }
+        Err(e) => {
+            report.error(
+                "structure",
+                format!("catalog is not a valid database: {}", e),
+                Some(RESTORE_SUGGESTION),
+            );
+            return Ok(report);
+        }
+    }
+
+    let app_id: i32 = sqlite.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
+    if app_id == 0 {
+        // The catalog was created but never initialized, which happens if the
+        // server crashed during its first boot. It is initialized on open.
+        return Ok(report);
+    } else if app_id != storage::APPLICATION_ID {
+        report.error(
+            "structure",
+            format!(
+                "{} is not a Materialize catalog (application ID {:#x})",
+                path.display(),
+                app_id
+            ),
+            Some("check that the data directory is correct"),
+        );
+        return Ok(report);
+    }
+
+    // Versions. A catalog written by a newer version of the server cannot be
+    // safely opened by this version.
+    let schema_version: u32 =
+        sqlite.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+    let too_new = "start a version of materialized at least as new as the version that last \
+                   ran against this data directory";
+    if schema_version > storage::SCHEMA_VERSION {
+        report.error(
+            "version",
+            format!(
+                "catalog schema version {} is newer than the newest version supported by \
+                 this version of materialized ({})",
+                schema_version,
+                storage::SCHEMA_VERSION
+            ),
+            Some(too_new),
+        );
+    }
+    let content_version: Option<u32> = sqlite
+        .query_row(
+            "SELECT CAST(value AS int) FROM settings WHERE name = 'catalog_content_version'",
+            params![],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap_or(None);
+    if let Some(content_version) = content_version {
+        if content_version > CONTENT_VERSION {
+            report.error(
+                "version",
+                format!(
+                    "catalog content version {} is newer than the newest version supported by \
+                     this version of materialized ({})",
+                    content_version, CONTENT_VERSION
+                ),
+                Some(too_new),
+            );
+        }
+    }
+
+    if level == CheckLevel::Quick || !report.is_ok() {
+        return Ok(report);
+    }
+    if schema_version < storage::SCHEMA_VERSION {
+        // The consistency checks assume the current schema.
+        report.warning(
+            "version",
+            format!(
+                "catalog schema version {} will be migrated to version {} when materialized \
+                 starts; consistency checks were skipped",
+                schema_version,
+                storage::SCHEMA_VERSION
+            ),
+            None,
+        );
+        return Ok(report);
+    }
+
+    // Encryption. The definitions of an encrypted catalog's items can only be
+    // checked with the key with which they were encrypted.
+    let key_check: Option<String> = sqlite
+        .query_row(
+            "SELECT value FROM settings WHERE name = 'encryption_key_check'",
+            params![],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let decrypt = match (key_check, encryption_key) {
+        (None, _) => Decrypt::Plaintext,
+        (Some(_), None) => {
+            report.warning(
+                "encryption",
+                "catalog is encrypted; definitions were not checked".into(),
+                Some("provide the catalog's encryption key to check its definitions"),
+            );
+            Decrypt::Skip
+        }
+        (Some(key_check), Some(key)) if key.matches(&key_check) => Decrypt::Key(key),
+        (Some(_), Some(_)) => {
+            report.error(
+                "encryption",
+                "catalog is not encrypted with the provided encryption key".into(),
+                Some("provide the key with which the catalog was encrypted"),
+            );
+            return Ok(report);
+        }
+    };
+
+    check_consistency(&sqlite, decrypt, &mut report)?;
+    Ok(report)
+}
+
+/// How to read the definitions of items.
+#[derive(Clone, Copy)]
+enum Decrypt<'a> {
+    /// The definitions are not encrypted.
+    Plaintext,
+    /// The definitions are encrypted with the specified key.
+    Key(&'a EncryptionKey),
+    /// The definitions are encrypted with an unknown key, and so cannot be
+    /// read.
+    Skip,
+}
+
+/// Checks that the catalog's contents refer only to objects that exist.
+fn check_consistency(
+    sqlite: &rusqlite::Connection,
+    decrypt: Decrypt,
+    report: &mut CheckReport,
+) -> Result<(), Error> {
+    // Every schema belongs to a database, and every item to a schema.
+    let orphans: Vec<String> = sqlite
+        .prepare(
+            "SELECT schemas.name FROM schemas
+             LEFT JOIN databases ON schemas.database_id = databases.id
+             WHERE schemas.database_id IS NOT NULL AND databases.id IS NULL",
+        )?
+        .query_map(params![], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for schema in orphans {
+        report.error(
+            "references",
+            format!(
+                "schema {} belongs to a database that does not exist",
+                schema
+            ),
+            Some(RESTORE_SUGGESTION),
+        );
+    }
+    let orphans: Vec<String> = sqlite
+        .prepare(
+            "SELECT items.name FROM items
+             LEFT JOIN schemas ON items.schema_id = schemas.id
+             WHERE schemas.id IS NULL",
+        )?
+        .query_map(params![], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for item in orphans {
+        report.error(
+            "references",
+            format!("item {} belongs to a schema that does not exist", item),
+            Some(RESTORE_SUGGESTION),
+        );
+    }
+
+    // Load the items, keyed by their qualified names as they appear in
+    // normalized SQL.
+    let items: Vec<(GlobalId, Vec<String>, Vec<u8>)> = sqlite
+        .prepare(
+            "SELECT items.gid, databases.name, schemas.name, items.name, items.definition
+             FROM items
+             JOIN schemas ON items.schema_id = schemas.id
+             LEFT JOIN databases ON schemas.database_id = databases.id",
+        )?
+        .query_map(params![], |row| {
+            let id: SqlVal<GlobalId> = row.get(0)?;
+            let database: Option<String> = row.get(1)?;
+            let name = database
+                .into_iter()
+                .chain(vec![row.get(2)?, row.get(3)?])
+                .collect();
+            Ok((id.0, name, row.get(4)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    let mut ids: HashMap<Vec<String>, GlobalId> = BUILTINS
+        .iter()
+        .map(|(id, builtin)| (vec![builtin.schema().into(), builtin.name().into()], *id))
+        .collect();
+    for (id, name, _) in &items {
+        ids.insert(name.clone(), *id);
+    }
+
+    // Every dependency of every item exists, and was created before the item,
+    // as items are reloaded in the order in which they were created.
+    let mut max_id = 0;
+    for (id, name, definition) in &items {
+        let name = name.join(".");
+        if let GlobalId::User(id) = id {
+            max_id = max_id.max(*id);
+        }
+        let definition = match decrypt {
+            Decrypt::Plaintext => definition.clone(),
+            Decrypt::Key(key) => match key.unseal(&storage::item_aad(*id), definition) {
+                Some(definition) => definition,
+                None => {
+                    report.error(
+                        "definitions",
+                        format!("definition of {} cannot be decrypted", name),
+                        Some(RESTORE_SUGGESTION),
+                    );
+                    continue;
+                }
+            },
+            Decrypt::Skip => continue,
+        };
+        let create_sql = match serde_json::from_slice(&definition) {
+            Ok(SerializedCatalogItem::V1 { create_sql, .. }) => create_sql,
+            Err(e) => {
+                report.error(
+                    "definitions",
+                    format!("definition of {} is unreadable: {}", name, e),
+                    Some(RESTORE_SUGGESTION),
+                );
+                continue;
+            }
+        };
+        let stmt = match sql::parse::parse(&create_sql) {
+            Ok(stmts) if stmts.len() == 1 => stmts.into_element(),
+            _ => {
+                report.error(
+                    "definitions",
+                    format!("definition of {} is not a valid statement", name),
+                    Some(RESTORE_SUGGESTION),
+                );
+                continue;
+            }
+        };
+        let mut collector = ReferenceCollector::default();
+        collector.visit_statement(&stmt);
+        for reference in collector.references {
+            match ids.get(&reference) {
+                None => report.error(
+                    "references",
+                    format!(
+                        "{} depends on {}, which does not exist",
+                        name,
+                        reference.join(".")
+                    ),
+                    Some(RESTORE_SUGGESTION),
+                ),
+                Some(dep_id) if dep_id.is_user() && dep_id > id => report.error(
+                    "references",
+                    format!(
+                        "{} depends on {}, which was created after it",
+                        name,
+                        reference.join(".")
+                    ),
+                    Some(RESTORE_SUGGESTION),
+                ),
+                Some(_) => (),
+            }
+        }
+    }
+
+    // The ID allocator never reissues an ID that is in use.
+    let next_id: i64 = sqlite.query_row("SELECT next_gid FROM gid_alloc", params![], |row| {
+        row.get(0)
+    })?;
+    if next_id <= 0 || (next_id as u64) <= max_id {
+        report.error(
+            "ids",
+            format!(
+                "next ID to allocate ({}) is not greater than the largest ID in use ({})",
+                next_id, max_id
+            ),
+            Some(RESTORE_SUGGESTION),
+        );
+    }
+
+    // Timestamp bindings belong to existing items, and the offsets of each
+    // partition never decrease as time advances.
+    let item_ids: HashSet<GlobalId> = items.iter().map(|(id, _, _)| *id).collect();
+    let mut stmt = sqlite.prepare(
+        "SELECT sid, pid, timestamp, offset FROM timestamps ORDER BY sid, pid, timestamp, offset",
+    )?;
+    let mut rows = stmt.query(params![])?;
+    let mut prev: Option<(GlobalId, String, i64, i64)> = None;
+    let mut dangling = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        let sid: SqlVal<GlobalId> = row.get(0)?;
+        let (sid, pid, timestamp, offset): (GlobalId, String, i64, i64) =
+            (sid.0, row.get(1)?, row.get(2)?, row.get(3)?);
+        if !item_ids.contains(&sid) {
+            *dangling.entry(sid).or_insert(0) += 1;
+        }
+        if let Some((prev_sid, prev_pid, prev_timestamp, prev_offset)) = &prev {
+            if *prev_sid == sid && *prev_pid == pid && *prev_offset > offset {
+                report.error(
+                    "timestamps",
+                    format!(
+                        "timestamp bindings for partition {} of source {} are not monotonic: \
+                         offset {} at timestamp {} precedes offset {} at timestamp {}",
+                        pid, sid, prev_offset, prev_timestamp, offset, timestamp
+                    ),
+                    Some(
+                        "recreate the source so that it re-ingests its data, or restore the \
+                         catalog from a backup by starting materialized with --restore-from",
+                    ),
+                );
+            }
+        }
+        prev = Some((sid, pid, timestamp, offset));
+    }
+    for (sid, count) in dangling {
+        report.warning(
+            "timestamps",
+            format!(
+                "{} timestamp bindings belong to source {}, which does not exist",
+                count, sid
+            ),
+            Some("the bindings are ignored and are harmless"),
+        );
+    }
+
+    Ok(())
+}
+
+/// Collects the names of the catalog objects that a statement refers to.
+///
+/// Only qualified names are collected, as normalized SQL qualifies every
+/// reference to a catalog object. Unqualified names refer to other things,
+/// like common table expressions.
+#[derive(Default)]
+struct ReferenceCollector {
+    references: Vec<Vec<String>>,
+}
+
+impl<'ast> Visit<'ast, Raw> for ReferenceCollector {
+    fn visit_unresolved_object_name(&mut self, name: &'ast UnresolvedObjectName) {
+        if name.0.len() > 1 {
+            let name = name.0.iter().map(|i| i.as_str().to_owned()).collect();
+            if !self.references.contains(&name) {
+                self.references.push(name);
+            }
+        }
+        visit::visit_unresolved_object_name(self, name);
+    }
+}
@@ -7,11 +7,16 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
+use std::fmt;
+use std::net::IpAddr;
 use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
 use build_info::BuildInfo;
 
+use crate::catalog::encryption::EncryptionKey;
+
 /// Configures a catalog.
 #[derive(Clone, Debug)]
 pub struct Config<'a> {
@@ -21,6 +26,9 @@ pub struct Config<'a> {
     pub experimental_mode: Option<bool>,
     /// Whether to enable safe mode.
     pub safe_mode: bool,
+    /// Whether to open the catalog read-only, in which case the catalog on
+    /// disk is never modified.
+    pub read_only: bool,
     /// Whether to enable logging sources and the views that depend upon them.
     pub enable_logging: bool,
     /// Information about this build of Materialize.
@@ -31,4 +39,77 @@ pub struct Config<'a> {
     pub timestamp_frequency: Duration,
     /// Function to generate wall clock now; can be mocked.
     pub now: ore::now::NowFn,
+    /// The durability of writes to the catalog.
+    ///
+    /// Using `None` is appropriate when reading the catalog outside the
+    /// context of starting the server, in which case the catalog's durability
+    /// settings are left untouched.
+    pub durability: Option<DurabilityLevel>,
+    /// The key with which the catalog is encrypted, if it is encrypted.
+    ///
+    /// A new catalog is encrypted with this key. An existing catalog must
+    /// already be encrypted with this key, or, if `None`, must not be
+    /// encrypted at all.
+    pub encryption_key: Option<EncryptionKey>,
+    /// The local address to which outbound connections to external systems
+    /// are bound, if any.
+    pub outbound_bind_addr: Option<IpAddr>,
+}
+
+/// The durability guarantees that the catalog provides for its writes.
+///
+/// Levels are ordered from least to most durable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DurabilityLevel {
+    /// Writes are never synced to disk. A crash of the process does not lose
+    /// writes, but a crash of the operating system or a power failure may
+    /// lose or corrupt the catalog. Suitable only for throwaway instances.
+    None,
+    /// Writes are synced to disk in batches, at most
+    /// [`ASYNC_SYNC_INTERVAL`] after they are made. A crash of the operating
+    /// system or a power failure may lose the most recent writes, but does
+    /// not corrupt the catalog.
+    Async,
+    /// Each write is synced to disk, along with the directory that contains
+    /// the catalog, before it is acknowledged.
+    Full,
+}
+
+/// The interval at which writes are synced at [`DurabilityLevel::Async`].
+pub const ASYNC_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+impl DurabilityLevel {
+    /// Returns the name of the level, as recorded in the catalog.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DurabilityLevel::None => "none",
+            DurabilityLevel::Async => "async",
+            DurabilityLevel::Full => "full",
+        }
+    }
+}
+
+impl Default for DurabilityLevel {
+    fn default() -> DurabilityLevel {
+        DurabilityLevel::Full
+    }
+}
+
+impl fmt::Display for DurabilityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DurabilityLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DurabilityLevel, String> {
+        match s {
+            "none" => Ok(DurabilityLevel::None),
+            "async" => Ok(DurabilityLevel::Async),
+            "full" => Ok(DurabilityLevel::Full),
+            _ => Err(format!("unknown durability level: {}", s)),
+        }
+    }
 }
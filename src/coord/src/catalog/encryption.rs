@@ -0,0 +1,191 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Encryption of the catalog at rest.
+//!
+//! When the catalog is encrypted, the definition of each item is sealed with
+//! AES-256-GCM under a fresh random nonce. The item's ID is bound to the
+//! sealed definition as associated data, so that a definition cannot be
+//! swapped onto a different item without detection. The names of databases,
+//! schemas, and items, and the timestamp bindings of sources, are not
+//! encrypted, as the catalog relies on them to enforce uniqueness.
+//!
+//! Whether a catalog is encrypted, and with which key, is recorded by sealing
+//! a known plaintext into the catalog's settings. Checking that the known
+//! plaintext can be unsealed distinguishes a wrong key from a damaged
+//! definition.
+
+use std::fmt;
+
+use openssl::rand::rand_bytes;
+use openssl::symm::{self, Cipher};
+
+use crate::catalog::error::{Error, ErrorKind};
+
+/// Identifies the format of a sealed value, so that the format can evolve.
+const MAGIC: &[u8] = b"mzenc1";
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The known plaintext that is sealed to record the catalog's key.
+const KEY_CHECK_PLAINTEXT: &[u8] = b"materialize catalog encryption key check";
+
+/// The associated data with which the known plaintext is sealed.
+const KEY_CHECK_AAD: &[u8] = b"encryption_key_check";
+
+/// A key with which the catalog is encrypted.
+///
+/// The key material is never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; EncryptionKey::LEN]);
+
+impl EncryptionKey {
+    /// The length of an encryption key, in bytes.
+    pub const LEN: usize = 32;
+
+    /// Constructs a key from exactly [`EncryptionKey::LEN`] bytes of key material.
+    pub fn from_bytes(bytes: &[u8]) -> Result<EncryptionKey, String> {
+        if bytes.len() != EncryptionKey::LEN {
+            return Err(format!(
+                "encryption key must be {} bytes, but got {} bytes",
+                EncryptionKey::LEN,
+                bytes.len()
+            ));
+        }
+        let mut key = [0; EncryptionKey::LEN];
+        key.copy_from_slice(bytes);
+        Ok(EncryptionKey(key))
+    }
+
+    /// Constructs a key from its hex encoding, ignoring surrounding
+    /// whitespace.
+    pub fn from_hex(s: &str) -> Result<EncryptionKey, String> {
+        let bytes =
+            hex::decode(s.trim()).map_err(|e| format!("encryption key is not valid hex: {}", e))?;
+        EncryptionKey::from_bytes(&bytes)
+    }
+
+    /// Seals `plaintext`, binding it to `aad`.
+    pub(super) fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand_bytes(&mut nonce).map_err(|e| Error::new(ErrorKind::Encryption(e)))?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = symm::encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(&nonce),
+            aad,
+            plaintext,
+            &mut tag,
+        )
+        .map_err(|e| Error::new(ErrorKind::Encryption(e)))?;
+        let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len() + TAG_LEN);
+        sealed.extend(MAGIC);
+        sealed.extend(&nonce);
+        sealed.extend(ciphertext);
+        sealed.extend(&tag);
+        Ok(sealed)
+    }
+
+    /// Unseals a value produced by [`EncryptionKey::seal`] with the same
+    /// `aad`.
+    ///
+    /// Returns `None` if the value is malformed, was sealed with a different
+    /// key or associated data, or has been tampered with.
+    pub(super) fn unseal(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < MAGIC.len() + NONCE_LEN + TAG_LEN || !sealed.starts_with(MAGIC) {
+            return None;
+        }
+        let sealed = &sealed[MAGIC.len()..];
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        symm::decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.0,
+            Some(nonce),
+            aad,
+            ciphertext,
+            tag,
+        )
+        .ok()
+    }
+
+    /// Produces the value that records that the catalog is encrypted with
+    /// this key.
+    pub(super) fn key_check(&self) -> Result<String, Error> {
+        Ok(hex::encode(self.seal(KEY_CHECK_AAD, KEY_CHECK_PLAINTEXT)?))
+    }
+
+    /// Reports whether `key_check`, as produced by
+    /// [`EncryptionKey::key_check`], was produced with this key.
+    pub(super) fn matches(&self, key_check: &str) -> bool {
+        match hex::decode(key_check) {
+            Ok(sealed) => {
+                self.unseal(KEY_CHECK_AAD, &sealed).as_deref() == Some(KEY_CHECK_PLAINTEXT)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EncryptionKey(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_roundtrip() {
+        let key = EncryptionKey::from_bytes(&[7; EncryptionKey::LEN]).unwrap();
+        let other = EncryptionKey::from_bytes(&[8; EncryptionKey::LEN]).unwrap();
+
+        let sealed = key.seal(b"u1", b"definition").unwrap();
+        assert_eq!(
+            key.unseal(b"u1", &sealed).as_deref(),
+            Some(&b"definition"[..])
+        );
+        assert_eq!(key.unseal(b"u2", &sealed), None);
+        assert_eq!(other.unseal(b"u1", &sealed), None);
+
+        // Nonces are never reused.
+        assert_ne!(sealed, key.seal(b"u1", b"definition").unwrap());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(key.unseal(b"u1", &tampered), None);
+        assert_eq!(key.unseal(b"u1", b"definition"), None);
+
+        let check = key.key_check().unwrap();
+        assert!(key.matches(&check));
+        assert!(!other.matches(&check));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let hex = "00".repeat(EncryptionKey::LEN);
+        assert_eq!(
+            EncryptionKey::from_hex(&format!(" {}\n", hex)).unwrap(),
+            EncryptionKey::from_bytes(&[0; EncryptionKey::LEN]).unwrap()
+        );
+        assert!(EncryptionKey::from_hex("00").is_err());
+        assert!(EncryptionKey::from_hex("zz").is_err());
+        assert_eq!(
+            format!(
+                "{:?}",
+                EncryptionKey::from_bytes(&[1; EncryptionKey::LEN]).unwrap()
+            ),
+            "EncryptionKey(<redacted>)"
+        );
+    }
+}
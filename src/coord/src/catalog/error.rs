@@ -8,12 +8,17 @@
 // by the Apache License, Version 2.0.
 
 use std::fmt;
+use std::io;
+use std::path::PathBuf;
 
 use backtrace::Backtrace;
 
 use ore::str::StrExt;
 use sql::catalog::CatalogError as SqlCatalogError;
 
+use crate::catalog::check::CheckReport;
+use crate::catalog::version::{self, CatalogVersion};
+
 #[derive(Debug)]
 pub struct Error {
     pub(crate) kind: ErrorKind,
@@ -44,6 +49,7 @@ pub enum ErrorKind {
         depender_name: String,
     },
     Storage(rusqlite::Error),
+    Io(io::Error),
     AmbiguousRename {
         depender: String,
         dependee: String,
@@ -56,6 +62,26 @@ pub enum ErrorKind {
         last_version: usize,
         cause: String,
     },
+    FailedCheck(CheckReport),
+    ReadOnlyCatalog(String),
+    EncryptionKeyRequired,
+    WrongEncryptionKey,
+    CatalogNotEncrypted,
+    Encryption(openssl::error::ErrorStack),
+    CatalogTooNew {
+        catalog: CatalogVersion,
+        server_version: String,
+    },
+    MigrationRequired {
+        catalog: CatalogVersion,
+        server_version: String,
+        migrations: Vec<String>,
+    },
+    StorageMismatch {
+        storage_directory: PathBuf,
+        catalog_directory: PathBuf,
+        missing: Vec<String>,
+    },
 }
 
 impl Error {
@@ -91,6 +117,12 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::new(ErrorKind::Io(e))
+    }
+}
+
 impl From<SqlCatalogError> for Error {
     fn from(e: SqlCatalogError) -> Error {
         Error::new(ErrorKind::Sql(e))
@@ -120,9 +152,19 @@ impl std::error::Error for Error {
             | ErrorKind::TypeRename(_)
             | ErrorKind::ExperimentalModeRequired
             | ErrorKind::ExperimentalModeUnavailable
-            | ErrorKind::FailedMigration { .. } => None,
+            | ErrorKind::FailedMigration { .. }
+            | ErrorKind::FailedCheck(_)
+            | ErrorKind::ReadOnlyCatalog(_)
+            | ErrorKind::EncryptionKeyRequired
+            | ErrorKind::WrongEncryptionKey
+            | ErrorKind::CatalogNotEncrypted
+            | ErrorKind::CatalogTooNew { .. }
+            | ErrorKind::MigrationRequired { .. }
+            | ErrorKind::StorageMismatch { .. } => None,
             ErrorKind::Sql(e) => Some(e),
             ErrorKind::Storage(e) => Some(e),
+            ErrorKind::Io(e) => Some(e),
+            ErrorKind::Encryption(e) => Some(e),
         }
     }
 }
@@ -174,6 +216,7 @@ impl fmt::Display for Error {
                 depender_name
             ),
             ErrorKind::Storage(e) => write!(f, "sqlite error: {}", e),
+            ErrorKind::Io(e) => write!(f, "catalog I/O error: {}", e),
             ErrorKind::AmbiguousRename {
                 depender,
                 dependee,
@@ -210,6 +253,89 @@ more details, see https://materialize.com/docs/cli#experimental-mode"#
                 "migration from catalog content version {} failed: {}",
                 last_version, cause,
             ),
+            ErrorKind::FailedCheck(report) => {
+                write!(f, "catalog failed integrity check:\n{}", report)
+            }
+            ErrorKind::ReadOnlyCatalog(detail) => {
+                write!(f, "cannot open catalog in read-only mode: {}", detail)
+            }
+            ErrorKind::EncryptionKeyRequired => {
+                write!(
+                    f,
+                    "catalog is encrypted, but no encryption key was provided"
+                )
+            }
+            ErrorKind::WrongEncryptionKey => {
+                write!(
+                    f,
+                    "catalog is not encrypted with the provided encryption key"
+                )
+            }
+            ErrorKind::CatalogNotEncrypted => write!(
+                f,
+                "an encryption key was provided, but the catalog is not encrypted; \
+                 encrypt it by running materialized with --encrypt-catalog"
+            ),
+            ErrorKind::Encryption(e) => write!(f, "catalog encryption error: {}", e),
+            ErrorKind::CatalogTooNew {
+                catalog,
+                server_version,
+            } => write!(
+                f,
+                "catalog ({}) was last opened by {}, and is newer than materialized {} \
+                 supports ({}); downgrades are not supported, so run a version of \
+                 materialized at least as new as the one that last opened the catalog, \
+                 or restore a catalog backup taken by materialized {} or older",
+                catalog,
+                catalog.describe_build(),
+                server_version,
+                version::current(),
+                server_version,
+            ),
+            ErrorKind::MigrationRequired {
+                catalog,
+                server_version,
+                migrations,
+            } => {
+                write!(
+                    f,
+                    "catalog ({}) was last opened by {}, and must be migrated to {} \
+                     before materialized {} can open it; the following migrations would run:",
+                    catalog,
+                    catalog.describe_build(),
+                    version::current(),
+                    server_version,
+                )?;
+                for migration in migrations {
+                    write!(f, "\n    {}", migration)?;
+                }
+                write!(
+                    f,
+                    "\nmigrations cannot be undone; to migrate the catalog, run materialized \
+                     with --allow-catalog-migration"
+                )
+            }
+            ErrorKind::StorageMismatch {
+                storage_directory,
+                catalog_directory,
+                missing,
+            } => {
+                write!(
+                    f,
+                    "storage directory {} does not belong to the catalog in {}; \
+                     the storage directory is missing:",
+                    storage_directory.display(),
+                    catalog_directory.display(),
+                )?;
+                for missing in missing {
+                    write!(f, "\n    {}", missing)?;
+                }
+                write!(
+                    f,
+                    "\nstart materialized with the storage directory that was last used with \
+                     this catalog"
+                )
+            }
         }
     }
 }
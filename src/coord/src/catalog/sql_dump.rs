@@ -0,0 +1,159 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Serialization of the catalog as executable SQL.
+
+use std::fmt::Write;
+
+use expr::GlobalId;
+use ore::collections::CollectionExt;
+use sql::ast::display::AstDisplay;
+use sql::ast::{
+    CreateDatabaseStatement, CreateSchemaStatement, Ident, IfExistsBehavior, Raw, Statement,
+    UnresolvedObjectName,
+};
+use sql::catalog::CatalogItem as SqlCatalogItem;
+
+use crate::catalog::{Catalog, CatalogEntry, CatalogItem};
+
+/// The database that exists in every freshly initialized catalog.
+const DEFAULT_DATABASE: &str = "materialize";
+
+/// The schema that exists in every database.
+const DEFAULT_SCHEMA: &str = "public";
+
+/// Configures [`Catalog::dump_sql`].
+#[derive(Debug, Clone, Default)]
+pub struct SqlDumpOptions {
+    /// Whether to include `IF NOT EXISTS` in each statement that supports it,
+    /// so that the dump can be replayed against a catalog that already
+    /// contains some of its objects.
+    pub if_not_exists: bool,
+    /// Whether to redact the credentials embedded in source and sink
+    /// definitions, as by [`sql::parse::redact_credentials`].
+    pub redact_credentials: bool,
+}
+
+impl Catalog {
+    /// Serializes the catalog's user objects as a sequence of SQL statements
+    /// that recreates them when executed against a fresh catalog.
+    ///
+    /// Databases and schemas are emitted first, followed by items in the
+    /// order in which they were created, which is the order in which the
+    /// catalog itself reloads them and so respects their dependencies.
+    /// Temporary items are omitted. Items that cannot be recreated by their
+    /// SQL alone are emitted as comments that explain why.
+    ///
+    /// The default database and the `public` schema of each database exist in
+    /// every catalog and so are always emitted with `IF NOT EXISTS`.
+    pub fn dump_sql(&self, options: &SqlDumpOptions) -> String {
+        let mut out = String::new();
+        let build_info = self.config.build_info;
+        let _ = writeln!(out, "-- Materialize catalog dump");
+        let _ = writeln!(out, "-- cluster ID: {}", self.config.cluster_id);
+        let _ = writeln!(
+            out,
+            "-- version: {} ({})",
+            build_info.version, build_info.sha
+        );
+        out.push('\n');
+
+        for database in self.by_name.values() {
+            let stmt = Statement::<Raw>::CreateDatabase(CreateDatabaseStatement {
+                name: Ident::new(database.name.clone()),
+                if_not_exists: options.if_not_exists || database.name == DEFAULT_DATABASE,
+            });
+            let _ = writeln!(out, "{};", stmt.to_ast_string_stable());
+            for schema in database.schemas.keys() {
+                if schema == DEFAULT_SCHEMA {
+                    continue;
+                }
+                let stmt = Statement::<Raw>::CreateSchema(CreateSchemaStatement {
+                    name: UnresolvedObjectName(vec![
+                        Ident::new(database.name.clone()),
+                        Ident::new(schema.clone()),
+                    ]),
+                    if_not_exists: options.if_not_exists,
+                });
+                let _ = writeln!(out, "{};", stmt.to_ast_string_stable());
+            }
+        }
+
+        for entry in self.by_id.values() {
+            if !entry.id().is_user() || entry.item().is_temporary() {
+                continue;
+            }
+            out.push('\n');
+            if let Some(reason) = self.unrecreatable_reason(entry) {
+                let _ = writeln!(
+                    out,
+                    "-- {} {} {}.",
+                    entry.item().typ(),
+                    entry.name(),
+                    reason
+                );
+                continue;
+            }
+            let mut stmt = sql::parse::parse(entry.create_sql())
+                .expect("create_sql cannot be invalid")
+                .into_element();
+            if options.if_not_exists {
+                set_if_not_exists(&mut stmt);
+            }
+            if options.redact_credentials {
+                sql::parse::redact_credentials(&mut stmt);
+            }
+            let _ = writeln!(out, "{};", stmt.to_ast_string_stable());
+        }
+        out
+    }
+
+    /// Reports why `entry` cannot be recreated by executing its SQL, if that
+    /// is the case.
+    fn unrecreatable_reason(&self, entry: &CatalogEntry) -> Option<&'static str> {
+        match entry.item() {
+            // Creating a table also creates its default index, whose ID is
+            // allocated immediately after the table's.
+            CatalogItem::Index(index) => match (index.on, entry.id()) {
+                (GlobalId::User(on), GlobalId::User(id))
+                    if id == on + 1 && self.get_by_id(&index.on).is_table() =>
+                {
+                    Some("is created by the statement that creates the table it indexes")
+                }
+                _ => None,
+            },
+            CatalogItem::Sink(_) => {
+                match sql::parse::parse(entry.create_sql()).map(|stmts| stmts.into_element()) {
+                    Ok(Statement::CreateSink(stmt)) if stmt.as_of.is_some() => Some(
+                        "was created AS OF a timestamp that is only meaningful to this cluster",
+                    ),
+                    _ => None,
+                }
+            }
+            CatalogItem::Table(_)
+            | CatalogItem::Source(_)
+            | CatalogItem::View(_)
+            | CatalogItem::Type(_)
+            | CatalogItem::Func(_) => None,
+        }
+    }
+}
+
+/// Makes `stmt` a no-op if the object it creates already exists, if the
+/// statement supports doing so.
+fn set_if_not_exists(stmt: &mut Statement<Raw>) {
+    match stmt {
+        Statement::CreateSource(stmt) => stmt.if_not_exists = true,
+        Statement::CreateTable(stmt) => stmt.if_not_exists = true,
+        Statement::CreateSink(stmt) => stmt.if_not_exists = true,
+        Statement::CreateView(stmt) => stmt.if_exists = IfExistsBehavior::Skip,
+        Statement::CreateIndex(stmt) => stmt.if_not_exists = true,
+        _ => (),
+    }
+}
@@ -8,11 +8,17 @@
 // by the Apache License, Version 2.0.
 
 use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
 
+use log::warn;
 use rusqlite::params;
 use rusqlite::types::{FromSql, FromSqlError, ToSql, ToSqlOutput, Value, ValueRef};
-use rusqlite::OptionalExtension;
+use rusqlite::{OpenFlags, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 use dataflow_types::MzOffset;
 use expr::{GlobalId, PartitionId};
@@ -22,17 +28,30 @@ use sql::catalog::CatalogError as SqlCatalogError;
 use sql::names::{DatabaseSpecifier, FullName};
 use uuid::Uuid;
 
-use crate::catalog::config::Config;
+use crate::catalog::config::{Config, DurabilityLevel};
+use crate::catalog::encryption::EncryptionKey;
 use crate::catalog::error::{Error, ErrorKind};
+use crate::catalog::version::CatalogVersion;
 
-const APPLICATION_ID: i32 = 0x1854_47dc;
+pub(super) const APPLICATION_ID: i32 = 0x1854_47dc;
+
+/// A schema migration for the on-disk state.
+pub(super) struct Migration {
+    /// A short summary of the migration's purpose, as reported to users
+    /// before the migration is run.
+    pub(super) summary: &'static str,
+    /// The SQL that performs the migration.
+    sql: &'static str,
+}
 
 /// Schema migrations for the on-disk state.
-const MIGRATIONS: &[&str] = &[
+pub(super) const MIGRATIONS: &[Migration] = &[
     // Creates initial schema.
     //
     // Introduced for v0.1.0.
-    "CREATE TABLE gid_alloc (
+    Migration {
+        summary: "create initial schema",
+        sql: "CREATE TABLE gid_alloc (
          next_gid integer NOT NULL
      );
 
@@ -70,6 +89,7 @@ const MIGRATIONS: &[&str] = &[
          (1, NULL, 'mz_catalog'),
          (2, NULL, 'pg_catalog'),
          (3, 1, 'public');",
+    },
     // Adjusts timestamp table to support multi-partition Kafka topics.
     //
     // Introduced for v0.1.4.
@@ -77,7 +97,9 @@ const MIGRATIONS: &[&str] = &[
     // ATTENTION: this migration blows away data and must not be used as a model
     // for future migrations! It is only acceptable now because we have not yet
     // made any consistency promises to users.
-    "DROP TABLE timestamps;
+    Migration {
+        summary: "adjust timestamp table to support multi-partition Kafka topics",
+        sql: "DROP TABLE timestamps;
      CREATE TABLE timestamps (
         sid blob NOT NULL,
         vid blob NOT NULL,
@@ -87,26 +109,36 @@ const MIGRATIONS: &[&str] = &[
         offset blob NOT NULL,
         PRIMARY KEY (sid, vid, pid, timestamp)
     );",
+    },
     // Introduces settings table to support persistent node settings.
     //
     // Introduced in v0.4.0.
-    "CREATE TABLE settings (
+    Migration {
+        summary: "introduce settings table",
+        sql: "CREATE TABLE settings (
         name TEXT PRIMARY KEY,
         value TEXT
     );",
+    },
     // Creates the roles table and a default "materialize" user.
     //
     // Introduced in v0.7.0.
-    "CREATE TABLE roles (
+    Migration {
+        summary: "create roles table",
+        sql: "CREATE TABLE roles (
         id   integer PRIMARY KEY,
         name text NOT NULL UNIQUE
     );
     INSERT INTO roles VALUES (1, 'materialize');",
+    },
     // Makes the mz_internal schema literal so it can store functions.
     //
     // Introduced in v0.7.0.
-    "INSERT INTO schemas (database_id, name) VALUES
+    Migration {
+        summary: "make mz_internal schema literal",
+        sql: "INSERT INTO schemas (database_id, name) VALUES
         (NULL, 'mz_internal');",
+    },
     // Adjusts timestamp table to support replayable source timestamp bindings.
     //
     // Introduced for v0.7.4
@@ -114,7 +146,9 @@ const MIGRATIONS: &[&str] = &[
     // ATTENTION: this migration blows away data and must not be used as a model
     // for future migrations! It is only acceptable now because we have not yet
     // made any consistency promises to users.
-    "DROP TABLE timestamps;
+    Migration {
+        summary: "adjust timestamp table to support replayable source timestamp bindings",
+        sql: "DROP TABLE timestamps;
      CREATE TABLE timestamps (
         sid blob NOT NULL,
         pid blob NOT NULL,
@@ -122,6 +156,7 @@ const MIGRATIONS: &[&str] = &[
         offset blob NOT NULL,
         PRIMARY KEY (sid, pid, timestamp, offset)
     );",
+    },
     // Add new migrations here.
     //
     // Migrations should be preceded with a comment of the following form:
@@ -132,6 +167,9 @@ const MIGRATIONS: &[&str] = &[
     //     >
     //     > Optional additional commentary about safety or approach.
     //
+    // The migration's `summary` is reported to users before the migration is
+    // run, so it should be phrased for someone who has not read this code.
+    //
     // Please include @benesch on any code reviews that add or edit migrations.
     // Migrations must preserve backwards compatibility with all past releases
     // of materialized. Migrations can be edited up until they ship in a
@@ -139,25 +177,162 @@ const MIGRATIONS: &[&str] = &[
     // migrations.
 ];
 
+/// The version of the catalog's schema produced by this version of the code,
+/// i.e., the index of the last migration in [`MIGRATIONS`].
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 - 1;
+
+/// Reads the versions of the catalog database at `path` without modifying
+/// it.
+///
+/// Returns `None` if there is no catalog at `path`, or if the catalog was
+/// never initialized.
+pub fn read_version(path: &Path) -> Result<Option<CatalogVersion>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let sqlite = rusqlite::Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let app_id: i32 = sqlite.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
+    if app_id == 0 {
+        return Ok(None);
+    } else if app_id != APPLICATION_ID {
+        return Err(Error::new(ErrorKind::Corruption {
+            detail: "catalog file has incorrect application_id".into(),
+        }));
+    }
+    Ok(Some(Connection::get_version(&sqlite)?))
+}
+
+/// Writes a consistent copy of the catalog database at `path` to a new
+/// database file at `dest` without modifying it.
+///
+/// `dest` must not exist or must be empty.
+pub fn copy(path: &Path, dest: &Path) -> Result<(), Error> {
+    let sqlite = rusqlite::Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    sqlite.execute("VACUUM INTO ?", params![dest.to_string_lossy()])?;
+    Ok(())
+}
+
+/// Prepares the catalog database at `path`, which was restored from a backup,
+/// to be opened by [`Connection::open`].
+///
+/// The timestamp bindings of all sources are discarded, so that sources
+/// re-ingest their data from their configured offsets rather than resuming
+/// from bindings that may not match the state of the upstream systems. If
+/// `regenerate_cluster_id` is true, the catalog's `cluster_id` setting is
+/// also discarded, so that a new cluster ID is generated when the catalog is
+/// next opened.
+pub fn prepare_restore(path: &Path, regenerate_cluster_id: bool) -> Result<(), Error> {
+    let mut sqlite = rusqlite::Connection::open(path)?;
+    let tx = sqlite.transaction()?;
+    let app_id: i32 = tx.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
+    if app_id != APPLICATION_ID {
+        return Err(Error::new(ErrorKind::Corruption {
+            detail: "catalog file has incorrect application_id".into(),
+        }));
+    }
+    tx.execute("DELETE FROM timestamps", params![])?;
+    if regenerate_cluster_id {
+        tx.execute("DELETE FROM settings WHERE name = 'cluster_id'", params![])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Encrypts the unencrypted catalog database at `path` with `key`.
+///
+/// The catalog is migrated to the current schema version, if necessary, and
+/// then the definition of every item is encrypted in a single transaction.
+/// Finally, the database is vacuumed so that no plaintext definitions linger
+/// in its free pages. Encrypting a catalog that is already encrypted with
+/// `key` has no effect.
+pub fn encrypt(path: &Path, key: &EncryptionKey) -> Result<(), Error> {
+    let mut sqlite = rusqlite::Connection::open(path)?;
+    let app_id: i32 = sqlite.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
+    if app_id != APPLICATION_ID {
+        return Err(Error::new(ErrorKind::Corruption {
+            detail: "catalog file has incorrect application_id".into(),
+        }));
+    }
+    Connection::migrate(&mut sqlite)?;
+    match Connection::get_key_check(&sqlite)? {
+        Some(key_check) if key.matches(&key_check) => return Ok(()),
+        Some(_) => return Err(Error::new(ErrorKind::WrongEncryptionKey)),
+        None => (),
+    }
+    let tx = sqlite.transaction()?;
+    rewrite_definitions(&tx, |aad, definition| key.seal(aad, &definition))?;
+    tx.execute(
+        "INSERT INTO settings VALUES ('encryption_key_check', ?);",
+        params![key.key_check()?],
+    )?;
+    tx.commit()?;
+    sqlite.execute_batch("VACUUM")?;
+    Ok(())
+}
+
+/// Replaces the definition of every item in the catalog with the result of
+/// applying `f` to the item's associated data and its current definition.
+fn rewrite_definitions<F>(tx: &rusqlite::Transaction, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(&[u8], Vec<u8>) -> Result<Vec<u8>, Error>,
+{
+    let items: Vec<(SqlVal<GlobalId>, Vec<u8>)> = tx
+        .prepare("SELECT gid, definition FROM items")?
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, definition) in items {
+        let definition = f(&item_aad(id.0), definition)?;
+        tx.execute(
+            "UPDATE items SET definition = ? WHERE gid = ?",
+            params![definition, id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the associated data to which the encrypted definition of the item
+/// with the specified ID is bound.
+pub(super) fn item_aad(id: GlobalId) -> Vec<u8> {
+    serde_json::to_vec(&id).expect("serializing a GlobalId cannot fail")
+}
+
 #[derive(Debug)]
 pub struct Connection {
     inner: rusqlite::Connection,
+    path: PathBuf,
+    durability: Option<DurabilityLevel>,
+    encryption_key: Option<EncryptionKey>,
+    /// Counts the committed changes to the catalog's definitions. The
+    /// connection holds a receiver so that sending never fails.
+    changes_tx: watch::Sender<u64>,
+    changes_rx: watch::Receiver<u64>,
 }
 
 impl Connection {
     pub fn open(config: &Config) -> Result<(Connection, bool, Uuid), Error> {
+        if config.read_only {
+            return Self::open_read_only(config);
+        }
+
         let mut sqlite = rusqlite::Connection::open(&config.path)?;
 
+        // Configure durability before making any writes. Changing the journal
+        // mode must happen outside of a transaction.
+        if let Some(durability) = config.durability {
+            Self::configure_durability(&sqlite, durability)?;
+        }
+
         // Validate application ID.
         let tx = sqlite.transaction()?;
         let app_id: i32 = tx.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
-        if app_id == 0 {
+        let fresh = app_id == 0;
+        if fresh {
             // Fresh catalog, so install the correct ID. We also apply the
             // zeroth migration for historical reasons: the default
             // `user_version` of zero indicates that the zeroth migration has
             // been applied.
             tx.execute_batch(&format!("PRAGMA application_id = {}", APPLICATION_ID))?;
-            tx.execute_batch(MIGRATIONS[0])?;
+            tx.execute_batch(MIGRATIONS[0].sql)?;
         } else if app_id != APPLICATION_ID {
             return Err(Error::new(ErrorKind::Corruption {
                 detail: "catalog file has incorrect application_id".into(),
@@ -165,25 +340,196 @@ impl Connection {
         };
         tx.commit()?;
 
-        // Run unapplied migrations. The `user_version` field stores the index
-        // of the last migration that was run.
+        // Migrating a catalog written by a newer version of the code would do
+        // nothing, and its contents would then be misinterpreted. Whether an
+        // older catalog may be migrated is up to the caller.
+        Self::get_version(&sqlite)?.check_compatible(config.build_info.version, true)?;
+        Self::migrate(&mut sqlite)?;
+
+        // A fresh catalog is encrypted with the configured key, if any. An
+        // existing catalog is left as it is, as encrypting it requires an
+        // explicit call to `encrypt`.
+        if fresh {
+            if let Some(key) = &config.encryption_key {
+                sqlite.execute(
+                    "INSERT INTO settings VALUES ('encryption_key_check', ?);",
+                    params![key.key_check()?],
+                )?;
+            }
+        }
+        Self::verify_encryption_key(&sqlite, config.encryption_key.as_ref())?;
+
+        let experimental_mode =
+            Self::set_or_get_experimental_mode(&mut sqlite, config.experimental_mode)?;
+        let cluster_id = Self::set_or_get_cluster_id(&mut sqlite)?;
+        if let Some(durability) = config.durability {
+            Self::set_durability(&mut sqlite, durability)?;
+        }
+
+        let (changes_tx, changes_rx) = watch::channel(0);
+        Ok((
+            Connection {
+                inner: sqlite,
+                path: config.path.clone(),
+                durability: config.durability,
+                encryption_key: config.encryption_key.clone(),
+                changes_tx,
+                changes_rx,
+            },
+            experimental_mode,
+            cluster_id,
+        ))
+    }
+
+    /// Runs unapplied schema migrations.
+    fn migrate(sqlite: &mut rusqlite::Connection) -> Result<(), Error> {
+        // The `user_version` field stores the index of the last migration
+        // that was run.
         let version: u32 = sqlite.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
-        for (i, sql) in MIGRATIONS
+        for (i, migration) in MIGRATIONS
             .iter()
             .enumerate()
             .skip(usize::cast_from(version) + 1)
         {
             let tx = sqlite.transaction()?;
-            tx.execute_batch(sql)?;
+            tx.execute_batch(migration.sql)?;
             tx.execute_batch(&format!("PRAGMA user_version = {}", i))?;
             tx.commit()?;
         }
+        Ok(())
+    }
+
+    /// Returns the versions of the catalog.
+    fn get_version(sqlite: &rusqlite::Connection) -> Result<CatalogVersion, Error> {
+        let schema_version: u32 =
+            sqlite.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+        // The settings table was introduced by the migration to schema
+        // version 2. Older catalogs have no settings.
+        if schema_version < 2 {
+            return Ok(CatalogVersion {
+                schema_version,
+                content_version: 0,
+                build_version: None,
+            });
+        }
+        let content_version: Option<u32> = sqlite
+            .query_row(
+                "SELECT CAST(value AS int) FROM settings WHERE name = 'catalog_content_version';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let build_version: Option<String> = sqlite
+            .query_row(
+                "SELECT value FROM settings WHERE name = 'build_version';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(CatalogVersion {
+            schema_version,
+            content_version: content_version.unwrap_or(0),
+            build_version,
+        })
+    }
+
+    /// Returns the value that records the key with which the catalog is
+    /// encrypted, or `None` if the catalog is not encrypted.
+    fn get_key_check(sqlite: &rusqlite::Connection) -> Result<Option<String>, Error> {
+        Ok(sqlite
+            .query_row(
+                "SELECT value FROM settings WHERE name = 'encryption_key_check';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
 
+    /// Verifies that the catalog is encrypted with `key`, or is unencrypted
+    /// if `key` is `None`.
+    ///
+    /// When the catalog is encrypted, SQLite is additionally configured to
+    /// overwrite deleted content, so that definitions that are rewritten
+    /// under a new key do not linger on disk.
+    fn verify_encryption_key(
+        sqlite: &rusqlite::Connection,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), Error> {
+        match (Self::get_key_check(sqlite)?, key) {
+            (None, None) => return Ok(()),
+            (None, Some(_)) => return Err(Error::new(ErrorKind::CatalogNotEncrypted)),
+            (Some(_), None) => return Err(Error::new(ErrorKind::EncryptionKeyRequired)),
+            (Some(key_check), Some(key)) if !key.matches(&key_check) => {
+                return Err(Error::new(ErrorKind::WrongEncryptionKey))
+            }
+            (Some(_), Some(_)) => (),
+        }
+        sqlite.execute_batch("PRAGMA secure_delete = ON")?;
+        Ok(())
+    }
+
+    /// Like [`Connection::open`], but opens the catalog without ever writing
+    /// to it.
+    ///
+    /// The catalog must already be initialized and fully migrated, as neither
+    /// is possible without writing to it.
+    fn open_read_only(config: &Config) -> Result<(Connection, bool, Uuid), Error> {
+        let read_only_error = |detail: String| Err(Error::new(ErrorKind::ReadOnlyCatalog(detail)));
+
+        if !config.path.exists() {
+            return read_only_error(format!("{} does not exist", config.path.display()));
+        }
+        let mut sqlite =
+            rusqlite::Connection::open_with_flags(&config.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        let app_id: i32 = sqlite.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
+        if app_id == 0 {
+            return read_only_error("catalog is not initialized".into());
+        } else if app_id != APPLICATION_ID {
+            return Err(Error::new(ErrorKind::Corruption {
+                detail: "catalog file has incorrect application_id".into(),
+            }));
+        }
+
+        let version = Self::get_version(&sqlite)?;
+        version.check_compatible(config.build_info.version, true)?;
+        if version.schema_version < SCHEMA_VERSION {
+            return read_only_error(format!(
+                "catalog schema version {} must be migrated to version {}",
+                version.schema_version, SCHEMA_VERSION
+            ));
+        }
+
+        // Reading the settings never writes them, as they were installed when
+        // the catalog was initialized.
         let experimental_mode =
             Self::set_or_get_experimental_mode(&mut sqlite, config.experimental_mode)?;
-        let cluster_id = Self::set_or_get_cluster_id(&mut sqlite)?;
+        let cluster_id: Option<SqlVal<Uuid>> = sqlite
+            .query_row(
+                "SELECT value FROM settings WHERE name = 'cluster_id';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let cluster_id = match cluster_id {
+            Some(cluster_id) => cluster_id.0,
+            None => return read_only_error("catalog has no cluster ID".into()),
+        };
+        Self::verify_encryption_key(&sqlite, config.encryption_key.as_ref())?;
 
-        Ok((Connection { inner: sqlite }, experimental_mode, cluster_id))
+        let (changes_tx, changes_rx) = watch::channel(0);
+        Ok((
+            Connection {
+                inner: sqlite,
+                path: config.path.clone(),
+                durability: config.durability,
+                encryption_key: config.encryption_key.clone(),
+                changes_tx,
+                changes_rx,
+            },
+            experimental_mode,
+            cluster_id,
+        ))
     }
 
     /// Sets catalog's `experimental_mode` setting on initialization or gets
@@ -248,6 +594,75 @@ impl Connection {
         res
     }
 
+    /// Configures SQLite to provide the guarantees of `durability`.
+    ///
+    /// At [`DurabilityLevel::Async`], the catalog is placed in WAL mode, in
+    /// which commits are not synced until the WAL is checkpointed by
+    /// [`Connection::sync`]. The other levels use a rollback journal. At
+    /// [`DurabilityLevel::Full`], SQLite syncs the journal and the directory
+    /// that contains it on every commit, and verifies pages as they are read.
+    /// At [`DurabilityLevel::None`], SQLite never syncs.
+    fn configure_durability(
+        sqlite: &rusqlite::Connection,
+        durability: DurabilityLevel,
+    ) -> Result<(), Error> {
+        let (journal_mode, pragmas) = match durability {
+            DurabilityLevel::Full => (
+                "DELETE",
+                "PRAGMA synchronous = EXTRA; PRAGMA cell_size_check = ON;",
+            ),
+            DurabilityLevel::Async => ("WAL", "PRAGMA synchronous = NORMAL;"),
+            DurabilityLevel::None => ("DELETE", "PRAGMA synchronous = OFF;"),
+        };
+        // Setting the journal mode reports the new mode as a row.
+        sqlite.query_row(
+            &format!("PRAGMA journal_mode = {}", journal_mode),
+            params![],
+            |_| Ok(()),
+        )?;
+        sqlite.execute_batch(pragmas)?;
+        Ok(())
+    }
+
+    /// Records `durability` as the catalog's `durability` setting, warning if
+    /// the catalog was previously written at a less durable level.
+    ///
+    /// Catalogs that predate the setting were always written at
+    /// [`DurabilityLevel::Full`].
+    fn set_durability(
+        sqlite: &mut rusqlite::Connection,
+        durability: DurabilityLevel,
+    ) -> Result<(), Error> {
+        let tx = sqlite.transaction()?;
+        let current_setting: Option<String> = tx
+            .query_row(
+                "SELECT value FROM settings WHERE name = 'durability';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let previous = match current_setting {
+            None => DurabilityLevel::Full,
+            Some(cs) => cs
+                .parse::<DurabilityLevel>()
+                .map_err(|detail| Error::new(ErrorKind::Corruption { detail }))?,
+        };
+        if previous < durability {
+            warn!(
+                "catalog was previously written with durability level {}; \
+                 writes made at that level may have been lost if the host \
+                 crashed before they were synced",
+                previous
+            );
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings VALUES ('durability', ?);",
+            params![durability.as_str()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
     /// Sets catalog's `cluster_id` setting on initialization or gets that value.
     fn set_or_get_cluster_id(sqlite: &mut rusqlite::Connection) -> Result<Uuid, Error> {
         let tx = sqlite.transaction()?;
@@ -312,6 +727,134 @@ impl Connection {
         Ok(())
     }
 
+    /// Records that materialized `build_version` opened the catalog.
+    pub fn set_build_version(&mut self, build_version: &str) -> Result<(), Error> {
+        self.inner.execute(
+            "INSERT OR REPLACE INTO settings VALUES ('build_version', ?);",
+            params![build_version],
+        )?;
+        Ok(())
+    }
+
+    /// Syncs all committed writes to disk.
+    ///
+    /// This is only necessary at [`DurabilityLevel::Async`]. At the other
+    /// levels, writes are either synced as they are committed or never synced
+    /// at all.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.inner
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", params![], |_| Ok(()))?;
+        Ok(())
+    }
+
+    /// Writes a consistent copy of the catalog to a new database file at
+    /// `path`.
+    ///
+    /// Returns the version of the catalog's schema, i.e., the index of the
+    /// last migration that was applied, and the version of its contents.
+    pub fn backup(&self, path: &str) -> Result<(u32, u32), Error> {
+        self.inner.execute("VACUUM INTO ?", params![path])?;
+        let schema_version: u32 =
+            self.inner
+                .query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+        let content_version: Option<u32> = self
+            .inner
+            .query_row(
+                "SELECT CAST(value AS int) FROM settings WHERE name = 'catalog_content_version';",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok((schema_version, content_version.unwrap_or(0)))
+    }
+
+    /// Rewrites the catalog database to its minimal form.
+    ///
+    /// A compacted copy of the database is written alongside it and synced,
+    /// and is then renamed over the original, so that a crash at any point
+    /// leaves either the original or the compacted database in place. The
+    /// connection is reopened against the compacted database.
+    ///
+    /// Returns the size of the database, including its write-ahead log, before
+    /// and after compaction.
+    pub fn compact(&mut self) -> Result<(u64, u64), Error> {
+        let size_before = self.size()?;
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".compact");
+        let tmp_path = self.path.with_file_name(tmp_name);
+
+        // A copy left behind by an interrupted compaction is incomplete.
+        match fs::remove_file(&tmp_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+
+        // Checkpointing first means that, once the connection is closed,
+        // nothing remains in the write-ahead log that the compacted database
+        // does not contain.
+        self.inner
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(()))?;
+        self.inner
+            .execute("VACUUM INTO ?", params![tmp_path.to_string_lossy()])?;
+        File::open(&tmp_path)?.sync_all()?;
+
+        let old = mem::replace(&mut self.inner, rusqlite::Connection::open_in_memory()?);
+        if let Err((old, e)) = old.close() {
+            self.inner = old;
+            return Err(e.into());
+        }
+        let swapped = self.swap(&tmp_path);
+        // Reopen even if the swap failed, in which case the original database
+        // is still in place.
+        self.inner = rusqlite::Connection::open(&self.path)?;
+        if let Some(durability) = self.durability {
+            Self::configure_durability(&self.inner, durability)?;
+        }
+        Self::verify_encryption_key(&self.inner, self.encryption_key.as_ref())?;
+        swapped?;
+
+        Ok((size_before, self.size()?))
+    }
+
+    /// Renames the compacted database at `tmp_path` over the catalog database.
+    ///
+    /// Must only be called while no connection to the catalog database is
+    /// open.
+    fn swap(&self, tmp_path: &Path) -> Result<(), Error> {
+        // The write-ahead log and shared-memory index were checkpointed before
+        // the connection closed, and must not be applied to the compacted
+        // database.
+        for suffix in &["-wal", "-shm"] {
+            match fs::remove_file(self.sidecar_path(suffix)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        fs::rename(tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the catalog database, including its write-ahead
+    /// log.
+    fn size(&self) -> Result<u64, Error> {
+        let mut size = fs::metadata(&self.path)?.len();
+        match fs::metadata(self.sidecar_path("-wal")) {
+            Ok(metadata) => size += metadata.len(),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => (),
+        }
+        Ok(size)
+    }
+
+    fn sidecar_path(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
     pub fn load_databases(&self) -> Result<Vec<(i64, String)>, Error> {
         self.inner
             .prepare("SELECT id, name FROM databases")?
@@ -350,6 +893,31 @@ impl Connection {
             .collect()
     }
 
+    /// Reencrypts the catalog with `key`.
+    ///
+    /// The definition of every item is decrypted with the current key and
+    /// encrypted with `key` in a single transaction, so a failure leaves the
+    /// catalog encrypted with the current key.
+    pub fn reencrypt(&mut self, key: EncryptionKey) -> Result<(), Error> {
+        let current_key = match &self.encryption_key {
+            Some(current_key) => current_key,
+            None => return Err(Error::new(ErrorKind::CatalogNotEncrypted)),
+        };
+        let tx = self.inner.transaction()?;
+        rewrite_definitions(&tx, |aad, definition| {
+            let definition = decrypt_definition(current_key, aad, &definition)?;
+            key.seal(aad, &definition)
+        })?;
+        tx.execute(
+            "UPDATE settings SET value = ? WHERE name = 'encryption_key_check';",
+            params![key.key_check()?],
+        )?;
+        tx.commit()?;
+        self.encryption_key = Some(key);
+        self.note_change();
+        Ok(())
+    }
+
     pub fn load_items(&self) -> Result<Vec<(GlobalId, FullName, Vec<u8>)>, Error> {
         // Order user views by their GlobalId
         self.inner
@@ -365,7 +933,10 @@ impl Connection {
                 let database: Option<String> = row.get(1)?;
                 let schema: String = row.get(2)?;
                 let item: String = row.get(3)?;
-                let definition: Vec<u8> = row.get(4)?;
+                let mut definition: Vec<u8> = row.get(4)?;
+                if let Some(key) = &self.encryption_key {
+                    definition = decrypt_definition(key, &item_aad(id.0), &definition)?;
+                }
                 Ok((
                     id.0,
                     FullName {
@@ -394,15 +965,49 @@ impl Connection {
         Ok(GlobalId::User(id as u64))
     }
 
+    /// Records that a change to the catalog's definitions was committed.
+    ///
+    /// Changes to timestamp bindings are not recorded, as they are not
+    /// carried over when the catalog is restored elsewhere.
+    pub fn note_change(&self) {
+        let changes = *self.changes_rx.borrow() + 1;
+        // Sending cannot fail, as `self` holds a receiver.
+        let _ = self.changes_tx.send(changes);
+    }
+
+    /// Returns a receiver that observes the number of changes to the
+    /// catalog's definitions committed since the catalog was opened.
+    pub fn subscribe_changes(&self) -> watch::Receiver<u64> {
+        self.changes_rx.clone()
+    }
+
     pub fn transaction(&mut self) -> Result<Transaction, Error> {
         Ok(Transaction {
             inner: self.inner.transaction()?,
+            encryption_key: self.encryption_key.as_ref(),
         })
     }
 }
 
+/// Decrypts the definition of an item.
+///
+/// The catalog's key has already been verified, so a definition that cannot
+/// be decrypted indicates corruption rather than a wrong key.
+fn decrypt_definition(
+    key: &EncryptionKey,
+    aad: &[u8],
+    definition: &[u8],
+) -> Result<Vec<u8>, Error> {
+    key.unseal(aad, definition).ok_or_else(|| {
+        Error::new(ErrorKind::Corruption {
+            detail: "item definition cannot be decrypted".into(),
+        })
+    })
+}
+
 pub struct Transaction<'a> {
     inner: rusqlite::Transaction<'a>,
+    encryption_key: Option<&'a EncryptionKey>,
 }
 
 impl Transaction<'_> {
@@ -502,6 +1107,7 @@ impl Transaction<'_> {
         item_name: &str,
         item: &[u8],
     ) -> Result<(), Error> {
+        let item = self.encrypt_definition(id, item)?;
         match self
             .inner
             .prepare_cached(
@@ -632,6 +1238,7 @@ impl Transaction<'_> {
     }
 
     pub fn update_item(&self, id: GlobalId, item_name: &str, item: &[u8]) -> Result<(), Error> {
+        let item = self.encrypt_definition(id, item)?;
         let n = self
             .inner
             .prepare_cached("UPDATE items SET name = ?, definition = ? WHERE gid = ?")?
@@ -645,8 +1252,22 @@ impl Transaction<'_> {
     }
 
     pub fn commit(self) -> Result<(), rusqlite::Error> {
+        // Fails the write as if the disk had.
+        fail::fail_point!("catalog_write", |_| Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_IOERR),
+            Some("injected catalog write failure".into()),
+        )));
         self.inner.commit()
     }
+
+    /// Encrypts the definition of the item with the specified ID, if the
+    /// catalog is encrypted.
+    fn encrypt_definition(&self, id: GlobalId, item: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.encryption_key {
+            Some(key) => key.seal(&item_aad(id), item),
+            None => Ok(item.to_vec()),
+        }
+    }
 }
 
 fn is_constraint_violation(err: &rusqlite::Error) -> bool {
@@ -0,0 +1,117 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Pairing of the catalog with the storage directory.
+//!
+//! The catalog may live in a different directory than the data of the
+//! objects it describes, so that the catalog can be placed on replicated
+//! storage while bulky data lives on cheap local disk. The storage directory
+//! is marked with the cluster ID of the catalog that it belongs to, in the
+//! [`MARKER_FILE`], so that booting with a catalog and a storage directory
+//! that do not belong together fails rather than silently serving from
+//! missing data.
+//!
+//! A storage directory without a marker is adopted by a new catalog. A
+//! storage directory that is also the catalog directory, as all storage
+//! directories were before they could be split from the catalog, always
+//! belongs to the catalog.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::catalog::error::{Error, ErrorKind};
+
+/// The name of the file in the storage directory that records the cluster ID
+/// of the catalog that the directory belongs to.
+pub const MARKER_FILE: &str = "storage_cluster_id";
+
+/// Verifies that `storage_directory` belongs to the catalog with the given
+/// `cluster_id` in `catalog_directory`, marking the directory if it is not
+/// yet marked.
+///
+/// `fresh` indicates whether the catalog was created by this boot.
+/// `file_references` are the files that the objects in the catalog refer to,
+/// as returned by [`Catalog::file_references`](super::Catalog::file_references);
+/// those that do not exist are reported if the directory does not belong to
+/// the catalog. In read-only mode, the directory is never marked.
+///
+/// This function performs blocking I/O.
+pub fn verify(
+    storage_directory: &Path,
+    catalog_directory: &Path,
+    cluster_id: Uuid,
+    fresh: bool,
+    file_references: &[(String, PathBuf)],
+    read_only: bool,
+) -> Result<(), Error> {
+    let marker_path = storage_directory.join(MARKER_FILE);
+    let mut missing = vec![];
+    match fs::read_to_string(&marker_path) {
+        Ok(marker) if marker.trim() == cluster_id.to_string() => return Ok(()),
+        Ok(_) | Err(_) if same_directory(storage_directory, catalog_directory) => {
+            // A catalog and a storage directory that share a directory
+            // cannot be mismatched, even if the catalog was restored from a
+            // backup with a new cluster ID.
+            if !read_only {
+                write_marker(storage_directory, cluster_id)?;
+            }
+            return Ok(());
+        }
+        Ok(marker) => missing.push(format!(
+            "storage marker for cluster {} ({} belongs to cluster {})",
+            cluster_id,
+            marker_path.display(),
+            marker.trim(),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if fresh {
+                if !read_only {
+                    write_marker(storage_directory, cluster_id)?;
+                }
+                return Ok(());
+            }
+            missing.push(format!(
+                "storage marker for cluster {} ({} does not exist)",
+                cluster_id,
+                marker_path.display(),
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    for (name, path) in file_references {
+        if !path.exists() {
+            missing.push(format!("file {} of {}", path.display(), name));
+        }
+    }
+    Err(Error::new(ErrorKind::StorageMismatch {
+        storage_directory: storage_directory.to_path_buf(),
+        catalog_directory: catalog_directory.to_path_buf(),
+        missing,
+    }))
+}
+
+fn same_directory(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn write_marker(storage_directory: &Path, cluster_id: Uuid) -> Result<(), Error> {
+    fs::create_dir_all(storage_directory)?;
+    let tmp_path = storage_directory.join(format!("{}.tmp", MARKER_FILE));
+    let mut tmp = File::create(&tmp_path)?;
+    writeln!(tmp, "{}", cluster_id)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, storage_directory.join(MARKER_FILE))?;
+    Ok(())
+}
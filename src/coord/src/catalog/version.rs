@@ -0,0 +1,165 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Compatibility between the catalog on disk and this version of the code.
+//!
+//! A catalog is described by two versions: the version of its schema, which
+//! is advanced by the schema migrations in [`storage`](super::storage), and
+//! the version of its contents, which is advanced by the content migrations.
+//! A catalog at older versions than this version of the code can be migrated
+//! forward. A catalog at newer versions cannot be opened at all, as migrations
+//! are never reversible.
+
+use std::fmt;
+
+use crate::catalog::error::{Error, ErrorKind};
+use crate::catalog::migrate::CONTENT_MIGRATIONS;
+use crate::catalog::storage::{MIGRATIONS, SCHEMA_VERSION};
+use crate::catalog::CONTENT_VERSION;
+
+/// The versions of a catalog on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogVersion {
+    /// The version of the catalog's schema.
+    pub schema_version: u32,
+    /// The version of the catalog's contents.
+    pub content_version: u32,
+    /// The version of materialized that last opened the catalog, if it was
+    /// recorded.
+    ///
+    /// Versions of materialized that predate recording it did not do so.
+    pub build_version: Option<String>,
+}
+
+impl CatalogVersion {
+    /// Reports whether the catalog is newer than this version of the code
+    /// supports.
+    pub fn is_too_new(&self) -> bool {
+        self.schema_version > SCHEMA_VERSION || self.content_version > CONTENT_VERSION
+    }
+
+    /// Describes the migrations that opening the catalog would run, in the
+    /// order in which they would run.
+    ///
+    /// Schema migrations always run before content migrations.
+    pub fn pending_migrations(&self) -> Vec<String> {
+        let mut migrations = vec![];
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            if i as u32 > self.schema_version {
+                migrations.push(format!("schema migration {}: {}", i, migration.summary));
+            }
+        }
+        for (i, migration) in CONTENT_MIGRATIONS.iter().enumerate() {
+            if i as u32 >= self.content_version {
+                migrations.push(format!(
+                    "content migration {}: {}",
+                    i + 1,
+                    migration.summary
+                ));
+            }
+        }
+        migrations
+    }
+
+    /// Verifies that materialized `server_version` can open the catalog.
+    ///
+    /// A catalog that is newer than this version of the code supports can
+    /// never be opened. A catalog that is older can only be opened if
+    /// `allow_migration` is true.
+    pub fn check_compatible(
+        &self,
+        server_version: &str,
+        allow_migration: bool,
+    ) -> Result<(), Error> {
+        if self.is_too_new() {
+            return Err(Error::new(ErrorKind::CatalogTooNew {
+                catalog: self.clone(),
+                server_version: server_version.into(),
+            }));
+        }
+        let migrations = self.pending_migrations();
+        if !allow_migration && !migrations.is_empty() {
+            return Err(Error::new(ErrorKind::MigrationRequired {
+                catalog: self.clone(),
+                server_version: server_version.into(),
+                migrations,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Describes the version of materialized that last opened the catalog.
+    pub fn describe_build(&self) -> String {
+        match &self.build_version {
+            Some(build_version) => format!("materialized {}", build_version),
+            None => "an unknown version of materialized".into(),
+        }
+    }
+}
+
+impl fmt::Display for CatalogVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "schema version {}, content version {}",
+            self.schema_version, self.content_version
+        )
+    }
+}
+
+/// The versions of the catalog that this version of the code produces.
+pub(super) fn current() -> CatalogVersion {
+    CatalogVersion {
+        schema_version: SCHEMA_VERSION,
+        content_version: CONTENT_VERSION,
+        build_version: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compatibility() {
+        let current = current();
+        assert!(current.pending_migrations().is_empty());
+        assert!(current.check_compatible("v0.0.0", false).is_ok());
+
+        let older = CatalogVersion {
+            schema_version: SCHEMA_VERSION - 1,
+            content_version: CONTENT_VERSION - 1,
+            build_version: None,
+        };
+        assert_eq!(
+            older.pending_migrations(),
+            vec![
+                format!(
+                    "schema migration {}: {}",
+                    SCHEMA_VERSION, MIGRATIONS[SCHEMA_VERSION as usize].summary
+                ),
+                format!(
+                    "content migration {}: {}",
+                    CONTENT_VERSION,
+                    CONTENT_MIGRATIONS[CONTENT_VERSION as usize - 1].summary
+                ),
+            ]
+        );
+        assert!(older.check_compatible("v0.0.0", true).is_ok());
+        assert!(older.check_compatible("v0.0.0", false).is_err());
+
+        let newer = CatalogVersion {
+            schema_version: SCHEMA_VERSION,
+            content_version: CONTENT_VERSION + 1,
+            build_version: Some("v99.0.0".into()),
+        };
+        assert!(newer.is_too_new());
+        assert!(newer.check_compatible("v0.0.0", true).is_err());
+    }
+}
@@ -9,26 +9,45 @@
 
 use std::convert::TryFrom;
 use std::future::Future;
+use std::mem;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::{oneshot, watch};
+use tracing::{field, Instrument};
 use uuid::Uuid;
 
-use dataflow_types::PeekResponse;
+use dataflow_types::{PeekResponse, TailBuffer};
 use expr::GlobalId;
 use ore::collections::CollectionExt;
 use ore::thread::JoinOnDropHandle;
-use repr::{Datum, Row};
+use repr::{Datum, RelationDesc, Row};
 use sql::ast::{Raw, Statement};
 
+use crate::activity::{ActiveStatement, StatementActivity};
+use crate::authz::{self, Authorizer, AuthzDecision};
+use crate::cancel::{CancelKeys, CancelOutcome};
+use crate::catalog::{CatalogCompaction, CatalogSnapshot, EncryptionKey, SqlDumpOptions};
 use crate::command::{
-    Cancelled, Command, ExecuteResponse, Response, SimpleExecuteResponse, SimpleResult,
-    StartupResponse,
+    Cancelled, CatalogSummary, Command, ExecuteResponse, Response, SessionInfo,
+    SimpleExecuteResponse, SimpleResult, SimpleRows, SourceTimestampLag, StartupResponse,
 };
+use crate::coord::LoggingConfig;
+use crate::copies::{ActiveCopies, ActiveCopy};
+use crate::dataflow_budget::DataflowMemoryBudget;
 use crate::error::CoordError;
+use crate::health::DataflowHealth;
 use crate::id_alloc::IdAllocator;
+use crate::lanes::{Lane, LaneSender};
+use crate::result_budget::{self, ResultReservation};
 use crate::session::{EndTransactionAction, Session};
+use crate::slow_query::{SlowQueryLog, TimedStatement};
+use crate::statement_durations::{StatementDuration, StatementDurations};
+use crate::statement_labels::{LabeledStatement, LabeledStatements, StatementLabels};
+use crate::tails::{ActiveTail, ActiveTails};
+use crate::timeout::StatementTimer;
+use crate::user_stats::{UserSession, UserStats};
 
 /// A handle to a running coordinator.
 ///
@@ -36,6 +55,9 @@ use crate::session::{EndTransactionAction, Session};
 /// the coordinator's thread to exit, which will only occur after all
 /// outstanding [`Client`]s for the coordinator have dropped.
 pub struct Handle {
+    // Must be dropped before `_thread` is joined, as the coordinator does not
+    // exit while any command senders remain.
+    pub(crate) cmd_tx: LaneSender<Command>,
     pub(crate) cluster_id: Uuid,
     pub(crate) session_id: Uuid,
     pub(crate) start_instant: Instant,
@@ -64,6 +86,40 @@ impl Handle {
     pub fn start_instant(&self) -> Instant {
         self.start_instant
     }
+
+    /// Writes a consistent snapshot of the catalog to a new database file at
+    /// `path`.
+    ///
+    /// See [`SessionClient::snapshot_catalog`].
+    pub async fn snapshot_catalog(&self, path: PathBuf) -> Result<CatalogSnapshot, CoordError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::SnapshotCatalog { path, tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Rewrites the catalog's on-disk state to its minimal form.
+    ///
+    /// See [`SessionClient::compact_catalog`].
+    pub async fn compact_catalog(&self) -> Result<CatalogCompaction, CoordError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::CompactCatalog { tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Dumps the catalog as a sequence of SQL statements.
+    ///
+    /// See [`Catalog::dump_sql`](crate::catalog::Catalog::dump_sql).
+    pub async fn dump_catalog_sql(&self, options: SqlDumpOptions) -> String {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::DumpCatalogSql { options, tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
 }
 
 /// A coordinator client.
@@ -75,22 +131,129 @@ impl Handle {
 /// outstanding clients have dropped.
 #[derive(Debug, Clone)]
 pub struct Client {
-    cmd_tx: mpsc::UnboundedSender<Command>,
+    cmd_tx: LaneSender<Command>,
+    priority_cmd_tx: LaneSender<Command>,
     id_alloc: Arc<IdAllocator>,
+    slow_query_log: SlowQueryLog,
+    cancel_keys: CancelKeys,
+    statement_activity: StatementActivity,
+    active_tails: ActiveTails,
+    active_copies: ActiveCopies,
+    labeled_statements: LabeledStatements,
+    user_stats: UserStats,
+    dataflow_memory_budget: DataflowMemoryBudget,
+    logical_compaction_window: Option<Duration>,
+    catalog_changes: watch::Receiver<u64>,
 }
 
 impl Client {
-    pub(crate) fn new(cmd_tx: mpsc::UnboundedSender<Command>) -> Client {
+    pub(crate) fn new(
+        cmd_tx: LaneSender<Command>,
+        priority_cmd_tx: LaneSender<Command>,
+        slow_query_log: SlowQueryLog,
+        cancel_keys: CancelKeys,
+        active_tails: ActiveTails,
+        active_copies: ActiveCopies,
+        labeled_statements: LabeledStatements,
+        statement_activity: StatementActivity,
+        user_stats: UserStats,
+        dataflow_memory_budget: DataflowMemoryBudget,
+        logical_compaction_window: Option<Duration>,
+        catalog_changes: watch::Receiver<u64>,
+    ) -> Client {
         Client {
             cmd_tx,
+            priority_cmd_tx,
             id_alloc: Arc::new(IdAllocator::new(1, 1 << 16)),
+            slow_query_log,
+            cancel_keys,
+            statement_activity,
+            active_tails,
+            active_copies,
+            labeled_statements,
+            user_stats,
+            dataflow_memory_budget,
+            logical_compaction_window,
+            catalog_changes,
+        }
+    }
+
+    /// Returns the log of slow statements.
+    pub fn slow_query_log(&self) -> &SlowQueryLog {
+        &self.slow_query_log
+    }
+
+    /// Returns the tracker of whether statements are executing in any
+    /// session.
+    pub fn statement_activity(&self) -> &StatementActivity {
+        &self.statement_activity
+    }
+
+    /// Returns the tracker of the sessions that are streaming the results of
+    /// a `TAIL`.
+    pub fn active_tails(&self) -> &ActiveTails {
+        &self.active_tails
+    }
+
+    /// Returns the tracker of the aggregate statistics of each user's
+    /// sessions.
+    pub fn user_stats(&self) -> &UserStats {
+        &self.user_stats
+    }
+
+    /// Returns the budget against which new dataflows are admitted.
+    pub fn dataflow_memory_budget(&self) -> &DataflowMemoryBudget {
+        &self.dataflow_memory_budget
+    }
+
+    /// Returns the logical compaction window that the coordinator applies to
+    /// indexes and sources that do not override it, or `None` if logical
+    /// compaction is disabled.
+    pub fn logical_compaction_window(&self) -> Option<Duration> {
+        self.logical_compaction_window
+    }
+
+    /// Returns a receiver that observes the number of changes to the
+    /// catalog's definitions committed since the coordinator booted.
+    ///
+    /// See [`Catalog::subscribe_changes`](crate::catalog::Catalog::subscribe_changes).
+    pub fn catalog_changes(&self) -> watch::Receiver<u64> {
+        self.catalog_changes.clone()
+    }
+
+    /// Writes a consistent snapshot of the catalog to a new database file at
+    /// `path`.
+    ///
+    /// See [`SessionClient::snapshot_catalog`].
+    pub async fn snapshot_catalog(&self, path: PathBuf) -> Result<CatalogSnapshot, CoordError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::SnapshotCatalog { path, tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Returns the number of commands waiting for the coordinator on `lane`.
+    pub fn queue_depth(&self, lane: Lane) -> u64 {
+        self.lane_tx(lane).depth()
+    }
+
+    /// Returns the sender for the commands sent on `lane`.
+    fn lane_tx(&self, lane: Lane) -> &LaneSender<Command> {
+        match lane {
+            Lane::Normal => &self.cmd_tx,
+            Lane::Priority => &self.priority_cmd_tx,
         }
     }
 
     /// Allocates a client for an incoming connection.
+    ///
+    /// The connection's commands are sent on the normal lane, unless changed
+    /// with [`ConnClient::set_lane`].
     pub fn new_conn(&self) -> Result<ConnClient, CoordError> {
         Ok(ConnClient {
             conn_id: self.id_alloc.alloc()?,
+            lane: Lane::Normal,
             inner: self.clone(),
         })
     }
@@ -116,6 +279,73 @@ impl Client {
         let response = self.system_execute(stmt).await?;
         Ok(response.results.into_element())
     }
+
+    /// Returns the paths of the local files that the catalog's sources and
+    /// sinks refer to.
+    ///
+    /// See [`Catalog::file_paths`](crate::catalog::Catalog::file_paths).
+    pub async fn catalog_files(&self) -> Vec<PathBuf> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::ListCatalogFiles { tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Reports how far the timestamps of each source in the catalog trail
+    /// the wall clock, ordered by source name.
+    pub async fn source_timestamp_lags(&self) -> Vec<SourceTimestampLag> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(Command::ListSourceTimestampLags { tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Cancels the queries whose peeks are served by any of the named
+    /// transient `dataflows`.
+    ///
+    /// Returns the name of the dataflow and the ID of the connection of each
+    /// query that was canceled.
+    ///
+    /// Like all cancellation, the command is sent on the priority lane.
+    pub async fn cancel_dataflow_queries(&self, dataflows: Vec<String>) -> Vec<(String, u32)> {
+        let (tx, rx) = oneshot::channel();
+        self.priority_cmd_tx
+            .send(Command::CancelDataflowQueries { dataflows, tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Lists the active sessions, as
+    /// [`SessionClient::list_sessions`] does.
+    ///
+    /// The command is sent on the priority lane, so that the sessions of a
+    /// busy coordinator can be listed promptly.
+    pub async fn list_sessions(&self) -> Vec<SessionInfo> {
+        let (tx, rx) = oneshot::channel();
+        self.priority_cmd_tx
+            .send(Command::ListSessions { tx })
+            .expect("coordinator unexpectedly gone");
+        rx.await.expect("coordinator unexpectedly canceled request")
+    }
+
+    /// Sends the coordinator a command that does nothing, and returns a
+    /// future that resolves once the coordinator has processed it.
+    ///
+    /// The command is sent immediately, rather than when the future is first
+    /// polled, so a caller that stops waiting on the future can resume
+    /// waiting on it later without sending another command. The command is
+    /// sent on the priority lane, so the future resolves once the coordinator
+    /// has finished the command it is processing and any priority commands
+    /// that were sent before it, however many user statements are queued.
+    pub fn ping(&self) -> impl Future<Output = ()> + Send {
+        let (tx, rx) = oneshot::channel();
+        self.priority_cmd_tx
+            .send(Command::Ping { tx })
+            .expect("coordinator unexpectedly gone");
+        async move { rx.await.expect("coordinator unexpectedly canceled request") }
+    }
 }
 
 /// A coordinator client that is bound to a connection.
@@ -127,6 +357,7 @@ impl Client {
 #[derive(Debug, Clone)]
 pub struct ConnClient {
     conn_id: u32,
+    lane: Lane,
     inner: Client,
 }
 
@@ -136,6 +367,16 @@ impl ConnClient {
         self.conn_id
     }
 
+    /// Sets the lane on which the connection sends its commands, including
+    /// those of the session that it starts up.
+    ///
+    /// Only connections that serve health probes or admin work belong on
+    /// [`Lane::Priority`], which the coordinator serves ahead of user
+    /// statements.
+    pub fn set_lane(&mut self, lane: Lane) {
+        self.lane = lane;
+    }
+
     /// Upgrades this connection client to a session client.
     ///
     /// A session is a connection that has successfully negotiated parameters,
@@ -156,11 +397,16 @@ impl ConnClient {
         // an in-progress statement.
         let (cancel_tx, cancel_rx) = watch::channel(Cancelled::NotCancelled);
         let cancel_tx = Arc::new(cancel_tx);
+        let user_session = self.inner.user_stats.session(session.user());
         let mut client = SessionClient {
             inner: self,
             session: Some(session),
             cancel_tx: cancel_tx.clone(),
             cancel_rx,
+            statement_durations: None,
+            authorizer: None,
+            statement_labels: StatementLabels::default(),
+            user_session,
         };
         let response = client
             .send(|tx, session| Command::Startup {
@@ -182,26 +428,45 @@ impl ConnClient {
     }
 
     /// Cancels the query currently running on another connection.
-    pub async fn cancel_request(&mut self, conn_id: u32, secret_key: u32) {
-        self.inner
-            .cmd_tx
-            .send(Command::CancelRequest {
-                conn_id,
-                secret_key,
-            })
-            .expect("coordinator unexpectedly canceled request")
+    ///
+    /// Requests whose secret key this instance could not have issued are
+    /// recognized as meant for another instance without consulting the
+    /// coordinator. The request is sent on the priority lane.
+    pub async fn cancel_request(&mut self, conn_id: u32, secret_key: u32) -> CancelOutcome {
+        if !self.inner.cancel_keys.owns(secret_key) {
+            return CancelOutcome::Foreign;
+        }
+        self.send_on(Lane::Priority, |tx| Command::CancelRequest {
+            conn_id,
+            secret_key: Some(secret_key),
+            tx,
+        })
+        .await
     }
 
     async fn send<T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(oneshot::Sender<T>) -> Command,
+    {
+        self.send_on(self.lane, f).await
+    }
+
+    async fn send_on<T, F>(&mut self, lane: Lane, f: F) -> T
     where
         F: FnOnce(oneshot::Sender<T>) -> Command,
     {
         let (tx, rx) = oneshot::channel();
+        let cmd = f(tx);
+        // The span covers the round trip through the coordinator, including
+        // any time the command spends queued behind other commands.
+        let span = tracing::info_span!("coord_command", command = cmd.name(), lane = lane.as_str());
         self.inner
-            .cmd_tx
-            .send(f(tx))
+            .lane_tx(lane)
+            .send(cmd)
             .expect("coordinator unexpectedly gone");
-        rx.await.expect("coordinator unexpectedly canceled request")
+        rx.instrument(span)
+            .await
+            .expect("coordinator unexpectedly canceled request")
     }
 }
 
@@ -224,6 +489,13 @@ pub struct SessionClient {
     session: Option<Session>,
     cancel_tx: Arc<watch::Sender<Cancelled>>,
     cancel_rx: watch::Receiver<Cancelled>,
+    statement_durations: Option<StatementDurations>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    /// The labels of the statement that is executing, or that most recently
+    /// executed.
+    statement_labels: StatementLabels,
+    /// Records the statistics of the session toward those of its user.
+    user_session: UserSession,
 }
 
 impl SessionClient {
@@ -286,7 +558,11 @@ impl SessionClient {
     }
 
     /// Executes a previously-bound portal.
+    ///
+    /// If an authorizer is installed, the statement bound to the portal is
+    /// authorized before it is submitted to the coordinator.
     pub async fn execute(&mut self, portal_name: String) -> Result<ExecuteResponse, CoordError> {
+        self.authorize_portal(&portal_name)?;
         self.send(|tx, session| Command::Execute {
             portal_name,
             session,
@@ -335,6 +611,94 @@ impl SessionClient {
             .await
     }
 
+    /// Dumps the catalog as a sequence of SQL statements.
+    ///
+    /// See [`Catalog::dump_sql`](crate::catalog::Catalog::dump_sql).
+    pub async fn dump_catalog_sql(&mut self, options: SqlDumpOptions) -> String {
+        self.inner
+            .send(|tx| Command::DumpCatalogSql { options, tx })
+            .await
+    }
+
+    /// Lists the active sessions.
+    pub async fn list_sessions(&mut self) -> Vec<SessionInfo> {
+        self.inner.send(|tx| Command::ListSessions { tx }).await
+    }
+
+    /// Reports the health of each source and sink in the catalog, ordered by
+    /// name.
+    pub async fn dataflow_health(&mut self) -> DataflowHealth {
+        self.inner
+            .send(|tx| Command::ListDataflowHealth { tx })
+            .await
+    }
+
+    /// Counts the objects in the catalog and the arrangements that the
+    /// dataflow layer maintains, without consulting the introspection sources.
+    pub async fn catalog_summary(&mut self) -> CatalogSummary {
+        self.inner.send(|tx| Command::SummarizeCatalog { tx }).await
+    }
+
+    /// Cancels the query currently running on the connection with the
+    /// specified ID, without presenting the connection's secret key.
+    ///
+    /// The request is sent on the priority lane.
+    pub async fn cancel_session(&mut self, conn_id: u32) -> CancelOutcome {
+        self.inner
+            .send_on(Lane::Priority, |tx| Command::CancelRequest {
+                conn_id,
+                secret_key: None,
+                tx,
+            })
+            .await
+    }
+
+    /// Writes a consistent snapshot of the catalog to a new database file at
+    /// `path`.
+    ///
+    /// The coordinator does not process other commands while it takes the
+    /// snapshot. The snapshot is as large as the catalog, which is typically
+    /// small, so the interruption is brief.
+    pub async fn snapshot_catalog(&mut self, path: PathBuf) -> Result<CatalogSnapshot, CoordError> {
+        self.inner
+            .send(|tx| Command::SnapshotCatalog { path, tx })
+            .await
+    }
+
+    /// Reencrypts the catalog with `key`, which replaces the key with which
+    /// the catalog is currently encrypted.
+    ///
+    /// See [`Catalog::reencrypt`](crate::catalog::Catalog::reencrypt).
+    pub async fn reencrypt_catalog(&mut self, key: EncryptionKey) -> Result<(), CoordError> {
+        self.inner
+            .send(|tx| Command::ReencryptCatalog { key, tx })
+            .await
+    }
+
+    /// Rewrites the catalog's on-disk state to its minimal form.
+    ///
+    /// The coordinator does not process other commands while the catalog is
+    /// compacted. Compaction is refused in read-only mode.
+    ///
+    /// See [`Catalog::compact`](crate::catalog::Catalog::compact).
+    pub async fn compact_catalog(&mut self) -> Result<CatalogCompaction, CoordError> {
+        self.inner.send(|tx| Command::CompactCatalog { tx }).await
+    }
+
+    /// Returns the current configuration of the introspection sources, or
+    /// `None` if the introspection sources are not enabled.
+    pub async fn logging_config(&mut self) -> Option<LoggingConfig> {
+        self.inner.send(|tx| Command::GetLoggingConfig { tx }).await
+    }
+
+    /// Reconfigures the introspection sources, activating or deactivating
+    /// the introspection dataflows as necessary.
+    pub async fn set_logging_config(&mut self, config: LoggingConfig) -> Result<(), CoordError> {
+        self.inner
+            .send(|tx| Command::SetLoggingConfig { config, tx })
+            .await
+    }
+
     /// Inserts a set of rows into the given table.
     ///
     /// The rows only contain the columns positions in `columns`, so they
@@ -369,98 +733,139 @@ impl SessionClient {
         &mut self,
         stmts: &str,
     ) -> Result<SimpleExecuteResponse, CoordError> {
-        // Convert most floats to a JSON Number. JSON Numbers don't support NaN or
-        // Infinity, so those will still be rendered as strings.
-        fn float_to_json(f: f64) -> serde_json::Value {
-            match serde_json::Number::from_f64(f) {
-                Some(n) => serde_json::Value::Number(n),
-                None => serde_json::Value::String(f.to_string()),
-            }
-        }
+        self.simple_execute_inner(stmts, None).await
+    }
 
-        fn datum_to_json(datum: &Datum) -> serde_json::Value {
-            match datum {
-                // Convert some common things to a native JSON value. This doesn't need to be
-                // too exhaustive because the SQL-over-HTTP interface is currently not hooked
-                // up to arbitrary external user queries.
-                Datum::Null | Datum::JsonNull => serde_json::Value::Null,
-                Datum::False => serde_json::Value::Bool(false),
-                Datum::True => serde_json::Value::Bool(true),
-                Datum::Int16(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
-                Datum::Int32(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
-                Datum::Int64(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
-                Datum::Float32(n) => float_to_json(n.into_inner() as f64),
-                Datum::Float64(n) => float_to_json(n.into_inner()),
-                Datum::Numeric(d) => {
-                    // serde_json requires floats to be finite
-                    if d.0.is_infinite() {
-                        serde_json::Value::String(d.0.to_string())
-                    } else {
-                        serde_json::Value::Number(
-                            serde_json::Number::from_f64(f64::try_from(d.0).unwrap()).unwrap(),
-                        )
-                    }
-                }
-                Datum::String(s) => serde_json::Value::String(s.to_string()),
-                Datum::List(list) => serde_json::Value::Array(
-                    list.iter().map(|entry| datum_to_json(&entry)).collect(),
-                ),
-                Datum::Map(map) => serde_json::Value::Object(
-                    map.iter()
-                        .map(|(k, v)| (k.to_owned(), datum_to_json(&v)))
-                        .collect(),
-                ),
-                _ => serde_json::Value::String(datum.to_string()),
-            }
+    /// Like [`SessionClient::simple_execute`], but grows `reservation` by the
+    /// memory occupied by the results as they are buffered.
+    ///
+    /// Fails with [`CoordError::ResultMemoryExhausted`] if the reservation
+    /// cannot grow.
+    pub async fn simple_execute_reserving(
+        &mut self,
+        stmts: &str,
+        reservation: &mut ResultReservation,
+    ) -> Result<SimpleExecuteResponse, CoordError> {
+        self.simple_execute_inner(stmts, Some(reservation)).await
+    }
+
+    /// Executes the single SQL statement in `stmt` like
+    /// [`SessionClient::simple_execute_reserving`], but returns its rows as
+    /// they were computed, along with their description, rather than as
+    /// JSON.
+    ///
+    /// The reservation continues to hold the memory occupied by the rows
+    /// until it is shrunk or dropped.
+    pub async fn simple_execute_rows(
+        &mut self,
+        stmt: &str,
+        reservation: &mut ResultReservation,
+    ) -> Result<SimpleRows, CoordError> {
+        let labels = StatementLabels::parse(stmt);
+        let mut stmts = sql::parse::parse(&stmt).map_err(|e| CoordError::Unstructured(e.into()))?;
+        if stmts.len() != 1 {
+            coord_bail!("expected exactly one statement, but got {}", stmts.len());
         }
+        self.start_transaction(None).await?;
+        self.simple_execute_one(stmts.remove(0), &labels, Some(reservation))
+            .await
+    }
 
+    async fn simple_execute_inner(
+        &mut self,
+        stmts: &str,
+        mut reservation: Option<&mut ResultReservation>,
+    ) -> Result<SimpleExecuteResponse, CoordError> {
+        let labels = StatementLabels::parse(stmts);
         let stmts = sql::parse::parse(&stmts).map_err(|e| CoordError::Unstructured(e.into()))?;
         self.start_transaction(None).await?;
-        const EMPTY_PORTAL: &str = "";
         let mut results = vec![];
         for stmt in stmts {
-            self.declare(EMPTY_PORTAL.into(), stmt, vec![]).await?;
-            let desc = self
-                .session()
-                .get_portal(EMPTY_PORTAL)
-                .map(|portal| portal.desc.clone())
-                .expect("unnamed portal should be present");
-            if !desc.param_types.is_empty() {
-                coord_bail!("parameters are not supported");
+            let rows = self
+                .simple_execute_one(stmt, &labels, reservation.as_deref_mut())
+                .await?;
+            results.push(rows_to_json(rows, reservation.as_deref_mut()).await?);
+        }
+        Ok(SimpleExecuteResponse { results })
+    }
+
+    /// Executes `stmt`, recording it like any other statement.
+    async fn simple_execute_one(
+        &mut self,
+        stmt: Statement<Raw>,
+        labels: &StatementLabels,
+        reservation: Option<&mut ResultReservation>,
+    ) -> Result<SimpleRows, CoordError> {
+        let _labeled_statement = self.label_statement(labels.clone());
+        let _active_statement = self.start_statement(&stmt);
+        let _user_statement = self.user_session.start_statement();
+        let _duration = self.start_statement_duration(&stmt);
+        let timer = self.start_timing(&stmt);
+        let statement_timer = self.start_statement_timer();
+        let span = tracing::info_span!("statement", labels = field::Empty);
+        if !labels.is_empty() {
+            span.record("labels", &field::display(labels));
+        }
+        let mut result = self
+            .simple_execute_one_inner(stmt, reservation)
+            .instrument(span)
+            .await;
+        if result.is_err() && statement_timer.map_or(false, |t| t.fired()) {
+            result = Err(CoordError::StatementTimeout);
+        }
+        if let Ok(result) = &result {
+            self.user_session.add_rows(result.rows.len());
+        }
+        if let Some(mut timer) = timer {
+            match &result {
+                Ok(result) => timer.add_rows(result.rows.len()),
+                Err(_) => timer.fail(),
             }
+            timer.finish();
+        }
+        result
+    }
+
+    async fn simple_execute_one_inner(
+        &mut self,
+        stmt: Statement<Raw>,
+        reservation: Option<&mut ResultReservation>,
+    ) -> Result<SimpleRows, CoordError> {
+        const EMPTY_PORTAL: &str = "";
+        self.declare(EMPTY_PORTAL.into(), stmt, vec![]).await?;
+        let desc = self
+            .session()
+            .get_portal(EMPTY_PORTAL)
+            .map(|portal| portal.desc.clone())
+            .expect("unnamed portal should be present");
+        if !desc.param_types.is_empty() {
+            coord_bail!("parameters are not supported");
+        }
 
-            let res = self.execute(EMPTY_PORTAL.into()).await?;
+        let res = self.execute(EMPTY_PORTAL.into()).await?;
 
-            let rows = match res {
-                ExecuteResponse::SendingRows(rows) => {
-                    let response = rows.await;
-                    response
-                }
-                _ => coord_bail!("unsupported statement type"),
-            };
-            let rows = match rows {
-                PeekResponse::Rows(rows) => rows,
-                PeekResponse::Error(e) => coord_bail!("{}", e),
-                PeekResponse::Canceled => coord_bail!("execution canceled"),
-            };
-            let mut sql_rows: Vec<Vec<serde_json::Value>> = vec![];
-            let col_names = match desc.relation_desc {
-                Some(desc) => desc
-                    .iter_names()
-                    .map(|name| name.map(|name| name.to_string()))
-                    .collect(),
-                None => vec![],
-            };
-            for row in rows {
-                let datums = row.unpack();
-                sql_rows.push(datums.iter().map(|datum| datum_to_json(datum)).collect());
+        let rows = match res {
+            ExecuteResponse::SendingRows(rows) => {
+                let response = rows.await;
+                response
             }
-            results.push(SimpleResult {
-                rows: sql_rows,
-                col_names,
-            })
+            _ => coord_bail!("unsupported statement type"),
+        };
+        let rows = match rows {
+            PeekResponse::Rows(rows) => rows,
+            PeekResponse::Error(e) => coord_bail!("{}", e),
+            PeekResponse::Canceled => coord_bail!("execution canceled"),
+        };
+        self.session().vars().check_result_size(&rows)?;
+        // Account for the packed rows until the caller releases them.
+        if let Some(reservation) = reservation {
+            let packed_size: usize = rows.iter().map(result_budget::row_size).sum();
+            reservation.grow(packed_size).await?;
         }
-        Ok(SimpleExecuteResponse { results })
+        Ok(SimpleRows {
+            desc: desc.relation_desc.unwrap_or_else(RelationDesc::empty),
+            rows,
+        })
     }
 
     /// Terminates this client session.
@@ -472,7 +877,7 @@ impl SessionClient {
         let session = self.session.take().expect("session invariant violated");
         self.inner
             .inner
-            .cmd_tx
+            .lane_tx(self.inner.lane)
             .send(Command::Terminate { session })
             .expect("coordinator unexpectedly gone");
     }
@@ -482,10 +887,195 @@ impl SessionClient {
         self.session.as_mut().unwrap()
     }
 
+    /// Returns the log of slow statements.
+    pub fn slow_query_log(&self) -> &SlowQueryLog {
+        self.inner.inner.slow_query_log()
+    }
+
+    /// Returns the tracker of the aggregate statistics of each user's
+    /// sessions.
+    pub fn user_stats(&self) -> &UserStats {
+        self.inner.inner.user_stats()
+    }
+
+    /// Starts timing the execution of `stmt` in this session for the slow
+    /// query log.
+    ///
+    /// See [`SlowQueryLog::start`].
+    pub fn start_timing(&mut self, stmt: &Statement<Raw>) -> Option<TimedStatement> {
+        let session = self.session.as_ref().unwrap();
+        self.inner
+            .inner
+            .slow_query_log
+            .start(stmt, session, &self.statement_labels)
+    }
+
+    /// Records how long the statements executed in this session take in
+    /// `statement_durations`.
+    ///
+    /// Statements executed via [`SessionClient::simple_execute`] are recorded
+    /// automatically. Other frontends call
+    /// [`SessionClient::start_statement_duration`].
+    pub fn set_statement_durations(&mut self, statement_durations: StatementDurations) {
+        self.statement_durations = Some(statement_durations);
+    }
+
+    /// Installs an authorizer that decides which statements this session may
+    /// execute.
+    pub fn set_authorizer(&mut self, authorizer: Arc<dyn Authorizer>) {
+        self.authorizer = Some(authorizer);
+    }
+
+    fn authorize_portal(&self, portal_name: &str) -> Result<(), CoordError> {
+        let authorizer = match &self.authorizer {
+            None => return Ok(()),
+            Some(authorizer) => authorizer,
+        };
+        let session = self.session.as_ref().unwrap();
+        let stmt = match session.get_portal(portal_name) {
+            Some(portal) => match &portal.stmt {
+                Some(stmt) => stmt,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        match authz::authorize(&**authorizer, session.user(), stmt) {
+            AuthzDecision::Allow => Ok(()),
+            AuthzDecision::Deny(reason) => Err(CoordError::Unauthorized(reason)),
+        }
+    }
+
+    /// Starts timing the execution of `stmt` in this session, if the session
+    /// records statement durations.
+    ///
+    /// See [`StatementDurations::start`].
+    pub fn start_statement_duration(&self, stmt: &Statement<Raw>) -> Option<StatementDuration> {
+        let statement_durations = self.statement_durations.as_ref()?;
+        Some(statement_durations.start(stmt, &self.statement_labels))
+    }
+
+    /// Like [`SessionClient::start_statement_duration`], but times the
+    /// statement bound to the named portal.
+    ///
+    /// Returns `None` if the portal does not exist or is empty.
+    pub fn start_statement_duration_portal(&self, portal_name: &str) -> Option<StatementDuration> {
+        let statement_durations = self.statement_durations.as_ref()?;
+        let session = self.session.as_ref().unwrap();
+        let stmt = session.get_portal(portal_name)?.stmt.as_ref()?;
+        Some(statement_durations.start(stmt, &self.statement_labels))
+    }
+
+    /// Records that `stmt` is executing in this session until the returned
+    /// guard is dropped.
+    ///
+    /// See [`StatementActivity::start`].
+    pub fn start_statement(&self, stmt: &Statement<Raw>) -> ActiveStatement {
+        let sql = self.inner.inner.slow_query_log.redact(stmt);
+        self.inner
+            .inner
+            .statement_activity
+            .start(self.inner.conn_id, Some(sql))
+    }
+
+    /// Like [`SessionClient::start_statement`], but records the statement
+    /// bound to the named portal.
+    pub fn start_statement_portal(&self, portal_name: &str) -> ActiveStatement {
+        let session = self.session.as_ref().unwrap();
+        let sql = session
+            .get_portal(portal_name)
+            .and_then(|portal| portal.stmt.as_ref())
+            .map(|stmt| self.inner.inner.slow_query_log.redact(stmt));
+        self.inner
+            .inner
+            .statement_activity
+            .start(self.inner.conn_id, sql)
+    }
+
+    /// Returns the guard that records the statistics of this session toward
+    /// those of its user.
+    ///
+    /// See [`UserStats::session`].
+    pub fn user_session(&self) -> &UserSession {
+        &self.user_session
+    }
+
+    /// Attaches `labels` to the statement that is about to execute in this
+    /// session, until the returned guard is dropped.
+    ///
+    /// The labels are recorded in the slow query log and statement durations
+    /// of statements whose timing starts after the call, and are reported in
+    /// the session listing while the guard is live.
+    pub fn label_statement(&mut self, labels: StatementLabels) -> LabeledStatement {
+        self.statement_labels = labels.clone();
+        self.inner
+            .inner
+            .labeled_statements
+            .start(self.inner.conn_id, labels)
+    }
+
+    /// Returns the labels of the statement that is executing in this session,
+    /// or that most recently executed.
+    pub fn statement_labels(&self) -> &StatementLabels {
+        &self.statement_labels
+    }
+
+    /// Records that this session is streaming the results of a `TAIL` from
+    /// `buffer` over `protocol` until the returned guard is dropped.
+    ///
+    /// See [`ActiveTails::start`].
+    pub fn start_tail(&self, protocol: &'static str, buffer: TailBuffer) -> ActiveTail {
+        self.inner
+            .inner
+            .active_tails
+            .start(self.inner.conn_id, protocol, buffer)
+    }
+
+    /// Records that this session is copying rows in `direction`, `to` or
+    /// `from` the client, in `format`, until the returned guard is dropped.
+    ///
+    /// See [`ActiveCopies::start`].
+    pub fn start_copy(&self, direction: &'static str, format: &'static str) -> ActiveCopy {
+        self.inner
+            .inner
+            .active_copies
+            .start(self.inner.conn_id, direction, format)
+    }
+
+    /// Arms a timer that cancels the statement that is about to execute in
+    /// this session if it runs for longer than the session's
+    /// `statement_timeout`.
+    ///
+    /// Returns `None` if the session has no statement timeout. Drop the timer
+    /// once the statement completes.
+    pub fn start_statement_timer(&mut self) -> Option<StatementTimer> {
+        let timeout = self.session().vars().statement_timeout()?;
+        Some(StatementTimer::arm(
+            timeout,
+            self.inner.conn_id,
+            self.inner.inner.priority_cmd_tx.clone(),
+        ))
+    }
+
+    /// Like [`SessionClient::start_timing`], but times the statement bound to
+    /// the named portal.
+    ///
+    /// Returns `None` if the portal does not exist or is empty.
+    pub fn start_timing_portal(&mut self, portal_name: &str) -> Option<TimedStatement> {
+        let session = self.session.as_ref().unwrap();
+        let stmt = session.get_portal(portal_name)?.stmt.as_ref()?;
+        self.inner
+            .inner
+            .slow_query_log
+            .start(stmt, session, &self.statement_labels)
+    }
+
     async fn send<T, F>(&mut self, f: F) -> Result<T, CoordError>
     where
         F: FnOnce(oneshot::Sender<Response<T>>, Session) -> Command,
     {
+        // Fails the command as if it had timed out on its way to the
+        // coordinator.
+        fail::fail_point!("coord_client_send", |_| Err(CoordError::StatementTimeout));
         let session = self.session.take().expect("session invariant violated");
         let res = self.inner.send(|tx| f(tx, session)).await;
         self.session = Some(res.session);
@@ -500,3 +1090,82 @@ impl Drop for SessionClient {
         }
     }
 }
+
+/// Converts the rows of a statement executed by a [`SessionClient`] into JSON,
+/// growing `reservation` by the memory that the JSON occupies and shrinking
+/// it by the memory that the packed rows occupied.
+async fn rows_to_json(
+    rows: SimpleRows,
+    mut reservation: Option<&mut ResultReservation>,
+) -> Result<SimpleResult, CoordError> {
+    // Convert most floats to a JSON Number. JSON Numbers don't support NaN or
+    // Infinity, so those will still be rendered as strings.
+    fn float_to_json(f: f64) -> serde_json::Value {
+        match serde_json::Number::from_f64(f) {
+            Some(n) => serde_json::Value::Number(n),
+            None => serde_json::Value::String(f.to_string()),
+        }
+    }
+
+    fn datum_to_json(datum: &Datum) -> serde_json::Value {
+        match datum {
+            // Convert some common things to a native JSON value. This doesn't need to be
+            // too exhaustive because the SQL-over-HTTP interface is currently not hooked
+            // up to arbitrary external user queries.
+            Datum::Null | Datum::JsonNull => serde_json::Value::Null,
+            Datum::False => serde_json::Value::Bool(false),
+            Datum::True => serde_json::Value::Bool(true),
+            Datum::Int16(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
+            Datum::Int32(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
+            Datum::Int64(n) => serde_json::Value::Number(serde_json::Number::from(*n)),
+            Datum::Float32(n) => float_to_json(n.into_inner() as f64),
+            Datum::Float64(n) => float_to_json(n.into_inner()),
+            Datum::Numeric(d) => {
+                // serde_json requires floats to be finite
+                if d.0.is_infinite() {
+                    serde_json::Value::String(d.0.to_string())
+                } else {
+                    serde_json::Value::Number(
+                        serde_json::Number::from_f64(f64::try_from(d.0).unwrap()).unwrap(),
+                    )
+                }
+            }
+            Datum::String(s) => serde_json::Value::String(s.to_string()),
+            Datum::List(list) => {
+                serde_json::Value::Array(list.iter().map(|entry| datum_to_json(&entry)).collect())
+            }
+            Datum::Map(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.to_owned(), datum_to_json(&v)))
+                    .collect(),
+            ),
+            _ => serde_json::Value::String(datum.to_string()),
+        }
+    }
+
+    let SimpleRows { desc, rows } = rows;
+    let packed_size: usize = rows.iter().map(result_budget::row_size).sum();
+    let mut sql_rows: Vec<Vec<serde_json::Value>> = vec![];
+    let col_names = desc
+        .iter_names()
+        .map(|name| name.map(|name| name.to_string()))
+        .collect();
+    sql_rows.reserve_exact(rows.len());
+    for row in rows {
+        let datums = row.unpack();
+        let sql_row: Vec<_> = datums.iter().map(|datum| datum_to_json(datum)).collect();
+        if let Some(reservation) = &mut reservation {
+            let size = mem::size_of::<Vec<serde_json::Value>>()
+                + sql_row.iter().map(result_budget::json_size).sum::<usize>();
+            reservation.grow(size).await?;
+        }
+        sql_rows.push(sql_row);
+    }
+    if let Some(reservation) = &mut reservation {
+        reservation.shrink(packed_size);
+    }
+    Ok(SimpleResult {
+        rows: sql_rows,
+        col_names,
+    })
+}
@@ -0,0 +1,211 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Detection of wall-clock regressions.
+//!
+//! The coordinator assigns timestamps from the wall clock. If the clock steps
+//! backward, as it may when a virtual machine migrates between hosts, the
+//! timestamps it would assign move backward too. The [`ClockMonitor`] reads
+//! the wall clock on the coordinator's behalf and never reports a time
+//! earlier than one it has already reported, so the timestamps the
+//! coordinator assigns remain monotone. A regression beyond the configured
+//! tolerance is logged and counted, and, if so configured, marks the server
+//! as not ready until the clock catches up.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounter, UIntGauge};
+use ore::now::{system_time, EpochMillis, NowFn};
+
+/// Configures the detection of wall-clock regressions.
+#[derive(Debug, Clone)]
+pub struct ClockConfig {
+    /// How far the wall clock may step backward before the step is reported
+    /// as a regression.
+    pub tolerance: Duration,
+    /// Whether the server reports that it is not ready after a regression,
+    /// until the wall clock catches up to the latest time it reported.
+    pub fail_readiness: bool,
+    /// The wall clock. Tests may substitute a mock clock.
+    pub now: NowFn,
+}
+
+impl Default for ClockConfig {
+    fn default() -> ClockConfig {
+        ClockConfig {
+            tolerance: Duration::from_secs(1),
+            fail_readiness: false,
+            now: system_time,
+        }
+    }
+}
+
+/// Reads the wall clock, holding the times it reports monotone and detecting
+/// regressions.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct ClockMonitor {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    now: NowFn,
+    tolerance_ms: u64,
+    fail_readiness: bool,
+    /// The latest time that the wall clock has reported.
+    high_water: AtomicU64,
+    /// Whether the wall clock has regressed and not yet caught up to
+    /// `high_water`.
+    regressed: AtomicBool,
+    regressions: UIntCounter,
+    skew: UIntGauge,
+}
+
+impl ClockMonitor {
+    /// Constructs a new clock monitor, registering its metrics into
+    /// `registry`.
+    pub fn register_into(registry: &MetricsRegistry, config: ClockConfig) -> ClockMonitor {
+        ClockMonitor {
+            inner: Arc::new(Inner {
+                now: config.now,
+                tolerance_ms: u64::try_from(config.tolerance.as_millis()).unwrap_or(u64::MAX),
+                fail_readiness: config.fail_readiness,
+                high_water: AtomicU64::new(0),
+                regressed: AtomicBool::new(false),
+                regressions: registry.register(metric!(
+                    name: "mz_clock_regressions_total",
+                    help: "number of times the wall clock stepped backward by more than the \
+                           configured tolerance",
+                )),
+                skew: registry.register(metric!(
+                    name: "mz_clock_skew_milliseconds",
+                    help: "how far the wall clock trails the latest time it has reported, \
+                           in milliseconds",
+                )),
+            }),
+        }
+    }
+
+    /// Reads the wall clock.
+    ///
+    /// If the wall clock has stepped backward, returns the latest time it
+    /// reported before the step instead, so that successive calls never
+    /// return a smaller time.
+    pub fn now(&self) -> EpochMillis {
+        let inner = &self.inner;
+        let now = (inner.now)();
+        let high_water = inner.high_water.fetch_max(now, Ordering::SeqCst);
+        if now >= high_water {
+            inner.skew.set(0);
+            if inner.regressed.swap(false, Ordering::SeqCst) {
+                tracing::info!(
+                    target: "clock",
+                    event = "clock.recovered",
+                    "wall clock caught up to the latest time it reported"
+                );
+            }
+            return now;
+        }
+        let skew = high_water - now;
+        inner.skew.set(skew);
+        if skew > inner.tolerance_ms && !inner.regressed.swap(true, Ordering::SeqCst) {
+            inner.regressions.inc();
+            tracing::error!(
+                target: "clock",
+                event = "clock.regressed",
+                skew_ms = skew,
+                tolerance_ms = inner.tolerance_ms,
+                high_water_ms = high_water,
+                now_ms = now,
+                "wall clock stepped backward by {}ms; holding timestamps at {} until it \
+                 catches up",
+                skew,
+                high_water,
+            );
+        }
+        high_water
+    }
+
+    /// Reports how far the wall clock trailed the latest time it reported,
+    /// as of the last read.
+    pub fn skew(&self) -> Duration {
+        Duration::from_millis(self.inner.skew.get())
+    }
+
+    /// Reports whether the wall clock has stepped backward by more than the
+    /// tolerance and, as of the last read, has not yet caught up.
+    pub fn is_regressed(&self) -> bool {
+        self.inner.regressed.load(Ordering::SeqCst)
+    }
+
+    /// Reports whether the server should report that it is not ready because
+    /// the wall clock has regressed.
+    pub fn fails_readiness(&self) -> bool {
+        self.inner.fail_readiness && self.is_regressed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use ore::metrics::MetricsRegistry;
+
+    use super::{ClockConfig, ClockMonitor};
+
+    static MOCK_NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn mock_now() -> u64 {
+        MOCK_NOW.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_clock_regression() {
+        let clock = ClockMonitor::register_into(
+            &MetricsRegistry::new(),
+            ClockConfig {
+                tolerance: Duration::from_millis(100),
+                fail_readiness: true,
+                now: mock_now,
+            },
+        );
+
+        MOCK_NOW.store(10_000, Ordering::SeqCst);
+        assert_eq!(clock.now(), 10_000);
+
+        // A step backward within the tolerance holds the time, but is not a
+        // regression.
+        MOCK_NOW.store(9_950, Ordering::SeqCst);
+        assert_eq!(clock.now(), 10_000);
+        assert_eq!(clock.skew(), Duration::from_millis(50));
+        assert!(!clock.is_regressed());
+
+        // A step beyond the tolerance is counted once and fails readiness.
+        MOCK_NOW.store(5_000, Ordering::SeqCst);
+        assert_eq!(clock.now(), 10_000);
+        MOCK_NOW.store(6_000, Ordering::SeqCst);
+        assert_eq!(clock.now(), 10_000);
+        assert_eq!(clock.skew(), Duration::from_millis(4_000));
+        assert!(clock.fails_readiness());
+        assert_eq!(clock.inner.regressions.get(), 1);
+
+        // Once the clock catches up, time advances again.
+        MOCK_NOW.store(10_001, Ordering::SeqCst);
+        assert_eq!(clock.now(), 10_001);
+        assert_eq!(clock.skew(), Duration::from_millis(0));
+        assert!(!clock.fails_readiness());
+    }
+}
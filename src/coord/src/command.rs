@@ -7,25 +7,37 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
+use std::collections::BTreeMap;
 use std::fmt;
 use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use derivative::Derivative;
 use serde::Serialize;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::oneshot;
 
 use dataflow_types::PeekResponse;
 use expr::GlobalId;
+use ore::now::EpochMillis;
 use ore::str::StrExt;
-use repr::Row;
+use repr::{RelationDesc, Row};
 use sql::ast::{FetchDirection, ObjectType, Raw, Statement};
 use sql::plan::ExecuteTimeout;
 use tokio::sync::watch;
 
+use crate::activity::CurrentStatement;
+use crate::cancel::CancelOutcome;
+use crate::catalog::{CatalogCompaction, CatalogSnapshot, EncryptionKey, SqlDumpOptions};
+use crate::coord::LoggingConfig;
+use crate::copies::CopyProgress;
 use crate::error::CoordError;
+use crate::health::DataflowHealth;
 use crate::session::{EndTransactionAction, Session};
+use crate::statement_labels::StatementLabels;
+use crate::tails::{TailBufferInfo, TailReceiver};
+use crate::transport::Transport;
 
 #[derive(Debug)]
 pub enum Command {
@@ -71,7 +83,10 @@ pub enum Command {
 
     CancelRequest {
         conn_id: u32,
-        secret_key: u32,
+        /// `None` if the request was authorized by means other than the
+        /// connection's secret key.
+        secret_key: Option<u32>,
+        tx: oneshot::Sender<CancelOutcome>,
     },
 
     DumpCatalog {
@@ -79,6 +94,63 @@ pub enum Command {
         tx: oneshot::Sender<Response<String>>,
     },
 
+    DumpCatalogSql {
+        options: SqlDumpOptions,
+        tx: oneshot::Sender<String>,
+    },
+
+    ListSessions {
+        tx: oneshot::Sender<Vec<SessionInfo>>,
+    },
+
+    ListCatalogFiles {
+        tx: oneshot::Sender<Vec<PathBuf>>,
+    },
+
+    ListSourceTimestampLags {
+        tx: oneshot::Sender<Vec<SourceTimestampLag>>,
+    },
+
+    ListDataflowHealth {
+        tx: oneshot::Sender<DataflowHealth>,
+    },
+
+    SummarizeCatalog {
+        tx: oneshot::Sender<CatalogSummary>,
+    },
+
+    CancelDataflowQueries {
+        dataflows: Vec<String>,
+        tx: oneshot::Sender<Vec<(String, u32)>>,
+    },
+
+    Ping {
+        tx: oneshot::Sender<()>,
+    },
+
+    GetLoggingConfig {
+        tx: oneshot::Sender<Option<LoggingConfig>>,
+    },
+
+    SnapshotCatalog {
+        path: PathBuf,
+        tx: oneshot::Sender<Result<CatalogSnapshot, CoordError>>,
+    },
+
+    SetLoggingConfig {
+        config: LoggingConfig,
+        tx: oneshot::Sender<Result<(), CoordError>>,
+    },
+
+    ReencryptCatalog {
+        key: EncryptionKey,
+        tx: oneshot::Sender<Result<(), CoordError>>,
+    },
+
+    CompactCatalog {
+        tx: oneshot::Sender<Result<CatalogCompaction, CoordError>>,
+    },
+
     CopyRows {
         id: GlobalId,
         columns: Vec<usize>,
@@ -92,6 +164,37 @@ pub enum Command {
     },
 }
 
+impl Command {
+    /// Returns the name of the command, for use in diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Startup { .. } => "startup",
+            Command::Declare { .. } => "declare",
+            Command::Describe { .. } => "describe",
+            Command::Execute { .. } => "execute",
+            Command::StartTransaction { .. } => "start_transaction",
+            Command::Commit { .. } => "commit",
+            Command::CancelRequest { .. } => "cancel_request",
+            Command::DumpCatalog { .. } => "dump_catalog",
+            Command::DumpCatalogSql { .. } => "dump_catalog_sql",
+            Command::ListSessions { .. } => "list_sessions",
+            Command::ListCatalogFiles { .. } => "list_catalog_files",
+            Command::ListSourceTimestampLags { .. } => "list_source_timestamp_lags",
+            Command::ListDataflowHealth { .. } => "list_dataflow_health",
+            Command::SummarizeCatalog { .. } => "summarize_catalog",
+            Command::CancelDataflowQueries { .. } => "cancel_dataflow_queries",
+            Command::Ping { .. } => "ping",
+            Command::GetLoggingConfig { .. } => "get_logging_config",
+            Command::SetLoggingConfig { .. } => "set_logging_config",
+            Command::SnapshotCatalog { .. } => "snapshot_catalog",
+            Command::ReencryptCatalog { .. } => "reencrypt_catalog",
+            Command::CompactCatalog { .. } => "compact_catalog",
+            Command::CopyRows { .. } => "copy_rows",
+            Command::Terminate { .. } => "terminate",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Response<T> {
     pub result: Result<T, CoordError>,
@@ -100,6 +203,78 @@ pub struct Response<T> {
 
 pub type RowsFuture = Pin<Box<dyn Future<Output = PeekResponse> + Send>>;
 
+/// Describes an active session, as returned by
+/// [`SessionClient::list_sessions`](crate::SessionClient::list_sessions).
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    /// The connection ID of the session.
+    pub conn_id: u32,
+    /// The correlation ID of the network connection that owns the session,
+    /// if any.
+    pub correlation_id: Option<String>,
+    /// The transport over which the client that owns the session connected,
+    /// if any.
+    pub transport: Option<Transport>,
+    /// The user that owns the session.
+    pub user: String,
+    /// The session's current `application_name`.
+    pub application_name: String,
+    /// The options that the client supplied when it established the session.
+    pub options: BTreeMap<String, String>,
+    /// The time at which the session started, in milliseconds since the Unix
+    /// epoch.
+    pub start_time: EpochMillis,
+    /// Whether the session is streaming the results of a `TAIL`.
+    pub tailing: bool,
+    /// The buffer of the `TAIL` that the session is streaming, if any.
+    pub tail_buffer: Option<TailBufferInfo>,
+    /// The progress of the `COPY` that the session is running, if any.
+    pub copy: Option<CopyProgress>,
+    /// The labels of the statement that the session is executing, if any.
+    pub labels: StatementLabels,
+    /// The statement that the session is executing, if any.
+    pub statement: Option<CurrentStatement>,
+}
+
+/// Describes how far a source's timestamps trail the wall clock, as returned
+/// by [`Client::source_timestamp_lags`](crate::Client::source_timestamp_lags).
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceTimestampLag {
+    /// The fully qualified name of the source.
+    pub name: String,
+    /// The time since the source's most recently closed timestamp, in
+    /// milliseconds, or `None` if the source has not yet closed a timestamp
+    /// or will never close another.
+    pub lag_ms: Option<u64>,
+}
+
+/// Counts the objects in the catalog and the arrangements that the dataflow
+/// layer maintains, as returned by
+/// [`SessionClient::catalog_summary`](crate::SessionClient::catalog_summary).
+///
+/// Unlike the introspection sources, the summary is derived from the
+/// coordinator's own state, and so is available even when introspection is
+/// disabled.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CatalogSummary {
+    /// The number of user tables.
+    pub tables: usize,
+    /// The number of user sources.
+    pub sources: usize,
+    /// The number of user views, including materialized views.
+    pub views: usize,
+    /// The number of user sinks.
+    pub sinks: usize,
+    /// The number of user indexes.
+    pub indexes: usize,
+    /// The number of indexes, including those on system objects, whose
+    /// arrangements the dataflow layer currently maintains.
+    pub arrangements: usize,
+    /// Whether the introspection dataflows are active. If not, the
+    /// introspection sources are empty or stale.
+    pub introspection_active: bool,
+}
+
 /// The response to [`ConnClient::startup`](crate::ConnClient::startup).
 #[derive(Debug)]
 pub struct StartupResponse {
@@ -255,7 +430,7 @@ pub enum ExecuteResponse {
     /// Updates to the requested source or view will be streamed to the
     /// contained receiver.
     Tailing {
-        rx: mpsc::UnboundedReceiver<Vec<Row>>,
+        rx: TailReceiver,
     },
     /// The specified number of rows were updated in the requested table.
     Updated(usize),
@@ -273,6 +448,15 @@ pub struct SimpleResult {
     pub col_names: Vec<Option<String>>,
 }
 
+/// The response to
+/// [`SessionClient::simple_execute_rows`](crate::SessionClient::simple_execute_rows).
+#[derive(Debug)]
+pub struct SimpleRows {
+    /// The names and types of the columns of `rows`.
+    pub desc: RelationDesc,
+    pub rows: Vec<Row>,
+}
+
 /// The state of a cancellation request.
 #[derive(Debug, Clone, Copy)]
 pub enum Cancelled {
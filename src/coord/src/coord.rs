@@ -38,11 +38,13 @@
 use std::cell::RefCell;
 use std::cmp;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::net::IpAddr;
 use std::path::Path;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use self::prometheus::{Scraper, ScraperMessage};
 use anyhow::{anyhow, Context};
@@ -55,9 +57,7 @@ use futures::stream::{self, StreamExt};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use ore::metrics::MetricsRegistry;
-use rand::Rng;
 use repr::adt::numeric;
-use timely::communication::WorkerGuards;
 use timely::order::PartialOrder;
 use timely::progress::frontier::MutableAntichain;
 use timely::progress::{Antichain, ChangeBatch, Timestamp as _};
@@ -67,12 +67,14 @@ use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use build_info::BuildInfo;
 use dataflow::{
-    SequencedCommand, TimestampBindingFeedback, WorkerFeedback, WorkerFeedbackWithMeta,
+    ArrangementSizes, SequencedCommand, TimestampBindingFeedback, WorkerFeedback,
+    WorkerFeedbackWithMeta,
 };
 use dataflow_types::logging::LoggingConfig as DataflowLoggingConfig;
 use dataflow_types::{
     DataflowDesc, ExternalSourceConnector, IndexDesc, PeekResponse, PostgresSourceConnector,
-    SinkConnector, SourceConnector, TailSinkConnector, TimestampSourceUpdate, Update,
+    SinkConnector, SourceConnector, TailBufferPolicy, TailSinkConnector, TimestampSourceUpdate,
+    Update,
 };
 use dataflow_types::{SinkAsOf, SinkEnvelope, Timeline};
 use expr::{
@@ -103,25 +105,48 @@ use sql::plan::{
 use transform::Optimizer;
 
 use self::arrangement_state::{ArrangementFrontiers, Frontiers, SinkWrites};
+use crate::activity::StatementActivity;
+use crate::cancel::{CancelKeys, CancelOutcome};
 use crate::catalog::builtin::{BUILTINS, MZ_VIEW_FOREIGN_KEYS, MZ_VIEW_KEYS};
-use crate::catalog::{self, BuiltinTableUpdate, Catalog, CatalogItem, SinkConnectorState};
+use crate::catalog::check::CheckLevel;
+use crate::catalog::{
+    self, BuiltinTableUpdate, Catalog, CatalogItem, DurabilityLevel, EncryptionKey,
+    SinkConnectorState,
+};
 use crate::client::{Client, Handle};
+use crate::clock::{ClockConfig, ClockMonitor};
 use crate::command::{
-    Cancelled, Command, ExecuteResponse, Response, StartupMessage, StartupResponse,
+    Cancelled, CatalogSummary, Command, ExecuteResponse, Response, SessionInfo, SourceTimestampLag,
+    StartupMessage, StartupResponse,
 };
 use crate::coord::antichain::AntichainToken;
+use crate::coord::session_metrics::SessionMetrics;
+use crate::copies::ActiveCopies;
+use crate::dataflow_budget::DataflowMemoryBudget;
 use crate::error::CoordError;
+use crate::health::{
+    DataflowHealth, HealthStatus, Progress, SinkHealth, SourceHealth, SourceReports,
+};
+use crate::lanes::{self, Lanes};
+use crate::maintenance::MaintenanceMode;
 use crate::session::{
-    EndTransactionAction, PreparedStatement, Session, TransactionOps, TransactionStatus, WriteOp,
+    EndTransactionAction, PreparedStatement, Session, SessionDefaults, TransactionOps,
+    TransactionStatus, WriteOp,
 };
 use crate::sink_connector;
+use crate::slow_query::SlowQueryLog;
+use crate::statement_labels::LabeledStatements;
+use crate::tails::{ActiveTails, TailReceiver};
 use crate::timestamp::{TimestampMessage, Timestamper};
+use crate::transport::Transport;
+use crate::user_stats::{UserStats, DEFAULT_MAX_TRACKED_USERS};
 use crate::util::ClientTransmitter;
 
 mod antichain;
 mod arrangement_state;
 mod dataflow_builder;
 mod prometheus;
+mod session_metrics;
 
 #[derive(Debug)]
 pub enum Message {
@@ -168,31 +193,53 @@ pub struct TimestampedUpdate {
 }
 
 /// Configures dataflow worker logging.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LoggingConfig {
     pub granularity: Duration,
     pub log_logging: bool,
     pub retain_readings_for: Duration,
+    /// Whether the introspection dataflows are running.
+    ///
+    /// The introspection sources exist in the catalog regardless, but can
+    /// only be queried while they are active.
+    pub active: bool,
 }
 
 /// Configures a coordinator.
 pub struct Config<'a> {
     pub workers: usize,
     pub timely_worker: timely::WorkerConfig,
+    pub worker_start_hook: Option<dataflow::WorkerStartHook>,
+    pub worker_stack_size: Option<usize>,
     pub symbiosis_url: Option<&'a str>,
     pub logging: Option<LoggingConfig>,
-    pub data_directory: &'a Path,
+    pub catalog_directory: &'a Path,
+    pub storage_directory: &'a Path,
+    pub scratch_directory: &'a Path,
     pub timestamp_frequency: Duration,
     pub logical_compaction_window: Option<Duration>,
     pub experimental_mode: bool,
     pub safe_mode: bool,
     pub build_info: &'static BuildInfo,
     pub metrics_registry: MetricsRegistry,
+    pub slow_query_log: SlowQueryLog,
+    pub durability: DurabilityLevel,
+    pub read_only: bool,
+    pub maintenance_mode: MaintenanceMode,
+    pub clock: ClockMonitor,
+    pub encryption_key: Option<EncryptionKey>,
+    pub session_defaults: SessionDefaults,
+    pub tail_buffer_size: usize,
+    pub tail_buffer_policy: TailBufferPolicy,
+    pub outbound_bind_addr: Option<IpAddr>,
+    pub user_stats_max_users: usize,
+    pub dataflow_memory_budget: Option<u64>,
+    pub dataflow_memory_budget_enforce: bool,
 }
 
 /// Glues the external world to the Timely workers.
 pub struct Coordinator {
-    worker_guards: WorkerGuards<()>,
+    worker_guards: dataflow::WorkerGuards,
     worker_txs: Vec<crossbeam_channel::Sender<SequencedCommand>>,
     /// Optimizer instance for logical optimization of views.
     view_optimizer: Optimizer,
@@ -207,8 +254,9 @@ pub struct Coordinator {
     sources: ArrangementFrontiers<Timestamp>,
     /// Delta from leading edge of an arrangement from which we allow compaction.
     logical_compaction_window_ms: Option<Timestamp>,
-    /// Whether base sources are enabled.
-    logging_enabled: bool,
+    /// The current configuration of the introspection sources, if they are
+    /// enabled.
+    logging: Option<LoggingConfig>,
     /// Channel to manange internal commands from the coordinator to itself.
     internal_cmd_tx: mpsc::UnboundedSender<Message>,
     /// Channel to communicate source status updates to the timestamper thread.
@@ -224,10 +272,30 @@ pub struct Coordinator {
     // TODO(justin): this is a hack, and does not work right with TAIL.
     need_advance: bool,
     transient_id_counter: u64,
+    /// Issues the secret keys with which clients cancel queries.
+    cancel_keys: CancelKeys,
+    /// The defaults applied to every new session.
+    session_defaults: SessionDefaults,
+    session_metrics: SessionMetrics,
+    /// Tracks the sessions that are streaming the results of a `TAIL`.
+    active_tails: ActiveTails,
+    /// The default limit on the bytes that a `TAIL` buffers for its client.
+    tail_buffer_size: usize,
+    /// What a `TAIL` does by default when its buffer is full.
+    tail_buffer_policy: TailBufferPolicy,
+    /// Tracks the sessions that are running a `COPY`.
+    active_copies: ActiveCopies,
+    /// Tracks the labels of the statements that are executing.
+    labeled_statements: LabeledStatements,
+    /// Tracks the statements that are executing.
+    statement_activity: StatementActivity,
     /// A map from connection ID to metadata about that connection for all
     /// active connections.
     active_conns: HashMap<u32, ConnMeta>,
     now: NowFn,
+    /// Reads the wall clock from which timestamps are assigned, holding the
+    /// times it reports monotone across regressions.
+    clock: ClockMonitor,
 
     /// Holds pending compaction messages to be sent to the dataflow workers. When
     /// `since_handles` are advanced or `txn_reads` are dropped, this can advance.
@@ -240,6 +308,21 @@ pub struct Coordinator {
     txn_reads: HashMap<u32, TxnReads>,
     /// Tracks write frontiers for active exactly-once sinks.
     sink_writes: HashMap<GlobalId, SinkWrites<Timestamp>>,
+    /// Tracks when the frontiers of sources and of the sinks in `sink_writes`
+    /// last advanced.
+    progress: HashMap<GlobalId, Progress>,
+    /// The errors and upstream offsets that the timestamper reports for each
+    /// source.
+    source_reports: SourceReports,
+    /// Whether the coordinator is in read-only mode, in which it rejects
+    /// statements that would modify the catalog or write data, and never
+    /// persists timestamp bindings.
+    read_only: bool,
+    /// Reports whether the server is in maintenance mode, in which the
+    /// coordinator rejects the same statements as in read-only mode.
+    maintenance_mode: MaintenanceMode,
+    /// Admits new dataflows against the budget for dataflow memory.
+    dataflow_memory_budget: DataflowMemoryBudget,
 }
 
 /// Metadata about an active connection.
@@ -256,6 +339,31 @@ struct ConnMeta {
     /// requests are required to authenticate with the secret of the connection
     /// that they are targeting.
     secret_key: u32,
+    /// The correlation ID of the network connection, if any.
+    correlation_id: Option<String>,
+    /// The transport over which the client connected, if known.
+    transport: Option<Transport>,
+    /// The user that owns the connection.
+    user: String,
+    /// The connection's current `application_name`.
+    application_name: String,
+    /// The label under which the connection is counted in the session
+    /// metrics.
+    application_label: String,
+    /// The options that the client supplied when it established the
+    /// connection.
+    options: BTreeMap<String, String>,
+    /// The time at which the connection started, in milliseconds since the
+    /// Unix epoch.
+    start_time: EpochMillis,
+    /// The name of the transient dataflow built for the connection's most
+    /// recent peek, if any. The dataflow may since have been dropped.
+    peek_dataflow: Option<String>,
+    /// The sinks of the `TAIL`s that the connection started in its current
+    /// transaction. They are dropped as soon as the connection's statement
+    /// is canceled, rather than when the transaction ends, so that a canceled
+    /// `TAIL` or `COPY (TAIL ...)` stops promptly.
+    tail_sinks: Vec<GlobalId>,
 }
 
 struct TxnReads {
@@ -296,7 +404,7 @@ impl Coordinator {
         // This is a hack. In a perfect world we would represent time as having a "real" dimension
         // and a "coordinator" dimension so that clients always observed linearizability from
         // things the coordinator did without being related to the real dimension.
-        let ts = (self.now)();
+        let ts = self.clock.now();
 
         if ts < self.read_lower_bound {
             self.read_lower_bound
@@ -347,6 +455,17 @@ impl Coordinator {
     ) -> Result<(), CoordError> {
         let entries: Vec<_> = self.catalog.entries().cloned().collect();
 
+        // Indexes and sinks that depend on the introspection sources cannot be
+        // built while the introspection dataflows are inactive.
+        if let Some(LoggingConfig { active: false, .. }) = &self.logging {
+            if let Some((dependent, source)) = self.find_introspection_dependent() {
+                return Err(CoordError::IntrospectionInUse {
+                    dependent: self.catalog.get_by_id(&dependent).name().to_string(),
+                    source: self.catalog.get_by_id(&source).name().to_string(),
+                });
+            }
+        }
+
         // Sources and indexes may be depended upon by other catalog items,
         // insert them first.
         for entry in &entries {
@@ -362,6 +481,7 @@ impl Coordinator {
                     let frontiers =
                         self.new_frontiers(entry.id(), Some(0), self.logical_compaction_window_ms);
                     self.sources.insert(entry.id(), frontiers);
+                    self.progress.insert(entry.id(), Progress::new());
                 }
                 CatalogItem::Index(_) => {
                     if BUILTINS.logs().any(|log| log.index_id == entry.id()) {
@@ -388,6 +508,10 @@ impl Coordinator {
         for entry in entries {
             match entry.item() {
                 CatalogItem::View(_) => (),
+                // Starting a sink writes to its external system and records
+                // its connector in the catalog, neither of which is permitted
+                // in read-only mode, so sinks are left pending.
+                CatalogItem::Sink(_) if self.read_only => (),
                 CatalogItem::Sink(sink) => {
                     let builder = match &sink.connector {
                         SinkConnectorState::Pending(builder) => builder,
@@ -408,7 +532,7 @@ impl Coordinator {
         self.send_builtin_table_updates(builtin_table_updates).await;
 
         // Announce primary and foreign key relationships.
-        if self.logging_enabled {
+        if self.logging.is_some() {
             for log in BUILTINS.logs() {
                 let log_id = &log.id.to_string();
                 self.send_builtin_table_updates(
@@ -472,19 +596,19 @@ impl Coordinator {
         Ok(())
     }
 
-    /// Serves the coordinator, receiving commands from users over `cmd_rx`
-    /// and feedback from dataflow workers over `feedback_rx`.
+    /// Serves the coordinator, receiving commands from users over the lanes
+    /// of `cmd_rx` and feedback from dataflow workers over `feedback_rx`.
     ///
     /// You must call `bootstrap` before calling this method.
     async fn serve(
         mut self,
         internal_cmd_rx: mpsc::UnboundedReceiver<Message>,
-        cmd_rx: mpsc::UnboundedReceiver<Command>,
+        cmd_rx: Lanes<Command>,
         feedback_rx: mpsc::UnboundedReceiver<WorkerFeedbackWithMeta>,
         _timestamper_thread_handle: JoinOnDropHandle<()>,
         _metric_thread_handle: Option<JoinOnDropHandle<()>>,
     ) {
-        let cmd_stream = UnboundedReceiverStream::new(cmd_rx)
+        let cmd_stream = cmd_rx
             .map(Message::Command)
             .chain(stream::once(future::ready(Message::Shutdown)));
 
@@ -568,13 +692,18 @@ impl Coordinator {
                 self.maintenance().await;
             }
             WorkerFeedback::TimestampBindings(TimestampBindingFeedback { bindings, changes }) => {
-                self.catalog
-                    .insert_timestamp_bindings(
-                        bindings
-                            .into_iter()
-                            .map(|(id, pid, ts, offset)| (id, pid.to_string(), ts, offset.offset)),
-                    )
-                    .expect("inserting timestamp bindings cannot fail");
+                // In read-only mode, timestamp bindings live only in memory,
+                // but are otherwise treated as if they had been persisted, so
+                // that sources continue to make progress.
+                if !self.read_only {
+                    self.catalog
+                        .insert_timestamp_bindings(
+                            bindings.into_iter().map(|(id, pid, ts, offset)| {
+                                (id, pid.to_string(), ts, offset.offset)
+                            }),
+                        )
+                        .expect("inserting timestamp bindings cannot fail");
+                }
 
                 let mut durability_updates = Vec::new();
                 for (source_id, mut changes) in changes {
@@ -621,9 +750,11 @@ impl Coordinator {
                                 .saturating_sub(1)
                         };
 
-                        self.catalog
-                            .compact_timestamp_bindings(source_id, compaction_ts)
-                            .expect("compacting timestamp bindings cannot fail");
+                        if !self.read_only {
+                            self.catalog
+                                .compact_timestamp_bindings(source_id, compaction_ts)
+                                .expect("compacting timestamp bindings cannot fail");
+                        }
                     }
                 }
 
@@ -715,10 +846,15 @@ impl Coordinator {
     async fn message_command(&mut self, cmd: Command) {
         match cmd {
             Command::Startup {
-                session,
+                mut session,
                 cancel_tx,
                 tx,
             } => {
+                session.vars_mut().apply_defaults(&self.session_defaults);
+                session.vars_mut().set_logical_compaction_window(
+                    self.logical_compaction_window_ms.map(Duration::from_millis),
+                );
+
                 if let Err(e) = self.catalog.create_temporary_schema(session.conn_id()) {
                     let _ = tx.send(Response {
                         result: Err(e.into()),
@@ -746,13 +882,24 @@ impl Coordinator {
                     ));
                 }
 
-                let secret_key = rand::thread_rng().gen();
+                let secret_key = self.cancel_keys.generate();
+                let application_name = session.vars().application_name().to_owned();
+                let application_label = self.session_metrics.session_started(&application_name);
 
                 self.active_conns.insert(
                     session.conn_id(),
                     ConnMeta {
                         cancel_tx,
                         secret_key,
+                        correlation_id: session.correlation_id().map(String::from),
+                        transport: session.transport().cloned(),
+                        user: session.user().into(),
+                        application_name,
+                        application_label,
+                        options: session.options().clone(),
+                        start_time: (self.now)(),
+                        peek_dataflow: None,
+                        tail_sinks: vec![],
                     },
                 );
 
@@ -939,8 +1086,9 @@ impl Coordinator {
             Command::CancelRequest {
                 conn_id,
                 secret_key,
+                tx,
             } => {
-                self.handle_cancel(conn_id, secret_key).await;
+                let _ = tx.send(self.handle_cancel(conn_id, secret_key).await);
             }
 
             Command::DumpCatalog { session, tx } => {
@@ -953,6 +1101,98 @@ impl Coordinator {
                 });
             }
 
+            Command::DumpCatalogSql { options, tx } => {
+                let _ = tx.send(self.catalog.dump_sql(&options));
+            }
+
+            Command::ListCatalogFiles { tx } => {
+                let _ = tx.send(self.catalog.file_paths());
+            }
+
+            Command::ListSourceTimestampLags { tx } => {
+                let _ = tx.send(self.source_timestamp_lags());
+            }
+
+            Command::ListDataflowHealth { tx } => {
+                let _ = tx.send(self.dataflow_health());
+            }
+
+            Command::SummarizeCatalog { tx } => {
+                let _ = tx.send(self.catalog_summary());
+            }
+
+            Command::CancelDataflowQueries { dataflows, tx } => {
+                let _ = tx.send(self.handle_cancel_dataflow_queries(&dataflows));
+            }
+
+            Command::Ping { tx } => {
+                let _ = tx.send(());
+            }
+
+            Command::ListSessions { tx } => {
+                let mut sessions: Vec<_> = self
+                    .active_conns
+                    .iter()
+                    .map(|(conn_id, conn_meta)| SessionInfo {
+                        conn_id: *conn_id,
+                        correlation_id: conn_meta.correlation_id.clone(),
+                        transport: conn_meta.transport.clone(),
+                        user: conn_meta.user.clone(),
+                        application_name: conn_meta.application_name.clone(),
+                        options: conn_meta.options.clone(),
+                        start_time: conn_meta.start_time,
+                        tailing: self.active_tails.is_tailing(*conn_id),
+                        tail_buffer: self.active_tails.buffer_info(*conn_id),
+                        copy: self.active_copies.progress(*conn_id),
+                        labels: self.labeled_statements.get(*conn_id).unwrap_or_default(),
+                        statement: self.statement_activity.current(*conn_id),
+                    })
+                    .collect();
+                sessions.sort_by_key(|session| session.conn_id);
+                let _ = tx.send(sessions);
+            }
+
+            Command::GetLoggingConfig { tx } => {
+                let _ = tx.send(self.logging.clone());
+            }
+
+            Command::SetLoggingConfig { config, tx } => {
+                let _ = tx.send(self.set_logging_config(config));
+            }
+
+            Command::ReencryptCatalog { key, tx } => {
+                // Like a snapshot, reencryption blocks the coordinator, but
+                // touches only the definitions of the catalog's items.
+                let _ = tx.send(self.catalog.reencrypt(key).map_err(CoordError::from));
+            }
+
+            Command::CompactCatalog { tx } => {
+                // Compaction rewrites the whole catalog, so, like a
+                // snapshot, it blocks the coordinator for its duration.
+                let result = if self.read_only {
+                    Err(CoordError::Unstructured(anyhow!(
+                        "the catalog cannot be compacted in read-only mode"
+                    )))
+                } else {
+                    self.catalog.compact().map_err(CoordError::from)
+                };
+                let _ = tx.send(result);
+            }
+
+            Command::SnapshotCatalog { path, tx } => {
+                // The coordinator is blocked for the duration of the
+                // snapshot, but the catalog is small, and copying it is much
+                // cheaper than the catalog transactions that DDL performs.
+                let result = match path.to_str() {
+                    Some(path) => self.catalog.snapshot(path).map_err(CoordError::from),
+                    None => Err(CoordError::Unstructured(anyhow!(
+                        "catalog snapshot path is not valid UTF-8: {}",
+                        path.display()
+                    ))),
+                };
+                let _ = tx.send(result);
+            }
+
             Command::CopyRows {
                 id,
                 columns,
@@ -1044,6 +1284,165 @@ impl Coordinator {
         frontier_changes
     }
 
+    /// Reports how far the timestamps of each source in the catalog trail
+    /// the wall clock.
+    ///
+    /// A source's timestamps are closed up to, but not including, its upper
+    /// frontier, so the lag is measured from the upper frontier.
+    fn source_timestamp_lags(&self) -> Vec<SourceTimestampLag> {
+        let now = (self.now)();
+        let mut lags: Vec<_> = self
+            .catalog
+            .entries()
+            .filter(|entry| matches!(entry.item(), CatalogItem::Source(_)))
+            .filter_map(|entry| {
+                let source_state = self.sources.get(&entry.id())?;
+                // An empty frontier means that the source is complete, and a
+                // frontier at the minimum time means that the source has not
+                // yet closed any timestamps. Neither has a meaningful lag.
+                let lag_ms = source_state
+                    .upper
+                    .frontier()
+                    .iter()
+                    .min()
+                    .filter(|upper| **upper > 0)
+                    .map(|upper| now.saturating_sub(*upper));
+                Some(SourceTimestampLag {
+                    name: entry.name().to_string(),
+                    lag_ms,
+                })
+            })
+            .collect();
+        lags.sort_by(|a, b| a.name.cmp(&b.name));
+        lags
+    }
+
+    /// Counts the user objects in the catalog and the arrangements that the
+    /// dataflow layer maintains.
+    fn catalog_summary(&self) -> CatalogSummary {
+        let mut summary = CatalogSummary {
+            introspection_active: matches!(&self.logging, Some(config) if config.active),
+            ..Default::default()
+        };
+        for entry in self.catalog.entries() {
+            if let CatalogItem::Index(_) = entry.item() {
+                if self.indexes.contains_key(entry.id()) {
+                    summary.arrangements += 1;
+                }
+            }
+            if !entry.id().is_user() {
+                continue;
+            }
+            match entry.item() {
+                CatalogItem::Table(_) => summary.tables += 1,
+                CatalogItem::Source(_) => summary.sources += 1,
+                CatalogItem::View(_) => summary.views += 1,
+                CatalogItem::Sink(_) => summary.sinks += 1,
+                CatalogItem::Index(_) => summary.indexes += 1,
+                CatalogItem::Type(_) | CatalogItem::Func(_) => (),
+            }
+        }
+        summary
+    }
+
+    /// Reports the health of each source and sink in the catalog.
+    ///
+    /// Sinks whose writes are not tracked report no progress, and are
+    /// considered running once their connector is ready.
+    fn dataflow_health(&self) -> DataflowHealth {
+        let now = Instant::now();
+        let now_ms = (self.now)();
+        let default_interval = self.catalog.config().timestamp_frequency;
+        let mut health = DataflowHealth::default();
+        for entry in self.catalog.entries() {
+            match entry.item() {
+                CatalogItem::Source(source) => {
+                    let (source_state, progress) = match (
+                        self.sources.get(&entry.id()),
+                        self.progress.get(&entry.id()),
+                    ) {
+                        (Some(source_state), Some(progress)) => (source_state, progress),
+                        _ => continue,
+                    };
+                    let upper = source_state.upper.frontier().iter().min().copied();
+                    let interval = match &source.connector {
+                        SourceConnector::External { ts_frequency, .. } => *ts_frequency,
+                        SourceConnector::Local(_) => default_interval,
+                    };
+                    let report = self.source_reports.get(entry.id()).unwrap_or_default();
+                    let error = report
+                        .error
+                        .as_ref()
+                        .map(|(error, at)| (error.as_str(), *at));
+                    health.sources.push(SourceHealth {
+                        id: entry.id().to_string(),
+                        name: entry.name().to_string(),
+                        connector: source.connector.name(),
+                        status: HealthStatus::determine(
+                            now,
+                            progress.last,
+                            progress.started,
+                            upper.is_none(),
+                            interval,
+                            error,
+                        ),
+                        upper,
+                        last_progress_ms: duration_to_timestamp_millis(now - progress.last),
+                        lag_ms: upper
+                            .filter(|upper| *upper > 0)
+                            .map(|upper| now_ms.saturating_sub(upper)),
+                        upstream_offsets: report.upstream_offsets,
+                    });
+                }
+                CatalogItem::Sink(sink) => {
+                    let connector = match &sink.connector {
+                        SinkConnectorState::Pending(_) => None,
+                        SinkConnectorState::Ready(connector) => Some(connector.name()),
+                    };
+                    let writes = self
+                        .sink_writes
+                        .get(&entry.id())
+                        .zip(self.progress.get(&entry.id()));
+                    let mut sink_health = SinkHealth {
+                        id: entry.id().to_string(),
+                        name: entry.name().to_string(),
+                        connector,
+                        status: HealthStatus::Starting,
+                        upper: None,
+                        last_progress_ms: None,
+                        lag_ms: None,
+                    };
+                    match (connector, writes) {
+                        (None, _) => (),
+                        (Some(_), None) => sink_health.status = HealthStatus::Running,
+                        (Some(_), Some((writes, progress))) => {
+                            let upper = writes.frontier.frontier().iter().min().copied();
+                            sink_health.status = HealthStatus::determine(
+                                now,
+                                progress.last,
+                                progress.started,
+                                upper.is_none(),
+                                default_interval,
+                                None,
+                            );
+                            sink_health.upper = upper;
+                            sink_health.last_progress_ms =
+                                Some(duration_to_timestamp_millis(now - progress.last));
+                            sink_health.lag_ms = upper
+                                .filter(|upper| *upper > 0)
+                                .map(|upper| now_ms.saturating_sub(upper));
+                        }
+                    }
+                    health.sinks.push(sink_health);
+                }
+                _ => (),
+            }
+        }
+        health.sources.sort_by(|a, b| a.name.cmp(&b.name));
+        health.sinks.sort_by(|a, b| a.name.cmp(&b.name));
+        health
+    }
+
     /// Updates the upper frontier of a named view.
     fn update_upper(&mut self, name: &GlobalId, changes: ChangeBatch<Timestamp>) {
         let num_workers = self.num_workers();
@@ -1085,6 +1484,9 @@ impl Coordinator {
                 Coordinator::validate_update_iter(&mut source_state.upper, changes, num_workers);
 
             if !changes.is_empty() {
+                if let Some(progress) = self.progress.get_mut(name) {
+                    progress.advance();
+                }
                 if let Some(compaction_window_ms) = source_state.compaction_window_ms {
                     if !source_state.upper.frontier().is_empty() {
                         self.since_handles.get_mut(name).unwrap().maybe_advance(
@@ -1103,6 +1505,9 @@ impl Coordinator {
 
             if !changes.is_empty() {
                 sink_state.advance_source_handles();
+                if let Some(progress) = self.progress.get_mut(name) {
+                    progress.advance();
+                }
             }
         }
     }
@@ -1233,21 +1638,49 @@ impl Coordinator {
 
     /// Instruct the dataflow layer to cancel any ongoing, interactive work for
     /// the named `conn_id`.
-    async fn handle_cancel(&mut self, conn_id: u32, secret_key: u32) {
-        if let Some(conn_meta) = self.active_conns.get(&conn_id) {
+    ///
+    /// The request must present the connection's `secret_key`, unless it was
+    /// authorized by other means, in which case `secret_key` is `None`.
+    async fn handle_cancel(&mut self, conn_id: u32, secret_key: Option<u32>) -> CancelOutcome {
+        match self.active_conns.get_mut(&conn_id) {
             // If the secret key specified by the client doesn't match the
             // actual secret key for the target connection, we treat this as a
             // rogue cancellation request and ignore it.
-            if conn_meta.secret_key != secret_key {
-                return;
+            Some(conn_meta) if secret_key.map_or(true, |key| key == conn_meta.secret_key) => {
+                // Inform the target session (if it asks) about the cancellation.
+                let _ = conn_meta.cancel_tx.send(Cancelled::Cancelled);
+                let tail_sinks = mem::take(&mut conn_meta.tail_sinks);
+
+                // Tell dataflow to cancel any pending peeks, and to stop any
+                // tails.
+                self.broadcast(SequencedCommand::CancelPeek { conn_id });
+                self.drop_sinks(tail_sinks).await;
+                CancelOutcome::Matched
             }
+            _ => CancelOutcome::Unmatched,
+        }
+    }
 
-            // Tell dataflow to cancel any pending peeks.
-            self.broadcast(SequencedCommand::CancelPeek { conn_id });
-
-            // Inform the target session (if it asks) about the cancellation.
-            let _ = conn_meta.cancel_tx.send(Cancelled::Cancelled);
+    /// Cancels the queries whose peeks are served by any of the named
+    /// transient `dataflows`, as if by a cancellation request from the
+    /// connections that issued them.
+    ///
+    /// Returns the name of the dataflow and the ID of the connection of each
+    /// query that was canceled.
+    fn handle_cancel_dataflow_queries(&self, dataflows: &[String]) -> Vec<(String, u32)> {
+        let mut canceled = vec![];
+        for (conn_id, conn_meta) in &self.active_conns {
+            if let Some(dataflow) = &conn_meta.peek_dataflow {
+                if dataflows.contains(dataflow) {
+                    canceled.push((dataflow.clone(), *conn_id));
+                    let _ = conn_meta.cancel_tx.send(Cancelled::Cancelled);
+                }
+            }
+        }
+        for (_, conn_id) in &canceled {
+            self.broadcast(SequencedCommand::CancelPeek { conn_id: *conn_id });
         }
+        canceled
     }
 
     /// Handle termination of a client session.
@@ -1261,7 +1694,10 @@ impl Coordinator {
         self.catalog
             .drop_temporary_schema(session.conn_id())
             .expect("unable to drop temporary schema");
-        self.active_conns.remove(&session.conn_id());
+        if let Some(conn_meta) = self.active_conns.remove(&session.conn_id()) {
+            self.session_metrics
+                .session_ended(&conn_meta.application_label);
+        }
     }
 
     /// Removes all temporary items created by the specified connection, though
@@ -1327,6 +1763,7 @@ impl Coordinator {
 
             let sink_writes = SinkWrites::new(tokens);
             self.sink_writes.insert(id, sink_writes);
+            self.progress.insert(id, Progress::new());
         }
         Ok(self.ship_dataflow(df).await)
     }
@@ -1337,6 +1774,21 @@ impl Coordinator {
         mut session: Session,
         plan: Plan,
     ) {
+        if let Some(op) = plan_write_op(&plan) {
+            if self.read_only {
+                tx.send(Err(CoordError::ReadOnlyServer(op)), session);
+                return;
+            }
+            // The maintenance mode is consulted only before a statement is
+            // sequenced, so statements that are underway when the mode is
+            // enabled are unaffected.
+            if let Some(maintenance) = self.maintenance_mode.get() {
+                let message = maintenance.message;
+                tx.send(Err(CoordError::MaintenanceMode { op, message }), session);
+                return;
+            }
+        }
+
         match plan {
             Plan::CreateDatabase(plan) => {
                 tx.send(self.sequence_create_database(plan).await, session);
@@ -1677,6 +2129,10 @@ impl Coordinator {
 
         let if_not_exists = plan.if_not_exists;
         let (metadata, ops) = self.generate_create_source_ops(session, vec![plan])?;
+        if metadata.iter().any(|(_, index_id)| index_id.is_some()) {
+            self.dataflow_memory_budget
+                .admit("CREATE MATERIALIZED SOURCE")?;
+        }
         match self.catalog_transact(ops).await {
             Ok(()) => {
                 self.ship_sources(metadata).await;
@@ -1699,6 +2155,7 @@ impl Coordinator {
             let frontiers =
                 self.new_frontiers(source_id, Some(0), self.logical_compaction_window_ms);
             self.sources.insert(source_id, frontiers);
+            self.progress.insert(source_id, Progress::new());
             if let Some(index_id) = idx_id {
                 let df = self.dataflow_builder().build_index_dataflow(index_id);
                 self.ship_dataflow(df).await;
@@ -1785,6 +2242,11 @@ impl Coordinator {
             depends_on,
         } = plan;
 
+        if let Err(e) = self.validate_introspection_active(vec![sink.from]) {
+            tx.send(Err(e), session);
+            return;
+        }
+
         // First try to allocate an ID and an OID. If either fails, we're done.
         let id = match self.catalog.allocate_id() {
             Ok(id) => id,
@@ -1867,6 +2329,9 @@ impl Coordinator {
         } = plan;
 
         self.validate_timeline(view.expr.global_uses())?;
+        if materialize {
+            self.validate_introspection_active(view.expr.global_uses())?;
+        }
 
         let mut ops = vec![];
 
@@ -1933,6 +2398,10 @@ impl Coordinator {
     ) -> Result<ExecuteResponse, CoordError> {
         let if_not_exists = plan.if_not_exists;
         let (ops, index_id) = self.generate_view_ops(session, plan)?;
+        if index_id.is_some() {
+            self.dataflow_memory_budget
+                .admit("CREATE MATERIALIZED VIEW")?;
+        }
 
         match self.catalog_transact(ops).await {
             Ok(()) => {
@@ -1965,6 +2434,10 @@ impl Coordinator {
                 index_ids.push(index_id);
             }
         }
+        if !index_ids.is_empty() {
+            self.dataflow_memory_budget
+                .admit("CREATE MATERIALIZED VIEWS")?;
+        }
 
         match self.catalog_transact(ops).await {
             Ok(()) => {
@@ -1994,6 +2467,7 @@ impl Coordinator {
             depends_on,
         } = plan;
 
+        self.validate_introspection_active(vec![index.on])?;
         for key in &mut index.keys {
             Self::prep_scalar_expr(key, ExprPrepStyle::Static)?;
         }
@@ -2012,6 +2486,7 @@ impl Coordinator {
             name,
             item: CatalogItem::Index(index),
         };
+        self.dataflow_memory_budget.admit("CREATE INDEX")?;
         match self.catalog_transact(vec![op]).await {
             Ok(()) => {
                 let df = self.dataflow_builder().build_index_dataflow(id);
@@ -2130,14 +2605,30 @@ impl Coordinator {
     }
 
     async fn sequence_set_variable(
-        &self,
+        &mut self,
         session: &mut Session,
         plan: SetVariablePlan,
     ) -> Result<ExecuteResponse, CoordError> {
         session.vars_mut().set(&plan.name, &plan.value)?;
+        self.update_application_name(session);
         Ok(ExecuteResponse::SetVariable { name: plan.name })
     }
 
+    /// Updates the application name reported for the session's connection,
+    /// if the session has changed it.
+    fn update_application_name(&mut self, session: &Session) {
+        let application_name = session.vars().application_name();
+        if let Some(conn_meta) = self.active_conns.get_mut(&session.conn_id()) {
+            if conn_meta.application_name != application_name {
+                self.session_metrics
+                    .session_ended(&conn_meta.application_label);
+                conn_meta.application_label =
+                    self.session_metrics.session_started(application_name);
+                conn_meta.application_name = application_name.into();
+            }
+        }
+    }
+
     async fn sequence_end_transaction(
         &mut self,
         session: &mut Session,
@@ -2150,6 +2641,9 @@ impl Coordinator {
 
         let (drop_sinks, txn) = session.clear_transaction();
         self.drop_sinks(drop_sinks).await;
+        if let Some(conn_meta) = self.active_conns.get_mut(&session.conn_id()) {
+            conn_meta.tail_sinks.clear();
+        }
 
         // Allow compaction of sources from this transaction, regardless of the action.
         self.txn_reads.remove(&session.conn_id());
@@ -2230,6 +2724,10 @@ impl Coordinator {
             }
         });
 
+        // Inactive introspection sources have nothing to read, so leave them
+        // out of the timedomain.
+        timedomain_ids.retain(|&id| self.validate_introspection_active(vec![id]).is_ok());
+
         Ok(timedomain_ids)
     }
 
@@ -2247,6 +2745,7 @@ impl Coordinator {
 
         let source_ids = source.global_uses();
         let timeline = self.validate_timeline(source_ids.clone())?;
+        self.validate_introspection_active(source_ids.clone())?;
         let conn_id = session.conn_id();
         let in_transaction = matches!(
             session.transaction(),
@@ -2406,7 +2905,11 @@ impl Coordinator {
                     .map(|k| MirScalarExpr::Column(*k))
                     .collect();
                 let view_id = self.allocate_transient_id()?;
-                let mut dataflow = DataflowDesc::new(format!("temp-view-{}", view_id));
+                let dataflow_name = format!("temp-view-{}", view_id);
+                if let Some(conn_meta) = self.active_conns.get_mut(&session.conn_id()) {
+                    conn_meta.peek_dataflow = Some(dataflow_name.clone());
+                }
+                let mut dataflow = DataflowDesc::new(dataflow_name);
                 dataflow.set_as_of(Antichain::from_elem(timestamp));
                 self.dataflow_builder()
                     .import_view_into_dataflow(&view_id, &source, &mut dataflow);
@@ -2485,7 +2988,10 @@ impl Coordinator {
             emit_progress,
             object_columns,
             desc,
+            buffer_size,
+            buffer_policy,
         } = plan;
+        self.validate_introspection_active(vec![source_id])?;
         // TAIL AS OF, similar to peeks, doesn't need to worry about transaction
         // timestamp semantics.
         if ts.is_none() {
@@ -2514,7 +3020,15 @@ impl Coordinator {
         );
         let sink_id = self.catalog.allocate_id()?;
         session.add_drop_sink(sink_id);
+        if let Some(conn_meta) = self.active_conns.get_mut(&session.conn_id()) {
+            conn_meta.tail_sinks.push(sink_id);
+        }
         let (tx, rx) = mpsc::unbounded_channel();
+        let buffer = self.active_tails.buffer(
+            buffer_size.unwrap_or(self.tail_buffer_size),
+            buffer_policy.unwrap_or(self.tail_buffer_policy),
+        );
+        let rx = TailReceiver::new(rx, buffer.clone());
 
         let df = self.dataflow_builder().build_sink_dataflow(
             sink_name,
@@ -2522,6 +3036,7 @@ impl Coordinator {
             source_id,
             SinkConnector::Tail(TailSinkConnector {
                 tx,
+                buffer,
                 emit_progress,
                 object_columns,
                 value_desc: desc,
@@ -2952,12 +3467,14 @@ impl Coordinator {
                 self.update_timestamper(id, false).await;
                 self.catalog.delete_timestamp_bindings(id)?;
                 self.sources.remove(&id);
+                self.progress.remove(&id);
             }
             self.broadcast(SequencedCommand::DropSources(sources_to_drop));
         }
         if !sinks_to_drop.is_empty() {
             for id in sinks_to_drop.iter() {
                 self.sink_writes.remove(id);
+                self.progress.remove(id);
             }
             self.broadcast(SequencedCommand::DropSinks(sinks_to_drop));
         }
@@ -2965,7 +3482,12 @@ impl Coordinator {
             self.drop_indexes(indexes_to_drop).await;
         }
         for (conn, slot_names) in replication_slots_to_drop {
-            postgres_util::drop_replication_slots(&conn, &slot_names).await?;
+            postgres_util::drop_replication_slots(
+                &conn,
+                &slot_names,
+                self.catalog.config().outbound_bind_addr,
+            )
+            .await?;
         }
 
         Ok(())
@@ -3288,6 +3810,117 @@ impl Coordinator {
         }
         Ok(timelines.into_iter().next())
     }
+
+    /// Installs the introspection dataflows described by `config` on the
+    /// dataflow workers.
+    fn enable_logging(&self, config: &LoggingConfig) {
+        self.broadcast(SequencedCommand::EnableLogging(DataflowLoggingConfig {
+            granularity_ns: config.granularity.as_nanos(),
+            active_logs: BUILTINS
+                .logs()
+                .map(|src| (src.variant.clone(), src.index_id))
+                .collect(),
+            log_logging: config.log_logging,
+        }));
+    }
+
+    /// Applies a new configuration to the introspection sources.
+    ///
+    /// Deactivating the introspection dataflows, or changing the configuration
+    /// of active dataflows, requires uninstalling them, which is refused while
+    /// any index or sink depends on an introspection source.
+    fn set_logging_config(&mut self, config: LoggingConfig) -> Result<(), CoordError> {
+        let current = match &self.logging {
+            Some(current) => current.clone(),
+            None => return Err(CoordError::IntrospectionNotConfigured),
+        };
+
+        let dataflows_changed =
+            current.granularity != config.granularity || current.log_logging != config.log_logging;
+        let disable = current.active && (!config.active || dataflows_changed);
+        if disable {
+            if let Some((dependent, source)) = self.find_introspection_dependent() {
+                return Err(CoordError::IntrospectionInUse {
+                    dependent: self.catalog.get_by_id(&dependent).name().to_string(),
+                    source: self.catalog.get_by_id(&source).name().to_string(),
+                });
+            }
+            self.broadcast(SequencedCommand::DisableLogging);
+        }
+        if config.active && (disable || !current.active) {
+            self.enable_logging(&config);
+        }
+
+        if let Some(tx) = &self.metric_scraper_tx {
+            tx.send(ScraperMessage::Configure {
+                interval: config.granularity,
+                retain_for: config.retain_readings_for,
+                active: config.active,
+            })
+            .expect("scraper should not drop first");
+        }
+        self.logging = Some(config);
+        Ok(())
+    }
+
+    /// Returns the ID of an introspection source on which any of `ids`
+    /// transitively depends, if any.
+    fn find_introspection_source(&self, mut ids: Vec<GlobalId>) -> Option<GlobalId> {
+        let mut seen = HashSet::new();
+        while let Some(id) = ids.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            if BUILTINS.logs().any(|log| log.id == id) {
+                return Some(id);
+            }
+            match self.catalog.get_by_id(&id).item() {
+                CatalogItem::Index(index) => ids.push(index.on),
+                CatalogItem::View(view) => ids.extend(view.optimized_expr.global_uses()),
+                CatalogItem::Sink(sink) => ids.push(sink.from),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Returns the ID of an index or sink that depends on an introspection
+    /// source, along with the ID of that source, if any such index or sink
+    /// exists.
+    ///
+    /// The indexes that are installed by the introspection dataflows
+    /// themselves are not considered.
+    fn find_introspection_dependent(&self) -> Option<(GlobalId, GlobalId)> {
+        self.catalog
+            .entries()
+            .filter(|entry| match entry.item() {
+                CatalogItem::Index(_) => !BUILTINS.logs().any(|log| log.index_id == entry.id()),
+                CatalogItem::Sink(_) => true,
+                _ => false,
+            })
+            .find_map(|entry| {
+                self.find_introspection_source(vec![entry.id()])
+                    .map(|source| (entry.id(), source))
+            })
+    }
+
+    /// Validates that none of `ids` depend on an introspection source while
+    /// the introspection dataflows are inactive.
+    ///
+    /// An inactive introspection source has no data, so reading from it would
+    /// otherwise block or silently return empty results.
+    fn validate_introspection_active(&self, ids: Vec<GlobalId>) -> Result<(), CoordError> {
+        match &self.logging {
+            Some(LoggingConfig { active: false, .. }) => (),
+            _ => return Ok(()),
+        }
+        match self.find_introspection_source(ids) {
+            Some(source) => Err(CoordError::IntrospectionInactive(
+                self.catalog.get_by_id(&source).name().to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Serves the coordinator based on the provided configuration.
@@ -3301,39 +3934,94 @@ pub async fn serve(
     Config {
         workers,
         timely_worker,
+        worker_start_hook,
+        worker_stack_size,
         symbiosis_url,
         logging,
-        data_directory,
+        catalog_directory,
+        storage_directory,
+        scratch_directory,
         timestamp_frequency,
         logical_compaction_window,
         experimental_mode,
         safe_mode,
         build_info,
         metrics_registry,
+        slow_query_log,
+        durability,
+        read_only,
+        maintenance_mode,
+        clock,
+        encryption_key,
+        session_defaults,
+        tail_buffer_size,
+        tail_buffer_policy,
+        outbound_bind_addr,
+        user_stats_max_users,
+        dataflow_memory_budget,
+        dataflow_memory_budget_enforce,
     }: Config<'_>,
 ) -> Result<(Handle, Client), CoordError> {
-    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let (cmd_tx, priority_cmd_tx, cmd_rx) = lanes::channel(&metrics_registry);
     let (feedback_tx, feedback_rx) = mpsc::unbounded_channel();
     let (internal_cmd_tx, internal_cmd_rx) = mpsc::unbounded_channel();
 
     let symbiosis = if let Some(symbiosis_url) = symbiosis_url {
-        Some(symbiosis::Postgres::open_and_erase(symbiosis_url).await?)
+        Some(symbiosis::Postgres::open_and_erase(symbiosis_url, outbound_bind_addr).await?)
     } else {
         None
     };
 
-    let path = data_directory.join("catalog");
+    // Validate the catalog before opening it, so that a damaged catalog
+    // produces an actionable error rather than a failure partway through
+    // opening it.
+    let path = catalog_directory.join("catalog");
+    let fresh = !path.exists();
+    let report = catalog::check::check(&path, CheckLevel::Quick, encryption_key.as_ref())?;
+    if !report.is_ok() {
+        return Err(catalog::Error::new(catalog::ErrorKind::FailedCheck(report)).into());
+    }
     let (catalog, builtin_table_updates) = Catalog::open(&catalog::Config {
         path: &path,
         experimental_mode: Some(experimental_mode),
         safe_mode,
+        read_only,
         enable_logging: logging.is_some(),
         build_info,
         num_workers: workers,
         timestamp_frequency,
         now: system_time,
+        // A read-only catalog's durability settings cannot be changed.
+        durability: if read_only { None } else { Some(durability) },
+        encryption_key,
+        outbound_bind_addr,
     })?;
     let cluster_id = catalog.config().cluster_id;
+    // Refuse to serve from a storage directory that does not belong to the
+    // catalog, before any dataflow reads from or writes to it.
+    catalog::storage_directory::verify(
+        storage_directory,
+        catalog_directory,
+        cluster_id,
+        fresh,
+        &catalog.file_references(),
+        read_only,
+    )?;
+    let catalog_changes = catalog.subscribe_changes();
+    let cancel_keys = CancelKeys::new(cluster_id);
+    let session_metrics = SessionMetrics::register_into(&metrics_registry);
+    let active_tails = ActiveTails::register_into(&metrics_registry);
+    let active_copies = ActiveCopies::register_into(&metrics_registry);
+    let user_stats = UserStats::register_into(&metrics_registry, user_stats_max_users);
+    let arrangement_sizes = ArrangementSizes::new(workers);
+    let dataflow_memory_budget = DataflowMemoryBudget::register_into(
+        &metrics_registry,
+        dataflow_memory_budget,
+        dataflow_memory_budget_enforce,
+        arrangement_sizes.clone(),
+    );
+    let labeled_statements = LabeledStatements::new();
+    let statement_activity = StatementActivity::new();
     let session_id = catalog.config().session_id;
     let start_instant = catalog.config().start_instant;
 
@@ -3342,15 +4030,21 @@ pub async fn serve(
     let worker_guards = dataflow::serve(dataflow::Config {
         command_receivers: worker_rxs,
         timely_worker,
+        worker_start_hook,
+        worker_stack_size,
         experimental_mode,
         now: system_time,
         metrics_registry: metrics_registry.clone(),
+        scratch_directory: scratch_directory.to_path_buf(),
+        outbound_bind_addr,
+        arrangement_sizes,
     })
     .map_err(|s| CoordError::Unstructured(anyhow!("{}", s)))?;
 
     let (metric_scraper_handle, metric_scraper_tx) = if let Some(LoggingConfig {
         granularity,
         retain_readings_for,
+        active,
         ..
     }) = logging
     {
@@ -3358,6 +4052,7 @@ pub async fn serve(
         let mut scraper = Scraper::new(
             granularity,
             retain_readings_for,
+            active,
             metrics_registry.clone(),
             rx,
             internal_cmd_tx.clone(),
@@ -3379,10 +4074,12 @@ pub async fn serve(
     // Spawn timestamper after any fallible operations so that if bootstrap fails we still
     // tell it to shut down.
     let (ts_tx, ts_rx) = std::sync::mpsc::channel();
+    let source_reports = SourceReports::default();
     let mut timestamper = Timestamper::new(
         Duration::from_millis(10),
         internal_cmd_tx.clone(),
         ts_rx,
+        source_reports.clone(),
         &metrics_registry,
     );
     let executor = TokioHandle::current();
@@ -3400,6 +4097,11 @@ pub async fn serve(
     // for bootstrap completion before proceeding.
     let (bootstrap_tx, bootstrap_rx) = std::sync::mpsc::channel();
     let handle = TokioHandle::current();
+    let coord_active_tails = active_tails.clone();
+    let coord_active_copies = active_copies.clone();
+    let coord_labeled_statements = labeled_statements.clone();
+    let coord_statement_activity = statement_activity.clone();
+    let coord_dataflow_memory_budget = dataflow_memory_budget.clone();
     let thread = thread::Builder::new()
         .name("coordinator".to_string())
         .spawn(move || {
@@ -3414,7 +4116,7 @@ pub async fn serve(
                 sources: ArrangementFrontiers::default(),
                 logical_compaction_window_ms: logical_compaction_window
                     .map(duration_to_timestamp_millis),
-                logging_enabled: logging.is_some(),
+                logging: logging.clone(),
                 internal_cmd_tx,
                 ts_tx: ts_tx.clone(),
                 metric_scraper_tx: metric_scraper_tx.clone(),
@@ -3423,23 +4125,33 @@ pub async fn serve(
                 last_op_was_read: false,
                 need_advance: true,
                 transient_id_counter: 1,
+                cancel_keys,
+                session_defaults,
+                session_metrics,
+                active_tails: coord_active_tails,
+                tail_buffer_size,
+                tail_buffer_policy,
+                active_copies: coord_active_copies,
+                labeled_statements: coord_labeled_statements,
+                statement_activity: coord_statement_activity,
                 active_conns: HashMap::new(),
                 txn_reads: HashMap::new(),
                 since_handles: HashMap::new(),
                 since_updates: Rc::new(RefCell::new(HashMap::new())),
                 sink_writes: HashMap::new(),
+                progress: HashMap::new(),
+                source_reports,
                 now,
+                clock,
+                read_only,
+                maintenance_mode,
+                dataflow_memory_budget: coord_dataflow_memory_budget,
             };
             coord.broadcast(SequencedCommand::EnableFeedback(feedback_tx));
             if let Some(config) = &logging {
-                coord.broadcast(SequencedCommand::EnableLogging(DataflowLoggingConfig {
-                    granularity_ns: config.granularity.as_nanos(),
-                    active_logs: BUILTINS
-                        .logs()
-                        .map(|src| (src.variant.clone(), src.index_id))
-                        .collect(),
-                    log_logging: config.log_logging,
-                }));
+                if config.active {
+                    coord.enable_logging(config);
+                }
             }
             let bootstrap = handle.block_on(coord.bootstrap(builtin_table_updates));
             let ok = bootstrap.is_ok();
@@ -3466,15 +4178,34 @@ pub async fn serve(
     match bootstrap_rx.recv().unwrap() {
         Ok(()) => {
             let handle = Handle {
+                cmd_tx: cmd_tx.clone(),
                 cluster_id,
                 session_id,
                 start_instant,
                 _thread: thread.join_on_drop(),
             };
-            let client = Client::new(cmd_tx);
+            let client = Client::new(
+                cmd_tx,
+                priority_cmd_tx,
+                slow_query_log,
+                cancel_keys,
+                active_tails,
+                active_copies,
+                labeled_statements,
+                statement_activity,
+                user_stats,
+                dataflow_memory_budget,
+                logical_compaction_window,
+                catalog_changes,
+            );
             Ok((handle, client))
         }
-        Err(e) => Err(e),
+        Err(e) => {
+            // Wait for the coordinator thread to exit, so that the catalog is
+            // closed by the time the error is reported.
+            let _ = thread.join();
+            Err(e)
+        }
     }
 }
 
@@ -3500,21 +4231,52 @@ pub fn serve_debug(
         enable_logging: true,
         experimental_mode: None,
         safe_mode: false,
+        read_only: false,
         build_info: &DUMMY_BUILD_INFO,
         num_workers: 0,
         timestamp_frequency: Duration::from_millis(1),
         now: get_debug_timestamp,
+        durability: None,
+        encryption_key: None,
+        outbound_bind_addr: None,
     })
     .unwrap();
-    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let cancel_keys = CancelKeys::new(catalog.config().cluster_id);
+    let catalog_changes = catalog.subscribe_changes();
+    let session_metrics = SessionMetrics::register_into(&metrics_registry);
+    let active_tails = ActiveTails::register_into(&metrics_registry);
+    let active_copies = ActiveCopies::register_into(&metrics_registry);
+    let user_stats = UserStats::register_into(&metrics_registry, DEFAULT_MAX_TRACKED_USERS);
+    let arrangement_sizes = ArrangementSizes::new(1);
+    let dataflow_memory_budget = DataflowMemoryBudget::register_into(
+        &metrics_registry,
+        None,
+        false,
+        arrangement_sizes.clone(),
+    );
+    let labeled_statements = LabeledStatements::new();
+    let statement_activity = StatementActivity::new();
+    let maintenance_mode = MaintenanceMode::register_into(&metrics_registry, None).unwrap();
+    let clock = ClockMonitor::register_into(
+        &metrics_registry,
+        ClockConfig {
+            now: get_debug_timestamp,
+            ..Default::default()
+        },
+    );
+    let (cmd_tx, priority_cmd_tx, cmd_rx) = lanes::channel(&metrics_registry);
     let (internal_cmd_tx, internal_cmd_rx) = mpsc::unbounded_channel();
     let (worker_tx, worker_rx) = crossbeam_channel::unbounded();
     let worker_guards = dataflow::serve(dataflow::Config {
         command_receivers: vec![worker_rx],
         timely_worker: timely::WorkerConfig::default(),
+        worker_start_hook: None,
+        worker_stack_size: None,
         experimental_mode: true,
         now: get_debug_timestamp,
         metrics_registry,
+        scratch_directory: std::env::temp_dir(),
+        arrangement_sizes,
     })
     .unwrap();
 
@@ -3555,46 +4317,82 @@ pub fn serve_debug(
 
     let (bootstrap_tx, bootstrap_rx) = std::sync::mpsc::channel();
     let handle = TokioHandle::current();
-    let thread = thread::spawn(move || {
-        let mut coord = Coordinator {
-            worker_guards,
-            worker_txs: vec![worker_tx],
-            view_optimizer: Optimizer::for_view(),
-            catalog,
-            symbiosis: None,
-            indexes: ArrangementFrontiers::default(),
-            sources: ArrangementFrontiers::default(),
-            logical_compaction_window_ms: None,
-            logging_enabled: false,
-            internal_cmd_tx,
-            ts_tx,
-            metric_scraper_tx: None,
-            closed_up_to: 1,
-            read_lower_bound: 1,
-            last_op_was_read: false,
-            need_advance: true,
-            transient_id_counter: 1,
-            active_conns: HashMap::new(),
-            txn_reads: HashMap::new(),
-            since_handles: HashMap::new(),
-            since_updates: Rc::new(RefCell::new(HashMap::new())),
-            sink_writes: HashMap::new(),
-            now: get_debug_timestamp,
-        };
-        coord.broadcast(SequencedCommand::EnableFeedback(feedback_tx));
-        let bootstrap = handle.block_on(coord.bootstrap(builtin_table_updates));
-        bootstrap_tx.send(bootstrap).unwrap();
-        handle.block_on(coord.serve(
-            internal_cmd_rx,
-            cmd_rx,
-            feedback_rx,
-            timestamper_thread_handle,
-            None,
-        ))
-    })
-    .join_on_drop();
+    let coord_active_tails = active_tails.clone();
+    let coord_active_copies = active_copies.clone();
+    let coord_labeled_statements = labeled_statements.clone();
+    let coord_statement_activity = statement_activity.clone();
+    let coord_dataflow_memory_budget = dataflow_memory_budget.clone();
+    let thread = thread::Builder::new()
+        .name("coordinator".to_string())
+        .spawn(move || {
+            let mut coord = Coordinator {
+                worker_guards,
+                worker_txs: vec![worker_tx],
+                view_optimizer: Optimizer::for_view(),
+                catalog,
+                symbiosis: None,
+                indexes: ArrangementFrontiers::default(),
+                sources: ArrangementFrontiers::default(),
+                logical_compaction_window_ms: None,
+                logging: None,
+                internal_cmd_tx,
+                ts_tx,
+                metric_scraper_tx: None,
+                closed_up_to: 1,
+                read_lower_bound: 1,
+                last_op_was_read: false,
+                need_advance: true,
+                transient_id_counter: 1,
+                cancel_keys,
+                session_defaults: SessionDefaults::default(),
+                session_metrics,
+                active_tails: coord_active_tails,
+                tail_buffer_size: 64 << 20,
+                tail_buffer_policy: TailBufferPolicy::Block,
+                active_copies: coord_active_copies,
+                labeled_statements: coord_labeled_statements,
+                statement_activity: coord_statement_activity,
+                active_conns: HashMap::new(),
+                txn_reads: HashMap::new(),
+                since_handles: HashMap::new(),
+                since_updates: Rc::new(RefCell::new(HashMap::new())),
+                sink_writes: HashMap::new(),
+                progress: HashMap::new(),
+                source_reports: SourceReports::default(),
+                now: get_debug_timestamp,
+                clock,
+                read_only: false,
+                maintenance_mode,
+                dataflow_memory_budget: coord_dataflow_memory_budget,
+            };
+            coord.broadcast(SequencedCommand::EnableFeedback(feedback_tx));
+            let bootstrap = handle.block_on(coord.bootstrap(builtin_table_updates));
+            bootstrap_tx.send(bootstrap).unwrap();
+            handle.block_on(coord.serve(
+                internal_cmd_rx,
+                cmd_rx,
+                feedback_rx,
+                timestamper_thread_handle,
+                None,
+            ))
+        })
+        .unwrap()
+        .join_on_drop();
     bootstrap_rx.recv().unwrap().unwrap();
-    let client = Client::new(cmd_tx);
+    let client = Client::new(
+        cmd_tx,
+        priority_cmd_tx,
+        SlowQueryLog::default(),
+        cancel_keys,
+        active_tails,
+        active_copies,
+        labeled_statements,
+        statement_activity,
+        user_stats,
+        dataflow_memory_budget,
+        None,
+        catalog_changes,
+    );
     (
         thread,
         client,
@@ -3773,3 +4571,50 @@ fn check_statement_safety(stmt: &Statement<Raw>) -> Result<(), CoordError> {
     }
     Ok(())
 }
+
+/// Describes the operation that `plan` performs if it would modify the catalog
+/// or write data, and so cannot be executed in read-only or maintenance mode.
+fn plan_write_op(plan: &Plan) -> Option<String> {
+    let op = match plan {
+        Plan::CreateDatabase(_) => "CREATE DATABASE".into(),
+        Plan::CreateSchema(_) => "CREATE SCHEMA".into(),
+        Plan::CreateRole(_) => "CREATE ROLE".into(),
+        Plan::CreateSource(_) => "CREATE SOURCE".into(),
+        Plan::CreateSink(_) => "CREATE SINK".into(),
+        Plan::CreateTable(_) => "CREATE TABLE".into(),
+        Plan::CreateView(_) | Plan::CreateViews(_) => "CREATE VIEW".into(),
+        Plan::CreateIndex(_) => "CREATE INDEX".into(),
+        Plan::CreateType(_) => "CREATE TYPE".into(),
+        Plan::DropDatabase(_) => "DROP DATABASE".into(),
+        Plan::DropSchema(_) => "DROP SCHEMA".into(),
+        Plan::DropRoles(_) => "DROP ROLE".into(),
+        Plan::DropItems(plan) => format!("DROP {}", plan.ty),
+        Plan::AlterNoop(plan) => format!("ALTER {}", plan.object_type),
+        Plan::AlterItemRename(plan) => format!("ALTER {}", plan.object_type),
+        Plan::AlterIndexSetOptions(_) | Plan::AlterIndexResetOptions(_) => "ALTER INDEX".into(),
+        Plan::Insert(_) => "INSERT".into(),
+        Plan::CopyFrom(_) => "COPY FROM".into(),
+        Plan::SendDiffs(plan) => match plan.kind {
+            MutationKind::Insert => "INSERT".into(),
+            MutationKind::Update => "UPDATE".into(),
+            MutationKind::Delete => "DELETE".into(),
+        },
+        Plan::DiscardTemp
+        | Plan::DiscardAll
+        | Plan::EmptyQuery
+        | Plan::ShowAllVariables
+        | Plan::ShowVariable(_)
+        | Plan::SetVariable(_)
+        | Plan::StartTransaction
+        | Plan::CommitTransaction
+        | Plan::AbortTransaction
+        | Plan::Peek(_)
+        | Plan::Tail(_)
+        | Plan::SendRows(_)
+        | Plan::Explain(_)
+        | Plan::Declare(_)
+        | Plan::Fetch(_)
+        | Plan::Close(_) => return None,
+    };
+    Some(op)
+}
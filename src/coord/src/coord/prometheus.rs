@@ -35,6 +35,9 @@ use super::{
 pub struct Scraper {
     interval: Duration,
     retain_for: u64,
+    /// Whether to scrape the registry. Metadata continues to expire while
+    /// scraping is paused.
+    active: bool,
     registry: MetricsRegistry,
     command_rx: std::sync::mpsc::Receiver<ScraperMessage>,
     internal_tx: UnboundedSender<super::Message>,
@@ -42,6 +45,12 @@ pub struct Scraper {
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum ScraperMessage {
+    /// Reconfigures the scraper, taking effect after the current interval.
+    Configure {
+        interval: Duration,
+        retain_for: Duration,
+        active: bool,
+    },
     Shutdown,
 }
 
@@ -136,6 +145,7 @@ impl Scraper {
     pub fn new(
         interval: Duration,
         retain_for: Duration,
+        active: bool,
         registry: MetricsRegistry,
         command_rx: std::sync::mpsc::Receiver<ScraperMessage>,
         internal_tx: UnboundedSender<super::Message>,
@@ -144,6 +154,7 @@ impl Scraper {
         Scraper {
             interval,
             retain_for,
+            active,
             registry,
             command_rx,
             internal_tx,
@@ -162,35 +173,24 @@ impl Scraper {
                 .as_millis()
                 .try_into()
                 .expect("materialized is younger than 550M years.");
-            if let Ok(cmd) = self.command_rx.try_recv() {
+            while let Ok(cmd) = self.command_rx.try_recv() {
                 match cmd {
+                    ScraperMessage::Configure {
+                        interval,
+                        retain_for,
+                        active,
+                    } => {
+                        self.interval = interval;
+                        self.retain_for = retain_for.as_millis() as u64;
+                        self.active = active;
+                    }
                     ScraperMessage::Shutdown => return,
                 }
             }
 
-            let timestamp = NaiveDateTime::from_timestamp(0, 0)
-                + chrono::Duration::from_std(Duration::from_millis(now))
-                    .expect("Couldn't convert timestamps");
-            let metric_fams = self.registry.gather();
-
-            let (value_readings, meta_value) =
-                convert_metrics_to_value_rows(timestamp, metric_fams.iter());
-            self.send_expiring_update(&MZ_PROMETHEUS_READINGS, value_readings);
-
-            let (histo_readings, meta_histo) =
-                convert_metrics_to_histogram_rows(timestamp, metric_fams.iter());
-            self.send_expiring_update(&MZ_PROMETHEUS_HISTOGRAMS, histo_readings);
-
-            // Find any metric metadata we need to add:
-            let missing = meta_value
-                .into_iter()
-                .chain(meta_histo.into_iter())
-                .filter(|metric| {
-                    metadata
-                        .insert(metric.clone(), now + self.retain_for)
-                        .is_none()
-                });
-            self.send_metadata_update(missing, 1);
+            if self.active {
+                self.scrape(now, &mut metadata);
+            }
 
             // Expire any that can now go (I would love HashMap.drain_filter here):
             self.send_metadata_update(
@@ -205,6 +205,34 @@ impl Scraper {
         }
     }
 
+    /// Scrapes the metrics registry, recording the metadata of any newly
+    /// observed metrics in `metadata`.
+    fn scrape(&self, now: Timestamp, metadata: &mut HashMap<Row, u64>) {
+        let timestamp = NaiveDateTime::from_timestamp(0, 0)
+            + chrono::Duration::from_std(Duration::from_millis(now))
+                .expect("Couldn't convert timestamps");
+        let metric_fams = self.registry.gather();
+
+        let (value_readings, meta_value) =
+            convert_metrics_to_value_rows(timestamp, metric_fams.iter());
+        self.send_expiring_update(&MZ_PROMETHEUS_READINGS, value_readings);
+
+        let (histo_readings, meta_histo) =
+            convert_metrics_to_histogram_rows(timestamp, metric_fams.iter());
+        self.send_expiring_update(&MZ_PROMETHEUS_HISTOGRAMS, histo_readings);
+
+        // Find any metric metadata we need to add:
+        let missing = meta_value
+            .into_iter()
+            .chain(meta_histo.into_iter())
+            .filter(|metric| {
+                metadata
+                    .insert(metric.clone(), now + self.retain_for)
+                    .is_none()
+            });
+        self.send_metadata_update(missing, 1);
+    }
+
     fn send_expiring_update(&self, table: &BuiltinTable, updates: Vec<Row>) {
         let id = table.id;
         self.internal_tx
@@ -0,0 +1,111 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Metrics that describe the active sessions.
+
+use std::collections::HashSet;
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntGaugeVec};
+
+/// The maximum number of distinct application names that receive their own
+/// label. Sessions of any further applications are counted under
+/// [`OTHER_LABEL`].
+const MAX_APPLICATION_LABELS: usize = 64;
+
+/// The maximum length of an application name that receives its own label.
+const MAX_APPLICATION_LABEL_LEN: usize = 64;
+
+/// The label for sessions whose application name is unset.
+const UNSET_LABEL: &str = "unset";
+
+/// The label for sessions whose application name is unexpected, or that
+/// arrive once [`MAX_APPLICATION_LABELS`] labels are in use.
+const OTHER_LABEL: &str = "other";
+
+/// Counts the active sessions by application name.
+///
+/// Application names are chosen by clients, so the label values are guarded
+/// against unbounded cardinality: names that are too long or contain unusual
+/// characters, and names first seen after the label limit is reached, are all
+/// counted under the `other` label.
+#[derive(Debug)]
+pub struct SessionMetrics {
+    active_sessions: UIntGaugeVec,
+    labels: HashSet<String>,
+}
+
+impl SessionMetrics {
+    pub fn register_into(registry: &MetricsRegistry) -> SessionMetrics {
+        SessionMetrics {
+            active_sessions: registry.register(metric!(
+                name: "mz_active_sessions",
+                help: "number of active sessions, by application name",
+                var_labels: ["application_name"],
+            )),
+            labels: HashSet::new(),
+        }
+    }
+
+    /// Counts a session of the named application, returning the label under
+    /// which the session is counted.
+    pub fn session_started(&mut self, application_name: &str) -> String {
+        let label = self.label(application_name);
+        self.active_sessions.with_label_values(&[&label]).inc();
+        label
+    }
+
+    /// Stops counting a session that was counted under `label`.
+    pub fn session_ended(&self, label: &str) {
+        self.active_sessions.with_label_values(&[label]).dec();
+    }
+
+    fn label(&mut self, application_name: &str) -> String {
+        if application_name.is_empty() {
+            return UNSET_LABEL.into();
+        }
+        let expected = application_name.len() <= MAX_APPLICATION_LABEL_LEN
+            && application_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || " _-.:/@".contains(c));
+        if !expected || application_name == UNSET_LABEL {
+            return OTHER_LABEL.into();
+        }
+        if self.labels.contains(application_name) {
+            return application_name.into();
+        }
+        if self.labels.len() >= MAX_APPLICATION_LABELS {
+            return OTHER_LABEL.into();
+        }
+        self.labels.insert(application_name.into());
+        application_name.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metrics::MetricsRegistry;
+
+    use super::{SessionMetrics, MAX_APPLICATION_LABELS};
+
+    #[test]
+    fn test_labels() {
+        let mut metrics = SessionMetrics::register_into(&MetricsRegistry::new());
+        assert_eq!(metrics.label(""), "unset");
+        assert_eq!(metrics.label("psql"), "psql");
+        assert_eq!(metrics.label("unset"), "other");
+        assert_eq!(metrics.label("bad\nname"), "other");
+        assert_eq!(metrics.label(&"x".repeat(65)), "other");
+        for i in 1..MAX_APPLICATION_LABELS {
+            assert_eq!(metrics.label(&format!("app{}", i)), format!("app{}", i));
+        }
+        assert_eq!(metrics.label("one-too-many"), "other");
+        assert_eq!(metrics.label("psql"), "psql");
+    }
+}
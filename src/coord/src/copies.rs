@@ -0,0 +1,182 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Tracking of the `COPY` statements that are transferring rows.
+//!
+//! Frontends call [`ActiveCopies::start`] when they begin a `COPY ... TO
+//! STDOUT` or `COPY ... FROM STDIN`, and report the rows and bytes that they
+//! transfer on the returned guard, so that the progress of each copy can be
+//! reported in the session listing.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use ore::cast::CastFrom;
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec};
+
+/// The directions in which rows can be copied.
+const DIRECTIONS: &[&str] = &["to", "from"];
+
+/// Tracks the `COPY` statements that are transferring rows, across all
+/// sessions.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct ActiveCopies {
+    inner: Arc<Mutex<HashMap<u32, CopyState>>>,
+    copied_rows: UIntCounterVec,
+}
+
+#[derive(Debug)]
+struct CopyState {
+    direction: &'static str,
+    format: &'static str,
+    start: Instant,
+    rows: u64,
+    bytes: u64,
+}
+
+/// Describes the progress of a `COPY` statement, as reported in
+/// [`SessionInfo`](crate::SessionInfo).
+#[derive(Debug, Clone, Serialize)]
+pub struct CopyProgress {
+    /// The direction of the copy, either `to` the client or `from` it.
+    pub direction: &'static str,
+    /// The format of the copied data, like `text` or `csv`.
+    pub format: &'static str,
+    /// The number of rows copied so far.
+    pub rows: u64,
+    /// The number of bytes of copied data sent or received so far.
+    pub bytes: u64,
+    /// The time since the copy started, in milliseconds.
+    pub elapsed_ms: u64,
+}
+
+impl ActiveCopies {
+    /// Constructs a tracker in which no copy is in progress, registering its
+    /// metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> ActiveCopies {
+        let copied_rows: UIntCounterVec = registry.register(metric!(
+            name: "mz_server_copied_rows_total",
+            help: "number of rows transferred by COPY statements, by direction",
+            var_labels: ["direction"],
+        ));
+        for direction in DIRECTIONS {
+            copied_rows.with_label_values(&[direction]);
+        }
+        ActiveCopies {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            copied_rows,
+        }
+    }
+
+    /// Records that the session with connection ID `conn_id` is copying rows
+    /// in `direction`, `to` or `from` the client, in `format`, until the
+    /// returned guard is dropped.
+    pub fn start(&self, conn_id: u32, direction: &'static str, format: &'static str) -> ActiveCopy {
+        let copy = CopyState {
+            direction,
+            format,
+            start: Instant::now(),
+            rows: 0,
+            bytes: 0,
+        };
+        // A session executes at most one statement at a time, so any
+        // previous copy's guard must have leaked. Forget it.
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .insert(conn_id, copy);
+        ActiveCopy {
+            copies: self.clone(),
+            conn_id,
+        }
+    }
+
+    /// Returns the progress of the copy that the session with connection ID
+    /// `conn_id` is running, if any.
+    pub fn progress(&self, conn_id: u32) -> Option<CopyProgress> {
+        let inner = self.inner.lock().expect("lock poisoned");
+        inner.get(&conn_id).map(|copy| CopyProgress {
+            direction: copy.direction,
+            format: copy.format,
+            rows: copy.rows,
+            bytes: copy.bytes,
+            elapsed_ms: u64::try_from(copy.start.elapsed().as_millis()).unwrap_or(u64::MAX),
+        })
+    }
+}
+
+/// Records that a session is copying rows, until dropped.
+///
+/// Created by [`ActiveCopies::start`].
+#[derive(Debug)]
+pub struct ActiveCopy {
+    copies: ActiveCopies,
+    conn_id: u32,
+}
+
+impl ActiveCopy {
+    /// Records that `rows` more rows, encoded in `bytes` more bytes, have
+    /// been copied.
+    pub fn record(&self, rows: usize, bytes: usize) {
+        let mut inner = self.copies.inner.lock().expect("lock poisoned");
+        if let Some(copy) = inner.get_mut(&self.conn_id) {
+            copy.rows += u64::cast_from(rows);
+            copy.bytes += u64::cast_from(bytes);
+            self.copies
+                .copied_rows
+                .with_label_values(&[copy.direction])
+                .inc_by(u64::cast_from(rows));
+        }
+    }
+}
+
+impl Drop for ActiveCopy {
+    fn drop(&mut self) {
+        self.copies
+            .inner
+            .lock()
+            .expect("lock poisoned")
+            .remove(&self.conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metrics::MetricsRegistry;
+
+    use super::ActiveCopies;
+
+    #[test]
+    fn test_active_copies() {
+        let copies = ActiveCopies::register_into(&MetricsRegistry::new());
+        let count = |direction| copies.copied_rows.with_label_values(&[direction]).get();
+
+        let copy = copies.start(1, "from", "csv");
+        copy.record(2, 10);
+        copy.record(3, 5);
+        let progress = copies.progress(1).unwrap();
+        assert_eq!(progress.direction, "from");
+        assert_eq!(progress.format, "csv");
+        assert_eq!((progress.rows, progress.bytes), (5, 15));
+        assert_eq!(count("from"), 5);
+        assert_eq!(count("to"), 0);
+        assert!(copies.progress(2).is_none());
+
+        drop(copy);
+        assert!(copies.progress(1).is_none());
+        assert_eq!(count("from"), 5);
+    }
+}
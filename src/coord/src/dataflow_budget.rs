@@ -0,0 +1,180 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A budget for the memory that dataflows occupy.
+//!
+//! Without a budget, the only check on the memory that dataflows occupy is
+//! the OOM killer. With one, the coordinator refuses to create an index,
+//! materialized view, or materialized source once the dataflows that it
+//! maintains are estimated to fill the budget.
+//!
+//! The estimate is crude. The dataflow workers report the number of records
+//! in their arrangements ([`ArrangementSizes`]), each of which is assumed to
+//! occupy [`ESTIMATED_BYTES_PER_RECORD`] bytes. The footprint of a new
+//! dataflow cannot be known before it is built, so a new dataflow is admitted
+//! only while the estimate, grown by a safety margin of
+//! [`SAFETY_MARGIN_PERCENT`], is within the budget.
+//!
+//! A budget that is not enforced admits every dataflow, but logs a warning
+//! for each that it would have rejected, so that a budget can be tried out
+//! before it is enforced.
+
+use std::sync::Arc;
+
+use log::warn;
+use serde::Serialize;
+
+use dataflow::ArrangementSizes;
+use ore::cast::CastFrom;
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec, UIntGauge};
+
+use crate::error::CoordError;
+
+/// The number of bytes that each record in an arrangement is assumed to
+/// occupy.
+pub const ESTIMATED_BYTES_PER_RECORD: u64 = 96;
+
+/// The margin, as a percentage of the current estimate, that is reserved for
+/// the footprint of a new dataflow.
+pub const SAFETY_MARGIN_PERCENT: u64 = 25;
+
+/// Admits new dataflows against a budget for the memory that dataflows
+/// occupy.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct DataflowMemoryBudget {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    budget: Option<u64>,
+    enforce: bool,
+    sizes: ArrangementSizes,
+    exceeded_metric: UIntCounterVec,
+}
+
+/// The budget for the memory that dataflows occupy and the current estimate
+/// of that memory, as returned by [`DataflowMemoryBudget::status`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DataflowMemoryStatus {
+    /// The budget, in bytes, or `None` if there is no budget.
+    pub budget_bytes: Option<u64>,
+    /// Whether new dataflows that would exceed the budget are rejected,
+    /// rather than admitted with a warning.
+    pub enforce: bool,
+    /// The estimated memory that the maintained dataflows occupy, in bytes.
+    pub estimated_bytes: u64,
+}
+
+impl DataflowMemoryBudget {
+    /// Constructs a budget of `budget` bytes, or no budget if `budget` is
+    /// `None`, against which the arrangements whose sizes `sizes` reports are
+    /// measured. If `enforce` is false, dataflows that would exceed the budget
+    /// are admitted with a warning.
+    ///
+    /// The budget's metrics are registered into `registry`.
+    pub fn register_into(
+        registry: &MetricsRegistry,
+        budget: Option<u64>,
+        enforce: bool,
+        sizes: ArrangementSizes,
+    ) -> DataflowMemoryBudget {
+        let budget_metric: UIntGauge = registry.register(metric!(
+            name: "mz_dataflow_memory_budget_bytes",
+            help: "the budget for the memory that dataflows occupy, or 0 if there is no budget",
+        ));
+        budget_metric.set(budget.unwrap_or(0));
+        let _: UIntGauge = registry.register_computed(
+            metric!(
+                name: "mz_dataflow_memory_estimated_bytes",
+                help: "the estimated memory that dataflows occupy, from the number of \
+                       records in their arrangements",
+            ),
+            {
+                let sizes = sizes.clone();
+                move |estimated: &UIntGauge| estimated.set(estimate(&sizes))
+            },
+        );
+        DataflowMemoryBudget {
+            inner: Arc::new(Inner {
+                budget,
+                enforce,
+                sizes,
+                exceeded_metric: registry.register(metric!(
+                    name: "mz_dataflow_memory_budget_exceeded_total",
+                    help: "number of new dataflows that would have exceeded the dataflow \
+                           memory budget, by whether they were rejected or admitted with \
+                           a warning",
+                    var_labels: ["action"],
+                )),
+            }),
+        }
+    }
+
+    /// Returns the estimated memory that the maintained dataflows occupy, in
+    /// bytes.
+    pub fn estimated_bytes(&self) -> u64 {
+        estimate(&self.inner.sizes)
+    }
+
+    /// Reports the budget and the current estimate.
+    pub fn status(&self) -> DataflowMemoryStatus {
+        DataflowMemoryStatus {
+            budget_bytes: self.inner.budget,
+            enforce: self.inner.enforce,
+            estimated_bytes: self.estimated_bytes(),
+        }
+    }
+
+    /// Checks whether a new dataflow, created by the statement `op`, fits
+    /// within the budget.
+    ///
+    /// Fails with [`CoordError::DataflowMemoryBudgetExceeded`] if it does not
+    /// and the budget is enforced.
+    pub(crate) fn admit(&self, op: &str) -> Result<(), CoordError> {
+        let budget = match self.inner.budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        let estimate = self.estimated_bytes();
+        let required = estimate.saturating_mul(100 + SAFETY_MARGIN_PERCENT) / 100;
+        if required <= budget {
+            return Ok(());
+        }
+        if self.inner.enforce {
+            self.inner
+                .exceeded_metric
+                .with_label_values(&["rejected"])
+                .inc();
+            Err(CoordError::DataflowMemoryBudgetExceeded {
+                op: op.into(),
+                estimate,
+                budget,
+            })
+        } else {
+            self.inner
+                .exceeded_metric
+                .with_label_values(&["warned"])
+                .inc();
+            warn!(
+                "{} admitted, but dataflows are estimated to occupy {} bytes, which with a \
+                 margin for the new dataflow exceeds the dataflow memory budget of {} bytes",
+                op, estimate, budget
+            );
+            Ok(())
+        }
+    }
+}
+
+fn estimate(sizes: &ArrangementSizes) -> u64 {
+    u64::cast_from(sizes.records()).saturating_mul(ESTIMATED_BYTES_PER_RECORD)
+}
@@ -24,36 +24,94 @@ pub enum CoordError {
     Catalog(catalog::Error),
     /// The specified session parameter is constrained to its current value.
     ConstrainedParameter(&'static (dyn Var + Send + Sync)),
+    /// The named operation would create a dataflow, but the dataflows that
+    /// the server maintains are estimated to occupy so much memory, in bytes,
+    /// that the new dataflow would exceed the server's budget.
+    DataflowMemoryBudgetExceeded {
+        op: String,
+        estimate: u64,
+        budget: u64,
+    },
     /// The cursor already exists.
     DuplicateCursor(String),
     /// An error while evaluating an expression.
     Eval(EvalError),
     /// The ID allocator exhausted all valid IDs.
     IdExhaustionError,
+    /// The named introspection source was read while the introspection
+    /// dataflows were inactive.
+    IntrospectionInactive(String),
+    /// The introspection dataflows cannot be uninstalled, as the named index
+    /// or sink depends on the named introspection source.
+    IntrospectionInUse { dependent: String, source: String },
+    /// The introspection sources are not enabled.
+    IntrospectionNotConfigured,
     /// The value for the specified parameter does not have the right type.
     InvalidParameterType(&'static (dyn Var + Send + Sync)),
+    /// The value for the specified parameter is not valid.
+    InvalidParameterValue {
+        parameter: &'static (dyn Var + Send + Sync),
+        value: String,
+    },
+    /// The named operation cannot be executed because the server is in
+    /// maintenance mode, for the specified reason, if any.
+    MaintenanceMode { op: String, message: Option<String> },
     /// The named operation cannot be run in a transaction.
     OperationProhibitsTransaction(String),
+    /// The value for the specified parameter exceeds the server's limit.
+    ParameterExceedsLimit {
+        parameter: &'static (dyn Var + Send + Sync),
+        limit: String,
+    },
     /// The named operation requires an active transaction.
     OperationRequiresTransaction(String),
     /// The transaction is in read-only mode.
     ReadOnlyTransaction,
     /// The specified session parameter is read-only.
     ReadOnlyParameter(&'static (dyn Var + Send + Sync)),
+    /// The named operation cannot be executed because the server is in
+    /// read-only mode.
+    ReadOnlyServer(String),
     /// A query in a transaction referenced a relation outside the first query's
     /// time domain.
     RelationOutsideTimeDomain {
         relation: String,
         names: Vec<String>,
     },
+    /// Buffering the statement's result would exceed the server's limit, in
+    /// bytes, on the memory occupied by buffered results.
+    ResultMemoryExhausted { limit: usize },
+    /// The statement's result exceeded the limit, in the specified unit, that
+    /// the specified parameter imposes.
+    ResultSizeLimitExceeded {
+        parameter: &'static (dyn Var + Send + Sync),
+        limit: u64,
+        unit: &'static str,
+    },
     /// The specified feature is not permitted in safe mode.
     SafeModeViolation(String),
+    /// The session already holds as many of the specified resource as the
+    /// quota that the specified parameter reports allows.
+    SessionQuotaExceeded {
+        parameter: &'static (dyn Var + Send + Sync),
+        limit: u64,
+        resource: &'static str,
+    },
     /// An error occurred in a SQL catalog operation.
     SqlCatalog(sql::catalog::CatalogError),
+    /// The statement was canceled because it exceeded the session's
+    /// statement timeout.
+    StatementTimeout,
+    /// The client of a `TAIL` fell so far behind that the updates awaiting
+    /// delivery exceeded the tail's buffer of the specified number of bytes.
+    TailBufferExceeded { limit: usize },
     /// The transaction is in single-tail mode.
     TailOnlyTransaction,
     /// An error occurred in the optimizer.
     Transform(TransformError),
+    /// The session's user is not authorized to execute the statement, for the
+    /// described reason.
+    Unauthorized(String),
     /// The named cursor does not exist.
     UnknownCursor(String),
     /// The named role does not exist.
@@ -74,6 +132,24 @@ impl CoordError {
         match self {
             CoordError::Catalog(c) => c.detail(),
             CoordError::Eval(e) => e.detail(),
+            CoordError::DataflowMemoryBudgetExceeded { .. } => Some(
+                "The estimate is derived from the number of records in the \
+                 arrangements that the server maintains, and a margin is reserved \
+                 for the new dataflow."
+                    .into(),
+            ),
+            CoordError::ReadOnlyServer(_) => Some(
+                "The Materialize server you are connected to is running in \
+                 read-only mode, which prohibits statements that modify the \
+                 catalog or write data."
+                    .into(),
+            ),
+            CoordError::MaintenanceMode { .. } => Some(
+                "The Materialize server you are connected to is in maintenance \
+                 mode, which prohibits statements that modify the catalog or \
+                 write data until the maintenance is over."
+                    .into(),
+            ),
             CoordError::SafeModeViolation(_) => Some(
                 "The Materialize server you are connected to is running in \
                  safe mode, which limits the features that are available."
@@ -88,6 +164,37 @@ impl CoordError {
         match self {
             CoordError::Catalog(c) => c.hint(),
             CoordError::Eval(e) => e.hint(),
+            CoordError::DataflowMemoryBudgetExceeded { .. } => Some(
+                "Drop indexes and materialized views that are no longer needed, or \
+                 raise the server's dataflow memory budget."
+                    .into(),
+            ),
+            CoordError::IntrospectionInactive(_) => Some(
+                "Activate the introspection sources via the /api/introspection HTTP endpoint."
+                    .into(),
+            ),
+            CoordError::IntrospectionInUse { dependent, .. } => {
+                Some(format!("Drop {} first.", dependent.quoted()))
+            }
+            CoordError::ResultMemoryExhausted { .. } => Some(
+                "Retry the request once other large results have been sent, or add a \
+                 LIMIT clause to the statement."
+                    .into(),
+            ),
+            CoordError::ResultSizeLimitExceeded { parameter, .. } => Some(format!(
+                "Add a LIMIT clause to the statement, or raise {} with SET.",
+                parameter.name().quoted()
+            )),
+            CoordError::SessionQuotaExceeded { .. } => Some(
+                "Close the portals and prepared statements that are no longer needed, \
+                 or finish reading the results of suspended portals."
+                    .into(),
+            ),
+            CoordError::TailBufferExceeded { .. } => Some(
+                "Read the TAIL's results faster, or run it WITH (buffer_policy = 'block') \
+                 to pause the TAIL while the client catches up."
+                    .into(),
+            ),
             CoordError::UnknownLoginRole(_) => {
                 // TODO(benesch): this will be a bad hint when people are used
                 // to creating roles in Materialize, since they might drop the
@@ -113,27 +220,72 @@ impl fmt::Display for CoordError {
                 p.name().quoted(),
                 p.value().quoted()
             ),
+            CoordError::DataflowMemoryBudgetExceeded {
+                op,
+                estimate,
+                budget,
+            } => write!(
+                f,
+                "cannot execute {}: dataflows are estimated to occupy {} bytes, which \
+                 leaves too little of the server's dataflow memory budget of {} bytes \
+                 for a new dataflow",
+                op, estimate, budget
+            ),
             CoordError::DuplicateCursor(name) => {
                 write!(f, "cursor {} already exists", name.quoted())
             }
             CoordError::Eval(e) => e.fmt(f),
             CoordError::IdExhaustionError => f.write_str("ID allocator exhausted all valid IDs"),
+            CoordError::IntrospectionInactive(source) => {
+                write!(f, "introspection source {} is not active", source.quoted())
+            }
+            CoordError::IntrospectionInUse { dependent, source } => write!(
+                f,
+                "cannot deactivate introspection sources: {} depends on {}",
+                dependent.quoted(),
+                source.quoted()
+            ),
+            CoordError::IntrospectionNotConfigured => {
+                f.write_str("introspection sources are not enabled")
+            }
             CoordError::InvalidParameterType(p) => write!(
                 f,
                 "parameter {} requires a {} value",
                 p.name().quoted(),
                 p.type_name().quoted()
             ),
+            CoordError::InvalidParameterValue { parameter, value } => write!(
+                f,
+                "invalid value for parameter {}: {}",
+                parameter.name().quoted(),
+                value.quoted()
+            ),
+            CoordError::MaintenanceMode { op, message } => {
+                write!(f, "cannot execute {} in maintenance mode", op)?;
+                if let Some(message) = message {
+                    write!(f, ": {}", message)?;
+                }
+                Ok(())
+            }
             CoordError::OperationProhibitsTransaction(op) => {
                 write!(f, "{} cannot be run inside a transaction block", op)
             }
             CoordError::OperationRequiresTransaction(op) => {
                 write!(f, "{} can only be used in transaction blocks", op)
             }
+            CoordError::ParameterExceedsLimit { parameter, limit } => write!(
+                f,
+                "parameter {} cannot exceed the server's limit of {}",
+                parameter.name().quoted(),
+                limit.quoted()
+            ),
             CoordError::ReadOnlyTransaction => f.write_str("transaction in read-only mode"),
             CoordError::ReadOnlyParameter(p) => {
                 write!(f, "parameter {} cannot be changed", p.name().quoted())
             }
+            CoordError::ReadOnlyServer(op) => {
+                write!(f, "cannot execute {} in read-only mode", op)
+            }
             CoordError::RelationOutsideTimeDomain { relation, names } => {
                 write!(
                     f,
@@ -152,10 +304,46 @@ impl fmt::Display for CoordError {
                     }
                 )
             }
+            CoordError::ResultMemoryExhausted { limit } => write!(
+                f,
+                "insufficient memory to buffer result: the server's limit of {} bytes \
+                 for buffered results is exhausted",
+                limit
+            ),
+            CoordError::ResultSizeLimitExceeded {
+                parameter,
+                limit,
+                unit,
+            } => write!(
+                f,
+                "result exceeds the limit of {} {} set by parameter {}",
+                limit,
+                unit,
+                parameter.name().quoted()
+            ),
             CoordError::SafeModeViolation(feature) => {
                 write!(f, "cannot create {} in safe mode", feature)
             }
+            CoordError::SessionQuotaExceeded {
+                parameter,
+                limit,
+                resource,
+            } => write!(
+                f,
+                "session exceeds the limit of {} {} set by parameter {}",
+                limit,
+                resource,
+                parameter.name().quoted()
+            ),
             CoordError::SqlCatalog(e) => e.fmt(f),
+            CoordError::StatementTimeout => {
+                f.write_str("canceling statement due to statement timeout")
+            }
+            CoordError::TailBufferExceeded { limit } => write!(
+                f,
+                "tail buffer exceeded: the client fell more than {} bytes behind",
+                limit
+            ),
             CoordError::TailOnlyTransaction => {
                 f.write_str("TAIL in transactions must be the only read statement")
             }
@@ -163,6 +351,7 @@ impl fmt::Display for CoordError {
             CoordError::UnknownCursor(name) => {
                 write!(f, "cursor {} does not exist", name.quoted())
             }
+            CoordError::Unauthorized(reason) => write!(f, "permission denied: {}", reason),
             CoordError::UnknownLoginRole(name) => {
                 write!(f, "role {} does not exist", name.quoted())
             }
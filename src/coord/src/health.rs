@@ -0,0 +1,306 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! The health of sources and sinks.
+//!
+//! The coordinator tracks when the frontier of each source, and of each sink
+//! whose writes it tracks, last advanced. The timestamper records the errors
+//! it encounters while timestamping each source, along with the upstream
+//! offsets of Kafka sources, in [`SourceReports`]. Together, these determine
+//! the [`HealthStatus`] of each object, as reported by
+//! [`SessionClient::dataflow_health`](crate::SessionClient::dataflow_health).
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use expr::GlobalId;
+
+/// The number of timestamp intervals without progress after which a source
+/// or sink is considered stalled.
+pub const STALL_FACTOR: u32 = 3;
+
+/// The status of a source or sink.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// The object has not yet made progress, and has existed for less than
+    /// [`STALL_FACTOR`] timestamp intervals.
+    Starting,
+    /// The object made progress within the last [`STALL_FACTOR`] timestamp
+    /// intervals.
+    Running,
+    /// The object has not made progress for [`STALL_FACTOR`] timestamp
+    /// intervals or more.
+    Stalled,
+    /// The object encountered an error, and has not made progress since.
+    Errored {
+        /// The message of the most recent error.
+        error: String,
+    },
+    /// The object has ingested or written all of its data, and will never
+    /// make progress again.
+    Complete,
+}
+
+impl HealthStatus {
+    /// Determines the status of an object.
+    ///
+    /// `last_progress` is when the object's frontier last advanced, or when
+    /// the object was created if it has yet to advance, and `started` is
+    /// whether it has advanced. `complete` is whether the frontier is empty,
+    /// and `interval` is the object's timestamp interval. `error` is the most
+    /// recent error, if any, and when it occurred.
+    pub fn determine(
+        now: Instant,
+        last_progress: Instant,
+        started: bool,
+        complete: bool,
+        interval: Duration,
+        error: Option<(&str, Instant)>,
+    ) -> HealthStatus {
+        match error {
+            Some((error, at)) if at >= last_progress => {
+                return HealthStatus::Errored {
+                    error: error.into(),
+                }
+            }
+            _ => (),
+        }
+        if complete {
+            HealthStatus::Complete
+        } else if now.saturating_duration_since(last_progress) >= interval * STALL_FACTOR {
+            HealthStatus::Stalled
+        } else if !started {
+            HealthStatus::Starting
+        } else {
+            HealthStatus::Running
+        }
+    }
+}
+
+/// Tracks when the frontier of a source or sink last advanced.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Progress {
+    /// When the frontier last advanced, or when the object was created if
+    /// the frontier has yet to advance.
+    pub last: Instant,
+    /// Whether the frontier has advanced.
+    pub started: bool,
+}
+
+impl Progress {
+    /// Starts tracking the progress of a newly created object.
+    pub fn new() -> Progress {
+        Progress {
+            last: Instant::now(),
+            started: false,
+        }
+    }
+
+    /// Records that the object's frontier advanced.
+    pub fn advance(&mut self) {
+        self.last = Instant::now();
+        self.started = true;
+    }
+}
+
+/// Describes the health of a source, as returned by
+/// [`SessionClient::dataflow_health`](crate::SessionClient::dataflow_health).
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceHealth {
+    /// The ID of the source.
+    pub id: String,
+    /// The fully qualified name of the source.
+    pub name: String,
+    /// The type of the source's connector, like `kafka`.
+    #[serde(rename = "type")]
+    pub connector: &'static str,
+    /// The status of the source.
+    #[serde(flatten)]
+    pub status: HealthStatus,
+    /// The source's upper frontier, in milliseconds since the Unix epoch, or
+    /// `None` if the source is complete.
+    pub upper: Option<u64>,
+    /// The time since the source's frontier last advanced, or since the
+    /// source was created if its frontier has yet to advance, in
+    /// milliseconds.
+    pub last_progress_ms: u64,
+    /// The time since the source's most recently closed timestamp, in
+    /// milliseconds, as reported by
+    /// [`Client::source_timestamp_lags`](crate::Client::source_timestamp_lags).
+    pub lag_ms: Option<u64>,
+    /// The offset up to which the upstream system holds data, by partition,
+    /// for the sources whose upstream offsets are known.
+    pub upstream_offsets: BTreeMap<String, i64>,
+}
+
+/// Describes the health of a sink, as returned by
+/// [`SessionClient::dataflow_health`](crate::SessionClient::dataflow_health).
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkHealth {
+    /// The ID of the sink.
+    pub id: String,
+    /// The fully qualified name of the sink.
+    pub name: String,
+    /// The type of the sink's connector, like `kafka`, or `None` if the
+    /// connector is still being created.
+    #[serde(rename = "type")]
+    pub connector: Option<&'static str>,
+    /// The status of the sink.
+    #[serde(flatten)]
+    pub status: HealthStatus,
+    /// The sink's write frontier, in milliseconds since the Unix epoch, if
+    /// the sink's writes are tracked and it is not complete.
+    pub upper: Option<u64>,
+    /// The time since the sink's write frontier last advanced, in
+    /// milliseconds, if the sink's writes are tracked.
+    pub last_progress_ms: Option<u64>,
+    /// The time since the sink's write frontier, in milliseconds, if the
+    /// sink's writes are tracked and it has written any data.
+    pub lag_ms: Option<u64>,
+}
+
+/// The health of every source and sink in the catalog, as returned by
+/// [`SessionClient::dataflow_health`](crate::SessionClient::dataflow_health).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DataflowHealth {
+    /// The sources, ordered by name.
+    pub sources: Vec<SourceHealth>,
+    /// The sinks, ordered by name.
+    pub sinks: Vec<SinkHealth>,
+}
+
+/// The reports about the sources that the timestamper is timestamping.
+///
+/// Reports are only recorded for sources that were registered with
+/// [`SourceReports::register`], so that a reporting thread that outlives its
+/// source cannot leak reports.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceReports {
+    inner: Arc<Mutex<HashMap<GlobalId, SourceReport>>>,
+}
+
+/// The reports about a source.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceReport {
+    /// The most recent error, if any, and when it occurred.
+    pub error: Option<(String, Instant)>,
+    /// The offset up to which the upstream system holds data, by partition.
+    pub upstream_offsets: BTreeMap<String, i64>,
+}
+
+impl SourceReports {
+    /// Starts recording reports about the source `id`.
+    pub fn register(&self, id: GlobalId) {
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .entry(id)
+            .or_default();
+    }
+
+    /// Stops recording reports about the source `id`, and forgets its
+    /// reports.
+    pub fn remove(&self, id: GlobalId) {
+        self.inner.lock().expect("lock poisoned").remove(&id);
+    }
+
+    /// Records that timestamping the source `id` failed with `error`.
+    pub fn record_error(&self, id: GlobalId, error: String) {
+        if let Some(report) = self.inner.lock().expect("lock poisoned").get_mut(&id) {
+            report.error = Some((error, Instant::now()));
+        }
+    }
+
+    /// Records the offset up to which the upstream system holds data for
+    /// `partition` of the source `id`.
+    pub fn record_upstream_offset(&self, id: GlobalId, partition: String, offset: i64) {
+        if let Some(report) = self.inner.lock().expect("lock poisoned").get_mut(&id) {
+            report.upstream_offsets.insert(partition, offset);
+        }
+    }
+
+    /// Returns the reports about the source `id`, if it is registered.
+    pub fn get(&self, id: GlobalId) -> Option<SourceReport> {
+        self.inner.lock().expect("lock poisoned").get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_determine_status() {
+        let interval = Duration::from_secs(1);
+        let created = Instant::now();
+        let status = |now, last_progress, started, complete, error| {
+            HealthStatus::determine(now, last_progress, started, complete, interval, error)
+        };
+        let soon = created + Duration::from_millis(500);
+        let later = created + interval * STALL_FACTOR;
+
+        assert_eq!(
+            status(soon, created, false, false, None),
+            HealthStatus::Starting
+        );
+        assert_eq!(
+            status(later, created, false, false, None),
+            HealthStatus::Stalled
+        );
+        assert_eq!(
+            status(soon, created, true, false, None),
+            HealthStatus::Running
+        );
+        assert_eq!(
+            status(later, created, true, false, None),
+            HealthStatus::Stalled
+        );
+        assert_eq!(
+            status(later, created, true, true, None),
+            HealthStatus::Complete
+        );
+
+        // An error is reported until the object makes progress again.
+        assert_eq!(
+            status(later, created, true, false, Some(("boom", soon))),
+            HealthStatus::Errored {
+                error: "boom".into()
+            }
+        );
+        assert_eq!(
+            status(later, soon, true, false, Some(("boom", created))),
+            HealthStatus::Stalled
+        );
+    }
+
+    #[test]
+    fn test_reports_require_registration() {
+        let reports = SourceReports::default();
+        let id = GlobalId::User(1);
+        reports.record_error(id, "early".into());
+        assert!(reports.get(id).is_none());
+
+        reports.register(id);
+        reports.record_error(id, "boom".into());
+        reports.record_upstream_offset(id, "0".into(), 42);
+        let report = reports.get(id).unwrap();
+        assert_eq!(report.error.unwrap().0, "boom");
+        assert_eq!(report.upstream_offsets.get("0"), Some(&42));
+
+        reports.remove(id);
+        reports.record_upstream_offset(id, "0".into(), 43);
+        assert!(reports.get(id).is_none());
+    }
+}
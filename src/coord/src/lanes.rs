@@ -0,0 +1,292 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Priority lanes for the commands that clients send to the coordinator.
+//!
+//! The coordinator processes commands one at a time, so a command waits for
+//! every command that was queued before it. A health probe that queues behind
+//! a storm of DDL would make a server that is merely busy look unresponsive.
+//! Commands that must be answered promptly however busy the coordinator
+//! is—health probes, cancellation, and the requests of the admin
+//! endpoints—are therefore sent on the [`Lane::Priority`] lane, which the
+//! coordinator serves ahead of the [`Lane::Normal`] lane on which user
+//! statements arrive.
+//!
+//! The priority lane cannot starve the normal lane. While commands are waiting
+//! in the normal lane, the coordinator serves at most [`PRIORITY_BURST`]
+//! priority commands in a row before it serves a normal one, however many
+//! priority commands are waiting.
+//!
+//! The number of commands waiting in each lane is reported in the
+//! `mz_server_coord_queue_depth` metric.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::Stream;
+use tokio::sync::mpsc::{self, error::SendError};
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec, UIntGauge, UIntGaugeVec};
+
+/// The number of priority commands that the coordinator serves in a row
+/// while commands are waiting in the normal lane.
+pub const PRIORITY_BURST: usize = 4;
+
+/// A lane on which commands are sent to the coordinator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// The lane for user statements.
+    Normal,
+    /// The lane for health probes, cancellation, and admin work, which is
+    /// served ahead of the normal lane.
+    Priority,
+}
+
+impl Lane {
+    /// Returns the name of the lane, as reported in metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lane::Normal => "normal",
+            Lane::Priority => "priority",
+        }
+    }
+}
+
+/// Constructs the lanes of commands, registering their metrics into
+/// `registry`.
+///
+/// Returns the senders for the normal and priority lanes, and the merger of
+/// the two lanes from which the coordinator receives commands.
+pub(crate) fn channel<T>(registry: &MetricsRegistry) -> (LaneSender<T>, LaneSender<T>, Lanes<T>) {
+    let served: UIntCounterVec = registry.register(metric!(
+        name: "mz_server_coord_commands_total",
+        help: "number of commands served by the coordinator, by lane",
+        var_labels: ["lane"],
+    ));
+    let depth: UIntGaugeVec = registry.register(metric!(
+        name: "mz_server_coord_queue_depth",
+        help: "number of commands waiting to be served by the coordinator, by lane",
+        var_labels: ["lane"],
+    ));
+    for lane in &[Lane::Normal, Lane::Priority] {
+        served.with_label_values(&[lane.as_str()]);
+    }
+    let (normal_tx, normal_rx) = mpsc::unbounded_channel();
+    let (priority_tx, priority_rx) = mpsc::unbounded_channel();
+    let normal_tx = LaneSender {
+        tx: normal_tx,
+        depth: depth.with_label_values(&[Lane::Normal.as_str()]),
+    };
+    let priority_tx = LaneSender {
+        tx: priority_tx,
+        depth: depth.with_label_values(&[Lane::Priority.as_str()]),
+    };
+    let lanes = Lanes {
+        normal: Some(normal_rx),
+        priority: Some(priority_rx),
+        streak: 0,
+        served,
+        depth,
+    };
+    (normal_tx, priority_tx, lanes)
+}
+
+/// Sends commands on a lane, counting the commands that are waiting in it.
+#[derive(Debug)]
+pub(crate) struct LaneSender<T> {
+    tx: mpsc::UnboundedSender<T>,
+    depth: UIntGauge,
+}
+
+impl<T> LaneSender<T> {
+    /// Sends `item` on the lane.
+    ///
+    /// Fails if the coordinator has stopped receiving commands.
+    pub(crate) fn send(&self, item: T) -> Result<(), SendError<T>> {
+        // The depth is raised before the command is visible to the
+        // coordinator, so that it never drops below zero.
+        self.depth.inc();
+        let result = self.tx.send(item);
+        if result.is_err() {
+            self.depth.dec();
+        }
+        result
+    }
+
+    /// Returns the number of commands waiting in the lane.
+    pub(crate) fn depth(&self) -> u64 {
+        self.depth.get()
+    }
+}
+
+impl<T> Clone for LaneSender<T> {
+    fn clone(&self) -> LaneSender<T> {
+        LaneSender {
+            tx: self.tx.clone(),
+            depth: self.depth.clone(),
+        }
+    }
+}
+
+/// Merges the lanes of commands into the order in which the coordinator
+/// serves them.
+///
+/// The stream ends once both lanes have closed.
+#[derive(Debug)]
+pub(crate) struct Lanes<T> {
+    normal: Option<mpsc::UnboundedReceiver<T>>,
+    priority: Option<mpsc::UnboundedReceiver<T>>,
+    /// The number of priority commands served since the normal lane was last
+    /// served or found empty.
+    streak: usize,
+    served: UIntCounterVec,
+    depth: UIntGaugeVec,
+}
+
+impl<T> Lanes<T> {
+    fn serve(&mut self, lane: Lane, item: T) -> Poll<Option<T>> {
+        match lane {
+            Lane::Normal => self.streak = 0,
+            Lane::Priority => self.streak += 1,
+        }
+        self.served.with_label_values(&[lane.as_str()]).inc();
+        self.depth.with_label_values(&[lane.as_str()]).dec();
+        Poll::Ready(Some(item))
+    }
+}
+impl<T> Stream for Lanes<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let this = &mut *self;
+        // Once the priority lane has used up its burst, the normal lane gets
+        // a turn. If it has nothing waiting, there is nothing to starve, and
+        // the priority lane starts a new burst.
+        if this.streak >= PRIORITY_BURST {
+            if let Poll::Ready(Some(item)) = poll_lane(&mut this.normal, cx) {
+                return this.serve(Lane::Normal, item);
+            }
+            this.streak = 0;
+        }
+        if let Poll::Ready(Some(item)) = poll_lane(&mut this.priority, cx) {
+            return this.serve(Lane::Priority, item);
+        }
+        if let Poll::Ready(Some(item)) = poll_lane(&mut this.normal, cx) {
+            return this.serve(Lane::Normal, item);
+        }
+        if this.normal.is_none() && this.priority.is_none() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Polls `lane` for its next command, forgetting the lane once it closes.
+fn poll_lane<T>(
+    lane: &mut Option<mpsc::UnboundedReceiver<T>>,
+    cx: &mut Context,
+) -> Poll<Option<T>> {
+    let rx = match lane {
+        Some(rx) => rx,
+        None => return Poll::Ready(None),
+    };
+    match rx.poll_recv(cx) {
+        Poll::Ready(None) => {
+            *lane = None;
+            Poll::Ready(None)
+        }
+        poll => poll,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream::StreamExt;
+    use tokio::sync::oneshot;
+
+    use ore::metrics::MetricsRegistry;
+
+    use super::{channel, PRIORITY_BURST};
+
+    /// A health probe sent while hundreds of user statements are queued is
+    /// served next, rather than after them.
+    #[tokio::test]
+    async fn test_priority_lane_bypasses_backlog() {
+        let (normal_tx, priority_tx, mut lanes) = channel(&MetricsRegistry::new());
+
+        let mut statements = vec![];
+        for _ in 0..500 {
+            let (tx, rx) = oneshot::channel();
+            normal_tx.send(tx).unwrap();
+            statements.push(rx);
+        }
+        let (probe_tx, mut probe_rx) = oneshot::channel();
+        priority_tx.send(probe_tx).unwrap();
+        assert_eq!(normal_tx.depth(), 500);
+        assert_eq!(priority_tx.depth(), 1);
+
+        // Serve commands one at a time, as the coordinator does, until the
+        // probe is answered.
+        let mut served = 0;
+        while probe_rx.try_recv().is_err() {
+            let tx: oneshot::Sender<()> = lanes.next().await.unwrap();
+            let _ = tx.send(());
+            served += 1;
+        }
+        assert_eq!(served, 1);
+        assert_eq!(priority_tx.depth(), 0);
+
+        // The statements are then served in order.
+        for mut rx in statements.drain(..10) {
+            let tx = lanes.next().await.unwrap();
+            let _ = tx.send(());
+            assert!(rx.try_recv().is_ok());
+        }
+        assert_eq!(normal_tx.depth(), 490);
+
+        // The stream ends once both lanes close.
+        drop(normal_tx);
+        drop(priority_tx);
+        assert_eq!(lanes.count().await, 490);
+    }
+
+    /// However many priority commands are waiting, the normal lane is served
+    /// at least once per burst.
+    #[tokio::test]
+    async fn test_normal_lane_not_starved() {
+        let (normal_tx, priority_tx, mut lanes) = channel(&MetricsRegistry::new());
+
+        for i in 0..3 {
+            normal_tx.send(("normal", i)).unwrap();
+        }
+        for i in 0..100 {
+            priority_tx.send(("priority", i)).unwrap();
+        }
+        let mut order = vec![];
+        for _ in 0..3 * (PRIORITY_BURST + 1) {
+            order.push(lanes.next().await.unwrap());
+        }
+        for (i, chunk) in order.chunks(PRIORITY_BURST + 1).enumerate() {
+            assert!(chunk[..PRIORITY_BURST]
+                .iter()
+                .all(|(lane, _)| *lane == "priority"));
+            assert_eq!(chunk[PRIORITY_BURST], ("normal", i));
+        }
+
+        // With the normal lane empty, the priority lane is served without
+        // interruption.
+        for i in 3 * PRIORITY_BURST..100 {
+            assert_eq!(lanes.next().await.unwrap(), ("priority", i));
+        }
+        assert_eq!(lanes.served.with_label_values(&["priority"]).get(), 100);
+        assert_eq!(lanes.served.with_label_values(&["normal"]).get(), 3);
+    }
+}
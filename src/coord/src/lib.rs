@@ -30,20 +30,67 @@ macro_rules! coord_bail {
     }
 }
 
+mod activity;
+mod authz;
+mod cancel;
 mod client;
+mod clock;
 mod command;
 mod coord;
+mod copies;
+mod dataflow_budget;
 mod error;
+mod health;
 mod id_alloc;
+mod lanes;
+mod maintenance;
+mod quotas;
+mod result_budget;
 mod sink_connector;
+mod slow_query;
+mod statement_durations;
+mod statement_labels;
+mod tails;
+mod timeout;
 mod timestamp;
+mod transport;
+mod user_stats;
 mod util;
 
 pub mod catalog;
 pub mod session;
 
+pub use crate::activity::{ActiveStatement, CurrentStatement, StatementActivity};
+pub use crate::authz::{Authorizer, AuthzClass, AuthzDecision, AuthzRequest, PolicyAuthorizer};
+pub use crate::cancel::CancelOutcome;
 pub use crate::client::{Client, ConnClient, Handle, SessionClient};
-pub use crate::command::{Cancelled, ExecuteResponse, StartupMessage, StartupResponse};
+pub use crate::clock::{ClockConfig, ClockMonitor};
+pub use crate::command::{
+    Cancelled, CatalogSummary, ExecuteResponse, SessionInfo, SimpleRows, SourceTimestampLag,
+    StartupMessage, StartupResponse,
+};
 pub use crate::coord::{serve, serve_debug, Config, LoggingConfig};
+pub use crate::copies::{ActiveCopies, ActiveCopy, CopyProgress};
+pub use crate::dataflow_budget::{
+    DataflowMemoryBudget, DataflowMemoryStatus, ESTIMATED_BYTES_PER_RECORD, SAFETY_MARGIN_PERCENT,
+};
 pub use crate::error::CoordError;
+pub use crate::health::{DataflowHealth, HealthStatus, SinkHealth, SourceHealth, STALL_FACTOR};
+pub use crate::lanes::{Lane, PRIORITY_BURST};
+pub use crate::maintenance::{Maintenance, MaintenanceMode};
+pub use crate::quotas::{
+    Quotas, SessionQuotas, DEFAULT_MAX_CONCURRENT_STATEMENTS, DEFAULT_MAX_OPEN_PORTALS,
+    DEFAULT_MAX_PREPARED_STATEMENTS,
+};
+pub use crate::result_budget::{ResultBudget, ResultReservation};
+pub use crate::slow_query::{SlowQueryLog, SlowQueryLogConfig, TimedStatement};
+pub use crate::statement_durations::{StatementClass, StatementDuration, StatementDurations};
+pub use crate::statement_labels::{LabeledStatement, LabeledStatements, StatementLabels};
+pub use crate::tails::{ActiveTail, ActiveTails, TailBufferInfo, TailReceiver};
+pub use crate::timeout::StatementTimer;
 pub use crate::timestamp::Timestamper;
+pub use crate::transport::{Transport, TransportGuard, TransportMonitor};
+pub use crate::user_stats::{
+    UserBytesSent, UserSession, UserStatement, UserStats, UserStatsSummary, UserSummary,
+    DEFAULT_MAX_TRACKED_USERS, OTHER_USER,
+};
@@ -0,0 +1,164 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! The server-wide maintenance mode.
+//!
+//! While the server is in maintenance mode, the coordinator rejects the
+//! statements that would modify the catalog or write data, as it does in
+//! read-only mode, but reads, including `TAIL`s, continue. Statements that
+//! the coordinator had already begun to sequence when the mode was enabled
+//! run to completion, and so do transactions that are committed while the
+//! mode is enabled.
+//!
+//! Unlike read-only mode, maintenance mode can be enabled and disabled while
+//! the server is running.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use futures::future;
+use tokio::sync::watch;
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntGauge};
+
+/// The state of a server that is in maintenance mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maintenance {
+    /// The message to show to clients whose statements are rejected, if any.
+    pub message: Option<String>,
+}
+
+/// Reports whether the server is in maintenance mode.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct MaintenanceMode {
+    tx: Arc<watch::Sender<Option<Maintenance>>>,
+    rx: watch::Receiver<Option<Maintenance>>,
+    /// The file to which the state is persisted, if it survives restarts.
+    /// Serializes writes to the file.
+    path: Arc<Mutex<Option<PathBuf>>>,
+    enabled: UIntGauge,
+}
+
+impl MaintenanceMode {
+    /// Constructs a new maintenance mode, registering its metrics into
+    /// `registry`.
+    ///
+    /// If `path` is present, the state is loaded from and persisted to the
+    /// file at `path`, so that the server remains in maintenance mode across
+    /// restarts. Otherwise the server starts out of maintenance mode. This
+    /// function performs blocking I/O.
+    pub fn register_into(
+        registry: &MetricsRegistry,
+        path: Option<PathBuf>,
+    ) -> Result<MaintenanceMode, anyhow::Error> {
+        let enabled: UIntGauge = registry.register(metric!(
+            name: "mz_server_maintenance_mode",
+            help: "whether the server is in maintenance mode (1) or not (0)",
+        ));
+        let state = match &path {
+            None => None,
+            Some(path) => match fs::read_to_string(path) {
+                Ok(message) => Some(Maintenance {
+                    message: Some(message.trim_end().to_owned()).filter(|m| !m.is_empty()),
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("reading maintenance mode: {}", path.display()))
+                }
+            },
+        };
+        enabled.set(u64::from(state.is_some()));
+        let (tx, rx) = watch::channel(state);
+        Ok(MaintenanceMode {
+            tx: Arc::new(tx),
+            rx,
+            path: Arc::new(Mutex::new(path)),
+            enabled,
+        })
+    }
+
+    /// Returns the maintenance state of the server, or `None` if the server
+    /// is not in maintenance mode.
+    pub fn get(&self) -> Option<Maintenance> {
+        self.rx.borrow().clone()
+    }
+
+    /// Puts the server into maintenance mode with the described state, or
+    /// takes it out of maintenance mode if `state` is `None`.
+    ///
+    /// If the state is persisted, the new state is persisted before it takes
+    /// effect. This function performs blocking I/O.
+    pub fn set(&self, state: Option<Maintenance>) -> Result<(), anyhow::Error> {
+        let path = self.path.lock().expect("lock poisoned");
+        if let Some(path) = &*path {
+            let res = match &state {
+                None => match fs::remove_file(path) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                    res => res,
+                },
+                Some(maintenance) => fs::write(path, maintenance.message.as_deref().unwrap_or("")),
+            };
+            res.with_context(|| format!("writing maintenance mode: {}", path.display()))?;
+        }
+        self.enabled.set(u64::from(state.is_some()));
+        // Sending cannot fail, as `self` holds a receiver.
+        let _ = self.tx.send(state);
+        Ok(())
+    }
+
+    /// Waits until the maintenance state of the server differs from `seen`.
+    ///
+    /// This function is cancel safe.
+    pub async fn changed(&self, seen: &Option<Maintenance>) {
+        let mut rx = self.rx.clone();
+        while *rx.borrow() == *seen {
+            if rx.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metrics::MetricsRegistry;
+
+    use super::{Maintenance, MaintenanceMode};
+
+    #[test]
+    fn test_persistence() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("maintenance_mode");
+
+        let mode = MaintenanceMode::register_into(&MetricsRegistry::new(), Some(path.clone()))?;
+        assert_eq!(mode.get(), None);
+        let maintenance = Maintenance {
+            message: Some("migrating hosts".into()),
+        };
+        mode.set(Some(maintenance.clone()))?;
+        assert_eq!(mode.get(), Some(maintenance.clone()));
+
+        let mode = MaintenanceMode::register_into(&MetricsRegistry::new(), Some(path.clone()))?;
+        assert_eq!(mode.get(), Some(maintenance));
+        mode.set(None)?;
+        assert!(!path.exists());
+
+        let mode = MaintenanceMode::register_into(&MetricsRegistry::new(), None)?;
+        mode.set(Some(Maintenance { message: None }))?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Per-session resource quotas.
+//!
+//! A single misbehaving client can consume resources far beyond what the
+//! limits on the number of connections anticipate, for example by pipelining
+//! statements that each leave a portal suspended. The quotas cap the number of
+//! statements that each session may have executing at once, the number of
+//! prepared statements it may hold, and the number of portals it may have open.
+//!
+//! The quotas are server-wide and may be adjusted while the server is running.
+//! Sessions report them in the read-only `max_concurrent_statements`,
+//! `max_prepared_statements`, and `max_open_portals` configuration parameters.
+
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+/// The default maximum number of statements that a session may have
+/// executing at once.
+pub const DEFAULT_MAX_CONCURRENT_STATEMENTS: u64 = 100;
+
+/// The default maximum number of prepared statements that a session may hold.
+pub const DEFAULT_MAX_PREPARED_STATEMENTS: u64 = 10_000;
+
+/// The default maximum number of portals that a session may have open.
+pub const DEFAULT_MAX_OPEN_PORTALS: u64 = 1_000;
+
+/// The resource quotas of each session.
+///
+/// A quota of zero is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SessionQuotas {
+    /// The maximum number of statements that a session may have executing at
+    /// once. A statement is executing from when it starts until it has
+    /// returned all of its rows, so a portal that is suspended partway
+    /// through its results counts against the quota.
+    pub max_concurrent_statements: u64,
+    /// The maximum number of prepared statements that a session may hold.
+    pub max_prepared_statements: u64,
+    /// The maximum number of portals that a session may have open.
+    pub max_open_portals: u64,
+}
+
+impl Default for SessionQuotas {
+    fn default() -> SessionQuotas {
+        SessionQuotas {
+            max_concurrent_statements: DEFAULT_MAX_CONCURRENT_STATEMENTS,
+            max_prepared_statements: DEFAULT_MAX_PREPARED_STATEMENTS,
+            max_open_portals: DEFAULT_MAX_OPEN_PORTALS,
+        }
+    }
+}
+
+/// The server-wide session quotas.
+///
+/// Clones share the same quotas, which may be replaced while the server is
+/// running. Established sessions are held to the new quotas from their next
+/// message.
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    quotas: Arc<RwLock<SessionQuotas>>,
+}
+
+impl Quotas {
+    /// Constructs a new holder of `quotas`.
+    pub fn new(quotas: SessionQuotas) -> Quotas {
+        Quotas {
+            quotas: Arc::new(RwLock::new(quotas)),
+        }
+    }
+
+    /// Replaces the quotas.
+    pub fn set(&self, quotas: SessionQuotas) {
+        *self.quotas.write().expect("lock poisoned") = quotas;
+    }
+
+    /// Returns the current quotas.
+    pub fn get(&self) -> SessionQuotas {
+        *self.quotas.read().expect("lock poisoned")
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Limits on the memory occupied by buffered query results.
+//!
+//! Clients that cannot stream results, like the HTTP SQL endpoint, buffer
+//! each result in full before sending it. Each such request takes a
+//! [`ResultReservation`] from the server-wide [`ResultBudget`], and grows it
+//! as it buffers rows, so that concurrent large results cannot exhaust the
+//! server's memory.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+use ore::cast::CastFrom;
+use ore::metrics::{UIntCounter, UIntGauge};
+use repr::Row;
+
+use crate::error::CoordError;
+
+/// Limits the number of bytes of results buffered across all requests.
+///
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct ResultBudget {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    limit: Option<usize>,
+    wait: Duration,
+    /// The number of bytes reserved by all outstanding reservations.
+    buffered: Mutex<usize>,
+    /// Notified whenever bytes are returned to the budget.
+    released: Notify,
+    buffered_metric: UIntGauge,
+    rejected_metric: UIntCounter,
+}
+
+impl ResultBudget {
+    /// Constructs a budget that allows at most `limit` bytes of results to be
+    /// buffered at once, or an unlimited number if `limit` is `None`.
+    ///
+    /// A reservation that would exceed the limit waits for at most `wait` for
+    /// other reservations to shrink before it fails.
+    pub fn new(
+        limit: Option<usize>,
+        wait: Duration,
+        buffered_metric: UIntGauge,
+        rejected_metric: UIntCounter,
+    ) -> ResultBudget {
+        ResultBudget {
+            inner: Arc::new(Inner {
+                limit,
+                wait,
+                buffered: Mutex::new(0),
+                released: Notify::new(),
+                buffered_metric,
+                rejected_metric,
+            }),
+        }
+    }
+
+    /// Returns an empty reservation against the budget.
+    pub fn reserve(&self) -> ResultReservation {
+        ResultReservation {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    /// Returns the number of bytes reserved by all outstanding reservations.
+    pub fn buffered_bytes(&self) -> usize {
+        *self.inner.buffered.lock().expect("lock poisoned")
+    }
+
+    /// Attempts to take `bytes` from the budget without waiting.
+    fn take(&self, bytes: usize) -> bool {
+        let mut buffered = self.inner.buffered.lock().expect("lock poisoned");
+        match self.inner.limit {
+            Some(limit) if *buffered + bytes > limit => false,
+            _ => {
+                *buffered += bytes;
+                self.inner.buffered_metric.set(u64::cast_from(*buffered));
+                true
+            }
+        }
+    }
+
+    /// Returns `bytes` to the budget, waking any reservations waiting for
+    /// room.
+    fn give(&self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        {
+            let mut buffered = self.inner.buffered.lock().expect("lock poisoned");
+            *buffered -= bytes;
+            self.inner.buffered_metric.set(u64::cast_from(*buffered));
+        }
+        self.inner.released.notify_waiters();
+    }
+
+    fn exhausted(&self) -> CoordError {
+        self.inner.rejected_metric.inc();
+        CoordError::ResultMemoryExhausted {
+            limit: self.inner.limit.unwrap_or(usize::MAX),
+        }
+    }
+}
+
+/// A number of bytes of results that one request has buffered.
+///
+/// The bytes are returned to the [`ResultBudget`] when the reservation is
+/// dropped.
+#[derive(Debug)]
+pub struct ResultReservation {
+    budget: ResultBudget,
+    bytes: usize,
+}
+
+impl ResultReservation {
+    /// Returns the number of bytes that the reservation holds.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Grows the reservation by `bytes`, waiting briefly for other
+    /// reservations to shrink if the budget is exhausted.
+    ///
+    /// Fails immediately if the reservation could not grow even if it were
+    /// the only one outstanding.
+    pub async fn grow(&mut self, bytes: usize) -> Result<(), CoordError> {
+        if self.budget.take(bytes) {
+            self.bytes += bytes;
+            return Ok(());
+        }
+        match self.budget.inner.limit {
+            Some(limit) if self.bytes + bytes > limit => return Err(self.budget.exhausted()),
+            _ => (),
+        }
+        let deadline = Instant::now() + self.budget.inner.wait;
+        loop {
+            // Register for notification before checking the budget again, so
+            // that a release in between is not missed.
+            let released = self.budget.inner.released.notified();
+            if self.budget.take(bytes) {
+                self.bytes += bytes;
+                return Ok(());
+            }
+            if time::timeout_at(deadline, released).await.is_err() {
+                return Err(self.budget.exhausted());
+            }
+        }
+    }
+
+    /// Grows the reservation by `bytes`, failing immediately if the budget
+    /// is exhausted.
+    pub fn try_grow(&mut self, bytes: usize) -> Result<(), CoordError> {
+        if self.budget.take(bytes) {
+            self.bytes += bytes;
+            Ok(())
+        } else {
+            Err(self.budget.exhausted())
+        }
+    }
+
+    /// Shrinks the reservation by `bytes`, or to nothing if it holds fewer.
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.bytes -= bytes;
+        self.budget.give(bytes);
+    }
+}
+
+impl Drop for ResultReservation {
+    fn drop(&mut self) {
+        self.budget.give(self.bytes);
+    }
+}
+
+/// Returns the number of bytes that `row` occupies in memory.
+pub fn row_size(row: &Row) -> usize {
+    mem::size_of::<Row>() + row.data().len()
+}
+
+/// Returns the number of bytes that `value` occupies in memory.
+///
+/// The count includes the value itself and everything it owns on the heap,
+/// but not the slack capacity of its strings and collections, which the
+/// conversion of results to JSON does not allocate.
+pub fn json_size(value: &serde_json::Value) -> usize {
+    use serde_json::Value;
+
+    mem::size_of::<Value>()
+        + match value {
+            Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+            Value::String(s) => s.len(),
+            Value::Array(values) => values.iter().map(json_size).sum(),
+            Value::Object(map) => map
+                .iter()
+                .map(|(k, v)| mem::size_of::<String>() + k.len() + json_size(v))
+                .sum(),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metric;
+    use ore::metrics::MetricsRegistry;
+
+    use super::*;
+
+    fn budget(limit: Option<usize>, wait: Duration) -> (ResultBudget, UIntGauge, UIntCounter) {
+        let registry = MetricsRegistry::new();
+        let buffered: UIntGauge = registry.register(metric!(
+            name: "buffered",
+            help: "buffered",
+        ));
+        let rejected: UIntCounter = registry.register(metric!(
+            name: "rejected",
+            help: "rejected",
+        ));
+        let budget = ResultBudget::new(limit, wait, buffered.clone(), rejected.clone());
+        (budget, buffered, rejected)
+    }
+
+    #[tokio::test]
+    async fn test_reservations_share_budget() {
+        let (budget, buffered, rejected) = budget(Some(100), Duration::from_millis(10));
+        let mut a = budget.reserve();
+        let mut b = budget.reserve();
+        a.grow(60).await.unwrap();
+        b.grow(40).await.unwrap();
+        assert_eq!(buffered.get(), 100);
+
+        // The budget is exhausted, so growing fails after waiting.
+        assert!(matches!(
+            b.grow(1).await,
+            Err(CoordError::ResultMemoryExhausted { limit: 100 })
+        ));
+        assert!(a.try_grow(1).is_err());
+        assert_eq!(rejected.get(), 2);
+        assert_eq!(b.bytes(), 40);
+
+        a.shrink(10);
+        b.grow(10).await.unwrap();
+        drop(a);
+        assert_eq!(budget.buffered_bytes(), 50);
+        drop(b);
+        assert_eq!(buffered.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_grow_waits_for_release() {
+        let (budget, _buffered, _rejected) = budget(Some(100), Duration::from_secs(60));
+        let mut a = budget.reserve();
+        a.grow(100).await.unwrap();
+        let waiter = {
+            let budget = budget.clone();
+            tokio::spawn(async move {
+                let mut b = budget.reserve();
+                b.grow(50).await.map(|()| b)
+            })
+        };
+        tokio::task::yield_now().await;
+        drop(a);
+        let b = waiter.await.unwrap().unwrap();
+        assert_eq!(b.bytes(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_grow_fails_immediately() {
+        let (budget, _buffered, _rejected) = budget(Some(100), Duration::from_secs(60));
+        let mut a = budget.reserve();
+        a.grow(90).await.unwrap();
+        // Even alone, the reservation could not hold 101 bytes, so there is
+        // no point in waiting.
+        assert!(a.grow(11).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let (budget, buffered, _rejected) = budget(None, Duration::from_secs(60));
+        let mut a = budget.reserve();
+        a.grow(usize::MAX / 2).await.unwrap();
+        assert_eq!(buffered.get(), u64::cast_from(usize::MAX / 2));
+    }
+
+    #[test]
+    fn test_json_size() {
+        let value = serde_json::json!({"a": ["bc", 1, null]});
+        let value_size = mem::size_of::<serde_json::Value>();
+        assert_eq!(
+            json_size(&value),
+            value_size
+                + mem::size_of::<String>()
+                + 1
+                + value_size
+                + (value_size + 2)
+                + value_size * 2
+        );
+    }
+}
@@ -11,7 +11,7 @@
 
 #![forbid(missing_docs)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
 
 use chrono::{DateTime, Utc};
@@ -24,10 +24,14 @@ use sql::ast::{Raw, Statement};
 use sql::plan::{Params, PlanContext, StatementDesc};
 
 use crate::error::CoordError;
+use crate::statement_labels::StatementLabels;
+use crate::transport::Transport;
 
+mod options;
 mod vars;
 
-pub use self::vars::{Var, Vars};
+pub use self::options::parse_options;
+pub use self::vars::{server_version_num, SessionDefaults, Var, Vars};
 
 const DUMMY_CONNECTION_ID: u32 = 0;
 
@@ -35,6 +39,9 @@ const DUMMY_CONNECTION_ID: u32 = 0;
 #[derive(Debug)]
 pub struct Session {
     conn_id: u32,
+    correlation_id: Option<String>,
+    transport: Option<Transport>,
+    options: BTreeMap<String, String>,
     prepared_statements: HashMap<String, PreparedStatement>,
     portals: HashMap<String, Portal>,
     transaction: TransactionStatus,
@@ -62,6 +69,9 @@ impl Session {
     fn new_internal(conn_id: u32, user: String) -> Session {
         Session {
             conn_id,
+            correlation_id: None,
+            transport: None,
+            options: BTreeMap::new(),
             transaction: TransactionStatus::Default,
             pcx: None,
             prepared_statements: HashMap::new(),
@@ -77,6 +87,51 @@ impl Session {
         self.conn_id
     }
 
+    /// Returns the correlation ID of the network connection that owns the
+    /// session, if any.
+    ///
+    /// Unlike the connection ID, which is reused once the connection closes,
+    /// the correlation ID is unique and appears in every log message that
+    /// pertains to the connection.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    /// Sets the correlation ID of the session.
+    pub fn set_correlation_id(&mut self, correlation_id: String) {
+        self.correlation_id = Some(correlation_id);
+    }
+
+    /// Returns the transport over which the client that owns the session
+    /// connected, if the session is owned by a network connection.
+    pub fn transport(&self) -> Option<&Transport> {
+        self.transport.as_ref()
+    }
+
+    /// Sets the transport over which the client that owns the session
+    /// connected.
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = Some(transport);
+    }
+
+    /// Returns the options that the client supplied when it established the
+    /// session.
+    pub fn options(&self) -> &BTreeMap<String, String> {
+        &self.options
+    }
+
+    /// Sets the options that the client supplied when it established the
+    /// session, e.g., via the `options` startup parameter.
+    ///
+    /// Options that name session variables also set those variables. Invalid
+    /// values are ignored, as they are for other startup parameters.
+    pub fn set_options(&mut self, options: BTreeMap<String, String>) {
+        for (name, value) in &options {
+            let _ = self.vars.set(name, value);
+        }
+        self.options = options;
+    }
+
     /// Returns the current transaction's PlanContext. Panics if there is not a
     /// current transaction.
     pub fn pcx(&self) -> &PlanContext {
@@ -232,6 +287,57 @@ impl Session {
         self.prepared_statements.get(name)
     }
 
+    /// Retrieves a mutable reference to the prepared statement associated
+    /// with `name`.
+    pub fn get_prepared_statement_mut(&mut self, name: &str) -> Option<&mut PreparedStatement> {
+        self.prepared_statements.get_mut(name)
+    }
+
+    /// Checks that registering a prepared statement under `name` would not
+    /// exceed the session's `max_prepared_statements` quota.
+    ///
+    /// Only named prepared statements count against the quota. The unnamed
+    /// prepared statement is replaced by each new one, as is a named prepared
+    /// statement that is registered again.
+    pub fn check_prepared_statement_quota(&self, name: &str) -> Result<(), CoordError> {
+        if name.is_empty() || self.prepared_statements.contains_key(name) {
+            return Ok(());
+        }
+        let named = self
+            .prepared_statements
+            .keys()
+            .filter(|name| !name.is_empty())
+            .count();
+        self.vars.check_prepared_statements(named)
+    }
+
+    /// Checks that binding the portal `portal_name` would not exceed the
+    /// session's `max_open_portals` quota.
+    ///
+    /// Only named portals count against the quota, as the unnamed portal is
+    /// replaced by each new one.
+    fn check_portal_quota(&self, portal_name: &str) -> Result<(), CoordError> {
+        if portal_name.is_empty() {
+            return Ok(());
+        }
+        let named = self.portals.keys().filter(|name| !name.is_empty()).count();
+        self.vars.check_open_portals(named)
+    }
+
+    /// Checks that starting another statement would not exceed the session's
+    /// `max_concurrent_statements` quota.
+    ///
+    /// Statements are executing from when their portal starts until it has
+    /// returned all of its rows.
+    pub fn check_concurrent_statement_quota(&self) -> Result<(), CoordError> {
+        let executing = self
+            .portals
+            .values()
+            .filter(|portal| matches!(portal.state, PortalState::InProgress(_)))
+            .count();
+        self.vars.check_concurrent_statements(executing)
+    }
+
     /// Binds the specified portal to the specified prepared statement.
     ///
     /// If the prepared statement contains parameters, the values and types of
@@ -241,6 +347,9 @@ impl Session {
     ///
     // The `results_formats` parameter sets the desired format of the results,
     /// and is stored on the portal.
+    ///
+    /// Fails if binding the portal would exceed the session's
+    /// `max_open_portals` quota.
     pub fn set_portal(
         &mut self,
         portal_name: String,
@@ -253,6 +362,7 @@ impl Session {
         if !portal_name.is_empty() && self.portals.contains_key(&portal_name) {
             return Err(CoordError::DuplicateCursor(portal_name));
         }
+        self.check_portal_quota(&portal_name)?;
         self.portals.insert(
             portal_name,
             Portal {
@@ -263,6 +373,7 @@ impl Session {
                     types: params.into_iter().map(|(_d, t)| t).collect(),
                 },
                 result_formats: result_formats.into_iter().map(Into::into).collect(),
+                labels: StatementLabels::default(),
                 state: PortalState::NotStarted,
             },
         );
@@ -320,12 +431,17 @@ impl Session {
 pub struct PreparedStatement {
     sql: Option<Statement<Raw>>,
     desc: StatementDesc,
+    labels: StatementLabels,
 }
 
 impl PreparedStatement {
-    /// Constructs a new prepared statement.
+    /// Constructs a new prepared statement, without labels.
     pub fn new(sql: Option<Statement<Raw>>, desc: StatementDesc) -> PreparedStatement {
-        PreparedStatement { sql, desc }
+        PreparedStatement {
+            sql,
+            desc,
+            labels: StatementLabels::default(),
+        }
     }
 
     /// Returns the raw SQL string associated with this prepared statement,
@@ -338,6 +454,16 @@ impl PreparedStatement {
     pub fn desc(&self) -> &StatementDesc {
         &self.desc
     }
+
+    /// Returns the labels that the prepared statement carries.
+    pub fn labels(&self) -> &StatementLabels {
+        &self.labels
+    }
+
+    /// Attaches `labels` to the prepared statement.
+    pub fn set_labels(&mut self, labels: StatementLabels) {
+        self.labels = labels;
+    }
 }
 
 /// A portal represents the execution state of a running or runnable query.
@@ -352,6 +478,8 @@ pub struct Portal {
     pub parameters: Params,
     /// The desired output format for each column in the result set.
     pub result_formats: Vec<pgrepr::Format>,
+    /// The labels that the statement carries.
+    pub labels: StatementLabels,
     /// The execution state of the portal.
     #[derivative(Debug = "ignore")]
     pub state: PortalState,
@@ -370,8 +498,8 @@ pub enum PortalState {
     Completed(Option<String>),
 }
 
-/// A stream of batched rows.
-pub type RowBatchStream = Box<dyn Stream<Item = Vec<Row>> + Send + Unpin>;
+/// A stream of batched rows, which ends early if producing the rows fails.
+pub type RowBatchStream = Box<dyn Stream<Item = Result<Vec<Row>, CoordError>> + Send + Unpin>;
 
 /// The transaction status of a session.
 ///
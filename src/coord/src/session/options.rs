@@ -0,0 +1,89 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+use std::collections::BTreeMap;
+
+/// Parses the `options` startup parameter.
+///
+/// Like PostgreSQL, the parameter is interpreted as a list of command-line
+/// arguments separated by whitespace, where a backslash escapes the next
+/// character. Arguments of the forms `-c name=value`, `-cname=value`, and
+/// `--name=value` specify an option. Dashes in the names of options of the
+/// last form are converted to underscores. Other arguments are ignored.
+pub fn parse_options(options: &str) -> BTreeMap<String, String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+
+    let mut out = BTreeMap::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (setting, dashes) = if arg == "-c" {
+            match args.next() {
+                Some(setting) => (setting, false),
+                None => break,
+            }
+        } else if let Some(setting) = arg.strip_prefix("--") {
+            (setting.to_owned(), true)
+        } else if let Some(setting) = arg.strip_prefix("-c") {
+            (setting.to_owned(), false)
+        } else {
+            continue;
+        };
+        if let Some((name, value)) = setting.split_once('=') {
+            let name = match dashes {
+                true => name.replace('-', "_"),
+                false => name.to_owned(),
+            };
+            out.insert(name, value.to_owned());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_options;
+
+    #[test]
+    fn test_parse_options() {
+        let test_cases = vec![
+            ("", vec![]),
+            ("-c statement_timeout=5s", vec![("statement_timeout", "5s")]),
+            (
+                "-cstatement_timeout=5s  --application-name=a\\ b",
+                vec![("application_name", "a b"), ("statement_timeout", "5s")],
+            ),
+            ("-c a=1 -c b=x=y", vec![("a", "1"), ("b", "x=y")]),
+            ("junk -c novalue -c", vec![]),
+        ];
+        for (input, expected) in test_cases {
+            let actual = parse_options(input);
+            let actual: Vec<_> = actual
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            assert_eq!(actual, expected, "input: {:?}", input);
+        }
+    }
+}
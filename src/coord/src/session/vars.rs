@@ -9,10 +9,16 @@
 
 use std::borrow::Borrow;
 use std::fmt;
+use std::time::Duration;
 
 use uncased::UncasedStr;
 
+use ore::cast::CastFrom;
+use ore::trace::TraceParent;
+use repr::Row;
+
 use crate::error::CoordError;
+use crate::quotas::SessionQuotas;
 
 // TODO(benesch): remove this when SergioBenitez/uncased#3 resolves.
 macro_rules! static_uncased_str {
@@ -53,12 +59,60 @@ const EXTRA_FLOAT_DIGITS: ServerVar<i32> = ServerVar {
     description: "Adjusts the number of digits displayed for floating-point values (PostgreSQL).",
 };
 
+const IDLE_IN_TRANSACTION_SESSION_TIMEOUT: ServerVar<Duration> = ServerVar {
+    name: static_uncased_str!("idle_in_transaction_session_timeout"),
+    value: &Duration::from_secs(0),
+    description: "Sets the maximum allowed duration of any idling transaction (PostgreSQL).",
+};
+
 const INTEGER_DATETIMES: ServerVar<bool> = ServerVar {
     name: static_uncased_str!("integer_datetimes"),
     value: &true,
     description: "Reports whether the server uses 64-bit-integer dates and times (PostgreSQL).",
 };
 
+const LOGICAL_COMPACTION_WINDOW: ServerVar<str> = ServerVar {
+    name: static_uncased_str!("logical_compaction_window"),
+    value: "off",
+    description:
+        "Shows how much historical detail the server retains, or off if unlimited (Materialize).",
+};
+
+const MAX_CONCURRENT_STATEMENTS: ServerVar<u64> = ServerVar {
+    name: static_uncased_str!("max_concurrent_statements"),
+    value: &crate::quotas::DEFAULT_MAX_CONCURRENT_STATEMENTS,
+    description:
+        "Shows the maximum number of statements that a session may have executing at once, or 0 if unlimited (Materialize).",
+};
+
+const MAX_OPEN_PORTALS: ServerVar<u64> = ServerVar {
+    name: static_uncased_str!("max_open_portals"),
+    value: &crate::quotas::DEFAULT_MAX_OPEN_PORTALS,
+    description:
+        "Shows the maximum number of portals that a session may have open, or 0 if unlimited (Materialize).",
+};
+
+const MAX_PREPARED_STATEMENTS: ServerVar<u64> = ServerVar {
+    name: static_uncased_str!("max_prepared_statements"),
+    value: &crate::quotas::DEFAULT_MAX_PREPARED_STATEMENTS,
+    description:
+        "Shows the maximum number of prepared statements that a session may hold, or 0 if unlimited (Materialize).",
+};
+
+const MAX_RESULT_BYTES: ServerVar<u64> = ServerVar {
+    name: static_uncased_str!("max_result_bytes"),
+    value: &0,
+    description:
+        "Sets the maximum size in bytes of the result of any statement, or 0 if unlimited (Materialize).",
+};
+
+const MAX_RESULT_ROWS: ServerVar<u64> = ServerVar {
+    name: static_uncased_str!("max_result_rows"),
+    value: &0,
+    description:
+        "Sets the maximum number of rows in the result of any statement, or 0 if unlimited (Materialize).",
+};
+
 const SEARCH_PATH: ServerVar<[&str]> = ServerVar {
     name: static_uncased_str!("search_path"),
     value: &["mz_catalog", "pg_catalog", "public", "mz_temp"],
@@ -95,6 +149,12 @@ const STANDARD_CONFORMING_STRINGS: ServerVar<bool> = ServerVar {
     description: "Causes '...' strings to treat backslashes literally (PostgreSQL).",
 };
 
+const STATEMENT_TIMEOUT: ServerVar<Duration> = ServerVar {
+    name: static_uncased_str!("statement_timeout"),
+    value: &Duration::from_secs(0),
+    description: "Sets the maximum allowed duration of any statement (PostgreSQL).",
+};
+
 const TIMEZONE: ServerVar<str> = ServerVar {
     // TimeZone has nonstandard capitalization for historical reasons.
     name: static_uncased_str!("TimeZone"),
@@ -102,6 +162,12 @@ const TIMEZONE: ServerVar<str> = ServerVar {
     description: "Sets the time zone for displaying and interpreting time stamps (PostgreSQL).",
 };
 
+const TRACEPARENT: ServerVar<str> = ServerVar {
+    name: static_uncased_str!("traceparent"),
+    value: "",
+    description: "Sets the W3C trace context to which subsequent statements belong (Materialize).",
+};
+
 const TRANSACTION_ISOLATION: ServerVar<str> = ServerVar {
     name: static_uncased_str!("transaction_isolation"),
     value: "serializable",
@@ -129,6 +195,10 @@ const TRANSACTION_ISOLATION: ServerVar<str> = ServerVar {
 /// The configuration parameters that exist are driven by compatibility with
 /// PostgreSQL drivers that expect them, not because they are particularly
 /// important.
+///
+/// The exceptions are the timeouts and the result size limits, whose defaults
+/// can be configured for the whole server when it starts. See
+/// [`SessionDefaults`].
 #[derive(Debug)]
 pub struct Vars {
     application_name: SessionVar<str>,
@@ -136,14 +206,49 @@ pub struct Vars {
     database: SessionVar<str>,
     date_style: ServerVar<str>,
     extra_float_digits: SessionVar<i32>,
+    idle_in_transaction_session_timeout: SessionVar<Duration>,
     integer_datetimes: ServerVar<bool>,
+    logical_compaction_window: SessionVar<str>,
+    max_concurrent_statements: SessionVar<u64>,
+    max_open_portals: SessionVar<u64>,
+    max_prepared_statements: SessionVar<u64>,
+    max_result_bytes: SessionVar<u64>,
+    max_result_rows: SessionVar<u64>,
     search_path: ServerVar<[&'static str]>,
-    server_version: ServerVar<str>,
-    server_version_num: ServerVar<i32>,
+    server_version: SessionVar<str>,
+    server_version_num: SessionVar<i32>,
     sql_safe_updates: SessionVar<bool>,
     standard_conforming_strings: ServerVar<bool>,
+    statement_timeout: SessionVar<Duration>,
     timezone: ServerVar<str>,
+    traceparent: SessionVar<str>,
     transaction_isolation: ServerVar<str>,
+    /// Whether the timeouts are fixed at their server defaults.
+    timeouts_locked: bool,
+    /// The largest `max_result_bytes` that the session may set, if any.
+    max_result_bytes_limit: Option<u64>,
+    /// The largest `max_result_rows` that the session may set, if any.
+    max_result_rows_limit: Option<u64>,
+}
+
+/// Server-wide defaults for the configuration parameters of every session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionDefaults {
+    /// The `server_version` to report instead of the default, if any.
+    ///
+    /// The version must be accepted by [`server_version_num`], which also
+    /// determines the reported `server_version_num`.
+    pub server_version: Option<String>,
+    /// The default `statement_timeout`, if any.
+    pub statement_timeout: Option<Duration>,
+    /// The default `idle_in_transaction_session_timeout`, if any.
+    pub idle_in_transaction_session_timeout: Option<Duration>,
+    /// Whether sessions are prohibited from changing the timeouts.
+    pub lock_timeouts: bool,
+    /// The default and largest permissible `max_result_rows`, if any.
+    pub max_result_rows: Option<u64>,
+    /// The default and largest permissible `max_result_bytes`, if any.
+    pub max_result_bytes: Option<u64>,
 }
 
 impl Default for Vars {
@@ -154,14 +259,28 @@ impl Default for Vars {
             database: SessionVar::new(&DATABASE),
             date_style: DATE_STYLE,
             extra_float_digits: SessionVar::new(&EXTRA_FLOAT_DIGITS),
+            idle_in_transaction_session_timeout: SessionVar::new(
+                &IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+            ),
             integer_datetimes: INTEGER_DATETIMES,
+            logical_compaction_window: SessionVar::new(&LOGICAL_COMPACTION_WINDOW),
+            max_concurrent_statements: SessionVar::new(&MAX_CONCURRENT_STATEMENTS),
+            max_open_portals: SessionVar::new(&MAX_OPEN_PORTALS),
+            max_prepared_statements: SessionVar::new(&MAX_PREPARED_STATEMENTS),
+            max_result_bytes: SessionVar::new(&MAX_RESULT_BYTES),
+            max_result_rows: SessionVar::new(&MAX_RESULT_ROWS),
             search_path: SEARCH_PATH,
-            server_version: SERVER_VERSION,
-            server_version_num: SERVER_VERSION_NUM,
+            server_version: SessionVar::new(&SERVER_VERSION),
+            server_version_num: SessionVar::new(&SERVER_VERSION_NUM),
             sql_safe_updates: SessionVar::new(&SQL_SAFE_UPDATES),
             standard_conforming_strings: STANDARD_CONFORMING_STRINGS,
+            statement_timeout: SessionVar::new(&STATEMENT_TIMEOUT),
             timezone: TIMEZONE,
+            traceparent: SessionVar::new(&TRACEPARENT),
             transaction_isolation: TRANSACTION_ISOLATION,
+            timeouts_locked: false,
+            max_result_bytes_limit: None,
+            max_result_rows_limit: None,
         }
     }
 }
@@ -176,13 +295,22 @@ impl Vars {
             &self.database,
             &self.date_style,
             &self.extra_float_digits,
+            &self.idle_in_transaction_session_timeout,
             &self.integer_datetimes,
+            &self.logical_compaction_window,
+            &self.max_concurrent_statements,
+            &self.max_open_portals,
+            &self.max_prepared_statements,
+            &self.max_result_bytes,
+            &self.max_result_rows,
             &self.search_path,
             &self.server_version,
             &self.server_version_num,
             &self.sql_safe_updates,
             &self.standard_conforming_strings,
+            &self.statement_timeout,
             &self.timezone,
+            &self.traceparent,
             &self.transaction_isolation,
         ]
         .into_iter()
@@ -224,8 +352,22 @@ impl Vars {
             Ok(&self.date_style)
         } else if name == EXTRA_FLOAT_DIGITS.name {
             Ok(&self.extra_float_digits)
+        } else if name == IDLE_IN_TRANSACTION_SESSION_TIMEOUT.name {
+            Ok(&self.idle_in_transaction_session_timeout)
         } else if name == INTEGER_DATETIMES.name {
             Ok(&self.integer_datetimes)
+        } else if name == LOGICAL_COMPACTION_WINDOW.name {
+            Ok(&self.logical_compaction_window)
+        } else if name == MAX_CONCURRENT_STATEMENTS.name {
+            Ok(&self.max_concurrent_statements)
+        } else if name == MAX_OPEN_PORTALS.name {
+            Ok(&self.max_open_portals)
+        } else if name == MAX_PREPARED_STATEMENTS.name {
+            Ok(&self.max_prepared_statements)
+        } else if name == MAX_RESULT_BYTES.name {
+            Ok(&self.max_result_bytes)
+        } else if name == MAX_RESULT_ROWS.name {
+            Ok(&self.max_result_rows)
         } else if name == SEARCH_PATH.name {
             Ok(&self.search_path)
         } else if name == SERVER_VERSION.name {
@@ -236,8 +378,12 @@ impl Vars {
             Ok(&self.sql_safe_updates)
         } else if name == STANDARD_CONFORMING_STRINGS.name {
             Ok(&self.standard_conforming_strings)
+        } else if name == STATEMENT_TIMEOUT.name {
+            Ok(&self.statement_timeout)
         } else if name == TIMEZONE.name {
             Ok(&self.timezone)
+        } else if name == TRACEPARENT.name {
+            Ok(&self.traceparent)
         } else if name == TRANSACTION_ISOLATION.name {
             Ok(&self.transaction_isolation)
         } else {
@@ -269,8 +415,31 @@ impl Vars {
             Ok(())
         } else if name == EXTRA_FLOAT_DIGITS.name {
             self.extra_float_digits.set(value)
+        } else if name == IDLE_IN_TRANSACTION_SESSION_TIMEOUT.name {
+            if self.timeouts_locked {
+                return Err(CoordError::ReadOnlyParameter(
+                    &IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+                ));
+            }
+            self.idle_in_transaction_session_timeout.set(value)
         } else if name == INTEGER_DATETIMES.name {
             Err(CoordError::ReadOnlyParameter(&INTEGER_DATETIMES))
+        } else if name == LOGICAL_COMPACTION_WINDOW.name {
+            Err(CoordError::ReadOnlyParameter(&LOGICAL_COMPACTION_WINDOW))
+        } else if name == MAX_CONCURRENT_STATEMENTS.name {
+            Err(CoordError::ReadOnlyParameter(&MAX_CONCURRENT_STATEMENTS))
+        } else if name == MAX_OPEN_PORTALS.name {
+            Err(CoordError::ReadOnlyParameter(&MAX_OPEN_PORTALS))
+        } else if name == MAX_PREPARED_STATEMENTS.name {
+            Err(CoordError::ReadOnlyParameter(&MAX_PREPARED_STATEMENTS))
+        } else if name == MAX_RESULT_BYTES.name {
+            set_limited(
+                &mut self.max_result_bytes,
+                self.max_result_bytes_limit,
+                value,
+            )
+        } else if name == MAX_RESULT_ROWS.name {
+            set_limited(&mut self.max_result_rows, self.max_result_rows_limit, value)
         } else if name == SEARCH_PATH.name {
             Err(CoordError::ReadOnlyParameter(&SEARCH_PATH))
         } else if name == SERVER_VERSION.name {
@@ -281,12 +450,26 @@ impl Vars {
             self.sql_safe_updates.set(value)
         } else if name == STANDARD_CONFORMING_STRINGS.name {
             Err(CoordError::ReadOnlyParameter(&STANDARD_CONFORMING_STRINGS))
+        } else if name == STATEMENT_TIMEOUT.name {
+            if self.timeouts_locked {
+                return Err(CoordError::ReadOnlyParameter(&STATEMENT_TIMEOUT));
+            }
+            self.statement_timeout.set(value)
         } else if name == TIMEZONE.name {
             if UncasedStr::new(value) != TIMEZONE.value {
                 return Err(CoordError::ConstrainedParameter(&TIMEZONE));
             } else {
                 Ok(())
             }
+        } else if name == TRACEPARENT.name {
+            // The empty string clears the trace context.
+            if !value.is_empty() && value.parse::<TraceParent>().is_err() {
+                return Err(CoordError::InvalidParameterValue {
+                    parameter: &TRACEPARENT,
+                    value: value.into(),
+                });
+            }
+            self.traceparent.set(value)
         } else if name == TRANSACTION_ISOLATION.name {
             Err(CoordError::ReadOnlyParameter(&TRANSACTION_ISOLATION))
         } else {
@@ -294,6 +477,69 @@ impl Vars {
         }
     }
 
+    /// Applies the server-wide `defaults` to this session.
+    ///
+    /// If the defaults lock the timeouts, any values that the session set
+    /// for the timeouts, e.g., in its startup parameters, are discarded, and
+    /// subsequent attempts to set the timeouts fail. Likewise, any result size
+    /// limits that the session set above the server's limits are discarded.
+    pub fn apply_defaults(&mut self, defaults: &SessionDefaults) {
+        if let Some(version) = &defaults.server_version {
+            if let Some(version_num) = server_version_num(version) {
+                self.server_version.set_default(version.clone());
+                self.server_version_num.set_default(version_num);
+            }
+        }
+        if let Some(timeout) = defaults.statement_timeout {
+            self.statement_timeout.set_default(timeout);
+        }
+        if let Some(timeout) = defaults.idle_in_transaction_session_timeout {
+            self.idle_in_transaction_session_timeout
+                .set_default(timeout);
+        }
+        if defaults.lock_timeouts {
+            self.statement_timeout.reset();
+            self.idle_in_transaction_session_timeout.reset();
+            self.timeouts_locked = true;
+        }
+        if let Some(limit) = defaults.max_result_rows {
+            self.max_result_rows.set_default(limit);
+            if !within_limit(*self.max_result_rows.value(), limit) {
+                self.max_result_rows.reset();
+            }
+            self.max_result_rows_limit = Some(limit);
+        }
+        if let Some(limit) = defaults.max_result_bytes {
+            self.max_result_bytes.set_default(limit);
+            if !within_limit(*self.max_result_bytes.value(), limit) {
+                self.max_result_bytes.reset();
+            }
+            self.max_result_bytes_limit = Some(limit);
+        }
+    }
+
+    /// Reports the server's logical compaction window in the
+    /// `logical_compaction_window` configuration parameter.
+    ///
+    /// A window of `None`, which disables logical compaction, is reported as
+    /// `off`.
+    pub fn set_logical_compaction_window(&mut self, window: Option<Duration>) {
+        if let Some(window) = window {
+            self.logical_compaction_window.set_default(window.format());
+        }
+    }
+
+    /// Reports the server's session quotas in the `max_concurrent_statements`,
+    /// `max_prepared_statements`, and `max_open_portals` configuration
+    /// parameters, which the quota checks consult.
+    pub fn set_session_quotas(&mut self, quotas: &SessionQuotas) {
+        self.max_concurrent_statements
+            .set_default(quotas.max_concurrent_statements);
+        self.max_prepared_statements
+            .set_default(quotas.max_prepared_statements);
+        self.max_open_portals.set_default(quotas.max_open_portals);
+    }
+
     /// Returns the value of the `application_name` configuration parameter.
     pub fn application_name(&self) -> &str {
         self.application_name.value()
@@ -319,24 +565,125 @@ impl Vars {
         *self.extra_float_digits.value()
     }
 
+    /// Returns the value of the `idle_in_transaction_session_timeout`
+    /// configuration parameter, or `None` if the timeout is disabled.
+    pub fn idle_in_transaction_session_timeout(&self) -> Option<Duration> {
+        nonzero(*self.idle_in_transaction_session_timeout.value())
+    }
+
     /// Returns the value of the `integer_datetimes` configuration parameter.
     pub fn integer_datetimes(&self) -> bool {
         *self.integer_datetimes.value
     }
 
+    /// Checks that a session with `count` statements executing may start
+    /// another without exceeding the `max_concurrent_statements` quota.
+    pub fn check_concurrent_statements(&self, count: usize) -> Result<(), CoordError> {
+        check_quota(
+            &self.max_concurrent_statements,
+            count,
+            "concurrent statements",
+        )
+    }
+
+    /// Checks that a session with `count` open portals may open another
+    /// without exceeding the `max_open_portals` quota.
+    pub fn check_open_portals(&self, count: usize) -> Result<(), CoordError> {
+        check_quota(&self.max_open_portals, count, "open portals")
+    }
+
+    /// Checks that a session holding `count` prepared statements may prepare
+    /// another without exceeding the `max_prepared_statements` quota.
+    pub fn check_prepared_statements(&self, count: usize) -> Result<(), CoordError> {
+        check_quota(&self.max_prepared_statements, count, "prepared statements")
+    }
+
+    /// Returns the value of the `max_result_bytes` configuration parameter,
+    /// or `None` if results are unlimited in size.
+    pub fn max_result_bytes(&self) -> Option<u64> {
+        nonzero_limit(*self.max_result_bytes.value())
+    }
+
+    /// Returns the value of the `max_result_rows` configuration parameter,
+    /// or `None` if results may have any number of rows.
+    pub fn max_result_rows(&self) -> Option<u64> {
+        nonzero_limit(*self.max_result_rows.value())
+    }
+
+    /// Checks the running totals of the rows and bytes that a statement has
+    /// accumulated against the `max_result_rows` and `max_result_bytes`
+    /// configuration parameters.
+    ///
+    /// Used by statements that accumulate rows across many batches, like
+    /// `COPY ... FROM STDIN`, for which checking each batch in isolation
+    /// would not bound the total.
+    pub fn check_result_totals(&self, rows: u64, bytes: u64) -> Result<(), CoordError> {
+        if let Some(limit) = self.max_result_rows() {
+            if rows > limit {
+                return Err(CoordError::ResultSizeLimitExceeded {
+                    parameter: &MAX_RESULT_ROWS,
+                    limit,
+                    unit: "rows",
+                });
+            }
+        }
+        if let Some(limit) = self.max_result_bytes() {
+            if bytes > limit {
+                return Err(CoordError::ResultSizeLimitExceeded {
+                    parameter: &MAX_RESULT_BYTES,
+                    limit,
+                    unit: "bytes",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a batch of result rows against the `max_result_rows` and
+    /// `max_result_bytes` configuration parameters.
+    ///
+    /// The bytes in a row are counted as the size of its packed
+    /// representation, which is independent of the wire format in which the
+    /// row is sent to the client.
+    pub fn check_result_size(&self, rows: &[Row]) -> Result<(), CoordError> {
+        if let Some(limit) = self.max_result_rows() {
+            if u64::cast_from(rows.len()) > limit {
+                return Err(CoordError::ResultSizeLimitExceeded {
+                    parameter: &MAX_RESULT_ROWS,
+                    limit,
+                    unit: "rows",
+                });
+            }
+        }
+        if let Some(limit) = self.max_result_bytes() {
+            let mut bytes = 0;
+            for row in rows {
+                bytes += u64::cast_from(repr::datums_size(row.iter()));
+                if bytes > limit {
+                    return Err(CoordError::ResultSizeLimitExceeded {
+                        parameter: &MAX_RESULT_BYTES,
+                        limit,
+                        unit: "bytes",
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the value of the `search_path` configuration parameter.
     pub fn search_path(&self) -> &'static [&'static str] {
         self.search_path.value
     }
 
     /// Returns the value of the `server_version` configuration parameter.
-    pub fn server_version(&self) -> &'static str {
-        self.server_version.value
+    pub fn server_version(&self) -> &str {
+        self.server_version.value()
     }
 
     /// Returns the value of the `server_version_num` configuration parameter.
     pub fn server_version_num(&self) -> i32 {
-        *self.server_version_num.value
+        *self.server_version_num.value()
     }
 
     /// Returns the value of the `sql_safe_updates` configuration parameter.
@@ -350,11 +697,22 @@ impl Vars {
         *self.standard_conforming_strings.value
     }
 
+    /// Returns the value of the `statement_timeout` configuration parameter,
+    /// or `None` if the timeout is disabled.
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        nonzero(*self.statement_timeout.value())
+    }
+
     /// Returns the value of the `timezone` configuration parameter.
     pub fn timezone(&self) -> &'static str {
         self.timezone.value
     }
 
+    /// Returns the value of the `traceparent` configuration parameter.
+    pub fn traceparent(&self) -> &str {
+        self.traceparent.value()
+    }
+
     /// Returns the value of the `transaction_isolation` configuration
     /// parameter.
     pub fn transaction_isolation(&self) -> &'static str {
@@ -362,6 +720,94 @@ impl Vars {
     }
 }
 
+/// Computes the `server_version_num` that corresponds to a `server_version`.
+///
+/// Returns `None` if `version` does not look like a PostgreSQL version, i.e.,
+/// a major and minor version number and, for versions before 10, a patch
+/// version number, optionally followed by a space and arbitrary text, as in
+/// `13.4`, `9.6.2`, or `13.4 (Debian 13.4-1)`.
+pub fn server_version_num(version: &str) -> Option<i32> {
+    let number = match version.split_once(' ') {
+        Some((number, _)) => number,
+        None => version,
+    };
+    let parts = number
+        .split('.')
+        .map(|part| match part.chars().all(|c| c.is_ascii_digit()) {
+            true => part.parse::<i32>().ok(),
+            false => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    match parts.as_slice() {
+        [major, minor] if (10..100).contains(major) && *minor < 10000 => {
+            Some(major * 10000 + minor)
+        }
+        [major, minor, patch] if (1..10).contains(major) && *minor < 100 && *patch < 100 => {
+            Some(major * 10000 + minor * 100 + patch)
+        }
+        _ => None,
+    }
+}
+
+/// Maps a timeout of zero, which disables the timeout, to `None`.
+fn nonzero(timeout: Duration) -> Option<Duration> {
+    if timeout == Duration::from_secs(0) {
+        None
+    } else {
+        Some(timeout)
+    }
+}
+
+/// Maps a result size limit of zero, which disables the limit, to `None`.
+fn nonzero_limit(limit: u64) -> Option<u64> {
+    match limit {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Checks that a session holding `count` of the resource that `var` limits may
+/// acquire another. A limit of zero is unlimited.
+fn check_quota(
+    var: &SessionVar<u64>,
+    count: usize,
+    resource: &'static str,
+) -> Result<(), CoordError> {
+    match nonzero_limit(*var.value()) {
+        Some(limit) if u64::cast_from(count) >= limit => Err(CoordError::SessionQuotaExceeded {
+            parameter: var.parent,
+            limit,
+            resource,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Reports whether the result size limit `value` is no more permissive than
+/// `limit`.
+fn within_limit(value: u64, limit: u64) -> bool {
+    value != 0 && value <= limit
+}
+
+/// Sets a result size limit, ensuring that it is no more permissive than the
+/// server's limit, if any.
+fn set_limited(
+    var: &mut SessionVar<u64>,
+    limit: Option<u64>,
+    value: &str,
+) -> Result<(), CoordError> {
+    let parsed = u64::parse(value).map_err(|()| CoordError::InvalidParameterType(var.parent))?;
+    if let Some(limit) = limit {
+        if !within_limit(parsed, limit) {
+            return Err(CoordError::ParameterExceedsLimit {
+                parameter: var.parent,
+                limit: limit.format(),
+            });
+        }
+    }
+    var.set(value)
+}
+
 /// A `Var` represents a configuration parameter of an arbitrary type.
 pub trait Var: fmt::Debug {
     /// Returns the name of the configuration parameter.
@@ -412,13 +858,15 @@ where
 }
 
 /// A `SessionVar` is the session value for a configuration parameter. If unset,
-/// the server default is used instead.
+/// the default configured for the server is used instead, if any, and
+/// otherwise the hardcoded default.
 #[derive(Debug)]
 pub struct SessionVar<V>
 where
     V: Value + fmt::Debug + ?Sized + 'static,
 {
     value: Option<V::Owned>,
+    default: Option<V::Owned>,
     parent: &'static ServerVar<V>,
 }
 
@@ -429,10 +877,22 @@ where
     pub fn new(parent: &'static ServerVar<V>) -> SessionVar<V> {
         SessionVar {
             value: None,
+            default: None,
             parent,
         }
     }
 
+    /// Overrides the hardcoded default with the default configured for the
+    /// server.
+    pub fn set_default(&mut self, default: V::Owned) {
+        self.default = Some(default);
+    }
+
+    /// Clears the session value, reverting to the default.
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+
     pub fn set(&mut self, s: &str) -> Result<(), CoordError> {
         match V::parse(s) {
             Ok(v) => {
@@ -446,6 +906,7 @@ where
     pub fn value(&self) -> &V {
         self.value
             .as_ref()
+            .or_else(|| self.default.as_ref())
             .map(|v| v.borrow())
             .unwrap_or(self.parent.value)
     }
@@ -514,6 +975,43 @@ impl Value for i32 {
     }
 }
 
+impl Value for u64 {
+    const TYPE_NAME: &'static str = "unsigned integer";
+
+    fn parse(s: &str) -> Result<u64, ()> {
+        s.parse().map_err(|_| ())
+    }
+
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Value for Duration {
+    const TYPE_NAME: &'static str = "duration";
+
+    /// Parses a duration like PostgreSQL, where a bare number is a number of
+    /// milliseconds, or like an interval, e.g., `30s` or `5min`.
+    fn parse(s: &str) -> Result<Duration, ()> {
+        let s = s.trim();
+        match s.parse::<u64>() {
+            Ok(ms) => Ok(Duration::from_millis(ms)),
+            Err(_) => repr::util::parse_duration(s).map_err(|_| ()),
+        }
+    }
+
+    fn format(&self) -> String {
+        let ms = self.as_millis();
+        if ms == 0 {
+            "0".into()
+        } else if ms % 1000 == 0 {
+            format!("{}s", ms / 1000)
+        } else {
+            format!("{}ms", ms)
+        }
+    }
+}
+
 impl Value for str {
     const TYPE_NAME: &'static str = "string";
 
@@ -538,3 +1036,27 @@ impl Value for [&str] {
         self.join(", ")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::server_version_num;
+
+    #[test]
+    fn test_server_version_num() {
+        let test_cases = vec![
+            ("9.5.0", Some(90500)),
+            ("9.6.21", Some(90621)),
+            ("13.4", Some(130004)),
+            ("13.4 (Debian 13.4-1.pgdg100+1)", Some(130004)),
+            ("13", None),
+            ("13.4.1", None),
+            ("9.6", None),
+            ("v13.4", None),
+            ("13.4-beta", None),
+            ("", None),
+        ];
+        for (version, expected) in test_cases {
+            assert_eq!(server_version_num(version), expected, "{}", version);
+        }
+    }
+}
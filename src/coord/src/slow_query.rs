@@ -0,0 +1,263 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A log of statements whose execution exceeds a configurable threshold.
+//!
+//! Frontends call [`SlowQueryLog::start`] when they begin executing a
+//! statement and [`TimedStatement::finish`] once the last result has been
+//! delivered to the client. If a `TimedStatement` is dropped before it is
+//! finished, e.g. because the client disconnected mid-statement, the statement
+//! is logged as cancelled.
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_json::json;
+
+use ore::cast::CastFrom;
+use sql::ast::display::AstDisplay;
+use sql::ast::{Raw, Statement};
+use sql::parse::RedactionPolicy;
+
+use crate::session::Session;
+use crate::statement_labels::StatementLabels;
+
+/// The sentinel value of `Inner::threshold_nanos` that indicates that no
+/// statements are to be logged.
+const NO_THRESHOLD: u64 = u64::MAX;
+
+/// Configures a [`SlowQueryLog`].
+#[derive(Debug, Clone, Default)]
+pub struct SlowQueryLogConfig {
+    /// Statements that take at least this long to execute are logged.
+    ///
+    /// If `None`, no statements are logged until a threshold is installed via
+    /// [`SlowQueryLog::set_threshold`].
+    pub threshold: Option<Duration>,
+    /// How to redact the logged SQL.
+    pub redaction: RedactionPolicy,
+    /// A file to which to additionally append slow statements, one JSON
+    /// object per line.
+    pub file: Option<PathBuf>,
+}
+
+/// A log of slow statements.
+///
+/// The log can be cheaply cloned. All clones share the same threshold.
+#[derive(Debug, Clone)]
+pub struct SlowQueryLog {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    threshold_nanos: AtomicU64,
+    redaction: RedactionPolicy,
+    file: Option<Mutex<File>>,
+}
+
+impl Default for SlowQueryLog {
+    /// Constructs a slow query log that does not log any statements until a
+    /// threshold is installed.
+    fn default() -> SlowQueryLog {
+        SlowQueryLog::new(SlowQueryLogConfig::default()).expect("no file to open")
+    }
+}
+
+impl SlowQueryLog {
+    /// Constructs a new slow query log.
+    pub fn new(config: SlowQueryLogConfig) -> Result<SlowQueryLog, anyhow::Error> {
+        let file = match config.file {
+            None => None,
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).with_context(|| {
+                        format!("creating slow query log directory: {}", parent.display())
+                    })?;
+                }
+                let file = fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .with_context(|| format!("opening slow query log: {}", path.display()))?;
+                Some(Mutex::new(file))
+            }
+        };
+        let log = SlowQueryLog {
+            inner: Arc::new(Inner {
+                threshold_nanos: AtomicU64::new(NO_THRESHOLD),
+                redaction: config.redaction,
+                file,
+            }),
+        };
+        log.set_threshold(config.threshold);
+        Ok(log)
+    }
+
+    /// Returns the current threshold, if any.
+    pub fn threshold(&self) -> Option<Duration> {
+        match self.inner.threshold_nanos.load(Ordering::Relaxed) {
+            NO_THRESHOLD => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Installs a new threshold.
+    ///
+    /// The new threshold applies to any statement that finishes after the
+    /// call, including those that are already running.
+    pub fn set_threshold(&self, threshold: Option<Duration>) {
+        let nanos = match threshold {
+            None => NO_THRESHOLD,
+            Some(threshold) => u64::try_from(threshold.as_nanos()).unwrap_or(NO_THRESHOLD - 1),
+        };
+        self.inner.threshold_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the SQL of `stmt`, redacted as the log redacts the SQL of slow
+    /// statements.
+    pub fn redact(&self, stmt: &Statement<Raw>) -> String {
+        self.inner.redaction.redact(&stmt.to_ast_string())
+    }
+
+    /// Starts timing the execution of `stmt`, which carries `labels`, in
+    /// `session`.
+    ///
+    /// Returns `None` if no threshold is installed, to avoid any overhead
+    /// when the slow query log is disabled.
+    pub fn start(
+        &self,
+        stmt: &Statement<Raw>,
+        session: &Session,
+        labels: &StatementLabels,
+    ) -> Option<TimedStatement> {
+        self.threshold()?;
+        Some(TimedStatement {
+            log: self.clone(),
+            stmt: stmt.clone(),
+            labels: labels.clone(),
+            user: session.user().into(),
+            application_name: session.vars().application_name().into(),
+            conn_id: session.conn_id(),
+            start: Instant::now(),
+            rows: 0,
+            failed: false,
+            finished: false,
+        })
+    }
+
+    fn record(&self, stmt: &TimedStatement, status: &str) {
+        let threshold = match self.threshold() {
+            None => return,
+            Some(threshold) => threshold,
+        };
+        let duration = stmt.start.elapsed();
+        if duration < threshold {
+            return;
+        }
+        let sql = self.redact(&stmt.stmt);
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        tracing::info!(
+            target: "slow_query",
+            conn_id = stmt.conn_id,
+            user = %stmt.user,
+            application_name = %stmt.application_name,
+            duration_ms,
+            rows = u64::cast_from(stmt.rows),
+            status,
+            labels = %stmt.labels,
+            sql = %sql,
+            "slow statement on connection {} took {:.3}ms: {}",
+            stmt.conn_id,
+            duration_ms,
+            sql,
+        );
+        if let Some(file) = &self.inner.file {
+            let mut line = json!({
+                "conn_id": stmt.conn_id,
+                "user": stmt.user,
+                "application_name": stmt.application_name,
+                "duration_ms": duration_ms,
+                "rows": stmt.rows,
+                "status": status,
+                "labels": stmt.labels,
+                "sql": sql,
+            })
+            .to_string();
+            line.push('\n');
+            let mut file = file.lock().expect("lock poisoned");
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                log::warn!("error writing slow query log: {}", e);
+            }
+        }
+    }
+}
+
+/// A statement whose execution is being timed by a [`SlowQueryLog`].
+#[derive(Debug)]
+pub struct TimedStatement {
+    log: SlowQueryLog,
+    stmt: Statement<Raw>,
+    labels: StatementLabels,
+    user: String,
+    application_name: String,
+    conn_id: u32,
+    start: Instant,
+    rows: usize,
+    failed: bool,
+    finished: bool,
+}
+
+impl TimedStatement {
+    /// Records that `n` more rows were returned to the client.
+    pub fn add_rows(&mut self, n: usize) {
+        self.rows += n;
+    }
+
+    /// Records that the statement failed.
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// Stops timing the statement, logging it if it was slow.
+    pub fn finish(mut self) {
+        self.finished = true;
+        let status = if self.failed { "error" } else { "success" };
+        self.log.record(&self, status);
+    }
+}
+
+impl Drop for TimedStatement {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.log.record(self, "cancelled");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let log = SlowQueryLog::default();
+        assert_eq!(log.threshold(), None);
+        log.set_threshold(Some(Duration::from_millis(500)));
+        assert_eq!(log.threshold(), Some(Duration::from_millis(500)));
+        log.set_threshold(None);
+        assert_eq!(log.threshold(), None);
+    }
+}
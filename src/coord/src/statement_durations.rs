@@ -0,0 +1,134 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A histogram of how long statements take to execute.
+//!
+//! Frontends call [`StatementDurations::start`] when they begin executing a
+//! statement, and drop the returned [`StatementDuration`] once the last
+//! result has been delivered to the client. Statements are labeled by a
+//! coarse [`StatementClass`], never by their text, so that the number of
+//! time series does not grow with the variety of statements. Statements are
+//! additionally labeled by the values of the [`StatementLabels`] whose keys
+//! the server allows.
+
+use std::time::Instant;
+
+use ore::metrics::{Histogram, HistogramVec};
+use sql::ast::{CopyRelation, CopyStatement, Raw, Statement};
+
+use crate::statement_labels::StatementLabels;
+
+/// A coarse classification of statements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementClass {
+    /// Queries, via `SELECT` or `COPY (SELECT ...) TO`.
+    Select,
+    /// Writes via `INSERT` or `COPY ... FROM`.
+    Insert,
+    /// Statements that create, alter, or drop catalog objects.
+    Ddl,
+    /// Subscriptions, via `TAIL` or `COPY (TAIL ...) TO`.
+    Tail,
+    /// Any other statement.
+    Other,
+}
+
+impl StatementClass {
+    /// Classifies `stmt`.
+    pub fn of(stmt: &Statement<Raw>) -> StatementClass {
+        match stmt {
+            Statement::Select(_) => StatementClass::Select,
+            Statement::Insert(_) => StatementClass::Insert,
+            Statement::Tail(_) => StatementClass::Tail,
+            Statement::Copy(CopyStatement { relation, .. }) => match relation {
+                CopyRelation::Select(_) => StatementClass::Select,
+                CopyRelation::Tail(_) => StatementClass::Tail,
+                CopyRelation::Table { .. } => StatementClass::Insert,
+            },
+            Statement::CreateDatabase(_)
+            | Statement::CreateSchema(_)
+            | Statement::CreateSource(_)
+            | Statement::CreateSink(_)
+            | Statement::CreateView(_)
+            | Statement::CreateViews(_)
+            | Statement::CreateTable(_)
+            | Statement::CreateIndex(_)
+            | Statement::CreateType(_)
+            | Statement::CreateRole(_)
+            | Statement::AlterObjectRename(_)
+            | Statement::AlterIndexOptions(_)
+            | Statement::DropDatabase(_)
+            | Statement::DropObjects(_) => StatementClass::Ddl,
+            _ => StatementClass::Other,
+        }
+    }
+
+    /// Returns the label under which statements of this class are counted.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatementClass::Select => "select",
+            StatementClass::Insert => "insert",
+            StatementClass::Ddl => "ddl",
+            StatementClass::Tail => "tail",
+            StatementClass::Other => "other",
+        }
+    }
+}
+
+/// Records how long statements take to execute, by [`StatementClass`] and by
+/// the allowed statement labels.
+///
+/// Clones share the same histogram.
+#[derive(Debug, Clone)]
+pub struct StatementDurations {
+    histogram: HistogramVec,
+    label_keys: Vec<String>,
+}
+
+impl StatementDurations {
+    /// Constructs a recorder that observes durations into `histogram`, which
+    /// must have a `class` label followed by one label for each of
+    /// `label_keys`.
+    pub fn new(histogram: HistogramVec, label_keys: Vec<String>) -> StatementDurations {
+        StatementDurations {
+            histogram,
+            label_keys,
+        }
+    }
+
+    /// Starts timing the execution of `stmt`, which carries `labels`.
+    ///
+    /// Labels whose keys are not allowed are ignored. Allowed labels that
+    /// `labels` lacks are recorded as empty.
+    pub fn start(&self, stmt: &Statement<Raw>, labels: &StatementLabels) -> StatementDuration {
+        let mut values = vec![StatementClass::of(stmt).as_str()];
+        for key in &self.label_keys {
+            values.push(labels.get(key).unwrap_or(""));
+        }
+        StatementDuration {
+            histogram: self.histogram.with_label_values(&values),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// Times the execution of a statement, until dropped.
+///
+/// Created by [`StatementDurations::start`].
+#[derive(Debug)]
+pub struct StatementDuration {
+    histogram: Histogram,
+    start: Instant,
+}
+
+impl Drop for StatementDuration {
+    fn drop(&mut self) {
+        self.histogram.observe(self.start.elapsed().as_secs_f64());
+    }
+}
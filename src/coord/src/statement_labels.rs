@@ -0,0 +1,215 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Labels that clients attach to statements via a leading comment.
+//!
+//! A client can attribute a statement to, e.g., the job that issued it by
+//! starting the SQL with a comment of the form `/* key=value, ... */`:
+//!
+//! ```sql
+//! /* job=nightly-revenue, team=finance */ SELECT sum(amount) FROM orders
+//! ```
+//!
+//! The labels are attached to the statement's tracing span, its slow query log
+//! entry, and its statement duration metric, and are reported in the session
+//! listing while the statement executes. Comments that are not of this form
+//! are ignored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// The maximum number of labels that a comment may specify.
+const MAX_LABELS: usize = 16;
+
+/// The maximum length of a label value, in bytes.
+const MAX_VALUE_LEN: usize = 128;
+
+/// The labels attached to a statement.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct StatementLabels(BTreeMap<String, String>);
+
+impl StatementLabels {
+    /// Parses the labels from the leading comment of `sql`, if any.
+    ///
+    /// Returns no labels if `sql` does not start with a comment, or if the
+    /// comment is malformed. Keys must match `[a-zA-Z_][a-zA-Z0-9_]*`, and
+    /// values must be nonempty and may not contain commas, equals signs, or
+    /// whitespace.
+    pub fn parse(sql: &str) -> StatementLabels {
+        StatementLabels::parse_comment(sql).unwrap_or_default()
+    }
+
+    fn parse_comment(sql: &str) -> Option<StatementLabels> {
+        let comment = sql.trim_start().strip_prefix("/*")?;
+        let (comment, _) = comment.split_once("*/")?;
+        let mut labels = BTreeMap::new();
+        for pair in comment.split(',') {
+            let (key, value) = pair.split_once('=')?;
+            let (key, value) = (key.trim(), value.trim());
+            let mut chars = key.chars();
+            let valid_key = match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }
+                _ => false,
+            };
+            let valid_value = !value.is_empty()
+                && value.len() <= MAX_VALUE_LEN
+                && !value.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control());
+            if !valid_key || !valid_value || labels.len() == MAX_LABELS {
+                return None;
+            }
+            labels.insert(key.into(), value.into());
+        }
+        Some(StatementLabels(labels))
+    }
+
+    /// Reports whether there are no labels.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the value of the label named `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns an iterator over the labels, in order by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl fmt::Display for StatementLabels {
+    /// Formats the labels as they would appear in a comment, without the
+    /// comment delimiters.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tracks the labels of the statements that are executing, across all
+/// sessions.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct LabeledStatements {
+    inner: Arc<Mutex<HashMap<u32, StatementLabels>>>,
+}
+
+impl LabeledStatements {
+    /// Constructs a tracker in which no labeled statement is executing.
+    pub fn new() -> LabeledStatements {
+        LabeledStatements::default()
+    }
+
+    /// Records that a statement with `labels` is executing in the session
+    /// with connection ID `conn_id` until the returned guard is dropped.
+    ///
+    /// If `labels` is empty, nothing is recorded.
+    pub fn start(&self, conn_id: u32, labels: StatementLabels) -> LabeledStatement {
+        if labels.is_empty() {
+            return LabeledStatement {
+                statements: None,
+                conn_id,
+            };
+        }
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .insert(conn_id, labels);
+        LabeledStatement {
+            statements: Some(self.clone()),
+            conn_id,
+        }
+    }
+
+    /// Returns the labels of the statement that is executing in the session
+    /// with connection ID `conn_id`, if any.
+    pub fn get(&self, conn_id: u32) -> Option<StatementLabels> {
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .get(&conn_id)
+            .cloned()
+    }
+}
+
+/// Records that a labeled statement is executing, until dropped.
+///
+/// Created by [`LabeledStatements::start`].
+#[derive(Debug)]
+pub struct LabeledStatement {
+    statements: Option<LabeledStatements>,
+    conn_id: u32,
+}
+
+impl Drop for LabeledStatement {
+    fn drop(&mut self) {
+        if let Some(statements) = &self.statements {
+            statements
+                .inner
+                .lock()
+                .expect("lock poisoned")
+                .remove(&self.conn_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LabeledStatements, StatementLabels};
+
+    #[test]
+    fn test_parse() {
+        let labels = StatementLabels::parse("  /* job=nightly-revenue, team = finance */ SELECT 1");
+        assert_eq!(labels.to_string(), "job=nightly-revenue, team=finance");
+        assert_eq!(labels.get("job"), Some("nightly-revenue"));
+
+        // Malformed or misplaced comments are ignored.
+        for sql in &[
+            "SELECT 1",
+            "SELECT 1 /* job=a */",
+            "/* job=a SELECT 1",
+            "/* just a comment */ SELECT 1",
+            "/* job=a, */ SELECT 1",
+            "/* job=a b */ SELECT 1",
+            "/* job=a=b */ SELECT 1",
+            "/* 1job=a */ SELECT 1",
+            "/* job= */ SELECT 1",
+        ] {
+            assert!(StatementLabels::parse(sql).is_empty(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_labeled_statements() {
+        let statements = LabeledStatements::new();
+        let labels = StatementLabels::parse("/* job=a */ SELECT 1");
+
+        let guard = statements.start(1, labels.clone());
+        let unlabeled = statements.start(2, StatementLabels::default());
+        assert_eq!(statements.get(1), Some(labels));
+        assert_eq!(statements.get(2), None);
+
+        drop(guard);
+        drop(unlabeled);
+        assert_eq!(statements.get(1), None);
+    }
+}
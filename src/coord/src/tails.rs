@@ -0,0 +1,321 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Tracking of the `TAIL` statements that are streaming results to clients.
+//!
+//! Frontends call [`ActiveTails::start`] when they begin streaming the
+//! results of a `TAIL` to a client, and drop the returned [`ActiveTail`] once
+//! the stream ends for any reason, whether the client closed it, the statement
+//! was canceled, or the client vanished.
+//!
+//! The updates that a `TAIL` has produced but its client has not yet received
+//! are held in a [`TailBuffer`], which the coordinator creates with
+//! [`ActiveTails::buffer`], and which limits how far the client may fall
+//! behind.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::{ready, Stream};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use dataflow_types::{TailBuffer, TailBufferPolicy};
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntGauge, UIntGaugeVec};
+use repr::Row;
+
+use crate::error::CoordError;
+
+/// The protocols over which results can be streamed.
+const PROTOCOLS: &[&str] = &["pgwire", "http"];
+
+/// Tracks the `TAIL` statements that are streaming results, across all
+/// sessions.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct ActiveTails {
+    inner: Arc<Mutex<HashMap<u32, Tail>>>,
+    active_tails: UIntGaugeVec,
+    buffered_bytes: UIntGauge,
+}
+
+#[derive(Debug)]
+struct Tail {
+    protocol: &'static str,
+    buffer: TailBuffer,
+    start: Instant,
+    /// Whether the tail has been reported as long running.
+    reported: bool,
+}
+
+impl ActiveTails {
+    /// Constructs a tracker in which no tail is streaming, registering its
+    /// metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> ActiveTails {
+        let active_tails: UIntGaugeVec = registry.register(metric!(
+            name: "mz_server_active_tails",
+            help: "number of TAIL statements streaming results, by protocol",
+            var_labels: ["protocol"],
+        ));
+        // Export every protocol, even those over which no tail has streamed.
+        for protocol in PROTOCOLS {
+            active_tails.with_label_values(&[protocol]);
+        }
+        let buffered_bytes = registry.register(metric!(
+            name: "mz_server_tail_buffered_bytes",
+            help: "number of bytes of TAIL updates awaiting delivery to clients",
+        ));
+        ActiveTails {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            active_tails,
+            buffered_bytes,
+        }
+    }
+
+    /// Constructs an empty buffer for the updates of a `TAIL`, which holds at
+    /// most `limit` bytes and reports its occupancy to the tracker's metrics.
+    pub fn buffer(&self, limit: usize, policy: TailBufferPolicy) -> TailBuffer {
+        TailBuffer::new(limit, policy, self.buffered_bytes.clone())
+    }
+
+    /// Records that the session with connection ID `conn_id` is streaming the
+    /// results of a `TAIL` from `buffer` over `protocol` until the returned
+    /// guard is dropped.
+    pub fn start(&self, conn_id: u32, protocol: &'static str, buffer: TailBuffer) -> ActiveTail {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        let tail = Tail {
+            protocol,
+            buffer,
+            start: Instant::now(),
+            reported: false,
+        };
+        if let Some(old) = inner.insert(conn_id, tail) {
+            // A session executes at most one statement at a time, so the old
+            // tail's guard must have leaked. Stop counting it.
+            self.active_tails.with_label_values(&[old.protocol]).dec();
+        }
+        self.active_tails.with_label_values(&[protocol]).inc();
+        ActiveTail {
+            tails: self.clone(),
+            conn_id,
+        }
+    }
+
+    /// Reports whether the session with connection ID `conn_id` is streaming
+    /// the results of a `TAIL`.
+    pub fn is_tailing(&self, conn_id: u32) -> bool {
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .contains_key(&conn_id)
+    }
+
+    /// Describes the buffer of the `TAIL` that the session with connection
+    /// ID `conn_id` is streaming, if any.
+    pub fn buffer_info(&self, conn_id: u32) -> Option<TailBufferInfo> {
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .get(&conn_id)
+            .map(|tail| TailBufferInfo {
+                buffered_bytes: tail.buffer.buffered_bytes(),
+                limit: tail.buffer.limit(),
+                policy: tail.buffer.policy(),
+            })
+    }
+
+    /// Logs each tail that has been streaming for at least `threshold`, once
+    /// per tail.
+    pub fn log_long_running(&self, threshold: Duration) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        for (conn_id, tail) in inner.iter_mut() {
+            let duration = tail.start.elapsed();
+            if tail.reported || duration < threshold {
+                continue;
+            }
+            tail.reported = true;
+            tracing::warn!(
+                target: "long_running_tail",
+                conn_id,
+                protocol = tail.protocol,
+                duration_s = duration.as_secs(),
+                "TAIL on connection {} has been streaming over {} for {:?}",
+                conn_id,
+                tail.protocol,
+                duration,
+            );
+        }
+    }
+}
+
+/// Describes the buffer of a `TAIL`, as reported in the session listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct TailBufferInfo {
+    /// The number of bytes of updates awaiting delivery to the client.
+    pub buffered_bytes: usize,
+    /// The maximum number of bytes that may await delivery.
+    pub limit: usize,
+    /// What the `TAIL` does when the buffer is full.
+    pub policy: TailBufferPolicy,
+}
+
+/// Receives the updates that a `TAIL` streams, releasing each batch from the
+/// tail's [`TailBuffer`] as it is received.
+///
+/// If the buffer overflows, the stream ends with
+/// [`CoordError::TailBufferExceeded`] once the updates that were admitted to
+/// the buffer have been received.
+#[derive(Debug)]
+pub struct TailReceiver {
+    rx: mpsc::UnboundedReceiver<Vec<Row>>,
+    buffer: TailBuffer,
+    done: bool,
+}
+
+impl TailReceiver {
+    /// Constructs a receiver for the updates that the tail sink sends to
+    /// `rx` after admitting them to `buffer`.
+    pub fn new(rx: mpsc::UnboundedReceiver<Vec<Row>>, buffer: TailBuffer) -> TailReceiver {
+        TailReceiver {
+            rx,
+            buffer,
+            done: false,
+        }
+    }
+
+    /// Returns the buffer from which the receiver takes updates.
+    pub fn buffer(&self) -> &TailBuffer {
+        &self.buffer
+    }
+}
+
+impl Stream for TailReceiver {
+    type Item = Result<Vec<Row>, CoordError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match ready!(self.rx.poll_recv(cx)) {
+            // The sink sends an empty batch only to report an overflow.
+            Some(rows) if rows.is_empty() && self.buffer.overflowed() => {
+                self.done = true;
+                Poll::Ready(Some(Err(CoordError::TailBufferExceeded {
+                    limit: self.buffer.limit(),
+                })))
+            }
+            Some(rows) => {
+                self.buffer.release(TailBuffer::batch_size(&rows));
+                Poll::Ready(Some(Ok(rows)))
+            }
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// Records that a session is streaming the results of a `TAIL`, until
+/// dropped.
+///
+/// Created by [`ActiveTails::start`].
+#[derive(Debug)]
+pub struct ActiveTail {
+    tails: ActiveTails,
+    conn_id: u32,
+}
+
+impl Drop for ActiveTail {
+    fn drop(&mut self) {
+        let mut inner = self.tails.inner.lock().expect("lock poisoned");
+        if let Some(tail) = inner.remove(&self.conn_id) {
+            self.tails
+                .active_tails
+                .with_label_values(&[tail.protocol])
+                .dec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{FutureExt, StreamExt};
+    use tokio::sync::mpsc;
+
+    use dataflow_types::{TailBuffer, TailBufferPolicy};
+    use ore::cast::CastFrom;
+    use ore::metrics::MetricsRegistry;
+    use repr::{Datum, Row};
+
+    use super::{ActiveTails, TailReceiver};
+    use crate::error::CoordError;
+
+    #[test]
+    fn test_active_tails() {
+        let tails = ActiveTails::register_into(&MetricsRegistry::new());
+        let count = |protocol| tails.active_tails.with_label_values(&[protocol]).get();
+        let buffer = || tails.buffer(1 << 20, TailBufferPolicy::Block);
+
+        let a = tails.start(1, "pgwire", buffer());
+        let b = tails.start(2, "pgwire", buffer());
+        assert_eq!(count("pgwire"), 2);
+        assert_eq!(count("http"), 0);
+        assert!(tails.is_tailing(1));
+        assert!(!tails.is_tailing(3));
+
+        tails.log_long_running(Duration::from_secs(0));
+        assert!(tails.inner.lock().unwrap().values().all(|t| t.reported));
+
+        drop(a);
+        assert_eq!(count("pgwire"), 1);
+        assert!(!tails.is_tailing(1));
+        drop(b);
+        assert_eq!(count("pgwire"), 0);
+    }
+
+    #[test]
+    fn test_tail_receiver() {
+        let tails = ActiveTails::register_into(&MetricsRegistry::new());
+        let buffer = tails.buffer(1, TailBufferPolicy::Error);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut rx = TailReceiver::new(rx, buffer.clone());
+        let _tail = tails.start(1, "pgwire", buffer.clone());
+
+        let rows = vec![Row::pack_slice(&[Datum::Int64(1)])];
+        let size = TailBuffer::batch_size(&rows);
+
+        // A batch is admitted to an empty buffer even if it exceeds the
+        // limit, but nothing more is admitted until it is received.
+        assert!(buffer.admit(size));
+        tx.send(rows.clone()).unwrap();
+        assert!(!buffer.admit(size));
+        buffer.overflow();
+        tx.send(vec![]).unwrap();
+        assert_eq!(tails.buffered_bytes.get(), u64::cast_from(size));
+        assert_eq!(tails.buffer_info(1).unwrap().buffered_bytes, size);
+
+        // The admitted batch is received before the overflow is reported.
+        match rx.next().now_or_never() {
+            Some(Some(Ok(received))) => assert_eq!(received, rows),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(buffer.buffered_bytes(), 0);
+        assert_eq!(tails.buffered_bytes.get(), 0);
+        match rx.next().now_or_never() {
+            Some(Some(Err(CoordError::TailBufferExceeded { limit: 1 }))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(matches!(rx.next().now_or_never(), Some(None)));
+    }
+}
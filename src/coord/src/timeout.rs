@@ -0,0 +1,115 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Statement timeouts.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time;
+
+use crate::command::Command;
+use crate::lanes::LaneSender;
+
+/// Cancels the statement running in a session if it runs for too long.
+///
+/// When the timer fires, it cancels the statement through the coordinator, as
+/// if by a cancellation request, so that any dataflow work on behalf of the
+/// statement stops too. Dropping the timer disarms it.
+#[derive(Debug)]
+pub struct StatementTimer {
+    fired: Arc<AtomicBool>,
+    _disarm: oneshot::Sender<()>,
+}
+
+impl StatementTimer {
+    /// Arms a timer that cancels the statement running on the connection
+    /// with ID `conn_id` once `timeout` elapses.
+    pub(crate) fn arm(
+        timeout: Duration,
+        conn_id: u32,
+        cmd_tx: LaneSender<Command>,
+    ) -> StatementTimer {
+        let fired = Arc::new(AtomicBool::new(false));
+        let (disarm_tx, disarm_rx) = oneshot::channel();
+        tokio::spawn({
+            let fired = Arc::clone(&fired);
+            async move {
+                tokio::select! {
+                    _ = time::sleep(timeout) => {
+                        fired.store(true, Ordering::SeqCst);
+                        // The outcome is of no interest: if the connection
+                        // has gone away, there is nothing to cancel.
+                        let (tx, _) = oneshot::channel();
+                        let _ = cmd_tx.send(Command::CancelRequest {
+                            conn_id,
+                            secret_key: None,
+                            tx,
+                        });
+                    }
+                    _ = disarm_rx => (),
+                }
+            }
+        });
+        StatementTimer {
+            fired,
+            _disarm: disarm_tx,
+        }
+    }
+
+    /// Reports whether the timer has fired, i.e., whether the statement was
+    /// canceled because it timed out.
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::stream::StreamExt;
+    use tokio::time;
+
+    use ore::metrics::MetricsRegistry;
+
+    use crate::command::Command;
+    use crate::lanes;
+
+    use super::StatementTimer;
+
+    #[tokio::test]
+    async fn test_timer_fires() {
+        let (_, cmd_tx, mut cmd_rx) = lanes::channel(&MetricsRegistry::new());
+        let timer = StatementTimer::arm(Duration::from_millis(50), 7, cmd_tx);
+        match cmd_rx.next().await {
+            Some(Command::CancelRequest {
+                conn_id: 7,
+                secret_key: None,
+                ..
+            }) => (),
+            _ => panic!("timer did not cancel the statement"),
+        }
+        assert!(timer.fired());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_timer_does_not_fire() {
+        let (_, cmd_tx, mut cmd_rx) = lanes::channel(&MetricsRegistry::new());
+        let timer = StatementTimer::arm(Duration::from_millis(50), 7, cmd_tx);
+        assert!(!timer.fired());
+        drop(timer);
+        // Once disarmed, the timer's task exits, dropping its sender, so the
+        // channel closes without ever receiving a command.
+        let res = time::timeout(Duration::from_secs(5), cmd_rx.next()).await;
+        assert!(matches!(res, Ok(None)), "disarmed timer fired");
+    }
+}
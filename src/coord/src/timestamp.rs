@@ -40,6 +40,7 @@ use expr::{GlobalId, PartitionId};
 use ore::collections::CollectionExt;
 
 use crate::coord;
+use crate::health::SourceReports;
 
 const CONF_DENYLIST: &'static [&'static str] = &["statistics.interval.ms"];
 
@@ -251,6 +252,10 @@ pub struct Timestamper {
     /// Frequency at which thread should run
     timestamp_frequency: Duration,
 
+    /// The errors and upstream offsets of each source, as reported to the
+    /// coordinator.
+    reports: SourceReports,
+
     /// Metrics that the timestamper reports.
     metrics: Metrics,
 }
@@ -481,6 +486,7 @@ impl Timestamper {
         frequency: Duration,
         tx: mpsc::UnboundedSender<coord::Message>,
         rx: std::sync::mpsc::Receiver<TimestampMessage>,
+        reports: SourceReports,
         registry: &MetricsRegistry,
     ) -> Self {
         info!(
@@ -494,6 +500,7 @@ impl Timestamper {
             tx,
             rx,
             timestamp_frequency: frequency,
+            reports,
             metrics: Metrics::register_with(registry),
         }
     }
@@ -544,6 +551,7 @@ impl Timestamper {
                         && !self.byo_sources.contains_key(&source_id)
                     {
                         // Did not know about source, must update
+                        self.reports.register(source_id);
                         match cons {
                             Consistency::RealTime => {
                                 info!(
@@ -574,6 +582,7 @@ impl Timestamper {
                 }
                 TimestampMessage::Drop(id) => {
                     self.drop_source(id);
+                    self.reports.remove(id);
                 }
                 TimestampMessage::Shutdown => {
                     // First, let's remove all of the threads consuming metadata
@@ -623,6 +632,10 @@ impl Timestamper {
                     "Failed to correctly parse messages from source {}; {}",
                     id, err
                 );
+                self.reports.record_error(
+                    *id,
+                    format!("failed to parse consistency messages: {}", err),
+                );
                 invalid_byo_sources.push(*id);
             }
         }
@@ -706,6 +719,8 @@ impl Timestamper {
             Ok(consumer) => consumer,
             Err(e) => {
                 error!("Failed to create Kafka Consumer {}", e);
+                self.reports
+                    .record_error(id, format!("failed to create Kafka consumer: {}", e));
                 return None;
             }
         };
@@ -736,12 +751,14 @@ impl Timestamper {
             .spawn({
                 let connector = connector.clone();
                 let metrics = self.metrics.clone();
+                let reports = self.reports.clone();
                 move || {
                     rt_kafka_metadata_fetch_loop(
                         connector,
                         consumer,
                         metadata_refresh_frequency,
                         &metrics,
+                        &reports,
                     )
                 }
             })
@@ -864,6 +881,14 @@ impl Timestamper {
                             "Consistency topic should contain a single partition. Contains {}",
                             partitions.len(),
                         );
+                        self.reports.record_error(
+                            id,
+                            format!(
+                                "consistency topic {} contains {} partitions, not one",
+                                timestamp_topic,
+                                partitions.len()
+                            ),
+                        );
                         None
                     }
                     Err(e) => {
@@ -878,6 +903,8 @@ impl Timestamper {
             }
             Err(e) => {
                 error!("Could not create a Kafka consumer. Error: {}", e);
+                self.reports
+                    .record_error(id, format!("failed to create Kafka consumer: {}", e));
                 None
             }
         }
@@ -889,6 +916,7 @@ fn rt_kafka_metadata_fetch_loop(
     consumer: BaseConsumer,
     wait: Duration,
     metrics: &Metrics,
+    reports: &SourceReports,
 ) {
     debug!(
         "Starting realtime Kafka thread for {} (source {})",
@@ -941,6 +969,10 @@ fn rt_kafka_metadata_fetch_loop(
                     "Unable to fetch kafka metadata for topic {} (source {}): {}",
                     c.topic, e, c.id
                 );
+                reports.record_error(
+                    c.id,
+                    format!("unable to fetch metadata for topic {}: {}", c.topic, e),
+                );
             }
         }
 
@@ -958,12 +990,20 @@ fn rt_kafka_metadata_fetch_loop(
                         &pid.to_string(),
                     ]);
                     max_offset.set(high);
+                    reports.record_upstream_offset(c.id, pid.to_string(), high);
                 }
                 Err(e) => {
                     error!(
                         "Unable to fetch Kafka watermarks for topic {} [{}] ({}): {}",
                         c.topic, pid, c.id, e
                     );
+                    reports.record_error(
+                        c.id,
+                        format!(
+                            "unable to fetch watermarks for topic {} partition {}: {}",
+                            c.topic, pid, e
+                        ),
+                    );
                 }
             }
         }
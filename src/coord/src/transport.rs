@@ -0,0 +1,163 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! The transports over which clients connect.
+//!
+//! Each client connection arrives either in plaintext or encrypted with TLS.
+//! The transport of every pgwire session is recorded on the session, so that
+//! it can be listed alongside the session, and the connections open over each
+//! transport are counted in the `mz_server_connections_active` metric, so
+//! that operators can verify that no client connects in plaintext.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde::Serialize;
+
+use ore::metrics::{UIntGauge, UIntGaugeVec};
+
+/// The minimum interval between warnings about plaintext sessions.
+const PLAINTEXT_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The transport over which a client connected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Transport {
+    /// An unencrypted TCP connection.
+    Plaintext,
+    /// A TCP connection encrypted with TLS.
+    Tls {
+        /// The negotiated protocol version, like `TLSv1.3`.
+        version: String,
+        /// The negotiated cipher suite, like `TLS_AES_256_GCM_SHA384`.
+        cipher: String,
+    },
+}
+
+impl Transport {
+    /// Returns the name of the transport, as reported in metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Plaintext => "plaintext",
+            Transport::Tls { .. } => "tls",
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Plaintext => f.write_str("plaintext"),
+            Transport::Tls { version, cipher } => write!(f, "tls ({}, {})", version, cipher),
+        }
+    }
+}
+
+/// Counts the client connections open over each transport, and optionally
+/// warns about plaintext sessions.
+///
+/// Clones share the same counts.
+#[derive(Debug, Clone)]
+pub struct TransportMonitor {
+    active: UIntGaugeVec,
+    warning: Option<Arc<PlaintextWarning>>,
+}
+
+/// Warns about plaintext sessions from other hosts, at most once per
+/// [`PLAINTEXT_WARNING_INTERVAL`].
+#[derive(Debug)]
+struct PlaintextWarning {
+    /// When a plaintext session was last reported, and how many have been
+    /// established since without being reported.
+    log_state: Mutex<(Option<Instant>, u64)>,
+}
+
+impl PlaintextWarning {
+    fn warn(&self, peer: Option<SocketAddr>, user: &str) {
+        let mut log_state = self.log_state.lock().expect("lock poisoned");
+        let (last_logged, unlogged) = &mut *log_state;
+        let now = Instant::now();
+        match last_logged {
+            Some(last_logged) if now.duration_since(*last_logged) < PLAINTEXT_WARNING_INTERVAL => {
+                *unlogged += 1;
+            }
+            _ => {
+                let peer = match peer {
+                    Some(peer) => peer.to_string(),
+                    None => "an unknown address".into(),
+                };
+                warn!(
+                    "user {} connected from {} without TLS encryption; \
+                     {} other plaintext sessions were established since this was last reported",
+                    user, peer, unlogged
+                );
+                *last_logged = Some(now);
+                *unlogged = 0;
+            }
+        }
+    }
+}
+
+impl TransportMonitor {
+    /// Constructs a monitor that reports the number of open connections over
+    /// each transport to `active`, which must have a `transport` label.
+    ///
+    /// If `warn_on_plaintext` is set, the monitor warns about plaintext
+    /// sessions that do not originate from a loopback address.
+    pub fn new(active: UIntGaugeVec, warn_on_plaintext: bool) -> TransportMonitor {
+        for transport in &["plaintext", "tls"] {
+            active.with_label_values(&[transport]);
+        }
+        TransportMonitor {
+            active,
+            warning: match warn_on_plaintext {
+                false => None,
+                true => Some(Arc::new(PlaintextWarning {
+                    log_state: Mutex::new((None, 0)),
+                })),
+            },
+        }
+    }
+
+    /// Records that a connection opened over `transport`. The connection is
+    /// counted until the returned guard is dropped.
+    pub fn connected(&self, transport: &Transport) -> TransportGuard {
+        let gauge = self.active.with_label_values(&[transport.as_str()]);
+        gauge.inc();
+        TransportGuard { gauge }
+    }
+
+    /// Records that `user` established a session over `transport` from
+    /// `peer`, warning if the session is in plaintext and does not originate
+    /// from a loopback address.
+    pub fn session_established(&self, transport: &Transport, peer: Option<SocketAddr>, user: &str) {
+        if let (Some(warning), Transport::Plaintext) = (&self.warning, transport) {
+            if !matches!(peer, Some(peer) if peer.ip().is_loopback()) {
+                warning.warn(peer, user);
+            }
+        }
+    }
+}
+
+/// Counts a connection toward its transport until dropped.
+///
+/// Constructed by [`TransportMonitor::connected`].
+#[derive(Debug)]
+pub struct TransportGuard {
+    gauge: UIntGauge,
+}
+
+impl Drop for TransportGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
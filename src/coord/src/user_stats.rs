@@ -0,0 +1,357 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Aggregate statistics of the sessions of each user.
+//!
+//! Frontends record the statements that each session executes and the rows
+//! and bytes that it sends on the [`UserSession`] that [`UserStats::session`]
+//! returns. The statistics accumulate per user, not per session, so they
+//! survive the churn of sessions and reset only when the process restarts.
+//!
+//! User names are chosen by clients, so the users that are tracked are
+//! bounded: users first seen once the limit is reached, as well as any user
+//! named [`OTHER_USER`], are counted together under that name.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use serde::Serialize;
+
+use ore::cast::CastFrom;
+use ore::metric;
+use ore::metrics::{
+    Counter, CounterVec, MetricsRegistry, UIntCounter, UIntCounterVec, UIntGauge, UIntGaugeVec,
+};
+
+/// The default maximum number of users whose statistics are tracked
+/// individually.
+pub const DEFAULT_MAX_TRACKED_USERS: usize = 100;
+
+/// The name under which the statistics of users that are not tracked
+/// individually are counted.
+pub const OTHER_USER: &str = "other";
+
+/// Tracks the aggregate statistics of the sessions of each user.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct UserStats {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    max_users: usize,
+    reset_at: SystemTime,
+    users: Mutex<HashMap<String, Arc<UserCounters>>>,
+    other: Arc<UserCounters>,
+    metrics: UserMetrics,
+}
+
+#[derive(Debug)]
+struct UserMetrics {
+    statements: UIntCounterVec,
+    rows_returned: UIntCounterVec,
+    bytes_sent: UIntCounterVec,
+    statement_seconds: CounterVec,
+    sessions: UIntGaugeVec,
+}
+
+#[derive(Debug)]
+struct UserCounters {
+    user: String,
+    statements: UIntCounter,
+    rows_returned: UIntCounter,
+    bytes_sent: UIntCounter,
+    statement_seconds: Counter,
+    sessions: UIntGauge,
+}
+
+impl UserMetrics {
+    fn counters(&self, user: &str) -> UserCounters {
+        UserCounters {
+            user: user.into(),
+            statements: self.statements.with_label_values(&[user]),
+            rows_returned: self.rows_returned.with_label_values(&[user]),
+            bytes_sent: self.bytes_sent.with_label_values(&[user]),
+            statement_seconds: self.statement_seconds.with_label_values(&[user]),
+            sessions: self.sessions.with_label_values(&[user]),
+        }
+    }
+}
+
+impl UserCounters {
+    fn summary(&self) -> UserSummary {
+        UserSummary {
+            user: self.user.clone(),
+            statements: self.statements.get(),
+            rows_returned: self.rows_returned.get(),
+            bytes_sent: self.bytes_sent.get(),
+            statement_seconds: self.statement_seconds.get(),
+            sessions: self.sessions.get(),
+        }
+    }
+}
+
+/// A summary of the statistics of every user, as returned by
+/// [`UserStats::summary`].
+#[derive(Debug, Clone)]
+pub struct UserStatsSummary {
+    /// When the statistics were last reset, which is when the process
+    /// started.
+    pub reset_at: SystemTime,
+    /// The maximum number of users whose statistics are tracked
+    /// individually.
+    pub max_users: usize,
+    /// The users whose statistics are tracked individually, in descending
+    /// order of the time that their statements took.
+    pub users: Vec<UserSummary>,
+    /// The statistics of all other users, counted together.
+    pub other: UserSummary,
+}
+
+/// The statistics of one user, or of the users counted together under
+/// [`OTHER_USER`].
+#[derive(Debug, Clone, Serialize)]
+pub struct UserSummary {
+    /// The name of the user.
+    pub user: String,
+    /// The number of statements that the user's sessions have executed.
+    pub statements: u64,
+    /// The number of rows returned to the user's sessions.
+    pub rows_returned: u64,
+    /// The number of bytes sent to the user's sessions.
+    pub bytes_sent: u64,
+    /// The total time that the user's statements took, in seconds.
+    pub statement_seconds: f64,
+    /// The number of sessions of the user that are active.
+    pub sessions: u64,
+}
+
+impl UserStats {
+    /// Constructs a tracker that tracks the statistics of at most
+    /// `max_users` users individually, registering its metrics into
+    /// `registry`.
+    pub fn register_into(registry: &MetricsRegistry, max_users: usize) -> UserStats {
+        let metrics = UserMetrics {
+            statements: registry.register(metric!(
+                name: "mz_user_statements_total",
+                help: "number of statements executed, by user",
+                var_labels: ["user"],
+            )),
+            rows_returned: registry.register(metric!(
+                name: "mz_user_rows_returned_total",
+                help: "number of rows returned to clients, by user",
+                var_labels: ["user"],
+            )),
+            bytes_sent: registry.register(metric!(
+                name: "mz_user_bytes_sent_total",
+                help: "number of bytes sent to clients, by user",
+                var_labels: ["user"],
+            )),
+            statement_seconds: registry.register(metric!(
+                name: "mz_user_statement_seconds_total",
+                help: "total time taken by executed statements, by user",
+                var_labels: ["user"],
+            )),
+            sessions: registry.register(metric!(
+                name: "mz_user_sessions",
+                help: "number of active sessions, by user",
+                var_labels: ["user"],
+            )),
+        };
+        UserStats {
+            inner: Arc::new(Inner {
+                max_users,
+                reset_at: SystemTime::now(),
+                users: Mutex::new(HashMap::new()),
+                other: Arc::new(metrics.counters(OTHER_USER)),
+                metrics,
+            }),
+        }
+    }
+
+    /// Records that a session of `user` is active until the returned guard
+    /// is dropped.
+    pub fn session(&self, user: &str) -> UserSession {
+        let counters = self.counters(user);
+        counters.sessions.inc();
+        UserSession { counters }
+    }
+
+    fn counters(&self, user: &str) -> Arc<UserCounters> {
+        let inner = &self.inner;
+        if user == OTHER_USER {
+            return Arc::clone(&inner.other);
+        }
+        let mut users = inner.users.lock().expect("lock poisoned");
+        if let Some(counters) = users.get(user) {
+            return Arc::clone(counters);
+        }
+        if users.len() >= inner.max_users {
+            return Arc::clone(&inner.other);
+        }
+        let counters = Arc::new(inner.metrics.counters(user));
+        users.insert(user.into(), Arc::clone(&counters));
+        counters
+    }
+
+    /// Summarizes the statistics of every user.
+    pub fn summary(&self) -> UserStatsSummary {
+        let inner = &self.inner;
+        let mut users: Vec<_> = inner
+            .users
+            .lock()
+            .expect("lock poisoned")
+            .values()
+            .map(|counters| counters.summary())
+            .collect();
+        users.sort_by(|a, b| {
+            b.statement_seconds
+                .partial_cmp(&a.statement_seconds)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.user.cmp(&b.user))
+        });
+        UserStatsSummary {
+            reset_at: inner.reset_at,
+            max_users: inner.max_users,
+            users,
+            other: inner.other.summary(),
+        }
+    }
+}
+
+/// A guard that records the statistics of a session, as returned by
+/// [`UserStats::session`].
+///
+/// The session stops counting as active when the guard is dropped.
+#[derive(Debug)]
+pub struct UserSession {
+    counters: Arc<UserCounters>,
+}
+
+impl UserSession {
+    /// Returns the name under which the session's statistics are counted,
+    /// which is [`OTHER_USER`] if its user is not tracked individually.
+    pub fn user(&self) -> &str {
+        &self.counters.user
+    }
+
+    /// Records that the session is executing a statement. The statement is
+    /// counted, along with the time it took, when the returned guard is
+    /// dropped.
+    pub fn start_statement(&self) -> UserStatement {
+        UserStatement {
+            counters: Arc::clone(&self.counters),
+            start: Instant::now(),
+        }
+    }
+
+    /// Records that `n` rows were returned to the session.
+    pub fn add_rows(&self, n: usize) {
+        self.counters.rows_returned.inc_by(u64::cast_from(n));
+    }
+
+    /// Records that `n` bytes were sent to the session.
+    pub fn add_bytes(&self, n: u64) {
+        self.counters.bytes_sent.inc_by(n);
+    }
+
+    /// Returns a handle that records bytes sent to the session, for use
+    /// where the session itself is not at hand, like a task that streams a
+    /// response.
+    pub fn bytes_sent(&self) -> UserBytesSent {
+        UserBytesSent {
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+/// Records bytes sent to a session, as returned by
+/// [`UserSession::bytes_sent`].
+#[derive(Debug, Clone)]
+pub struct UserBytesSent {
+    counters: Arc<UserCounters>,
+}
+
+impl UserBytesSent {
+    /// Records that `n` bytes were sent to the session.
+    pub fn add(&self, n: u64) {
+        self.counters.bytes_sent.inc_by(n);
+    }
+}
+
+impl Drop for UserSession {
+    fn drop(&mut self) {
+        self.counters.sessions.dec();
+    }
+}
+
+/// A guard that counts a statement toward its user's statistics when
+/// dropped, as returned by [`UserSession::start_statement`].
+#[derive(Debug)]
+pub struct UserStatement {
+    counters: Arc<UserCounters>,
+    start: Instant,
+}
+
+impl Drop for UserStatement {
+    fn drop(&mut self) {
+        self.counters.statements.inc();
+        self.counters
+            .statement_seconds
+            .inc_by(self.start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metrics::MetricsRegistry;
+
+    use super::{UserStats, OTHER_USER};
+
+    #[test]
+    fn test_user_stats() {
+        let stats = UserStats::register_into(&MetricsRegistry::new(), 2);
+
+        let alice = stats.session("alice");
+        alice.add_rows(3);
+        alice.add_bytes(100);
+        drop(alice.start_statement());
+        drop(alice);
+        let alice = stats.session("alice");
+        let bob = stats.session("bob");
+        assert_eq!(bob.user(), "bob");
+
+        // Users first seen past the limit, and users named like the bucket,
+        // are counted together.
+        let carol = stats.session("carol");
+        assert_eq!(carol.user(), OTHER_USER);
+        carol.add_rows(1);
+        let other = stats.session(OTHER_USER);
+        assert_eq!(other.user(), OTHER_USER);
+
+        let summary = stats.summary();
+        assert_eq!(summary.max_users, 2);
+        assert_eq!(summary.users.len(), 2);
+        let alice_summary = summary.users.iter().find(|u| u.user == "alice").unwrap();
+        assert_eq!(alice_summary.statements, 1);
+        assert_eq!(alice_summary.rows_returned, 3);
+        assert_eq!(alice_summary.bytes_sent, 100);
+        assert_eq!(alice_summary.sessions, 1);
+        assert_eq!(summary.other.rows_returned, 1);
+        assert_eq!(summary.other.sessions, 2);
+
+        drop((alice, bob, carol, other));
+        let summary = stats.summary();
+        assert!(summary.users.iter().all(|u| u.sessions == 0));
+        assert_eq!(summary.other.sessions, 0);
+    }
+}
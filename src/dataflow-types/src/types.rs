@@ -15,16 +15,21 @@
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::mem;
 use std::ops::Add;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use globset::Glob;
 use log::warn;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use timely::progress::frontier::Antichain;
+use timely::scheduling::SyncActivator;
 use tokio::sync::mpsc;
 use url::Url;
 use uuid::Uuid;
@@ -34,6 +39,8 @@ use expr::{GlobalId, MirRelationExpr, MirScalarExpr, OptimizedMirRelationExpr, P
 use interchange::avro::{self, DebeziumDeduplicationStrategy};
 use interchange::protobuf::{decode_descriptors, validate_descriptors};
 use kafka_util::KafkaAddrs;
+use ore::cast::CastFrom;
+use ore::metrics::UIntGauge;
 use repr::{ColumnName, ColumnType, Diff, RelationDesc, RelationType, Row, ScalarType, Timestamp};
 
 /// The response from a `Peek`.
@@ -1089,11 +1096,167 @@ impl SinkConnector {
 pub struct TailSinkConnector {
     #[serde(skip)]
     pub tx: mpsc::UnboundedSender<Vec<Row>>,
+    #[serde(skip)]
+    pub buffer: TailBuffer,
     pub emit_progress: bool,
     pub object_columns: usize,
     pub value_desc: RelationDesc,
 }
 
+/// What a `TAIL` does when its client falls so far behind that the updates
+/// awaiting delivery would exceed the limit on its [`TailBuffer`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TailBufferPolicy {
+    /// Stop producing updates until the client catches up.
+    Block,
+    /// Terminate the `TAIL` with an error.
+    Error,
+}
+
+impl TailBufferPolicy {
+    /// Returns the name of the policy, as accepted by [`FromStr`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TailBufferPolicy::Block => "block",
+            TailBufferPolicy::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for TailBufferPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TailBufferPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<TailBufferPolicy, anyhow::Error> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(TailBufferPolicy::Block),
+            "error" => Ok(TailBufferPolicy::Error),
+            _ => bail!(
+                "invalid TAIL buffer policy: {}; expected 'block' or 'error'",
+                s
+            ),
+        }
+    }
+}
+
+/// Counts the bytes of updates that a `TAIL` has produced but its client has
+/// not yet received.
+///
+/// The tail sink admits each batch of updates to the buffer before sending
+/// it, and the receiver releases the batch once the client takes it. When a
+/// batch would take the buffer past its limit, the sink either waits to be
+/// activated by a release or marks the buffer as overflowed, according to the
+/// buffer's [`TailBufferPolicy`]. A batch is always admitted to an empty
+/// buffer, so that a batch larger than the limit does not stall the tail.
+///
+/// Clones share the same buffer.
+#[derive(Clone, Debug)]
+pub struct TailBuffer {
+    inner: Arc<TailBufferInner>,
+}
+
+#[derive(Debug)]
+struct TailBufferInner {
+    limit: usize,
+    policy: TailBufferPolicy,
+    buffered: AtomicUsize,
+    overflowed: AtomicBool,
+    /// Schedules the sink that produces the updates, once it is built.
+    activator: Mutex<Option<SyncActivator>>,
+    /// The number of bytes buffered across all tails.
+    buffered_metric: UIntGauge,
+}
+
+impl TailBuffer {
+    /// Constructs an empty buffer that holds at most `limit` bytes, and
+    /// adds the bytes it holds to `buffered_metric`.
+    pub fn new(limit: usize, policy: TailBufferPolicy, buffered_metric: UIntGauge) -> TailBuffer {
+        TailBuffer {
+            inner: Arc::new(TailBufferInner {
+                limit,
+                policy,
+                buffered: AtomicUsize::new(0),
+                overflowed: AtomicBool::new(false),
+                activator: Mutex::new(None),
+                buffered_metric,
+            }),
+        }
+    }
+
+    /// Returns the maximum number of bytes the buffer holds.
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Returns what the tail does when the buffer is full.
+    pub fn policy(&self) -> TailBufferPolicy {
+        self.inner.policy
+    }
+
+    /// Returns the number of bytes the buffer holds.
+    pub fn buffered_bytes(&self) -> usize {
+        self.inner.buffered.load(Ordering::SeqCst)
+    }
+
+    /// Reports whether the tail was terminated because the buffer was full.
+    pub fn overflowed(&self) -> bool {
+        self.inner.overflowed.load(Ordering::SeqCst)
+    }
+
+    /// Arranges for `activator` to be activated whenever bytes are released.
+    pub fn set_activator(&self, activator: SyncActivator) {
+        *self.inner.activator.lock().expect("lock poisoned") = Some(activator);
+    }
+
+    /// Admits a batch of `bytes` to the buffer, if it has room.
+    pub fn admit(&self, bytes: usize) -> bool {
+        let buffered = self.inner.buffered.load(Ordering::SeqCst);
+        if buffered > 0 && buffered + bytes > self.inner.limit {
+            return false;
+        }
+        self.inner.buffered.fetch_add(bytes, Ordering::SeqCst);
+        self.inner.buffered_metric.add(u64::cast_from(bytes));
+        true
+    }
+
+    /// Releases a batch of `bytes` from the buffer and activates the sink, as
+    /// it may be waiting for room.
+    pub fn release(&self, bytes: usize) {
+        self.inner.buffered.fetch_sub(bytes, Ordering::SeqCst);
+        self.inner.buffered_metric.sub(u64::cast_from(bytes));
+        if let Some(activator) = &*self.inner.activator.lock().expect("lock poisoned") {
+            // The dataflow may already be gone, in which case there is
+            // nothing to wake.
+            let _ = activator.activate();
+        }
+    }
+
+    /// Records that the tail was terminated because the buffer was full.
+    pub fn overflow(&self) {
+        self.inner.overflowed.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the number of bytes that `rows` count for in a buffer.
+    pub fn batch_size(rows: &[Row]) -> usize {
+        rows.iter()
+            .map(|row| mem::size_of::<Row>() + row.data().len())
+            .sum()
+    }
+}
+
+impl Drop for TailBufferInner {
+    fn drop(&mut self) {
+        let buffered = *self.buffered.get_mut();
+        self.buffered_metric.sub(u64::cast_from(buffered));
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum SinkConnectorBuilder {
     Kafka(KafkaSinkConnectorBuilder),
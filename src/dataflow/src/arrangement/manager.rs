@@ -12,11 +12,13 @@
 use std::any::Any;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
 
 use differential_dataflow::operators::arrange::TraceAgent;
 use differential_dataflow::trace::implementations::ord::{OrdKeyBatch, OrdValBatch};
 use differential_dataflow::trace::implementations::spine_fueled::Spine;
-use differential_dataflow::trace::TraceReader;
+use differential_dataflow::trace::{BatchReader, TraceReader};
 use ore::metric;
 use ore::metrics::{
     CounterVec, CounterVecExt, DeleteOnDropCounter, DeleteOnDropGauge, GaugeVecExt,
@@ -92,6 +94,44 @@ impl MaintenanceMetrics {
     }
 }
 
+/// The number of records in the arrangements that each worker maintains.
+///
+/// Workers report their counts each time they perform maintenance on their
+/// traces. Clones share the same counts.
+#[derive(Clone, Debug)]
+pub struct ArrangementSizes {
+    records: Arc<Vec<AtomicUsize>>,
+}
+
+impl ArrangementSizes {
+    /// Constructs the counts for `workers` workers, which are initially zero.
+    pub fn new(workers: usize) -> ArrangementSizes {
+        ArrangementSizes {
+            records: Arc::new((0..workers).map(|_| AtomicUsize::new(0)).collect()),
+        }
+    }
+
+    /// Returns the number of records in the arrangements of all workers, as
+    /// of each worker's most recent report.
+    pub fn records(&self) -> usize {
+        self.records
+            .iter()
+            .map(|records| records.load(atomic::Ordering::Relaxed))
+            .sum()
+    }
+
+    fn set(&self, worker_id: usize, records: usize) {
+        // Worker indexes are global across processes, but the counts are
+        // per process.
+        let slot = worker_id
+            .checked_rem(self.records.len())
+            .and_then(|i| self.records.get(i));
+        if let Some(slot) = slot {
+            slot.store(records, atomic::Ordering::Relaxed);
+        }
+    }
+}
+
 /// A `TraceManager` stores maps from global identifiers to the primary arranged
 /// representation of that collection.
 pub struct TraceManager {
@@ -105,10 +145,13 @@ pub struct TraceManager {
     /// maintenance completes
     doing_maintenance: DeleteOnDropGauge<'static, AtomicU64, Vec<String>>,
     metrics: TraceMetrics,
+    /// Where the number of records in the managed traces is reported after
+    /// each round of maintenance.
+    sizes: ArrangementSizes,
 }
 
 impl TraceManager {
-    pub fn new(metrics: TraceMetrics, worker_id: usize) -> Self {
+    pub fn new(metrics: TraceMetrics, worker_id: usize, sizes: ArrangementSizes) -> Self {
         let doing_maintenance = metrics.maintenance_flag_metric(worker_id);
         TraceManager {
             traces: HashMap::new(),
@@ -116,6 +159,7 @@ impl TraceManager {
             metrics,
             maintenance_metrics: HashMap::new(),
             doing_maintenance,
+            sizes,
         }
     }
 
@@ -128,6 +172,7 @@ impl TraceManager {
     /// be able to remove this code.
     pub fn maintenance(&mut self) {
         let mut antichain = Antichain::new();
+        let mut records = 0;
         for (arrangement_id, bundle) in self.traces.iter_mut() {
             // Update maintenance metrics
             // Entry is guaranteed to exist as it gets created when we initialize the partition.
@@ -147,7 +192,11 @@ impl TraceManager {
                 .inc_by(now.elapsed().as_secs_f64());
             // signal that maintenance has ended
             self.doing_maintenance.set(0);
+
+            bundle.oks.map_batches(|batch| records += batch.len());
+            bundle.errs.map_batches(|batch| records += batch.len());
         }
+        self.sizes.set(self.worker_id, records);
     }
 
     /// Enables compaction of traces associated with the identifier.
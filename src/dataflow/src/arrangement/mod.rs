@@ -10,4 +10,4 @@
 //! Types related to the arrangement and management of collections.
 
 pub mod manager;
-pub use manager::{KeysValsHandle, TraceManager};
+pub use manager::{ArrangementSizes, KeysValsHandle, TraceManager};
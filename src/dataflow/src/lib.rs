@@ -22,8 +22,9 @@ mod sink;
 pub mod logging;
 pub mod source;
 
+pub use arrangement::ArrangementSizes;
 pub use render::plan::Plan;
 pub use server::{
     serve, Config, SequencedCommand, TimestampBindingFeedback, WorkerFeedback,
-    WorkerFeedbackWithMeta,
+    WorkerFeedbackWithMeta, WorkerGuards, WorkerStartHook,
 };
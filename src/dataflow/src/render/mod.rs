@@ -103,6 +103,8 @@
 use std::any::Any;
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::rc::Weak;
 
@@ -159,6 +161,12 @@ pub struct RenderState {
     pub sink_write_frontiers: HashMap<GlobalId, Rc<RefCell<Antichain<Timestamp>>>>,
     /// Metrics reported by all dataflows.
     pub metrics: Metrics,
+    /// The directory in which dataflows may store state that need not
+    /// survive a restart.
+    pub scratch_directory: PathBuf,
+    /// The local address to which outbound connections to external systems
+    /// are bound, if any.
+    pub outbound_bind_addr: Option<IpAddr>,
 }
 
 /// A container for "tokens" that are relevant to an in-construction dataflow.
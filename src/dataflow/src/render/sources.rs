@@ -185,7 +185,12 @@ where
 
                         (ok_stream.as_collection(), capability)
                     } else if let ExternalSourceConnector::Postgres(pg_connector) = connector {
-                        let source = PostgresSourceReader::new(src.name.clone(), pg_connector);
+                        let source = PostgresSourceReader::new(
+                            src.name.clone(),
+                            pg_connector,
+                            render_state.scratch_directory.clone(),
+                            render_state.outbound_bind_addr,
+                        );
 
                         let ((ok_stream, err_stream), capability) =
                             source::create_source_simple(source_config, source);
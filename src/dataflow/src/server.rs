@@ -9,22 +9,29 @@
 
 //! An interactive dataflow server.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::sync::Mutex;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+use differential_dataflow::logging::DifferentialEvent;
 use differential_dataflow::operators::arrange::arrangement::Arrange;
 use differential_dataflow::trace::cursor::Cursor;
 use differential_dataflow::trace::TraceReader;
 use differential_dataflow::Collection;
 use ore::metrics::MetricsRegistry;
 use serde::{Deserialize, Serialize};
-use timely::communication::initialize::WorkerGuards;
+use timely::communication::allocator::{AllocateBuilder, Generic, GenericBuilder, Process};
 use timely::communication::Allocate;
+use timely::dataflow::operators::capture::EventLink;
 use timely::dataflow::operators::unordered_input::UnorderedHandle;
 use timely::dataflow::operators::ActivateCapability;
-use timely::logging::Logger;
+use timely::logging::{Logger, TimelyEvent, WorkerIdentifier};
 use timely::order::PartialOrder;
 use timely::progress::frontier::Antichain;
 use timely::progress::ChangeBatch;
@@ -40,9 +47,10 @@ use expr::{GlobalId, PartitionId, RowSetFinishing};
 use ore::{now::NowFn, result::ResultExt};
 use repr::{Diff, Row, RowArena, Timestamp};
 
-use crate::arrangement::manager::{TraceBundle, TraceManager, TraceMetrics};
+use crate::arrangement::manager::{ArrangementSizes, TraceBundle, TraceManager, TraceMetrics};
 use crate::logging;
 use crate::logging::materialized::MaterializedEvent;
+use crate::logging::BatchLogger;
 use crate::metrics::Metrics;
 use crate::operator::CollectionExt;
 use crate::render::{self, plan::Plan as RenderPlan, RenderState};
@@ -160,7 +168,15 @@ pub enum SequencedCommand {
     EnableFeedback(mpsc::UnboundedSender<WorkerFeedbackWithMeta>),
     /// Request that the logging sources in the contained configuration are
     /// installed.
+    ///
+    /// Logging must not already be enabled.
     EnableLogging(LoggingConfig),
+    /// Request that the logging sources be uninstalled.
+    ///
+    /// The traces for the logging sources are dropped, and their reported
+    /// frontiers are reset to the minimum timestamp, from which the traces
+    /// will start over if logging is enabled again.
+    DisableLogging,
     /// Disconnect inputs, drain dataflows, and shut down timely workers.
     Shutdown,
 }
@@ -193,6 +209,10 @@ pub enum WorkerFeedback {
     TimestampBindings(TimestampBindingFeedback),
 }
 
+/// A function that is called on each worker thread, with the index of the
+/// worker, before the worker begins processing commands.
+pub type WorkerStartHook = Arc<dyn Fn(usize) + Send + Sync>;
+
 /// Configures a dataflow server.
 pub struct Config {
     /// Command stream receivers for each desired workers.
@@ -202,16 +222,31 @@ pub struct Config {
     pub command_receivers: Vec<crossbeam_channel::Receiver<SequencedCommand>>,
     /// The Timely worker configuration.
     pub timely_worker: timely::WorkerConfig,
+    /// A function to call on each worker thread as it starts, if any.
+    pub worker_start_hook: Option<WorkerStartHook>,
+    /// The size of the stack of each worker thread, in bytes.
+    ///
+    /// If `None`, worker threads use the default stack size for new threads.
+    pub worker_stack_size: Option<usize>,
     /// Whether the server is running in experimental mode.
     pub experimental_mode: bool,
     /// Function to get wall time now.
     pub now: NowFn,
     /// Metrics registry through which dataflow metrics will be reported.
     pub metrics_registry: MetricsRegistry,
+    /// The directory in which workers may store state that need not survive
+    /// a restart.
+    pub scratch_directory: PathBuf,
+    /// The local address to which outbound connections to external systems
+    /// are bound, if any.
+    pub outbound_bind_addr: Option<IpAddr>,
+    /// Where workers report the number of records in the arrangements that
+    /// they maintain.
+    pub arrangement_sizes: ArrangementSizes,
 }
 
 /// Initiates a timely dataflow computation, processing materialized commands.
-pub fn serve(config: Config) -> Result<WorkerGuards<()>, String> {
+pub fn serve(config: Config) -> Result<WorkerGuards, String> {
     let server_metrics = ServerMetrics::register_with(&config.metrics_registry);
     let dataflow_source_metrics = SourceBaseMetrics::register_with(&config.metrics_registry);
     let dataflow_sink_metrics = SinkBaseMetrics::register_with(&config.metrics_registry);
@@ -229,49 +264,174 @@ pub fn serve(config: Config) -> Result<WorkerGuards<()>, String> {
 
     let tokio_executor = tokio::runtime::Handle::current();
     let now = config.now;
+    let scratch_directory = config.scratch_directory;
+    let outbound_bind_addr = config.outbound_bind_addr;
+    let arrangement_sizes = config.arrangement_sizes;
+    let worker_start_hook = config.worker_start_hook;
     let metrics = Metrics::register_with(&config.metrics_registry);
     let trace_metrics = TraceMetrics::register_with(&config.metrics_registry);
-    timely::execute::execute(
-        timely::Config {
-            communication: timely::CommunicationConfig::Process(workers),
-            worker: config.timely_worker,
-        },
-        move |timely_worker| {
-            let _tokio_guard = tokio_executor.enter();
-            let command_rx = command_rxs.lock().unwrap()[timely_worker.index() % workers]
-                .take()
-                .unwrap();
-            let worker_idx = timely_worker.index();
-            let metrics = metrics.clone();
-            let trace_metrics = trace_metrics.clone();
-            let dataflow_source_metrics = dataflow_source_metrics.clone();
-            let dataflow_sink_metrics = dataflow_sink_metrics.clone();
-            Worker {
-                timely_worker,
-                render_state: RenderState {
-                    traces: TraceManager::new(trace_metrics, worker_idx),
-                    local_inputs: HashMap::new(),
-                    ts_source_mapping: HashMap::new(),
-                    ts_histories: HashMap::default(),
-                    dataflow_tokens: HashMap::new(),
-                    sink_write_frontiers: HashMap::new(),
-                    metrics,
-                },
-                materialized_logger: None,
-                command_rx,
-                pending_peeks: Vec::new(),
-                feedback_tx: None,
-                reported_frontiers: HashMap::new(),
-                reported_bindings_frontiers: HashMap::new(),
-                last_bindings_feedback: Instant::now(),
-                metrics: server_metrics.for_worker_id(worker_idx),
-                now,
-                dataflow_source_metrics,
-                dataflow_sink_metrics,
-            }
-            .run()
-        },
-    )
+    let logic = Arc::new(move |timely_worker: &mut TimelyWorker<Generic>| {
+        let _tokio_guard = tokio_executor.enter();
+        let command_rx = command_rxs.lock().unwrap()[timely_worker.index() % workers]
+            .take()
+            .unwrap();
+        let worker_idx = timely_worker.index();
+        if let Some(worker_start_hook) = &worker_start_hook {
+            worker_start_hook(worker_idx);
+        }
+        let metrics = metrics.clone();
+        let trace_metrics = trace_metrics.clone();
+        let arrangement_sizes = arrangement_sizes.clone();
+        let dataflow_source_metrics = dataflow_source_metrics.clone();
+        let dataflow_sink_metrics = dataflow_sink_metrics.clone();
+        Worker {
+            timely_worker,
+            render_state: RenderState {
+                traces: TraceManager::new(trace_metrics, worker_idx, arrangement_sizes),
+                local_inputs: HashMap::new(),
+                ts_source_mapping: HashMap::new(),
+                ts_histories: HashMap::default(),
+                dataflow_tokens: HashMap::new(),
+                sink_write_frontiers: HashMap::new(),
+                metrics,
+                scratch_directory: scratch_directory.clone(),
+                outbound_bind_addr,
+            },
+            materialized_logger: None,
+            logging_config: None,
+            loggers: None,
+            command_rx,
+            pending_peeks: Vec::new(),
+            feedback_tx: None,
+            reported_frontiers: HashMap::new(),
+            reported_bindings_frontiers: HashMap::new(),
+            last_bindings_feedback: Instant::now(),
+            metrics: server_metrics.for_worker_id(worker_idx),
+            now,
+            dataflow_source_metrics,
+            dataflow_sink_metrics,
+        }
+        .run()
+    });
+
+    // Timely does not allow configuring the threads on which it runs its
+    // workers, so the workers are spawned here instead, as Timely would spawn
+    // them.
+    let mut guards = Vec::with_capacity(workers);
+    for (index, builder) in Process::new_vector(workers).into_iter().enumerate() {
+        let logic = Arc::clone(&logic);
+        let worker_config = config.timely_worker.clone();
+        let mut thread_builder = thread::Builder::new().name(format!("timely-worker-{}", index));
+        if let Some(stack_size) = config.worker_stack_size {
+            thread_builder = thread_builder.stack_size(stack_size);
+        }
+        let guard = thread_builder
+            .spawn(move || {
+                let allocator = GenericBuilder::Process(builder).build();
+                let mut timely_worker = TimelyWorker::new(worker_config, allocator);
+                logic(&mut timely_worker);
+                while timely_worker.step_or_park(None) {}
+            })
+            .map_err(|e| format!("spawning worker thread: {}", e))?;
+        guards.push(guard);
+    }
+    Ok(WorkerGuards { guards })
+}
+
+/// Handles to the threads running the dataflow workers.
+///
+/// Dropping the guards waits for the workers to exit.
+pub struct WorkerGuards {
+    guards: Vec<JoinHandle<()>>,
+}
+
+impl WorkerGuards {
+    /// Returns the handles to the worker threads, in order of worker index.
+    pub fn guards(&self) -> &[JoinHandle<()>] {
+        &self.guards
+    }
+}
+
+impl Drop for WorkerGuards {
+    fn drop(&mut self) {
+        for guard in self.guards.drain(..) {
+            guard.join().expect("worker thread panicked");
+        }
+    }
+}
+
+/// A batch logger that publishes events of type `E` to a logging dataflow.
+type EventBatchLogger<E> =
+    BatchLogger<E, WorkerIdentifier, Rc<EventLink<Timestamp, (Duration, WorkerIdentifier, E)>>>;
+
+/// A registered logger for events of type `E`, along with the slot for the
+/// batch logger to which it forwards its events.
+///
+/// The slot is empty while logging is disabled, in which case the logger
+/// discards its events.
+type LoggerWithSlot<E> = (Logger<E>, Rc<RefCell<Option<EventBatchLogger<E>>>>);
+
+/// The loggers registered with Timely's logging framework.
+///
+/// Every dataflow captures the registered loggers when it is built, so
+/// replacing the registered loggers would not affect existing dataflows.
+/// Instead, the same loggers are registered each time logging is enabled, and
+/// enabling and disabling logging fills and empties the slots to which they
+/// forward their events.
+struct RegisteredLoggers {
+    timely: LoggerWithSlot<TimelyEvent>,
+    differential: LoggerWithSlot<DifferentialEvent>,
+    materialized: LoggerWithSlot<MaterializedEvent>,
+}
+
+impl RegisteredLoggers {
+    fn new(now: Instant, unix: Duration, worker_index: usize) -> RegisteredLoggers {
+        fn logger_with_slot<E: 'static>(
+            now: Instant,
+            unix: Duration,
+            worker_index: usize,
+        ) -> LoggerWithSlot<E> {
+            let slot: Rc<RefCell<Option<EventBatchLogger<E>>>> = Rc::new(RefCell::new(None));
+            let logger = Logger::new(now, unix, worker_index, {
+                let slot = Rc::clone(&slot);
+                move |time, data| {
+                    if let Some(batch_logger) = &mut *slot.borrow_mut() {
+                        batch_logger.publish_batch(time, data)
+                    } else {
+                        data.clear();
+                    }
+                }
+            });
+            (logger, slot)
+        }
+
+        RegisteredLoggers {
+            timely: logger_with_slot(now, unix, worker_index),
+            differential: logger_with_slot(now, unix, worker_index),
+            materialized: logger_with_slot(now, unix, worker_index),
+        }
+    }
+
+    fn register<A: Allocate>(&self, worker: &TimelyWorker<A>) {
+        let mut register = worker.log_register();
+        register.insert_logger("timely", self.timely.0.clone());
+        register.insert_logger("differential/arrange", self.differential.0.clone());
+        register.insert_logger("materialized", self.materialized.0.clone());
+    }
+
+    fn unregister<A: Allocate>(&self, worker: &TimelyWorker<A>) {
+        let mut register = worker.log_register();
+        register.remove("timely");
+        register.remove("differential/arrange");
+        register.remove("materialized");
+    }
+
+    /// Empties the slots, dropping the batch loggers.
+    fn clear(&self) {
+        self.timely.1.borrow_mut().take();
+        self.differential.1.borrow_mut().take();
+        self.materialized.1.borrow_mut().take();
+    }
 }
 
 /// State maintained for each worker thread.
@@ -288,6 +448,11 @@ where
     render_state: RenderState,
     /// The logger, from Timely's logging framework, if logs are enabled.
     materialized_logger: Option<logging::materialized::Logger>,
+    /// The logging configuration, if logs are enabled.
+    logging_config: Option<LoggingConfig>,
+    /// The loggers registered with Timely's logging framework, if logs have
+    /// ever been enabled.
+    loggers: Option<RegisteredLoggers>,
     /// The channel from which commands are drawn.
     command_rx: crossbeam_channel::Receiver<SequencedCommand>,
     /// Peek commands that are awaiting fulfillment.
@@ -314,28 +479,40 @@ where
 {
     /// Initializes timely dataflow logging and publishes as a view.
     fn initialize_logging(&mut self, logging: &LoggingConfig) {
-        if self.materialized_logger.is_some() {
+        if self.logging_config.is_some() {
             panic!("dataflow server has already initialized logging");
         }
 
-        use crate::logging::BatchLogger;
-        use timely::dataflow::operators::capture::event::link::EventLink;
-
         let granularity_ms = std::cmp::max(1, logging.granularity_ns / 1_000_000) as Timestamp;
 
         // Track time relative to the Unix epoch, rather than when the server
         // started, so that the logging sources can be joined with tables and
         // other real time sources for semi-sensible results.
-        let now = Instant::now();
-        let unix = Duration::from_millis((self.now)());
+        if self.loggers.is_none() {
+            let now = Instant::now();
+            let unix = Duration::from_millis((self.now)());
+            self.loggers = Some(RegisteredLoggers::new(
+                now,
+                unix,
+                self.timely_worker.index(),
+            ));
+        }
+        let loggers = self.loggers.as_ref().unwrap();
+
+        // The loggers may be registered from a previous time that logging was
+        // enabled. Unregister them, so we can either log the logging or not,
+        // as we like.
+        loggers.unregister(&self.timely_worker);
 
         // Establish loggers first, so we can either log the logging or not, as we like.
-        let t_linked = std::rc::Rc::new(EventLink::new());
-        let mut t_logger = BatchLogger::new(t_linked.clone(), granularity_ms);
-        let d_linked = std::rc::Rc::new(EventLink::new());
-        let mut d_logger = BatchLogger::new(d_linked.clone(), granularity_ms);
-        let m_linked = std::rc::Rc::new(EventLink::new());
-        let mut m_logger = BatchLogger::new(m_linked.clone(), granularity_ms);
+        let t_linked = Rc::new(EventLink::new());
+        *loggers.timely.1.borrow_mut() = Some(BatchLogger::new(t_linked.clone(), granularity_ms));
+        let d_linked = Rc::new(EventLink::new());
+        *loggers.differential.1.borrow_mut() =
+            Some(BatchLogger::new(d_linked.clone(), granularity_ms));
+        let m_linked = Rc::new(EventLink::new());
+        *loggers.materialized.1.borrow_mut() =
+            Some(BatchLogger::new(m_linked.clone(), granularity_ms));
 
         let mut t_traces = HashMap::new();
         let mut d_traces = HashMap::new();
@@ -361,26 +538,7 @@ where
         }
 
         // Register each logger endpoint.
-        self.timely_worker.log_register().insert_logger(
-            "timely",
-            Logger::new(now, unix, self.timely_worker.index(), move |time, data| {
-                t_logger.publish_batch(time, data)
-            }),
-        );
-
-        self.timely_worker.log_register().insert_logger(
-            "differential/arrange",
-            Logger::new(now, unix, self.timely_worker.index(), move |time, data| {
-                d_logger.publish_batch(time, data)
-            }),
-        );
-
-        self.timely_worker.log_register().insert_logger(
-            "materialized",
-            Logger::new(now, unix, self.timely_worker.index(), move |time, data| {
-                m_logger.publish_batch(time, data)
-            }),
-        );
+        loggers.register(&self.timely_worker);
 
         let errs = self
             .timely_worker
@@ -443,6 +601,54 @@ where
         }
 
         self.materialized_logger = Some(logger);
+        self.logging_config = Some(logging.clone());
+    }
+
+    /// Uninstalls the logging dataflows, if logging is enabled.
+    ///
+    /// The loggers remain registered, as existing dataflows hold on to them,
+    /// but discard their events until logging is enabled again.
+    fn disable_logging(&mut self) {
+        let logging = match self.logging_config.take() {
+            Some(logging) => logging,
+            None => return,
+        };
+
+        // Dropping the batch loggers closes the inputs to the logging
+        // dataflows, which allows them to shut down.
+        if let Some(loggers) = &self.loggers {
+            loggers.clear();
+        }
+        self.materialized_logger = None;
+
+        // The traces will start over from the minimum timestamp if logging is
+        // enabled again, so reset the reported frontiers to match.
+        let mut progress = Vec::new();
+        for id in logging.active_logs.values() {
+            self.render_state.traces.del_trace(id);
+            if let Some(prev_frontier) = self.reported_frontiers.get_mut(id) {
+                let mut changes = ChangeBatch::new();
+                for time in prev_frontier.elements() {
+                    changes.update(*time, -1);
+                }
+                changes.update(0, 1);
+                changes.compact();
+                if !changes.is_empty() {
+                    progress.push((*id, changes));
+                }
+                *prev_frontier = Antichain::from_elem(0);
+            }
+        }
+        if let Some(feedback_tx) = &self.feedback_tx {
+            if !progress.is_empty() {
+                feedback_tx
+                    .send(WorkerFeedbackWithMeta {
+                        worker_id: self.timely_worker.index(),
+                        message: WorkerFeedback::FrontierUppers(progress),
+                    })
+                    .expect("feedback receiver should not drop first");
+            }
+        }
     }
 
     /// Disables timely dataflow logging.
@@ -455,6 +661,9 @@ where
             .log_register()
             .remove("differential/arrange");
         self.timely_worker.log_register().remove("materialized");
+        if let Some(loggers) = &self.loggers {
+            loggers.clear();
+        }
     }
 
     /// Draws from `dataflow_command_receiver` until shutdown.
@@ -844,6 +1053,9 @@ where
             SequencedCommand::EnableLogging(config) => {
                 self.initialize_logging(&config);
             }
+            SequencedCommand::DisableLogging => {
+                self.disable_logging();
+            }
             SequencedCommand::Shutdown => {
                 // this should lead timely to wind down eventually
                 self.render_state.traces.del_all_traces();
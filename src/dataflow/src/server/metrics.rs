@@ -128,6 +128,8 @@ struct CommandsProcessedMetrics {
     enable_feedback: IntCounter,
     enable_logging_int: i32,
     enable_logging: IntCounter,
+    disable_logging_int: i32,
+    disable_logging: IntCounter,
     shutdown_int: i32,
     shutdown: IntCounter,
     advance_all_local_inputs_int: i32,
@@ -178,6 +180,9 @@ impl CommandsProcessedMetrics {
             enable_logging_int: 0,
             enable_logging: commands_processed_metric
                 .with_label_values(&[worker, "enable_logging"]),
+            disable_logging_int: 0,
+            disable_logging: commands_processed_metric
+                .with_label_values(&[worker, "disable_logging"]),
             shutdown_int: 0,
             shutdown: commands_processed_metric.with_label_values(&[worker, "shutdown"]),
             advance_all_local_inputs_int: 0,
@@ -208,6 +213,7 @@ impl CommandsProcessedMetrics {
             }
             SequencedCommand::EnableFeedback(..) => self.enable_feedback_int += 1,
             SequencedCommand::EnableLogging(_) => self.enable_logging_int += 1,
+            SequencedCommand::DisableLogging => self.disable_logging_int += 1,
             SequencedCommand::Shutdown { .. } => self.shutdown_int += 1,
             SequencedCommand::AdvanceAllLocalInputs { .. } => {
                 self.advance_all_local_inputs_int += 1
@@ -274,6 +280,10 @@ impl CommandsProcessedMetrics {
             self.enable_feedback.inc_by(self.enable_feedback_int as i64);
             self.enable_feedback_int = 0;
         }
+        if self.disable_logging_int > 0 {
+            self.disable_logging.inc_by(self.disable_logging_int as i64);
+            self.disable_logging_int = 0;
+        }
         if self.shutdown_int > 0 {
             self.shutdown.inc_by(self.shutdown_int as i64);
             self.shutdown_int = 0;
@@ -8,6 +8,7 @@
 // by the Apache License, Version 2.0.
 
 use std::any::Any;
+use std::collections::VecDeque;
 
 use differential_dataflow::operators::arrange::ArrangeByKey;
 use differential_dataflow::trace::cursor::Cursor;
@@ -16,7 +17,8 @@ use differential_dataflow::Collection;
 
 use itertools::Itertools;
 use timely::dataflow::channels::pact::Pipeline;
-use timely::dataflow::operators::Operator;
+use timely::dataflow::operators::generic::builder_rc::OperatorBuilder;
+use timely::dataflow::operators::generic::FrontieredInputHandle;
 use timely::dataflow::scopes::Child;
 use timely::dataflow::Scope;
 use timely::progress::frontier::AntichainRef;
@@ -24,7 +26,7 @@ use timely::progress::timestamp::Timestamp as TimelyTimestamp;
 use timely::progress::Antichain;
 use timely::PartialOrder;
 
-use dataflow_types::{SinkAsOf, SinkDesc, TailSinkConnector};
+use dataflow_types::{SinkAsOf, SinkDesc, TailBuffer, TailBufferPolicy, TailSinkConnector};
 use expr::GlobalId;
 use ore::cast::CastFrom;
 use repr::adt::numeric::{self, Numeric};
@@ -95,114 +97,172 @@ fn tail<G>(
         .arrange_by_key()
         .stream;
 
+    let scope = batches.scope();
+    let mut builder = OperatorBuilder::new(format!("tail-{}", id), scope.clone());
+    let mut input = builder.new_input(&batches, Pipeline);
+    builder.set_notify(false);
+
+    // The receiver activates the sink whenever the client takes updates out
+    // of the buffer, as the sink may be waiting for room. Only the worker
+    // that receives the updates ever waits, so it registers the activator
+    // when its first updates arrive.
+    let mut activator = Some(scope.sync_activator_for(&builder.operator_info().address[..]));
+
     let mut errored = false;
     let mut packer = Row::default();
     let mut received_data = false;
+    let mut vector = Vec::new();
+
+    // Results that have been produced but not yet admitted to the buffer.
+    // While any are pending, the sink leaves further batches in its input,
+    // so that a slow client holds back the sink rather than accumulating
+    // rows in memory.
+    let mut pending: VecDeque<Vec<Row>> = VecDeque::new();
 
     // Initialize to the minimal input frontier.
     let mut input_frontier = Antichain::from_elem(<G::Timestamp as TimelyTimestamp>::minimum());
 
-    batches.sink(Pipeline, &format!("tail-{}", id), move |input| {
-        input.for_each(|_, batches| {
+    builder.build(|_capabilities| {
+        move |frontiers| {
+            let mut input = FrontieredInputHandle::new(&mut input, &frontiers[0]);
+
             if errored {
                 // TODO(benesch): we should actually drop the sink if the
                 // receiver has gone away.
+                input.for_each(|_, _| ());
                 return;
             }
-            received_data = true;
-            let mut results = vec![];
-            for batch in batches.iter() {
-                let mut cursor = batch.cursor();
-                while cursor.key_valid(&batch) {
-                    while cursor.val_valid(&batch) {
-                        let row = cursor.val(&batch);
-                        cursor.map_times(&batch, |time, diff| {
-                            let diff = *diff;
-                            let should_emit = if as_of.strict {
-                                as_of.frontier.less_than(time)
-                            } else {
-                                as_of.frontier.less_equal(time)
-                            };
-                            if should_emit {
-                                packer.push(Datum::from(numeric::Numeric::from(*time)));
-                                if connector.emit_progress {
-                                    // When sinking with PROGRESS, the output
-                                    // includes an additional column that
-                                    // indicates whether a timestamp is
-                                    // complete. For regular "data" upates this
-                                    // is always `false`.
-                                    packer.push(Datum::False);
-                                }
 
-                                packer.push(Datum::Int64(i64::cast_from(diff)));
+            loop {
+                // Forward as many results as the buffer admits.
+                while let Some(results) = pending.front() {
+                    if !connector.buffer.admit(TailBuffer::batch_size(results)) {
+                        match connector.buffer.policy() {
+                            // Wait to be activated by the receiver.
+                            TailBufferPolicy::Block => return,
+                            TailBufferPolicy::Error => {
+                                // Wake the receiver, which reports the
+                                // overflow once it has received the results
+                                // already admitted.
+                                connector.buffer.overflow();
+                                let _ = connector.tx.send(vec![]);
+                                errored = true;
+                                pending.clear();
+                                return;
+                            }
+                        }
+                    }
+                    let results = pending.pop_front().expect("known to exist");
+                    if connector.tx.send(results).is_err() {
+                        errored = true;
+                        pending.clear();
+                        return;
+                    }
+                }
 
-                                packer.extend_by_row(&row);
+                let received = match input.next() {
+                    Some((_, mut batches)) => {
+                        batches.swap(&mut vector);
+                        true
+                    }
+                    None => false,
+                };
+                if !received {
+                    // The input is drained, and all results admitted, so the
+                    // client can be told of any progress in the frontier.
+                    let progress_row = update_progress(
+                        &mut input_frontier,
+                        input.frontier().frontier(),
+                        &mut packer,
+                        connector.object_columns + 1,
+                    );
 
-                                let row = packer.finish_and_reuse();
+                    // Only emit updates if this operator/worker received actual
+                    // data for emission. For TAIL, data is exchanged to one worker,
+                    // which forwards all the data to the client process. If we
+                    // blindly forwarded the frontier from all workers we would get
+                    // multiple progress updates in the client.
+                    match progress_row {
+                        Some(progress_row) if connector.emit_progress && received_data => {
+                            pending.push_back(vec![progress_row]);
+                            continue;
+                        }
+                        _ => return,
+                    }
+                }
+                received_data = true;
+                if let Some(activator) = activator.take() {
+                    connector.buffer.set_activator(activator);
+                }
+                let mut results = vec![];
+                for batch in vector.iter() {
+                    let mut cursor = batch.cursor();
+                    while cursor.key_valid(&batch) {
+                        while cursor.val_valid(&batch) {
+                            let row = cursor.val(&batch);
+                            cursor.map_times(&batch, |time, diff| {
+                                let diff = *diff;
+                                let should_emit = if as_of.strict {
+                                    as_of.frontier.less_than(time)
+                                } else {
+                                    as_of.frontier.less_equal(time)
+                                };
+                                if should_emit {
+                                    packer.push(Datum::from(numeric::Numeric::from(*time)));
+                                    if connector.emit_progress {
+                                        // When sinking with PROGRESS, the output
+                                        // includes an additional column that
+                                        // indicates whether a timestamp is
+                                        // complete. For regular "data" upates this
+                                        // is always `false`.
+                                        packer.push(Datum::False);
+                                    }
 
-                                // Add the unpacked timestamp so we can sort by them later.
-                                results.push((*time, row));
-                            }
-                        });
-                        cursor.step_val(&batch);
+                                    packer.push(Datum::Int64(i64::cast_from(diff)));
+
+                                    packer.extend_by_row(&row);
+
+                                    let row = packer.finish_and_reuse();
+
+                                    // Add the unpacked timestamp so we can sort by them later.
+                                    results.push((*time, row));
+                                }
+                            });
+                            cursor.step_val(&batch);
+                        }
+                        cursor.step_key(&batch);
                     }
-                    cursor.step_key(&batch);
                 }
-            }
 
-            // Sort results by time and convert to Vec<Row>. We use stable sort here even
-            // though it is slower because it will produce deterministic results since the
-            // cursor will always produce rows in the same order.
-            results.sort_by_key(|(time, _)| *time);
-            let mut results: Vec<Row> = results.into_iter().map(|(_, row)| row).collect();
-
-            if let Some(batch) = batches.last() {
-                let progress_row = update_progress(
-                    &mut input_frontier,
-                    batch.desc.upper().borrow(),
-                    &mut packer,
-                    connector.object_columns + 1,
-                );
-                if connector.emit_progress {
-                    if let Some(progress_row) = progress_row {
-                        results.push(progress_row);
+                // Sort results by time and convert to Vec<Row>. We use stable sort here even
+                // though it is slower because it will produce deterministic results since the
+                // cursor will always produce rows in the same order.
+                results.sort_by_key(|(time, _)| *time);
+                let mut results: Vec<Row> = results.into_iter().map(|(_, row)| row).collect();
+
+                if let Some(batch) = vector.last() {
+                    let progress_row = update_progress(
+                        &mut input_frontier,
+                        batch.desc.upper().borrow(),
+                        &mut packer,
+                        connector.object_columns + 1,
+                    );
+                    if connector.emit_progress {
+                        if let Some(progress_row) = progress_row {
+                            results.push(progress_row);
+                        }
                     }
                 }
-            }
+                vector.clear();
 
-            // TODO(benesch): the lack of backpressure here can result in
-            // unbounded memory usage.
-            if connector.tx.send(results).is_err() {
-                errored = true;
-                return;
-            }
-        });
-
-        let progress_row = update_progress(
-            &mut input_frontier,
-            input.frontier().frontier(),
-            &mut packer,
-            connector.object_columns + 1,
-        );
-
-        // Only emit updates if this operator/worker received actual
-        // data for emission. For TAIL, data is exchanged to one worker,
-        // which forwards all the data to the client process. If we
-        // blindly forwarded the frontier from all workers we would get
-        // multiple progress updates in the client.
-        if connector.emit_progress && received_data {
-            if let Some(progress_row) = progress_row {
-                let results = vec![progress_row];
-
-                // TODO(benesch): the lack of backpressure here can result in
-                // unbounded memory usage.
-                if connector.tx.send(results).is_err() {
-                    errored = true;
-                    return;
+                // An empty batch would wake the receiver for nothing, and is
+                // reserved to signal an overflow.
+                if !results.is_empty() {
+                    pending.push_back(results);
                 }
             }
         }
-    })
+    });
 }
 
 // Checks if there is progress between `current_input_frontier` and
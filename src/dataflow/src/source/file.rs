@@ -97,16 +97,19 @@ impl SourceReader for FileSourceReader {
                 } else {
                     FileReadStyle::ReadOnce
                 };
-                std::thread::spawn(move || {
-                    read_file_task(
-                        fc.path,
-                        tx,
-                        Some(consumer_activator),
-                        tail,
-                        fc.compression,
-                        ctor,
-                    );
-                });
+                thread::Builder::new()
+                    .name("file-reader".to_string())
+                    .spawn(move || {
+                        read_file_task(
+                            fc.path,
+                            tx,
+                            Some(consumer_activator),
+                            tail,
+                            fc.compression,
+                            ctor,
+                        );
+                    })
+                    .expect("failed to spawn file reader thread");
                 rx
             }
             ExternalSourceConnector::AvroOcf(fc) => {
@@ -138,16 +141,19 @@ impl SourceReader for FileSourceReader {
                 } else {
                     FileReadStyle::ReadOnce
                 };
-                std::thread::spawn(move || {
-                    read_file_task(
-                        fc.path,
-                        tx,
-                        Some(consumer_activator),
-                        tail,
-                        fc.compression,
-                        ctor,
-                    );
-                });
+                thread::Builder::new()
+                    .name("file-reader".to_string())
+                    .spawn(move || {
+                        read_file_task(
+                            fc.path,
+                            tx,
+                            Some(consumer_activator),
+                            tail,
+                            fc.compression,
+                            ctor,
+                        );
+                    })
+                    .expect("failed to spawn file reader thread");
                 rx
             }
             _ => unreachable!(),
@@ -271,11 +277,14 @@ fn open_file_stream(
             //
             // https://github.com/notify-rs/notify/issues/240
             #[cfg(not(target_os = "linux"))]
-            thread::spawn(move || {
-                while let Ok(()) = notice_tx.send(()) {
-                    thread::sleep(std::time::Duration::from_millis(100));
-                }
-            });
+            thread::Builder::new()
+                .name("file-poller".to_string())
+                .spawn(move || {
+                    while let Ok(()) = notice_tx.send(()) {
+                        thread::sleep(std::time::Duration::from_millis(100));
+                    }
+                })
+                .context("file source: failed to spawn poller thread")?;
 
             #[cfg(target_os = "linux")]
             {
@@ -284,53 +293,56 @@ fn open_file_stream(
                 inotify
                     .add_watch(&_path, WatchMask::ALL_EVENTS)
                     .with_context(|| format!("failed to add watch for file {}", _path.display()))?;
-                thread::spawn(move || {
-                    // This buffer must be at least `sizeof(struct inotify_event) + NAME_MAX + 1`.
-                    // The `inotify` crate documentation uses 1KB, so that's =
-                    // what we do too.
-                    let mut buf = [0; 1024];
-                    loop {
-                        match inotify.read_events_blocking(&mut buf) {
-                            Err(err) => {
-                                if notice_tx
-                                    .send(Err(format!(
-                                    "file source: failed to get events for file: {:#} (path: {})",
-                                    err,
-                                    _path.display()
-                                )))
-                                    .is_err()
-                                {
-                                    // If the notice_tx returns an error, it's because
-                                    // the source has been dropped. Just exit the
-                                    // thread.
-                                    return;
-                                }
-                                // We have no method for recovering from this error
-                                // Close this thread and log an error message (which duplicates the err above)
-                                error!(
-                                    "file source: closing stream due to read errors (path: {})",
-                                    _path.display()
-                                );
-                                return;
-                            }
-                            Ok(mut events) => {
-                                if events.any(|x| x.mask == EventMask::ATTRIB) && !_path.exists() {
+                thread::Builder::new()
+                    .name("file-inotify".to_string())
+                    .spawn(move || {
+                        // This buffer must be at least `sizeof(struct inotify_event) + NAME_MAX + 1`.
+                        // The `inotify` crate documentation uses 1KB, so that's =
+                        // what we do too.
+                        let mut buf = [0; 1024];
+                        loop {
+                            match inotify.read_events_blocking(&mut buf) {
+                                Err(err) => {
+                                    if notice_tx
+                                        .send(Err(format!(
+                                        "file source: failed to get events for file: {:#} (path: {})",
+                                        err,
+                                        _path.display()
+                                    )))
+                                        .is_err()
+                                    {
+                                        // If the notice_tx returns an error, it's because
+                                        // the source has been dropped. Just exit the
+                                        // thread.
+                                        return;
+                                    }
+                                    // We have no method for recovering from this error
+                                    // Close this thread and log an error message (which duplicates the err above)
                                     error!(
-                                        "file source: closing stream due to deleted file (path: {})",
+                                        "file source: closing stream due to read errors (path: {})",
                                         _path.display()
                                     );
                                     return;
                                 }
+                                Ok(mut events) => {
+                                    if events.any(|x| x.mask == EventMask::ATTRIB) && !_path.exists() {
+                                        error!(
+                                            "file source: closing stream due to deleted file (path: {})",
+                                            _path.display()
+                                        );
+                                        return;
+                                    }
+                                }
+                            }
+                            if notice_tx.send(Ok(())).is_err() {
+                                // If the notice_tx returns an error, it's because
+                                // the source has been dropped. Just exit the
+                                // thread.
+                                return;
                             }
                         }
-                        if notice_tx.send(Ok(())).is_err() {
-                            // If the notice_tx returns an error, it's because
-                            // the source has been dropped. Just exit the
-                            // thread.
-                            return;
-                        }
-                    }
-                });
+                    })
+                    .context("file source: failed to spawn inotify thread")?;
             };
 
             Ok(Box::new(ForeverTailedFile {
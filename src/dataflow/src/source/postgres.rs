@@ -10,6 +10,8 @@
 use std::convert::TryInto;
 use std::error::Error;
 use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail};
@@ -43,6 +45,11 @@ pub struct PostgresSourceReader {
     connector: PostgresSourceConnector,
     /// Our cursor into the WAL
     lsn: PgLsn,
+    /// The directory in which to buffer the initial snapshot.
+    scratch_directory: PathBuf,
+    /// The local address to which connections to the upstream database are
+    /// bound, if any.
+    bind_addr: Option<IpAddr>,
 }
 
 trait ErrorExt {
@@ -89,6 +96,14 @@ enum ReplicationError {
     Fatal(anyhow::Error),
 }
 
+impl ErrorExt for anyhow::Error {
+    fn is_recoverable(&self) -> bool {
+        // An outbound address that cannot be bound is misconfigured, and
+        // retrying will not fix it.
+        !self.is::<postgres_util::BindError>()
+    }
+}
+
 impl<E: ErrorExt + Into<anyhow::Error>> From<E> for ReplicationError {
     fn from(err: E) -> Self {
         if err.is_recoverable() {
@@ -118,11 +133,18 @@ macro_rules! try_recoverable {
 
 impl PostgresSourceReader {
     /// Constructs a new instance
-    pub fn new(source_name: String, connector: PostgresSourceConnector) -> Self {
+    pub fn new(
+        source_name: String,
+        connector: PostgresSourceConnector,
+        scratch_directory: PathBuf,
+        bind_addr: Option<IpAddr>,
+    ) -> Self {
         Self {
             source_name,
             connector,
             lsn: 0.into(),
+            scratch_directory,
+            bind_addr,
         }
     }
 
@@ -136,7 +158,7 @@ impl PostgresSourceReader {
         buffer: &mut W,
     ) -> Result<(), ReplicationError> {
         let client =
-            try_recoverable!(postgres_util::connect_replication(&self.connector.conn).await);
+            postgres_util::connect_replication(&self.connector.conn, self.bind_addr).await?;
 
         // We're initialising this source so any previously existing slot must be removed and
         // re-created. Once we have data persistence we will be able to reuse slots across restarts
@@ -148,10 +170,12 @@ impl PostgresSourceReader {
             .await;
 
         // Get all the relevant tables for this publication
-        let publication_tables = try_recoverable!(
-            postgres_util::publication_info(&self.connector.conn, &self.connector.publication)
-                .await
-        );
+        let publication_tables = postgres_util::publication_info(
+            &self.connector.conn,
+            &self.connector.publication,
+            self.bind_addr,
+        )
+        .await?;
 
         // Start a transaction and immediatelly create a replication slot with the USE SNAPSHOT
         // directive. This makes the starting point of the slot and the snapshot of the transaction
@@ -262,7 +286,7 @@ impl PostgresSourceReader {
         use ReplicationError::*;
 
         let client =
-            try_recoverable!(postgres_util::connect_replication(&self.connector.conn).await);
+            postgres_util::connect_replication(&self.connector.conn, self.bind_addr).await?;
 
         let query = format!(
             r#"START_REPLICATION SLOT "{name}" LOGICAL {lsn}
@@ -382,10 +406,11 @@ impl SimpleSource for PostgresSourceReader {
             let mut snapshot_tx = timestamper.start_tx().await;
             loop {
                 let file =
-                    tokio::fs::File::from_std(tempfile::tempfile().map_err(|e| SourceError {
+                    tempfile::tempfile_in(&self.scratch_directory).map_err(|e| SourceError {
                         source_name: self.source_name.clone(),
                         error: SourceErrorDetails::FileIO(e.to_string()),
-                    })?);
+                    })?;
+                let file = tokio::fs::File::from_std(file);
                 let mut writer = tokio::io::BufWriter::new(file);
                 match self.produce_snapshot(&mut snapshot_tx, &mut writer).await {
                     Ok(_) => {
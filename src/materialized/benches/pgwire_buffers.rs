@@ -0,0 +1,138 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Benchmarks the effect of the pgwire buffer configuration on the throughput
+//! of a large `SELECT` and on the memory held by idle connections.
+//!
+//! Memory is not a timing, so the resident set size of idle connections is
+//! printed to standard output rather than measured by Criterion. The server
+//! and its clients share a process, but the clients are the same for each
+//! configuration, so differences in resident set size are attributable to
+//! the server.
+
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+use futures::future;
+
+#[path = "../tests/util.rs"]
+#[allow(dead_code)]
+mod util;
+
+/// The number of rows returned by the large `SELECT`.
+const ROWS: u64 = 100_000;
+
+/// The number of idle connections whose memory is measured.
+const IDLE_CONNECTIONS: usize = 5_000;
+
+/// How long an idle connection waits before shrinking its buffers, in the
+/// configurations that shrink them.
+const SHRINK_AFTER: Duration = Duration::from_secs(1);
+
+/// The buffer configurations to compare: the default, which matches the
+/// behavior before buffers were configurable, and a configuration tuned for
+/// large results and many idle connections.
+fn configs() -> Vec<(&'static str, util::Config)> {
+    vec![
+        ("default", util::Config::default()),
+        (
+            "tuned",
+            util::Config::default().pgwire_buffers(Some(64 << 10), None, Some(SHRINK_AFTER)),
+        ),
+    ]
+}
+
+fn bench_large_select(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pgwire_large_select");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS));
+    for (name, config) in configs() {
+        let server = util::start_server(config).unwrap();
+        let mut client = server.connect(postgres::NoTls).unwrap();
+        client
+            .batch_execute(&format!(
+                "CREATE MATERIALIZED VIEW wide AS
+                 SELECT generate_series AS a, repeat('x', 200) AS b
+                 FROM generate_series(1, {})",
+                ROWS
+            ))
+            .unwrap();
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let rows = client.query("SELECT * FROM wide", &[]).unwrap();
+                assert_eq!(rows.len() as u64, ROWS);
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Returns the resident set size of the process, in bytes, if it can be
+/// determined.
+fn resident_set_size() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size as u64)
+}
+
+/// Opens [`IDLE_CONNECTIONS`] connections to `server`, each of which
+/// retrieves a large row and then goes idle, and reports how much the
+/// resident set size grew.
+fn measure_idle_connections(name: &str, server: &util::Server) -> Result<(), Box<dyn Error>> {
+    let before = match resident_set_size() {
+        None => {
+            println!(
+                "pgwire_idle_connections/{}: resident set size unavailable",
+                name
+            );
+            return Ok(());
+        }
+        Some(before) => before,
+    };
+    let config = server.pg_config_async();
+    let clients = server
+        .runtime
+        .block_on(future::try_join_all((0..IDLE_CONNECTIONS).map(|_| {
+            let config = config.clone();
+            async move {
+                let (client, conn) = config.connect(tokio_postgres::NoTls).await?;
+                tokio::spawn(conn);
+                client.query_one("SELECT repeat('x', 65536)", &[]).await?;
+                Ok::<_, tokio_postgres::Error>(client)
+            }
+        })))?;
+    let busy = resident_set_size().unwrap_or(before);
+    std::thread::sleep(SHRINK_AFTER * 3);
+    let idle = resident_set_size().unwrap_or(before);
+    println!(
+        "pgwire_idle_connections/{}: {} connections; RSS grew {} MiB after a large row, {} MiB once idle",
+        name,
+        clients.len(),
+        busy.saturating_sub(before) >> 20,
+        idle.saturating_sub(before) >> 20,
+    );
+    Ok(())
+}
+
+fn bench_idle_connections() {
+    for (name, config) in configs() {
+        let server = util::start_server(config).unwrap();
+        measure_idle_connections(name, &server).unwrap();
+    }
+}
+
+fn main() {
+    bench_idle_connections();
+    let mut c = Criterion::default().configure_from_args();
+    bench_large_select(&mut c);
+    c.final_summary();
+}
@@ -0,0 +1,157 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Determines the Git SHA and time of the build, along with the version of
+//! the compiler and the change date of the license.
+//!
+//! These are exported to the crate as the environment variables `BUILD_SHA`,
+//! `BUILD_TIME`, `RUSTC_VERSION`, and `LICENSE_CHANGE_DATE`, from which
+//! `BUILD_INFO` is constructed. They are computed
+//! here, rather than by shelling out from a macro, so that building requires
+//! neither a POSIX shell nor coreutils.
+//!
+//! The SHA is taken from the `MZ_DEV_BUILD_SHA` environment variable, if set,
+//! and otherwise from Git. The time is the current time, unless the
+//! `SOURCE_DATE_EPOCH` environment variable is set, in which case it is the
+//! time that variable specifies, for reproducible builds.
+//!
+//! The license change date is read from the `Change Date` parameter of the
+//! Business Source License in the repository's `LICENSE` file, if present.
+//!
+//! No `rerun-if` directives are emitted, as any such directive would disable
+//! Cargo's default of rerunning the build script whenever a file in the
+//! package changes, on which the rest of the build script relies.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+pub fn emit() -> Result<(), anyhow::Error> {
+    println!("cargo:rustc-env=BUILD_SHA={}", sha()?);
+    println!("cargo:rustc-env=BUILD_TIME={}", time()?);
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version()?);
+    println!(
+        "cargo:rustc-env=LICENSE_CHANGE_DATE={}",
+        license_change_date()?
+    );
+    Ok(())
+}
+
+fn sha() -> Result<String, anyhow::Error> {
+    match env::var("MZ_DEV_BUILD_SHA") {
+        Ok(sha) if !sha.is_empty() => return Ok(sha),
+        _ => (),
+    }
+    let sha = match Command::new("git")
+        .args(&["rev-parse", "--verify", "HEAD"])
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        }
+        Ok(_) => None,
+        // Without a `git` binary, which minimal build containers often lack,
+        // read the SHA from the repository directly.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            read_head(&PathBuf::from(env::var("CARGO_MANIFEST_DIR")?))
+        }
+        Err(e) => return Err(e).context("running git"),
+    };
+    sha.ok_or_else(|| {
+        anyhow!(
+            "unable to determine Git SHA; either build from working Git clone \
+             (see https://materialize.com/docs/install/#build-from-source), \
+             or specify SHA manually by setting MZ_DEV_BUILD_SHA environment variable"
+        )
+    })
+}
+
+/// Reads the SHA of the commit checked out in the Git repository that
+/// contains `dir`, without the help of `git`.
+fn read_head(dir: &Path) -> Option<String> {
+    let git_dir = dir
+        .ancestors()
+        .map(|d| d.join(".git"))
+        .find(|d| d.is_dir())?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let name = match head.strip_prefix("ref: ") {
+        None => return Some(head.to_owned()),
+        Some(name) => name,
+    };
+    if let Ok(sha) = fs::read_to_string(git_dir.join(name)) {
+        return Some(sha.trim().to_owned());
+    }
+    // The ref may have been packed, in which case it appears in the
+    // `packed-refs` file as a line of the form `<sha> <name>`.
+    let packed_refs = fs::read_to_string(git_dir.join("packed-refs")).ok()?;
+    packed_refs
+        .lines()
+        .find_map(|line| match line.split_once(' ') {
+            Some((sha, n)) if n == name => Some(sha.to_owned()),
+            _ => None,
+        })
+}
+
+fn time() -> Result<String, anyhow::Error> {
+    let time = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            let secs = epoch.parse().with_context(|| {
+                format!("SOURCE_DATE_EPOCH is not a Unix timestamp: {:?}", epoch)
+            })?;
+            match NaiveDateTime::from_timestamp_opt(secs, 0) {
+                Some(time) => time,
+                None => bail!("SOURCE_DATE_EPOCH is out of range: {}", epoch),
+            }
+        }
+        Err(_) => Utc::now().naive_utc(),
+    };
+    Ok(time.format(TIME_FORMAT).to_string())
+}
+
+fn rustc_version() -> Result<String, anyhow::Error> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let output = Command::new(&rustc)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("running {} --version", rustc))?;
+    if !output.status.success() {
+        bail!("{} --version failed", rustc);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn license_change_date() -> Result<String, anyhow::Error> {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
+    let license = match manifest_dir
+        .ancestors()
+        .map(|d| d.join("LICENSE"))
+        .find(|p| p.is_file())
+    {
+        Some(path) => fs::read_to_string(path).context("reading LICENSE")?,
+        None => return Ok("".into()),
+    };
+    let date = match license
+        .lines()
+        .find_map(|line| line.strip_prefix("Change Date:"))
+    {
+        Some(date) => date.trim(),
+        None => return Ok("".into()),
+    };
+    let date = NaiveDate::parse_from_str(date, "%B %d, %Y")
+        .with_context(|| format!("LICENSE has malformed change date: {:?}", date))?;
+    Ok(date.format("%Y-%m-%d").to_string())
+}
@@ -9,10 +9,12 @@
 
 use std::env;
 
+mod build_info;
 mod npm;
 
 fn main() -> Result<(), anyhow::Error> {
     println!("cargo:rustc-env=TARGET_TRIPLE={}", env::var("TARGET")?);
+    build_info::emit()?;
 
     cc::Build::new()
         .file("src/bin/materialized/sys.c")
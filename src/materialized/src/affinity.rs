@@ -0,0 +1,262 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Pinning of Timely worker threads to CPU cores.
+//!
+//! Left to the OS scheduler, worker threads migrate between cores, and on
+//! machines with several sockets, between NUMA nodes. Pinning each worker to
+//! a core avoids that migration. Only the worker threads are pinned; the
+//! threads of the Tokio runtime are left to the scheduler. Pinning is only
+//! supported on Linux.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use cfg_if::cfg_if;
+use itertools::Itertools;
+
+/// Determines the core to which each Timely worker thread is pinned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AffinityPolicy {
+    /// Pins the workers to consecutive cores, starting from the lowest
+    /// numbered core available to the process.
+    Compact,
+    /// Pins the workers to cores spaced evenly across the cores available to
+    /// the process. Linux usually numbers the cores of each socket
+    /// consecutively, so this spreads the workers across sockets.
+    Spread,
+    /// Pins the `i`th worker to the `i`th core in the list.
+    Explicit(Vec<usize>),
+}
+
+impl FromStr for AffinityPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AffinityPolicy, anyhow::Error> {
+        match s {
+            "compact" => Ok(AffinityPolicy::Compact),
+            "spread" => Ok(AffinityPolicy::Spread),
+            _ => {
+                let mut cores = vec![];
+                for part in s.split(',') {
+                    let parse = |core: &str| {
+                        core.trim()
+                            .parse::<usize>()
+                            .map_err(|_| anyhow!("invalid core ID {:?}", core.trim()))
+                    };
+                    match part.split_once('-') {
+                        None => cores.push(parse(part)?),
+                        Some((lo, hi)) => {
+                            let (lo, hi) = (parse(lo)?, parse(hi)?);
+                            if lo > hi {
+                                bail!("invalid core range {:?}", part.trim());
+                            }
+                            cores.extend(lo..=hi);
+                        }
+                    }
+                }
+                Ok(AffinityPolicy::Explicit(cores))
+            }
+        }
+    }
+}
+
+impl fmt::Display for AffinityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AffinityPolicy::Compact => f.write_str("compact"),
+            AffinityPolicy::Spread => f.write_str("spread"),
+            AffinityPolicy::Explicit(cores) => write!(f, "{}", cores.iter().join(",")),
+        }
+    }
+}
+
+/// Assigns a core to each of `workers` workers according to `policy`, given
+/// the cores that are `available` to the process, in ascending order.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn assign(
+    policy: &AffinityPolicy,
+    workers: usize,
+    available: &[usize],
+) -> Result<Vec<usize>, anyhow::Error> {
+    if available.is_empty() {
+        bail!("no cores are available to this process");
+    }
+    match policy {
+        AffinityPolicy::Compact => Ok((0..workers)
+            .map(|i| available[i % available.len()])
+            .collect()),
+        AffinityPolicy::Spread => Ok((0..workers)
+            .map(|i| available[(i * available.len() / workers) % available.len()])
+            .collect()),
+        AffinityPolicy::Explicit(cores) => {
+            if cores.len() != workers {
+                bail!(
+                    "{} cores were specified for {} workers; specify one core per worker",
+                    cores.len(),
+                    workers
+                );
+            }
+            for core in cores {
+                if !available.contains(core) {
+                    bail!(
+                        "core {} is not available to this process; available cores: {}",
+                        core,
+                        available.iter().join(",")
+                    );
+                }
+            }
+            Ok(cores.clone())
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        pub use enabled::*;
+    } else {
+        pub use disabled::*;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod disabled {
+    use std::sync::Arc;
+
+    use log::warn;
+
+    use super::AffinityPolicy;
+
+    /// Assigns a core to each of `workers` workers according to `policy`.
+    ///
+    /// Worker threads cannot be pinned on this platform, so no cores are
+    /// ever assigned.
+    pub fn plan(
+        policy: &Option<AffinityPolicy>,
+        _workers: usize,
+    ) -> Result<Option<Vec<usize>>, anyhow::Error> {
+        if policy.is_some() {
+            warn!("worker affinity is only supported on Linux; ignoring worker affinity policy");
+        }
+        Ok(None)
+    }
+
+    /// Returns a hook that pins each worker thread to its assigned core.
+    ///
+    /// Worker threads cannot be pinned on this platform, so the hook does
+    /// nothing.
+    pub fn hook(_: Vec<usize>) -> dataflow::WorkerStartHook {
+        Arc::new(|_| ())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod enabled {
+    use std::sync::Arc;
+
+    use anyhow::Context;
+    use log::{info, warn};
+    use nix::sched::{self, CpuSet};
+    use nix::unistd::Pid;
+
+    use super::{assign, AffinityPolicy};
+
+    /// Assigns a core to each of `workers` workers according to `policy`.
+    ///
+    /// Returns an error if `policy` names cores that are not available to
+    /// the process.
+    pub fn plan(
+        policy: &Option<AffinityPolicy>,
+        workers: usize,
+    ) -> Result<Option<Vec<usize>>, anyhow::Error> {
+        match policy {
+            None => Ok(None),
+            Some(policy) => {
+                let available = available_cores()?;
+                let cores = assign(policy, workers, &available)?;
+                Ok(Some(cores))
+            }
+        }
+    }
+
+    /// Returns the cores on which the process is permitted to run, e.g. as
+    /// restricted by `taskset` or a cgroup, in ascending order.
+    fn available_cores() -> Result<Vec<usize>, anyhow::Error> {
+        let set =
+            sched::sched_getaffinity(Pid::from_raw(0)).context("determining available cores")?;
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|core| set.is_set(*core).unwrap_or(false))
+            .collect())
+    }
+
+    /// Returns a hook that pins each worker thread to its assigned core.
+    pub fn hook(cores: Vec<usize>) -> dataflow::WorkerStartHook {
+        Arc::new(move |worker| {
+            let core = cores[worker];
+            let mut set = CpuSet::new();
+            let res = set
+                .set(core)
+                .and_then(|()| sched::sched_setaffinity(Pid::from_raw(0), &set));
+            match res {
+                Ok(()) => info!("pinned timely worker {} to core {}", worker, core),
+                Err(e) => warn!(
+                    "unable to pin timely worker {} to core {}: {}",
+                    worker, core, e
+                ),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign, AffinityPolicy};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "compact".parse::<AffinityPolicy>().unwrap(),
+            AffinityPolicy::Compact
+        );
+        assert_eq!(
+            "spread".parse::<AffinityPolicy>().unwrap(),
+            AffinityPolicy::Spread
+        );
+        assert_eq!(
+            "0,2,4-6".parse::<AffinityPolicy>().unwrap(),
+            AffinityPolicy::Explicit(vec![0, 2, 4, 5, 6])
+        );
+        assert!("0,x".parse::<AffinityPolicy>().is_err());
+        assert!("6-4".parse::<AffinityPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_assign() {
+        let available: Vec<_> = (0..8).collect();
+        assert_eq!(
+            assign(&AffinityPolicy::Compact, 4, &available).unwrap(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            assign(&AffinityPolicy::Spread, 4, &available).unwrap(),
+            vec![0, 2, 4, 6]
+        );
+        assert_eq!(
+            assign(&AffinityPolicy::Compact, 10, &available).unwrap(),
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 1]
+        );
+        assert_eq!(
+            assign(&AffinityPolicy::Explicit(vec![7, 3]), 2, &available).unwrap(),
+            vec![7, 3]
+        );
+        assert!(assign(&AffinityPolicy::Explicit(vec![8, 3]), 2, &available).is_err());
+        assert!(assign(&AffinityPolicy::Explicit(vec![3]), 2, &available).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Tuning of the memory allocator.
+//!
+//! By default, jemalloc retains unused pages for a while in the hope of
+//! reusing them, which shows up as high resident memory in long-running
+//! processes. The settings here control how eagerly those pages are returned
+//! to the OS. Unused pages can also be returned to the OS on demand, by
+//! trimming, e.g. once the server has gone idle after a burst of activity.
+//! jemalloc is not used on macOS, where the settings have no effect and
+//! trimming is not possible.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cfg_if::cfg_if;
+use log::{info, warn};
+use serde::Serialize;
+
+use coord::StatementActivity;
+
+use crate::scheduler::Job;
+use crate::watchdog::ByteSize;
+
+/// Configures the memory allocator.
+///
+/// Settings that are `None` are left at the allocator's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct AllocatorConfig {
+    /// Whether to purge unused pages on background threads, rather than only
+    /// while allocating and deallocating.
+    pub background_thread: Option<bool>,
+    /// How long unused dirty pages are retained before they are purged.
+    pub dirty_decay: Option<Duration>,
+    /// How long unused muzzy pages are retained before they are purged.
+    pub muzzy_decay: Option<Duration>,
+    /// The number of arenas.
+    ///
+    /// The number of arenas is fixed when the process starts, before the
+    /// configuration can be applied, so it must instead be set with the
+    /// `MALLOC_CONF` environment variable. This setting verifies that it was.
+    pub narenas: Option<u32>,
+}
+
+/// The outcome of trimming, i.e., returning unused memory to the OS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrimReport {
+    /// The number of bytes that the allocator held resident before the trim.
+    pub resident_before: u64,
+    /// The number of bytes that the allocator held resident after the trim.
+    pub resident_after: u64,
+}
+
+impl TrimReport {
+    /// Returns the number of bytes that the trim returned to the OS.
+    pub fn released(&self) -> u64 {
+        self.resident_before.saturating_sub(self.resident_after)
+    }
+}
+
+/// Statistics about the memory that the allocator manages, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AllocatorStats {
+    /// Bytes in active pages.
+    pub active: u64,
+    /// Bytes allocated by the application.
+    pub allocated: u64,
+    /// Bytes dedicated to the allocator's own metadata.
+    pub metadata: u64,
+    /// Bytes in physically resident pages.
+    pub resident: u64,
+    /// Bytes in virtual memory mappings that were retained rather than
+    /// returned to the OS.
+    pub retained: u64,
+}
+
+/// Constructs the idle trim job, which trims the allocator once no statement
+/// has executed for `interval`, and then not again until another statement
+/// has executed.
+pub fn idle_trim_job(interval: Duration, activity: StatementActivity, metrics: Metrics) -> Job {
+    // The instant at which the server last went idle and was then trimmed.
+    let trimmed_idle_since: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    Job::every("memory_trim", interval / 4, move || {
+        let activity = activity.clone();
+        let metrics = metrics.clone();
+        let trimmed_idle_since = Arc::clone(&trimmed_idle_since);
+        async move {
+            let idle_since = match activity.idle_since() {
+                Some(idle_since) if idle_since.elapsed() >= interval => idle_since,
+                _ => return,
+            };
+            if *trimmed_idle_since.lock().expect("lock poisoned") == Some(idle_since) {
+                return;
+            }
+            match tokio::task::spawn_blocking(move || trim(&metrics)).await {
+                Ok(Ok(Some(report))) => {
+                    *trimmed_idle_since.lock().expect("lock poisoned") = Some(idle_since);
+                    info!(
+                        "trimmed memory after {:?} idle: resident memory {} -> {}, {} released",
+                        idle_since.elapsed(),
+                        ByteSize(report.resident_before),
+                        ByteSize(report.resident_after),
+                        ByteSize(report.released()),
+                    );
+                }
+                // Another trim is in progress, and will do as well.
+                Ok(Ok(None)) => (),
+                Ok(Err(e)) => warn!("trimming memory: {:#}", e),
+                Err(e) => warn!("trimming memory: {}", e),
+            }
+        }
+    })
+}
+
+cfg_if! {
+    if #[cfg(target_os = "macos")] {
+        pub use disabled::*;
+    } else {
+        pub use enabled::*;
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod disabled {
+    use log::warn;
+
+    use ore::metrics::MetricsRegistry;
+
+    use super::{AllocatorConfig, AllocatorStats, TrimReport};
+
+    /// Applies `config` to the allocator.
+    pub fn apply(config: &Option<AllocatorConfig>) -> Result<(), anyhow::Error> {
+        if config.is_some() {
+            warn!("jemalloc is not used on macOS; ignoring allocator configuration");
+        }
+        Ok(())
+    }
+
+    /// Describes the allocator's settings, if they can be determined.
+    pub fn describe_settings() -> Option<String> {
+        None
+    }
+
+    /// Returns unused memory to the OS. Not possible on macOS.
+    pub fn trim(_: &Metrics) -> Result<Option<TrimReport>, anyhow::Error> {
+        anyhow::bail!("jemalloc is not used on macOS")
+    }
+
+    /// Returns the number of bytes of memory that the allocator holds
+    /// resident. Not available on macOS.
+    pub fn resident() -> Option<u64> {
+        None
+    }
+
+    /// Returns the allocator's statistics. Not available on macOS.
+    pub fn stats() -> Option<AllocatorStats> {
+        None
+    }
+
+    /// Allocator metrics. Not reported on macOS.
+    #[derive(Debug, Clone)]
+    pub struct Metrics;
+
+    impl Metrics {
+        pub fn register_with(_: &MetricsRegistry) -> Metrics {
+            Metrics
+        }
+
+        pub fn update(&self) {}
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod enabled {
+    use std::convert::TryFrom;
+    use std::sync::{Mutex, TryLockError};
+    use std::time::Duration;
+
+    use anyhow::{bail, Context};
+    use lazy_static::lazy_static;
+    use log::warn;
+    use prof::jemalloc::AllocatorSettings;
+
+    use ore::metric;
+    use ore::metrics::{MetricsRegistry, UIntCounter, UIntGaugeVec};
+
+    use super::{AllocatorConfig, AllocatorStats, TrimReport};
+
+    lazy_static! {
+        /// Held while trimming, so that trims never run concurrently.
+        static ref TRIM_LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    /// Applies `config` to the allocator.
+    pub fn apply(config: &Option<AllocatorConfig>) -> Result<(), anyhow::Error> {
+        let config = match config {
+            None => return Ok(()),
+            Some(config) => config,
+        };
+        let mut settings = AllocatorSettings::read().context("reading allocator settings")?;
+        if let Some(narenas) = config.narenas {
+            if narenas != settings.narenas {
+                bail!(
+                    "the allocator was configured with {} arenas, but {} were requested; \
+                     the number of arenas can only be set when the process starts, \
+                     by setting MALLOC_CONF=narenas:{} in its environment",
+                    settings.narenas,
+                    narenas,
+                    narenas,
+                );
+            }
+        }
+        if let Some(background_thread) = config.background_thread {
+            settings.background_thread = background_thread;
+        }
+        if let Some(dirty_decay) = config.dirty_decay {
+            settings.dirty_decay_ms = decay_ms(dirty_decay)?;
+        }
+        if let Some(muzzy_decay) = config.muzzy_decay {
+            settings.muzzy_decay_ms = decay_ms(muzzy_decay)?;
+        }
+        settings.apply().context("applying allocator settings")?;
+        Ok(())
+    }
+
+    fn decay_ms(decay: Duration) -> Result<isize, anyhow::Error> {
+        match isize::try_from(decay.as_millis()) {
+            Ok(ms) => Ok(ms),
+            Err(_) => bail!("allocator decay time {:?} is too large", decay),
+        }
+    }
+
+    /// Describes the allocator's settings, if they can be determined.
+    pub fn describe_settings() -> Option<String> {
+        match AllocatorSettings::read() {
+            Ok(settings) => Some(format!(
+                "background_thread={}, dirty_decay_ms={}, muzzy_decay_ms={}, narenas={}",
+                settings.background_thread,
+                settings.dirty_decay_ms,
+                settings.muzzy_decay_ms,
+                settings.narenas
+            )),
+            Err(e) => {
+                warn!("unable to read allocator settings: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns unused memory to the OS, and records the trim in `metrics`.
+    ///
+    /// Purging every arena is expensive, so only one trim runs at a time.
+    /// Returns `None` without trimming if another trim is in progress.
+    pub fn trim(metrics: &Metrics) -> Result<Option<TrimReport>, anyhow::Error> {
+        let _guard = match TRIM_LOCK.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        };
+        let resident_before = prof::jemalloc::stats()?.resident as u64;
+        prof::jemalloc::purge()?;
+        let resident_after = prof::jemalloc::stats()?.resident as u64;
+        let report = TrimReport {
+            resident_before,
+            resident_after,
+        };
+        metrics.purges.inc();
+        metrics.purged_bytes.inc_by(report.released());
+        metrics.update();
+        Ok(Some(report))
+    }
+
+    /// Returns the number of bytes of memory that the allocator holds
+    /// resident, if it can be determined.
+    pub fn resident() -> Option<u64> {
+        match prof::jemalloc::stats() {
+            Ok(stats) => Some(stats.resident as u64),
+            Err(e) => {
+                warn!("unable to read allocator statistics: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the allocator's statistics, if they can be determined.
+    pub fn stats() -> Option<AllocatorStats> {
+        match prof::jemalloc::stats() {
+            Ok(stats) => Some(AllocatorStats {
+                active: stats.active as u64,
+                allocated: stats.allocated as u64,
+                metadata: stats.metadata as u64,
+                resident: stats.resident as u64,
+                retained: stats.retained as u64,
+            }),
+            Err(e) => {
+                warn!("unable to read allocator statistics: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Allocator metrics.
+    #[derive(Debug, Clone)]
+    pub struct Metrics {
+        /// The allocator's statistics, in bytes.
+        bytes: Option<UIntGaugeVec>,
+        /// The number of times unused memory was returned to the OS on
+        /// request.
+        purges: UIntCounter,
+        /// The number of bytes of unused memory returned to the OS on
+        /// request.
+        purged_bytes: UIntCounter,
+    }
+
+    impl Metrics {
+        /// Registers the metrics, labeled with the allocator's settings.
+        ///
+        /// The settings must already have been applied, so that the labels
+        /// reflect them.
+        pub fn register_with(registry: &MetricsRegistry) -> Metrics {
+            let bytes = AllocatorSettings::read().ok().map(|settings| {
+                registry.register(metric!(
+                    name: "mz_jemalloc_bytes",
+                    help: "statistics of the jemalloc allocator, in bytes",
+                    const_labels: {
+                        "background_thread" => &settings.background_thread.to_string(),
+                        "dirty_decay_ms" => &settings.dirty_decay_ms.to_string(),
+                        "muzzy_decay_ms" => &settings.muzzy_decay_ms.to_string(),
+                        "narenas" => &settings.narenas.to_string()
+                    },
+                    var_labels: ["stat"],
+                ))
+            });
+            Metrics {
+                bytes,
+                purges: registry.register(metric!(
+                    name: "mz_jemalloc_purges_total",
+                    help: "the number of times unused memory was returned to the OS on request",
+                )),
+                purged_bytes: registry.register(metric!(
+                    name: "mz_jemalloc_purged_bytes_total",
+                    help: "the number of bytes of unused memory returned to the OS on request",
+                )),
+            }
+        }
+
+        /// Updates the metrics with the allocator's current statistics.
+        pub fn update(&self) {
+            let bytes = match &self.bytes {
+                Some(bytes) => bytes,
+                None => return,
+            };
+            match prof::jemalloc::stats() {
+                Ok(stats) => {
+                    for (stat, value) in &[
+                        ("active", stats.active),
+                        ("allocated", stats.allocated),
+                        ("metadata", stats.metadata),
+                        ("resident", stats.resident),
+                        ("retained", stats.retained),
+                    ] {
+                        bytes.with_label_values(&[stat]).set(*value as u64);
+                    }
+                }
+                Err(e) => warn!("unable to read allocator statistics: {}", e),
+            }
+        }
+    }
+}
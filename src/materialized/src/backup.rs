@@ -0,0 +1,449 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Online catalog backups.
+//!
+//! A backup is a tarball that contains two entries:
+//!
+//!   * `manifest.json`, a [`Manifest`] that describes the backup.
+//!   * `catalog`, a consistent copy of the catalog database.
+//!
+//! The copy of the catalog is taken by the coordinator, which does not
+//! process other commands while it does so. Everything else, including
+//! building the tarball, happens without involving the coordinator, so that
+//! queries are blocked only for as long as it takes to copy the catalog.
+//!
+//! A backup is restored by [`restore`] before the server boots. Restoring a
+//! backup installs its copy of the catalog in the data directory, after
+//! verifying that the catalog is compatible with this version of the server.
+
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tempfile::{NamedTempFile, TempDir};
+
+use coord::catalog::{self, CatalogSnapshot};
+use coord::CoordError;
+use ore::metric;
+use ore::metrics::{HistogramVec, MetricsRegistry, UIntCounterVec, UIntGauge};
+
+use crate::BUILD_INFO;
+
+/// The version of the backup format described by the [`Manifest`].
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The name of the manifest entry in a backup tarball.
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+
+/// The name of the catalog entry in a backup tarball.
+pub const CATALOG_ENTRY: &str = "catalog";
+
+/// Describes a catalog backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of the backup format.
+    pub format_version: u32,
+    /// The time at which the backup was taken, as an RFC 3339 timestamp.
+    pub created_at: String,
+    /// The ID of the cluster to which the catalog belongs.
+    pub cluster_id: String,
+    /// The version of the catalog's schema.
+    pub catalog_schema_version: u32,
+    /// The version of the catalog's contents.
+    pub catalog_content_version: u32,
+    /// The version of the server that took the backup.
+    pub build_version: String,
+    /// The SHA of the Git commit from which the server was built.
+    pub build_sha: String,
+    /// The time at which the server was built.
+    pub build_time: String,
+    /// The target triple for which the server was built.
+    pub build_target: String,
+}
+
+/// Catalog backup metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// The number of backups, by outcome.
+    backups: UIntCounterVec,
+    /// The duration of each backup, by phase.
+    ///
+    /// The `snapshot` phase is the time for which the catalog was locked,
+    /// while the `total` phase includes writing the tarball.
+    duration: HistogramVec,
+    /// The size of the most recent successful backup.
+    size: UIntGauge,
+    /// The number of backups that are currently being taken or written.
+    in_progress: UIntGauge,
+}
+
+impl Metrics {
+    /// Registers the catalog backup metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            backups: registry.register(metric!(
+                name: "mz_catalog_backups_total",
+                help: "number of catalog backups, by outcome",
+                var_labels: ["status"],
+            )),
+            duration: registry.register(metric!(
+                name: "mz_catalog_backup_duration_seconds",
+                help: "duration of catalog backups, by phase",
+                var_labels: ["phase"],
+            )),
+            size: registry.register(metric!(
+                name: "mz_catalog_backup_size_bytes",
+                help: "size of the most recent catalog backup",
+            )),
+            in_progress: registry.register(metric!(
+                name: "mz_catalog_backups_in_progress",
+                help: "number of catalog backups that are being taken or written",
+            )),
+        }
+    }
+
+    /// Reports whether any backup is currently being taken or written.
+    pub fn in_progress(&self) -> bool {
+        self.in_progress.get() > 0
+    }
+
+    fn record_failure(&self) {
+        self.backups.with_label_values(&["error"]).inc();
+    }
+}
+
+/// A snapshot of the catalog that is ready to be written as a backup.
+///
+/// The backup is reported as in progress until the snapshot is dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    manifest: Manifest,
+    path: PathBuf,
+    start: Instant,
+    metrics: Metrics,
+    // Deletes the copy of the catalog when the snapshot is dropped.
+    _dir: TempDir,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the catalog.
+    ///
+    /// `snapshot_catalog` is invoked with the path to which the coordinator
+    /// should write the copy of the catalog, as by
+    /// [`coord::Handle::snapshot_catalog`]. The copy is written to a
+    /// temporary directory within `scratch_directory`.
+    pub async fn take<F, Fut>(
+        snapshot_catalog: F,
+        scratch_directory: &Path,
+        metrics: &Metrics,
+    ) -> Result<Snapshot, anyhow::Error>
+    where
+        F: FnOnce(PathBuf) -> Fut,
+        Fut: Future<Output = Result<CatalogSnapshot, CoordError>>,
+    {
+        let start = Instant::now();
+        metrics.in_progress.inc();
+        let res = async {
+            let dir = tempfile::tempdir_in(scratch_directory)?;
+            let path = dir.path().join(CATALOG_ENTRY);
+            let catalog = snapshot_catalog(path.clone()).await?;
+            Ok::<_, anyhow::Error>((dir, path, catalog))
+        }
+        .await;
+        let (dir, path, catalog) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                metrics.in_progress.dec();
+                metrics.record_failure();
+                return Err(e);
+            }
+        };
+        metrics
+            .duration
+            .with_label_values(&["snapshot"])
+            .observe(catalog.duration.as_secs_f64());
+        Ok(Snapshot {
+            manifest: Manifest::new(&catalog),
+            path,
+            start,
+            metrics: metrics.clone(),
+            _dir: dir,
+        })
+    }
+
+    /// Writes the snapshot to `w` as a tarball, returning `w`.
+    ///
+    /// This function performs blocking I/O.
+    pub fn write_tarball<W>(self, w: W) -> Result<W, anyhow::Error>
+    where
+        W: Write,
+    {
+        match self.write_tarball_inner(w) {
+            Ok((w, size)) => {
+                self.metrics.backups.with_label_values(&["success"]).inc();
+                self.metrics
+                    .duration
+                    .with_label_values(&["total"])
+                    .observe(self.start.elapsed().as_secs_f64());
+                self.metrics.size.set(size);
+                Ok(w)
+            }
+            Err(e) => {
+                self.metrics.record_failure();
+                Err(e)
+            }
+        }
+    }
+
+    fn write_tarball_inner<W>(&self, w: W) -> Result<(W, u64), anyhow::Error>
+    where
+        W: Write,
+    {
+        write_tarball(&self.manifest, &self.path, w)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.metrics.in_progress.dec();
+    }
+}
+
+impl Manifest {
+    /// Describes a backup of the copy of the catalog described by `catalog`,
+    /// taken now by this server.
+    pub(crate) fn new(catalog: &CatalogSnapshot) -> Manifest {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            created_at: DateTime::<Utc>::from(SystemTime::now())
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            cluster_id: catalog.cluster_id.to_string(),
+            catalog_schema_version: catalog.schema_version,
+            catalog_content_version: catalog.content_version,
+            build_version: BUILD_INFO.version.into(),
+            build_sha: BUILD_INFO.sha.into(),
+            build_time: BUILD_INFO.time.into(),
+            build_target: BUILD_INFO.target_triple.into(),
+        }
+    }
+}
+
+/// Writes a backup tarball with the given manifest and the copy of the
+/// catalog at `catalog_path` to `w`, returning `w` and the number of bytes
+/// written.
+///
+/// This function performs blocking I/O.
+pub(crate) fn write_tarball<W>(
+    manifest: &Manifest,
+    catalog_path: &Path,
+    w: W,
+) -> Result<(W, u64), anyhow::Error>
+where
+    W: Write,
+{
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let mut builder = tar::Builder::new(CountingWriter { inner: w, count: 0 });
+
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    builder.append_data(&mut header, MANIFEST_ENTRY, &manifest[..])?;
+
+    builder.append_path_with_name(catalog_path, CATALOG_ENTRY)?;
+
+    let mut w = builder.into_inner()?;
+    w.flush()?;
+    Ok((w.inner, w.count))
+}
+
+/// Configures restoring a catalog backup.
+#[derive(Debug, Clone)]
+pub struct RestoreConfig {
+    /// The path to the backup tarball.
+    pub path: PathBuf,
+    /// Whether to overwrite the catalog in the data directory, if one exists.
+    pub force: bool,
+    /// What to do with the cluster ID recorded in the backup.
+    pub cluster_id: ClusterIdPolicy,
+}
+
+/// Describes what to do with the cluster ID of a restored catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterIdPolicy {
+    /// The restored server keeps the cluster ID recorded in the backup. This
+    /// is appropriate when the restored server replaces the server that took
+    /// the backup.
+    Keep,
+    /// The restored server generates a new cluster ID. This is appropriate
+    /// when the restored server runs alongside the server that took the
+    /// backup, e.g., when cloning a server.
+    Regenerate,
+}
+
+/// The name of the catalog file in the data directory.
+pub(crate) const CATALOG_FILE: &str = "catalog";
+
+/// Restores the backup described by `config` into `data_directory`,
+/// returning the backup's manifest.
+///
+/// The backup is refused if its format or catalog versions are newer than
+/// those supported by this version of the server, or, unless
+/// [`RestoreConfig::force`] is set, if `data_directory` already contains a
+/// catalog. The restored catalog does not retain the timestamp bindings of
+/// its sources, so sources re-ingest their data from their configured
+/// offsets when the server boots.
+///
+/// This function performs blocking I/O.
+pub fn restore(config: &RestoreConfig, data_directory: &Path) -> Result<Manifest, anyhow::Error> {
+    let catalog_path = data_directory.join(CATALOG_FILE);
+    if catalog_path.exists() && !config.force {
+        bail!(
+            "data directory {} already contains a catalog; \
+             refusing to overwrite it with a restored backup",
+            data_directory.display()
+        );
+    }
+    fs::create_dir_all(data_directory)
+        .with_context(|| format!("creating data directory {}", data_directory.display()))?;
+
+    // Extract the catalog to a temporary file in the data directory, so that
+    // it can be atomically moved into place once it has been verified.
+    let file = File::open(&config.path)
+        .with_context(|| format!("opening backup {}", config.path.display()))?;
+    let mut archive = tar::Archive::new(file);
+    let mut manifest = None;
+    let mut catalog = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path == Path::new(MANIFEST_ENTRY) {
+            let mut buf = vec![];
+            entry.read_to_end(&mut buf)?;
+            let m: Manifest = serde_json::from_slice(&buf).context("parsing backup manifest")?;
+            manifest = Some(m);
+        } else if path == Path::new(CATALOG_ENTRY) {
+            let mut tmp = NamedTempFile::new_in(data_directory)?;
+            io::copy(&mut entry, &mut tmp)?;
+            catalog = Some(tmp);
+        }
+    }
+    let manifest = manifest.ok_or_else(|| anyhow!("backup is missing {}", MANIFEST_ENTRY))?;
+    let catalog = catalog.ok_or_else(|| anyhow!("backup is missing {}", CATALOG_ENTRY))?;
+
+    if manifest.format_version > FORMAT_VERSION {
+        bail!(
+            "backup format version {} is newer than the newest version supported \
+             by materialized {} ({})",
+            manifest.format_version,
+            BUILD_INFO.version,
+            FORMAT_VERSION,
+        );
+    }
+    if manifest.catalog_schema_version > catalog::storage::SCHEMA_VERSION {
+        bail!(
+            "backup catalog schema version {} is newer than the newest version \
+             supported by materialized {} ({}); the backup was taken by \
+             materialized {}",
+            manifest.catalog_schema_version,
+            BUILD_INFO.version,
+            catalog::storage::SCHEMA_VERSION,
+            manifest.build_version,
+        );
+    }
+    if manifest.catalog_content_version > catalog::CONTENT_VERSION {
+        bail!(
+            "backup catalog content version {} is newer than the newest version \
+             supported by materialized {} ({}); the backup was taken by \
+             materialized {}",
+            manifest.catalog_content_version,
+            BUILD_INFO.version,
+            catalog::CONTENT_VERSION,
+            manifest.build_version,
+        );
+    }
+
+    catalog::storage::prepare_restore(
+        catalog.path(),
+        config.cluster_id == ClusterIdPolicy::Regenerate,
+    )
+    .context("preparing restored catalog")?;
+
+    install_catalog(catalog, data_directory)?;
+
+    info!(
+        "restored catalog backup of cluster {} taken at {} by materialized {} ({}); \
+         cluster ID {}",
+        manifest.cluster_id,
+        manifest.created_at,
+        manifest.build_version,
+        manifest.build_sha,
+        match config.cluster_id {
+            ClusterIdPolicy::Keep => "kept",
+            ClusterIdPolicy::Regenerate => "regenerated",
+        },
+    );
+    Ok(manifest)
+}
+
+/// Atomically replaces the catalog in `data_directory` with `catalog`, which
+/// must be a temporary file within `data_directory`.
+///
+/// This function performs blocking I/O.
+pub(crate) fn install_catalog(
+    catalog: NamedTempFile,
+    data_directory: &Path,
+) -> Result<(), anyhow::Error> {
+    // Remove any SQLite journal left behind by the catalog that is being
+    // replaced, as it would otherwise be applied to the new catalog.
+    for suffix in &["-journal", "-wal", "-shm"] {
+        let path = data_directory.join(format!("{}{}", CATALOG_FILE, suffix));
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("removing {}", path.display())),
+        }
+    }
+    let catalog_path = data_directory.join(CATALOG_FILE);
+    catalog
+        .persist(&catalog_path)
+        .with_context(|| format!("installing catalog at {}", catalog_path.display()))?;
+    Ok(())
+}
+
+/// A writer that counts the bytes written to the inner writer.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> Write for CountingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
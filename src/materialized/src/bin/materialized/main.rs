@@ -18,12 +18,10 @@
 //! [0]: https://paper.dropbox.com/doc/Materialize-architecture-plans--AYSu6vvUu7ZDoOEZl7DNi8UQAg-sZj5rhJmISdZSfK0WBxAl
 
 use std::cmp;
-use std::env;
-use std::ffi::CStr;
+use std::convert::TryFrom;
 use std::fmt;
 use std::fs;
-use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::panic;
 use std::panic::PanicInfo;
 use std::path::PathBuf;
@@ -37,19 +35,19 @@ use std::time::Duration;
 use anyhow::{bail, Context};
 use backtrace::Backtrace;
 use clap::AppSettings;
-use itertools::Itertools;
 use lazy_static::lazy_static;
-use log::info;
-use ore::metric;
-use ore::metrics::{IntCounterVec, MetricsRegistry};
+use ore::metrics::MetricsRegistry;
+use sql::parse::RedactionPolicy;
 use structopt::StructOpt;
-use sysinfo::{ProcessorExt, SystemExt};
+use tokio::signal::unix::{signal, SignalKind};
 
-use self::tracing::MetricsRecorderLayer;
-use materialized::TlsMode;
+use materialized::{
+    AffinityPolicy, AllocatorConfig, ByteSize, FlightRecorderConfig, LivenessConfig, LogConfig,
+    LogFormat, LogTarget, LogThrottleConfig, MemoryWatchdogConfig, SyslogConfig, SyslogProtocol,
+    TelemetryMode, TlsMode, TraceExportConfig,
+};
 
 mod sys;
-mod tracing;
 
 type OptionalDuration = Option<Duration>;
 
@@ -60,6 +58,25 @@ fn parse_optional_duration(s: &str) -> Result<OptionalDuration, anyhow::Error> {
     }
 }
 
+fn parse_name_value(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((name, value)) => Ok((name.into(), value.into())),
+        None => bail!("expected NAME=VALUE"),
+    }
+}
+
+fn parse_syslog_facility(s: &str) -> Result<u8, anyhow::Error> {
+    const FACILITIES: &[&str] = &[
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+        "authpriv", "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2",
+        "local3", "local4", "local5", "local6", "local7",
+    ];
+    match FACILITIES.iter().position(|f| *f == s) {
+        Some(code) => Ok(u8::try_from(code).expect("fewer than 256 facilities")),
+        None => bail!("unknown syslog facility {:?}", s),
+    }
+}
+
 /// The streaming SQL materialized view engine.
 #[derive(StructOpt)]
 #[structopt(settings = &[AppSettings::NextLineHelp, AppSettings::UnifiedHelpMessage], usage = "materialized [OPTION]...")]
@@ -71,6 +88,27 @@ struct Args {
     /// dependencies.
     #[structopt(short, long, parse(from_occurrences))]
     version: usize,
+    /// Check the integrity of the data directory and exit.
+    ///
+    /// The findings are printed to stdout as JSON. The exit status is nonzero
+    /// if the data directory cannot be used. The data directory is not
+    /// modified.
+    #[structopt(long)]
+    check: bool,
+    /// Start the server, run a self test of the query path, and exit.
+    ///
+    /// The self test creates a temporary table and materialized view,
+    /// queries them, and scrapes the metrics endpoint. Its outcome is printed
+    /// to stdout as JSON. The exit status is nonzero if any step fails. TLS
+    /// must be disabled.
+    #[structopt(long, conflicts_with = "read-only")]
+    self_test: bool,
+    /// Encrypt the existing, unencrypted catalog in the data directory with
+    /// the configured data encryption key and exit.
+    ///
+    /// materialized must not be running against the data directory.
+    #[structopt(long, conflicts_with = "check")]
+    encrypt_catalog: bool,
     /// Allow running this dev (unoptimized) build.
     #[cfg(debug_assertions)]
     #[structopt(long)]
@@ -89,11 +127,39 @@ struct Args {
     /// (cloud.materialize.com), but may be useful in other contexts as well.
     #[structopt(long, hidden = true)]
     safe: bool,
+    /// Run in read-only mode.
+    ///
+    /// In read-only mode, statements that would modify the catalog or write
+    /// data are rejected, and the data directory is never modified. Sources
+    /// still ingest data, and views are still maintained in memory.
+    #[structopt(long, conflicts_with_all = &["restore-from", "cleanup-orphans"])]
+    read_only: bool,
+    /// Keep the server in maintenance mode across restarts.
+    ///
+    /// By default, the server always starts out of maintenance mode. With
+    /// this option, the maintenance mode is persisted to the data directory
+    /// whenever it is enabled or disabled via the HTTP API.
+    #[structopt(long, conflicts_with = "read-only")]
+    sticky_maintenance_mode: bool,
 
     // === Timely worker configuration. ===
     /// Number of dataflow worker threads.
     #[structopt(short, long, env = "MZ_WORKERS", value_name = "N", default_value)]
     workers: WorkerCount,
+    /// How to pin dataflow worker threads to CPU cores: `none`, `compact`,
+    /// `spread`, or a comma-separated list of core IDs and ranges, with one
+    /// core per worker. Linux only.
+    #[structopt(
+        long,
+        env = "MZ_WORKER_AFFINITY",
+        value_name = "POLICY",
+        default_value = "none"
+    )]
+    worker_affinity: WorkerAffinity,
+    /// The stack size of each dataflow worker thread, like `8MiB`. Defaults
+    /// to the platform's default for new threads.
+    #[structopt(long, env = "MZ_WORKER_STACK_SIZE", value_name = "SIZE")]
+    worker_stack_size: Option<ByteSize>,
     /// Log Timely logging itself.
     #[structopt(long, hidden = true)]
     debug_introspection: bool,
@@ -111,6 +177,59 @@ struct Args {
     /// Set to "off" to disable introspection.
     #[structopt(long, env = "MZ_INTROSPECTION_FREQUENCY", parse(try_from_str = parse_optional_duration), value_name = "FREQUENCY", default_value = "1s")]
     introspection_frequency: OptionalDuration,
+    /// Start with the introspection sources inactive.
+    ///
+    /// The introspection sources exist, but their dataflows are not installed
+    /// until they are activated via the /api/introspection HTTP endpoint.
+    #[structopt(long, env = "MZ_INTROSPECTION_INACTIVE")]
+    introspection_inactive: bool,
+    /// Do not export metrics that describe the memory and load of the host.
+    ///
+    /// The host metrics are refreshed at the introspection frequency.
+    #[structopt(long, env = "MZ_DISABLE_HOST_METRICS")]
+    disable_host_metrics: bool,
+    /// Republish a selection of the introspection sources as Prometheus
+    /// metrics.
+    ///
+    /// The metrics are refreshed at the introspection frequency.
+    #[structopt(long, env = "MZ_INTROSPECTION_METRICS")]
+    introspection_metrics: bool,
+    /// The maximum number of series to export for each metric.
+    ///
+    /// Series with new label sets beyond the limit are aggregated into a
+    /// single series labeled overflow="true". Set to 0 to disable the limit.
+    #[structopt(
+        long,
+        env = "MZ_METRICS_MAX_SERIES",
+        value_name = "N",
+        default_value = "10000"
+    )]
+    metrics_max_series: usize,
+    /// A label to remove from every exported metric.
+    ///
+    /// May be specified multiple times. Series that differ only in the
+    /// removed labels are aggregated.
+    #[structopt(
+        long = "metrics-drop-label",
+        env = "MZ_METRICS_DROP_LABELS",
+        value_name = "LABEL",
+        number_of_values = 1,
+        use_delimiter = true,
+        conflicts_with = "metrics-keep-label"
+    )]
+    metrics_drop_labels: Vec<String>,
+    /// A label to keep on every exported metric, removing all others.
+    ///
+    /// May be specified multiple times. Series that differ only in the
+    /// removed labels are aggregated.
+    #[structopt(
+        long = "metrics-keep-label",
+        env = "MZ_METRICS_KEEP_LABELS",
+        value_name = "LABEL",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    metrics_keep_labels: Vec<String>,
     /// How much historical detail to maintain in arrangements.
     ///
     /// Set to "off" to disable logical compaction.
@@ -119,6 +238,14 @@ struct Args {
     /// Default frequency with which to advance timestamps
     #[structopt(long, env = "MZ_TIMESTAMP_FREQUENCY", hidden = true, parse(try_from_str =repr::util::parse_duration), value_name = "DURATION", default_value = "1s")]
     timestamp_frequency: Duration,
+    /// How far the wall clock may step backward before the step is reported
+    /// as a clock regression.
+    #[structopt(long, env = "MZ_CLOCK_REGRESSION_TOLERANCE", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "1s")]
+    clock_regression_tolerance: Duration,
+    /// Report that the server is not ready after a clock regression, until
+    /// the wall clock catches up.
+    #[structopt(long, env = "MZ_FAIL_READINESS_ON_CLOCK_REGRESSION")]
+    fail_readiness_on_clock_regression: bool,
 
     /// [ADVANCED] Timely progress tracking mode.
     #[structopt(long, env = "MZ_TIMELY_PROGRESS_MODE", value_name = "MODE", possible_values = &["eager", "demand"], default_value = "demand")]
@@ -126,6 +253,137 @@ struct Args {
     /// [ADVANCED] Amount of compaction to perform when idle.
     #[structopt(long, env = "MZ_DIFFERENTIAL_IDLE_MERGE_EFFORT", value_name = "N")]
     differential_idle_merge_effort: Option<isize>,
+    /// [ADVANCED] Whether the memory allocator purges unused memory on
+    /// background threads.
+    #[structopt(long, env = "MZ_ALLOCATOR_BACKGROUND_THREAD", value_name = "BOOL")]
+    allocator_background_thread: Option<bool>,
+    /// [ADVANCED] How long the memory allocator retains unused dirty pages
+    /// before returning them to the OS.
+    #[structopt(long, env = "MZ_ALLOCATOR_DIRTY_DECAY", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION")]
+    allocator_dirty_decay: Option<Duration>,
+    /// [ADVANCED] How long the memory allocator retains unused muzzy pages
+    /// before returning them to the OS.
+    #[structopt(long, env = "MZ_ALLOCATOR_MUZZY_DECAY", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION")]
+    allocator_muzzy_decay: Option<Duration>,
+    /// [ADVANCED] The number of arenas the memory allocator must use.
+    ///
+    /// The number of arenas can only be set when the process starts, with
+    /// MALLOC_CONF=narenas:N. If the allocator uses a different number of
+    /// arenas, materialized refuses to start.
+    #[structopt(long, env = "MZ_ALLOCATOR_NARENAS", value_name = "N")]
+    allocator_narenas: Option<u32>,
+    /// Watch memory usage against a memory limit.
+    ///
+    /// Set to a size, like "8GiB", to watch against that limit, or to "auto"
+    /// to watch against the memory limit of materialized's cgroup. As memory
+    /// usage approaches the limit, materialized logs diagnostics and, if
+    /// requested, sheds load.
+    #[structopt(long, env = "MZ_MEMORY_LIMIT", value_name = "SIZE")]
+    memory_limit: Option<MemoryLimit>,
+    /// The percentage of the memory limit above which memory usage is high.
+    #[structopt(
+        long,
+        env = "MZ_MEMORY_WARNING_THRESHOLD",
+        value_name = "PERCENT",
+        default_value = "80"
+    )]
+    memory_warning_threshold: f64,
+    /// The percentage of the memory limit above which memory usage is
+    /// critical.
+    #[structopt(
+        long,
+        env = "MZ_MEMORY_CRITICAL_THRESHOLD",
+        value_name = "PERCENT",
+        default_value = "95"
+    )]
+    memory_critical_threshold: f64,
+    /// Dump a heap profile to the diagnostics directory when memory usage
+    /// becomes high or critical.
+    #[structopt(long, env = "MZ_MEMORY_HEAP_PROFILES", requires = "memory-limit")]
+    memory_heap_profiles: bool,
+    /// Refuse new SQL connections while memory usage is critical.
+    #[structopt(long, env = "MZ_MEMORY_REFUSE_CONNECTIONS", requires = "memory-limit")]
+    memory_refuse_connections: bool,
+    /// Cancel the queries with the largest dataflows while memory usage is
+    /// critical.
+    #[structopt(long, env = "MZ_MEMORY_CANCEL_QUERIES", requires = "memory-limit")]
+    memory_cancel_queries: bool,
+    /// Refuse to create indexes, materialized views, and materialized sources
+    /// once dataflows are estimated to occupy nearly this much memory.
+    ///
+    /// The estimate is derived from the number of records in the
+    /// arrangements that materialized maintains.
+    #[structopt(long, env = "MZ_DATAFLOW_MEMORY_BUDGET", value_name = "SIZE")]
+    dataflow_memory_budget: Option<ByteSize>,
+    /// Log a warning for new dataflows that would exceed
+    /// --dataflow-memory-budget, rather than refusing to create them.
+    #[structopt(
+        long,
+        env = "MZ_DATAFLOW_MEMORY_BUDGET_WARN_ONLY",
+        requires = "dataflow-memory-budget"
+    )]
+    dataflow_memory_budget_warn_only: bool,
+    /// Record a snapshot of memory usage, the largest arrangements, and the
+    /// active sessions to the diagnostics directory this often.
+    ///
+    /// Snapshots are also recorded whenever the resident set size jumps, as
+    /// configured by --flight-recorder-rss-jump, and are included in the
+    /// diagnostics bundle.
+    #[structopt(long, env = "MZ_FLIGHT_RECORDER_INTERVAL", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION")]
+    flight_recorder_interval: Option<Duration>,
+    /// The percentage by which the resident set size must grow within
+    /// --flight-recorder-rss-window to record an additional snapshot.
+    #[structopt(
+        long,
+        env = "MZ_FLIGHT_RECORDER_RSS_JUMP",
+        value_name = "PERCENT",
+        default_value = "20"
+    )]
+    flight_recorder_rss_jump: f64,
+    /// The window over which growth of the resident set size is measured.
+    #[structopt(long, env = "MZ_FLIGHT_RECORDER_RSS_WINDOW", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "60s")]
+    flight_recorder_rss_window: Duration,
+    /// The maximum number of flight recorder snapshots to keep.
+    #[structopt(
+        long,
+        env = "MZ_FLIGHT_RECORDER_MAX_SNAPSHOTS",
+        value_name = "N",
+        default_value = "20"
+    )]
+    flight_recorder_max_snapshots: usize,
+    /// The maximum total size of the flight recorder snapshots to keep.
+    #[structopt(
+        long,
+        env = "MZ_FLIGHT_RECORDER_MAX_SIZE",
+        value_name = "SIZE",
+        default_value = "64MiB"
+    )]
+    flight_recorder_max_size: ByteSize,
+    /// Dump a heap profile with each flight recorder snapshot.
+    #[structopt(
+        long,
+        env = "MZ_FLIGHT_RECORDER_HEAP_PROFILES",
+        requires = "flight-recorder-interval"
+    )]
+    flight_recorder_heap_profiles: bool,
+    /// Return unused memory to the OS once no statement has executed for
+    /// this long.
+    ///
+    /// The special value "off" disables trimming. Not supported on macOS.
+    #[structopt(long, env = "MZ_MEMORY_TRIM_INTERVAL", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    memory_trim_interval: OptionalDuration,
+    /// [ADVANCED] Adjust how readily the kernel's OOM killer chooses
+    /// materialized, from -1000 (never) to 1000 (first).
+    ///
+    /// Only supported on Linux. Lowering the adjustment requires the
+    /// CAP_SYS_RESOURCE capability.
+    #[structopt(
+        long,
+        env = "MZ_OOM_SCORE_ADJ",
+        value_name = "N",
+        allow_hyphen_values = true
+    )]
+    oom_score_adj: Option<i32>,
 
     // === Logging options. ===
     /// Where to emit log messages.
@@ -134,6 +392,24 @@ struct Args {
     /// stream. All other values are taken as file paths.
     #[structopt(long, env = "MZ_LOG_FILE", value_name = "PATH")]
     log_file: Option<String>,
+    /// The size in bytes beyond which the log file is rotated.
+    ///
+    /// The current log file is renamed by appending ".1" to its name, any
+    /// previously rotated files are shifted up by one, and a new log file is
+    /// started. If unspecified, the log file is never rotated.
+    #[structopt(long, env = "MZ_LOG_FILE_MAX_SIZE", value_name = "BYTES")]
+    log_file_max_size: Option<u64>,
+    /// The number of rotated log files to keep.
+    #[structopt(
+        long,
+        env = "MZ_LOG_FILE_MAX_FILES",
+        value_name = "N",
+        default_value = "5"
+    )]
+    log_file_max_files: usize,
+    /// Whether to gzip rotated log files.
+    #[structopt(long, requires = "log-file-max-size")]
+    log_file_compress: bool,
     /// Which log messages to emit.
     ///
     /// This value is a comma-separated list of filter directives. Each filter
@@ -164,6 +440,311 @@ struct Args {
         default_value = "info"
     )]
     log_filter: String,
+    /// The format in which to emit log messages.
+    ///
+    /// If set to "text", log messages are emitted as human-readable text. If
+    /// set to "json", each log message is emitted as a JSON object on a single
+    /// line with the fields "timestamp", "level", "target", "message",
+    /// "fields", and "spans".
+    #[structopt(
+        long,
+        env = "MZ_LOG_FORMAT",
+        possible_values = &["text", "json"],
+        default_value = "text",
+        value_name = "FORMAT"
+    )]
+    log_format: String,
+    /// Emit log messages to the syslog server at this address instead of to
+    /// a log file.
+    ///
+    /// The address is a host and port, like "syslog.example.com:6514".
+    /// Messages are formatted according to RFC 5424. Warnings and errors are
+    /// additionally emitted to stderr.
+    #[structopt(
+        long,
+        env = "MZ_LOG_SYSLOG_ADDR",
+        value_name = "HOST:PORT",
+        conflicts_with = "log-file"
+    )]
+    log_syslog_addr: Option<String>,
+    /// The protocol with which to send messages to the syslog server.
+    #[structopt(
+        long,
+        env = "MZ_LOG_SYSLOG_PROTOCOL",
+        possible_values = &["udp", "tcp", "tls"],
+        default_value = "udp",
+        value_name = "PROTOCOL"
+    )]
+    log_syslog_protocol: String,
+    /// The facility with which to tag syslog messages, like "daemon" or
+    /// "local0".
+    #[structopt(
+        long,
+        env = "MZ_LOG_SYSLOG_FACILITY",
+        parse(try_from_str = parse_syslog_facility),
+        default_value = "daemon",
+        value_name = "FACILITY"
+    )]
+    log_syslog_facility: u8,
+    /// The application name with which to tag syslog messages.
+    #[structopt(
+        long,
+        env = "MZ_LOG_SYSLOG_APP_NAME",
+        default_value = "materialized",
+        value_name = "NAME"
+    )]
+    log_syslog_app_name: String,
+    /// The period over which repetitions of a log message are counted for
+    /// throttling.
+    ///
+    /// Log messages emitted from the same line of source code at the same
+    /// level are repetitions of one another. Within each period, only the
+    /// first few repetitions of a message are emitted, and a summary of the
+    /// suppressed repetitions is emitted at the end of the period. The special
+    /// value "off" disables throttling.
+    #[structopt(long, env = "MZ_LOG_THROTTLE_WINDOW", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "60s")]
+    log_throttle_window: OptionalDuration,
+    /// The number of repetitions of a log message below the ERROR level to
+    /// emit per throttling period.
+    #[structopt(
+        long,
+        env = "MZ_LOG_THROTTLE_BURST",
+        value_name = "N",
+        default_value = "10"
+    )]
+    log_throttle_burst: u32,
+    /// The number of repetitions of a log message at the ERROR level to emit
+    /// per throttling period.
+    #[structopt(
+        long,
+        env = "MZ_LOG_THROTTLE_ERROR_BURST",
+        value_name = "N",
+        default_value = "100"
+    )]
+    log_throttle_error_burst: u32,
+    /// How to redact SQL text in log messages, slow query log entries, and
+    /// crash reports: "none", "literals", or "full".
+    ///
+    /// The "literals" policy replaces each literal value with a placeholder.
+    /// The "full" policy replaces the entire text of each statement. Errors
+    /// returned to clients are never redacted.
+    #[structopt(
+        long,
+        env = "MZ_LOG_REDACTION",
+        value_name = "POLICY",
+        default_value = "literals"
+    )]
+    log_redaction: RedactionPolicy,
+    /// The size of the in-memory buffer of recent log events that is served
+    /// by the /api/logs HTTP endpoint.
+    ///
+    /// Once the buffer is full, the oldest events are evicted.
+    #[structopt(
+        long,
+        env = "MZ_LOG_BUFFER_SIZE",
+        value_name = "SIZE",
+        default_value = "4MiB"
+    )]
+    log_buffer_size: ByteSize,
+    /// Whether to emit a log message with timing information whenever a
+    /// tracing span closes.
+    #[structopt(long, hidden = true)]
+    log_span_timings: bool,
+    /// Export trace spans to the OTLP/HTTP endpoint at this URL.
+    ///
+    /// Spans are posted to the /v1/traces path beneath the URL, e.g.,
+    /// http://localhost:4318/v1/traces.
+    #[structopt(long, env = "MZ_TRACE_EXPORT_ENDPOINT", value_name = "URL")]
+    trace_export_endpoint: Option<String>,
+    /// The fraction of traces to export, between 0 and 1.
+    #[structopt(
+        long,
+        env = "MZ_TRACE_SAMPLE_RATIO",
+        value_name = "RATIO",
+        default_value = "0.01"
+    )]
+    trace_sample_ratio: f64,
+    /// An attribute that describes this process in exported traces. May be
+    /// specified multiple times.
+    #[structopt(long = "trace-resource-attribute", requires = "trace-export-endpoint", parse(try_from_str = parse_name_value), value_name = "NAME=VALUE", number_of_values = 1)]
+    trace_resource_attributes: Vec<(String, String)>,
+
+    // === Slow query log options. ===
+    /// Log statements that take at least this long to execute.
+    ///
+    /// The special value "off" disables the slow query log. The threshold can
+    /// be changed at runtime via the /api/slow-query-threshold HTTP endpoint.
+    #[structopt(long, env = "MZ_SLOW_QUERY_THRESHOLD", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    slow_query_threshold: OptionalDuration,
+    /// Additionally append slow statements to this file, one JSON object per
+    /// line.
+    #[structopt(long, env = "MZ_SLOW_QUERY_LOG_FILE", value_name = "PATH")]
+    slow_query_log_file: Option<PathBuf>,
+    /// Log TAIL statements that have streamed results for at least this long.
+    ///
+    /// Each statement is logged at most once. The special value "off"
+    /// disables logging.
+    #[structopt(long, env = "MZ_LONG_RUNNING_TAIL_THRESHOLD", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    long_running_tail_threshold: OptionalDuration,
+    /// A key of the statement labels by which to label the statement
+    /// duration metric. May be specified multiple times, or as a
+    /// comma-separated list.
+    ///
+    /// Statements carry labels in a leading comment of the form
+    /// /* key=value, ... */. Labels with other keys are still logged.
+    #[structopt(
+        long = "statement-label-key",
+        env = "MZ_STATEMENT_LABEL_KEYS",
+        value_name = "KEY",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    statement_label_keys: Vec<String>,
+
+    // === Session options. ===
+    /// Cancel statements that run for longer than this.
+    ///
+    /// Sessions may choose a different timeout via the statement_timeout
+    /// session variable, unless --lock-timeouts is specified. The special
+    /// value "off" disables the default timeout.
+    #[structopt(long, env = "MZ_STATEMENT_TIMEOUT", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    statement_timeout: OptionalDuration,
+    /// Terminate sessions that are idle in an open transaction for longer
+    /// than this.
+    ///
+    /// Sessions may choose a different timeout via the
+    /// idle_in_transaction_session_timeout session variable, unless
+    /// --lock-timeouts is specified. The special value "off" disables the
+    /// default timeout.
+    #[structopt(long, env = "MZ_IDLE_IN_TRANSACTION_SESSION_TIMEOUT", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    idle_in_transaction_session_timeout: OptionalDuration,
+    /// Forbid sessions from changing the statement and idle-in-transaction
+    /// timeouts.
+    #[structopt(long, env = "MZ_LOCK_TIMEOUTS")]
+    lock_timeouts: bool,
+    /// Fail statements whose results have more than this many rows.
+    ///
+    /// Sessions may choose a lower limit via the max_result_rows session
+    /// variable.
+    #[structopt(long, env = "MZ_MAX_RESULT_ROWS", value_name = "N")]
+    max_result_rows: Option<u64>,
+    /// Fail statements whose results are larger than this many bytes.
+    ///
+    /// The results of TAIL statements are limited batch by batch. Sessions
+    /// may choose a lower limit via the max_result_bytes session variable.
+    #[structopt(long, env = "MZ_MAX_RESULT_BYTES", value_name = "N")]
+    max_result_bytes: Option<usize>,
+    /// The maximum amount of TAIL updates to buffer for a client that is not
+    /// keeping up with them.
+    ///
+    /// Individual TAIL statements may choose a different limit with the
+    /// buffer_size option.
+    #[structopt(
+        long,
+        env = "MZ_TAIL_BUFFER_SIZE",
+        value_name = "SIZE",
+        default_value = "64MiB"
+    )]
+    tail_buffer_size: ByteSize,
+    /// What a TAIL does when its client falls behind by more than
+    /// --tail-buffer-size: pause until the client catches up (block), or
+    /// fail (error).
+    ///
+    /// Individual TAIL statements may choose a different policy with the
+    /// buffer_policy option.
+    #[structopt(
+        long,
+        env = "MZ_TAIL_BUFFER_POLICY",
+        value_name = "POLICY",
+        possible_values = &["block", "error"],
+        default_value = "block"
+    )]
+    tail_buffer_policy: materialized::TailBufferPolicy,
+    /// The maximum number of users whose statistics are tracked individually.
+    ///
+    /// The statistics of users first seen once the limit is reached are
+    /// tracked together under the user "other".
+    #[structopt(
+        long,
+        env = "MZ_USER_STATS_MAX_USERS",
+        value_name = "N",
+        default_value = "100"
+    )]
+    user_stats_max_users: usize,
+    /// The maximum number of statements that each session may have executing
+    /// at once, or 0 for no limit.
+    ///
+    /// A statement whose portal is suspended partway through its results is
+    /// still executing. Adjustable at runtime via the
+    /// /api/admin/session-quotas HTTP endpoint.
+    #[structopt(
+        long,
+        env = "MZ_MAX_CONCURRENT_STATEMENTS",
+        value_name = "N",
+        default_value = "100"
+    )]
+    max_concurrent_statements: u64,
+    /// The maximum number of named prepared statements that each session may
+    /// hold, or 0 for no limit.
+    #[structopt(
+        long,
+        env = "MZ_MAX_PREPARED_STATEMENTS",
+        value_name = "N",
+        default_value = "10000"
+    )]
+    max_prepared_statements: u64,
+    /// The maximum number of named portals that each session may have open,
+    /// or 0 for no limit.
+    #[structopt(
+        long,
+        env = "MZ_MAX_OPEN_PORTALS",
+        value_name = "N",
+        default_value = "1000"
+    )]
+    max_open_portals: u64,
+    /// Authorize statements according to the policy in this TOML file.
+    ///
+    /// The policy lists which classes of statement (read, write, ddl, and
+    /// admin) each user may execute. Statements that are not authorized fail
+    /// with SQLSTATE 42501.
+    #[structopt(long, env = "MZ_AUTHZ_POLICY_FILE", value_name = "PATH")]
+    authz_policy_file: Option<PathBuf>,
+    /// Send this notice to clients when they connect.
+    ///
+    /// The notice may refer to {version}, {cluster_id}, and {server_name},
+    /// which are replaced with the version of materialized, the ID of the
+    /// cluster, and the host name of the server.
+    #[structopt(
+        long,
+        env = "MZ_CONNECTION_NOTICE",
+        value_name = "TEXT",
+        conflicts_with = "connection-notice-file"
+    )]
+    connection_notice: Option<String>,
+    /// Send the notice in this file to clients when they connect.
+    ///
+    /// The file is reread when the server receives SIGHUP. The notice may
+    /// refer to the same variables as --connection-notice.
+    #[structopt(long, env = "MZ_CONNECTION_NOTICE_FILE", value_name = "PATH")]
+    connection_notice_file: Option<PathBuf>,
+
+    // === Crash report options. ===
+    /// The number of crash reports to keep in the crash directory.
+    ///
+    /// When materialized panics, it writes a crash report to the "crash"
+    /// directory within the data directory. Older reports are deleted. The
+    /// value 0 disables crash reports.
+    #[structopt(
+        long,
+        env = "MZ_MAX_CRASH_REPORTS",
+        value_name = "N",
+        default_value = "10"
+    )]
+    max_crash_reports: usize,
+    /// Leave the entries that may contain the text of SQL statements, namely
+    /// recent logs and crash reports, out of diagnostics bundles.
+    #[structopt(long, env = "MZ_DIAGNOSTICS_EXCLUDE_SQL")]
+    diagnostics_exclude_sql: bool,
 
     // == Connection options.
     /// The address on which to listen for connections.
@@ -174,6 +755,84 @@ struct Args {
         default_value = "0.0.0.0:6875"
     )]
     listen_addr: SocketAddr,
+    /// Take over the listener of the materialized process that is listening
+    /// on this address with the same data directory, rather than binding
+    /// --listen-addr.
+    ///
+    /// The other process stops accepting connections at once, so that some
+    /// process is listening throughout a restart. It then waits up to
+    /// --handoff-drain-timeout for its clients to disconnect and exits, at
+    /// which point this process opens the catalog. Until then, clients that
+    /// connect to this process receive an error that reports that the server
+    /// is starting up.
+    #[structopt(long, env = "MZ_TAKEOVER_FROM", value_name = "HOST:PORT")]
+    takeover_from: Option<SocketAddr>,
+    /// Once another materialized process has taken over the listener, wait
+    /// this long for clients to disconnect before exiting.
+    #[structopt(long, env = "MZ_HANDOFF_DRAIN_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "5m")]
+    handoff_drain_timeout: Duration,
+    /// The local IP address from which to make outbound connections to the
+    /// upstream databases of PostgreSQL sources.
+    ///
+    /// Connections to Kafka, Kinesis, S3, PubNub, and schema registries are
+    /// not bound to this address. By default, the operating system chooses
+    /// the local address.
+    #[structopt(long, env = "MZ_OUTBOUND_BIND_ADDR", value_name = "IP")]
+    outbound_bind_addr: Option<IpAddr>,
+    /// How long a client may take to send enough bytes to determine whether
+    /// it speaks pgwire or HTTP, before its connection is closed.
+    #[structopt(long, env = "MZ_PROTOCOL_DETECT_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "5s")]
+    protocol_detect_timeout: Duration,
+    /// Which protocols to serve on the listen address.
+    ///
+    /// With "multiplexed", both PostgreSQL and HTTP clients are served. With
+    /// "pgwire-only" or "http-only", connections that speak the other
+    /// protocol are closed.
+    #[structopt(
+        long,
+        env = "MZ_PROTOCOL_POLICY",
+        value_name = "POLICY",
+        possible_values = &["multiplexed", "pgwire-only", "http-only"],
+        default_value = "multiplexed"
+    )]
+    protocol_policy: materialized::ProtocolPolicy,
+    /// Limit new connection attempts from each source address to this many
+    /// per second.
+    ///
+    /// Attempts over the limit are rejected before any TLS handshake.
+    /// Connections from loopback addresses are exempt.
+    #[structopt(long, env = "MZ_CONNECTION_RATE_LIMIT", value_name = "N")]
+    connection_rate_limit: Option<f64>,
+    /// The number of connection attempts that each source address may make
+    /// in a burst, beyond --connection-rate-limit.
+    #[structopt(
+        long,
+        env = "MZ_CONNECTION_RATE_BURST",
+        value_name = "N",
+        default_value = "10"
+    )]
+    connection_rate_burst: u32,
+    /// Limit new connection attempts across all source addresses to this many
+    /// per second.
+    #[structopt(
+        long,
+        env = "MZ_CONNECTION_RATE_LIMIT_GLOBAL",
+        value_name = "N",
+        requires = "connection-rate-limit"
+    )]
+    connection_rate_limit_global: Option<f64>,
+    /// What to do with connection attempts over the rate limit.
+    ///
+    /// "drop" closes the connection immediately. "tarpit" holds it open
+    /// for a while first, to slow down clients that wait for a response.
+    #[structopt(
+        long,
+        env = "MZ_CONNECTION_RATE_LIMIT_ACTION",
+        value_name = "ACTION",
+        possible_values = &["drop", "tarpit"],
+        default_value = "drop"
+    )]
+    connection_rate_limit_action: materialized::RateLimitAction,
     /// How stringently to demand TLS authentication and encryption.
     ///
     /// If set to "disable", then materialized rejects HTTP and PostgreSQL
@@ -231,6 +890,159 @@ struct Args {
         value_name = "PATH"
     )]
     tls_key: Option<PathBuf>,
+    /// Warn when a client that is not on the local host establishes a
+    /// PostgreSQL session without TLS encryption.
+    ///
+    /// Warnings are emitted at most once a minute. Sessions are counted by
+    /// transport in the mz_server_connections_active metric regardless.
+    #[structopt(long, env = "MZ_WARN_ON_PLAINTEXT")]
+    warn_on_plaintext: bool,
+    /// [ADVANCED] How many bytes to accumulate before writing to a PostgreSQL
+    /// connection. Defaults to 8KiB.
+    #[structopt(long, env = "MZ_PGWIRE_BUFFER_SIZE", value_name = "BYTES")]
+    pgwire_buffer_size: Option<usize>,
+    /// [ADVANCED] The initial size, in bytes, of the buffer into which
+    /// messages from PostgreSQL clients are read. Defaults to 8KiB.
+    #[structopt(long, env = "MZ_PGWIRE_READ_BUFFER_SIZE", value_name = "BYTES")]
+    pgwire_read_buffer_size: Option<usize>,
+    /// [ADVANCED] How long a PostgreSQL connection must be idle before its
+    /// buffers shrink back to their initial sizes. By default, buffers never
+    /// shrink.
+    #[structopt(long, env = "MZ_PGWIRE_BUFFER_SHRINK_AFTER", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION")]
+    pgwire_buffer_shrink_after: Option<Duration>,
+    /// [ADVANCED] The PostgreSQL version to report to clients, like 13.4 or
+    /// 9.6.2, for clients that enable features based on the server version.
+    /// Defaults to 9.5.0.
+    #[structopt(long, env = "MZ_PGWIRE_SERVER_VERSION", value_name = "VERSION")]
+    pgwire_server_version: Option<String>,
+    /// [ADVANCED] The maximum size of a message from a PostgreSQL client.
+    /// Clients that send larger messages receive an error and are
+    /// disconnected.
+    #[structopt(
+        long,
+        env = "MZ_PGWIRE_MAX_MESSAGE_SIZE",
+        value_name = "SIZE",
+        default_value = "64MiB"
+    )]
+    pgwire_max_message_size: ByteSize,
+    /// The maximum number of PostgreSQL sessions to serve at once.
+    ///
+    /// Unlimited by default.
+    #[structopt(long, env = "MZ_MAX_CONNECTIONS", value_name = "N")]
+    max_connections: Option<usize>,
+    /// The number of sessions out of --max-connections that only superusers
+    /// may take.
+    #[structopt(
+        long,
+        env = "MZ_RESERVED_SUPERUSER_CONNECTIONS",
+        value_name = "N",
+        default_value = "3"
+    )]
+    reserved_superuser_connections: usize,
+    /// A user who may take the sessions reserved by
+    /// --reserved-superuser-connections. May be specified multiple times, or
+    /// as a comma-separated list.
+    #[structopt(
+        long = "superuser",
+        env = "MZ_SUPERUSERS",
+        value_name = "USER",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    superusers: Vec<String>,
+    /// The maximum number of HTTP requests to serve concurrently.
+    ///
+    /// Further requests wait for a turn, and are rejected with status 429 if
+    /// the queue is full or if they wait too long. Requests to the /metrics,
+    /// /status, and /api/status endpoints are exempt. Unlimited by default.
+    #[structopt(long, env = "MZ_HTTP_MAX_CONCURRENT_REQUESTS", value_name = "N")]
+    http_max_concurrent_requests: Option<usize>,
+    /// The number of HTTP requests beyond --http-max-concurrent-requests that
+    /// may wait to be served.
+    #[structopt(
+        long,
+        env = "MZ_HTTP_REQUEST_QUEUE_SIZE",
+        value_name = "N",
+        default_value = "100"
+    )]
+    http_request_queue_size: usize,
+    /// How long an HTTP request beyond --http-max-concurrent-requests may
+    /// wait to be served.
+    #[structopt(long, env = "MZ_HTTP_REQUEST_QUEUE_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "5s")]
+    http_request_queue_timeout: Duration,
+    /// The maximum amount of memory that results buffered by requests to the
+    /// /sql endpoint may occupy at once.
+    ///
+    /// A request that would exceed the limit waits briefly for other requests
+    /// to finish, and then fails with status 507. Unlimited by default.
+    #[structopt(long, env = "MZ_HTTP_RESULT_MEMORY_LIMIT", value_name = "SIZE")]
+    http_result_memory_limit: Option<ByteSize>,
+    /// The maximum number of rows in each record batch of results that the
+    /// /sql endpoint returns in the Arrow format.
+    ///
+    /// Requests may ask for smaller batches with the batch_size parameter,
+    /// but not for larger ones.
+    #[structopt(
+        long,
+        env = "MZ_HTTP_ARROW_MAX_BATCH_SIZE",
+        value_name = "N",
+        default_value = "65536"
+    )]
+    http_arrow_max_batch_size: usize,
+    /// Speak HTTP/2 over plaintext connections to clients that know in
+    /// advance that the server supports it.
+    ///
+    /// HTTP/2 is always available over TLS, to clients that negotiate it.
+    #[structopt(long, env = "MZ_HTTP2_CLEARTEXT")]
+    http2_cleartext: bool,
+    /// Close HTTP/1 keep-alive connections that are idle for this long.
+    ///
+    /// A duration of 0s disables keep-alive. The special value "off" leaves
+    /// idle connections open until the client closes them.
+    #[structopt(long, env = "MZ_HTTP1_KEEP_ALIVE_TIMEOUT", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    http1_keep_alive_timeout: OptionalDuration,
+    /// Close HTTP/1 connections once they have served this many requests.
+    #[structopt(long, env = "MZ_HTTP1_MAX_REQUESTS_PER_CONNECTION", value_name = "N")]
+    http1_max_requests_per_connection: Option<usize>,
+    /// Shut down once no client has been connected for this long.
+    ///
+    /// Connections of every kind, whether PostgreSQL or HTTP, count. The
+    /// special value "off" disables idle shutdown.
+    #[structopt(long, env = "MZ_IDLE_EXIT_AFTER", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    idle_exit_after: OptionalDuration,
+    /// When asked to terminate, keep refusing new connections for this long
+    /// before exiting.
+    ///
+    /// During the grace period, clients that connect receive an error that
+    /// reports that the server is shutting down, rather than finding nothing
+    /// listening.
+    #[structopt(long, env = "MZ_DRAIN_GRACE_PERIOD", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "0s")]
+    drain_grace_period: Duration,
+    /// How often to probe the coordinator to detect that it has stopped
+    /// responding.
+    #[structopt(long, env = "MZ_COORD_PROBE_INTERVAL", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "5s")]
+    coord_probe_interval: Duration,
+    /// How long to wait for the coordinator to answer a probe.
+    #[structopt(long, env = "MZ_COORD_PROBE_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "10s")]
+    coord_probe_timeout: Duration,
+    /// The number of consecutive probes that must time out before the
+    /// coordinator is considered unresponsive.
+    #[structopt(
+        long,
+        env = "MZ_COORD_PROBE_FAILURES",
+        value_name = "N",
+        default_value = "3"
+    )]
+    coord_probe_failures: usize,
+    /// Exit once the coordinator is unresponsive, so that a supervisor can
+    /// restart materialized.
+    #[structopt(long, env = "MZ_EXIT_ON_UNRESPONSIVE_COORD")]
+    exit_on_unresponsive_coord: bool,
+    /// Give up on starting if the server has not started after this long.
+    ///
+    /// The special value "off" allows startup to take arbitrarily long.
+    #[structopt(long, env = "MZ_STARTUP_TIMEOUT", parse(try_from_str = parse_optional_duration), value_name = "DURATION", default_value = "off")]
+    startup_timeout: OptionalDuration,
 
     // === Storage options. ===
     /// Where to store data.
@@ -242,15 +1054,174 @@ struct Args {
         default_value = "mzdata"
     )]
     data_directory: PathBuf,
+    /// Where to store the catalog.
+    ///
+    /// Defaults to the data directory. The catalog is small but must be
+    /// durable, so this directory is typically placed on replicated storage.
+    #[structopt(long, env = "MZ_CATALOG_DIRECTORY", value_name = "PATH")]
+    catalog_directory: Option<PathBuf>,
+    /// Where to store the data of the sources and sinks in the catalog.
+    ///
+    /// Defaults to the data directory. The directory is marked as belonging
+    /// to the catalog, and materialized refuses to start with a storage
+    /// directory that belongs to another catalog.
+    #[structopt(long, env = "MZ_STORAGE_DIRECTORY", value_name = "PATH")]
+    storage_directory: Option<PathBuf>,
+    /// Where to store temporary files that need not survive a restart.
+    ///
+    /// Defaults to the "scratch" directory within the data directory. The
+    /// contents of the directory are deleted when the server starts, so it
+    /// must not be shared with other programs.
+    #[structopt(long, env = "MZ_SCRATCH_DIRECTORY", value_name = "PATH")]
+    scratch_directory: Option<PathBuf>,
+    /// Restore the catalog from a backup tarball before starting.
+    ///
+    /// Backups are taken via the /api/catalog/backup HTTP endpoint. Sources
+    /// in the restored catalog re-ingest their data from their configured
+    /// offsets.
+    #[structopt(
+        long,
+        env = "MZ_RESTORE_FROM",
+        value_name = "PATH",
+        requires = "restore-cluster-id"
+    )]
+    restore_from: Option<PathBuf>,
+    /// Whether a restored catalog keeps the cluster ID recorded in the backup
+    /// or generates a new one.
+    ///
+    /// Keep the cluster ID when the restored server replaces the server that
+    /// took the backup. Regenerate it when both servers will run at once.
+    #[structopt(
+        long,
+        env = "MZ_RESTORE_CLUSTER_ID",
+        possible_values = &["keep", "regenerate"],
+        value_name = "POLICY",
+        requires = "restore-from"
+    )]
+    restore_cluster_id: Option<String>,
+    /// Allow --restore-from to overwrite the catalog in the data directory.
+    #[structopt(long, env = "MZ_RESTORE_FORCE", requires = "restore-from")]
+    restore_force: bool,
+    /// Boot as a warm standby from the catalog replicated to the specified
+    /// directory or HTTP(S) URL by --catalog-replica.
+    ///
+    /// The replicated catalog replaces any catalog in the data directory and
+    /// keeps the cluster ID of the server that replicated it. Sources in the
+    /// restored catalog re-ingest their data from their configured offsets.
+    #[structopt(
+        long,
+        env = "MZ_FROM_REPLICA",
+        value_name = "TARGET",
+        conflicts_with_all = &["restore-from", "read-only"]
+    )]
+    from_replica: Option<materialized::ReplicaTarget>,
+    /// Allow migrating a catalog written by an older version of materialized.
+    ///
+    /// Without this option, materialized refuses to start if the catalog
+    /// requires migration, and reports which migrations would run. A copy of
+    /// the catalog from before the migration is kept in the data directory.
+    #[structopt(long, env = "MZ_ALLOW_CATALOG_MIGRATION")]
+    allow_catalog_migration: bool,
+    /// How durably to store writes to the catalog.
+    ///
+    /// "full" syncs each write to disk before acknowledging it. "async" syncs
+    /// writes in batches once per second, and so may lose the most recent
+    /// writes if the host crashes. "none" never syncs writes and is suitable
+    /// only for throwaway instances.
+    #[structopt(
+        long,
+        env = "MZ_DURABILITY",
+        possible_values = &["full", "async", "none"],
+        value_name = "LEVEL",
+        default_value = "full"
+    )]
+    durability: materialized::DurabilityLevel,
+    /// How often to compact the catalog.
+    ///
+    /// Compaction rewrites the catalog to reclaim the space left behind by
+    /// dropped objects. It is skipped while a catalog backup is in progress,
+    /// and can also be triggered via POST /api/admin/catalog/compact.
+    #[structopt(long, env = "MZ_CATALOG_COMPACT_INTERVAL", parse(try_from_str = parse_optional_duration), value_name = "INTERVAL", default_value = "off")]
+    catalog_compact_interval: OptionalDuration,
+    /// How often to rescan the data directory for orphaned files.
+    ///
+    /// The data directory is always scanned at startup. Orphaned files are
+    /// files that belong neither to materialized nor to any source or sink in
+    /// the catalog. They are reported in the log and via metrics.
+    #[structopt(long, env = "MZ_ORPHAN_SCAN_INTERVAL", parse(try_from_str = parse_optional_duration), value_name = "INTERVAL", default_value = "off")]
+    orphan_scan_interval: OptionalDuration,
+    /// Move orphaned files in the data directory to its "quarantine"
+    /// directory, and delete them after --orphan-retention.
+    #[structopt(long, env = "MZ_CLEANUP_ORPHANS")]
+    cleanup_orphans: bool,
+    /// How long to keep quarantined orphaned files before deleting them.
+    #[structopt(
+        long,
+        env = "MZ_ORPHAN_RETENTION",
+        parse(try_from_str = repr::util::parse_duration),
+        value_name = "DURATION",
+        default_value = "7d"
+    )]
+    orphan_retention: Duration,
+    /// Encrypt the catalog at rest with the key in the specified file.
+    ///
+    /// The file must contain either exactly 32 bytes or their encoding as 64
+    /// hexadecimal digits.
+    #[structopt(
+        long,
+        env = "MZ_DATA_ENCRYPTION_KEY_FILE",
+        value_name = "PATH",
+        conflicts_with_all = &["data-encryption-key-env", "data-encryption-key-command"]
+    )]
+    data_encryption_key_file: Option<PathBuf>,
+    /// Encrypt the catalog at rest with the key in the specified environment
+    /// variable, encoded as 64 hexadecimal digits.
+    #[structopt(
+        long,
+        env = "MZ_DATA_ENCRYPTION_KEY_ENV",
+        value_name = "VAR",
+        conflicts_with = "data-encryption-key-command"
+    )]
+    data_encryption_key_env: Option<String>,
+    /// Encrypt the catalog at rest with the key that the specified shell
+    /// command prints, encoded as 64 hexadecimal digits.
+    #[structopt(long, env = "MZ_DATA_ENCRYPTION_KEY_COMMAND", value_name = "COMMAND")]
+    data_encryption_key_command: Option<String>,
+    /// Replicate the catalog to the specified directory or HTTP(S) URL
+    /// whenever it changes, so that a warm standby can boot from it with
+    /// --from-replica.
+    ///
+    /// Copies of the catalog are uploaded to URLs with PUT requests.
+    /// Failures to replicate are logged and retried, and never block DDL.
+    #[structopt(long, env = "MZ_CATALOG_REPLICA", value_name = "TARGET")]
+    catalog_replica: Option<materialized::ReplicaTarget>,
     /// Enable symbioisis with a PostgreSQL server.
     #[structopt(long, env = "MZ_SYMBIOSIS", hidden = true)]
     symbiosis: Option<String>,
 
+    // === Instance metadata options. ===
+    /// A static label to attach to server metadata metrics and telemetry
+    /// reports, like "environment=prod".
+    ///
+    /// May be specified multiple times. Label names must be valid Prometheus
+    /// label names and may not begin with "mz_" or "__".
+    #[structopt(long = "instance-label", parse(try_from_str = parse_name_value), value_name = "NAME=VALUE", number_of_values = 1)]
+    instance_labels: Vec<(String, String)>,
+    /// A name that identifies this server among a fleet of servers, like
+    /// "mz-prod-1".
+    ///
+    /// The name is reported to clients, attached to errors and to server
+    /// metadata metrics, and included in lifecycle log events. It may contain
+    /// only ASCII letters, digits, ".", "-", and "_", and may be at most 63
+    /// characters long.
+    #[structopt(long, env = "MZ_SERVER_NAME", value_name = "NAME")]
+    server_name: Option<String>,
+
     // === Telemetry options. ===
     // TODO(benesch): add an environment variable once we upgrade to clap v3.
     // Doesn't presently work in clap v2. See: clap-rs/clap#1476.
     /// Disable telemetry reporting.
-    #[structopt(long, conflicts_with_all = &["telemetry-domain", "telemetry-interval"])]
+    #[structopt(long, conflicts_with_all = &["telemetry-domain", "telemetry-interval", "telemetry-mode", "telemetry-log-file", "telemetry-spool-dir"])]
     disable_telemetry: bool,
     /// The domain hosting the telemetry server.
     #[structopt(long, env = "MZ_TELEMETRY_DOMAIN", hidden = true)]
@@ -258,6 +1229,111 @@ struct Args {
     /// The interval at which to report telemetry data.
     #[structopt(long, env = "MZ_TELEMETRY_INTERVAL", parse(try_from_str = repr::util::parse_duration), hidden = true)]
     telemetry_interval: Option<Duration>,
+    /// What to do with each telemetry report.
+    ///
+    /// If set to "log", the exact request that would be sent to the telemetry
+    /// server is written to the log (or to --telemetry-log-file, if specified)
+    /// instead, and no network requests are made. If set to "send-and-log",
+    /// the request is both sent and logged; if sending is retried, only the
+    /// last attempt is logged. If set to "spool", the report is spooled in
+    /// --telemetry-spool-dir for later export instead, and no network
+    /// requests are made.
+    #[structopt(long, env = "MZ_TELEMETRY_MODE", possible_values = &["send", "log", "send-and-log", "spool"], value_name = "MODE", hidden = true)]
+    telemetry_mode: Option<String>,
+    /// The maximum time to wait to connect to the telemetry server.
+    #[structopt(long, env = "MZ_TELEMETRY_CONNECT_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "10s", hidden = true)]
+    telemetry_connect_timeout: Duration,
+    /// The maximum time to wait for the telemetry server to respond.
+    #[structopt(long, env = "MZ_TELEMETRY_REQUEST_TIMEOUT", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "10s", hidden = true)]
+    telemetry_request_timeout: Duration,
+    /// Derive telemetry reporting delays from the cluster ID rather than
+    /// choosing them randomly.
+    #[structopt(long, hidden = true)]
+    deterministic_telemetry_splay: bool,
+    /// The URL of a proxy through which to send telemetry reports.
+    ///
+    /// If unspecified, the standard HTTPS_PROXY, ALL_PROXY, and NO_PROXY
+    /// environment variables are obeyed.
+    #[structopt(long, env = "MZ_TELEMETRY_PROXY", value_name = "URL", hidden = true)]
+    telemetry_proxy: Option<String>,
+    /// The user name with which to authenticate to the telemetry proxy.
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_PROXY_USERNAME",
+        requires = "telemetry-proxy",
+        value_name = "USERNAME",
+        hidden = true
+    )]
+    telemetry_proxy_username: Option<String>,
+    /// The password with which to authenticate to the telemetry proxy.
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_PROXY_PASSWORD",
+        requires = "telemetry-proxy-username",
+        value_name = "PASSWORD",
+        hidden = true
+    )]
+    telemetry_proxy_password: Option<String>,
+    /// A comma-separated list of hosts for which to bypass the telemetry
+    /// proxy.
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_NO_PROXY",
+        requires = "telemetry-proxy",
+        value_name = "HOSTS",
+        use_delimiter = true,
+        hidden = true
+    )]
+    telemetry_no_proxy: Vec<String>,
+    /// The file to which logged telemetry reports are appended.
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_LOG_FILE",
+        value_name = "PATH",
+        hidden = true
+    )]
+    telemetry_log_file: Option<PathBuf>,
+    /// The directory in which to spool telemetry reports that fail to send.
+    ///
+    /// Spooled reports are sent once the telemetry server is reachable
+    /// again, and can be downloaded from the /api/admin/telemetry/spool HTTP
+    /// endpoint. A relative path is resolved against the data directory.
+    /// Defaults to "telemetry_spool" if --telemetry-mode is "spool".
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_SPOOL_DIR",
+        value_name = "PATH",
+        hidden = true
+    )]
+    telemetry_spool_dir: Option<PathBuf>,
+    /// The maximum total size of the spooled telemetry reports. The oldest
+    /// reports are dropped to make room for new reports.
+    #[structopt(
+        long,
+        env = "MZ_TELEMETRY_SPOOL_MAX_SIZE",
+        value_name = "SIZE",
+        default_value = "64MiB",
+        hidden = true
+    )]
+    telemetry_spool_max_size: ByteSize,
+
+    // === Version check options. ===
+    /// Periodically check this URL for new releases.
+    ///
+    /// The request carries only the running version. Whether an upgrade is
+    /// available is reported in the mz_server_upgrade_available metric and
+    /// the /api/status HTTP endpoint.
+    #[structopt(long, env = "MZ_VERSION_CHECK_URL", value_name = "URL")]
+    version_check_url: Option<String>,
+    /// How often to check for new releases.
+    #[structopt(long, env = "MZ_VERSION_CHECK_INTERVAL", parse(try_from_str = repr::util::parse_duration), value_name = "DURATION", default_value = "24h")]
+    version_check_interval: Duration,
+
+    // === Testing options. ===
+    /// Enable fault injection via the /api/failpoints HTTP endpoint.
+    #[cfg(feature = "failpoints")]
+    #[structopt(long, hidden = true)]
+    fault_injection: bool,
 }
 
 /// This type is a hack to allow a dynamic default for the `--workers` argument,
@@ -288,6 +1364,34 @@ impl fmt::Display for WorkerCount {
     }
 }
 
+/// The `--worker-affinity` argument, which adds `none` to the policies that
+/// [`AffinityPolicy`] parses.
+struct WorkerAffinity(Option<AffinityPolicy>);
+
+impl FromStr for WorkerAffinity {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<WorkerAffinity, anyhow::Error> {
+        match s {
+            "none" => Ok(WorkerAffinity(None)),
+            _ => Ok(WorkerAffinity(Some(s.parse()?))),
+        }
+    }
+}
+
+/// The `--memory-limit` argument, which adds `auto` to the sizes that
+/// [`ByteSize`] parses.
+struct MemoryLimit(Option<ByteSize>);
+
+impl FromStr for MemoryLimit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<MemoryLimit, anyhow::Error> {
+        match s {
+            "auto" => Ok(MemoryLimit(None)),
+            _ => Ok(MemoryLimit(Some(s.parse()?))),
+        }
+    }
+}
+
 fn main() {
     if let Err(err) = run(Args::from_args()) {
         eprintln!("materialized: {:#}", err);
@@ -296,20 +1400,76 @@ fn main() {
 }
 
 fn run(args: Args) -> Result<(), anyhow::Error> {
-    panic::set_hook(Box::new(handle_panic));
+    let log_format = match args.log_format.as_str() {
+        "text" => LogFormat::Text,
+        "json" => LogFormat::Json,
+        _ => unreachable!(),
+    };
+    panic::set_hook(Box::new(move |panic_info| {
+        handle_panic(panic_info, log_format)
+    }));
     sys::enable_sigbus_sigsegv_backtraces()?;
     sys::enable_termination_signal_cleanup()?;
 
     if args.version > 0 {
         println!("materialized {}", materialized::BUILD_INFO.human_version());
         if args.version > 1 {
-            for bi in build_info() {
+            for bi in materialized::dependency_versions() {
                 println!("{}", bi);
             }
         }
         return Ok(());
     }
 
+    let data_encryption = match (
+        args.data_encryption_key_file,
+        args.data_encryption_key_env,
+        args.data_encryption_key_command,
+    ) {
+        (Some(path), None, None) => Some(materialized::KeySource::File(path)),
+        (None, Some(var), None) => Some(materialized::KeySource::Env(var)),
+        (None, None, Some(command)) => Some(materialized::KeySource::Command(command)),
+        (None, None, None) => None,
+        _ => unreachable!(),
+    }
+    .map(|key_source| materialized::EncryptionConfig { key_source });
+
+    if args.encrypt_catalog {
+        let data_encryption = match &data_encryption {
+            Some(data_encryption) => data_encryption,
+            None => bail!(
+                "--encrypt-catalog requires --data-encryption-key-file, \
+                 --data-encryption-key-env, or --data-encryption-key-command"
+            ),
+        };
+        let catalog_directory = args
+            .catalog_directory
+            .as_deref()
+            .unwrap_or(&args.data_directory);
+        materialized::encrypt_catalog(catalog_directory, data_encryption)?;
+        println!(
+            "encrypted catalog in catalog directory {}",
+            catalog_directory.display()
+        );
+        return Ok(());
+    }
+
+    if args.check {
+        let catalog_directory = args
+            .catalog_directory
+            .as_deref()
+            .unwrap_or(&args.data_directory);
+        let report = materialized::check(catalog_directory, data_encryption.as_ref())?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.is_ok() {
+            bail!(
+                "catalog directory {} failed integrity check",
+                catalog_directory.display()
+            );
+        }
+        return Ok(());
+    }
+
     // Prevent accidental usage of development builds.
     //
     // TODO(benesch): offload environment variable check to clap once we upgrade
@@ -332,12 +1492,18 @@ fn run(args: Args) -> Result<(), anyhow::Error> {
             granularity,
             log_logging,
             retain_readings_for,
+            active: !args.introspection_inactive,
         });
     if log_logging && logging.is_none() {
         bail!(
             "cannot specify --debug-introspection and --introspection-frequency=off simultaneously"
         );
     }
+    if args.introspection_inactive && logging.is_none() {
+        bail!(
+            "cannot specify --introspection-inactive and --introspection-frequency=off simultaneously"
+        );
+    }
 
     // Configure connections.
     let tls = if args.tls_mode == "disable" {
@@ -376,9 +1542,28 @@ fn run(args: Args) -> Result<(), anyhow::Error> {
     let data_directory = args.data_directory;
     fs::create_dir_all(&data_directory)
         .with_context(|| format!("creating data directory: {}", data_directory.display()))?;
+    for (purpose, directory) in &[
+        ("catalog", &args.catalog_directory),
+        ("storage", &args.storage_directory),
+    ] {
+        if let Some(directory) = directory {
+            fs::create_dir_all(directory).with_context(|| {
+                format!("creating {} directory: {}", purpose, directory.display())
+            })?;
+        }
+    }
+    let restore = args.restore_from.map(|path| materialized::RestoreConfig {
+        path,
+        force: args.restore_force,
+        cluster_id: match args.restore_cluster_id.as_deref() {
+            Some("keep") => materialized::ClusterIdPolicy::Keep,
+            Some("regenerate") => materialized::ClusterIdPolicy::Regenerate,
+            _ => unreachable!(),
+        },
+    });
 
     // If --disable-telemetry is present, disable telemetry. Otherwise, if a
-    // custom telemetry domain or interval is provided, enable telemetry as
+    // custom telemetry domain, interval, or mode is provided, enable telemetry as
     // specified. Otherwise (the defaults), enable the production server for
     // release mode and disable telemetry in debug mode. This should allow for
     // good defaults (on in release, off in debug), but also easy development
@@ -386,10 +1571,19 @@ fn run(args: Args) -> Result<(), anyhow::Error> {
     let telemetry = if args.disable_telemetry
         || (cfg!(debug_assertions)
             && args.telemetry_domain.is_none()
-            && args.telemetry_interval.is_none())
+            && args.telemetry_interval.is_none()
+            && args.telemetry_mode.is_none()
+            && args.telemetry_spool_dir.is_none())
     {
         None
     } else {
+        let mode = match args.telemetry_mode.as_deref() {
+            None | Some("send") => TelemetryMode::Send,
+            Some("log") => TelemetryMode::Log,
+            Some("send-and-log") => TelemetryMode::SendAndLog,
+            Some("spool") => TelemetryMode::Spool,
+            _ => unreachable!(),
+        };
         Some(materialized::TelemetryConfig {
             domain: args
                 .telemetry_domain
@@ -397,136 +1591,95 @@ fn run(args: Args) -> Result<(), anyhow::Error> {
             interval: args
                 .telemetry_interval
                 .unwrap_or_else(|| Duration::from_secs(3600)),
+            mode,
+            deterministic_splay: args.deterministic_telemetry_splay,
+            connect_timeout: args.telemetry_connect_timeout,
+            request_timeout: args.telemetry_request_timeout,
+            proxy: args
+                .telemetry_proxy
+                .map(|url| materialized::TelemetryProxyConfig {
+                    url,
+                    username: args.telemetry_proxy_username,
+                    password: args.telemetry_proxy_password,
+                    no_proxy: args.telemetry_no_proxy,
+                }),
+            log_file: args.telemetry_log_file,
+            spool_dir: match (args.telemetry_spool_dir, mode) {
+                (None, TelemetryMode::Spool) => {
+                    Some(materialized::DEFAULT_TELEMETRY_SPOOL_DIR.into())
+                }
+                (spool_dir, _) => spool_dir,
+            },
+            spool_max_size: args.telemetry_spool_max_size.as_u64(),
         })
     };
 
-    let metrics_registry = MetricsRegistry::new();
-    // Configure tracing.
-    {
-        use tracing_subscriber::filter::{EnvFilter, LevelFilter};
-        use tracing_subscriber::fmt;
-        use tracing_subscriber::layer::SubscriberExt;
-        use tracing_subscriber::util::SubscriberInitExt;
-
-        use crate::tracing::FilterLayer;
-
-        let env_filter = EnvFilter::try_new(args.log_filter)
-            .context("parsing --log-filter option")?
-            // Ensure panics are logged, even if the user has specified
-            // otherwise.
-            .add_directive("panic=error".parse().unwrap());
-
-        let log_message_counter: IntCounterVec = metrics_registry.register(metric!(
-            name: "mz_log_message_total",
-            help: "The number of log messages produced by this materialized instance",
-            var_labels: ["severity"],
-        ));
-
-        match args.log_file.as_deref() {
-            Some("stderr") => {
-                // The user explicitly directed logs to stderr. Log only to stderr
-                // with the user-specified `env_filter`.
-                tracing_subscriber::registry()
-                    .with(MetricsRecorderLayer::new(log_message_counter))
-                    .with(env_filter)
-                    .with(
-                        fmt::layer()
-                            .with_writer(io::stderr)
-                            .with_ansi(atty::is(atty::Stream::Stderr)),
-                    )
-                    .init()
-            }
-            log_file => {
-                // Logging to a file. If the user did not explicitly specify
-                // a file, bubble up warnings and errors to stderr.
-                let stderr_level = match log_file {
-                    Some(_) => LevelFilter::OFF,
-                    None => LevelFilter::WARN,
-                };
-                tracing_subscriber::registry()
-                    .with(MetricsRecorderLayer::new(log_message_counter))
-                    .with(env_filter)
-                    .with({
-                        let path = match log_file {
-                            Some(log_file) => PathBuf::from(log_file),
-                            None => data_directory.join("materialized.log"),
-                        };
-                        if let Some(parent) = path.parent() {
-                            fs::create_dir_all(parent).with_context(|| {
-                                format!("creating log file directory: {}", parent.display())
-                            })?;
-                        }
-                        let file = fs::OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(&path)
-                            .with_context(|| format!("creating log file: {}", path.display()))?;
-                        fmt::layer().with_ansi(false).with_writer(move || {
-                            file.try_clone().expect("failed to clone log file")
-                        })
-                    })
-                    .with(FilterLayer::new(
-                        fmt::layer()
-                            .with_writer(io::stderr)
-                            .with_ansi(atty::is(atty::Stream::Stderr)),
-                        stderr_level,
-                    ))
-                    .init()
-            }
-        }
+    // Configure logging. If the user did not explicitly specify a log file,
+    // log to a file in the data directory and bubble up warnings and errors
+    // to stderr.
+    let log_rotation = args
+        .log_file_max_size
+        .map(|max_size| materialized::LogRotationConfig {
+            max_size,
+            max_files: args.log_file_max_files,
+            compress: args.log_file_compress,
+        });
+    if log_rotation.is_some() && args.log_file.as_deref() == Some("stderr") {
+        bail!("cannot specify --log-file=stderr and --log-file-max-size simultaneously");
+    }
+    if log_rotation.is_some() && args.log_syslog_addr.is_some() {
+        bail!("cannot specify --log-syslog-addr and --log-file-max-size simultaneously");
     }
+    let log = LogConfig {
+        filter: args.log_filter,
+        format: log_format,
+        target: match (args.log_syslog_addr, args.log_file.as_deref()) {
+            (Some(addr), _) => LogTarget::Syslog(SyslogConfig {
+                addr,
+                protocol: match args.log_syslog_protocol.as_str() {
+                    "udp" => SyslogProtocol::Udp,
+                    "tcp" => SyslogProtocol::Tcp,
+                    "tls" => SyslogProtocol::Tls,
+                    _ => unreachable!(),
+                },
+                facility: args.log_syslog_facility,
+                app_name: args.log_syslog_app_name,
+                stderr_level: Some(tracing::Level::WARN),
+            }),
+            (None, Some("stderr")) => LogTarget::Stderr,
+            (None, Some(path)) => LogTarget::File {
+                path: PathBuf::from(path),
+                stderr_level: None,
+                rotation: log_rotation,
+            },
+            (None, None) => LogTarget::File {
+                path: data_directory.join("materialized.log"),
+                stderr_level: Some(tracing::Level::WARN),
+                rotation: log_rotation,
+            },
+        },
+        span_timings: args.log_span_timings,
+        throttle: args.log_throttle_window.map(|window| LogThrottleConfig {
+            window,
+            burst: args.log_throttle_burst,
+            error_burst: args.log_throttle_error_burst,
+        }),
+        redaction: args.log_redaction,
+        buffer_size: usize::try_from(args.log_buffer_size.as_u64())
+            .context("log buffer size too large")?,
+        trace_export: args
+            .trace_export_endpoint
+            .map(|endpoint| TraceExportConfig {
+                endpoint,
+                sample_ratio: args.trace_sample_ratio,
+                resource_attributes: args.trace_resource_attributes.into_iter().collect(),
+            }),
+    };
 
+    let metrics_registry = MetricsRegistry::new();
     // Configure prometheus process metrics.
     mz_process_collector::register_default_process_collector(&metrics_registry);
 
-    // Print system information as the very first thing in the logs. The goal is
-    // to increase the probability that we can reproduce a reported bug if all
-    // we get is the log file.
-    let mut system = sysinfo::System::new();
-    system.refresh_system();
-    info!(
-        "booting server
-materialized {mz_version}
-{dep_versions}
-invoked as: {invocation}
-os: {os}
-cpus: {ncpus_logical} logical, {ncpus_physical} physical
-cpu0: {cpu0}
-memory: {memory_total}KB total, {memory_used}KB used
-swap: {swap_total}KB total, {swap_used}KB used",
-        mz_version = materialized::BUILD_INFO.human_version(),
-        dep_versions = build_info().join("\n"),
-        invocation = {
-            use shell_words::quote as escape;
-            env::vars_os()
-                .map(|(name, value)| {
-                    (
-                        name.to_string_lossy().into_owned(),
-                        value.to_string_lossy().into_owned(),
-                    )
-                })
-                .filter(|(name, _value)| name.starts_with("MZ_"))
-                .map(|(name, value)| format!("{}={}", escape(&name), escape(&value)))
-                .chain(env::args().into_iter().map(|arg| escape(&arg).into_owned()))
-                .join(" ")
-        },
-        os = os_info::get(),
-        ncpus_logical = num_cpus::get(),
-        ncpus_physical = num_cpus::get_physical(),
-        cpu0 = {
-            match &system.processors().get(0) {
-                None => "<unknown>".to_string(),
-                Some(cpu0) => format!("{} {}MHz", cpu0.brand(), cpu0.frequency()),
-            }
-        },
-        memory_total = system.total_memory(),
-        memory_used = system.used_memory(),
-        swap_total = system.total_swap(),
-        swap_used = system.used_swap(),
-    );
-
-    sys::adjust_rlimits();
-
     // Build Timely worker configuration.
     let mut timely_worker =
         timely::WorkerConfig::default().progress_mode(args.timely_progress_mode);
@@ -537,6 +1690,57 @@ swap: {swap_total}KB total, {swap_used}KB used",
         },
     );
 
+    // Configure the memory allocator, if requested.
+    let allocator = if args.allocator_background_thread.is_some()
+        || args.allocator_dirty_decay.is_some()
+        || args.allocator_muzzy_decay.is_some()
+        || args.allocator_narenas.is_some()
+    {
+        Some(AllocatorConfig {
+            background_thread: args.allocator_background_thread,
+            dirty_decay: args.allocator_dirty_decay,
+            muzzy_decay: args.allocator_muzzy_decay,
+            narenas: args.allocator_narenas,
+        })
+    } else {
+        None
+    };
+
+    // Configure the connection rate limit, if requested.
+    let connection_rate_limit =
+        args.connection_rate_limit
+            .map(|per_address_rate| materialized::RateLimitConfig {
+                per_address_rate,
+                burst: args.connection_rate_burst,
+                global_rate: args.connection_rate_limit_global,
+                action: args.connection_rate_limit_action,
+                max_addresses: materialized::DEFAULT_RATE_LIMIT_ADDRESSES,
+            });
+
+    // Configure the memory watchdog, if requested.
+    let memory_watchdog = args
+        .memory_limit
+        .map(|MemoryLimit(limit)| MemoryWatchdogConfig {
+            limit,
+            warning_threshold: args.memory_warning_threshold / 100.0,
+            critical_threshold: args.memory_critical_threshold / 100.0,
+            heap_profiles: args.memory_heap_profiles,
+            refuse_connections: args.memory_refuse_connections,
+            cancel_queries: args.memory_cancel_queries,
+        });
+
+    // Configure the flight recorder, if requested.
+    let flight_recorder = args
+        .flight_recorder_interval
+        .map(|interval| FlightRecorderConfig {
+            interval,
+            rss_jump_threshold: args.flight_recorder_rss_jump / 100.0,
+            rss_jump_window: args.flight_recorder_rss_window,
+            max_snapshots: args.flight_recorder_max_snapshots,
+            max_size: args.flight_recorder_max_size,
+            heap_profiles: args.flight_recorder_heap_profiles,
+        });
+
     // Start Tokio runtime.
     let runtime = Arc::new(
         tokio::runtime::Builder::new_multi_thread()
@@ -551,22 +1755,158 @@ swap: {swap_total}KB total, {swap_used}KB used",
             .build()?,
     );
 
-    let server = runtime.block_on(materialized::serve(materialized::Config {
+    let mut server = runtime.block_on(materialized::serve(materialized::Config {
+        manage_logging: true,
+        log,
+        slow_query_threshold: args.slow_query_threshold,
+        slow_query_log_file: args.slow_query_log_file,
+        long_running_tail_threshold: args.long_running_tail_threshold,
+        statement_label_keys: args.statement_label_keys,
+        statement_timeout: args.statement_timeout,
+        idle_in_transaction_session_timeout: args.idle_in_transaction_session_timeout,
+        lock_timeouts: args.lock_timeouts,
+        max_result_rows: args.max_result_rows,
+        max_result_bytes: args.max_result_bytes,
+        tail_buffer_size: usize::try_from(args.tail_buffer_size.as_u64())
+            .context("TAIL buffer size too large")?,
+        tail_buffer_policy: args.tail_buffer_policy,
+        user_stats_max_users: args.user_stats_max_users,
+        session_quotas: materialized::SessionQuotas {
+            max_concurrent_statements: args.max_concurrent_statements,
+            max_prepared_statements: args.max_prepared_statements,
+            max_open_portals: args.max_open_portals,
+        },
+        authz: match args.authz_policy_file {
+            None => None,
+            Some(path) => Some(Arc::new(materialized::PolicyAuthorizer::load(&path)?)),
+        },
+        crash_reports: match args.max_crash_reports {
+            0 => None,
+            max_reports => Some(materialized::CrashReportConfig { max_reports }),
+        },
+        diagnostics_exclude_sql: args.diagnostics_exclude_sql,
         workers: args.workers.0,
         timely_worker,
+        worker_affinity: args.worker_affinity.0,
+        worker_stack_size: args
+            .worker_stack_size
+            .map(|size| usize::try_from(size.as_u64()))
+            .transpose()
+            .context("worker stack size too large")?,
         logging,
         logical_compaction_window: args.logical_compaction_window,
         timestamp_frequency: args.timestamp_frequency,
+        clock: materialized::ClockConfig {
+            tolerance: args.clock_regression_tolerance,
+            fail_readiness: args.fail_readiness_on_clock_regression,
+            ..Default::default()
+        },
+        allocator,
+        memory_watchdog,
+        dataflow_memory_budget: args.dataflow_memory_budget,
+        dataflow_memory_budget_enforce: !args.dataflow_memory_budget_warn_only,
+        flight_recorder,
+        memory_trim_interval: args.memory_trim_interval,
+        oom_score_adj: args.oom_score_adj,
         listen_addr: args.listen_addr,
+        takeover_from: args.takeover_from,
+        outbound_bind_addr: args.outbound_bind_addr,
+        protocol_detect_timeout: args.protocol_detect_timeout,
+        protocol_policy: args.protocol_policy,
+        connection_rate_limit,
         tls,
+        warn_on_plaintext: args.warn_on_plaintext,
+        pgwire_buffer_size: args.pgwire_buffer_size,
+        pgwire_read_buffer_size: args.pgwire_read_buffer_size,
+        pgwire_buffer_shrink_after: args.pgwire_buffer_shrink_after,
+        pgwire_server_version: args.pgwire_server_version,
+        pgwire_max_message_size: usize::try_from(args.pgwire_max_message_size.as_u64())
+            .context("pgwire max message size too large")?,
+        max_connections: args.max_connections,
+        reserved_superuser_connections: args.reserved_superuser_connections,
+        superusers: args.superusers,
+        http_max_concurrent_requests: args.http_max_concurrent_requests,
+        http_request_queue_size: args.http_request_queue_size,
+        http_request_queue_timeout: args.http_request_queue_timeout,
+        http_result_memory_limit: args
+            .http_result_memory_limit
+            .map(|limit| usize::try_from(limit.as_u64()))
+            .transpose()
+            .context("HTTP result memory limit too large")?,
+        http_arrow_max_batch_size: args.http_arrow_max_batch_size,
+        http2_cleartext: args.http2_cleartext,
+        http1_keep_alive_timeout: args.http1_keep_alive_timeout,
+        http1_max_requests_per_connection: args.http1_max_requests_per_connection,
+        drain_notices: true,
+        connection_notice: match (args.connection_notice, args.connection_notice_file) {
+            (Some(text), None) => Some(materialized::ConnectionNoticeSource::Text(text)),
+            (None, Some(path)) => Some(materialized::ConnectionNoticeSource::File(path)),
+            (None, None) => None,
+            _ => unreachable!(),
+        },
+        idle_exit_after: args.idle_exit_after,
+        coord_liveness: LivenessConfig {
+            probe_interval: args.coord_probe_interval,
+            probe_timeout: args.coord_probe_timeout,
+            max_failures: args.coord_probe_failures,
+            exit: args.exit_on_unresponsive_coord,
+        },
+        startup_timeout: args.startup_timeout,
         data_directory,
+        catalog_directory: args.catalog_directory,
+        storage_directory: args.storage_directory,
+        scratch_directory: args.scratch_directory,
+        restore,
+        from_replica: args.from_replica,
+        allow_catalog_migration: args.allow_catalog_migration,
+        durability: args.durability,
+        catalog_compact_interval: args.catalog_compact_interval,
+        orphan_scan_interval: args.orphan_scan_interval,
+        cleanup_orphans: args.cleanup_orphans,
+        orphan_retention: args.orphan_retention,
+        data_encryption,
+        catalog_replica: args.catalog_replica,
         symbiosis_url: args.symbiosis,
         experimental_mode: args.experimental,
         safe_mode: args.safe,
+        read_only: args.read_only,
+        sticky_maintenance_mode: args.sticky_maintenance_mode,
+        self_test: args.self_test,
         telemetry,
+        version_check: args
+            .version_check_url
+            .map(|url| materialized::VersionCheckConfig {
+                url,
+                interval: args.version_check_interval,
+            }),
+        #[cfg(feature = "failpoints")]
+        fault_injection: if args.fault_injection {
+            Some(materialized::FaultConfig::default())
+        } else {
+            None
+        },
+        instance_labels: args.instance_labels.into_iter().collect(),
+        server_name: args.server_name,
+        metrics_max_series: match args.metrics_max_series {
+            0 => None,
+            n => Some(n),
+        },
+        metrics_filter: if !args.metrics_drop_labels.is_empty() {
+            Some(materialized::MetricsFilter::Drop(
+                args.metrics_drop_labels.into_iter().collect(),
+            ))
+        } else if !args.metrics_keep_labels.is_empty() {
+            Some(materialized::MetricsFilter::Keep(
+                args.metrics_keep_labels.into_iter().collect(),
+            ))
+        } else {
+            None
+        },
         introspection_frequency: args
             .introspection_frequency
             .unwrap_or_else(|| Duration::from_secs(1)),
+        host_metrics: !args.disable_host_metrics,
+        introspection_metrics: args.introspection_metrics,
         metrics_registry,
     }))?;
 
@@ -608,23 +1948,83 @@ For more details, see https://materialize.com/docs/cli#experimental-mode
         );
     }
 
+    if let Some(report) = server.self_test_report() {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        let failed_step = report.failed_step().cloned();
+        match &failed_step {
+            None => server.halt("self test passed"),
+            Some(_) => server.halt("self test failed"),
+        }
+        if let Some(step) = failed_step {
+            bail!(
+                "self test failed at step {}: {}",
+                step.name,
+                step.error.unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
     println!(
         "materialized {} listening on {}...",
         materialized::BUILD_INFO.human_version(),
         server.local_addr(),
     );
 
-    // Block forever.
-    loop {
-        thread::park();
+    // Block until asked to terminate, then report that the server has stopped
+    // before terminating as the signal would have. A server that exits
+    // because it is idle exits successfully instead, and a server that exits
+    // because its coordinator is unresponsive exits with an error, so that
+    // its supervisor restarts it. A server whose listener was taken over by a
+    // new server exits successfully once its clients disconnect.
+    let (reason, signum, failed, handed_off) = runtime.block_on(async {
+        tokio::select! {
+            res = wait_for_termination() => {
+                res.map(|(reason, signum)| (reason.to_owned(), Some(signum), false, false))
+            }
+            reason = server.idle_exit() => Ok((reason, None, false, false)),
+            reason = server.unresponsive_exit() => Ok((reason, None, true, false)),
+            reason = server.handed_off() => Ok((reason, None, false, true)),
+        }
+    })?;
+    if handed_off {
+        let closed = tokio::time::timeout(args.handoff_drain_timeout, server.connections_closed());
+        if runtime.block_on(closed).is_err() {
+            log::warn!(
+                "clients still connected after {:?}; exiting anyway",
+                args.handoff_drain_timeout
+            );
+        }
+    } else if args.drain_grace_period > Duration::from_secs(0) {
+        server.drain(&reason);
+        runtime.block_on(tokio::time::sleep(args.drain_grace_period));
+    }
+    server.halt(&reason);
+    if let Some(signum) = signum {
+        sys::raise_termination_signal(signum);
+    }
+    if failed {
+        bail!("{}", reason);
     }
+    Ok(())
+}
+
+/// Waits for the process to receive SIGINT or SIGTERM, and returns a
+/// description of the signal along with its number.
+async fn wait_for_termination() -> Result<(&'static str, i32), anyhow::Error> {
+    let mut sigint = signal(SignalKind::interrupt()).context("installing SIGINT handler")?;
+    let mut sigterm = signal(SignalKind::terminate()).context("installing SIGTERM handler")?;
+    Ok(tokio::select! {
+        _ = sigint.recv() => ("received SIGINT", libc::SIGINT),
+        _ = sigterm.recv() => ("received SIGTERM", libc::SIGTERM),
+    })
 }
 
 lazy_static! {
     static ref PANIC_MUTEX: Mutex<()> = Mutex::new(());
 }
 
-fn handle_panic(panic_info: &PanicInfo) {
+fn handle_panic(panic_info: &PanicInfo, log_format: LogFormat) {
     let _guard = PANIC_MUTEX.lock();
 
     let thr = thread::current();
@@ -644,39 +2044,54 @@ fn handle_panic(panic_info: &PanicInfo) {
         "<unknown>".to_string()
     };
 
-    log::error!(
-        target: "panic",
-        "{msg}
+    match log_format {
+        LogFormat::Text => {
+            log::error!(
+                target: "panic",
+                "{msg}
 thread: {thr_name}
 location: {location}
 version: {version} ({sha})
 backtrace:
 {backtrace:?}",
-        msg = msg,
-        thr_name = thr_name,
-        location = location,
-        version = materialized::BUILD_INFO.version,
-        sha = materialized::BUILD_INFO.sha,
-        backtrace = Backtrace::new(),
-    );
-    eprintln!(
-        r#"materialized encountered an internal error and crashed.
+                msg = msg,
+                thr_name = thr_name,
+                location = location,
+                version = materialized::BUILD_INFO.version,
+                sha = materialized::BUILD_INFO.sha,
+                backtrace = Backtrace::new(),
+            );
+            eprintln!(
+                r#"materialized encountered an internal error and crashed.
 
 We rely on bug reports to diagnose and fix these errors. Please
 copy and paste the above details and file a report at:
 
     https://materialize.com/s/bug
 "#,
-    );
+            );
+        }
+        LogFormat::Json => {
+            // Keep stderr parseable: emit the details as structured fields,
+            // and the bug report instructions as a second event rather than
+            // as free-form text.
+            tracing::error!(
+                target: "panic",
+                thread = thr_name,
+                location = %location,
+                version = materialized::BUILD_INFO.version,
+                sha = materialized::BUILD_INFO.sha,
+                backtrace = ?Backtrace::new(),
+                "{}",
+                msg,
+            );
+            tracing::error!(
+                target: "panic",
+                "materialized encountered an internal error and crashed; \
+                 please file a report at https://materialize.com/s/bug",
+            );
+        }
+    }
+    materialized::flush_logs();
     process::exit(1);
 }
-
-fn build_info() -> Vec<String> {
-    let openssl_version =
-        unsafe { CStr::from_ptr(openssl_sys::OpenSSL_version(openssl_sys::OPENSSL_VERSION)) };
-    let rdkafka_version = unsafe { CStr::from_ptr(rdkafka_sys::bindings::rd_kafka_version_str()) };
-    vec![
-        openssl_version.to_string_lossy().into_owned(),
-        format!("librdkafka v{}", rdkafka_version.to_string_lossy()),
-    ]
-}
@@ -17,92 +17,9 @@ use std::ptr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::{bail, Context};
-use log::trace;
 use nix::errno;
 use nix::sys::signal;
 
-#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "ios")))]
-pub fn adjust_rlimits() {
-    trace!("rlimit crate does not support this OS; not adjusting nofile limit");
-}
-
-/// Attempts to increase the soft nofile rlimit to the maximum possible value.
-#[cfg(any(target_os = "macos", target_os = "linux", target_os = "ios"))]
-pub fn adjust_rlimits() {
-    use log::warn;
-    use rlimit::Resource;
-
-    // getrlimit/setrlimit can have surprisingly different behavior across
-    // platforms, even with the rlimit wrapper crate that we use. This function
-    // is chattier than normal at the trace log level in an attempt to ease
-    // debugging of such differences.
-
-    let (soft, hard) = match Resource::NOFILE.get() {
-        Ok(limits) => limits,
-        Err(e) => {
-            trace!("unable to read initial nofile rlimit: {}", e);
-            return;
-        }
-    };
-    trace!("initial nofile rlimit: ({}, {})", soft, hard);
-
-    #[cfg(target_os = "macos")]
-    let hard = {
-        use ore::result::ResultExt;
-        use std::cmp;
-        use std::convert::TryFrom;
-        use sysctl::Sysctl;
-
-        // On macOS, getrlimit by default reports that the hard limit is
-        // unlimited, but there is usually a stricter hard limit discoverable
-        // via sysctl. Failing to discover this secret stricter hard limit will
-        // cause the call to setrlimit below to fail.
-        let res = sysctl::Ctl::new("kern.maxfilesperproc")
-            .and_then(|ctl| ctl.value())
-            .map_err_to_string()
-            .and_then(|v| match v {
-                sysctl::CtlValue::Int(v) => u64::try_from(v)
-                    .map_err(|_| format!("kern.maxfilesperproc unexpectedly negative: {}", v)),
-                o => Err(format!("unexpected sysctl value type: {:?}", o)),
-            });
-        match res {
-            Ok(v) => {
-                trace!("sysctl kern.maxfilesperproc hard limit: {}", v);
-                cmp::min(v, hard)
-            }
-            Err(e) => {
-                trace!("error while reading sysctl: {}", e);
-                hard
-            }
-        }
-    };
-
-    trace!("attempting to adjust nofile rlimit to ({0}, {0})", hard);
-    if let Err(e) = Resource::NOFILE.set(hard, hard) {
-        trace!("error adjusting nofile rlimit: {}", e);
-        return;
-    }
-
-    // Check whether getrlimit reflects the limit we installed with setrlimit.
-    // Some platforms will silently ignore invalid values in setrlimit.
-    let (soft, hard) = match Resource::NOFILE.get() {
-        Ok(limits) => limits,
-        Err(e) => {
-            trace!("unable to read adjusted nofile rlimit: {}", e);
-            return;
-        }
-    };
-    trace!("adjusted nofile rlimit: ({}, {})", soft, hard);
-
-    const RECOMMENDED_SOFT: u64 = 1024;
-    if soft < RECOMMENDED_SOFT {
-        warn!(
-            "soft nofile rlimit ({}) is dangerously low; at least {} is recommended",
-            soft, RECOMMENDED_SOFT
-        )
-    }
-}
-
 /// Attempts to enable backtraces when SIGBUS or SIGSEGV occurs.
 ///
 /// In particular, this means producing backtraces on stack overflow, as stack
@@ -205,8 +122,9 @@ pub fn enable_termination_signal_cleanup() -> Result<(), anyhow::Error> {
         signal::SigSet::empty(),
     );
 
+    // SIGHUP is deliberately absent, as the server uses it to reopen its log
+    // file.
     for signum in &[
-        signal::SIGHUP,
         signal::SIGINT,
         signal::SIGPIPE,
         signal::SIGALRM,
@@ -221,6 +139,16 @@ pub fn enable_termination_signal_cleanup() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Terminates the process as if the termination signal `signum` had been
+/// received while the handlers installed by
+/// [`enable_termination_signal_cleanup`] were active.
+///
+/// This is useful after some other handler, like one installed by Tokio, has
+/// intercepted the signal.
+pub fn raise_termination_signal(signum: i32) {
+    handle_termination_signal(signum)
+}
+
 extern "C" {
     fn __llvm_profile_write_file() -> libc::c_int;
 }
@@ -0,0 +1,152 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Compaction of the catalog while the server is running.
+//!
+//! Dropping objects and rewriting their definitions leaves free pages behind
+//! in the catalog database, which SQLite does not return to the filesystem on
+//! its own. Compaction rewrites the database to its minimal form, either on
+//! demand via the HTTP API or periodically.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use coord::catalog::CatalogCompaction;
+use coord::CoordError;
+use ore::metric;
+use ore::metrics::{HistogramVec, MetricsRegistry, UIntCounterVec, UIntGaugeVec};
+
+use crate::backup;
+use crate::scheduler::Job;
+
+/// Catalog compaction metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// The number of compactions, by outcome.
+    compactions: UIntCounterVec,
+    /// The time for which the catalog was locked for each compaction.
+    duration: HistogramVec,
+    /// The size of the catalog before and after the most recent successful
+    /// compaction.
+    size: UIntGaugeVec,
+}
+
+impl Metrics {
+    /// Registers the catalog compaction metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            compactions: registry.register(metric!(
+                name: "mz_catalog_compactions_total",
+                help: "number of catalog compactions, by outcome",
+                var_labels: ["status"],
+            )),
+            duration: registry.register(metric!(
+                name: "mz_catalog_compaction_duration_seconds",
+                help: "duration for which the catalog was locked to compact it",
+            )),
+            size: registry.register(metric!(
+                name: "mz_catalog_compaction_size_bytes",
+                help: "size of the catalog before and after the most recent compaction",
+                var_labels: ["phase"],
+            )),
+        }
+    }
+}
+
+/// An error that prevented a compaction of the catalog.
+#[derive(Debug)]
+pub enum CompactionError {
+    /// A catalog backup was in progress.
+    BackupInProgress,
+    /// The coordinator failed to compact the catalog.
+    Coord(CoordError),
+}
+
+impl fmt::Display for CompactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompactionError::BackupInProgress => {
+                f.write_str("a catalog backup is in progress; retry once it completes")
+            }
+            CompactionError::Coord(e) => write!(f, "unable to compact catalog: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CompactionError {}
+
+/// Compacts the catalog, unless a backup is in progress.
+///
+/// `compact_catalog` is invoked to ask the coordinator to compact the
+/// catalog, as by [`coord::Client::compact_catalog`].
+pub async fn compact<F, Fut>(
+    compact_catalog: F,
+    backup_metrics: &backup::Metrics,
+    metrics: &Metrics,
+) -> Result<CatalogCompaction, CompactionError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<CatalogCompaction, CoordError>>,
+{
+    if backup_metrics.in_progress() {
+        metrics.compactions.with_label_values(&["skipped"]).inc();
+        return Err(CompactionError::BackupInProgress);
+    }
+    match compact_catalog().await {
+        Ok(compaction) => {
+            metrics.compactions.with_label_values(&["success"]).inc();
+            metrics.duration.observe(compaction.duration.as_secs_f64());
+            metrics
+                .size
+                .with_label_values(&["before"])
+                .set(compaction.size_before);
+            metrics
+                .size
+                .with_label_values(&["after"])
+                .set(compaction.size_after);
+            info!(
+                "compacted catalog from {} to {} bytes in {:?}",
+                compaction.size_before, compaction.size_after, compaction.duration
+            );
+            Ok(compaction)
+        }
+        Err(e) => {
+            metrics.compactions.with_label_values(&["error"]).inc();
+            Err(CompactionError::Coord(e))
+        }
+    }
+}
+
+/// Returns a job that compacts the catalog every `interval`, starting one
+/// `interval` after the server boots.
+///
+/// A compaction that is skipped because a backup is in progress is retried
+/// at the next interval.
+pub fn job(
+    coord_client: coord::Client,
+    backup_metrics: backup::Metrics,
+    metrics: Metrics,
+    interval: Duration,
+) -> Job {
+    Job::every("catalog_compaction", interval, move || {
+        let coord_client = coord_client.clone();
+        let backup_metrics = backup_metrics.clone();
+        let metrics = metrics.clone();
+        async move {
+            let compact_catalog = || coord_client.compact_catalog();
+            if let Err(e) = compact(compact_catalog, &backup_metrics, &metrics).await {
+                warn!("periodic catalog compaction: {}", e);
+            }
+        }
+    })
+    .first_run_after(interval)
+}
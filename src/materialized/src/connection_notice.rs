@@ -0,0 +1,111 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A notice shown to clients when they connect.
+//!
+//! The notice is sent to pgwire clients as a `NOTICE` once their session
+//! starts, and is reported by the `/api/status` and `/sql` HTTP endpoints.
+//! It may refer to the following variables, which are substituted when the
+//! notice is loaded:
+//!
+//!   * `{version}`, the version of `materialized`.
+//!   * `{cluster_id}`, the ID of the cluster.
+//!   * `{server_name}`, the host name of the server.
+//!
+//! A notice that is read from a file is reread when the server receives
+//! SIGHUP.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use uuid::Uuid;
+
+use crate::BUILD_INFO;
+
+/// Where to load the notice that is shown to clients when they connect.
+#[derive(Debug, Clone)]
+pub enum ConnectionNoticeSource {
+    /// The text of the notice.
+    Text(String),
+    /// A file that contains the text of the notice.
+    File(PathBuf),
+}
+
+impl ConnectionNoticeSource {
+    /// Loads the notice from the source and substitutes the variables in it.
+    ///
+    /// Returns `None` if the notice is empty. This function performs blocking
+    /// I/O.
+    pub fn load(&self, cluster_id: Uuid) -> Result<Option<String>, anyhow::Error> {
+        let template = match self {
+            ConnectionNoticeSource::Text(text) => text.clone(),
+            ConnectionNoticeSource::File(path) => fs::read_to_string(path)
+                .with_context(|| format!("reading connection notice file {}", path.display()))?,
+        };
+        let notice = template.trim_end();
+        if notice.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            notice
+                .replace("{version}", BUILD_INFO.version)
+                .replace("{cluster_id}", &cluster_id.to_string())
+                .replace("{server_name}", &server_name()),
+        ))
+    }
+
+    /// Reports whether the notice can change while the server is running.
+    pub fn is_reloadable(&self) -> bool {
+        matches!(self, ConnectionNoticeSource::File(_))
+    }
+}
+
+/// Returns the host name of the server, or `"unknown"` if it cannot be
+/// determined.
+fn server_name() -> String {
+    let mut buf = [0; 256];
+    match nix::unistd::gethostname(&mut buf) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => "unknown".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::ConnectionNoticeSource;
+    use crate::BUILD_INFO;
+
+    #[test]
+    fn test_load() -> Result<(), anyhow::Error> {
+        let cluster_id = Uuid::new_v4();
+
+        let source = ConnectionNoticeSource::Text("staging {version} {cluster_id}\n".into());
+        assert_eq!(
+            source.load(cluster_id)?,
+            Some(format!("staging {} {}", BUILD_INFO.version, cluster_id))
+        );
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notice");
+        fs::write(&path, "  \n")?;
+        let source = ConnectionNoticeSource::File(path.clone());
+        assert_eq!(source.load(cluster_id)?, None);
+        fs::write(&path, "data resets nightly")?;
+        assert_eq!(source.load(cluster_id)?, Some("data resets nightly".into()));
+
+        fs::remove_file(&path)?;
+        assert!(source.load(cluster_id).is_err());
+        Ok(())
+    }
+}
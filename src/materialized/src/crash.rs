@@ -0,0 +1,544 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Crash reports.
+//!
+//! When the process panics, a crash report is written to a file in the crash
+//! directory. The report describes the panic along with enough context about
+//! the build and configuration of the server to diagnose it, and does not
+//! depend on stderr being captured in full.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write as _};
+use std::panic::{self, PanicInfo};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Instant, SystemTime};
+
+use backtrace::Backtrace;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::logging;
+use crate::{Config, KeySource, LogTarget, TelemetryConfig, BUILD_INFO};
+
+/// The prefix of the name of every crash report file.
+const REPORT_PREFIX: &str = "crash-";
+
+/// The extension of every crash report file.
+const REPORT_EXTENSION: &str = "txt";
+
+/// Installs a panic hook that writes a crash report to `dir` before invoking
+/// the previously installed panic hook.
+///
+/// `config` is a description of the server's configuration to include in each
+/// report; see [`describe_config`]. At most `max_reports` reports are
+/// retained in `dir`.
+pub fn install_hook(dir: PathBuf, max_reports: usize, config: String) {
+    let start = Instant::now();
+    prune(&dir, max_reports);
+    let prev_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |panic_info| {
+        // Writing the report is best effort. A failure to write the report
+        // must not prevent the previous hook, which typically logs the panic
+        // and exits, from running.
+        //
+        // Note that `eprintln!` panics if stderr is closed, so stderr is
+        // written to directly instead.
+        let report = render(panic_info, start, &config);
+        let _ = match write(&dir, &report) {
+            Ok(path) => writeln!(
+                io::stderr(),
+                "materialized: wrote crash report to {}",
+                path.display()
+            ),
+            Err(e) => writeln!(
+                io::stderr(),
+                "materialized: unable to write crash report: {}",
+                e
+            ),
+        };
+        prune(&dir, max_reports);
+        prev_hook(panic_info);
+    }));
+}
+
+fn render(panic_info: &PanicInfo, start: Instant, config: &str) -> String {
+    let msg = match panic_info.payload().downcast_ref::<&'static str>() {
+        Some(s) => *s,
+        None => match panic_info.payload().downcast_ref::<String>() {
+            Some(s) => &s[..],
+            None => "Box<Any>",
+        },
+    };
+    let location = match panic_info.location() {
+        Some(loc) => loc.to_string(),
+        None => "<unknown>".into(),
+    };
+    let thr = thread::current();
+
+    // Writing to a `String` cannot fail, so the results of `writeln!` are
+    // ignored throughout.
+    let mut report = String::new();
+    let _ = writeln!(report, "materialized crash report");
+    let _ = writeln!(report);
+    let _ = writeln!(
+        report,
+        "time: {}",
+        DateTime::<Utc>::from(SystemTime::now()).to_rfc3339_opts(SecondsFormat::Millis, true)
+    );
+    let _ = writeln!(report, "version: {}", BUILD_INFO.version);
+    let _ = writeln!(report, "sha: {}", BUILD_INFO.sha);
+    let _ = writeln!(report, "build time: {}", BUILD_INFO.time);
+    let _ = writeln!(report, "target: {}", BUILD_INFO.target_triple);
+    let _ = writeln!(report, "uptime: {:.3}s", start.elapsed().as_secs_f64());
+    let _ = writeln!(report, "pid: {}", process::id());
+    let _ = writeln!(report, "thread: {}", thr.name().unwrap_or("<unnamed>"));
+    let _ = writeln!(report, "location: {}", location);
+    let _ = writeln!(report, "message: {}", msg);
+    let _ = writeln!(report);
+    let _ = writeln!(report, "== backtrace ==");
+    let _ = writeln!(report, "{:?}", Backtrace::new());
+    let _ = writeln!(report, "== configuration ==");
+    let _ = writeln!(report, "{}", config);
+    let _ = writeln!(report, "== recent log messages ==");
+    match logging::recent_messages() {
+        Some(messages) if !messages.is_empty() => {
+            for message in messages {
+                report.push_str(&message);
+            }
+        }
+        _ => {
+            let _ = writeln!(report, "<unavailable>");
+        }
+    }
+    report
+}
+
+fn write(dir: &Path, report: &str) -> Result<PathBuf, anyhow::Error> {
+    // Multiple threads may panic at once. The sequence number ensures each
+    // report is written to its own file.
+    static SEQNO: AtomicUsize = AtomicUsize::new(0);
+
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}{}-{}-{}.{}",
+        REPORT_PREFIX,
+        DateTime::<Utc>::from(SystemTime::now()).format("%Y%m%dT%H%M%S%.6fZ"),
+        process::id(),
+        SEQNO.fetch_add(1, Ordering::SeqCst),
+        REPORT_EXTENSION,
+    ));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Removes all but the newest `max_reports` crash reports from `dir`.
+///
+/// Report file names begin with a timestamp, so sorting by name sorts the
+/// reports from oldest to newest. Errors are ignored.
+fn prune(dir: &Path, max_reports: usize) {
+    let mut reports = list(dir);
+    if reports.len() <= max_reports {
+        return;
+    }
+    reports.sort();
+    for path in &reports[..reports.len() - max_reports] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Returns the paths of the at most `max_reports` most recent crash reports in
+/// `dir`, newest first.
+pub fn recent_reports(dir: &Path, max_reports: usize) -> Vec<PathBuf> {
+    let mut reports = list(dir);
+    // Report names embed the time at which they were written, so sorting by
+    // name sorts by age.
+    reports.sort();
+    reports.reverse();
+    reports.truncate(max_reports);
+    reports
+}
+
+/// Lists the crash reports in `dir`, in no particular order.
+fn list(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let is_report = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with(REPORT_PREFIX));
+            is_report
+                && path
+                    .extension()
+                    .map_or(false, |ext| ext == REPORT_EXTENSION)
+        })
+        .collect()
+}
+
+/// Describes `config` for inclusion in a crash report or diagnostics bundle.
+///
+/// Values that may contain credentials, like the symbiosis URL, are redacted.
+pub fn describe_config(config: &Config) -> String {
+    fn redact<T>(value: &Option<T>) -> &'static str {
+        match value {
+            None => "<none>",
+            Some(_) => "<redacted>",
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "workers: {}", config.workers);
+    let _ = writeln!(out, "timely_worker: {:?}", config.timely_worker);
+    let _ = writeln!(out, "worker_affinity: {:?}", config.worker_affinity);
+    let _ = writeln!(out, "worker_stack_size: {:?}", config.worker_stack_size);
+    let _ = writeln!(out, "manage_logging: {}", config.manage_logging);
+    let _ = writeln!(out, "log.filter: {}", config.log.filter);
+    let _ = writeln!(out, "log.format: {:?}", config.log.format);
+    match &config.log.target {
+        LogTarget::Stderr => {
+            let _ = writeln!(out, "log.target: stderr");
+        }
+        LogTarget::File {
+            path,
+            stderr_level,
+            rotation,
+        } => {
+            let _ = writeln!(out, "log.target: {}", path.display());
+            let _ = writeln!(out, "log.stderr_level: {:?}", stderr_level);
+            let _ = writeln!(out, "log.rotation: {:?}", rotation);
+        }
+        LogTarget::Syslog(syslog) => {
+            let _ = writeln!(out, "log.target: syslog");
+            let _ = writeln!(out, "log.syslog: {:?}", syslog);
+        }
+    }
+    let _ = writeln!(out, "log.throttle: {:?}", config.log.throttle);
+    let _ = writeln!(out, "log.redaction: {}", config.log.redaction);
+    let _ = writeln!(out, "log.buffer_size: {}", config.log.buffer_size);
+    let _ = writeln!(
+        out,
+        "log.trace_export: {:?}",
+        config.log.trace_export.as_ref().map(|t| &t.endpoint)
+    );
+    let _ = writeln!(
+        out,
+        "slow_query_threshold: {:?}",
+        config.slow_query_threshold
+    );
+    let _ = writeln!(out, "slow_query_log_file: {:?}", config.slow_query_log_file);
+    let _ = writeln!(
+        out,
+        "long_running_tail_threshold: {:?}",
+        config.long_running_tail_threshold
+    );
+    let _ = writeln!(
+        out,
+        "statement_label_keys: {:?}",
+        config.statement_label_keys
+    );
+    let _ = writeln!(out, "statement_timeout: {:?}", config.statement_timeout);
+    let _ = writeln!(
+        out,
+        "idle_in_transaction_session_timeout: {:?}",
+        config.idle_in_transaction_session_timeout
+    );
+    let _ = writeln!(out, "lock_timeouts: {}", config.lock_timeouts);
+    let _ = writeln!(out, "max_result_rows: {:?}", config.max_result_rows);
+    let _ = writeln!(out, "max_result_bytes: {:?}", config.max_result_bytes);
+    let _ = writeln!(out, "tail_buffer_size: {}", config.tail_buffer_size);
+    let _ = writeln!(out, "tail_buffer_policy: {}", config.tail_buffer_policy);
+    let _ = writeln!(out, "user_stats_max_users: {}", config.user_stats_max_users);
+    let _ = writeln!(out, "authz: {:?}", config.authz);
+    let _ = writeln!(out, "crash_reports: {:?}", config.crash_reports);
+    let _ = writeln!(
+        out,
+        "diagnostics_exclude_sql: {}",
+        config.diagnostics_exclude_sql
+    );
+    let _ = writeln!(out, "logging: {:?}", config.logging);
+    let _ = writeln!(
+        out,
+        "introspection_frequency: {:?}",
+        config.introspection_frequency
+    );
+    let _ = writeln!(out, "host_metrics: {}", config.host_metrics);
+    let _ = writeln!(
+        out,
+        "introspection_metrics: {}",
+        config.introspection_metrics
+    );
+    let _ = writeln!(
+        out,
+        "logical_compaction_window: {:?}",
+        config.logical_compaction_window
+    );
+    let _ = writeln!(out, "timestamp_frequency: {:?}", config.timestamp_frequency);
+    let _ = writeln!(
+        out,
+        "clock: tolerance={:?} fail_readiness={}",
+        config.clock.tolerance, config.clock.fail_readiness
+    );
+    let _ = writeln!(out, "allocator: {:?}", config.allocator);
+    let _ = writeln!(out, "memory_watchdog: {:?}", config.memory_watchdog);
+    let _ = writeln!(
+        out,
+        "dataflow_memory_budget: {:?}",
+        config.dataflow_memory_budget
+    );
+    let _ = writeln!(
+        out,
+        "dataflow_memory_budget_enforce: {}",
+        config.dataflow_memory_budget_enforce
+    );
+    let _ = writeln!(out, "flight_recorder: {:?}", config.flight_recorder);
+    let _ = writeln!(
+        out,
+        "memory_trim_interval: {:?}",
+        config.memory_trim_interval
+    );
+    let _ = writeln!(out, "oom_score_adj: {:?}", config.oom_score_adj);
+    let _ = writeln!(out, "listen_addr: {}", config.listen_addr);
+    let _ = writeln!(out, "takeover_from: {:?}", config.takeover_from);
+    let _ = writeln!(out, "outbound_bind_addr: {:?}", config.outbound_bind_addr);
+    let _ = writeln!(
+        out,
+        "protocol_detect_timeout: {:?}",
+        config.protocol_detect_timeout
+    );
+    let _ = writeln!(out, "protocol_policy: {}", config.protocol_policy);
+    let _ = writeln!(
+        out,
+        "connection_rate_limit: {:?}",
+        config.connection_rate_limit
+    );
+    let _ = writeln!(
+        out,
+        "tls.mode: {:?}",
+        config.tls.as_ref().map(|tls| &tls.mode)
+    );
+    let _ = writeln!(out, "pgwire_buffer_size: {:?}", config.pgwire_buffer_size);
+    let _ = writeln!(
+        out,
+        "pgwire_read_buffer_size: {:?}",
+        config.pgwire_read_buffer_size
+    );
+    let _ = writeln!(
+        out,
+        "pgwire_buffer_shrink_after: {:?}",
+        config.pgwire_buffer_shrink_after
+    );
+    let _ = writeln!(
+        out,
+        "pgwire_server_version: {:?}",
+        config.pgwire_server_version
+    );
+    let _ = writeln!(
+        out,
+        "pgwire_max_message_size: {}",
+        config.pgwire_max_message_size
+    );
+    let _ = writeln!(out, "max_connections: {:?}", config.max_connections);
+    let _ = writeln!(
+        out,
+        "reserved_superuser_connections: {}",
+        config.reserved_superuser_connections
+    );
+    let _ = writeln!(out, "superusers: {:?}", config.superusers);
+    let _ = writeln!(
+        out,
+        "http_max_concurrent_requests: {:?}",
+        config.http_max_concurrent_requests
+    );
+    let _ = writeln!(
+        out,
+        "http_request_queue_size: {}",
+        config.http_request_queue_size
+    );
+    let _ = writeln!(
+        out,
+        "http_request_queue_timeout: {:?}",
+        config.http_request_queue_timeout
+    );
+    let _ = writeln!(
+        out,
+        "http_result_memory_limit: {:?}",
+        config.http_result_memory_limit
+    );
+    let _ = writeln!(
+        out,
+        "http_arrow_max_batch_size: {}",
+        config.http_arrow_max_batch_size
+    );
+    let _ = writeln!(out, "http2_cleartext: {}", config.http2_cleartext);
+    let _ = writeln!(
+        out,
+        "http1_keep_alive_timeout: {:?}",
+        config.http1_keep_alive_timeout
+    );
+    let _ = writeln!(
+        out,
+        "http1_max_requests_per_connection: {:?}",
+        config.http1_max_requests_per_connection
+    );
+    let _ = writeln!(out, "drain_notices: {}", config.drain_notices);
+    let _ = writeln!(out, "connection_notice: {:?}", config.connection_notice);
+    let _ = writeln!(out, "idle_exit_after: {:?}", config.idle_exit_after);
+    let _ = writeln!(out, "coord_liveness: {:?}", config.coord_liveness);
+    let _ = writeln!(out, "startup_timeout: {:?}", config.startup_timeout);
+    let _ = writeln!(out, "self_test: {}", config.self_test);
+    let _ = writeln!(out, "data_directory: {}", config.data_directory.display());
+    let _ = writeln!(out, "catalog_directory: {:?}", config.catalog_directory);
+    let _ = writeln!(out, "storage_directory: {:?}", config.storage_directory);
+    let _ = writeln!(out, "scratch_directory: {:?}", config.scratch_directory);
+    let _ = writeln!(out, "restore: {:?}", config.restore);
+    // Replica targets are described by their display form, which omits the
+    // credentials of URLs.
+    let _ = writeln!(
+        out,
+        "from_replica: {:?}",
+        config.from_replica.as_ref().map(|t| t.to_string())
+    );
+    let _ = writeln!(
+        out,
+        "allow_catalog_migration: {}",
+        config.allow_catalog_migration
+    );
+    let _ = writeln!(out, "durability: {}", config.durability);
+    let _ = writeln!(
+        out,
+        "catalog_compact_interval: {:?}",
+        config.catalog_compact_interval
+    );
+    let _ = writeln!(
+        out,
+        "orphan_scan_interval: {:?}",
+        config.orphan_scan_interval
+    );
+    let _ = writeln!(out, "cleanup_orphans: {}", config.cleanup_orphans);
+    let _ = writeln!(out, "orphan_retention: {:?}", config.orphan_retention);
+    // The key source is described without its details, as a key command
+    // may embed credentials.
+    let _ = writeln!(
+        out,
+        "data_encryption: {}",
+        match config.data_encryption.as_ref().map(|e| &e.key_source) {
+            None => "<none>",
+            Some(KeySource::File(_)) => "file",
+            Some(KeySource::Env(_)) => "env",
+            Some(KeySource::Command(_)) => "command",
+        }
+    );
+    let _ = writeln!(
+        out,
+        "catalog_replica: {:?}",
+        config.catalog_replica.as_ref().map(|t| t.to_string())
+    );
+    let _ = writeln!(out, "symbiosis_url: {}", redact(&config.symbiosis_url));
+    let _ = writeln!(out, "experimental_mode: {}", config.experimental_mode);
+    let _ = writeln!(out, "safe_mode: {}", config.safe_mode);
+    let _ = writeln!(out, "read_only: {}", config.read_only);
+    let _ = writeln!(
+        out,
+        "sticky_maintenance_mode: {}",
+        config.sticky_maintenance_mode
+    );
+    match &config.telemetry {
+        None => {
+            let _ = writeln!(out, "telemetry: <disabled>");
+        }
+        Some(TelemetryConfig {
+            domain, interval, ..
+        }) => {
+            let _ = writeln!(out, "telemetry.domain: {}", domain);
+            let _ = writeln!(out, "telemetry.interval: {:?}", interval);
+        }
+    }
+    match &config.version_check {
+        None => {
+            let _ = writeln!(out, "version_check: <disabled>");
+        }
+        Some(version_check) => {
+            // The URL may carry credentials.
+            let _ = writeln!(out, "version_check.url: <redacted>");
+            let _ = writeln!(out, "version_check.interval: {:?}", version_check.interval);
+        }
+    }
+    let _ = writeln!(out, "instance_labels: {:?}", config.instance_labels);
+    let _ = writeln!(out, "server_name: {:?}", config.server_name);
+    let _ = writeln!(out, "metrics_max_series: {:?}", config.metrics_max_series);
+    let _ = writeln!(out, "metrics_filter: {:?}", config.metrics_filter);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        for name in &[
+            "crash-20210101T000000.000000Z-1-0.txt",
+            "crash-20210102T000000.000000Z-1-0.txt",
+            "crash-20210103T000000.000000Z-1-0.txt",
+            "unrelated.txt",
+        ] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        prune(dir.path(), 2);
+        let mut remaining: Vec<_> = fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+            .collect::<Result<_, anyhow::Error>>()?;
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "crash-20210102T000000.000000Z-1-0.txt",
+                "crash-20210103T000000.000000Z-1-0.txt",
+                "unrelated.txt",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent_reports() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        for name in &[
+            "crash-20210102T000000.000000Z-1-0.txt",
+            "crash-20210101T000000.000000Z-1-0.txt",
+            "crash-20210103T000000.000000Z-1-0.txt",
+            "unrelated.txt",
+        ] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        let names: Vec<_> = recent_reports(dir.path(), 2)
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "crash-20210103T000000.000000Z-1-0.txt",
+                "crash-20210102T000000.000000Z-1-0.txt",
+            ]
+        );
+        assert!(recent_reports(&dir.path().join("missing"), 2).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Management of the directories in which the server stores files.
+//!
+//! The server stores state that must survive a restart in the data directory,
+//! or, if so configured, the catalog in the catalog directory and the data of
+//! the objects in the catalog in the storage directory. It stores state that
+//! need not survive a restart, like buffered source snapshots and in-progress
+//! backups, in the scratch directory. Each may be placed on different storage.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use tempfile::NamedTempFile;
+
+/// Prepares `scratch_directory` for use by the server.
+///
+/// Creates the directory if it does not exist, deletes any contents left
+/// behind by a previous run, and verifies that the directory is writable.
+///
+/// Refuses to prepare a scratch directory that contains any of the
+/// `durable_directories`, named by their purpose, as cleaning it would
+/// destroy the server's durable state.
+pub fn prepare_scratch_directory(
+    scratch_directory: &Path,
+    durable_directories: &[(&str, &Path)],
+) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(scratch_directory).with_context(|| {
+        format!(
+            "creating scratch directory: {}",
+            scratch_directory.display()
+        )
+    })?;
+    let scratch_directory = scratch_directory.canonicalize()?;
+    for (purpose, directory) in durable_directories {
+        if let Ok(directory) = directory.canonicalize() {
+            if directory.starts_with(&scratch_directory) {
+                bail!(
+                    "scratch directory {} must not contain the {} directory",
+                    scratch_directory.display(),
+                    purpose,
+                );
+            }
+        }
+    }
+    clean(&scratch_directory).with_context(|| {
+        format!(
+            "cleaning scratch directory: {}",
+            scratch_directory.display()
+        )
+    })?;
+    NamedTempFile::new_in(&scratch_directory).with_context(|| {
+        format!(
+            "scratch directory {} is not writable",
+            scratch_directory.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// Deletes the contents of `dir`, but not `dir` itself.
+fn clean(dir: &Path) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Computes the total size in bytes of the files within `dir`, excluding the
+/// contents of each directory in `exclude` that is within `dir`.
+///
+/// Symbolic links are not followed. Files that cannot be read, or that are
+/// removed while the directory is being walked, are ignored.
+///
+/// This function performs blocking I/O.
+pub fn usage(dir: &Path, exclude: &[&Path]) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut total = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if exclude.contains(&path.as_path()) {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => total += usage(&path, exclude),
+            Ok(metadata) => total += metadata.len(),
+            Err(_) => (),
+        }
+    }
+    total
+}
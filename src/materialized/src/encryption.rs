@@ -0,0 +1,118 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Sources of the key with which the catalog is encrypted at rest.
+//!
+//! The key is loaded from its source when the server starts, and again
+//! whenever the catalog is reencrypted, so that the key can be rotated by
+//! changing what the source provides. The key is never logged or persisted
+//! by the server.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use log::warn;
+
+use coord::catalog::EncryptionKey;
+
+/// Configures the encryption of the catalog at rest.
+#[derive(Debug, Clone)]
+pub struct EncryptionConfig {
+    /// Where to load the encryption key from.
+    pub key_source: KeySource,
+}
+
+/// Where to load the key with which the catalog is encrypted.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A file that contains the key, either as 32 raw bytes or as 64
+    /// hexadecimal digits.
+    File(PathBuf),
+    /// An environment variable that contains the key as 64 hexadecimal
+    /// digits.
+    Env(String),
+    /// A shell command that prints the key as 64 hexadecimal digits.
+    Command(String),
+}
+
+impl KeySource {
+    /// Reports whether the key that the source provides can change while the
+    /// server is running.
+    ///
+    /// Only such sources permit the catalog to be reencrypted.
+    pub fn is_rotatable(&self) -> bool {
+        !matches!(self, KeySource::Env(_))
+    }
+
+    /// Loads the key from the source.
+    ///
+    /// This function performs blocking I/O.
+    pub fn load(&self) -> Result<EncryptionKey, anyhow::Error> {
+        match self {
+            KeySource::File(path) => {
+                let contents = fs::read(path).with_context(|| {
+                    format!("reading data encryption key file {}", path.display())
+                })?;
+                warn_if_accessible(path);
+                if contents.len() == EncryptionKey::LEN {
+                    Ok(EncryptionKey::from_bytes(&contents).map_err(anyhow::Error::msg)?)
+                } else {
+                    let contents = String::from_utf8(contents).map_err(|_| {
+                        anyhow::anyhow!(
+                            "data encryption key file {} contains neither {} raw bytes nor \
+                             hexadecimal digits",
+                            path.display(),
+                            EncryptionKey::LEN
+                        )
+                    })?;
+                    Ok(EncryptionKey::from_hex(&contents).map_err(anyhow::Error::msg)?)
+                }
+            }
+            KeySource::Env(var) => {
+                let value = env::var(var).with_context(|| {
+                    format!("reading data encryption key environment variable {}", var)
+                })?;
+                Ok(EncryptionKey::from_hex(&value).map_err(anyhow::Error::msg)?)
+            }
+            KeySource::Command(command) => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .output()
+                    .context("running data encryption key command")?;
+                if !output.status.success() {
+                    bail!(
+                        "data encryption key command failed ({}): {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let stdout = String::from_utf8(output.stdout)
+                    .context("data encryption key command printed invalid UTF-8")?;
+                Ok(EncryptionKey::from_hex(&stdout).map_err(anyhow::Error::msg)?)
+            }
+        }
+    }
+}
+
+/// Warns if the key file at `path` can be read by users other than its owner.
+fn warn_if_accessible(path: &Path) {
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            warn!(
+                "data encryption key file {} is accessible to users other than its owner",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Fault injection, for testing the server's resilience.
+//!
+//! The server has named failpoints at the places where failures have proven
+//! hard to reproduce. A failpoint does nothing unless it is activated, in
+//! which case it injects a fault each time it is hit, subject to a
+//! probability and to limits on which hits inject a fault.
+//!
+//! Failpoints are compiled in only with the `failpoints` feature. Without
+//! it, they compile to nothing, and this module does not exist. Failpoints
+//! are global to the process, so activating one affects every server in the
+//! process.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+/// The failpoints of the server, and where each is hit.
+pub const FAILPOINTS: &[(&str, &str)] = &[
+    ("mux_accept", "after accepting a TCP connection"),
+    (
+        "tls_accept",
+        "before performing the server side of a TLS handshake",
+    ),
+    (
+        "coord_client_send",
+        "before a session sends a command to the coordinator",
+    ),
+    ("catalog_write", "before committing a write to the catalog"),
+];
+
+/// Configures fault injection.
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    /// The failpoints to activate as the server starts.
+    ///
+    /// Failpoints can also be activated and deactivated while the server is
+    /// running, via the `/api/failpoints` HTTP endpoint.
+    pub failpoints: Vec<Failpoint>,
+}
+
+/// A fault that a failpoint can inject.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Fails the operation at the failpoint.
+    ///
+    /// Accepting a connection fails with `EMFILE`, a TLS handshake fails as
+    /// if the client had aborted it, a command fails as if it had timed out,
+    /// and a catalog write fails with an I/O error.
+    Error,
+    /// Delays the operation at the failpoint.
+    ///
+    /// The delay blocks the thread that hits the failpoint.
+    Delay(Duration),
+    /// Panics at the failpoint.
+    Panic,
+}
+
+/// The activation of a failpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failpoint {
+    /// The name of the failpoint. See [`FAILPOINTS`].
+    pub name: String,
+    /// The fault to inject.
+    pub fault: Fault,
+    /// The probability with which each eligible hit injects the fault.
+    pub probability: f64,
+    /// The number of hits to let pass before any hit is eligible.
+    pub after: u64,
+    /// The maximum number of faults to inject, if any.
+    pub times: Option<u64>,
+}
+
+impl Failpoint {
+    /// Constructs an activation of the named failpoint that injects `fault`
+    /// on every hit.
+    pub fn new<S>(name: S, fault: Fault) -> Failpoint
+    where
+        S: Into<String>,
+    {
+        Failpoint {
+            name: name.into(),
+            fault,
+            probability: 1.0,
+            after: 0,
+            times: None,
+        }
+    }
+
+    /// Activates the failpoint, replacing any previous activation.
+    pub fn activate(&self) -> Result<(), anyhow::Error> {
+        validate_name(&self.name)?;
+        if !(self.probability > 0.0 && self.probability <= 1.0) {
+            bail!(
+                "failpoint probability must be greater than 0 and at most 1: {}",
+                self.probability
+            );
+        }
+        if self.times == Some(0) {
+            bail!("failpoint times must be at least 1");
+        }
+        fail::cfg(&self.name, &self.actions()).map_err(|e| anyhow!(e))
+    }
+
+    /// Describes the activation in the syntax of the `fail` crate.
+    fn actions(&self) -> String {
+        let mut actions = String::new();
+        if self.after > 0 {
+            let _ = write!(actions, "{}*off->", self.after);
+        }
+        if self.probability < 1.0 {
+            let _ = write!(actions, "{}%", self.probability * 100.0);
+        }
+        if let Some(times) = self.times {
+            let _ = write!(actions, "{}*", times);
+        }
+        match &self.fault {
+            Fault::Error => actions.push_str("return"),
+            Fault::Delay(delay) => {
+                let _ = write!(actions, "sleep({})", delay.as_millis());
+            }
+            Fault::Panic => actions.push_str("panic"),
+        }
+        actions
+    }
+}
+
+/// Deactivates the named failpoint.
+pub fn deactivate(name: &str) -> Result<(), anyhow::Error> {
+    validate_name(name)?;
+    fail::remove(name);
+    Ok(())
+}
+
+/// Returns the name and activation of each active failpoint, in the syntax of
+/// the `fail` crate.
+pub fn list() -> Vec<(String, String)> {
+    let mut failpoints = fail::list();
+    failpoints.sort();
+    failpoints
+}
+
+fn validate_name(name: &str) -> Result<(), anyhow::Error> {
+    if !FAILPOINTS.iter().any(|(n, _)| *n == name) {
+        bail!("unknown failpoint: {}", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Failpoint, Fault};
+
+    #[test]
+    fn test_actions() {
+        let failpoint = Failpoint::new("mux_accept", Fault::Error);
+        assert_eq!(failpoint.actions(), "return");
+
+        let failpoint = Failpoint {
+            probability: 0.25,
+            after: 3,
+            times: Some(2),
+            ..Failpoint::new("catalog_write", Fault::Delay(Duration::from_millis(500)))
+        };
+        assert_eq!(failpoint.actions(), "3*off->25%2*sleep(500)");
+
+        let failpoint = Failpoint::new("nonexistent", Fault::Panic);
+        assert!(failpoint.activate().is_err());
+    }
+}
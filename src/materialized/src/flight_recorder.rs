@@ -0,0 +1,621 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Flight recorder.
+//!
+//! Diagnosing a regression in memory usage or query latency after the fact
+//! requires knowing what the server was doing before it regressed, which is
+//! rarely recorded. The flight recorder takes snapshots of the server's state
+//! periodically, and additionally whenever the resident set size jumps, so
+//! that the state before and during a regression is on hand when the problem
+//! is noticed.
+//!
+//! Each snapshot records the allocator's statistics, the arrangements that
+//! hold the most records, the active sessions and the statements they are
+//! executing, and the number of commands waiting for the coordinator, and,
+//! if so configured, is accompanied by a heap profile. Snapshots are written
+//! to the [`DIRECTORY`] subdirectory of the diagnostics directory, which is
+//! bounded by the number of snapshots and their total size by deleting the
+//! oldest snapshots, and are included in the diagnostics bundle.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{info, warn};
+use serde::Serialize;
+use tokio::time;
+
+use coord::{Lane, SessionInfo};
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec, UIntGauge};
+
+use crate::allocator::{self, AllocatorStats};
+use crate::scheduler::Job;
+use crate::watchdog::{self, heap_profile, ByteSize};
+
+/// The name of the subdirectory of the diagnostics directory that holds the
+/// flight recorder's snapshots.
+pub const DIRECTORY: &str = "flight-recorder";
+
+/// The prefix of the names of the files that belong to a snapshot.
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+/// The number of arrangements to describe in each snapshot.
+const TOP_ARRANGEMENTS: usize = 20;
+
+/// The maximum time to wait for the coordinator while taking a snapshot.
+const COORD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configures the flight recorder.
+#[derive(Debug, Clone)]
+pub struct FlightRecorderConfig {
+    /// How often to take a snapshot.
+    pub interval: Duration,
+    /// The fraction by which the resident set size must grow within
+    /// `rss_jump_window` to take an additional snapshot.
+    pub rss_jump_threshold: f64,
+    /// The window over which growth of the resident set size is measured.
+    pub rss_jump_window: Duration,
+    /// The maximum number of snapshots to keep.
+    pub max_snapshots: usize,
+    /// The maximum total size of the snapshots to keep, including their heap
+    /// profiles.
+    pub max_size: ByteSize,
+    /// Whether to dump a heap profile with each snapshot.
+    pub heap_profiles: bool,
+}
+
+impl Default for FlightRecorderConfig {
+    fn default() -> FlightRecorderConfig {
+        FlightRecorderConfig {
+            interval: Duration::from_secs(5 * 60),
+            rss_jump_threshold: 0.2,
+            rss_jump_window: Duration::from_secs(60),
+            max_snapshots: 20,
+            max_size: ByteSize(64 << 20),
+            heap_profiles: false,
+        }
+    }
+}
+
+impl FlightRecorderConfig {
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.interval == Duration::from_secs(0) {
+            bail!("flight recorder interval must be positive");
+        }
+        if !(self.rss_jump_threshold > 0.0) {
+            bail!(
+                "flight recorder RSS jump threshold must be positive, got {}",
+                self.rss_jump_threshold
+            );
+        }
+        if self.rss_jump_window == Duration::from_secs(0) {
+            bail!("flight recorder RSS jump window must be positive");
+        }
+        if self.max_snapshots == 0 {
+            bail!("flight recorder must keep at least one snapshot");
+        }
+        if self.max_size.as_u64() == 0 {
+            bail!("flight recorder maximum size must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// What caused a snapshot to be taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    /// The interval elapsed since the last snapshot.
+    Interval,
+    /// The resident set size grew by more than the threshold from its
+    /// minimum within the window, which was `baseline` bytes.
+    RssJump { baseline: u64 },
+}
+
+impl Trigger {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Interval => "interval",
+            Trigger::RssJump { .. } => "rss_jump",
+        }
+    }
+}
+
+/// The samples of the resident set size from which the flight recorder
+/// decides when to take a snapshot.
+#[derive(Debug, Default)]
+struct State {
+    /// The resident set size at each sample within the jump window.
+    samples: VecDeque<(Instant, u64)>,
+    /// When the last snapshot was taken.
+    last_snapshot: Option<Instant>,
+}
+
+impl State {
+    /// Records a sample of the resident set size, `rss`, taken at `now`, and
+    /// returns whether a snapshot is due, and why.
+    fn observe(
+        &mut self,
+        config: &FlightRecorderConfig,
+        now: Instant,
+        rss: Option<u64>,
+    ) -> Option<Trigger> {
+        let mut trigger = None;
+        if let Some(rss) = rss {
+            let window = config.rss_jump_window;
+            self.samples
+                .retain(|(at, _)| now.saturating_duration_since(*at) <= window);
+            let baseline = self.samples.iter().map(|(_, rss)| *rss).min();
+            if let Some(baseline) = baseline {
+                if rss as f64 > baseline as f64 * (1.0 + config.rss_jump_threshold) {
+                    // Growth is measured afresh from the jump, so that a
+                    // single jump is not reported on every sample within the
+                    // window.
+                    self.samples.clear();
+                    trigger = Some(Trigger::RssJump { baseline });
+                }
+            }
+            self.samples.push_back((now, rss));
+        }
+        if trigger.is_none() {
+            let due = match self.last_snapshot {
+                None => true,
+                Some(last) => now.saturating_duration_since(last) >= config.interval,
+            };
+            if due {
+                trigger = Some(Trigger::Interval);
+            }
+        }
+        if trigger.is_some() {
+            self.last_snapshot = Some(now);
+        }
+        trigger
+    }
+}
+
+/// Describes an arrangement, as recorded in a snapshot.
+#[derive(Debug, Serialize)]
+struct Arrangement {
+    operator_id: i64,
+    operator: String,
+    dataflow_id: i64,
+    dataflow: String,
+    records: i64,
+}
+
+/// The number of commands waiting for the coordinator, as recorded in a
+/// snapshot.
+#[derive(Debug, Serialize)]
+struct QueueDepth {
+    normal: u64,
+    priority: u64,
+}
+
+/// A snapshot of the server's state, as written to disk.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    taken_at: String,
+    trigger: &'static str,
+    resident_set_bytes: Option<u64>,
+    /// The minimum resident set size within the jump window, if the snapshot
+    /// was triggered by a jump.
+    rss_baseline_bytes: Option<u64>,
+    allocator: Option<AllocatorStats>,
+    top_arrangements: Vec<Arrangement>,
+    /// Why the arrangements could not be described, if they could not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_arrangements_error: Option<String>,
+    sessions: Vec<SessionInfo>,
+    /// Why the sessions could not be listed, if they could not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sessions_error: Option<String>,
+    coord_queue_depth: QueueDepth,
+    /// The name of the heap profile that accompanies the snapshot, if any.
+    heap_profile: Option<String>,
+}
+
+/// Flight recorder metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    snapshots: UIntCounterVec,
+    size_bytes: UIntGauge,
+}
+
+impl Metrics {
+    /// Registers the flight recorder metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            snapshots: registry.register(metric!(
+                name: "mz_flight_recorder_snapshots_total",
+                help: "the number of snapshots the flight recorder took, by trigger",
+                var_labels: ["trigger"],
+            )),
+            size_bytes: registry.register(metric!(
+                name: "mz_flight_recorder_size_bytes",
+                help: "the total size of the snapshots the flight recorder keeps",
+            )),
+        }
+    }
+}
+
+/// Takes snapshots of the server's state.
+pub struct FlightRecorder {
+    config: FlightRecorderConfig,
+    coord_client: coord::Client,
+    directory: PathBuf,
+    introspection_enabled: bool,
+    metrics: Metrics,
+    state: Mutex<State>,
+}
+
+impl FlightRecorder {
+    /// Constructs a flight recorder that writes snapshots to `directory`.
+    ///
+    /// The arrangements are described only if `introspection_enabled`.
+    pub fn new(
+        config: FlightRecorderConfig,
+        coord_client: coord::Client,
+        directory: PathBuf,
+        introspection_enabled: bool,
+        metrics: Metrics,
+    ) -> FlightRecorder {
+        FlightRecorder {
+            config,
+            coord_client,
+            directory,
+            introspection_enabled,
+            metrics,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns a job that samples the resident set size every `frequency`,
+    /// taking a snapshot whenever one is due.
+    pub fn into_job(self, frequency: Duration) -> Job {
+        let recorder = Arc::new(self);
+        Job::every("flight_recorder", frequency, move || {
+            let recorder = Arc::clone(&recorder);
+            async move { recorder.sample().await }
+        })
+    }
+
+    async fn sample(&self) {
+        let rss = tokio::task::spawn_blocking(watchdog::memory_usage)
+            .await
+            .unwrap_or(None);
+        let trigger =
+            self.state
+                .lock()
+                .expect("lock poisoned")
+                .observe(&self.config, Instant::now(), rss);
+        if let Some(trigger) = trigger {
+            if let Trigger::RssJump { baseline } = trigger {
+                info!(
+                    "flight recorder: resident set size grew from {} to {} within {:?}; \
+                     taking a snapshot",
+                    ByteSize(baseline),
+                    ByteSize(rss.unwrap_or(0)),
+                    self.config.rss_jump_window,
+                );
+            }
+            if let Err(e) = self.snapshot(trigger, rss).await {
+                warn!("flight recorder: unable to write snapshot: {:#}", e);
+            }
+        }
+    }
+
+    async fn snapshot(&self, trigger: Trigger, rss: Option<u64>) -> Result<(), anyhow::Error> {
+        let now = DateTime::<Utc>::from(SystemTime::now());
+        let stem = format!(
+            "{}{}-{}",
+            SNAPSHOT_PREFIX,
+            now.format("%Y%m%dT%H%M%S%.3fZ"),
+            trigger.as_str()
+        );
+
+        let top_arrangements = if self.introspection_enabled {
+            match time::timeout(COORD_TIMEOUT, self.top_arrangements()).await {
+                Ok(res) => res.map_err(|e| format!("{:#}", e)),
+                Err(_) => Err("timed out querying arrangements".into()),
+            }
+        } else {
+            Err("introspection is disabled".into())
+        };
+        let (top_arrangements, top_arrangements_error) = split(top_arrangements);
+        let (sessions, sessions_error) = split(
+            time::timeout(COORD_TIMEOUT, self.coord_client.list_sessions())
+                .await
+                .map_err(|_| "timed out listing sessions".into()),
+        );
+
+        let heap_profile = if self.config.heap_profiles {
+            let path = self.directory.join(format!("{}.prof", stem));
+            match heap_profile::dump(&path).await {
+                Ok(true) => Some(format!("{}.prof", stem)),
+                Ok(false) => {
+                    info!(
+                        "flight recorder: heap profiling was inactive, so no heap profile was \
+                         written; activated heap profiling for the next snapshot"
+                    );
+                    None
+                }
+                Err(e) => {
+                    warn!("flight recorder: unable to write heap profile: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let snapshot = Snapshot {
+            taken_at: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            trigger: trigger.as_str(),
+            resident_set_bytes: rss,
+            rss_baseline_bytes: match trigger {
+                Trigger::RssJump { baseline } => Some(baseline),
+                Trigger::Interval => None,
+            },
+            allocator: allocator::stats(),
+            top_arrangements,
+            top_arrangements_error,
+            sessions,
+            sessions_error,
+            coord_queue_depth: QueueDepth {
+                normal: self.coord_client.queue_depth(Lane::Normal),
+                priority: self.coord_client.queue_depth(Lane::Priority),
+            },
+            heap_profile,
+        };
+        let contents = serde_json::to_vec_pretty(&snapshot)?;
+        let directory = self.directory.clone();
+        let config = self.config.clone();
+        let size = tokio::task::spawn_blocking(move || -> Result<u64, anyhow::Error> {
+            fs::create_dir_all(&directory).with_context(|| {
+                format!(
+                    "creating flight recorder directory: {}",
+                    directory.display()
+                )
+            })?;
+            fs::write(directory.join(format!("{}.json", stem)), contents)?;
+            rotate(&directory, config.max_snapshots, config.max_size.as_u64())
+        })
+        .await??;
+        self.metrics
+            .snapshots
+            .with_label_values(&[trigger.as_str()])
+            .inc();
+        self.metrics.size_bytes.set(size);
+        Ok(())
+    }
+
+    /// Queries the introspection sources for the arrangements that hold the
+    /// most records, summed across workers.
+    async fn top_arrangements(&self) -> Result<Vec<Arrangement>, anyhow::Error> {
+        let res = self
+            .coord_client
+            .system_execute_one(&format!(
+                "SELECT
+                    o.id::pg_catalog.int8,
+                    o.name,
+                    o.dataflow_id::pg_catalog.int8,
+                    d.name,
+                    pg_catalog.sum(o.records)::pg_catalog.int8
+                 FROM mz_catalog.mz_records_per_dataflow_operator o
+                 JOIN mz_catalog.mz_dataflow_names d
+                 ON o.dataflow_id = d.id AND o.worker = d.worker
+                 GROUP BY o.id, o.name, o.dataflow_id, d.name
+                 ORDER BY pg_catalog.sum(o.records) DESC
+                 LIMIT {}",
+                TOP_ARRANGEMENTS
+            ))
+            .await?;
+        res.rows
+            .into_iter()
+            .map(|row| match row.as_slice() {
+                [operator_id, serde_json::Value::String(operator), dataflow_id, serde_json::Value::String(dataflow), records] => {
+                    Ok(Arrangement {
+                        operator_id: operator_id.as_i64().unwrap_or(0),
+                        operator: operator.clone(),
+                        dataflow_id: dataflow_id.as_i64().unwrap_or(0),
+                        dataflow: dataflow.clone(),
+                        records: records.as_i64().unwrap_or(0),
+                    })
+                }
+                _ => bail!("unexpected row: {:?}", row),
+            })
+            .collect()
+    }
+}
+
+/// Splits the result of describing part of a snapshot into the description,
+/// which is empty on failure, and the reason for the failure, if any.
+fn split<T>(res: Result<Vec<T>, String>) -> (Vec<T>, Option<String>) {
+    match res {
+        Ok(items) => (items, None),
+        Err(e) => (vec![], Some(e)),
+    }
+}
+
+/// Lists the files that belong to the snapshots in `dir`, oldest first.
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with(SNAPSHOT_PREFIX))
+        })
+        .collect();
+    // Snapshot names embed the time at which they were taken, so sorting by
+    // name sorts by age.
+    files.sort();
+    files
+}
+
+/// Deletes the oldest snapshots in `dir`, along with their heap profiles,
+/// until at most `max_snapshots` remain and their total size is at most
+/// `max_size` bytes. The newest snapshot is kept however large it is.
+///
+/// Returns the total size of the snapshots that remain.
+fn rotate(dir: &Path, max_snapshots: usize, max_size: u64) -> Result<u64, anyhow::Error> {
+    // A snapshot's files share a name up to their extension.
+    let mut snapshots: BTreeMap<String, Vec<(PathBuf, u64)>> = BTreeMap::new();
+    for path in list(dir) {
+        let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_owned(),
+            None => continue,
+        };
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        snapshots.entry(stem).or_default().push((path, size));
+    }
+    let mut total: u64 = snapshots.values().flatten().map(|(_, size)| size).sum();
+    while snapshots.len() > max_snapshots || (total > max_size && snapshots.len() > 1) {
+        let oldest = snapshots
+            .keys()
+            .next()
+            .cloned()
+            .expect("snapshots nonempty");
+        for (path, size) in snapshots.remove(&oldest).expect("key exists") {
+            fs::remove_file(&path)
+                .with_context(|| format!("removing snapshot file: {}", path.display()))?;
+            total -= size;
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    use super::{list, rotate, FlightRecorderConfig, State, Trigger};
+
+    #[test]
+    fn test_triggers() {
+        let config = FlightRecorderConfig {
+            interval: Duration::from_secs(300),
+            rss_jump_threshold: 0.2,
+            rss_jump_window: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // The first sample always takes a snapshot.
+        assert_eq!(
+            state.observe(&config, at(0), Some(1000)),
+            Some(Trigger::Interval)
+        );
+        // Growth within the threshold does not.
+        assert_eq!(state.observe(&config, at(10), Some(1100)), None);
+        assert_eq!(state.observe(&config, at(20), Some(1200)), None);
+        // Growth beyond the threshold from the minimum within the window
+        // does, once.
+        assert_eq!(
+            state.observe(&config, at(30), Some(1300)),
+            Some(Trigger::RssJump { baseline: 1000 })
+        );
+        assert_eq!(state.observe(&config, at(40), Some(1350)), None);
+        // Growth that is spread over more than the window does not.
+        assert_eq!(state.observe(&config, at(100), Some(1450)), None);
+        assert_eq!(state.observe(&config, at(160), Some(1550)), None);
+        assert_eq!(state.observe(&config, at(220), Some(1650)), None);
+        // The interval is measured from the last snapshot, whatever its
+        // trigger.
+        assert_eq!(
+            state.observe(&config, at(330), Some(1650)),
+            Some(Trigger::Interval)
+        );
+        // Without a resident set size, only the interval applies.
+        assert_eq!(state.observe(&config, at(340), None), None);
+        assert_eq!(
+            state.observe(&config, at(630), None),
+            Some(Trigger::Interval)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(FlightRecorderConfig::default().validate().is_ok());
+        for config in &[
+            FlightRecorderConfig {
+                interval: Duration::from_secs(0),
+                ..Default::default()
+            },
+            FlightRecorderConfig {
+                rss_jump_threshold: 0.0,
+                ..Default::default()
+            },
+            FlightRecorderConfig {
+                max_snapshots: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn test_rotate() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        for (name, size) in &[
+            ("snapshot-1-interval.json", 100),
+            ("snapshot-1-interval.prof", 1000),
+            ("snapshot-2-interval.json", 100),
+            ("snapshot-3-rss_jump.json", 100),
+            ("snapshot-3-rss_jump.prof", 1000),
+            ("snapshot-4-interval.json", 100),
+            ("unrelated.txt", 5000),
+        ] {
+            fs::write(dir.path().join(name), vec![0; *size])?;
+        }
+        let names = |dir: &tempfile::TempDir| -> Vec<String> {
+            list(dir.path())
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+                .collect()
+        };
+
+        // Rotating by count removes the oldest snapshot with its profile.
+        assert_eq!(rotate(dir.path(), 3, u64::MAX)?, 1300);
+        assert_eq!(
+            names(&dir),
+            &[
+                "snapshot-2-interval.json",
+                "snapshot-3-rss_jump.json",
+                "snapshot-3-rss_jump.prof",
+                "snapshot-4-interval.json",
+            ]
+        );
+
+        // Rotating by size removes snapshots until the rest fit.
+        assert_eq!(rotate(dir.path(), 3, 1000)?, 100);
+        assert_eq!(names(&dir), &["snapshot-4-interval.json"]);
+
+        // The newest snapshot is kept even if it alone is too large.
+        assert_eq!(rotate(dir.path(), 3, 10)?, 100);
+        assert_eq!(names(&dir), &["snapshot-4-interval.json"]);
+        assert!(dir.path().join("unrelated.txt").exists());
+        Ok(())
+    }
+}
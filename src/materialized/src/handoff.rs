@@ -0,0 +1,271 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Handoff of the listener from one server process to its successor.
+//!
+//! To restart without refusing connections, some process must be listening
+//! on the server's address at every moment. A server serves a control
+//! socket, [`CONTROL_SOCKET`] in its data directory, through which a new
+//! server process, started with
+//! [`Config::takeover_from`](crate::Config::takeover_from), takes over its
+//! listener:
+//!
+//!   1. The successor connects to the control socket and sends
+//!      `takeover <addr>\n`, naming the address on which it expects the
+//!      server to be listening.
+//!   2. The server responds with `ok\n`, accompanied by the file descriptor
+//!      of its listening socket (`SCM_RIGHTS`), or with `error <message>\n`.
+//!      Having handed off the listener, the server stops accepting
+//!      connections, so that the successor accepts every connection from
+//!      then on, and drains its existing sessions.
+//!   3. The server holds the control connection open until its process
+//!      exits, at which point it no longer holds the catalog. The successor
+//!      waits for the connection to close before it opens the catalog, and
+//!      until then answers connections as any starting server does.
+//!
+//! The successor serves its own control socket at the same path, replacing
+//! the server's, so that it can in turn hand off the listener to its own
+//! successor. Once the listener has been handed off, the server removes its
+//! control socket, unless the successor has already replaced it. If the
+//! handoff fails, the server keeps its control socket and continues to serve,
+//! and the listener may be handed off again.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{self, SocketAddr};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use futures::future::Future;
+use log::{info, warn};
+use nix::fcntl::{self, FcntlArg, FdFlag};
+use nix::sys::socket::{self, ControlMessage, ControlMessageOwned, MsgFlags};
+use nix::sys::uio::IoVec;
+use nix::unistd;
+use tokio::task;
+
+/// The name of the control socket in the data directory.
+pub const CONTROL_SOCKET: &str = "handoff.sock";
+
+/// How long either side of the control connection waits for the other to
+/// speak.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request or response on the control connection.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Serves the control socket in `data_directory`, through which a successor
+/// may take over `listener`.
+///
+/// The returned future resolves to the control connection once a successor
+/// has taken over the listener. The caller should then stop accepting
+/// connections, drain, and hold the connection open until the process
+/// exits. Dropping the future stops serving the control socket.
+pub fn serve(
+    data_directory: &Path,
+    listener: &tokio::net::TcpListener,
+) -> Result<impl Future<Output = UnixStream>, anyhow::Error> {
+    let local_addr = listener.local_addr()?;
+    // The descriptor that is handed off is a duplicate, so that it remains
+    // valid however long the handoff takes.
+    let listener = duplicate(listener.as_raw_fd()).context("duplicating listener")?;
+    let path = data_directory.join(CONTROL_SOCKET);
+    // A control socket left behind by a process that did not exit cleanly
+    // would prevent the bind.
+    match fs::remove_file(&path) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => {
+            return Err(e).with_context(|| format!("removing stale {}", path.display()));
+        }
+    }
+    let control = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("binding handoff control socket {}", path.display()))?;
+    let control_ino = fs::metadata(&path)
+        .with_context(|| format!("inspecting handoff control socket {}", path.display()))?
+        .ino();
+    Ok(async move {
+        loop {
+            // The request is read with blocking I/O, which is simpler to bound
+            // with a timeout.
+            let conn = control.accept().await.and_then(|(conn, _)| {
+                let conn = conn.into_std()?;
+                conn.set_nonblocking(false)?;
+                Ok(conn)
+            });
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("error accepting handoff control connection: {}", e);
+                    continue;
+                }
+            };
+            let res = task::spawn_blocking(move || {
+                let res = read_request(&conn, local_addr);
+                (conn, res)
+            })
+            .await;
+            let conn = match res {
+                Ok((conn, Ok(true))) => conn,
+                Ok((_, Ok(false))) => continue,
+                Ok((_, Err(e))) => {
+                    warn!("error reading handoff request: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("error reading handoff request: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = send_listener(&conn, &listener) {
+                warn!("error handing off listener: {}", e);
+                continue;
+            }
+            info!("listener on {} handed off to a new server", local_addr);
+            // The successor replaces the control socket once it holds the
+            // listener, and its control socket must not be removed.
+            if matches!(fs::metadata(&path), Ok(m) if m.ino() == control_ino) {
+                let _ = fs::remove_file(&path);
+            }
+            return conn;
+        }
+    })
+}
+
+/// Reads a request from a successor, and reports whether it is a valid
+/// request to take over the listener on `local_addr`. Invalid requests are
+/// answered with an error.
+fn read_request(conn: &UnixStream, local_addr: SocketAddr) -> Result<bool, io::Error> {
+    conn.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(conn.take(MAX_MESSAGE_LEN as u64)).read_line(&mut request)?;
+    let request = request.trim_end();
+    let error = match request.strip_prefix("takeover ") {
+        None => format!("unrecognized request {:?}", request),
+        Some(addr) => match addr.parse::<SocketAddr>() {
+            Err(_) => format!("invalid address {:?}", addr),
+            Ok(addr) if addr != local_addr => {
+                format!("server is listening on {}, not {}", local_addr, addr)
+            }
+            Ok(_) => return Ok(true),
+        },
+    };
+    warn!("refusing handoff request: {}", error);
+    (&*conn).write_all(format!("error {}\n", error).as_bytes())?;
+    Ok(false)
+}
+
+fn send_listener(conn: &UnixStream, listener: &net::TcpListener) -> Result<(), anyhow::Error> {
+    let fds = [listener.as_raw_fd()];
+    socket::sendmsg(
+        conn.as_raw_fd(),
+        &[IoVec::from_slice(b"ok\n")],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+    Ok(())
+}
+
+/// The server process whose listener this process took over.
+#[derive(Debug)]
+pub struct Predecessor {
+    conn: UnixStream,
+}
+
+impl Predecessor {
+    /// Waits for the predecessor to exit, once it has drained its sessions.
+    pub async fn exited(self) -> Result<(), anyhow::Error> {
+        let mut conn = self.conn;
+        tokio::task::spawn_blocking(move || {
+            let mut buf = [0; 64];
+            loop {
+                match conn.read(&mut buf) {
+                    Ok(0) => return,
+                    Ok(_) => (),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    // Any other error means the connection is gone.
+                    Err(_) => return,
+                }
+            }
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Takes over the listener of the server process that is listening on
+/// `addr` with the data directory `data_directory`.
+///
+/// This function performs blocking I/O.
+pub fn take_over(
+    data_directory: &Path,
+    addr: SocketAddr,
+) -> Result<(net::TcpListener, Predecessor), anyhow::Error> {
+    let path = data_directory.join(CONTROL_SOCKET);
+    let conn = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "connecting to handoff control socket {}; is a server running with this data directory?",
+            path.display()
+        )
+    })?;
+    conn.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    (&conn).write_all(format!("takeover {}\n", addr).as_bytes())?;
+
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+    let (len, fd) = {
+        let msg = socket::recvmsg(
+            conn.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut buf)],
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )
+        .context("receiving handoff response")?;
+        let mut fds = msg.cmsgs().flat_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds,
+            _ => vec![],
+        });
+        let fd = fds.next();
+        // Only one descriptor is expected.
+        for fd in fds {
+            let _ = unistd::close(fd);
+        }
+        (msg.bytes, fd)
+    };
+    // Take ownership of the descriptor, so that it is closed on error.
+    let listener = fd.map(|fd| unsafe { net::TcpListener::from_raw_fd(fd) });
+    let response = String::from_utf8_lossy(&buf[..len]);
+    let response = response.trim_end();
+    let listener = match (response, listener) {
+        ("ok", Some(listener)) => listener,
+        ("", _) => bail!(
+            "server listening on {} closed the handoff control connection",
+            addr
+        ),
+        (response, _) => match response.strip_prefix("error ") {
+            Some(error) => bail!("server listening on {} refused handoff: {}", addr, error),
+            None => bail!("unexpected handoff response {:?}", response),
+        },
+    };
+    fcntl::fcntl(listener.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    listener.set_nonblocking(true)?;
+    conn.set_read_timeout(None)?;
+    Ok((listener, Predecessor { conn }))
+}
+
+/// Duplicates the socket `fd` into a new listener that closes on exec.
+fn duplicate(fd: RawFd) -> Result<net::TcpListener, nix::Error> {
+    let fd = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+    Ok(unsafe { net::TcpListener::from_raw_fd(fd) })
+}
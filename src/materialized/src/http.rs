@@ -12,31 +12,74 @@
 //! materialized embeds an HTTP server for introspection into the running
 //! process. At the moment, its primary exports are Prometheus metrics, heap
 //! profiles, and catalog dumps.
+//!
+//! The server speaks HTTP/1.1, and HTTP/2 to clients that negotiate it via
+//! ALPN during the TLS handshake. If configured, it also speaks HTTP/2 over
+//! plaintext connections to clients that know in advance that the server
+//! supports it, i.e., that begin the connection with the HTTP/2 preface.
 
-use std::pin::Pin;
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures::future::TryFutureExt;
-use hyper::{service, Method, StatusCode};
+use futures::future::{self, TryFutureExt};
+use hyper::header::{self, HeaderValue};
+use hyper::{service, Body, Method, Request, StatusCode, Version};
 use hyper_openssl::MaybeHttpsStream;
 use openssl::nid::Nid;
 use openssl::ssl::{Ssl, SslContext};
 use ore::metrics::MetricsRegistry;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tokio_openssl::SslStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time;
+use tracing::{field, info, Instrument, Span};
 
 use coord::session::Session;
+use coord::{Lane, Transport};
 use ore::future::OreFutureExt;
-use ore::netio::SniffedStream;
+use ore::netio::{SniffedStream, TlsHandshakePool};
+
+use crate::allocator;
+use crate::backup;
+use crate::compaction;
+use crate::http::limit::RequestLimiter;
+use crate::idle::ConnectionTracker;
+use crate::liveness::CoordHealth;
+use crate::logging::{LogFileHandle, LogFilterHandle};
+use crate::metrics_guard::MetricsGuard;
+use crate::source_lag::SourceLagMetrics;
+use crate::startup::StartupProgress;
+use crate::system::HostEnvironment;
+use crate::version_check::VersionStatus;
+use crate::{EncryptionConfig, Metrics};
 
-use crate::Metrics;
+pub use build_info::RuntimeFlags;
+pub use diagnostics::DiagnosticsConfig;
 
+mod api;
+mod arrow_ipc;
+mod build_info;
 mod catalog;
+mod diagnostics;
+#[cfg(feature = "failpoints")]
+mod failpoints;
+mod introspection;
+mod limit;
+mod log_filter;
+mod logs;
+mod maintenance;
 mod memory;
 mod metrics;
 mod prof;
+mod quotas;
 mod root;
+mod sessions;
+mod slow_query;
+mod sources;
 mod sql;
+mod startup;
+mod telemetry;
+mod users;
 mod util;
 
 const SYSTEM_USER: &str = "mz_system";
@@ -47,23 +90,101 @@ const METHODS: &[&[u8]] = &[
 
 const TLS_HANDSHAKE_START: u8 = 22;
 
+/// The preface with which every HTTP/2 connection begins.
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The ALPN protocols that the server supports over TLS, in order of
+/// preference, in the wire format expected by
+/// [`openssl::ssl::select_next_proto`].
+pub const ALPN_PROTOCOLS: &[u8] = b"\x02h2\x08http/1.1";
+
+/// The delay, in seconds, after which clients of a draining server are asked
+/// to retry their requests against another server.
+const DRAIN_RETRY_AFTER: &str = "1";
+
+/// The header that reports the name of the server, if it has one.
+pub const SERVER_NAME_HEADER: &str = "x-materialize-server-name";
+
+/// How long a request to the SQL endpoint waits for other requests to
+/// release buffered results before it fails.
+const RESULT_MEMORY_WAIT: Duration = Duration::from_secs(1);
+
 fn sniff_tls(buf: &[u8]) -> bool {
     !buf.is_empty() && buf[0] == TLS_HANDSHAKE_START
 }
 
+fn sniff_http2(buf: &[u8]) -> bool {
+    !buf.is_empty() && HTTP2_PREFACE.starts_with(buf)
+}
+
+fn match_handshake(tls: &Option<TlsConfig>, http2_cleartext: bool, buf: &[u8]) -> bool {
+    if tls.is_some() && sniff_tls(buf) {
+        return true;
+    }
+    if http2_cleartext && sniff_http2(buf) {
+        return true;
+    }
+    let buf = if let Some(pos) = buf.iter().position(|&b| b == b' ') {
+        &buf[..pos]
+    } else {
+        &buf[..]
+    };
+    METHODS.contains(&buf)
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub tls: Option<TlsConfig>,
     pub coord_client: coord::Client,
     pub start_time: Instant,
     pub metrics_registry: MetricsRegistry,
+    pub metrics_guard: MetricsGuard,
     pub global_metrics: Metrics,
+    pub telemetry_toggle: Option<crate::telemetry::Toggle>,
+    pub telemetry_spool: Option<crate::telemetry::Spool>,
+    pub log_filter: Option<LogFilterHandle>,
+    pub log_file: Option<LogFileHandle>,
+    pub backup_metrics: backup::Metrics,
+    pub compaction_metrics: compaction::Metrics,
+    pub read_only: bool,
+    pub runtime_flags: RuntimeFlags,
+    pub scratch_directory: PathBuf,
+    pub data_encryption: Option<EncryptionConfig>,
+    pub allocator_metrics: allocator::Metrics,
+    pub startup: StartupProgress,
+    pub draining: pgwire::Draining,
+    pub drain_notices: bool,
+    pub coord_health: CoordHealth,
+    pub version_status: VersionStatus,
+    pub source_lag: SourceLagMetrics,
+    pub statement_durations: coord::StatementDurations,
+    pub authorizer: Option<Arc<dyn coord::Authorizer>>,
+    pub max_concurrent_requests: Option<usize>,
+    pub request_queue_size: usize,
+    pub request_queue_timeout: Duration,
+    pub result_memory_limit: Option<usize>,
+    pub arrow_max_batch_size: usize,
+    pub http2_cleartext: bool,
+    pub http1_keep_alive_timeout: Option<Duration>,
+    pub http1_max_requests_per_connection: Option<usize>,
+    pub connection_notice: pgwire::ConnectionNotice,
+    pub maintenance_mode: coord::MaintenanceMode,
+    pub clock: coord::ClockMonitor,
+    pub session_quotas: coord::Quotas,
+    pub transport_monitor: coord::TransportMonitor,
+    pub host_environment: Arc<HostEnvironment>,
+    pub server_name: Option<String>,
+    pub diagnostics: DiagnosticsConfig,
+    pub connection_tracker: ConnectionTracker,
+    #[cfg(feature = "failpoints")]
+    pub fault_injection: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
     pub context: SslContext,
     pub mode: TlsMode,
+    pub handshake_pool: TlsHandshakePool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -78,17 +199,107 @@ pub struct Server {
     coord_client: coord::Client,
     start_time: Instant,
     metrics_registry: MetricsRegistry,
+    metrics_guard: MetricsGuard,
     global_metrics: Metrics,
+    telemetry_toggle: Option<crate::telemetry::Toggle>,
+    telemetry_spool: Option<crate::telemetry::Spool>,
+    log_filter: Option<LogFilterHandle>,
+    log_file: Option<LogFileHandle>,
+    backup_metrics: backup::Metrics,
+    compaction_metrics: compaction::Metrics,
+    read_only: bool,
+    runtime_flags: RuntimeFlags,
+    scratch_directory: PathBuf,
+    data_encryption: Option<EncryptionConfig>,
+    allocator_metrics: allocator::Metrics,
+    startup: StartupProgress,
+    draining: pgwire::Draining,
+    drain_notices: bool,
+    coord_health: CoordHealth,
+    version_status: VersionStatus,
+    source_lag: SourceLagMetrics,
+    statement_durations: coord::StatementDurations,
+    authorizer: Option<Arc<dyn coord::Authorizer>>,
+    request_limiter: Option<RequestLimiter>,
+    result_budget: coord::ResultBudget,
+    arrow_max_batch_size: usize,
+    http2_cleartext: bool,
+    http1_keep_alive_timeout: Option<Duration>,
+    http1_max_requests_per_connection: Option<usize>,
+    connection_notice: pgwire::ConnectionNotice,
+    maintenance_mode: coord::MaintenanceMode,
+    clock: coord::ClockMonitor,
+    session_quotas: coord::Quotas,
+    transport_monitor: coord::TransportMonitor,
+    host_environment: Arc<HostEnvironment>,
+    server_name: Option<String>,
+    diagnostics: DiagnosticsConfig,
+    connection_tracker: ConnectionTracker,
+    #[cfg(feature = "failpoints")]
+    fault_injection: bool,
 }
 
 impl Server {
     pub fn new(config: Config) -> Server {
+        let request_limiter = config.max_concurrent_requests.map(|max| {
+            RequestLimiter::new(
+                max,
+                config.request_queue_size,
+                config.request_queue_timeout,
+                config.global_metrics.http_requests_in_flight.clone(),
+                config.global_metrics.http_requests_queued.clone(),
+                config.global_metrics.http_requests_rejected.clone(),
+            )
+        });
+        let result_budget = coord::ResultBudget::new(
+            config.result_memory_limit,
+            RESULT_MEMORY_WAIT,
+            config.global_metrics.http_result_buffered_bytes.clone(),
+            config.global_metrics.http_result_rejected.clone(),
+        );
         Server {
             tls: config.tls,
             coord_client: config.coord_client,
             start_time: config.start_time,
             metrics_registry: config.metrics_registry,
+            metrics_guard: config.metrics_guard,
             global_metrics: config.global_metrics,
+            telemetry_toggle: config.telemetry_toggle,
+            telemetry_spool: config.telemetry_spool,
+            log_filter: config.log_filter,
+            log_file: config.log_file,
+            backup_metrics: config.backup_metrics,
+            compaction_metrics: config.compaction_metrics,
+            read_only: config.read_only,
+            runtime_flags: config.runtime_flags,
+            scratch_directory: config.scratch_directory,
+            data_encryption: config.data_encryption,
+            allocator_metrics: config.allocator_metrics,
+            startup: config.startup,
+            draining: config.draining,
+            drain_notices: config.drain_notices,
+            coord_health: config.coord_health,
+            version_status: config.version_status,
+            source_lag: config.source_lag,
+            statement_durations: config.statement_durations,
+            authorizer: config.authorizer,
+            request_limiter,
+            result_budget,
+            arrow_max_batch_size: config.arrow_max_batch_size,
+            http2_cleartext: config.http2_cleartext,
+            http1_keep_alive_timeout: config.http1_keep_alive_timeout,
+            http1_max_requests_per_connection: config.http1_max_requests_per_connection,
+            connection_notice: config.connection_notice,
+            maintenance_mode: config.maintenance_mode,
+            clock: config.clock,
+            session_quotas: config.session_quotas,
+            transport_monitor: config.transport_monitor,
+            host_environment: config.host_environment,
+            server_name: config.server_name,
+            diagnostics: config.diagnostics,
+            connection_tracker: config.connection_tracker,
+            #[cfg(feature = "failpoints")]
+            fault_injection: config.fault_injection,
         }
     }
 
@@ -96,37 +307,55 @@ impl Server {
         self.tls.as_ref().map(|tls| tls.mode)
     }
 
-    fn tls_context(&self) -> Option<&SslContext> {
-        self.tls.as_ref().map(|tls| &tls.context)
-    }
-
     pub fn match_handshake(&self, buf: &[u8]) -> bool {
-        if self.tls.is_some() && sniff_tls(buf) {
-            return true;
-        }
-        let buf = if let Some(pos) = buf.iter().position(|&b| b == b' ') {
-            &buf[..pos]
-        } else {
-            &buf[..]
-        };
-        METHODS.contains(&buf)
+        match_handshake(&self.tls, self.http2_cleartext, buf)
     }
 
-    pub async fn handle_connection<A>(&self, conn: SniffedStream<A>) -> Result<(), anyhow::Error>
+    /// Handles a connection from a client.
+    ///
+    /// The `correlation_id` is attached to the session of every request on
+    /// the connection and returned to the client in the `x-correlation-id`
+    /// header of every response.
+    pub async fn handle_connection<A>(
+        &self,
+        conn: SniffedStream<A>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error>
     where
         A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
     {
-        let conn = match (&self.tls_context(), sniff_tls(&conn.sniff_buffer())) {
-            (Some(tls_context), true) => {
-                let mut ssl_stream = SslStream::new(Ssl::new(tls_context)?, conn)?;
-                if let Err(e) = Pin::new(&mut ssl_stream).accept().await {
-                    let _ = ssl_stream.get_mut().shutdown().await;
-                    return Err(e.into());
-                }
-                MaybeHttpsStream::Https(ssl_stream)
+        let conn = match (&self.tls, sniff_tls(&conn.sniff_buffer())) {
+            (Some(tls), true) => {
+                let ssl = Ssl::new(&tls.context)?;
+                MaybeHttpsStream::Https(tls.handshake_pool.accept(ssl, conn).await?)
             }
             _ => MaybeHttpsStream::Http(conn),
         };
+        let transport = match &conn {
+            MaybeHttpsStream::Http(_) => Transport::Plaintext,
+            MaybeHttpsStream::Https(conn) => Transport::Tls {
+                version: conn.ssl().version_str().into(),
+                cipher: conn
+                    .ssl()
+                    .current_cipher()
+                    .map_or("unknown", |cipher| cipher.name())
+                    .into(),
+            },
+        };
+        Span::current().record("transport", &field::display(&transport));
+        // The connection counts toward its transport until it closes.
+        let _transport_guard = self.transport_monitor.connected(&transport);
+
+        // Speak HTTP/2 if the client negotiated it during the TLS handshake,
+        // or began a plaintext connection with the HTTP/2 preface.
+        let http2 = match &conn {
+            MaybeHttpsStream::Https(conn) => conn.ssl().selected_alpn_protocol() == Some(b"h2"),
+            MaybeHttpsStream::Http(conn) => {
+                self.http2_cleartext && sniff_http2(&conn.sniff_buffer())
+            }
+        };
+        let activity = ConnectionActivity::new();
+        let requests = AtomicUsize::new(0);
 
         // Validate that the connection is compatible with the TLS mode.
         //
@@ -147,21 +376,211 @@ impl Server {
                 .map(|cn| cn.to_string())
                 .ok_or("invalid user name in client certificate"),
         };
+        if let Ok(user) = &user {
+            Span::current().record("user", &user.as_str());
+        }
 
-        let svc = service::service_fn(move |req| {
+        let server_name_header = self
+            .server_name
+            .as_deref()
+            .and_then(|name| HeaderValue::from_str(name).ok());
+        let svc_activity = activity.clone();
+        let svc = service::service_fn(move |req: Request<Body>| {
+            self.global_metrics
+                .http_requests
+                .with_label_values(&[protocol_label(req.version())])
+                .inc();
+            let active_request = svc_activity.start();
+            // Close HTTP/1 connections once they have served the maximum
+            // number of requests.
+            let requests = requests.fetch_add(1, Ordering::SeqCst) + 1;
+            let close_after = !http2
+                && matches!(self.http1_max_requests_per_connection, Some(max) if requests >= max);
+            let span = tracing::info_span!(
+                "http_request",
+                method = %req.method(),
+                path = req.uri().path(),
+                traceparent = field::Empty,
+            );
+            if let Some(traceparent) = req.headers().get("traceparent") {
+                if let Ok(traceparent) = traceparent.to_str() {
+                    span.record("traceparent", &traceparent);
+                }
+            }
             let user = user.clone();
+            let correlation_id = correlation_id.clone();
+            let transport = transport.clone();
+            let header_value = HeaderValue::from_str(&correlation_id).ok();
             let coord_client = self.coord_client.clone();
             let start_time = self.start_time;
             let metrics_registry = self.metrics_registry.clone();
+            let metrics_guard = self.metrics_guard.clone();
             let global_metrics = self.global_metrics.clone();
+            let telemetry_toggle = self.telemetry_toggle.clone();
+            let telemetry_spool = self.telemetry_spool.clone();
+            let log_filter = self.log_filter.clone();
+            let log_file = self.log_file.clone();
+            let backup_metrics = self.backup_metrics.clone();
+            let compaction_metrics = self.compaction_metrics.clone();
+            let read_only = self.read_only;
+            let runtime_flags = self.runtime_flags;
+            let logical_compaction_window = self.coord_client.logical_compaction_window();
+            let dataflow_memory_budget = self.coord_client.dataflow_memory_budget().clone();
+            let scratch_directory = self.scratch_directory.clone();
+            let data_encryption = self.data_encryption.clone();
+            let allocator_metrics = self.allocator_metrics.clone();
+            let startup = self.startup.clone();
+            let draining = self.draining.clone();
+            let drain_notices = self.drain_notices;
+            let coord_health = self.coord_health.clone();
+            let version_status = self.version_status.clone();
+            let source_lag = self.source_lag.clone();
+            let statement_durations = self.statement_durations.clone();
+            let authorizer = self.authorizer.clone();
+            let request_limiter = self.request_limiter.clone();
+            let result_budget = self.result_budget.clone();
+            let arrow_max_batch_size = self.arrow_max_batch_size;
+            let connection_notice = self.connection_notice.get();
+            let maintenance_mode = self.maintenance_mode.clone();
+            let clock = self.clock.clone();
+            let session_quotas = self.session_quotas.clone();
+            let host_environment = Arc::clone(&self.host_environment);
+            let server_name = self.server_name.clone();
+            let server_name_header = server_name_header.clone();
+            let diagnostics = self.diagnostics.clone();
+            let connection_tracker = self.connection_tracker.clone();
+            #[cfg(feature = "failpoints")]
+            let fault_injection = self.fault_injection;
+            // Responses from the `/api` endpoints are served at the version
+            // that the client asks for.
+            let method = req.method().clone();
+            let path = req.uri().path().to_owned();
+            let api_version = match api::is_api_path(&path) {
+                true => api::negotiate(&req),
+                false => Ok(api::API_VERSION),
+            };
+            let served_version = *api_version.as_ref().unwrap_or(&api::API_VERSION);
             let future = async move {
+                let _active_request = active_request;
                 let user = match user {
                     Ok(user) => user,
                     Err(e) => return Ok(util::error_response(StatusCode::UNAUTHORIZED, e)),
                 };
+                if let Err(res) = api_version {
+                    return Ok(res);
+                }
+
+                // A draining server serves only requests to cancel the queries
+                // of sessions that are already established.
+                let is_cancel = req.method() == Method::POST
+                    && sessions::cancel_target(req.uri().path()).is_some();
+                if draining.is_draining() && !is_cancel {
+                    info!("refusing HTTP request: server is draining");
+                    global_metrics
+                        .connections_rejected_draining
+                        .with_label_values(&["http"])
+                        .inc();
+                    let mut res = util::error_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "the server is shutting down",
+                    );
+                    res.headers_mut().insert(
+                        header::RETRY_AFTER,
+                        HeaderValue::from_static(DRAIN_RETRY_AFTER),
+                    );
+                    return Ok(res);
+                }
+
+                // Faults are managed without a session, so that faults injected
+                // into the coordinator cannot prevent their own removal.
+                #[cfg(feature = "failpoints")]
+                {
+                    if req.uri().path() == "/api/failpoints"
+                        && matches!(req.method(), &Method::GET | &Method::POST)
+                    {
+                        return failpoints::handle_failpoints(req, fault_injection).await;
+                    }
+                }
+
+                // The build information is static, and so is served without a
+                // session.
+                if req.method() == Method::GET && req.uri().path() == "/api/build-info" {
+                    return Ok(build_info::handle_build_info(&req, &runtime_flags));
+                }
+
+                // As is the index of the `/api` endpoints.
+                if req.method() == Method::GET && req.uri().path() == "/api" {
+                    return Ok(api::handle_api_index());
+                }
+
+                // Requests beyond the concurrency limit wait their turn, except
+                // for the endpoints that report on the server's health, so
+                // that an overloaded server can still be observed.
+                let is_exempt = req.method() == Method::GET
+                    && matches!(req.uri().path(), "/metrics" | "/status" | "/api/status");
+                let _permit = match &request_limiter {
+                    Some(request_limiter) if !is_exempt => match request_limiter.admit().await {
+                        Ok(permit) => Some(permit),
+                        Err(res) => return Ok(res),
+                    },
+                    _ => None,
+                };
 
-                let coord_client = coord_client.new_conn()?;
-                let session = Session::new(coord_client.conn_id(), user);
+                // An unresponsive coordinator would never start a session, so
+                // the endpoints that report on the server's health answer
+                // without one.
+                if coord_health.is_unresponsive() {
+                    match (req.method(), req.uri().path()) {
+                        (&Method::GET, "/api/status") => {
+                            return Ok(metrics::handle_api_status_unresponsive(
+                                req,
+                                start_time,
+                                read_only,
+                                logical_compaction_window,
+                                &startup,
+                                &version_status,
+                                &source_lag,
+                                connection_notice.as_deref(),
+                                maintenance_mode.get().as_ref(),
+                                &clock,
+                                &host_environment,
+                                server_name.as_deref(),
+                                &dataflow_memory_budget,
+                            ));
+                        }
+                        (&Method::GET, "/metrics") => {
+                            return metrics::handle_prometheus(
+                                req,
+                                &metrics_registry,
+                                &metrics_guard,
+                                &global_metrics,
+                            )
+                            .await;
+                        }
+                        _ => (),
+                    }
+                }
+
+                // User statements queue in the coordinator's normal lane. The
+                // health and admin endpoints use the priority lane, so that
+                // they answer promptly however many statements are queued.
+                let mut coord_client = coord_client.new_conn()?;
+                match (req.method(), req.uri().path()) {
+                    (&Method::GET, "/") | (&Method::POST, "/sql") => (),
+                    _ => coord_client.set_lane(Lane::Priority),
+                }
+                let mut session = Session::new(coord_client.conn_id(), user);
+                session.set_correlation_id(correlation_id);
+                session.set_transport(transport);
+                // Clients identify themselves with the same settings that
+                // pgwire clients supply as startup parameters.
+                let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+                if let Some(options) = header("x-session-options") {
+                    session.set_options(coord::session::parse_options(options));
+                }
+                if let Some(application_name) = header("x-application-name") {
+                    let _ = session.vars_mut().set("application_name", application_name);
+                }
                 let (mut coord_client, _) = match coord_client.startup(session).await {
                     Ok(coord_client) => coord_client,
                     Err(e) => {
@@ -171,15 +590,18 @@ impl Server {
                         ))
                     }
                 };
+                coord_client.set_statement_durations(statement_durations);
+                if let Some(authorizer) = &authorizer {
+                    coord_client.set_authorizer(Arc::clone(authorizer));
+                }
 
                 let res = match (req.method(), req.uri().path()) {
                     (&Method::GET, "/") => root::handle_home(req, &mut coord_client).await,
                     (&Method::GET, "/metrics") => {
                         metrics::handle_prometheus(
                             req,
-                            &mut coord_client,
-                            start_time,
                             &metrics_registry,
+                            &metrics_guard,
                             &global_metrics,
                         )
                         .await
@@ -190,7 +612,28 @@ impl Server {
                             &mut coord_client,
                             start_time,
                             &metrics_registry,
+                            &metrics_guard,
                             &global_metrics,
+                            read_only,
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/status") => {
+                        metrics::handle_api_status(
+                            req,
+                            &mut coord_client,
+                            start_time,
+                            read_only,
+                            logical_compaction_window,
+                            &startup,
+                            &version_status,
+                            &source_lag,
+                            connection_notice.as_deref(),
+                            maintenance_mode.get().as_ref(),
+                            &clock,
+                            &host_environment,
+                            server_name.as_deref(),
+                            &dataflow_memory_budget,
                         )
                         .await
                     }
@@ -198,8 +641,150 @@ impl Server {
                     (&Method::GET, "/memory") => {
                         memory::handle_memory(req, &mut coord_client).await
                     }
+                    (&Method::GET, "/api/memory") => {
+                        memory::handle_api_memory(req, &mut coord_client, &connection_tracker).await
+                    }
+                    (&Method::POST, "/api/memory/purge") => {
+                        memory::handle_memory_purge(req, &mut coord_client, &allocator_metrics)
+                            .await
+                    }
+                    (&Method::POST, "/api/admin/trim-memory") => {
+                        memory::handle_trim_memory(req, &mut coord_client, &allocator_metrics).await
+                    }
                     (&Method::POST, "/prof") => prof::handle_prof(req, &mut coord_client).await,
-                    (&Method::POST, "/sql") => sql::handle_sql(req, &mut coord_client).await,
+                    (&Method::POST, "/sql") => {
+                        sql::handle_sql(
+                            req,
+                            &mut coord_client,
+                            connection_notice.as_deref(),
+                            &result_budget,
+                            arrow_max_batch_size,
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/telemetry") | (&Method::POST, "/api/telemetry") => {
+                        telemetry::handle_telemetry(
+                            req,
+                            &mut coord_client,
+                            telemetry_toggle.as_ref(),
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/admin/telemetry/spool")
+                    | (&Method::POST, "/api/admin/telemetry/spool/drain") => {
+                        telemetry::handle_telemetry_spool(
+                            req,
+                            &mut coord_client,
+                            telemetry_spool.as_ref(),
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/admin/maintenance")
+                    | (&Method::POST, "/api/admin/maintenance") => {
+                        maintenance::handle_maintenance(req, &mut coord_client, &maintenance_mode)
+                            .await
+                    }
+                    (&Method::GET, "/api/admin/session-quotas")
+                    | (&Method::POST, "/api/admin/session-quotas") => {
+                        quotas::handle_session_quotas(req, &mut coord_client, &session_quotas).await
+                    }
+                    (&Method::GET, "/api/admin/diagnostics") => {
+                        let status = metrics::status_json(
+                            "ready",
+                            start_time,
+                            read_only,
+                            logical_compaction_window,
+                            &startup,
+                            &version_status,
+                            &source_lag,
+                            connection_notice.as_deref(),
+                            maintenance_mode.get().as_ref(),
+                            &clock,
+                            &host_environment,
+                            server_name.as_deref(),
+                            &dataflow_memory_budget,
+                        );
+                        diagnostics::handle_diagnostics(
+                            req,
+                            &mut coord_client,
+                            authorizer.as_deref(),
+                            &diagnostics,
+                            status,
+                            &metrics_registry,
+                            &metrics_guard,
+                            &global_metrics,
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/log-filter") | (&Method::PUT, "/api/log-filter") => {
+                        log_filter::handle_log_filter(req, &mut coord_client, log_filter.as_ref())
+                            .await
+                    }
+                    (&Method::GET, "/api/logs") => {
+                        logs::handle_logs(req, &mut coord_client, log_filter.as_ref()).await
+                    }
+                    (&Method::POST, "/api/log-file/reopen") => {
+                        log_filter::handle_log_file_reopen(
+                            req,
+                            &mut coord_client,
+                            log_file.as_ref(),
+                        )
+                        .await
+                    }
+                    (&Method::GET, "/api/slow-query-threshold")
+                    | (&Method::PUT, "/api/slow-query-threshold") => {
+                        slow_query::handle_slow_query_threshold(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/introspection")
+                    | (&Method::POST, "/api/introspection") => {
+                        introspection::handle_introspection(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/sessions") => {
+                        sessions::handle_sessions(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/users/stats") => {
+                        users::handle_user_stats(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/sources") => {
+                        sources::handle_sources(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/sinks") => {
+                        sources::handle_sinks(req, &mut coord_client).await
+                    }
+                    (&Method::POST, _) if is_cancel => {
+                        sessions::handle_cancel_session(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/catalog/dump") => {
+                        catalog::handle_catalog_dump(req, &mut coord_client).await
+                    }
+                    (&Method::GET, "/api/catalog/backup") => {
+                        catalog::handle_catalog_backup(
+                            req,
+                            &mut coord_client,
+                            &scratch_directory,
+                            &backup_metrics,
+                        )
+                        .await
+                    }
+                    (&Method::POST, "/api/catalog/reencrypt") => {
+                        catalog::handle_catalog_reencrypt(
+                            req,
+                            &mut coord_client,
+                            data_encryption.as_ref(),
+                            read_only,
+                        )
+                        .await
+                    }
+                    (&Method::POST, "/api/admin/catalog/compact") => {
+                        catalog::handle_catalog_compact(
+                            req,
+                            &mut coord_client,
+                            &backup_metrics,
+                            &compaction_metrics,
+                            read_only,
+                        )
+                        .await
+                    }
                     (&Method::GET, "/internal/catalog") => {
                         catalog::handle_internal_catalog(req, &mut coord_client).await
                     }
@@ -227,10 +812,79 @@ impl Server {
             // in the future itself. If Rust ever supports asynchronous
             // destructors ("AsyncDrop"), those will admit a more natural
             // solution to the problem.
-            future.spawn_if_canceled()
+            let draining = self.draining.clone();
+            let future =
+                future.and_then(move |res| api::serve_version(method, path, served_version, res));
+            let future = future.map_ok(move |mut res| {
+                if let Some(header_value) = header_value {
+                    res.headers_mut().insert("x-correlation-id", header_value);
+                }
+                if let Some(server_name_header) = server_name_header {
+                    res.headers_mut()
+                        .insert(SERVER_NAME_HEADER, server_name_header);
+                }
+                // Ask clients of a draining server to send their next
+                // requests elsewhere, rather than over this connection.
+                let drain_notice = drain_notices && draining.is_draining();
+                let headers = res.headers_mut();
+                if drain_notice {
+                    headers.insert(
+                        header::RETRY_AFTER,
+                        HeaderValue::from_static(DRAIN_RETRY_AFTER),
+                    );
+                }
+                // HTTP/2 has no `Connection` header. HTTP/2 connections are
+                // instead closed by the graceful shutdown below.
+                if !http2 && (drain_notice || close_after) {
+                    headers.insert(header::CONNECTION, HeaderValue::from_static("close"));
+                }
+                res
+            });
+            future.instrument(span).spawn_if_canceled()
         });
-        let http = hyper::server::conn::Http::new();
-        http.serve_connection(conn, svc).err_into().await
+        let mut http = hyper::server::conn::Http::new();
+        let keep_alive_timeout = if http2 {
+            http.http2_only(true);
+            None
+        } else {
+            http.http1_only(true);
+            match self.http1_keep_alive_timeout {
+                Some(timeout) if timeout == Duration::from_secs(0) => {
+                    http.http1_keep_alive(false);
+                    None
+                }
+                timeout => timeout,
+            }
+        };
+        let conn = http.serve_connection(conn, svc);
+        tokio::pin!(conn);
+        loop {
+            // The connection is idle once it has served no request for the
+            // keep-alive timeout. While a request is in progress, check back
+            // after the timeout.
+            let idle_deadline = keep_alive_timeout
+                .map(|timeout| activity.idle_since().unwrap_or_else(Instant::now) + timeout);
+            tokio::select! {
+                res = &mut conn => return res.map_err(Into::into),
+                // Once the server begins draining, close the connection as
+                // soon as it is idle, so that keep-alive clients reconnect to
+                // another server before this one shuts down.
+                _ = self.draining.wait(), if self.drain_notices => {
+                    conn.as_mut().graceful_shutdown();
+                    break;
+                }
+                _ = time::sleep_until(idle_deadline.unwrap_or_else(Instant::now).into()),
+                    if idle_deadline.is_some() =>
+                {
+                    let timeout = keep_alive_timeout.expect("idle deadline requires timeout");
+                    if matches!(activity.idle_since(), Some(since) if since.elapsed() >= timeout) {
+                        conn.as_mut().graceful_shutdown();
+                        break;
+                    }
+                }
+            }
+        }
+        conn.err_into().await
     }
 
     // Handler functions are attached by various submodules. They all have a
@@ -241,3 +895,155 @@ impl Server {
     // If you add a new handler, please add it to the most appropriate
     // submodule, or create a new submodule if necessary. Don't add it here!
 }
+
+/// Returns the label that identifies `version` in metrics.
+fn protocol_label(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "http/0.9",
+        Version::HTTP_10 => "http/1.0",
+        Version::HTTP_11 => "http/1.1",
+        Version::HTTP_2 => "h2",
+        Version::HTTP_3 => "h3",
+        _ => "unknown",
+    }
+}
+
+/// Tracks the requests in progress on a connection.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+struct ConnectionActivity {
+    inner: Arc<Mutex<ConnectionActivityInner>>,
+}
+
+#[derive(Debug)]
+struct ConnectionActivityInner {
+    /// The number of requests in progress.
+    active: usize,
+    /// When the last request finished, or when the connection was
+    /// established, if no request has finished.
+    idle_since: Instant,
+}
+
+impl ConnectionActivity {
+    fn new() -> ConnectionActivity {
+        ConnectionActivity {
+            inner: Arc::new(Mutex::new(ConnectionActivityInner {
+                active: 0,
+                idle_since: Instant::now(),
+            })),
+        }
+    }
+
+    /// Records that a request is in progress until the returned guard is
+    /// dropped.
+    fn start(&self) -> ActiveRequest {
+        self.inner.lock().expect("lock poisoned").active += 1;
+        ActiveRequest(self.clone())
+    }
+
+    /// Returns when the last request finished, or `None` if a request is in
+    /// progress.
+    fn idle_since(&self) -> Option<Instant> {
+        let inner = self.inner.lock().expect("lock poisoned");
+        match inner.active {
+            0 => Some(inner.idle_since),
+            _ => None,
+        }
+    }
+}
+
+/// Records that a request is in progress, until dropped.
+#[derive(Debug)]
+struct ActiveRequest(ConnectionActivity);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        let mut inner = self.0.inner.lock().expect("lock poisoned");
+        inner.active -= 1;
+        if inner.active == 0 {
+            inner.idle_since = Instant::now();
+        }
+    }
+}
+
+/// An HTTP server that answers every request with a report of startup
+/// progress while the coordinator boots.
+#[derive(Debug)]
+pub struct StartingServer {
+    tls: Option<TlsConfig>,
+    http2_cleartext: bool,
+    startup: StartupProgress,
+    runtime_flags: RuntimeFlags,
+    server_name: Option<String>,
+}
+
+impl StartingServer {
+    pub fn new(
+        tls: Option<TlsConfig>,
+        http2_cleartext: bool,
+        startup: StartupProgress,
+        runtime_flags: RuntimeFlags,
+        server_name: Option<String>,
+    ) -> StartingServer {
+        StartingServer {
+            tls,
+            http2_cleartext,
+            startup,
+            runtime_flags,
+            server_name,
+        }
+    }
+
+    pub fn match_handshake(&self, buf: &[u8]) -> bool {
+        match_handshake(&self.tls, self.http2_cleartext, buf)
+    }
+
+    /// Handles a connection from a client.
+    pub async fn handle_connection<A>(
+        &self,
+        conn: SniffedStream<A>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error>
+    where
+        A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let conn = match (&self.tls, sniff_tls(&conn.sniff_buffer())) {
+            (Some(tls), true) => {
+                let ssl = Ssl::new(&tls.context)?;
+                MaybeHttpsStream::Https(tls.handshake_pool.accept(ssl, conn).await?)
+            }
+            _ => MaybeHttpsStream::Http(conn),
+        };
+        let header_value = HeaderValue::from_str(&correlation_id).ok();
+        let server_name_header = self
+            .server_name
+            .as_deref()
+            .and_then(|name| HeaderValue::from_str(name).ok());
+        let startup = self.startup.clone();
+        let runtime_flags = self.runtime_flags;
+        let svc = service::service_fn(move |req: Request<Body>| {
+            let is_api = api::is_api_path(req.uri().path());
+            let mut res = match (req.method(), req.uri().path()) {
+                (&Method::GET, "/api/build-info") => {
+                    build_info::handle_build_info(&req, &runtime_flags)
+                }
+                _ => startup::handle_starting(req, &startup),
+            };
+            if is_api {
+                res.headers_mut()
+                    .insert(api::API_VERSION_HEADER, HeaderValue::from(api::API_VERSION));
+            }
+            if let Some(header_value) = header_value.clone() {
+                res.headers_mut().insert("x-correlation-id", header_value);
+            }
+            if let Some(server_name_header) = server_name_header.clone() {
+                res.headers_mut()
+                    .insert(SERVER_NAME_HEADER, server_name_header);
+            }
+            future::ok::<_, anyhow::Error>(res)
+        });
+        let http = hyper::server::conn::Http::new();
+        http.serve_connection(conn, svc).err_into().await
+    }
+}
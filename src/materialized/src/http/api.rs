@@ -0,0 +1,273 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Versioning of the `/api` HTTP endpoints.
+//!
+//! The responses of the `/api` endpoints form a versioned interface, so that
+//! tooling built against them survives upgrades. The interface as a whole has
+//! a version, [`API_VERSION`], and each route records the version in which
+//! its response took its current shape. The compatibility policy is:
+//!
+//!   * Additive changes, like new fields or new routes, do not change the
+//!     version.
+//!   * Any other change to the shape of a route's response bumps
+//!     [`API_VERSION`], sets the route's version to the new version, and
+//!     gives the route a downgrade that converts the new shape into the old.
+//!   * Clients may ask for the prior version with the `api-version` query
+//!     parameter or an `Accept` header naming a versioned media type, like
+//!     `application/vnd.materialize.v1+json`, and receive the old shape.
+//!     Requests for any other version are refused.
+//!
+//! Every response from an `/api` endpoint reports the version it was served
+//! at in the `X-Materialize-Api-Version` header.
+
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use url::form_urlencoded;
+
+use crate::http::util;
+
+/// The current version of the `/api` endpoints.
+pub const API_VERSION: u32 = 1;
+
+/// The oldest version of the `/api` endpoints that is still served.
+pub const OLDEST_API_VERSION: u32 = if API_VERSION > 1 {
+    API_VERSION - 1
+} else {
+    API_VERSION
+};
+
+/// The header that reports the version at which a response was served.
+pub const API_VERSION_HEADER: &str = "x-materialize-api-version";
+
+/// The prefix and suffix of the versioned media types that clients may
+/// accept, which surround the version number.
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.materialize.v";
+const MEDIA_TYPE_SUFFIX: &str = "+json";
+
+/// Converts a response body from the current shape of a route into the shape
+/// of the prior version.
+type Downgrade = fn(serde_json::Value) -> serde_json::Value;
+
+/// A route of the `/api` endpoints.
+#[derive(Debug)]
+pub struct ApiRoute {
+    /// The methods that the route accepts.
+    pub methods: &'static [&'static str],
+    /// The path of the route. A segment in braces matches any segment.
+    pub path: &'static str,
+    /// The version in which the route's response took its current shape.
+    pub version: u32,
+    /// Converts the route's response into the shape of the version before
+    /// `version`, if the route existed then in another shape.
+    downgrade: Option<Downgrade>,
+}
+
+impl ApiRoute {
+    const fn new(methods: &'static [&'static str], path: &'static str) -> ApiRoute {
+        ApiRoute {
+            methods,
+            path,
+            version: 1,
+            downgrade: None,
+        }
+    }
+
+    /// Reports whether the route serves a request for `path`.
+    fn matches(&self, path: &str) -> bool {
+        let mut pattern = self.path.split('/');
+        let mut path = path.split('/');
+        loop {
+            match (pattern.next(), path.next()) {
+                (None, None) => return true,
+                (Some(p), Some(s)) if p == s || (p.starts_with('{') && !s.is_empty()) => (),
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// The routes of the `/api` endpoints, as listed by the `GET /api` index.
+pub const ROUTES: &[ApiRoute] = &[
+    ApiRoute::new(&["GET"], "/api"),
+    ApiRoute::new(&["GET"], "/api/build-info"),
+    ApiRoute::new(&["GET"], "/api/status"),
+    ApiRoute::new(&["GET"], "/api/memory"),
+    ApiRoute::new(&["POST"], "/api/memory/purge"),
+    ApiRoute::new(&["GET"], "/api/sessions"),
+    ApiRoute::new(&["POST"], "/api/sessions/{conn_id}/cancel"),
+    ApiRoute::new(&["GET"], "/api/users/stats"),
+    ApiRoute::new(&["GET"], "/api/sources"),
+    ApiRoute::new(&["GET"], "/api/sinks"),
+    ApiRoute::new(&["GET", "POST"], "/api/introspection"),
+    ApiRoute::new(&["GET", "POST"], "/api/telemetry"),
+    ApiRoute::new(&["GET", "PUT"], "/api/log-filter"),
+    ApiRoute::new(&["POST"], "/api/log-file/reopen"),
+    ApiRoute::new(&["GET"], "/api/logs"),
+    ApiRoute::new(&["GET", "PUT"], "/api/slow-query-threshold"),
+    ApiRoute::new(&["GET"], "/api/catalog/dump"),
+    ApiRoute::new(&["GET"], "/api/catalog/backup"),
+    ApiRoute::new(&["POST"], "/api/catalog/reencrypt"),
+    ApiRoute::new(&["POST"], "/api/admin/catalog/compact"),
+    ApiRoute::new(&["GET"], "/api/admin/diagnostics"),
+    ApiRoute::new(&["GET", "POST"], "/api/admin/maintenance"),
+    ApiRoute::new(&["GET", "POST"], "/api/admin/session-quotas"),
+    ApiRoute::new(&["GET"], "/api/admin/telemetry/spool"),
+    ApiRoute::new(&["POST"], "/api/admin/telemetry/spool/drain"),
+    ApiRoute::new(&["POST"], "/api/admin/trim-memory"),
+];
+
+/// Reports whether `path` names an `/api` endpoint.
+pub fn is_api_path(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+/// Determines the version of the `/api` endpoints that `req` asks for.
+///
+/// The version may be named by the `api-version` query parameter, which
+/// takes precedence, or by an `Accept` header that names a versioned media
+/// type. Requests that name no version are served at [`API_VERSION`].
+/// Requests for a version that is not served receive the returned error
+/// response.
+pub fn negotiate(req: &Request<Body>) -> Result<u32, Response<Body>> {
+    let requested = match requested_version(req) {
+        Ok(None) => return Ok(API_VERSION),
+        Ok(Some(version)) => version,
+        Err(message) => return Err(not_acceptable(message)),
+    };
+    if (OLDEST_API_VERSION..=API_VERSION).contains(&requested) {
+        Ok(requested)
+    } else {
+        Err(not_acceptable(format!(
+            "API version {} is not supported; supported versions: {}",
+            requested,
+            supported_versions()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+}
+
+fn requested_version(req: &Request<Body>) -> Result<Option<u32>, String> {
+    let query = req.uri().query().unwrap_or("");
+    if let Some((_, version)) =
+        form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "api-version")
+    {
+        return match version.parse() {
+            Ok(version) => Ok(Some(version)),
+            Err(_) => Err(format!("invalid `api-version` parameter: {}", version)),
+        };
+    }
+    for accept in req.headers().get_all(header::ACCEPT) {
+        let accept = match accept.to_str() {
+            Ok(accept) => accept,
+            Err(_) => continue,
+        };
+        for media_type in accept.split(',') {
+            let media_type = media_type.split(';').next().unwrap_or("").trim();
+            let version = match media_type
+                .strip_prefix(MEDIA_TYPE_PREFIX)
+                .and_then(|v| v.strip_suffix(MEDIA_TYPE_SUFFIX))
+            {
+                Some(version) => version,
+                None => continue,
+            };
+            return match version.parse() {
+                Ok(version) => Ok(Some(version)),
+                Err(_) => Err(format!("invalid media type: {}", media_type)),
+            };
+        }
+    }
+    Ok(None)
+}
+
+fn supported_versions() -> impl Iterator<Item = u32> {
+    OLDEST_API_VERSION..=API_VERSION
+}
+
+fn not_acceptable<S>(message: S) -> Response<Body>
+where
+    S: Into<String>,
+{
+    let mut res = util::error_response(StatusCode::NOT_ACCEPTABLE, message);
+    res.headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(API_VERSION));
+    res
+}
+
+/// Serves `res`, the response to a request for `path` with `method`, at
+/// `version`.
+///
+/// Responses from the `/api` endpoints are marked with the version. If the
+/// route's response has changed shape since `version`, a successful JSON
+/// response is converted into the shape that the client expects.
+pub async fn serve_version(
+    method: Method,
+    path: String,
+    version: u32,
+    mut res: Response<Body>,
+) -> Result<Response<Body>, anyhow::Error> {
+    if !is_api_path(&path) {
+        return Ok(res);
+    }
+    res.headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(version));
+    let route = ROUTES
+        .iter()
+        .find(|route| route.methods.contains(&method.as_str()) && route.matches(&path));
+    let downgrade = match route {
+        Some(ApiRoute {
+            version: route_version,
+            downgrade: Some(downgrade),
+            ..
+        }) if version < *route_version => downgrade,
+        _ => return Ok(res),
+    };
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(false, |v| v == "application/json");
+    if !res.status().is_success() || !is_json {
+        return Ok(res);
+    }
+    let (mut parts, body) = res.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let body = downgrade(serde_json::from_slice(&body)?);
+    let body = serde_json::to_vec(&body)?;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Lists the routes of the `/api` endpoints and the versions that the server
+/// supports, as JSON.
+pub fn handle_api_index() -> Response<Body> {
+    let routes: Vec<_> = ROUTES
+        .iter()
+        .map(|route| {
+            json!({
+                "methods": route.methods,
+                "path": route.path,
+                "version": route.version,
+            })
+        })
+        .collect();
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "version": API_VERSION,
+                "supported_versions": supported_versions().collect::<Vec<_>>(),
+                "routes": routes,
+            })
+            .to_string(),
+        ))
+        .unwrap()
+}
@@ -0,0 +1,460 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Encoding of query results as Arrow IPC streams.
+//!
+//! Each column's SQL type maps to an Arrow type as follows:
+//!
+//! SQL type                   | Arrow type
+//! ---------------------------|-----------
+//! `boolean`                  | `Boolean`
+//! `smallint`                 | `Int16`
+//! `integer`                  | `Int32`
+//! `bigint`                   | `Int64`
+//! `oid`                      | `UInt32`
+//! `real`                     | `Float32`
+//! `double precision`         | `Float64`
+//! `numeric(p, s)`            | `Decimal(38, s)`
+//! `numeric`                  | `Utf8`
+//! `date`                     | `Date32`
+//! `time`                     | `Time64(Microsecond)`
+//! `timestamp`                | `Timestamp(Microsecond)`
+//! `timestamp with time zone` | `Timestamp(Microsecond, "UTC")`
+//! `interval`                 | `Struct<months: Int32, nanoseconds: Int64>`
+//! `bytea`                    | `Binary`
+//! `text` and all others      | `Utf8`
+//!
+//! A `numeric` column with a scale maps to a decimal with that scale and the
+//! greatest precision that Arrow supports. Values that do not fit in that
+//! precision, which is one digit less than `numeric`'s, fail the request, as
+//! do `NaN` values, which Arrow cannot represent. A `numeric` column without
+//! a scale holds values of differing scales, which no Arrow decimal type can
+//! represent exactly, so its values are encoded as decimal text instead.
+//!
+//! Timestamps are truncated to microseconds, which is the precision at which
+//! SQL stores them. Those with a time zone are converted to UTC.
+//!
+//! Arrow's interval types cannot represent an `interval` losslessly: a
+//! `YearMonth` interval has no room for the days and time, and a `DayTime`
+//! interval has only millisecond precision. An `interval` is instead encoded
+//! as a struct of its months and the remainder of its duration in
+//! nanoseconds. Intervals whose duration exceeds the range of an `Int64`,
+//! about 292 years, fail the request.
+//!
+//! Values of the remaining types, like `jsonb`, `uuid`, and lists, are
+//! encoded as their text representation, as they are in JSON results.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail};
+use arrow::array::{
+    ArrayBuilder, ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, DecimalBuilder,
+    Float32Builder, Float64Builder, Int16Builder, Int32Builder, Int64Builder, StringBuilder,
+    StructBuilder, Time64MicrosecondBuilder, TimestampMicrosecondArray, UInt32Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::{NaiveDate, Timelike};
+
+use coord::SimpleRows;
+use repr::adt::jsonb::JsonbRef;
+use repr::adt::numeric::{self, Numeric};
+use repr::{Datum, Row, ScalarType};
+
+/// The media type of an Arrow IPC stream.
+pub const CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+
+/// The precision of the Arrow decimals that `numeric` values with a scale
+/// are encoded as, which is the greatest precision that Arrow supports.
+const DECIMAL_PRECISION: usize = 38;
+
+/// The time zone of the Arrow timestamps that `timestamp with time zone`
+/// values are encoded as.
+const UTC: &str = "UTC";
+
+/// Encodes the rows of a query result as an Arrow IPC stream, in record
+/// batches of a bounded number of rows.
+///
+/// The stream is produced in chunks, one per record batch, so that the
+/// stream need never be held in memory in its entirety.
+pub struct IpcStream {
+    schema: SchemaRef,
+    types: Vec<ScalarType>,
+    rows: Vec<Row>,
+    /// The number of rows that have been encoded so far.
+    offset: usize,
+    batch_size: usize,
+    writer: Option<StreamWriter<ChunkBuffer>>,
+    buf: ChunkBuffer,
+}
+
+impl IpcStream {
+    /// Prepares to encode `rows` in record batches of at most `batch_size`
+    /// rows.
+    ///
+    /// If `notice` is present, it is attached to the schema's metadata under
+    /// the key `notice`, as JSON results attach it.
+    pub fn new(
+        rows: SimpleRows,
+        batch_size: usize,
+        notice: Option<&str>,
+    ) -> Result<IpcStream, anyhow::Error> {
+        assert!(batch_size > 0, "batch size must be positive");
+        let fields = rows
+            .desc
+            .iter()
+            .map(|(name, typ)| {
+                let name = match name {
+                    Some(name) => name.as_str(),
+                    None => "?column?",
+                };
+                Field::new(name, data_type(&typ.scalar_type), typ.nullable)
+            })
+            .collect();
+        let mut metadata = HashMap::new();
+        if let Some(notice) = notice {
+            metadata.insert("notice".into(), notice.into());
+        }
+        let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+        let types = rows
+            .desc
+            .iter()
+            .map(|(_, typ)| typ.scalar_type.clone())
+            .collect();
+        let buf = ChunkBuffer::default();
+        let writer = StreamWriter::try_new(buf.clone(), &schema)?;
+        Ok(IpcStream {
+            schema,
+            types,
+            rows: rows.rows,
+            offset: 0,
+            batch_size,
+            writer: Some(writer),
+            buf,
+        })
+    }
+
+    /// Encodes the next record batch, and returns the bytes of the stream
+    /// that encode it. The first chunk also encodes the schema, and the last
+    /// chunk marks the end of the stream. Returns `None` once the stream is
+    /// complete.
+    ///
+    /// A result without rows is encoded as a stream with only a schema.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(None),
+        };
+        if self.offset < self.rows.len() {
+            let end = self.rows.len().min(self.offset + self.batch_size);
+            let batch = encode_batch(&self.schema, &self.types, &self.rows[self.offset..end])?;
+            writer.write(&batch)?;
+            self.offset = end;
+        }
+        if self.offset == self.rows.len() {
+            writer.finish()?;
+            self.writer = None;
+        }
+        Ok(Some(self.buf.take()))
+    }
+}
+
+/// Returns the Arrow type of a column of SQL type `typ`.
+fn data_type(typ: &ScalarType) -> DataType {
+    match typ {
+        ScalarType::Bool => DataType::Boolean,
+        ScalarType::Int16 => DataType::Int16,
+        ScalarType::Int32 => DataType::Int32,
+        ScalarType::Int64 => DataType::Int64,
+        ScalarType::Oid => DataType::UInt32,
+        ScalarType::Float32 => DataType::Float32,
+        ScalarType::Float64 => DataType::Float64,
+        ScalarType::Numeric { scale: Some(scale) } => {
+            DataType::Decimal(DECIMAL_PRECISION, usize::from(*scale))
+        }
+        ScalarType::Date => DataType::Date32,
+        ScalarType::Time => DataType::Time64(TimeUnit::Microsecond),
+        ScalarType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+        ScalarType::TimestampTz => DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.into())),
+        ScalarType::Interval => DataType::Struct(interval_fields()),
+        ScalarType::Bytes => DataType::Binary,
+        _ => DataType::Utf8,
+    }
+}
+
+/// Returns the fields of the Arrow struct that an `interval` is encoded as.
+fn interval_fields() -> Vec<Field> {
+    vec![
+        Field::new("months", DataType::Int32, false),
+        Field::new("nanoseconds", DataType::Int64, false),
+    ]
+}
+
+/// Encodes `rows`, whose columns have the SQL types `types`, as a record
+/// batch with schema `schema`.
+fn encode_batch(
+    schema: &SchemaRef,
+    types: &[ScalarType],
+    rows: &[Row],
+) -> Result<RecordBatch, anyhow::Error> {
+    let mut builders: Vec<_> = types
+        .iter()
+        .map(|typ| ColumnBuilder::new(typ, rows.len()))
+        .collect();
+    for row in rows {
+        for (datum, builder) in row.iter().zip(&mut builders) {
+            builder.append(datum)?;
+        }
+    }
+    let columns = builders
+        .into_iter()
+        .map(|builder| builder.finish())
+        .collect();
+    Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+}
+
+/// Builds the Arrow array for one column of a record batch.
+enum ColumnBuilder {
+    Boolean(BooleanBuilder),
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    UInt32(UInt32Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Decimal {
+        builder: DecimalBuilder,
+        scale: u8,
+    },
+    Date32(Date32Builder),
+    Time64(Time64MicrosecondBuilder),
+    // Timestamp builders cannot set a time zone, so timestamps are
+    // collected and converted into an array at once.
+    Timestamp {
+        values: Vec<Option<i64>>,
+        timezone: Option<String>,
+    },
+    Interval(StructBuilder),
+    Binary(BinaryBuilder),
+    Utf8 {
+        builder: StringBuilder,
+        jsonb: bool,
+    },
+}
+
+impl ColumnBuilder {
+    /// Constructs a builder for a column of SQL type `typ` with room for
+    /// `capacity` values.
+    fn new(typ: &ScalarType, capacity: usize) -> ColumnBuilder {
+        match typ {
+            ScalarType::Bool => ColumnBuilder::Boolean(BooleanBuilder::new(capacity)),
+            ScalarType::Int16 => ColumnBuilder::Int16(Int16Builder::new(capacity)),
+            ScalarType::Int32 => ColumnBuilder::Int32(Int32Builder::new(capacity)),
+            ScalarType::Int64 => ColumnBuilder::Int64(Int64Builder::new(capacity)),
+            ScalarType::Oid => ColumnBuilder::UInt32(UInt32Builder::new(capacity)),
+            ScalarType::Float32 => ColumnBuilder::Float32(Float32Builder::new(capacity)),
+            ScalarType::Float64 => ColumnBuilder::Float64(Float64Builder::new(capacity)),
+            ScalarType::Numeric { scale: Some(scale) } => ColumnBuilder::Decimal {
+                builder: DecimalBuilder::new(capacity, DECIMAL_PRECISION, usize::from(*scale)),
+                scale: *scale,
+            },
+            ScalarType::Date => ColumnBuilder::Date32(Date32Builder::new(capacity)),
+            ScalarType::Time => ColumnBuilder::Time64(Time64MicrosecondBuilder::new(capacity)),
+            ScalarType::Timestamp => ColumnBuilder::Timestamp {
+                values: Vec::with_capacity(capacity),
+                timezone: None,
+            },
+            ScalarType::TimestampTz => ColumnBuilder::Timestamp {
+                values: Vec::with_capacity(capacity),
+                timezone: Some(UTC.into()),
+            },
+            ScalarType::Interval => {
+                let builders: Vec<Box<dyn ArrayBuilder>> = vec![
+                    Box::new(Int32Builder::new(capacity)),
+                    Box::new(Int64Builder::new(capacity)),
+                ];
+                ColumnBuilder::Interval(StructBuilder::new(interval_fields(), builders))
+            }
+            ScalarType::Bytes => ColumnBuilder::Binary(BinaryBuilder::new(capacity)),
+            typ => ColumnBuilder::Utf8 {
+                builder: StringBuilder::new(capacity),
+                jsonb: matches!(typ, ScalarType::Jsonb),
+            },
+        }
+    }
+
+    /// Appends `datum` to the column.
+    fn append(&mut self, datum: Datum) -> Result<(), anyhow::Error> {
+        if datum.is_null() {
+            return self.append_null();
+        }
+        match self {
+            ColumnBuilder::Boolean(builder) => builder.append_value(datum.unwrap_bool())?,
+            ColumnBuilder::Int16(builder) => builder.append_value(datum.unwrap_int16())?,
+            ColumnBuilder::Int32(builder) => builder.append_value(datum.unwrap_int32())?,
+            ColumnBuilder::Int64(builder) => builder.append_value(datum.unwrap_int64())?,
+            // Object identifiers are unsigned, but are stored as `Int32`
+            // datums.
+            ColumnBuilder::UInt32(builder) => builder.append_value(datum.unwrap_int32() as u32)?,
+            ColumnBuilder::Float32(builder) => builder.append_value(datum.unwrap_float32())?,
+            ColumnBuilder::Float64(builder) => builder.append_value(datum.unwrap_float64())?,
+            ColumnBuilder::Decimal { builder, scale } => {
+                builder.append_value(decimal_value(datum.unwrap_numeric().0, *scale)?)?
+            }
+            ColumnBuilder::Date32(builder) => {
+                let epoch = NaiveDate::from_ymd(1970, 1, 1);
+                let days = (datum.unwrap_date() - epoch).num_days();
+                builder.append_value(i32::try_from(days)?)?
+            }
+            ColumnBuilder::Time64(builder) => {
+                let time = datum.unwrap_time();
+                let micros = i64::from(time.num_seconds_from_midnight()) * 1_000_000
+                    + i64::from(time.nanosecond() / 1_000);
+                builder.append_value(micros)?
+            }
+            ColumnBuilder::Timestamp { values, .. } => {
+                let ts = match datum {
+                    Datum::TimestampTz(ts) => ts.naive_utc(),
+                    datum => datum.unwrap_timestamp(),
+                };
+                let micros = ts.timestamp() * 1_000_000 + i64::from(ts.timestamp_subsec_micros());
+                values.push(Some(micros));
+            }
+            ColumnBuilder::Interval(builder) => {
+                let interval = datum.unwrap_interval();
+                let nanos = i64::try_from(interval.duration).map_err(|_| {
+                    anyhow!(
+                        "interval {} is too long to encode as Arrow: its duration must not \
+                         exceed {} nanoseconds",
+                        interval,
+                        i64::MAX
+                    )
+                })?;
+                interval_field::<Int32Builder>(builder, 0).append_value(interval.months)?;
+                interval_field::<Int64Builder>(builder, 1).append_value(nanos)?;
+                builder.append(true)?;
+            }
+            ColumnBuilder::Binary(builder) => builder.append_value(datum.unwrap_bytes())?,
+            ColumnBuilder::Utf8 { builder, jsonb } => match datum {
+                Datum::String(s) if !*jsonb => builder.append_value(s)?,
+                datum if *jsonb => {
+                    builder.append_value(&JsonbRef::from_datum(datum).to_string())?
+                }
+                datum => builder.append_value(&datum.to_string())?,
+            },
+        }
+        Ok(())
+    }
+
+    fn append_null(&mut self) -> Result<(), anyhow::Error> {
+        match self {
+            ColumnBuilder::Boolean(builder) => builder.append_null()?,
+            ColumnBuilder::Int16(builder) => builder.append_null()?,
+            ColumnBuilder::Int32(builder) => builder.append_null()?,
+            ColumnBuilder::Int64(builder) => builder.append_null()?,
+            ColumnBuilder::UInt32(builder) => builder.append_null()?,
+            ColumnBuilder::Float32(builder) => builder.append_null()?,
+            ColumnBuilder::Float64(builder) => builder.append_null()?,
+            ColumnBuilder::Decimal { builder, .. } => builder.append_null()?,
+            ColumnBuilder::Date32(builder) => builder.append_null()?,
+            ColumnBuilder::Time64(builder) => builder.append_null()?,
+            ColumnBuilder::Timestamp { values, .. } => values.push(None),
+            ColumnBuilder::Interval(builder) => {
+                // The fields of a null struct must still hold a value.
+                interval_field::<Int32Builder>(builder, 0).append_value(0)?;
+                interval_field::<Int64Builder>(builder, 1).append_value(0)?;
+                builder.append(false)?;
+            }
+            ColumnBuilder::Binary(builder) => builder.append_null()?,
+            ColumnBuilder::Utf8 { builder, .. } => builder.append_null()?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Boolean(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int16(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int32(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int64(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::UInt32(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float32(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float64(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Decimal { mut builder, .. } => Arc::new(builder.finish()),
+            ColumnBuilder::Date32(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Time64(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Timestamp { values, timezone } => {
+                Arc::new(TimestampMicrosecondArray::from_opt_vec(values, timezone))
+            }
+            ColumnBuilder::Interval(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Binary(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Utf8 { mut builder, .. } => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Returns the builder of the `i`th field of an interval struct.
+fn interval_field<T>(builder: &mut StructBuilder, i: usize) -> &mut T
+where
+    T: ArrayBuilder,
+{
+    builder
+        .field_builder(i)
+        .expect("interval struct has months and nanoseconds fields")
+}
+
+/// Returns the unscaled value of `n` at `scale`, as an Arrow decimal holds
+/// it.
+fn decimal_value(mut n: Numeric, scale: u8) -> Result<i128, anyhow::Error> {
+    if n.is_nan() {
+        bail!("numeric value NaN cannot be encoded as Arrow");
+    }
+    let mut cx = numeric::cx_datum();
+    cx.rescale(&mut n, &Numeric::from(-i32::from(scale)));
+    let fits =
+        !cx.status().invalid_operation() && numeric::get_precision(&n) <= DECIMAL_PRECISION as u32;
+    if !fits {
+        bail!(
+            "numeric value {} exceeds the maximum precision of Arrow decimals, {}",
+            n,
+            DECIMAL_PRECISION
+        );
+    }
+    // Having been rescaled, the value's coefficient is its unscaled value.
+    Ok(n.coefficient::<i128>()?)
+}
+
+/// A buffer that the [`StreamWriter`] writes into, and from which each
+/// chunk of the stream is taken once written.
+#[derive(Clone, Default)]
+struct ChunkBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl ChunkBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().expect("lock poisoned"))
+    }
+}
+
+impl io::Write for ChunkBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! An HTTP endpoint that describes the build and its runtime flags.
+//!
+//! Everything the endpoint reports is fixed for the lifetime of the process,
+//! so it is served even while the coordinator boots.
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+
+use crate::BUILD_INFO;
+
+/// The flags, fixed at startup, that determine which features the server
+/// offers.
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeFlags {
+    pub experimental_mode: bool,
+    pub safe_mode: bool,
+    pub read_only: bool,
+}
+
+impl RuntimeFlags {
+    /// Returns the entity tag of the build information response, which
+    /// changes only if the build or the runtime flags change.
+    fn etag(&self) -> String {
+        format!(
+            "\"{}-{}{}{}\"",
+            BUILD_INFO.sha,
+            u8::from(self.experimental_mode),
+            u8::from(self.safe_mode),
+            u8::from(self.read_only),
+        )
+    }
+}
+
+/// Reports the build information and the runtime flags as JSON.
+///
+/// The response carries an `ETag`, so that a poller that presents it in
+/// `If-None-Match` receives an empty `304 Not Modified` response instead.
+pub fn handle_build_info(req: &Request<Body>, flags: &RuntimeFlags) -> Response<Body> {
+    let etag = flags.etag();
+    let not_modified = req
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let license_change_date = match BUILD_INFO.license_change_date {
+        "" => None,
+        date => Some(date),
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ETAG, etag)
+        .body(Body::from(
+            json!({
+                "build": {
+                    "version": BUILD_INFO.version,
+                    "sha": BUILD_INFO.sha,
+                    "time": BUILD_INFO.time,
+                    "target_triple": BUILD_INFO.target_triple,
+                    "rustc_version": BUILD_INFO.rustc_version,
+                    "license_change_date": license_change_date,
+                },
+                "runtime": {
+                    "experimental_mode": flags.experimental_mode,
+                    "safe_mode": flags.safe_mode,
+                    "read_only": flags.read_only,
+                    "allocator": if cfg!(target_os = "macos") { "system" } else { "jemalloc" },
+                },
+            })
+            .to_string(),
+        ))
+        .unwrap()
+}
@@ -9,7 +9,22 @@
 
 //! Catalog introspection HTTP endpoints.
 
-use hyper::{header, Body, Request, Response};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use hyper::body::{Bytes, Sender};
+use hyper::{header, Body, Request, Response, StatusCode};
+use log::warn;
+use serde_json::json;
+use url::form_urlencoded;
+
+use coord::catalog::SqlDumpOptions;
+
+use crate::backup;
+use crate::compaction::{self, CompactionError};
+use crate::http::util;
+use crate::EncryptionConfig;
 
 pub async fn handle_internal_catalog(
     _: Request<Body>,
@@ -21,3 +36,213 @@ pub async fn handle_internal_catalog(
         .body(Body::from(dump))
         .unwrap())
 }
+
+/// Dumps the catalog as a sequence of SQL statements that recreates its
+/// objects.
+///
+/// The query string may contain the following parameters:
+///
+///   * `format`, which must be `sql`, the only supported format.
+///   * `if_not_exists`, either `true` or `false`, to control whether the
+///     statements include `IF NOT EXISTS`. Defaults to `false`.
+///   * `redact_credentials`, either `true` or `false`, to control whether
+///     credentials in source and sink definitions are redacted. Defaults to
+///     `false`.
+pub async fn handle_catalog_dump(
+    req: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let params: HashMap<_, _> =
+        form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).collect();
+    let mut options = SqlDumpOptions::default();
+    for (key, value) in params {
+        let res = match key.as_ref() {
+            "format" if value == "sql" => Ok(()),
+            "format" => Err("expected `sql`".into()),
+            "if_not_exists" => util::parse_bool(&value).map(|b| options.if_not_exists = b),
+            "redact_credentials" => {
+                util::parse_bool(&value).map(|b| options.redact_credentials = b)
+            }
+            _ => Err(format!("unknown parameter `{}`", key)),
+        };
+        if let Err(e) = res {
+            return Ok(util::error_response(
+                StatusCode::BAD_REQUEST,
+                format!("invalid `{}` parameter: {}", key, e),
+            ));
+        }
+    }
+    let dump = coord_client.dump_catalog_sql(options).await;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/sql")
+        .body(Body::from(dump))
+        .unwrap())
+}
+
+/// Streams a backup of the catalog as a tarball.
+///
+/// See the [`backup`] module for details on the contents of the backup.
+pub async fn handle_catalog_backup(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    scratch_directory: &Path,
+    metrics: &backup::Metrics,
+) -> Result<Response<Body>, anyhow::Error> {
+    let snapshot_catalog = |path| async move { coord_client.snapshot_catalog(path).await };
+    let snapshot = match backup::Snapshot::take(snapshot_catalog, scratch_directory, metrics).await
+    {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            return Ok(util::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unable to back up catalog: {:#}", e),
+            ))
+        }
+    };
+    let (sender, body) = Body::channel();
+    tokio::task::spawn_blocking(move || {
+        let w = BufWriter::with_capacity(64 << 10, BodyWriter(Some(sender)));
+        match snapshot.write_tarball(w).map(|w| w.into_inner()) {
+            Ok(Ok(w)) => w.finish(),
+            Ok(Err(e)) => warn!("unable to write catalog backup: {}", e.error()),
+            Err(e) => warn!("unable to write catalog backup: {:#}", e),
+        }
+    });
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"catalog-backup.tar\"",
+        )
+        .body(body)
+        .unwrap())
+}
+
+/// Reencrypts the catalog with the key that its key source currently
+/// provides.
+///
+/// To rotate the key, change the key that the key source provides, and then
+/// call this endpoint. Keys provided by an environment variable cannot be
+/// rotated while the server is running.
+pub async fn handle_catalog_reencrypt(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    data_encryption: Option<&EncryptionConfig>,
+    read_only: bool,
+) -> Result<Response<Body>, anyhow::Error> {
+    let key_source = match data_encryption {
+        Some(data_encryption) => data_encryption.key_source.clone(),
+        None => {
+            return Ok(util::error_response(
+                StatusCode::NOT_FOUND,
+                "catalog is not encrypted",
+            ))
+        }
+    };
+    if read_only {
+        return Ok(util::error_response(
+            StatusCode::CONFLICT,
+            "the catalog cannot be reencrypted in read-only mode",
+        ));
+    }
+    if !key_source.is_rotatable() {
+        return Ok(util::error_response(
+            StatusCode::CONFLICT,
+            "a key provided by an environment variable cannot be rotated while the server \
+             is running",
+        ));
+    }
+    let key = match tokio::task::spawn_blocking(move || key_source.load()).await? {
+        Ok(key) => key,
+        Err(e) => {
+            return Ok(util::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unable to load data encryption key: {:#}", e),
+            ))
+        }
+    };
+    if let Err(e) = coord_client.reencrypt_catalog(key).await {
+        return Ok(util::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unable to reencrypt catalog: {}", e),
+        ));
+    }
+    Ok(Response::new(Body::empty()))
+}
+
+/// Compacts the catalog, and reports its size before and after.
+///
+/// Compaction is refused in read-only mode and while a catalog backup is in
+/// progress.
+pub async fn handle_catalog_compact(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    backup_metrics: &backup::Metrics,
+    metrics: &compaction::Metrics,
+    read_only: bool,
+) -> Result<Response<Body>, anyhow::Error> {
+    if read_only {
+        return Ok(util::error_response(
+            StatusCode::CONFLICT,
+            "the catalog cannot be compacted in read-only mode",
+        ));
+    }
+    let compact_catalog = || async move { coord_client.compact_catalog().await };
+    let compaction = match compaction::compact(compact_catalog, backup_metrics, metrics).await {
+        Ok(compaction) => compaction,
+        Err(e @ CompactionError::BackupInProgress) => {
+            return Ok(util::error_response(StatusCode::CONFLICT, e.to_string()))
+        }
+        Err(e) => {
+            return Ok(util::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+            ))
+        }
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "size_before_bytes": compaction.size_before,
+                "size_after_bytes": compaction.size_after,
+                "duration_ms": compaction.duration.as_millis() as u64,
+            })
+            .to_string(),
+        ))
+        .unwrap())
+}
+
+/// A writer that streams into the body of an HTTP response.
+///
+/// If the writer is dropped before [`BodyWriter::finish`] is called, the
+/// response is aborted, so that the client observes a failed request rather
+/// than a truncated backup.
+struct BodyWriter(Option<Sender>);
+
+impl BodyWriter {
+    fn finish(mut self) {
+        self.0.take();
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let sender = self.0.as_mut().expect("BodyWriter used after finish");
+        futures::executor::block_on(sender.send_data(Bytes::copy_from_slice(buf)))
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BodyWriter {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.take() {
+            sender.abort();
+        }
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Diagnostics bundle HTTP endpoint.
+//!
+//! The bundle is a tarball that collects, in a single download, the
+//! information that is usually requested when diagnosing a problem with the
+//! server: its configuration, status, recent logs, metrics, sessions, the
+//! health of its sources and sinks, memory usage, recent crash reports, and
+//! the flight recorder's snapshots. A `manifest.json` entry, which is written
+//! last, describes every other entry, along with any that were omitted or
+//! could not be generated.
+//!
+//! The tarball is streamed to the client as each entry is generated, rather
+//! than assembled in memory. Each entry is capped in size and in the time
+//! allotted to generate it, so that a bundle can be downloaded even from a
+//! server that is in trouble.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::body::{Bytes, Sender};
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use tokio::time;
+use url::form_urlencoded;
+
+use coord::{AuthzClass, AuthzDecision, AuthzRequest};
+use ore::metrics::MetricsRegistry;
+
+use crate::http::{metrics, sources, util};
+use crate::logging::{self, RecentLogQuery};
+use crate::metrics_guard::MetricsGuard;
+use crate::{allocator, crash, flight_recorder, watchdog, Metrics, BUILD_INFO};
+
+/// The maximum size of each entry in the bundle, in bytes. Larger entries are
+/// truncated.
+const MAX_ENTRY_SIZE: usize = 8 << 20;
+
+/// The maximum time to spend generating each entry in the bundle.
+const ENTRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of most recent crash reports to include in the bundle.
+const MAX_CRASH_REPORTS: usize = 5;
+
+/// The span of recent logs to include in the bundle, unless the request
+/// specifies otherwise.
+const DEFAULT_LOG_MINUTES: u64 = 15;
+
+/// The size of a block of a tar archive.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Configures the diagnostics bundle.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsConfig {
+    /// The server's configuration, with credentials redacted, as described
+    /// by [`crash::describe_config`].
+    pub config: Arc<str>,
+    /// The directory that holds crash reports.
+    pub crash_directory: PathBuf,
+    /// The directory that holds the flight recorder's snapshots, if the
+    /// flight recorder is enabled.
+    pub flight_recorder_directory: Option<PathBuf>,
+    /// Whether to leave out every entry that may contain SQL text, namely
+    /// the logs, crash reports, and flight recorder snapshots.
+    pub exclude_sql: bool,
+}
+
+/// Describes an entry in the bundle, for the manifest.
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    name: String,
+    size_bytes: usize,
+    truncated: bool,
+}
+
+/// Describes an entry that is missing from the bundle, for the manifest.
+#[derive(Debug, Serialize)]
+struct MissingEntry {
+    name: String,
+    reason: String,
+}
+
+/// Streams a diagnostics bundle.
+///
+/// If the server has an authorizer, the requesting user must be authorized
+/// to perform administrative actions.
+pub async fn handle_diagnostics(
+    req: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    authorizer: Option<&dyn coord::Authorizer>,
+    config: &DiagnosticsConfig,
+    status: serde_json::Value,
+    metrics_registry: &MetricsRegistry,
+    metrics_guard: &MetricsGuard,
+    global_metrics: &Metrics,
+) -> Result<Response<Body>, anyhow::Error> {
+    if let Some(authorizer) = authorizer {
+        let request = AuthzRequest {
+            user: coord_client.session().user(),
+            class: AuthzClass::Admin,
+            objects: &[],
+        };
+        if let AuthzDecision::Deny(reason) = authorizer.authorize(&request) {
+            return Ok(util::error_response(
+                StatusCode::FORBIDDEN,
+                format!("permission denied: {}", reason),
+            ));
+        }
+    }
+
+    let params: HashMap<_, _> =
+        form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).collect();
+    let log_minutes = match params.get("log_minutes") {
+        None => DEFAULT_LOG_MINUTES,
+        Some(minutes) => match minutes.parse() {
+            Ok(minutes) => minutes,
+            Err(_) => {
+                return Ok(util::error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid log_minutes: {}", minutes),
+                ))
+            }
+        },
+    };
+
+    // Sessions, sources, and sinks are listed up front, as the session client
+    // is not available once the response is returned.
+    let sessions = match time::timeout(ENTRY_TIMEOUT, coord_client.list_sessions()).await {
+        Ok(sessions) => serde_json::to_vec_pretty(&sessions).map_err(|e| e.to_string()),
+        Err(_) => Err("timed out listing sessions".into()),
+    };
+    let dataflows = match time::timeout(ENTRY_TIMEOUT, sources::summarize(coord_client)).await {
+        Ok(dataflows) => to_json(&dataflows),
+        Err(_) => Err("timed out summarizing sources and sinks".into()),
+    };
+
+    let (sender, body) = Body::channel();
+    let mut bundle = Bundle::new(sender);
+    let config = config.clone();
+    let metrics_registry = metrics_registry.clone();
+    let metrics_guard = metrics_guard.clone();
+    let global_metrics = global_metrics.clone();
+    tokio::spawn(async move {
+        // The stream ends early, and the client sees a truncated tarball, if
+        // the client goes away or an entry cannot be written.
+        let _ = bundle
+            .write(
+                config,
+                status,
+                sessions,
+                dataflows,
+                metrics_registry,
+                metrics_guard,
+                global_metrics,
+                log_minutes,
+            )
+            .await;
+    });
+
+    let filename = format!(
+        "materialized-diagnostics-{}.tar",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(body)
+        .unwrap())
+}
+
+/// A diagnostics bundle that is being streamed to a client.
+struct Bundle {
+    sender: Sender,
+    mtime: u64,
+    entries: Vec<ManifestEntry>,
+    missing: Vec<MissingEntry>,
+}
+
+impl Bundle {
+    fn new(sender: Sender) -> Bundle {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Bundle {
+            sender,
+            mtime,
+            entries: vec![],
+            missing: vec![],
+        }
+    }
+
+    /// Generates and writes every entry of the bundle, followed by the
+    /// manifest.
+    async fn write(
+        &mut self,
+        config: DiagnosticsConfig,
+        status: serde_json::Value,
+        sessions: Result<Vec<u8>, String>,
+        dataflows: Result<Vec<u8>, String>,
+        metrics_registry: MetricsRegistry,
+        metrics_guard: MetricsGuard,
+        global_metrics: Metrics,
+        log_minutes: u64,
+    ) -> Result<(), anyhow::Error> {
+        let generated_at = SystemTime::now();
+
+        self.append("config.txt", Ok(config.config.as_bytes().to_vec()))
+            .await?;
+        self.append("status.json", to_json(&status)).await?;
+        let prometheus =
+            metrics::encode_prometheus(&metrics_registry, &metrics_guard, &global_metrics)
+                .map_err(|e| e.to_string());
+        self.append("metrics.txt", prometheus).await?;
+        self.append("sessions.json", sessions).await?;
+        self.append("dataflows.json", dataflows).await?;
+        self.append(
+            "memory.json",
+            to_json(&json!({
+                "resident_set_bytes": watchdog::resident_set_size(),
+                "cgroup_limit_bytes": watchdog::cgroup_memory_limit(),
+                "allocator_resident_bytes": allocator::resident(),
+            })),
+        )
+        .await?;
+        let jemalloc = match allocator::stats() {
+            Some(stats) => to_json(&json!({
+                "stats": stats,
+                "settings": allocator::describe_settings(),
+            })),
+            None => Err("allocator statistics are unavailable".into()),
+        };
+        self.append("jemalloc.json", jemalloc).await?;
+
+        // Logs, crash reports, and flight recorder snapshots may contain the
+        // text of SQL statements, for example in the slow query log.
+        if config.exclude_sql {
+            let mut names = vec!["logs.jsonl", "crash/"];
+            if config.flight_recorder_directory.is_some() {
+                names.push("flight-recorder/");
+            }
+            for name in names {
+                self.missing.push(MissingEntry {
+                    name: name.to_string(),
+                    reason: "excluded because it may contain SQL text".into(),
+                });
+            }
+        } else {
+            let cutoff = generated_at - Duration::from_secs(log_minutes.saturating_mul(60));
+            self.append("logs.jsonl", Ok(recent_logs(cutoff))).await?;
+            let reports = crash::recent_reports(&config.crash_directory, MAX_CRASH_REPORTS);
+            for path in reports {
+                let name = match path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) => format!("crash/{}", name),
+                    None => continue,
+                };
+                let contents = read_capped(path).await;
+                self.append(&name, contents).await?;
+            }
+            if let Some(dir) = &config.flight_recorder_directory {
+                for path in flight_recorder::list(dir) {
+                    let name = match path.file_name().and_then(|name| name.to_str()) {
+                        Some(name) => format!("{}/{}", flight_recorder::DIRECTORY, name),
+                        None => continue,
+                    };
+                    let contents = read_capped(path).await;
+                    self.append(&name, contents).await?;
+                }
+            }
+        }
+
+        let manifest = json!({
+            "version": BUILD_INFO.version,
+            "sha": BUILD_INFO.sha,
+            "generated_at": DateTime::<Utc>::from(generated_at)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            "exclude_sql": config.exclude_sql,
+            "log_minutes": log_minutes,
+            "max_entry_size_bytes": MAX_ENTRY_SIZE,
+            "entries": self.entries,
+            "missing": self.missing,
+        });
+        let manifest = to_json(&manifest);
+        self.append("manifest.json", manifest).await?;
+
+        // An archive ends with two empty blocks.
+        self.send(vec![0; 2 * TAR_BLOCK_SIZE]).await
+    }
+
+    /// Appends the entry `name` to the bundle, or records in the manifest
+    /// that it could not be generated.
+    async fn append(
+        &mut self,
+        name: &str,
+        contents: Result<Vec<u8>, String>,
+    ) -> Result<(), anyhow::Error> {
+        let mut contents = match contents {
+            Ok(contents) => contents,
+            Err(reason) => {
+                self.missing.push(MissingEntry {
+                    name: name.into(),
+                    reason,
+                });
+                return Ok(());
+            }
+        };
+        let truncated = contents.len() > MAX_ENTRY_SIZE;
+        contents.truncate(MAX_ENTRY_SIZE);
+        let size = contents.len();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_path(name)?;
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        header.set_cksum();
+        self.send(header.as_bytes().to_vec()).await?;
+
+        // Entries are padded to a whole number of blocks.
+        let padding = (TAR_BLOCK_SIZE - size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        contents.resize(size + padding, 0);
+        self.send(contents).await?;
+
+        if name != "manifest.json" {
+            self.entries.push(ManifestEntry {
+                name: name.into(),
+                size_bytes: size,
+                truncated,
+            });
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), anyhow::Error> {
+        Ok(self.sender.send_data(Bytes::from(data)).await?)
+    }
+}
+
+fn to_json(value: &serde_json::Value) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| e.to_string())
+}
+
+/// Returns the log events in the ring buffer of recent events that occurred
+/// since `cutoff`, as JSON lines, keeping only the most recent events if they
+/// would exceed the maximum entry size.
+fn recent_logs(cutoff: SystemTime) -> Vec<u8> {
+    let messages = logging::recent_events(&RecentLogQuery {
+        since: Some(cutoff),
+        ..Default::default()
+    });
+    let mut size = 0;
+    let mut start = messages.len();
+    for message in messages.iter().rev() {
+        if size + message.len() > MAX_ENTRY_SIZE {
+            break;
+        }
+        size += message.len();
+        start -= 1;
+    }
+    messages[start..].concat().into_bytes()
+}
+
+/// Reads at most one byte more than the maximum entry size from the file at
+/// `path`, so that the entry is marked as truncated if the file is larger.
+async fn read_capped(path: PathBuf) -> Result<Vec<u8>, String> {
+    let read = tokio::task::spawn_blocking(move || read_prefix(&path, MAX_ENTRY_SIZE + 1));
+    match time::timeout(ENTRY_TIMEOUT, read).await {
+        Ok(Ok(Ok(contents))) => Ok(contents),
+        Ok(Ok(Err(e))) => Err(e.to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out reading file".into()),
+    }
+}
+
+fn read_prefix(path: &Path, limit: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut contents = vec![];
+    File::open(path)?
+        .take(limit as u64)
+        .read_to_end(&mut contents)?;
+    Ok(contents)
+}
@@ -0,0 +1,96 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Fault injection HTTP endpoints.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use url::form_urlencoded;
+
+use crate::fault::{self, Failpoint, Fault, FAILPOINTS};
+use crate::http::util;
+
+/// Reports the active failpoints, or, for a `POST` request, activates or
+/// deactivates a failpoint first.
+pub async fn handle_failpoints(
+    req: Request<Body>,
+    enabled: bool,
+) -> Result<Response<Body>, anyhow::Error> {
+    if !enabled {
+        return Ok(util::error_response(
+            StatusCode::NOT_FOUND,
+            "fault injection is not enabled",
+        ));
+    }
+    if req.method() == Method::POST {
+        let body = hyper::body::to_bytes(req).await?;
+        let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
+        if let Err(e) = configure(&body) {
+            return Ok(util::error_response(StatusCode::BAD_REQUEST, e.to_string()));
+        }
+    }
+    let active: Vec<_> = fault::list()
+        .into_iter()
+        .map(|(name, actions)| json!({ "name": name, "actions": actions }))
+        .collect();
+    let available: Vec<_> = FAILPOINTS
+        .iter()
+        .map(|(name, description)| json!({ "name": name, "description": description }))
+        .collect();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "active": active, "available": available }).to_string(),
+        ))
+        .unwrap())
+}
+
+/// Activates or deactivates the failpoint described by the form `params`.
+fn configure(params: &HashMap<Cow<str>, Cow<str>>) -> Result<(), anyhow::Error> {
+    let params: HashMap<&str, &str> = params.iter().map(|(k, v)| (&**k, &**v)).collect();
+    let name = match params.get("name") {
+        Some(name) => *name,
+        None => bail!("expected `name` parameter"),
+    };
+    let fault = match params.get("fault").copied() {
+        Some("off") => return fault::deactivate(name),
+        Some("error") => Fault::Error,
+        Some("panic") => Fault::Panic,
+        Some("delay") => {
+            let delay = params
+                .get("delay")
+                .ok_or_else(|| anyhow!("expected `delay` parameter for `delay` fault"))?;
+            Fault::Delay(repr::util::parse_duration(delay)?)
+        }
+        _ => bail!("expected `fault` parameter to be `error`, `delay`, `panic`, or `off`"),
+    };
+    let mut failpoint = Failpoint::new(name, fault);
+    if let Some(probability) = params.get("probability") {
+        failpoint.probability = probability
+            .parse()
+            .map_err(|_| anyhow!("invalid `probability` parameter: {}", probability))?;
+    }
+    if let Some(after) = params.get("after") {
+        failpoint.after = after
+            .parse()
+            .map_err(|_| anyhow!("invalid `after` parameter: {}", after))?;
+    }
+    if let Some(times) = params.get("times") {
+        failpoint.times = Some(
+            times
+                .parse()
+                .map_err(|_| anyhow!("invalid `times` parameter: {}", times))?,
+        );
+    }
+    failpoint.activate()
+}
@@ -0,0 +1,109 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Introspection control HTTP endpoints.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use url::form_urlencoded;
+
+use coord::CoordError;
+
+use crate::http::util;
+
+/// Reports the configuration of the introspection sources as JSON or, for
+/// `POST` requests, reconfigures them according to the form-encoded request
+/// body.
+///
+/// The body may contain any of the following parameters, each of which
+/// defaults to its current value:
+///
+///   * `active`, either `true` or `false`, to activate or deactivate the
+///     introspection dataflows.
+///   * `frequency`, a duration like `1s`, the granularity at which the
+///     introspection sources are updated.
+///   * `debug`, either `true` or `false`, to control whether the
+///     introspection dataflows log their own activity.
+///   * `retain_metrics_for`, a duration like `5min`, the duration for which
+///     Prometheus metrics are retained.
+pub async fn handle_introspection(
+    req: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let mut config = match coord_client.logging_config().await {
+        Some(config) => config,
+        None => {
+            return Ok(util::error_response(
+                StatusCode::NOT_FOUND,
+                "introspection is not configured",
+            ))
+        }
+    };
+    if req.method() == Method::POST {
+        let body = hyper::body::to_bytes(req).await?;
+        let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
+        for (key, value) in body {
+            let res = match key.as_ref() {
+                "active" => util::parse_bool(&value).map(|active| config.active = active),
+                "frequency" => {
+                    parse_duration(&value).map(|frequency| config.granularity = frequency)
+                }
+                "debug" => util::parse_bool(&value).map(|debug| config.log_logging = debug),
+                "retain_metrics_for" => {
+                    parse_duration(&value).map(|retain| config.retain_readings_for = retain)
+                }
+                _ => Err(format!("unknown parameter `{}`", key)),
+            };
+            if let Err(e) = res {
+                return Ok(util::error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid `{}` parameter: {}", key, e),
+                ));
+            }
+        }
+        if config.granularity == Duration::from_secs(0) {
+            return Ok(util::error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid `frequency` parameter: must be greater than zero",
+            ));
+        }
+        match coord_client.set_logging_config(config.clone()).await {
+            Ok(()) => (),
+            Err(e @ CoordError::IntrospectionNotConfigured) => {
+                return Ok(util::error_response(StatusCode::NOT_FOUND, e.to_string()))
+            }
+            Err(e) => {
+                let mut message = e.to_string();
+                if let Some(hint) = e.hint() {
+                    message = format!("{}\nhint: {}", message, hint);
+                }
+                return Ok(util::error_response(StatusCode::CONFLICT, message));
+            }
+        }
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "active": config.active,
+                "frequency": format!("{}ms", config.granularity.as_millis()),
+                "debug": config.log_logging,
+                "retain_metrics_for": format!("{}ms", config.retain_readings_for.as_millis()),
+            })
+            .to_string(),
+        ))
+        .unwrap())
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    repr::util::parse_duration(value).map_err(|e| format!("{:#}", e))
+}
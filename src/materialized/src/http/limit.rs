@@ -0,0 +1,147 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Limits on the number of HTTP requests served concurrently.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Response, StatusCode};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+
+use ore::metrics::{UIntCounter, UIntGauge};
+
+use crate::http::util;
+
+/// Limits the number of HTTP requests served concurrently.
+///
+/// Requests in excess of the limit wait in a bounded queue for another
+/// request to finish. Requests that find the queue full, or that wait in it
+/// for too long, are rejected.
+///
+/// Clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct RequestLimiter {
+    semaphore: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+    queue_size: usize,
+    queue_timeout: Duration,
+    in_flight_metric: UIntGauge,
+    queued_metric: UIntGauge,
+    rejected_metric: UIntCounter,
+}
+
+impl RequestLimiter {
+    /// Constructs a limiter that serves at most `max_concurrent_requests` at
+    /// once, and queues at most `queue_size` more for at most
+    /// `queue_timeout` each.
+    pub fn new(
+        max_concurrent_requests: usize,
+        queue_size: usize,
+        queue_timeout: Duration,
+        in_flight_metric: UIntGauge,
+        queued_metric: UIntGauge,
+        rejected_metric: UIntCounter,
+    ) -> RequestLimiter {
+        RequestLimiter {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            queue_size,
+            queue_timeout,
+            in_flight_metric,
+            queued_metric,
+            rejected_metric,
+        }
+    }
+
+    /// Waits for the request to be admitted.
+    ///
+    /// The request counts against the limit until the returned permit is
+    /// dropped. If the request is rejected, returns the response to send
+    /// instead.
+    pub async fn admit(&self) -> Result<RequestPermit, Response<Body>> {
+        let permit = match Arc::clone(&self.semaphore).try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => self.wait().await,
+        };
+        match permit {
+            Some(permit) => {
+                self.in_flight_metric.inc();
+                Ok(RequestPermit {
+                    _permit: permit,
+                    in_flight_metric: self.in_flight_metric.clone(),
+                })
+            }
+            None => {
+                self.rejected_metric.inc();
+                Err(self.rejection())
+            }
+        }
+    }
+
+    /// Waits in the queue for a permit, if there is room in the queue.
+    async fn wait(&self) -> Option<OwnedSemaphorePermit> {
+        let queue_size = self.queue_size;
+        let joined = self
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < queue_size).then(|| queued + 1)
+            });
+        if joined.is_err() {
+            return None;
+        }
+        self.queued_metric.inc();
+        let permit = time::timeout(
+            self.queue_timeout,
+            Arc::clone(&self.semaphore).acquire_owned(),
+        )
+        .await;
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        self.queued_metric.dec();
+        match permit {
+            Ok(Ok(permit)) => Some(permit),
+            // The semaphore is never closed, so the only failure is a
+            // timeout.
+            _ => None,
+        }
+    }
+
+    /// Returns the response to a rejected request, which asks the client to
+    /// retry once a queued request would have given up.
+    fn rejection(&self) -> Response<Body> {
+        let retry_after = self.queue_timeout.as_secs_f64().ceil().max(1.0) as u64;
+        let mut res = util::error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many concurrent HTTP requests",
+        );
+        res.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from_str(&retry_after.to_string()).expect("valid header value"),
+        );
+        res
+    }
+}
+
+/// Admits a request, until dropped.
+///
+/// Created by [`RequestLimiter::admit`].
+#[derive(Debug)]
+pub struct RequestPermit {
+    _permit: OwnedSemaphorePermit,
+    in_flight_metric: UIntGauge,
+}
+
+impl Drop for RequestPermit {
+    fn drop(&mut self) {
+        self.in_flight_metric.dec();
+    }
+}
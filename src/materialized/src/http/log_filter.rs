@@ -0,0 +1,62 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Log control HTTP endpoints.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::http::util;
+use crate::logging::{LogFileHandle, LogFilterHandle};
+
+/// Reports the current log filter or, for `PUT` requests, replaces the log
+/// filter with the request body.
+pub async fn handle_log_filter(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    log_filter: Option<&LogFilterHandle>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let log_filter = match log_filter {
+        Some(log_filter) => log_filter,
+        None => {
+            return Ok(util::error_response(
+                StatusCode::NOT_FOUND,
+                "logging is not managed by this server",
+            ))
+        }
+    };
+    if req.method() == Method::PUT {
+        let body = hyper::body::to_bytes(req).await?;
+        let filter = String::from_utf8_lossy(&body);
+        if let Err(e) = log_filter.set(filter.trim()) {
+            return Ok(util::error_response(
+                StatusCode::BAD_REQUEST,
+                format!("{:#}", e),
+            ));
+        }
+    }
+    Ok(Response::new(Body::from(log_filter.current())))
+}
+
+/// Asks the server to close and reopen its log file, as on SIGHUP.
+pub async fn handle_log_file_reopen(
+    _: Request<Body>,
+    _: &mut coord::SessionClient,
+    log_file: Option<&LogFileHandle>,
+) -> Result<Response<Body>, anyhow::Error> {
+    match log_file {
+        Some(log_file) => {
+            log_file.reopen();
+            Ok(Response::new(Body::empty()))
+        }
+        None => Ok(util::error_response(
+            StatusCode::NOT_FOUND,
+            "server is not logging to a file",
+        )),
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Recent log events HTTP endpoint.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use hyper::{header, Body, Request, Response, StatusCode};
+use url::form_urlencoded;
+
+use crate::http::util;
+use crate::logging::{self, LogFilterHandle, RecentLogQuery};
+
+/// Reports the log events in the in-memory ring buffer of recent events as
+/// JSON lines, oldest first.
+///
+/// The events may be narrowed with the following query parameters:
+///
+///   * `since`: only events that occurred at or after this time, given as an
+///     RFC 3339 timestamp or as a duration before now, like `5m`.
+///   * `level`: only events at least as severe as this level, like `warn`.
+///   * `target`: only events emitted from this module path or from within it.
+pub async fn handle_logs(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    log_filter: Option<&LogFilterHandle>,
+) -> Result<Response<Body>, anyhow::Error> {
+    if log_filter.is_none() {
+        return Ok(util::error_response(
+            StatusCode::NOT_FOUND,
+            "logging is not managed by this server",
+        ));
+    }
+    let params: HashMap<_, _> =
+        form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).collect();
+    let query = match parse_query(&params) {
+        Ok(query) => query,
+        Err(e) => return Ok(util::error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from(logging::recent_events(&query).concat()))
+        .unwrap())
+}
+
+/// Parses the query parameters of a request to [`handle_logs`].
+fn parse_query(params: &HashMap<Cow<str>, Cow<str>>) -> Result<RecentLogQuery, String> {
+    let mut query = RecentLogQuery::default();
+    for (name, value) in params {
+        let value = value.as_ref();
+        match name.as_ref() {
+            "since" => query.since = Some(parse_since(value)?),
+            "level" => {
+                query.level = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid level {:?}", value))?,
+                )
+            }
+            "target" => query.target = Some(value.into()),
+            name => return Err(format!("unknown parameter {:?}", name)),
+        }
+    }
+    Ok(query)
+}
+
+/// Parses a `since` parameter, which is either an RFC 3339 timestamp or a
+/// duration before the current time.
+fn parse_since(value: &str) -> Result<SystemTime, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    match repr::util::parse_duration(value) {
+        Ok(duration) => Ok(SystemTime::now()
+            .checked_sub(duration)
+            .unwrap_or(SystemTime::UNIX_EPOCH)),
+        Err(_) => Err(format!(
+            "invalid since {:?}: expected an RFC 3339 timestamp or a duration",
+            value
+        )),
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Maintenance mode HTTP endpoints.
+
+use std::collections::HashMap;
+
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use log::info;
+use serde_json::json;
+use url::form_urlencoded;
+
+use coord::{Maintenance, MaintenanceMode};
+
+use crate::http::util;
+
+/// Reports whether the server is in maintenance mode as JSON or, for `POST`
+/// requests, enables or disables maintenance mode according to the
+/// form-encoded request body.
+///
+/// The body must contain an `enabled` parameter, either `true` or `false`,
+/// and may contain a `message` parameter that is shown to clients whose
+/// statements are rejected while the server is in maintenance mode.
+pub async fn handle_maintenance(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    maintenance_mode: &MaintenanceMode,
+) -> Result<Response<Body>, anyhow::Error> {
+    if req.method() == Method::POST {
+        let body = hyper::body::to_bytes(req).await?;
+        let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
+        let enabled = match body.get("enabled").map(|v| util::parse_bool(v)) {
+            Some(Ok(enabled)) => enabled,
+            _ => {
+                return Ok(util::error_response(
+                    StatusCode::BAD_REQUEST,
+                    "expected `enabled` parameter to be `true` or `false`",
+                ))
+            }
+        };
+        let message = body
+            .get("message")
+            .map(|m| m.trim().to_owned())
+            .filter(|m| !m.is_empty());
+        if message.is_some() && !enabled {
+            return Ok(util::error_response(
+                StatusCode::BAD_REQUEST,
+                "the `message` parameter requires `enabled` to be `true`",
+            ));
+        }
+        let state = match enabled {
+            true => Some(Maintenance { message }),
+            false => None,
+        };
+        if let Err(e) = maintenance_mode.set(state) {
+            return Ok(util::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{:#}", e),
+            ));
+        }
+        info!(
+            "maintenance mode {} via HTTP API",
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+    let maintenance = maintenance_mode.get();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "enabled": maintenance.is_some(),
+                "message": maintenance.and_then(|m| m.message),
+            })
+            .to_string(),
+        ))
+        .unwrap())
+}
@@ -8,22 +8,121 @@
 // by the Apache License, Version 2.0.
 
 use askama::Template;
-use hyper::{Body, Request, Response};
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
 
+use crate::allocator::{self, TrimReport};
 use crate::http::util;
-use crate::BUILD_INFO;
+use crate::idle::ConnectionTracker;
+use crate::{watchdog, BUILD_INFO};
 
 #[derive(Template)]
 #[template(path = "http/templates/memory.html")]
 struct MemoryTemplate<'a> {
     version: &'a str,
+    introspection_disabled: bool,
 }
 
+/// Renders the memory visualizations, which are drawn from the
+/// introspection sources and so are empty when introspection is disabled.
 pub async fn handle_memory(
     _: Request<Body>,
-    _: &mut coord::SessionClient,
+    coord_client: &mut coord::SessionClient,
 ) -> Result<Response<Body>, anyhow::Error> {
+    let summary = coord_client.catalog_summary().await;
     Ok(util::template_response(MemoryTemplate {
         version: BUILD_INFO.version,
+        introspection_disabled: !summary.introspection_active,
     }))
 }
+
+/// Reports the memory usage of the server as JSON.
+///
+/// None of the report depends on the introspection sources: the object and
+/// arrangement counts come from the coordinator, the memory statistics from
+/// the OS and the allocator, and the connection counts from the connection
+/// tracker. Whether introspection is disabled is reported alongside, as the
+/// per-dataflow breakdown on the `/memory` page is then unavailable.
+pub async fn handle_api_memory(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    connection_tracker: &ConnectionTracker,
+) -> Result<Response<Body>, anyhow::Error> {
+    let summary = coord_client.catalog_summary().await;
+    let (open_connections, protocols) = connection_tracker.open_connections();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "introspection_disabled": !summary.introspection_active,
+                "catalog": summary,
+                "connections": {
+                    "open": open_connections,
+                    "by_protocol": protocols,
+                },
+                "resident_set_bytes": watchdog::resident_set_size(),
+                "cgroup_limit_bytes": watchdog::cgroup_memory_limit(),
+                "allocator": allocator::stats(),
+            })
+            .to_string(),
+        ))
+        .unwrap())
+}
+
+/// Returns unused memory that the allocator retains to the OS, as after a
+/// large query has completed.
+pub async fn handle_memory_purge(
+    _: Request<Body>,
+    _: &mut coord::SessionClient,
+    allocator_metrics: &allocator::Metrics,
+) -> Result<Response<Body>, anyhow::Error> {
+    match trim(allocator_metrics).await? {
+        Ok(_) => Ok(Response::new(Body::empty())),
+        Err(res) => Ok(res),
+    }
+}
+
+/// Returns unused memory that the allocator retains to the OS, and reports
+/// how much memory the allocator held resident before and after.
+pub async fn handle_trim_memory(
+    _: Request<Body>,
+    _: &mut coord::SessionClient,
+    allocator_metrics: &allocator::Metrics,
+) -> Result<Response<Body>, anyhow::Error> {
+    let report = match trim(allocator_metrics).await? {
+        Ok(report) => report,
+        Err(res) => return Ok(res),
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({
+                "resident_before_bytes": report.resident_before,
+                "resident_after_bytes": report.resident_after,
+                "released_bytes": report.released(),
+            })
+            .to_string(),
+        ))
+        .unwrap())
+}
+
+/// Trims the allocator, or returns the error response to send if it cannot
+/// be trimmed.
+async fn trim(
+    allocator_metrics: &allocator::Metrics,
+) -> Result<Result<TrimReport, Response<Body>>, anyhow::Error> {
+    if cfg!(target_os = "macos") {
+        return Ok(Err(util::error_response(
+            StatusCode::NOT_FOUND,
+            "jemalloc is not used on macOS",
+        )));
+    }
+    let allocator_metrics = allocator_metrics.clone();
+    match tokio::task::spawn_blocking(move || allocator::trim(&allocator_metrics)).await?? {
+        Some(report) => Ok(Ok(report)),
+        None => Ok(Err(util::error_response(
+            StatusCode::CONFLICT,
+            "memory is already being trimmed",
+        ))),
+    }
+}
@@ -11,15 +11,22 @@
 
 use crate::{Metrics, BUILD_INFO};
 use std::collections::{BTreeMap, BTreeSet};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use askama::Template;
-use hyper::{Body, Request, Response};
+use coord::{ClockMonitor, DataflowMemoryBudget, Maintenance};
+use hyper::{header, Body, Request, Response, StatusCode};
 use ore::metrics::MetricsRegistry;
 use prometheus::Encoder;
+use serde_json::json;
 
 use crate::http::util;
+use crate::metrics_guard::MetricsGuard;
 use crate::server_metrics::PromMetric;
+use crate::source_lag::SourceLagMetrics;
+use crate::startup::StartupProgress;
+use crate::system::HostEnvironment;
+use crate::version_check::VersionStatus;
 
 #[derive(Template)]
 #[template(path = "http/templates/status.html")]
@@ -27,18 +34,20 @@ struct StatusTemplate<'a> {
     version: &'a str,
     query_count: u64,
     start_time: Instant,
+    read_only: bool,
+    introspection_disabled: bool,
     metrics: Vec<&'a PromMetric<'a>>,
 }
 
-/// Call [`prometheus::gather`], ensuring that all our metrics are up to date
+/// Call [`prometheus::gather`] and apply the metrics guardrails, recording
+/// how long it took.
 fn load_prom_metrics(
-    start_time: Instant,
     registry: &MetricsRegistry,
+    metrics_guard: &MetricsGuard,
     global_metrics: &Metrics,
 ) -> Vec<prometheus::proto::MetricFamily> {
     let before_gather = Instant::now();
-    global_metrics.update_uptime(start_time);
-    let result = registry.gather();
+    let result = metrics_guard.apply(registry.gather());
 
     global_metrics
         .request_metrics_gather
@@ -48,12 +57,25 @@ fn load_prom_metrics(
 
 pub async fn handle_prometheus(
     _: Request<Body>,
-    _: &mut coord::SessionClient,
-    start_time: Instant,
     registry: &MetricsRegistry,
+    metrics_guard: &MetricsGuard,
     global_metrics: &Metrics,
 ) -> Result<Response<Body>, anyhow::Error> {
-    let metric_families = load_prom_metrics(start_time, registry, global_metrics);
+    Ok(Response::new(Body::from(encode_prometheus(
+        registry,
+        metrics_guard,
+        global_metrics,
+    )?)))
+}
+
+/// Gathers the metrics in `registry`, applies `metrics_guard`, and encodes
+/// them in the Prometheus text format.
+pub fn encode_prometheus(
+    registry: &MetricsRegistry,
+    metrics_guard: &MetricsGuard,
+    global_metrics: &Metrics,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let metric_families = load_prom_metrics(registry, metrics_guard, global_metrics);
     let mut buffer = Vec::new();
     let encoder = prometheus::TextEncoder::new();
     let start = Instant::now();
@@ -61,18 +83,19 @@ pub async fn handle_prometheus(
     global_metrics
         .request_metrics_encode
         .set(Instant::elapsed(&start).as_micros() as u64);
-
-    Ok(Response::new(Body::from(buffer)))
+    Ok(buffer)
 }
 
 pub async fn handle_status(
     _: Request<Body>,
-    _: &mut coord::SessionClient,
+    coord_client: &mut coord::SessionClient,
     start_time: Instant,
     registry: &MetricsRegistry,
+    metrics_guard: &MetricsGuard,
     global_metrics: &Metrics,
+    read_only: bool,
 ) -> Result<Response<Body>, anyhow::Error> {
-    let metric_families = load_prom_metrics(start_time, registry, global_metrics);
+    let metric_families = load_prom_metrics(registry, metrics_guard, global_metrics);
 
     let desired_metrics = {
         let mut s = BTreeSet::new();
@@ -137,10 +160,154 @@ pub async fn handle_status(
         })
         .unwrap_or(0);
 
+    let summary = coord_client.catalog_summary().await;
+
     Ok(util::template_response(StatusTemplate {
         version: BUILD_INFO.version,
         query_count,
         start_time,
+        read_only,
+        introspection_disabled: !summary.introspection_active,
         metrics: metrics.values().collect(),
     }))
 }
+
+/// Reports the status of the server as JSON.
+///
+/// The report includes whether introspection is disabled, and the object and
+/// arrangement counts that the coordinator tracks regardless.
+pub async fn handle_api_status(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+    start_time: Instant,
+    read_only: bool,
+    logical_compaction_window: Option<Duration>,
+    startup: &StartupProgress,
+    version_status: &VersionStatus,
+    source_lag: &SourceLagMetrics,
+    connection_notice: Option<&str>,
+    maintenance: Option<&Maintenance>,
+    clock: &ClockMonitor,
+    host_environment: &HostEnvironment,
+    server_name: Option<&str>,
+    dataflow_memory_budget: &DataflowMemoryBudget,
+) -> Result<Response<Body>, anyhow::Error> {
+    // A server whose wall clock has regressed may serve inconsistent
+    // results, and so, if so configured, is not ready until the clock
+    // catches up.
+    let (status_code, status) = match clock.fails_readiness() {
+        true => (StatusCode::SERVICE_UNAVAILABLE, "clock_regressed"),
+        false => (StatusCode::OK, "ready"),
+    };
+    let mut status = status_json(
+        status,
+        start_time,
+        read_only,
+        logical_compaction_window,
+        startup,
+        version_status,
+        source_lag,
+        connection_notice,
+        maintenance,
+        clock,
+        host_environment,
+        server_name,
+        dataflow_memory_budget,
+    );
+    let summary = coord_client.catalog_summary().await;
+    status["introspection_disabled"] = json!(!summary.introspection_active);
+    status["catalog"] = json!(summary);
+    Ok(Response::builder()
+        .status(status_code)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(status.to_string()))
+        .unwrap())
+}
+
+/// Reports as JSON that the server is unhealthy because its coordinator is
+/// unresponsive.
+///
+/// Unlike [`handle_api_status`], this requires no session, as an
+/// unresponsive coordinator would never start one.
+pub fn handle_api_status_unresponsive(
+    _: Request<Body>,
+    start_time: Instant,
+    read_only: bool,
+    logical_compaction_window: Option<Duration>,
+    startup: &StartupProgress,
+    version_status: &VersionStatus,
+    source_lag: &SourceLagMetrics,
+    connection_notice: Option<&str>,
+    maintenance: Option<&Maintenance>,
+    clock: &ClockMonitor,
+    host_environment: &HostEnvironment,
+    server_name: Option<&str>,
+    dataflow_memory_budget: &DataflowMemoryBudget,
+) -> Response<Body> {
+    let status = status_json(
+        "unresponsive",
+        start_time,
+        read_only,
+        logical_compaction_window,
+        startup,
+        version_status,
+        source_lag,
+        connection_notice,
+        maintenance,
+        clock,
+        host_environment,
+        server_name,
+        dataflow_memory_budget,
+    );
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(status.to_string()))
+        .unwrap()
+}
+
+/// Describes the status of the server, as reported by `/api/status`.
+///
+/// The description is built without consulting the coordinator, so that it
+/// can be reported while the coordinator is unresponsive.
+///
+/// `status` is the overall health of the server, either `ready`,
+/// `clock_regressed`, or `unresponsive`.
+pub fn status_json(
+    status: &str,
+    start_time: Instant,
+    read_only: bool,
+    logical_compaction_window: Option<Duration>,
+    startup: &StartupProgress,
+    version_status: &VersionStatus,
+    source_lag: &SourceLagMetrics,
+    connection_notice: Option<&str>,
+    maintenance: Option<&Maintenance>,
+    clock: &ClockMonitor,
+    host_environment: &HostEnvironment,
+    server_name: Option<&str>,
+    dataflow_memory_budget: &DataflowMemoryBudget,
+) -> serde_json::Value {
+    json!({
+        "version": BUILD_INFO.version,
+        "sha": BUILD_INFO.sha,
+        "server_name": server_name,
+        "status": status,
+        "phase": startup.phase().as_str(),
+        "startup_ms": startup.elapsed().as_millis() as u64,
+        "uptime_ms": start_time.elapsed().as_millis() as u64,
+        "read_only": read_only,
+        "logical_compaction_window_ms": logical_compaction_window.map(|w| w.as_millis() as u64),
+        "latest_version": version_status.latest().map(|v| v.to_string()),
+        "upgrade_available": version_status.upgrade_available(),
+        "source_timestamp_lags": source_lag.latest(),
+        "max_source_timestamp_lag_ms": source_lag.max_lag_ms(),
+        "connection_notice": connection_notice,
+        "maintenance_mode": maintenance.is_some(),
+        "maintenance_message": maintenance.and_then(|m| m.message.as_deref()),
+        "clock_regressed": clock.is_regressed(),
+        "clock_skew_ms": clock.skew().as_millis() as u64,
+        "host_environment": host_environment,
+        "dataflow_memory": dataflow_memory_budget.status(),
+    })
+}
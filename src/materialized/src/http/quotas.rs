@@ -0,0 +1,76 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Session quota HTTP endpoints.
+
+use std::collections::HashMap;
+
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use log::info;
+use url::form_urlencoded;
+
+use coord::Quotas;
+
+use crate::http::util;
+
+/// Reports the session quotas as JSON or, for `POST` requests, adjusts them
+/// according to the form-encoded request body.
+///
+/// The body may contain any of the following parameters, each of which
+/// defaults to its current value, and each of which is a number, or `0` for
+/// no limit:
+///
+///   * `max_concurrent_statements`, the number of statements that a session
+///     may have executing at once.
+///   * `max_prepared_statements`, the number of prepared statements that a
+///     session may hold.
+///   * `max_open_portals`, the number of portals that a session may have
+///     open.
+///
+/// Established sessions are held to the new quotas from their next message,
+/// but keep any resources they already hold beyond them.
+pub async fn handle_session_quotas(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    session_quotas: &Quotas,
+) -> Result<Response<Body>, anyhow::Error> {
+    let mut quotas = session_quotas.get();
+    if req.method() == Method::POST {
+        let body = hyper::body::to_bytes(req).await?;
+        let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
+        for (key, value) in body {
+            let quota = match key.as_ref() {
+                "max_concurrent_statements" => &mut quotas.max_concurrent_statements,
+                "max_prepared_statements" => &mut quotas.max_prepared_statements,
+                "max_open_portals" => &mut quotas.max_open_portals,
+                _ => {
+                    return Ok(util::error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("unknown parameter `{}`", key),
+                    ))
+                }
+            };
+            match value.parse() {
+                Ok(value) => *quota = value,
+                Err(_) => {
+                    return Ok(util::error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("invalid `{}` parameter: expected a number", key),
+                    ))
+                }
+            }
+        }
+        session_quotas.set(quotas);
+        info!("session quotas adjusted via HTTP API: {:?}", quotas);
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&quotas)?))
+        .unwrap())
+}
@@ -0,0 +1,68 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Session introspection HTTP endpoints.
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+
+use coord::CancelOutcome;
+
+use crate::http::util;
+
+/// Lists the sessions connected to the coordinator, including the correlation
+/// ID of each session's connection, as JSON.
+pub async fn handle_sessions(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let sessions = coord_client.list_sessions().await;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&sessions)?))
+        .unwrap())
+}
+
+/// Returns the ID of the connection named by a path of the form
+/// `/api/sessions/{id}/cancel`, if `path` is of that form.
+pub fn cancel_target(path: &str) -> Option<u32> {
+    path.strip_prefix("/api/sessions/")?
+        .strip_suffix("/cancel")?
+        .parse()
+        .ok()
+}
+
+/// Cancels the query currently running in the session named by the request
+/// path, which must be of the form `/api/sessions/{id}/cancel`.
+///
+/// Unlike a pgwire cancellation request, which may reach any instance behind
+/// a load balancer, this request can be routed to the instance that serves
+/// the session.
+pub async fn handle_cancel_session(
+    req: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let conn_id = match cancel_target(req.uri().path()) {
+        Some(conn_id) => conn_id,
+        None => return Ok(util::error_response(StatusCode::NOT_FOUND, "not found")),
+    };
+    match coord_client.cancel_session(conn_id).await {
+        CancelOutcome::Matched => Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&json!({
+                "conn_id": conn_id,
+                "canceled": true,
+            }))?))
+            .unwrap()),
+        CancelOutcome::Unmatched | CancelOutcome::Foreign => Ok(util::error_response(
+            StatusCode::NOT_FOUND,
+            format!("session {} does not exist", conn_id),
+        )),
+    }
+}
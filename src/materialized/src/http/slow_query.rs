@@ -0,0 +1,47 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Slow query log HTTP endpoints.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::http::util;
+
+/// Reports the current slow query threshold or, for `PUT` requests, replaces
+/// the threshold with the request body.
+///
+/// The threshold is a duration like `500ms`, or `off` to disable the slow
+/// query log.
+pub async fn handle_slow_query_threshold(
+    req: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let slow_query_log = coord_client.slow_query_log();
+    if req.method() == Method::PUT {
+        let body = hyper::body::to_bytes(req).await?;
+        let threshold = match String::from_utf8_lossy(&body).trim() {
+            "off" => None,
+            s => match repr::util::parse_duration(s) {
+                Ok(threshold) => Some(threshold),
+                Err(e) => {
+                    return Ok(util::error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("invalid slow query threshold: {:#}", e),
+                    ))
+                }
+            },
+        };
+        slow_query_log.set_threshold(threshold);
+    }
+    let threshold = match slow_query_log.threshold() {
+        None => "off".into(),
+        Some(threshold) => format!("{}ms", threshold.as_millis()),
+    };
+    Ok(Response::new(Body::from(threshold)))
+}
@@ -0,0 +1,173 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Source and sink health HTTP endpoints.
+
+use std::collections::{BTreeMap, HashMap};
+
+use hyper::{header, Body, Request, Response};
+use log::debug;
+use serde::Serialize;
+use serde_json::json;
+
+use coord::{SinkHealth, SourceHealth};
+
+/// Reads the greatest offset that any instance of each source has ingested,
+/// by partition, from the introspection sources.
+const INGESTED_OFFSETS_QUERY: &str = "SELECT source_id, partition_id, pg_catalog.max(\"offset\")
+     FROM mz_catalog.mz_source_info
+     GROUP BY source_id, partition_id";
+
+/// The partition key of sources that have no notion of partitions.
+const NO_PARTITION: &str = "none";
+
+/// Summarizes the health of a source.
+#[derive(Debug, Serialize)]
+struct SourceSummary {
+    #[serde(flatten)]
+    health: SourceHealth,
+    /// The offset up to which the source has ingested data, by partition, if
+    /// the introspection sources are active.
+    ingested_offsets: BTreeMap<String, i64>,
+    /// Whether introspection is disabled, in which case the ingested offsets,
+    /// and so the offset lag, are unknown.
+    introspection_disabled: bool,
+    /// The number of offsets that the upstream system holds but the source
+    /// has yet to ingest, summed over the partitions for which both offsets
+    /// are known, or `None` if there are no such partitions.
+    offset_lag: Option<i64>,
+}
+
+/// Reports the health of each source as JSON.
+pub async fn handle_sources(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let sources = coord_client.dataflow_health().await.sources;
+    let sources = summarize_sources(coord_client, sources).await;
+    Ok(json_response(&sources)?)
+}
+
+/// Reports the health of each sink as JSON.
+pub async fn handle_sinks(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let sinks: Vec<SinkHealth> = coord_client.dataflow_health().await.sinks;
+    Ok(json_response(&sinks)?)
+}
+
+/// Summarizes the health of each source and sink, as reported by
+/// [`handle_sources`] and [`handle_sinks`].
+pub async fn summarize(coord_client: &mut coord::SessionClient) -> serde_json::Value {
+    let health = coord_client.dataflow_health().await;
+    let introspection_disabled = introspection_disabled(coord_client).await;
+    let sources = summarize_sources(coord_client, health.sources).await;
+    json!({
+        "introspection_disabled": introspection_disabled,
+        "sources": sources,
+        "sinks": health.sinks,
+    })
+}
+
+/// Adds the offsets that each source has ingested to its health.
+///
+/// The ingested offsets are read from the introspection sources. When
+/// introspection is disabled, they are not queried, and each summary says so.
+async fn summarize_sources(
+    coord_client: &mut coord::SessionClient,
+    sources: Vec<SourceHealth>,
+) -> Vec<SourceSummary> {
+    let introspection_disabled = introspection_disabled(coord_client).await;
+    let mut ingested = if introspection_disabled {
+        HashMap::new()
+    } else {
+        ingested_offsets(coord_client).await
+    };
+    sources
+        .into_iter()
+        .map(|health| {
+            let ingested_offsets = ingested.remove(&health.id).unwrap_or_default();
+            let offset_lag = offset_lag(&health.upstream_offsets, &ingested_offsets);
+            SourceSummary {
+                health,
+                ingested_offsets,
+                introspection_disabled,
+                offset_lag,
+            }
+        })
+        .collect()
+}
+
+/// Reports whether the introspection dataflows are inactive, either because
+/// introspection is not configured or because it has been deactivated.
+async fn introspection_disabled(coord_client: &mut coord::SessionClient) -> bool {
+    !matches!(coord_client.logging_config().await, Some(config) if config.active)
+}
+
+/// Returns the offsets that each source has ingested, by source ID and
+/// partition, or nothing if the introspection sources cannot be queried, for
+/// example because they are inactive.
+async fn ingested_offsets(
+    coord_client: &mut coord::SessionClient,
+) -> HashMap<String, BTreeMap<String, i64>> {
+    let mut offsets: HashMap<String, BTreeMap<String, i64>> = HashMap::new();
+    let response = match coord_client.simple_execute(INGESTED_OFFSETS_QUERY).await {
+        Ok(response) => response,
+        Err(e) => {
+            debug!("unable to read ingested source offsets: {}", e);
+            return offsets;
+        }
+    };
+    for result in response.results {
+        for row in result.rows {
+            // The ID of a source instance is the ID of its source followed by
+            // the ID of the dataflow that instantiates it.
+            let source_id = match row.get(0).and_then(|id| id.as_str()) {
+                Some(id) => id.split('/').next().unwrap_or(id).to_owned(),
+                None => continue,
+            };
+            let partition = row
+                .get(1)
+                .and_then(|p| p.as_str())
+                .unwrap_or(NO_PARTITION)
+                .to_owned();
+            let offset = match row.get(2).and_then(|o| o.as_i64()) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let entry = offsets
+                .entry(source_id)
+                .or_default()
+                .entry(partition)
+                .or_insert(offset);
+            *entry = (*entry).max(offset);
+        }
+    }
+    offsets
+}
+
+/// Sums the difference between the upstream and ingested offsets of each
+/// partition for which both are known.
+fn offset_lag(upstream: &BTreeMap<String, i64>, ingested: &BTreeMap<String, i64>) -> Option<i64> {
+    let mut lag = None;
+    for (partition, upstream) in upstream {
+        if let Some(ingested) = ingested.get(partition) {
+            *lag.get_or_insert(0) += (upstream - ingested).max(0);
+        }
+    }
+    lag
+}
+
+fn json_response<T: Serialize>(value: &T) -> Result<Response<Body>, serde_json::Error> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(value)?))
+        .unwrap())
+}
@@ -7,18 +7,78 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::io;
 
 use anyhow::bail;
 use hyper::{header, Body, Request, Response, StatusCode};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
 use url::form_urlencoded;
 
+use coord::{CoordError, ResultBudget, ResultReservation};
+use ore::cast::CastFrom;
+
+use crate::http::arrow_ipc::{self, IpcStream};
 use crate::http::util;
 
+/// The number of rows in each record batch of Arrow results, if the request
+/// does not specify a number, or the server's maximum, if lower.
+pub const DEFAULT_ARROW_BATCH_SIZE: usize = 8192;
+
+/// The response to a request to the SQL endpoint.
+#[derive(Serialize)]
+struct SqlResponse<'a, T> {
+    #[serde(flatten)]
+    response: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notice: Option<&'a str>,
+}
+
+/// The format in which the SQL endpoint returns results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// A JSON object with the rows and column names of each statement's
+    /// results.
+    Json,
+    /// An Arrow IPC stream of the results of the single statement, in record
+    /// batches of at most `batch_size` rows.
+    Arrow { batch_size: usize },
+}
+
+/// The results of a request, encoded.
+enum Encoded {
+    Json(Vec<u8>),
+    /// The stream of results, and its first chunk.
+    Arrow(IpcStream, Vec<u8>),
+}
+
+/// Executes the statements in the `sql` parameter of the request body, and
+/// returns their results as JSON or, with `format=arrow`, as an Arrow IPC
+/// stream.
+///
+/// Results are not streamed from the dataflow layer: the coordinator returns
+/// the complete result of a statement, which is buffered in memory and
+/// counted against `result_budget` until the response has been sent. An
+/// Arrow response is encoded incrementally, one record batch after another,
+/// so its peak memory is the full result plus one encoded batch, rather than
+/// the full result plus the full encoding.
 pub async fn handle_sql(
     req: Request<Body>,
     coord_client: &mut coord::SessionClient,
+    connection_notice: Option<&str>,
+    result_budget: &ResultBudget,
+    arrow_max_batch_size: usize,
 ) -> Result<Response<Body>, anyhow::Error> {
+    let params: HashMap<_, _> =
+        form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).collect();
+    let format = match parse_query(&params, arrow_max_batch_size) {
+        Ok(format) => format,
+        Err(e) => return Ok(util::error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut reservation = result_budget.reserve();
     let res = async {
         let body = hyper::body::to_bytes(req).await?;
         let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
@@ -26,15 +86,200 @@ pub async fn handle_sql(
             Some(sql) => sql,
             None => bail!("expected `sql` parameter"),
         };
-        let res = coord_client.simple_execute(sql).await?;
-        Ok(Response::builder()
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_string(&res)?))
-            .unwrap())
+        match format {
+            Format::Json => Ok(Encoded::Json(
+                execute_json(sql, coord_client, connection_notice, &mut reservation).await?,
+            )),
+            Format::Arrow { batch_size } => {
+                // The complete result is buffered here, and the reservation
+                // holds it until the stream is complete. Only the encoding is
+                // incremental: each record batch is encoded once the previous
+                // one has been sent, so the encoded stream occupies no more
+                // memory than a batch.
+                let rows = coord_client
+                    .simple_execute_rows(sql, &mut reservation)
+                    .await?;
+                let mut stream = IpcStream::new(rows, batch_size, connection_notice)?;
+                // Encode the first batch before responding, so that most
+                // encoding errors fail the request cleanly.
+                let chunk = stream.next_chunk()?.expect("stream has a first chunk");
+                Ok(Encoded::Arrow(stream, chunk))
+            }
+        }
     }
     .await;
-    match res {
-        Ok(res) => Ok(res),
-        Err(e) => Ok(util::error_response(StatusCode::BAD_REQUEST, e.to_string())),
+    let encoded = match res {
+        Ok(encoded) => encoded,
+        Err(e) => match e.downcast_ref::<CoordError>() {
+            Some(CoordError::ResultMemoryExhausted { .. }) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::INSUFFICIENT_STORAGE)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "error": e.to_string() }).to_string()))
+                    .unwrap())
+            }
+            _ => return Ok(util::error_response(StatusCode::BAD_REQUEST, e.to_string())),
+        },
+    };
+    match encoded {
+        Encoded::Json(buf) => {
+            // Hold the reservation until hyper has taken the body.
+            let len = buf.len();
+            coord_client.user_session().add_bytes(u64::cast_from(len));
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                let _ = sender.send_data(buf.into()).await;
+                drop(reservation);
+            });
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::CONTENT_LENGTH, len)
+                .body(body)
+                .unwrap())
+        }
+        Encoded::Arrow(mut stream, chunk) => {
+            // Send each chunk as it is encoded, holding the reservation until
+            // the last has been sent. If encoding a later batch fails, the
+            // response is aborted, so that the client sees an incomplete
+            // stream rather than a complete one that is missing rows.
+            let bytes_sent = coord_client.user_session().bytes_sent();
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                let mut chunk = Some(chunk);
+                while let Some(data) = chunk {
+                    bytes_sent.add(u64::cast_from(data.len()));
+                    if sender.send_data(data.into()).await.is_err() {
+                        // The client went away.
+                        return;
+                    }
+                    chunk = match stream.next_chunk() {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            warn!("error encoding Arrow results: {:#}", e);
+                            sender.abort();
+                            return;
+                        }
+                    };
+                }
+                drop(reservation);
+            });
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, arrow_ipc::CONTENT_TYPE)
+                .body(body)
+                .unwrap())
+        }
+    }
+}
+
+/// Executes the statements in `sql`, and encodes their results as JSON.
+async fn execute_json(
+    sql: &str,
+    coord_client: &mut coord::SessionClient,
+    connection_notice: Option<&str>,
+    reservation: &mut ResultReservation,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let results = coord_client
+        .simple_execute_reserving(sql, reservation)
+        .await?;
+    // The reservation now holds the results. Serialize them into a buffer
+    // that grows the reservation too, and then release the results.
+    let results_size = reservation.bytes();
+    let mut writer = ReservedWriter {
+        buf: vec![],
+        reservation: &mut *reservation,
+        exhausted: None,
+    };
+    let res = SqlResponse {
+        response: &results,
+        notice: connection_notice,
+    };
+    if let Err(e) = serde_json::to_writer(&mut writer, &res) {
+        return Err(match writer.exhausted {
+            Some(e) => e.into(),
+            None => e.into(),
+        });
+    }
+    let buf = writer.buf;
+    drop(results);
+    reservation.shrink(results_size);
+    Ok(buf)
+}
+
+/// Parses the query parameters of a request to [`handle_sql`].
+fn parse_query(
+    params: &HashMap<Cow<str>, Cow<str>>,
+    arrow_max_batch_size: usize,
+) -> Result<Format, String> {
+    let mut format = Format::Json;
+    let mut batch_size = None;
+    for (name, value) in params {
+        let value = value.as_ref();
+        match name.as_ref() {
+            "format" => {
+                format = match value {
+                    "json" => Format::Json,
+                    "arrow" => Format::Arrow {
+                        batch_size: DEFAULT_ARROW_BATCH_SIZE.min(arrow_max_batch_size),
+                    },
+                    _ => return Err(format!("unknown format {:?}", value)),
+                }
+            }
+            "batch_size" => {
+                batch_size = match value.parse() {
+                    Ok(0) | Err(_) => {
+                        return Err(format!(
+                            "invalid batch_size {:?}: must be a positive integer",
+                            value
+                        ))
+                    }
+                    Ok(n) if n > arrow_max_batch_size => {
+                        return Err(format!(
+                            "batch_size {} exceeds the maximum of {}",
+                            n, arrow_max_batch_size
+                        ))
+                    }
+                    Ok(n) => Some(n),
+                }
+            }
+            name => return Err(format!("unknown parameter {:?}", name)),
+        }
+    }
+    match (format, batch_size) {
+        (format, None) => Ok(format),
+        (Format::Arrow { .. }, Some(batch_size)) => Ok(Format::Arrow { batch_size }),
+        (Format::Json, Some(_)) => Err("batch_size requires format=arrow".into()),
+    }
+}
+
+/// Buffers the serialized response to a request, growing the request's
+/// reservation as the buffer's capacity grows.
+struct ReservedWriter<'a> {
+    buf: Vec<u8>,
+    reservation: &'a mut ResultReservation,
+    /// The error that the reservation failed with, if any.
+    exhausted: Option<CoordError>,
+}
+
+impl io::Write for ReservedWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let needed = self.buf.len() + data.len();
+        let capacity = self.buf.capacity();
+        if needed > capacity {
+            let new_capacity = needed.max(capacity * 2);
+            if let Err(e) = self.reservation.try_grow(new_capacity - capacity) {
+                self.exhausted = Some(e);
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "result memory exhausted",
+                ));
+            }
+            self.buf.reserve_exact(new_capacity - self.buf.len());
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
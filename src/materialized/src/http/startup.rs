@@ -0,0 +1,35 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! HTTP endpoints served while the server starts.
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+
+use crate::startup::StartupProgress;
+use crate::BUILD_INFO;
+
+/// Answers every request with a 503 response that reports startup progress.
+pub fn handle_starting(_: Request<Body>, startup: &StartupProgress) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::RETRY_AFTER, "1")
+        .body(Body::from(
+            json!({
+                "version": BUILD_INFO.version,
+                "sha": BUILD_INFO.sha,
+                "status": "starting",
+                "phase": startup.phase().as_str(),
+                "elapsed_ms": startup.elapsed().as_millis() as u64,
+            })
+            .to_string(),
+        ))
+        .unwrap()
+}
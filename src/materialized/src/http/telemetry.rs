@@ -0,0 +1,94 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Telemetry control HTTP endpoints.
+
+use std::collections::HashMap;
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+use url::form_urlencoded;
+
+use crate::http::util;
+use crate::telemetry::{Spool, Toggle};
+
+pub async fn handle_telemetry(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    toggle: Option<&Toggle>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let toggle = match toggle {
+        Some(toggle) => toggle,
+        None => {
+            return Ok(util::error_response(
+                StatusCode::NOT_FOUND,
+                "telemetry is not configured",
+            ))
+        }
+    };
+    if req.method() == hyper::Method::POST {
+        let body = hyper::body::to_bytes(req).await?;
+        let body: HashMap<_, _> = form_urlencoded::parse(&body).collect();
+        let enabled = match body.get("enabled").map(|v| v.as_ref()) {
+            Some("true") => true,
+            Some("false") => false,
+            _ => {
+                return Ok(util::error_response(
+                    StatusCode::BAD_REQUEST,
+                    "expected `enabled` parameter to be `true` or `false`",
+                ))
+            }
+        };
+        if let Err(e) = toggle.set_enabled(enabled) {
+            return Ok(util::error_response(StatusCode::CONFLICT, e.to_string()));
+        }
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "enabled": toggle.enabled() }).to_string(),
+        ))
+        .unwrap())
+}
+
+/// Downloads the spooled telemetry reports as newline-delimited JSON, oldest
+/// first.
+///
+/// A `POST` request additionally removes the downloaded reports from the
+/// spool. Reports that are spooled concurrently are kept for the next
+/// download.
+pub async fn handle_telemetry_spool(
+    req: Request<Body>,
+    _: &mut coord::SessionClient,
+    spool: Option<&Spool>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let spool = match spool {
+        Some(spool) => spool,
+        None => {
+            return Ok(util::error_response(
+                StatusCode::NOT_FOUND,
+                "telemetry spooling is not configured",
+            ))
+        }
+    };
+    let clear = req.method() == hyper::Method::POST;
+    let mut body = vec![];
+    for report in spool.export(clear).await? {
+        body.extend(report);
+        body.push(b'\n');
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"telemetry-spool.ndjson\"",
+        )
+        .body(Body::from(body))
+        .unwrap())
+}
@@ -0,0 +1,37 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! User statistics HTTP endpoints.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{header, Body, Request, Response};
+use serde_json::json;
+
+/// Summarizes the aggregate statistics of each user's sessions since the
+/// process started, as JSON.
+///
+/// Users are listed in descending order of the time that their statements
+/// took. Users that are not tracked individually are summarized together
+/// under `other`.
+pub async fn handle_user_stats(
+    _: Request<Body>,
+    coord_client: &mut coord::SessionClient,
+) -> Result<Response<Body>, anyhow::Error> {
+    let summary = coord_client.user_stats().summary();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&json!({
+            "reset_at": DateTime::<Utc>::from(summary.reset_at)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            "max_users": summary.max_users,
+            "users": summary.users,
+            "other": summary.other,
+        }))?))
+        .unwrap())
+}
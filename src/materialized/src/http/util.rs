@@ -31,3 +31,12 @@ where
         .body(Body::from(message.into()))
         .unwrap()
 }
+
+/// Parses a boolean request parameter, which must be `true` or `false`.
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err("expected `true` or `false`".into()),
+    }
+}
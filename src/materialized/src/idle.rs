@@ -0,0 +1,214 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Tracking client connections, and shutting down servers that have none.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future;
+use tokio::time::{self, Instant};
+
+use ore::metrics::UIntGaugeVec;
+
+/// Tracks the connections open to a server and how long the server has been
+/// without any.
+#[derive(Debug, Clone)]
+pub struct ConnectionTracker {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    active: usize,
+    idle_since: Instant,
+    /// The number of open connections that speak each protocol.
+    protocols: BTreeMap<&'static str, u64>,
+    /// Where to report the number of open connections that speak each
+    /// protocol, once the server is draining.
+    draining_connections: Option<UIntGaugeVec>,
+}
+
+impl State {
+    fn report(&self, protocol: &'static str) {
+        if let Some(gauge) = &self.draining_connections {
+            gauge
+                .with_label_values(&[protocol])
+                .set(self.protocols.get(protocol).copied().unwrap_or(0));
+        }
+    }
+}
+
+impl ConnectionTracker {
+    /// Constructs a new tracker, which considers the server idle from now.
+    pub fn new() -> ConnectionTracker {
+        ConnectionTracker {
+            state: Arc::new(Mutex::new(State {
+                active: 0,
+                idle_since: Instant::now(),
+                protocols: BTreeMap::new(),
+                draining_connections: None,
+            })),
+        }
+    }
+
+    /// Records that a connection opened. The connection is considered open
+    /// until the returned guard is dropped.
+    pub fn open(&self) -> ConnectionGuard {
+        self.state.lock().expect("lock poisoned").active += 1;
+        ConnectionGuard {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Records that a connection opened with [`ConnectionTracker::open`]
+    /// speaks `protocol`, once that is known. The connection is counted
+    /// toward `protocol` until the returned guard is dropped.
+    pub fn identify(&self, protocol: &'static str) -> ProtocolGuard {
+        let mut state = self.state.lock().expect("lock poisoned");
+        *state.protocols.entry(protocol).or_default() += 1;
+        state.report(protocol);
+        ProtocolGuard {
+            state: Arc::clone(&self.state),
+            protocol,
+        }
+    }
+
+    /// Returns the number of open connections, and the number of those whose
+    /// protocol is known that speak each protocol.
+    pub fn open_connections(&self) -> (usize, BTreeMap<&'static str, u64>) {
+        let state = self.state.lock().expect("lock poisoned");
+        (state.active, state.protocols.clone())
+    }
+
+    /// Reports the number of open connections that speak each protocol to
+    /// `gauge`, from now on, as connections open and close.
+    ///
+    /// Meant to be called once the server begins draining, so that whoever
+    /// is waiting to shut the server down can tell how many connections
+    /// remain.
+    pub fn report_draining(&self, gauge: UIntGaugeVec) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.draining_connections = Some(gauge);
+        let protocols: Vec<_> = state.protocols.keys().copied().collect();
+        for protocol in protocols {
+            state.report(protocol);
+        }
+    }
+
+    /// Waits until the server has had no open connections for `idle_after`,
+    /// then marks the server as draining.
+    ///
+    /// The server is marked as draining while the tracker is locked, so a
+    /// connection that opens concurrently is either counted in time to
+    /// restart the wait or finds the server draining and is refused. Never
+    /// returns if something else marks the server as draining first.
+    pub async fn drain_when_idle(&self, idle_after: Duration, draining: &pgwire::Draining) {
+        loop {
+            let deadline = {
+                let state = self.state.lock().expect("lock poisoned");
+                if state.active > 0 {
+                    // The server cannot become idle for long enough any
+                    // sooner than this.
+                    Instant::now() + idle_after
+                } else if Instant::now() >= state.idle_since + idle_after {
+                    if draining.start() {
+                        return;
+                    }
+                    break;
+                } else {
+                    state.idle_since + idle_after
+                }
+            };
+            time::sleep_until(deadline).await;
+        }
+        future::pending().await
+    }
+
+    /// Waits until the server has no open connections.
+    pub async fn closed(&self) {
+        while self.state.lock().expect("lock poisoned").active > 0 {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// Marks a connection as open until dropped.
+///
+/// Constructed by [`ConnectionTracker::open`].
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.active -= 1;
+        if state.active == 0 {
+            state.idle_since = Instant::now();
+        }
+    }
+}
+
+/// Counts a connection toward its protocol until dropped.
+///
+/// Constructed by [`ConnectionTracker::identify`].
+#[derive(Debug)]
+pub struct ProtocolGuard {
+    state: Arc<Mutex<State>>,
+    protocol: &'static str,
+}
+
+impl Drop for ProtocolGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if let Some(count) = state.protocols.get_mut(self.protocol) {
+            *count -= 1;
+        }
+        state.report(self.protocol);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::runtime::Runtime;
+    use tokio::time::{self, Instant};
+
+    use super::ConnectionTracker;
+
+    #[test]
+    fn test_drain_when_idle() {
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let tracker = ConnectionTracker::new();
+            let draining = pgwire::Draining::new();
+            let idle_after = Duration::from_millis(200);
+
+            // An open connection keeps the server from draining, and the wait
+            // starts over once it closes.
+            let start = Instant::now();
+            let guard = tracker.open();
+            let wait = tokio::spawn({
+                let tracker = tracker.clone();
+                let draining = draining.clone();
+                async move { tracker.drain_when_idle(idle_after, &draining).await }
+            });
+            time::sleep(Duration::from_millis(300)).await;
+            assert!(!draining.is_draining());
+            drop(guard);
+            wait.await.unwrap();
+            assert!(draining.is_draining());
+            assert!(start.elapsed() >= Duration::from_millis(500));
+        });
+    }
+}
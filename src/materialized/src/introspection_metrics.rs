@@ -0,0 +1,292 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Republishes a selection of introspection data as Prometheus metrics.
+//!
+//! The introspection sources describe the dataflow engine in detail, but can
+//! only be read with SQL. The bridge in this module periodically queries a
+//! few of them and exports the results as metrics, for monitoring systems
+//! that only speak Prometheus.
+//!
+//! Operator names are unbounded, so only the [`TOP_OPERATORS`] most
+//! significant operator classes are exported by name. The remainder are
+//! summed into an operator class named `other`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use log::{debug, info};
+
+use ore::metric;
+use ore::metrics::{Gauge, GaugeVec, MetricsRegistry, UIntCounter};
+
+use crate::scheduler::Job;
+
+/// The number of operator classes exported by name.
+pub const TOP_OPERATORS: usize = 10;
+
+/// The operator class into which operators beyond the top
+/// [`TOP_OPERATORS`] are summed.
+const OTHER_OPERATORS: &str = "other";
+
+/// Periodically republishes introspection data as Prometheus metrics.
+pub struct Bridge {
+    coord_client: coord::Client,
+    metrics: Metrics,
+    inactive: Mutex<bool>,
+}
+
+impl Bridge {
+    /// Constructs a bridge that queries the introspection sources via
+    /// `coord_client`.
+    pub fn new(coord_client: coord::Client, metrics: Metrics) -> Bridge {
+        Bridge {
+            coord_client,
+            metrics,
+            inactive: Mutex::new(false),
+        }
+    }
+
+    /// Returns a job that refreshes the metrics every `frequency`.
+    pub fn into_job(self, frequency: Duration) -> Job {
+        let bridge = Arc::new(self);
+        Job::every("introspection_metrics", frequency, move || {
+            let bridge = Arc::clone(&bridge);
+            async move { bridge.refresh().await }
+        })
+    }
+
+    /// Queries the introspection sources and republishes the results.
+    ///
+    /// If the introspection sources cannot be queried, for example because
+    /// they have been deactivated, the metrics are removed rather than left
+    /// to go stale.
+    async fn refresh(&self) {
+        let start = Instant::now();
+        let res = self.query().await;
+        self.metrics
+            .scrape_duration
+            .set(start.elapsed().as_secs_f64());
+        let mut inactive = self.inactive.lock().expect("lock poisoned");
+        match res {
+            Ok(readings) => {
+                if *inactive {
+                    debug!("introspection metrics: introspection sources readable again");
+                    *inactive = false;
+                }
+                self.metrics.operator_elapsed.replace(readings.elapsed);
+                self.metrics.arrangement_records.replace(readings.records);
+                self.metrics.peek_durations.replace(readings.peeks);
+            }
+            Err(e) => {
+                self.metrics.scrape_errors.inc();
+                if !*inactive {
+                    info!(
+                        "introspection metrics: unable to query introspection sources: {:#}",
+                        e
+                    );
+                    *inactive = true;
+                }
+                self.metrics.operator_elapsed.clear();
+                self.metrics.arrangement_records.clear();
+                self.metrics.peek_durations.clear();
+            }
+        }
+    }
+
+    async fn query(&self) -> Result<Readings, anyhow::Error> {
+        let elapsed = self
+            .query_by_operator(
+                "SELECT o.name, e.worker, pg_catalog.sum(e.elapsed_ns)::pg_catalog.int8
+                 FROM mz_catalog.mz_scheduling_elapsed e
+                 JOIN mz_catalog.mz_dataflow_operators o
+                     ON o.id = e.id AND o.worker = e.worker
+                 GROUP BY o.name, e.worker",
+            )
+            .await?
+            .into_iter()
+            .map(|(labels, ns)| (labels, ns / 1e9))
+            .collect();
+        let records = self
+            .query_by_operator(
+                "SELECT o.name, a.worker, pg_catalog.sum(a.records)::pg_catalog.int8
+                 FROM mz_catalog.mz_arrangement_sizes a
+                 JOIN mz_catalog.mz_dataflow_operators o
+                     ON o.id = a.operator AND o.worker = a.worker
+                 GROUP BY o.name, a.worker",
+            )
+            .await?;
+        let peeks = self.query_peek_durations().await?;
+        Ok(Readings {
+            elapsed,
+            records,
+            peeks,
+        })
+    }
+
+    /// Runs a query that returns an operator name, a worker, and a value,
+    /// and keeps only the operator classes with the largest total values.
+    async fn query_by_operator(
+        &self,
+        query: &str,
+    ) -> Result<BTreeMap<Vec<String>, f64>, anyhow::Error> {
+        let mut totals = BTreeMap::new();
+        let mut readings = vec![];
+        for row in self.coord_client.system_execute_one(query).await?.rows {
+            match (row.get(0), row.get(1), row.get(2)) {
+                (Some(serde_json::Value::String(name)), Some(worker), Some(value)) => {
+                    let value = value.as_f64().unwrap_or(0.0);
+                    *totals.entry(name.clone()).or_insert(0.0) += value;
+                    readings.push((name.clone(), worker.to_string(), value));
+                }
+                _ => bail!("unexpected row: {:?}", row),
+            }
+        }
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        let top: BTreeSet<_> = totals
+            .into_iter()
+            .take(TOP_OPERATORS)
+            .map(|(name, _)| name)
+            .collect();
+        let mut out = BTreeMap::new();
+        for (name, worker, value) in readings {
+            let name = if top.contains(&name) {
+                name
+            } else {
+                OTHER_OPERATORS.into()
+            };
+            *out.entry(vec![worker, name]).or_insert(0.0) += value;
+        }
+        Ok(out)
+    }
+
+    /// Queries the peek duration histogram, whose buckets are bounded by
+    /// powers of two nanoseconds, and accumulates it into cumulative buckets
+    /// as Prometheus expects.
+    async fn query_peek_durations(&self) -> Result<BTreeMap<Vec<String>, f64>, anyhow::Error> {
+        let query = "SELECT worker, duration_ns, count FROM mz_catalog.mz_peek_durations";
+        let mut buckets: BTreeMap<String, BTreeMap<i64, f64>> = BTreeMap::new();
+        for row in self.coord_client.system_execute_one(query).await?.rows {
+            match (row.get(0), row.get(1).and_then(|d| d.as_i64()), row.get(2)) {
+                (Some(worker), Some(duration_ns), Some(count)) => {
+                    *buckets
+                        .entry(worker.to_string())
+                        .or_default()
+                        .entry(duration_ns)
+                        .or_insert(0.0) += count.as_f64().unwrap_or(0.0);
+                }
+                _ => bail!("unexpected row: {:?}", row),
+            }
+        }
+        let mut out = BTreeMap::new();
+        for (worker, buckets) in buckets {
+            let mut cumulative = 0.0;
+            for (duration_ns, count) in buckets {
+                cumulative += count;
+                let le = (duration_ns as f64 / 1e9).to_string();
+                out.insert(vec![worker.clone(), le], cumulative);
+            }
+            out.insert(vec![worker, "+Inf".into()], cumulative);
+        }
+        Ok(out)
+    }
+}
+
+/// The values read from the introspection sources, keyed by label values.
+struct Readings {
+    elapsed: BTreeMap<Vec<String>, f64>,
+    records: BTreeMap<Vec<String>, f64>,
+    peeks: BTreeMap<Vec<String>, f64>,
+}
+
+/// Metrics exported by the introspection metrics bridge.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    operator_elapsed: LabeledGauges,
+    arrangement_records: LabeledGauges,
+    peek_durations: LabeledGauges,
+    scrape_duration: Gauge,
+    scrape_errors: UIntCounter,
+}
+
+impl Metrics {
+    /// Registers the introspection metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            operator_elapsed: LabeledGauges::new(registry.register(metric!(
+                name: "mz_worker_operator_elapsed_seconds",
+                help: "the time that each worker has spent running operators of each class",
+                var_labels: ["worker", "operator"],
+            ))),
+            arrangement_records: LabeledGauges::new(registry.register(metric!(
+                name: "mz_worker_arrangement_records",
+                help: "the number of records in the arrangements maintained by operators \
+                       of each class on each worker",
+                var_labels: ["worker", "operator"],
+            ))),
+            peek_durations: LabeledGauges::new(registry.register(metric!(
+                name: "mz_worker_peek_duration_seconds_bucket",
+                help: "the number of peeks on each worker that took at most le seconds",
+                var_labels: ["worker", "le"],
+            ))),
+            scrape_duration: registry.register(metric!(
+                name: "mz_introspection_metrics_scrape_duration_seconds",
+                help: "how long the most recent refresh of the introspection metrics took",
+            )),
+            scrape_errors: registry.register(metric!(
+                name: "mz_introspection_metrics_scrape_errors_total",
+                help: "the number of times the introspection sources could not be queried",
+            )),
+        }
+    }
+}
+
+/// A gauge whose set of label values is replaced wholesale on each refresh.
+#[derive(Debug, Clone)]
+struct LabeledGauges {
+    gauge: GaugeVec,
+    current: Arc<Mutex<BTreeSet<Vec<String>>>>,
+}
+
+impl LabeledGauges {
+    fn new(gauge: GaugeVec) -> LabeledGauges {
+        LabeledGauges {
+            gauge,
+            current: Arc::new(Mutex::new(BTreeSet::new())),
+        }
+    }
+
+    /// Sets the gauge to `values`, removing any label values that are not
+    /// present in `values`.
+    fn replace(&self, values: BTreeMap<Vec<String>, f64>) {
+        let mut current = self.current.lock().expect("lock poisoned");
+        for labels in current.iter() {
+            if !values.contains_key(labels) {
+                let labels: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+                let _ = self.gauge.remove_label_values(&labels);
+            }
+        }
+        current.clear();
+        for (labels, value) in values {
+            {
+                let labels: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+                self.gauge.with_label_values(&labels).set(value);
+            }
+            current.insert(labels);
+        }
+    }
+
+    /// Removes all label values.
+    fn clear(&self) {
+        self.replace(BTreeMap::new());
+    }
+}
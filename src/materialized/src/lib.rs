@@ -13,33 +13,114 @@
 //! [differential dataflow]: ../differential_dataflow/index.html
 //! [timely dataflow]: ../timely/index.html
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::env;
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::ffi::CStr;
+use std::io::Write;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use compile_time_run::run_command_str;
-use futures::StreamExt;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use anyhow::{bail, Context};
+use futures::{future, FutureExt, StreamExt};
+use itertools::Itertools;
+use log::{info, warn};
+use openssl::ssl::{self, AlpnError, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use ore::cast::CastFrom;
+use ore::netio::TlsHandshakePool;
 use ore::{
     metric,
-    metrics::{Gauge, MetricsRegistry, UIntGauge, UIntGaugeVec},
+    metrics::{
+        Counter, Gauge, HistogramVec, MetricsRegistry, PrometheusOpts, UIntCounter, UIntCounterVec,
+        UIntGauge, UIntGaugeVec,
+    },
+    stats::LONG_DURATION_BUCKETS,
 };
-use sysinfo::{ProcessorExt, SystemExt};
+use tempfile::TempDir;
 use tokio::net::TcpListener;
-use tokio::sync::oneshot;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, watch};
 use tokio_stream::wrappers::TcpListenerStream;
 
 use build_info::BuildInfo;
+use coord::catalog::check::{CheckLevel, CheckReport};
 use coord::LoggingConfig;
+use sql::parse::RedactionPolicy;
 
+use crate::idle::ConnectionTracker;
+use crate::liveness::CoordHealth;
+use crate::metrics_guard::MetricsGuard;
 use crate::mux::Mux;
+use crate::scheduler::{Job, Scheduler};
+use crate::source_lag::SourceLagMetrics;
+use crate::startup::{Phase, StartupProgress};
+use crate::system::{HostEnvironment, HostMetrics, SystemInfo};
+use crate::version_check::VersionStatus;
+
+pub use crate::affinity::AffinityPolicy;
+pub use crate::allocator::AllocatorConfig;
+pub use crate::backup::{ClusterIdPolicy, RestoreConfig};
+pub use crate::connection_notice::ConnectionNoticeSource;
+pub use crate::encryption::{EncryptionConfig, KeySource};
+#[cfg(feature = "failpoints")]
+pub use crate::fault::{Failpoint, Fault, FaultConfig, FAILPOINTS};
+pub use crate::flight_recorder::FlightRecorderConfig;
+pub use crate::liveness::LivenessConfig;
+pub use crate::metrics_guard::MetricsFilter;
+pub use crate::mux::ProtocolPolicy;
+pub use crate::rate_limit::{RateLimitAction, RateLimitConfig, DEFAULT_RATE_LIMIT_ADDRESSES};
+pub use crate::replication::ReplicaTarget;
+pub use crate::self_test::{SelfTestReport, SelfTestStep};
+pub use crate::startup::{Phase, StartupTimeoutError};
+pub use crate::version_check::VersionCheckConfig;
+pub use crate::watchdog::{ByteSize, MemoryWatchdogConfig};
+pub use coord::catalog::DurabilityLevel;
+pub use coord::{
+    Authorizer, AuthzClass, AuthzDecision, AuthzRequest, ClockConfig, PolicyAuthorizer,
+    SessionQuotas, DEFAULT_MAX_TRACKED_USERS,
+};
+pub use dataflow_types::TailBufferPolicy;
 
+mod affinity;
+mod allocator;
+mod backup;
+mod compaction;
+mod connection_notice;
+mod crash;
+mod disk;
+mod encryption;
+#[cfg(feature = "failpoints")]
+mod fault;
+mod flight_recorder;
+mod handoff;
 mod http;
+mod idle;
+mod introspection_metrics;
+mod lifecycle;
+mod limits;
+mod liveness;
+mod logging;
+mod metrics_guard;
+mod migration;
 mod mux;
+mod orphans;
+mod rate_limit;
+mod replication;
+mod scheduler;
+mod self_test;
 mod server_metrics;
+mod source_lag;
+mod startup;
+mod system;
 mod telemetry;
+mod tls;
+mod version_check;
+mod watchdog;
 
 // Disable jemalloc on macOS, as it is not well supported [0][1][2].
 // The issues present as runaway latency on load test workloads that are
@@ -55,26 +136,11 @@ static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 pub const BUILD_INFO: BuildInfo = BuildInfo {
     version: env!("CARGO_PKG_VERSION"),
-    sha: run_command_str!(
-        "sh",
-        "-c",
-        r#"if [ -n "$MZ_DEV_BUILD_SHA" ]; then
-            echo "$MZ_DEV_BUILD_SHA"
-        else
-            # Unfortunately we need to suppress error messages from `git`, as
-            # run_command_str will display no error message at all if we print
-            # more than one line of output to stderr.
-            git rev-parse --verify HEAD 2>/dev/null || {
-                printf "error: unable to determine Git SHA; " >&2
-                printf "either build from working Git clone " >&2
-                printf "(see https://materialize.com/docs/install/#build-from-source), " >&2
-                printf "or specify SHA manually by setting MZ_DEV_BUILD_SHA environment variable" >&2
-                exit 1
-            }
-        fi"#
-    ),
-    time: run_command_str!("date", "-u", "+%Y-%m-%dT%H:%M:%SZ"),
+    sha: env!("BUILD_SHA"),
+    time: env!("BUILD_TIME"),
     target_triple: env!("TARGET_TRIPLE"),
+    rustc_version: env!("RUSTC_VERSION"),
+    license_change_date: env!("LICENSE_CHANGE_DATE"),
 };
 
 /// Configuration for a `materialized` server.
@@ -85,11 +151,130 @@ pub struct Config {
     pub workers: usize,
     /// The Timely worker configuration.
     pub timely_worker: timely::WorkerConfig,
+    /// How to pin the Timely worker threads to CPU cores, if at all.
+    pub worker_affinity: Option<AffinityPolicy>,
+    /// The size of the stack of each Timely worker thread, in bytes.
+    ///
+    /// If `None`, worker threads use the default stack size for new threads.
+    /// Must be at least [`MIN_WORKER_STACK_SIZE`].
+    pub worker_stack_size: Option<usize>,
+
+    // === Logging options. ===
+    /// Whether `serve` should install a global tracing subscriber, as
+    /// configured by `log`.
+    ///
+    /// Embedders that install their own subscriber should set this to false,
+    /// in which case `log` is ignored.
+    pub manage_logging: bool,
+    /// Configures the server's log messages.
+    ///
+    /// Not to be confused with `logging`, which configures the introspection
+    /// dataflows.
+    pub log: LogConfig,
+
+    // === Slow query log options. ===
+    /// Statements that take at least this long to execute are logged.
+    ///
+    /// If `None`, the slow query log is disabled until a threshold is
+    /// installed via the HTTP API.
+    pub slow_query_threshold: Option<Duration>,
+    /// A file to which to additionally append slow statements.
+    pub slow_query_log_file: Option<PathBuf>,
+    /// `TAIL` statements that stream results for at least this long are
+    /// logged, once per statement.
+    ///
+    /// If `None`, long-running `TAIL` statements are not logged.
+    pub long_running_tail_threshold: Option<Duration>,
+    /// The keys of the statement labels by which the statement duration
+    /// metric is labeled.
+    ///
+    /// Statements carry labels in a leading comment of the form
+    /// `/* key=value, ... */`. Labels with other keys are still logged, but
+    /// are not attached to the metric, so that the number of time series
+    /// remains bounded. See [`validate_statement_label_keys`].
+    pub statement_label_keys: Vec<String>,
+
+    // === Session options. ===
+    /// The default `statement_timeout` of every session.
+    ///
+    /// If `None`, statements may run indefinitely unless the session sets a
+    /// timeout of its own.
+    pub statement_timeout: Option<Duration>,
+    /// The default `idle_in_transaction_session_timeout` of every session.
+    pub idle_in_transaction_session_timeout: Option<Duration>,
+    /// Whether to forbid sessions from changing the statement and
+    /// idle-in-transaction timeouts from the server's defaults.
+    pub lock_timeouts: bool,
+    /// The largest number of rows that the result of any statement may have.
+    ///
+    /// Sessions may lower the limit via the `max_result_rows` session
+    /// variable. If `None`, results may have any number of rows unless the
+    /// session sets a limit of its own.
+    pub max_result_rows: Option<u64>,
+    /// The largest size in bytes of the result of any statement, or of any
+    /// batch of results of a `TAIL`.
+    ///
+    /// Sessions may lower the limit via the `max_result_bytes` session
+    /// variable. If `None`, results are unlimited in size unless the session
+    /// sets a limit of its own.
+    pub max_result_bytes: Option<usize>,
+    /// The largest number of bytes of updates that a `TAIL` may buffer for a
+    /// client that is not keeping up.
+    ///
+    /// Statements may choose a different limit with the `buffer_size`
+    /// option.
+    pub tail_buffer_size: usize,
+    /// What a `TAIL` does when its client falls behind by more than
+    /// `tail_buffer_size` bytes.
+    ///
+    /// Statements may choose a different policy with the `buffer_policy`
+    /// option.
+    pub tail_buffer_policy: TailBufferPolicy,
+    /// The maximum number of users whose statistics are tracked
+    /// individually.
+    ///
+    /// The statistics of users first seen once the limit is reached are
+    /// tracked together under the `other` user.
+    pub user_stats_max_users: usize,
+    /// The resource quotas to which each pgwire session is held.
+    ///
+    /// The quotas may be adjusted while the server is running via the
+    /// `/api/admin/session-quotas` HTTP endpoint.
+    pub session_quotas: coord::SessionQuotas,
+    /// If present, decides which statements each session may execute. The
+    /// authorizer is consulted before each statement is submitted to the
+    /// coordinator.
+    pub authz: Option<Arc<dyn Authorizer>>,
+
+    // === Crash report options. ===
+    /// If present, `serve` installs a panic hook that writes a crash report
+    /// to the `crash` directory within `data_directory` whenever the process
+    /// panics.
+    ///
+    /// Embedders that install their own panic hook may wish to set this to
+    /// `None`. Otherwise, the previously installed hook is invoked after the
+    /// crash report is written.
+    pub crash_reports: Option<CrashReportConfig>,
+    /// Whether to leave out of diagnostics bundles every entry that may
+    /// contain the text of SQL statements, namely recent logs and crash
+    /// reports.
+    pub diagnostics_exclude_sql: bool,
 
     // === Performance tuning options. ===
     pub logging: Option<LoggingConfig>,
     /// The frequency at which to update introspection.
     pub introspection_frequency: Duration,
+    /// Whether to export metrics that describe the memory and load of the
+    /// host, refreshed at the introspection frequency.
+    ///
+    /// Embedders that collect these metrics by other means may wish to
+    /// disable them.
+    pub host_metrics: bool,
+    /// Whether to republish a selection of the introspection sources as
+    /// Prometheus metrics, refreshed at the introspection frequency.
+    ///
+    /// Has no effect if `logging` is `None`.
+    pub introspection_metrics: bool,
     /// The historical window in which distinctions are maintained for
     /// arrangements.
     ///
@@ -104,16 +289,249 @@ pub struct Config {
     pub logical_compaction_window: Option<Duration>,
     /// The interval at which sources should be timestamped.
     pub timestamp_frequency: Duration,
+    /// How to detect and handle regressions of the wall clock from which
+    /// timestamps are assigned.
+    pub clock: ClockConfig,
+    /// If present, `serve` tunes the memory allocator before booting the
+    /// coordinator.
+    pub allocator: Option<AllocatorConfig>,
+    /// If present, `serve` watches the server's memory usage, and captures
+    /// diagnostics and sheds load as usage approaches the memory limit.
+    pub memory_watchdog: Option<MemoryWatchdogConfig>,
+    /// The memory that dataflows may occupy, as estimated from the number of
+    /// records in their arrangements.
+    ///
+    /// If present, creating an index, materialized view, or materialized
+    /// source fails once the estimate leaves too little of the budget for a
+    /// new dataflow, unless `dataflow_memory_budget_enforce` is false. The
+    /// budget and the estimate are reported by `/api/status`.
+    pub dataflow_memory_budget: Option<ByteSize>,
+    /// Whether to reject new dataflows that would exceed
+    /// `dataflow_memory_budget`, rather than admit them with a warning.
+    pub dataflow_memory_budget_enforce: bool,
+    /// If present, `serve` periodically records snapshots of the server's
+    /// memory usage, arrangements, and sessions to the diagnostics directory.
+    pub flight_recorder: Option<FlightRecorderConfig>,
+    /// If present, `serve` returns unused memory to the OS once no statement
+    /// has executed for this long. Ignored on macOS.
+    pub memory_trim_interval: Option<Duration>,
+    /// If present, `serve` adjusts the score by which the kernel's OOM killer
+    /// chooses the server, from -1000 (never) to 1000 (first). Ignored on
+    /// platforms other than Linux.
+    pub oom_score_adj: Option<i32>,
 
     // === Connection options. ===
     /// The IP address and port to listen on.
     pub listen_addr: SocketAddr,
+    /// If present, take over the listener of the server process that is
+    /// listening on this address with the same data directory, rather than
+    /// binding `listen_addr`.
+    ///
+    /// The other process stops accepting connections as soon as this server
+    /// holds its listener, drains its sessions, and exits. Until it exits,
+    /// which is when it releases the catalog, this server answers
+    /// connections with an error that indicates that it is starting up.
+    pub takeover_from: Option<SocketAddr>,
+    /// The local IP address to which outbound connections are bound, if any.
+    ///
+    /// The connections to the upstream databases of PostgreSQL sources and
+    /// to the symbiosis server are bound to this address before they
+    /// connect. If `None`, the operating system chooses the local address.
+    pub outbound_bind_addr: Option<IpAddr>,
+    /// How long a client may take to send enough bytes to determine its
+    /// protocol, before its connection is closed.
+    pub protocol_detect_timeout: Duration,
+    /// Which protocols to serve on `listen_addr`.
+    ///
+    /// Connections that speak another protocol are closed as soon as their
+    /// protocol is recognized.
+    pub protocol_policy: ProtocolPolicy,
+    /// If present, limits the rate of new connection attempts per source
+    /// address.
+    ///
+    /// Attempts over the limit are rejected as soon as they are accepted.
+    /// Connections from loopback addresses are exempt.
+    pub connection_rate_limit: Option<RateLimitConfig>,
     /// TLS encryption configuration.
     pub tls: Option<TlsConfig>,
+    /// Whether to warn, at most once a minute, when a client that is not on
+    /// the local host establishes a pgwire session without TLS encryption.
+    pub warn_on_plaintext: bool,
+    /// How much data to accumulate before writing it to a pgwire connection.
+    ///
+    /// If `None`, data is written whenever 8KiB accumulate.
+    pub pgwire_buffer_size: Option<usize>,
+    /// The initial size of the buffer into which messages from pgwire clients
+    /// are read.
+    ///
+    /// If `None`, defaults to 8KiB.
+    pub pgwire_read_buffer_size: Option<usize>,
+    /// How long a pgwire connection must be idle before buffers that grew to
+    /// fit large messages are shrunk back to their initial sizes.
+    ///
+    /// If `None`, buffers never shrink.
+    pub pgwire_buffer_shrink_after: Option<Duration>,
+    /// The PostgreSQL version to report to pgwire clients, e.g., `13.4`.
+    ///
+    /// If `None`, Materialize reports version 9.5.0. Only the version that
+    /// clients see changes; `mz_version()` and the HTTP status endpoints
+    /// always report the real version of Materialize.
+    pub pgwire_server_version: Option<String>,
+    /// The maximum size of a message from a pgwire client, in bytes.
+    ///
+    /// A client that sends a larger message receives an error, and its
+    /// connection is closed. Messages sent to clients are not limited.
+    pub pgwire_max_message_size: usize,
+    /// The maximum number of pgwire sessions to serve at once.
+    ///
+    /// Clients that connect once every session is taken receive a
+    /// `too_many_connections` error. If `None`, the number of sessions is
+    /// unlimited.
+    pub max_connections: Option<usize>,
+    /// The number of sessions out of `max_connections` that only the users
+    /// named in `superusers` may take, so that an administrator can always
+    /// connect.
+    pub reserved_superuser_connections: usize,
+    /// The users who may take the sessions reserved by
+    /// `reserved_superuser_connections`.
+    pub superusers: Vec<String>,
+    /// The maximum number of HTTP requests to serve concurrently.
+    ///
+    /// Requests to the `/metrics`, `/status`, and `/api/status` endpoints are
+    /// always served, and do not count against the limit. If `None`, the
+    /// number of concurrent requests is unlimited.
+    pub http_max_concurrent_requests: Option<usize>,
+    /// The number of HTTP requests in excess of
+    /// `http_max_concurrent_requests` that may wait to be served. Further
+    /// requests are rejected immediately.
+    pub http_request_queue_size: usize,
+    /// How long an HTTP request in excess of `http_max_concurrent_requests`
+    /// may wait to be served before it is rejected.
+    pub http_request_queue_timeout: Duration,
+    /// The maximum number of bytes of results that requests to the HTTP SQL
+    /// endpoint may buffer at once, across all requests.
+    ///
+    /// A request that would exceed the limit waits briefly for other requests
+    /// to finish, and then fails. If `None`, the memory is unlimited.
+    pub http_result_memory_limit: Option<usize>,
+    /// The maximum number of rows in each record batch of results that the
+    /// HTTP SQL endpoint returns in the Arrow format.
+    ///
+    /// Requests may ask for smaller batches, but not for larger ones.
+    pub http_arrow_max_batch_size: usize,
+    /// Whether to speak HTTP/2 over plaintext connections to clients that
+    /// begin the connection with the HTTP/2 preface.
+    ///
+    /// HTTP/2 is always available over TLS, to clients that negotiate it.
+    pub http2_cleartext: bool,
+    /// How long an HTTP/1 keep-alive connection may be idle before it is
+    /// closed.
+    ///
+    /// A timeout of zero disables keep-alive. If `None`, idle connections
+    /// remain open until the client closes them.
+    pub http1_keep_alive_timeout: Option<Duration>,
+    /// The maximum number of requests to serve over an HTTP/1 connection,
+    /// after which the connection is closed.
+    ///
+    /// If `None`, the number of requests is unlimited.
+    pub http1_max_requests_per_connection: Option<usize>,
+    /// Whether to notify clients when the server begins draining.
+    ///
+    /// If enabled, idle pgwire sessions receive a notice, and HTTP keep-alive
+    /// connections are closed, so that clients can reconnect to another
+    /// server before this one shuts down.
+    pub drain_notices: bool,
+    /// The notice to send to clients when they connect, if any.
+    ///
+    /// The notice is sent to pgwire clients once their session starts, unless
+    /// they set `client_min_messages` to a level above `notice` in their
+    /// startup parameters, and is reported by the `/api/status` and `/sql`
+    /// HTTP endpoints. A notice that is read from a file is reread when the
+    /// server receives SIGHUP.
+    pub connection_notice: Option<ConnectionNoticeSource>,
+    /// Shut down once no client has been connected for this long.
+    ///
+    /// Connections of every kind, whether pgwire or HTTP, count. If `None`,
+    /// the server runs until it is shut down by other means.
+    pub idle_exit_after: Option<Duration>,
+    /// How to probe the coordinator to detect that it has stopped responding,
+    /// e.g. because it has deadlocked.
+    pub coord_liveness: LivenessConfig,
+    /// How long the server may take to start, from when `serve` is called
+    /// until the coordinator has booted.
+    ///
+    /// If startup takes longer, `serve` abandons it and returns a
+    /// [`StartupTimeoutError`]. If `None`, startup may take arbitrarily long.
+    pub startup_timeout: Option<Duration>,
 
     // === Storage options. ===
     /// The directory in which `materialized` should store its own metadata.
     pub data_directory: PathBuf,
+    /// The directory in which `materialized` should store the catalog.
+    ///
+    /// If `None`, defaults to `data_directory`. The catalog is small but
+    /// must be durable, and so this directory is typically placed on
+    /// replicated storage.
+    pub catalog_directory: Option<PathBuf>,
+    /// The directory in which `materialized` should store the data of the
+    /// objects in the catalog.
+    ///
+    /// If `None`, defaults to `data_directory`. The directory is marked as
+    /// belonging to the catalog, and `serve` refuses to start with a storage
+    /// directory that belongs to another catalog, or that is missing data
+    /// that the catalog refers to.
+    pub storage_directory: Option<PathBuf>,
+    /// The directory in which `materialized` should store temporary files
+    /// that need not survive a restart.
+    ///
+    /// If `None`, defaults to the `scratch` directory within
+    /// `data_directory`, or, in read-only mode, to a temporary directory that
+    /// is removed when the server stops. `serve` deletes the contents of the
+    /// directory before booting the coordinator.
+    pub scratch_directory: Option<PathBuf>,
+    /// If present, `serve` restores the specified catalog backup into
+    /// the catalog directory before booting the coordinator.
+    pub restore: Option<RestoreConfig>,
+    /// If present, `serve` restores the most recent copy of the catalog
+    /// replicated to the specified target into the catalog directory before
+    /// booting the coordinator, replacing any catalog it contains.
+    pub from_replica: Option<ReplicaTarget>,
+    /// Whether `serve` may migrate a catalog written by an older version of
+    /// `materialized`.
+    ///
+    /// If false, `serve` refuses to start if the catalog requires migration,
+    /// and reports which migrations would run. If true, a copy of the
+    /// catalog from before the migration is kept in the catalog directory,
+    /// and the copy is reinstalled if the migration fails.
+    pub allow_catalog_migration: bool,
+    /// The durability of writes to the catalog.
+    pub durability: DurabilityLevel,
+    /// The interval at which to compact the catalog, if any.
+    ///
+    /// Compaction is skipped while a catalog backup is in progress. It is
+    /// always available on demand via the HTTP API.
+    pub catalog_compact_interval: Option<Duration>,
+    /// The interval at which to rescan `data_directory` for orphaned files.
+    ///
+    /// The data directory is always scanned once at startup. If `None`, it is
+    /// not rescanned.
+    pub orphan_scan_interval: Option<Duration>,
+    /// Whether to move orphaned files in `data_directory` to its quarantine
+    /// directory, and to delete them once they have been quarantined for
+    /// `orphan_retention`.
+    pub cleanup_orphans: bool,
+    /// How long orphaned files are quarantined before they are deleted.
+    pub orphan_retention: Duration,
+    /// If present, the catalog is encrypted at rest with the configured key.
+    ///
+    /// A new catalog is encrypted when it is created. An existing catalog
+    /// that is not encrypted must first be encrypted with
+    /// [`encrypt_catalog`].
+    pub data_encryption: Option<EncryptionConfig>,
+    /// If present, a copy of the catalog is replicated to the specified
+    /// target whenever the catalog changes, so that a warm standby can boot
+    /// from it. See [`Config::from_replica`].
+    pub catalog_replica: Option<ReplicaTarget>,
 
     // === Mode switches. ===
     /// An optional symbiosis endpoint. See the
@@ -123,12 +541,237 @@ pub struct Config {
     pub experimental_mode: bool,
     /// Whether to run in safe mode.
     pub safe_mode: bool,
+    /// Whether to run in read-only mode.
+    ///
+    /// In read-only mode, the catalog is opened read-only, statements that
+    /// would modify the catalog or write data are rejected, and no state is
+    /// persisted to `data_directory`. Sources still ingest data and views are
+    /// still maintained in memory.
+    pub read_only: bool,
+    /// Whether the server remains in maintenance mode across restarts.
+    ///
+    /// If set, the maintenance mode is persisted to `data_directory` whenever
+    /// it is enabled or disabled. Otherwise the server always starts out of
+    /// maintenance mode.
+    pub sticky_maintenance_mode: bool,
+    /// Whether to run the self test once the server has started.
+    ///
+    /// The self test creates a temporary table and materialized view,
+    /// queries them, and scrapes the server's metrics endpoint. Its outcome
+    /// is available via [`Server::self_test_report`]. The caller is expected
+    /// to shut the server down afterwards.
+    pub self_test: bool,
     /// Telemetry configuration.
     pub telemetry: Option<TelemetryConfig>,
+    /// Configures a periodic check for new releases, if any.
+    ///
+    /// The check honors the environment variables and the persisted
+    /// preference that disable telemetry.
+    pub version_check: Option<VersionCheckConfig>,
+    /// Configures fault injection, if any.
+    ///
+    /// If present, the failpoints it names are activated as the server
+    /// starts, and the `/api/failpoints` HTTP endpoint is available.
+    #[cfg(feature = "failpoints")]
+    pub fault_injection: Option<FaultConfig>,
+    /// Static labels that describe this instance, like its environment or
+    /// region.
+    ///
+    /// The labels are attached to the server metadata metrics and included in
+    /// telemetry reports. Label names must be valid Prometheus label names
+    /// and must not collide with reserved names. See
+    /// [`validate_instance_labels`].
+    pub instance_labels: BTreeMap<String, String>,
+    /// The name of this server, if any, which identifies it among a fleet of
+    /// servers.
+    ///
+    /// The name is reported to pgwire clients in the `mz_server_name`
+    /// parameter, attached to every error sent to clients, attached to the
+    /// server metadata metrics as the `server_name` label, reported by the
+    /// `/api/status` endpoint, and included in lifecycle events. See
+    /// [`validate_server_name`].
+    pub server_name: Option<String>,
+    /// The maximum number of series to export for each metric family, if
+    /// any.
+    ///
+    /// Series with label sets that are seen once a family reaches the limit
+    /// are aggregated into a single series labeled `overflow="true"`.
+    pub metrics_max_series: Option<usize>,
+    /// Filters the labels of the exported metrics, if present.
+    pub metrics_filter: Option<MetricsFilter>,
     /// The place where the server's metrics will be reported from.
     pub metrics_registry: MetricsRegistry,
 }
 
+/// Configures the server's log messages.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Which log messages to emit.
+    ///
+    /// This value is a comma-separated list of filter directives, as described
+    /// by the `--log-filter` command-line option.
+    pub filter: String,
+    /// The format in which to emit log messages.
+    pub format: LogFormat,
+    /// Where to emit log messages.
+    pub target: LogTarget,
+    /// Whether to additionally emit an event with timing information whenever
+    /// a span closes.
+    pub span_timings: bool,
+    /// If present, where to export trace spans.
+    pub trace_export: Option<TraceExportConfig>,
+    /// If present, how to throttle repetitive log messages.
+    pub throttle: Option<LogThrottleConfig>,
+    /// How to redact SQL text in log messages, slow query log entries, and
+    /// crash reports.
+    ///
+    /// Errors returned to clients are never redacted.
+    pub redaction: RedactionPolicy,
+    /// The size in bytes of the in-memory ring buffer of recent log events.
+    ///
+    /// The buffer holds the events that pass the log filter, whatever the
+    /// log target, and is served by the `/api/logs` HTTP endpoint.
+    pub buffer_size: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            filter: "info".into(),
+            format: LogFormat::Text,
+            target: LogTarget::Stderr,
+            span_timings: false,
+            trace_export: None,
+            throttle: None,
+            redaction: RedactionPolicy::default(),
+            buffer_size: logging::DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+/// Configures the throttling of repetitive log messages.
+///
+/// Log messages are repetitions of one another if they have the same level and
+/// were emitted from the same line of source code. Within each window, only
+/// the first few repetitions of a message are emitted. The number of
+/// repetitions that were suppressed is reported in a summary at the end of the
+/// window.
+#[derive(Debug, Clone)]
+pub struct LogThrottleConfig {
+    /// The period over which repetitions are counted.
+    pub window: Duration,
+    /// The number of repetitions of a message below the `ERROR` level to emit
+    /// per window.
+    pub burst: u32,
+    /// The number of repetitions of a message at the `ERROR` level to emit per
+    /// window.
+    pub error_burst: u32,
+}
+
+/// Configures the export of trace spans via the OpenTelemetry protocol (OTLP).
+///
+/// Spans are created for the lifetime of each connection, for the execution of
+/// each statement, and for each round trip through the coordinator. Like log
+/// messages, spans are subject to the log filter.
+#[derive(Debug, Clone)]
+pub struct TraceExportConfig {
+    /// The base URL of the OTLP/HTTP endpoint, like `http://localhost:4318`.
+    /// Spans are posted to the `/v1/traces` path beneath this URL.
+    pub endpoint: String,
+    /// The fraction of traces to export, between 0 and 1.
+    ///
+    /// Applies only to traces that originate in this process. Traces that are
+    /// continued from a client's `traceparent` follow the client's sampling
+    /// decision.
+    pub sample_ratio: f64,
+    /// Additional attributes that describe this process, like
+    /// `deployment.environment`.
+    pub resource_attributes: BTreeMap<String, String>,
+}
+
+/// The format of log messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line, with stable field names.
+    ///
+    /// Span timings are not emitted in this format.
+    Json,
+}
+
+/// Where to emit log messages.
+#[derive(Debug, Clone)]
+pub enum LogTarget {
+    /// Emit log messages to the standard error stream.
+    Stderr,
+    /// Emit log messages to a file.
+    File {
+        /// The path to the file. Messages are appended if the file exists.
+        path: PathBuf,
+        /// If present, messages at least as severe as this level are
+        /// additionally emitted to the standard error stream.
+        stderr_level: Option<tracing::Level>,
+        /// If present, how to rotate the file as it grows.
+        rotation: Option<LogRotationConfig>,
+    },
+    /// Emit log messages to a syslog server.
+    Syslog(SyslogConfig),
+}
+
+/// Configures the emission of log messages to a syslog server.
+///
+/// Messages are formatted according to RFC 5424, with the structured fields of
+/// each message as structured data.
+#[derive(Debug, Clone)]
+pub struct SyslogConfig {
+    /// The address of the syslog server, like `syslog.example.com:6514`.
+    pub addr: String,
+    /// The transport protocol with which to send messages.
+    pub protocol: SyslogProtocol,
+    /// The numeric code of the facility with which to tag messages, between 0
+    /// and 23, like 3 for `daemon`.
+    pub facility: u8,
+    /// The application name with which to tag messages.
+    pub app_name: String,
+    /// If present, messages at least as severe as this level are additionally
+    /// emitted to the standard error stream.
+    pub stderr_level: Option<tracing::Level>,
+}
+
+/// A transport protocol for syslog messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogProtocol {
+    /// Unreliable delivery via UDP.
+    Udp,
+    /// Reliable delivery via TCP.
+    Tcp,
+    /// Reliable, encrypted delivery via TCP with TLS.
+    Tls,
+}
+
+/// Configures crash reports.
+#[derive(Debug, Clone)]
+pub struct CrashReportConfig {
+    /// The number of crash reports to keep. Older reports are deleted.
+    pub max_reports: usize,
+}
+
+/// Configures size-based rotation of the log file.
+#[derive(Debug, Clone)]
+pub struct LogRotationConfig {
+    /// The size in bytes beyond which the log file is rotated.
+    pub max_size: u64,
+    /// The number of rotated log files to keep.
+    ///
+    /// Rotated files are named by appending `.1`, `.2`, and so on to the path
+    /// of the log file, where `.1` is the most recent.
+    pub max_files: usize,
+    /// Whether to gzip rotated log files, in which case their names
+    /// additionally end in `.gz`.
+    pub compress: bool,
+}
+
 /// Configures TLS encryption for connections.
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
@@ -168,79 +811,902 @@ pub struct TelemetryConfig {
     pub domain: String,
     /// The interval at which to report telemetry data.
     pub interval: Duration,
+    /// What to do with each telemetry report.
+    pub mode: TelemetryMode,
+    /// The maximum time to wait to connect to the telemetry server.
+    pub connect_timeout: Duration,
+    /// The maximum time to wait for the telemetry server to respond.
+    pub request_timeout: Duration,
+    /// Whether to derive the randomized delay before the first report, and
+    /// the jitter between subsequent reports, deterministically from the
+    /// cluster ID. Intended for tests.
+    pub deterministic_splay: bool,
+    /// An explicit proxy through which to send telemetry reports.
+    ///
+    /// If not present, the standard proxy environment variables (e.g.,
+    /// `HTTPS_PROXY` and `NO_PROXY`) are obeyed.
+    pub proxy: Option<TelemetryProxyConfig>,
+    /// The file to which logged telemetry reports are appended.
+    ///
+    /// If not present, logged reports are written to the server log. Ignored
+    /// if `mode` is [`TelemetryMode::Send`].
+    pub log_file: Option<PathBuf>,
+    /// The directory in which to spool reports that fail to send, so that
+    /// they can be sent once the telemetry server is reachable again, or
+    /// downloaded via the HTTP API.
+    ///
+    /// A relative path is resolved against [`Config::data_directory`]. If not
+    /// present, reports that fail to send are discarded. Required if `mode`
+    /// is [`TelemetryMode::Spool`].
+    pub spool_dir: Option<PathBuf>,
+    /// The maximum total size of the spooled reports, in bytes.
+    ///
+    /// Once the spool is full, the oldest reports are dropped to make room
+    /// for new reports.
+    pub spool_max_size: u64,
+}
+
+/// Configures a proxy for telemetry reports.
+#[derive(Debug, Clone)]
+pub struct TelemetryProxyConfig {
+    /// The URL of the proxy server.
+    pub url: String,
+    /// The user name with which to authenticate to the proxy, if any.
+    pub username: Option<String>,
+    /// The password with which to authenticate to the proxy, if any.
+    pub password: Option<String>,
+    /// Hosts for which the proxy should be bypassed.
+    ///
+    /// Each entry matches the named host and all of its subdomains. The
+    /// special entry `*` matches all hosts.
+    pub no_proxy: Vec<String>,
+}
+
+/// Determines what happens to each telemetry report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryMode {
+    /// Send the report to the telemetry server.
+    Send,
+    /// Log the exact request that would have been sent, without making any
+    /// network requests.
+    Log,
+    /// Both send the report and log it.
+    SendAndLog,
+    /// Spool the report on disk for later export, without making any network
+    /// requests.
+    Spool,
+}
+
+impl TelemetryMode {
+    /// Reports whether telemetry reports are sent to the telemetry server.
+    pub fn sends(&self) -> bool {
+        matches!(self, TelemetryMode::Send | TelemetryMode::SendAndLog)
+    }
+
+    /// Reports whether telemetry reports are logged locally.
+    pub fn logs(&self) -> bool {
+        matches!(self, TelemetryMode::Log | TelemetryMode::SendAndLog)
+    }
+}
+
+/// The names of the labels that are built in to the server metadata metrics.
+const BUILTIN_METADATA_LABELS: &[&str] = &[
+    "build_time",
+    "version",
+    "build_sha",
+    "os",
+    "ncpus_logical",
+    "ncpus_physical",
+    "cpu0",
+    "memory_total",
+    "count",
+    "kind",
+    "purpose",
+    "worker",
+    "core",
+    "server_name",
+];
+
+/// The maximum length of [`Config::server_name`], in bytes.
+pub const MAX_SERVER_NAME_LEN: usize = 63;
+
+/// The label name prefixes that are reserved for use by Materialize and by
+/// Prometheus itself.
+const RESERVED_LABEL_PREFIXES: &[&str] = &["mz_", "__"];
+
+/// The smallest permissible stack size for Timely worker threads, in bytes.
+///
+/// This matches the default stack size for new threads. Smaller stacks are
+/// all but guaranteed to overflow while rendering dataflows.
+pub const MIN_WORKER_STACK_SIZE: usize = 2 << 20;
+
+/// The default value of [`Config::reserved_superuser_connections`].
+pub const DEFAULT_RESERVED_SUPERUSER_CONNECTIONS: usize = 3;
+
+/// The default value of [`Config::http_request_queue_size`].
+pub const DEFAULT_HTTP_REQUEST_QUEUE_SIZE: usize = 100;
+
+/// The default value of [`Config::http_arrow_max_batch_size`].
+pub const DEFAULT_HTTP_ARROW_MAX_BATCH_SIZE: usize = 65_536;
+
+/// The default value of [`Config::http_request_queue_timeout`].
+pub const DEFAULT_HTTP_REQUEST_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default value of [`Config::protocol_detect_timeout`].
+pub const DEFAULT_PROTOCOL_DETECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default value of [`TelemetryConfig::spool_dir`], when spooling is
+/// enabled.
+pub const DEFAULT_TELEMETRY_SPOOL_DIR: &str = "telemetry_spool";
+
+/// The file in the data directory to which the maintenance mode is persisted,
+/// if [`Config::sticky_maintenance_mode`] is set.
+pub const MAINTENANCE_MODE_FILE: &str = "maintenance_mode";
+
+/// Validates the names of instance labels.
+///
+/// Each name must be a valid Prometheus label name, i.e., match the regular
+/// expression `[a-zA-Z_][a-zA-Z0-9_]*`. Names that begin with a reserved
+/// prefix (`mz_` or `__`) or that collide with the names of the labels built
+/// in to the server metadata metrics are rejected.
+pub fn validate_instance_labels(labels: &BTreeMap<String, String>) -> Result<(), anyhow::Error> {
+    for name in labels.keys() {
+        if !is_valid_label_name(name) {
+            bail!(
+                "invalid instance label name {:?}: label names must match [a-zA-Z_][a-zA-Z0-9_]*",
+                name
+            );
+        }
+        if let Some(prefix) = RESERVED_LABEL_PREFIXES
+            .iter()
+            .find(|p| name.starts_with(*p))
+        {
+            bail!(
+                "invalid instance label name {:?}: the {:?} prefix is reserved",
+                name,
+                prefix
+            );
+        }
+        if BUILTIN_METADATA_LABELS.contains(&name.as_str()) {
+            bail!(
+                "invalid instance label name {:?}: name is reserved for a built-in label",
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Validates the name of the server.
+///
+/// The name must be between 1 and [`MAX_SERVER_NAME_LEN`] bytes long and
+/// consist only of ASCII letters, digits, `.`, `-`, and `_`, so that it can
+/// be included in log messages, HTTP headers, and error messages without
+/// escaping.
+pub fn validate_server_name(name: &str) -> Result<(), anyhow::Error> {
+    if name.is_empty() || name.len() > MAX_SERVER_NAME_LEN {
+        bail!(
+            "invalid server name {:?}: name must be between 1 and {} bytes long",
+            name,
+            MAX_SERVER_NAME_LEN
+        );
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+    {
+        bail!(
+            "invalid server name {:?}: name must consist of ASCII letters, digits, '.', '-', \
+             and '_'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Validates the keys of the statement labels that are attached to the
+/// statement duration metric.
+///
+/// Each key must be a valid Prometheus label name that does not begin with a
+/// reserved prefix, and must not collide with the metric's `class` label, with
+/// an instance label, or with another key.
+pub fn validate_statement_label_keys(
+    keys: &[String],
+    instance_labels: &BTreeMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    for (i, key) in keys.iter().enumerate() {
+        if !is_valid_label_name(key) {
+            bail!(
+                "invalid statement label key {:?}: keys must match [a-zA-Z_][a-zA-Z0-9_]*",
+                key
+            );
+        }
+        if let Some(prefix) = RESERVED_LABEL_PREFIXES.iter().find(|p| key.starts_with(*p)) {
+            bail!(
+                "invalid statement label key {:?}: the {:?} prefix is reserved",
+                key,
+                prefix
+            );
+        }
+        if key == "class" || instance_labels.contains_key(key) || keys[..i].contains(key) {
+            bail!(
+                "invalid statement label key {:?}: key collides with another label",
+                key
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reports whether `name` is a valid Prometheus label name, i.e., whether it
+/// matches the regular expression `[a-zA-Z_][a-zA-Z0-9_]*`.
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Attaches the instance labels to `opts` as constant labels.
+fn with_instance_labels(
+    mut opts: PrometheusOpts,
+    instance_labels: &BTreeMap<String, String>,
+) -> PrometheusOpts {
+    for (name, value) in instance_labels {
+        opts = opts.const_label(name, value);
+    }
+    opts
 }
 
 /// Global metrics for the materialized server
 #[derive(Debug, Clone)]
 pub struct Metrics {
     /// The number of workers active in the system.
-    worker_count: UIntGaugeVec,
+    worker_count: UIntGauge,
+
+    /// The number of workers active in the system, reported as a label
+    /// rather than as the value.
+    ///
+    /// Deprecated in favor of `worker_count`, and to be removed in the next
+    /// release.
+    worker_count_deprecated: UIntGaugeVec,
+
+    /// The core to which each worker is pinned, as a label.
+    worker_affinity: UIntGaugeVec,
+
+    /// The number of logical and physical CPUs.
+    cpus: UIntGaugeVec,
+
+    /// The total amount of memory, in bytes.
+    ///
+    /// Set by [`Metrics::register_metadata`] once information about the
+    /// system has been collected.
+    memory_total: UIntGauge,
+
+    /// The amount of memory the server may use, in bytes.
+    ///
+    /// Set by [`Metrics::register_metadata`] if the server has no memory
+    /// limit and so may use all the memory on the system.
+    memory_limit: UIntGauge,
 
-    /// The number of seconds that the system has been running.
-    uptime: Gauge,
+    /// When the server started, from which its uptime is computed.
+    start_instant: Instant,
+
+    /// The number of seconds that the server has been running, computed as
+    /// the metric is gathered.
+    uptime: Counter,
 
     /// The amount of time we spend gathering metrics in prometheus endpoints.
     request_metrics_gather: UIntGauge,
 
     /// The amount of time we spend encoding metrics in prometheus endpoints.
     request_metrics_encode: UIntGauge,
+
+    /// Whether the server is running in read-only mode.
+    read_only: UIntGauge,
+
+    /// The logical compaction window, in milliseconds, or -1 if logical
+    /// compaction is disabled.
+    logical_compaction_window: Gauge,
+
+    /// The number of bytes stored in each of the server's directories.
+    disk_usage: UIntGaugeVec,
+
+    /// Whether the server is draining.
+    draining: UIntGauge,
+
+    /// When the server began draining, in seconds since the Unix epoch.
+    drain_started: Gauge,
+
+    /// The number of connections that remain open while the server is
+    /// draining, by protocol.
+    draining_connections: UIntGaugeVec,
+
+    /// The number of connections refused because the server is draining, by
+    /// protocol.
+    connections_rejected_draining: UIntCounterVec,
+
+    /// The number of pgwire sessions and HTTP connections open, by transport.
+    connections_active: UIntGaugeVec,
+
+    /// The number of connections closed because the protocol policy does
+    /// not permit their protocol, by protocol.
+    connections_rejected_protocol: UIntCounterVec,
+
+    /// The number of connection attempts rejected by the connection rate
+    /// limit, by action.
+    connections_rate_limited: UIntCounterVec,
+
+    /// The number of connections closed because their protocol could not be
+    /// determined, by reason.
+    protocol_detect_failures: UIntCounterVec,
+
+    /// The number of HTTP requests being served that count against the
+    /// concurrency limit.
+    http_requests_in_flight: UIntGauge,
+
+    /// The number of HTTP requests waiting to be served because of the
+    /// concurrency limit.
+    http_requests_queued: UIntGauge,
+
+    /// The number of HTTP requests rejected because of the concurrency limit.
+    http_requests_rejected: UIntCounter,
+
+    /// The number of bytes of results buffered by requests to the HTTP SQL
+    /// endpoint.
+    http_result_buffered_bytes: UIntGauge,
+
+    /// The number of requests to the HTTP SQL endpoint that failed because
+    /// the memory limit on buffered results was exhausted.
+    http_result_rejected: UIntCounter,
+
+    /// The number of HTTP requests received, by protocol.
+    http_requests: UIntCounterVec,
+
+    /// How long connections stayed open, by protocol, observed when they
+    /// close.
+    connection_durations: HistogramVec,
+
+    /// How long statements took to execute, by statement class and by the
+    /// allowed statement labels.
+    statement_durations: HistogramVec,
 }
 
 impl Metrics {
-    fn register_with(registry: &MetricsRegistry) -> Self {
-        let mut system = sysinfo::System::new();
-        system.refresh_system();
-
+    fn register_with(
+        registry: &MetricsRegistry,
+        instance_labels: &BTreeMap<String, String>,
+        statement_label_keys: &[String],
+        start_instant: Instant,
+    ) -> Self {
         let request_metrics: UIntGaugeVec = registry.register(metric!(
             name: "mz_server_scrape_metrics_times",
             help: "how long it took to gather metrics, used for very low frequency high accuracy measures",
             var_labels: ["action"],
         ));
+        let with_instance_labels = |opts| with_instance_labels(opts, instance_labels);
         Self {
-            worker_count: registry.register(metric!(
-                name: "mz_server_metadata_timely_worker_threads",
+            worker_count: registry.register(with_instance_labels(metric!(
+                name: "mz_server_timely_worker_threads",
                 help: "number of timely worker threads",
+            ))),
+            worker_count_deprecated: registry.register(with_instance_labels(metric!(
+                name: "mz_server_metadata_timely_worker_threads",
+                help: "number of timely worker threads, as the count label; deprecated in \
+                       favor of mz_server_timely_worker_threads and will be removed in the \
+                       next release",
                 var_labels: ["count"],
-            )),
-            uptime: registry.register(metric!(
-                name: "mz_server_metadata_seconds",
-                help: "server metadata, value is uptime",
-                const_labels: {
-                    "build_time" => BUILD_INFO.time,
-                    "version" => BUILD_INFO.version,
-                    "build_sha" => BUILD_INFO.sha,
-                    "os" => &os_info::get().to_string(),
-                    "ncpus_logical" => &num_cpus::get().to_string(),
-                    "ncpus_physical" => &num_cpus::get_physical().to_string(),
-                    "cpu0" => &{
-                        match &system.processors().get(0) {
-                            None => "<unknown>".to_string(),
-                            Some(cpu0) => format!("{} {}MHz", cpu0.brand(), cpu0.frequency()),
+            ))),
+            worker_affinity: registry.register(with_instance_labels(metric!(
+                name: "mz_server_timely_worker_affinity",
+                help: "the core to which each timely worker thread is pinned, as the core label",
+                var_labels: ["worker", "core"],
+            ))),
+            cpus: registry.register(with_instance_labels(metric!(
+                name: "mz_server_cpus",
+                help: "the number of logical or physical CPUs",
+                var_labels: ["kind"],
+            ))),
+            memory_total: registry.register(with_instance_labels(metric!(
+                name: "mz_server_memory_total_bytes",
+                help: "the total amount of memory on the system",
+            ))),
+            memory_limit: registry.register(with_instance_labels(metric!(
+                name: "mz_server_memory_limit_bytes",
+                help: "the amount of memory the server may use: the memory watchdog's limit, \
+                       or else the memory limit of the server's cgroup, or else the total \
+                       amount of memory on the system",
+            ))),
+            start_instant,
+            uptime: {
+                // Concurrent gathers must not both add the same increment.
+                let lock = Mutex::new(());
+                registry.register_computed(
+                    with_instance_labels(metric!(
+                        name: "mz_server_uptime_seconds_total",
+                        help: "the number of seconds that the server has been running",
+                    )),
+                    move |uptime: &Counter| {
+                        let _guard = lock.lock().expect("lock poisoned");
+                        let delta = start_instant.elapsed().as_secs_f64() - uptime.get();
+                        if delta > 0.0 {
+                            uptime.inc_by(delta);
                         }
                     },
-                    "memory_total" => &system.total_memory().to_string()
-                },
-            )),
+                )
+            },
             request_metrics_gather: request_metrics.with_label_values(&["gather"]),
             request_metrics_encode: request_metrics.with_label_values(&["encode"]),
+            read_only: registry.register(with_instance_labels(metric!(
+                name: "mz_server_read_only",
+                help: "whether the server is running in read-only mode (1) or not (0)",
+            ))),
+            logical_compaction_window: registry.register(with_instance_labels(metric!(
+                name: "mz_server_logical_compaction_window_ms",
+                help: "the logical compaction window applied to indexes and sources that do \
+                       not override it, in milliseconds, or -1 if logical compaction is disabled",
+            ))),
+            disk_usage: registry.register(with_instance_labels(metric!(
+                name: "mz_server_disk_usage_bytes",
+                help: "the number of bytes stored in the data, catalog, storage, or scratch directory",
+                var_labels: ["purpose"],
+            ))),
+            draining: registry.register(with_instance_labels(metric!(
+                name: "mz_server_draining",
+                help: "whether the server is draining (1) or serving (0)",
+            ))),
+            drain_started: registry.register(with_instance_labels(metric!(
+                name: "mz_server_drain_started_timestamp_seconds",
+                help: "when the server began draining, in seconds since the Unix epoch, \
+                       or 0 if it is not draining",
+            ))),
+            draining_connections: registry.register(with_instance_labels(metric!(
+                name: "mz_server_draining_connections",
+                help: "the number of connections that remain open while the server is \
+                       draining, by protocol",
+                var_labels: ["protocol"],
+            ))),
+            connections_rejected_draining: registry.register(with_instance_labels(metric!(
+                name: "mz_server_connections_rejected_draining_total",
+                help: "the number of connections refused because the server is draining, \
+                       by protocol",
+                var_labels: ["protocol"],
+            ))),
+            connections_active: registry.register(with_instance_labels(metric!(
+                name: "mz_server_connections_active",
+                help: "the number of pgwire sessions and HTTP connections open, by transport: \
+                       plaintext or tls",
+                var_labels: ["transport"],
+            ))),
+            connections_rejected_protocol: registry.register(with_instance_labels(metric!(
+                name: "mz_server_connections_rejected_protocol_total",
+                help: "the number of connections closed because the protocol policy does \
+                       not permit their protocol, by protocol",
+                var_labels: ["protocol"],
+            ))),
+            connections_rate_limited: registry.register(with_instance_labels(metric!(
+                name: "mz_server_connections_rate_limited_total",
+                help: "the number of connection attempts rejected by the connection rate \
+                       limit, by action",
+                var_labels: ["action"],
+            ))),
+            protocol_detect_failures: registry.register(with_instance_labels(metric!(
+                name: "mz_server_protocol_detect_failures_total",
+                help: "the number of connections closed because their protocol could not be \
+                       determined, by reason: timeout or unrecognized",
+                var_labels: ["reason"],
+            ))),
+            http_requests_in_flight: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_requests_in_flight",
+                help: "the number of HTTP requests being served that count against the \
+                       concurrency limit",
+            ))),
+            http_requests_queued: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_requests_queued",
+                help: "the number of HTTP requests waiting to be served because of the \
+                       concurrency limit",
+            ))),
+            http_requests_rejected: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_requests_rejected_total",
+                help: "the number of HTTP requests rejected because of the concurrency limit",
+            ))),
+            http_result_buffered_bytes: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_result_buffered_bytes",
+                help: "the number of bytes of results buffered by requests to the HTTP SQL \
+                       endpoint",
+            ))),
+            http_result_rejected: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_result_rejected_total",
+                help: "the number of requests to the HTTP SQL endpoint that failed because the \
+                       memory limit on buffered results was exhausted",
+            ))),
+            http_requests: registry.register(with_instance_labels(metric!(
+                name: "mz_server_http_requests_total",
+                help: "the number of HTTP requests received, by negotiated protocol",
+                var_labels: ["protocol"],
+            ))),
+            connection_durations: registry.register_histogram_vec(
+                with_instance_labels(metric!(
+                    name: "mz_connection_duration_seconds",
+                    help: "how long pgwire and HTTP connections stayed open, by protocol",
+                    var_labels: ["protocol"],
+                )),
+                &LONG_DURATION_BUCKETS,
+            ),
+            statement_durations: registry.register_histogram_vec(
+                {
+                    let mut opts = with_instance_labels(metric!(
+                        name: "mz_statement_duration_seconds",
+                        help: "how long statements executed via pgwire and HTTP took, by statement \
+                               class: select, insert, ddl, tail, or other, and by the allowed \
+                               statement labels",
+                        var_labels: ["class"],
+                    ));
+                    opts.variable_labels
+                        .extend(statement_label_keys.iter().cloned());
+                    opts
+                },
+                &LONG_DURATION_BUCKETS,
+            ),
+        }
+    }
+
+    /// Registers the server metadata metric, whose labels describe the
+    /// system and whose value is the server's uptime, computed as the metric
+    /// is gathered.
+    ///
+    /// If the server has no `memory_limit`, the total amount of memory on the
+    /// system is reported as its memory limit.
+    fn register_metadata(
+        &self,
+        registry: &MetricsRegistry,
+        instance_labels: &BTreeMap<String, String>,
+        system: &SystemInfo,
+        memory_limit: Option<u64>,
+    ) {
+        let start_instant = self.start_instant;
+        let _: Gauge = registry.register_computed(
+            with_instance_labels(
+                metric!(
+                    name: "mz_server_metadata_seconds",
+                    help: "server metadata, value is uptime; the ncpus_logical, ncpus_physical, \
+                           and memory_total labels are deprecated in favor of mz_server_cpus and \
+                           mz_server_memory_total_bytes and will be removed in the next release",
+                    const_labels: {
+                        "build_time" => BUILD_INFO.time,
+                        "version" => BUILD_INFO.version,
+                        "build_sha" => BUILD_INFO.sha,
+                        "os" => &system.os,
+                        "ncpus_logical" => &num_cpus::get().to_string(),
+                        "ncpus_physical" => &num_cpus::get_physical().to_string(),
+                        "cpu0" => &system.cpu0,
+                        "memory_total" => &system.memory_total.to_string()
+                    },
+                ),
+                instance_labels,
+            ),
+            move |uptime: &Gauge| uptime.set(start_instant.elapsed().as_secs_f64()),
+        );
+        self.memory_total.set(system.memory_total * 1024);
+        if memory_limit.is_none() {
+            self.memory_limit.set(system.memory_total * 1024);
+        }
+    }
+
+    /// Measures the size of the server's directories.
+    ///
+    /// The catalog and storage directories are measured separately only if
+    /// they are not the data directory. The scratch directory is never
+    /// counted toward another directory.
+    ///
+    /// This function performs blocking I/O.
+    fn update_disk_usage(
+        &self,
+        data_directory: &Path,
+        catalog_directory: &Path,
+        storage_directory: &Path,
+        scratch_directory: &Path,
+    ) {
+        let mut purposes = vec![("data", data_directory)];
+        if catalog_directory != data_directory {
+            purposes.push(("catalog", catalog_directory));
         }
+        if storage_directory != data_directory && storage_directory != catalog_directory {
+            purposes.push(("storage", storage_directory));
+        }
+        for (purpose, directory) in &purposes {
+            let mut exclude: Vec<_> = purposes
+                .iter()
+                .map(|(_, d)| *d)
+                .filter(|d| d != directory)
+                .collect();
+            exclude.push(scratch_directory);
+            self.disk_usage
+                .with_label_values(&[purpose])
+                .set(disk::usage(directory, &exclude));
+        }
+        self.disk_usage
+            .with_label_values(&["scratch"])
+            .set(disk::usage(scratch_directory, &[]));
     }
+}
 
-    fn update_uptime(&self, start_time: Instant) {
-        let uptime = start_time.elapsed();
-        let (secs, milli_part) = (uptime.as_secs() as f64, uptime.subsec_millis() as f64);
-        self.uptime.set(secs + milli_part / 1_000.0);
+/// Checks the integrity of the catalog in `catalog_directory` without
+/// starting the server.
+///
+/// Unlike the subset of checks that [`serve`] runs on every boot, this
+/// function verifies the internal consistency of the catalog's contents. It
+/// never modifies the catalog directory. The definitions in an encrypted
+/// catalog are only checked if `data_encryption` is provided.
+pub fn check(
+    catalog_directory: &Path,
+    data_encryption: Option<&EncryptionConfig>,
+) -> Result<CheckReport, anyhow::Error> {
+    let encryption_key = match data_encryption {
+        None => None,
+        Some(data_encryption) => Some(data_encryption.key_source.load()?),
+    };
+    Ok(coord::catalog::check::check(
+        &catalog_directory.join("catalog"),
+        CheckLevel::Full,
+        encryption_key.as_ref(),
+    )?)
+}
+
+/// Encrypts the unencrypted catalog in `catalog_directory` with the key
+/// configured by `data_encryption`, without starting the server.
+///
+/// The server must not be running against `catalog_directory`. Encrypting a
+/// catalog that is already encrypted with the configured key has no effect.
+pub fn encrypt_catalog(
+    catalog_directory: &Path,
+    data_encryption: &EncryptionConfig,
+) -> Result<(), anyhow::Error> {
+    let path = catalog_directory.join("catalog");
+    if !path.exists() {
+        bail!(
+            "catalog {} does not exist; a new catalog is encrypted when materialized \
+             first starts with an encryption key",
+            path.display()
+        );
+    }
+    // Encrypting the catalog must not migrate it behind the user's back.
+    if let Some(version) = coord::catalog::storage::read_version(&path)? {
+        version.check_compatible(BUILD_INFO.version, false)?;
     }
+    let key = data_encryption.key_source.load()?;
+    coord::catalog::storage::encrypt(&path, &key)
+        .with_context(|| format!("encrypting catalog {}", path.display()))?;
+    Ok(())
 }
 
 /// Start a `materialized` server.
 pub async fn serve(config: Config) -> Result<Server, anyhow::Error> {
+    let start_instant = Instant::now();
+    let start_system_time = SystemTime::now();
+
+    // Scanning the system can be slow, so it happens in the background while
+    // the server starts.
+    let system_info = tokio::task::spawn_blocking(SystemInfo::collect);
+
+    // Install the tracing subscriber, if requested, and then print system
+    // information as the very first thing in the logs. The goal is to
+    // increase the probability that we can reproduce a reported bug if all we
+    // get is the log file.
+    let (log_filter, log_file) = if config.manage_logging {
+        let (filter_handle, file_handle) = logging::init(&config.log, &config.metrics_registry)?;
+        log_boot_banner(config.log.format);
+        (Some(filter_handle), file_handle)
+    } else {
+        (None, None)
+    };
+    // Raise the nofile limit before describing the host environment, so
+    // that the effective limit is reported.
+    limits::adjust_rlimits();
+    let host_environment = HostEnvironment::collect(&config.data_directory);
+    // The server name appears in every lifecycle event, so it is validated
+    // before the first.
+    if let Some(server_name) = &config.server_name {
+        validate_server_name(server_name)?;
+    }
+    lifecycle::starting(
+        config.read_only,
+        &host_environment,
+        config.server_name.as_deref(),
+    );
+    if config.read_only {
+        info!("running in read-only mode; the data directory will not be modified");
+    }
+
+    // Reopen the log file on SIGHUP, to cooperate with external log rotation
+    // tools like logrotate.
+    if let Some(log_file) = log_file.clone() {
+        let mut sighup = signal(SignalKind::hangup()).context("installing SIGHUP handler")?;
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                info!("received SIGHUP; reopening log file");
+                log_file.reopen();
+            }
+        });
+    }
+
+    let config_description = crash::describe_config(&config);
+    if let Some(crash_reports) = &config.crash_reports {
+        crash::install_hook(
+            config.data_directory.join("crash"),
+            crash_reports.max_reports,
+            config_description.clone(),
+        );
+    }
+
+    if let Some(adj) = config.oom_score_adj {
+        if !limits::OOM_SCORE_ADJ_RANGE.contains(&adj) {
+            bail!(
+                "OOM score adjustment must be between -1000 and 1000, got {}",
+                adj
+            );
+        }
+        limits::adjust_oom_score(adj)?;
+    }
+
+    allocator::apply(&config.allocator)?;
+    if let Some(settings) = allocator::describe_settings() {
+        info!("allocator settings: {}", settings);
+    }
+    match config.outbound_bind_addr {
+        None => info!("outbound connections bound to: <any>"),
+        // librdkafka and the HTTP clients of the other connectors offer no
+        // way to bind their connections, so only Postgres connections honor
+        // the setting.
+        Some(addr) => info!(
+            "outbound connections bound to: {} (PostgreSQL sources and symbiosis only; \
+             connections to Kafka, Kinesis, S3, PubNub, and schema registries are not bound)",
+            addr
+        ),
+    }
+
     let workers = config.workers;
 
+    // Assign the worker threads to cores, if requested. Invalid assignments
+    // are rejected now, before any worker has started.
+    let worker_cores = affinity::plan(&config.worker_affinity, workers)?;
+
+    match config.worker_stack_size {
+        Some(size) if size < MIN_WORKER_STACK_SIZE => bail!(
+            "worker stack size of {} bytes is less than the minimum of {} bytes",
+            size,
+            MIN_WORKER_STACK_SIZE
+        ),
+        Some(size) => info!("timely worker stack size: {} bytes", size),
+        None => info!("timely worker stack size: default"),
+    }
+
+    validate_instance_labels(&config.instance_labels)?;
+    // The server name, if any, is attached to the server metadata metrics
+    // alongside the instance labels.
+    let mut metadata_labels = config.instance_labels.clone();
+    if let Some(server_name) = &config.server_name {
+        metadata_labels.insert("server_name".into(), server_name.clone());
+    }
+    validate_statement_label_keys(&config.statement_label_keys, &metadata_labels)?;
+    if config.protocol_detect_timeout == Duration::from_secs(0) {
+        bail!("protocol detection timeout must be positive");
+    }
+    if let Some(connection_rate_limit) = &config.connection_rate_limit {
+        connection_rate_limit.validate()?;
+    }
+
+    // The self test creates objects, which read-only mode forbids, and
+    // scrapes the metrics endpoint over plain HTTP.
+    if config.read_only && config.self_test {
+        bail!("cannot run the self test in read-only mode");
+    }
+    if config.self_test && config.tls.is_some() {
+        bail!("cannot run the self test with TLS enabled");
+    }
+
+    // Reject invalid configurations before binding or taking over a
+    // listener, so that a server that cannot start does not take the listener
+    // of a healthy server.
+    if config.read_only && config.sticky_maintenance_mode {
+        bail!("cannot persist the maintenance mode in read-only mode");
+    }
+    if config.read_only && config.restore.is_some() {
+        bail!("cannot restore a backup in read-only mode");
+    }
+    if config.read_only && config.from_replica.is_some() {
+        bail!("cannot restore a catalog replica in read-only mode");
+    }
+    if config.restore.is_some() && config.from_replica.is_some() {
+        bail!("cannot restore both a backup and a catalog replica");
+    }
+    if config.read_only && config.cleanup_orphans {
+        bail!("cannot clean up orphaned files in read-only mode");
+    }
+    if config.read_only && config.catalog_compact_interval.is_some() {
+        bail!("cannot compact the catalog in read-only mode");
+    }
+    if let Some(memory_watchdog) = &config.memory_watchdog {
+        memory_watchdog.validate()?;
+    }
+    if let Some(flight_recorder) = &config.flight_recorder {
+        flight_recorder.validate()?;
+    }
+    if config.memory_trim_interval == Some(Duration::from_secs(0)) {
+        bail!("memory trim interval must be positive");
+    }
+    if config.long_running_tail_threshold == Some(Duration::from_secs(0)) {
+        bail!("long-running TAIL threshold must be positive");
+    }
+    if let Some(max_connections) = config.max_connections {
+        if config.reserved_superuser_connections >= max_connections {
+            bail!(
+                "reserved superuser connections ({}) must be fewer than max connections ({})",
+                config.reserved_superuser_connections,
+                max_connections
+            );
+        }
+    }
+    if config.max_result_rows == Some(0) {
+        bail!("maximum result rows must be positive");
+    }
+    if config.max_result_bytes == Some(0) {
+        bail!("maximum result bytes must be positive");
+    }
+    if config.tail_buffer_size == 0 {
+        bail!("TAIL buffer size must be positive");
+    }
+    if config.http_max_concurrent_requests == Some(0) {
+        bail!("HTTP concurrency limit must be positive");
+    }
+    if config.dataflow_memory_budget == Some(ByteSize(0)) {
+        bail!("dataflow memory budget must be positive");
+    }
+    if config.http_result_memory_limit == Some(0) {
+        bail!("HTTP result memory limit must be positive");
+    }
+    if config.http_arrow_max_batch_size == 0 {
+        bail!("maximum HTTP Arrow batch size must be positive");
+    }
+    if config.http1_max_requests_per_connection == Some(0) {
+        bail!("maximum HTTP/1 requests per connection must be positive");
+    }
+    config.coord_liveness.validate()?;
+    if let Some(version_check) = &config.version_check {
+        version_check.validate()?;
+    }
+    if let Some(telemetry) = &config.telemetry {
+        if telemetry.spool_dir.is_none() && telemetry.mode == TelemetryMode::Spool {
+            bail!("telemetry mode spool requires a spool directory");
+        }
+        if telemetry.spool_dir.is_some() && config.read_only {
+            bail!("telemetry reports cannot be spooled in read-only mode");
+        }
+    }
+
+    if let Some(version) = &config.pgwire_server_version {
+        if coord::session::server_version_num(version).is_none() {
+            bail!(
+                "invalid pgwire server version {:?}: expected a PostgreSQL version like 13.4 or 9.6.2",
+                version
+            );
+        }
+        info!(
+            "reporting PostgreSQL server version {} to pgwire clients",
+            version
+        );
+    }
+
     // Validate TLS configuration, if present.
     let (pgwire_tls, http_tls) = match &config.tls {
         None => (None, None),
         Some(tls_config) => {
+            // OpenSSL's own errors rarely explain what is wrong with the
+            // certificate or key, so check them first.
+            tls_config.validate()?;
             let context = {
                 // Mozilla publishes three presets: old, intermediate, and modern. They
                 // recommend the intermediate preset for general purpose servers, which
@@ -250,19 +1716,38 @@ pub async fn serve(config: Config) -> Result<Server, anyhow::Error> {
                 // incompatible with Fivetran, and presumably other JDBC-based tools.
                 let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
                 if let TlsMode::VerifyCa { ca } | TlsMode::VerifyFull { ca } = &tls_config.mode {
-                    builder.set_ca_file(ca)?;
+                    builder
+                        .set_ca_file(ca)
+                        .with_context(|| format!("loading TLS CA certificate {}", ca.display()))?;
                     builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
                 }
-                builder.set_certificate_file(&tls_config.cert, SslFiletype::PEM)?;
-                builder.set_private_key_file(&tls_config.key, SslFiletype::PEM)?;
+                builder
+                    .set_certificate_chain_file(&tls_config.cert)
+                    .with_context(|| {
+                        format!("loading TLS certificate {}", tls_config.cert.display())
+                    })?;
+                builder
+                    .set_private_key_file(&tls_config.key, SslFiletype::PEM)
+                    .with_context(|| format!("loading TLS key {}", tls_config.key.display()))?;
+                // Offer HTTP/2 to HTTP clients that support it. pgwire
+                // clients do not negotiate a protocol, and are unaffected.
+                builder.set_alpn_select_callback(|_, client| {
+                    ssl::select_next_proto(http::ALPN_PROTOCOLS, client).ok_or(AlpnError::NOACK)
+                });
                 builder.build().into_context()
             };
+            // Handshakes are expensive, so they are performed on a dedicated
+            // pool to keep them from stalling the threads that accept and
+            // serve connections.
+            let handshake_pool = TlsHandshakePool::new((num_cpus::get() / 2).clamp(1, 4))
+                .context("starting TLS handshake pool")?;
             let pgwire_tls = pgwire::TlsConfig {
                 context: context.clone(),
                 mode: match tls_config.mode {
                     TlsMode::Require | TlsMode::VerifyCa { .. } => pgwire::TlsMode::Require,
                     TlsMode::VerifyFull { .. } => pgwire::TlsMode::VerifyUser,
                 },
+                handshake_pool: handshake_pool.clone(),
             };
             let http_tls = http::TlsConfig {
                 context,
@@ -270,109 +1755,1332 @@ pub async fn serve(config: Config) -> Result<Server, anyhow::Error> {
                     TlsMode::Require | TlsMode::VerifyCa { .. } => http::TlsMode::Require,
                     TlsMode::VerifyFull { .. } => http::TlsMode::AssumeUser,
                 },
+                handshake_pool,
             };
             (Some(pgwire_tls), Some(http_tls))
         }
     };
     let metrics_registry = config.metrics_registry;
-    let metrics = Metrics::register_with(&metrics_registry);
+    let metrics = Metrics::register_with(
+        &metrics_registry,
+        &metadata_labels,
+        &config.statement_label_keys,
+        start_instant,
+    );
+    let backup_metrics = backup::Metrics::register_into(&metrics_registry);
+    let compaction_metrics = compaction::Metrics::register_into(&metrics_registry);
+    let allocator_metrics = allocator::Metrics::register_with(&metrics_registry);
+    let metrics_guard = MetricsGuard::register_into(
+        &metrics_registry,
+        config.metrics_filter.clone(),
+        config.metrics_max_series,
+    );
 
-    // Set this metric once so that it shows up in the metric export.
+    // Set these metrics once so that they show up in the metric export.
+    metrics.worker_count.set(workers.try_into().unwrap());
     metrics
-        .worker_count
+        .worker_count_deprecated
         .with_label_values(&[&workers.to_string()])
         .set(workers.try_into().unwrap());
+    metrics
+        .cpus
+        .with_label_values(&["logical"])
+        .set(num_cpus::get().try_into().unwrap());
+    metrics
+        .cpus
+        .with_label_values(&["physical"])
+        .set(num_cpus::get_physical().try_into().unwrap());
+    metrics.read_only.set(u64::from(config.read_only));
+    let host_environment_info: UIntGauge = metrics_registry.register(with_instance_labels(
+        metric!(
+            name: "mz_host_environment",
+            help: "facts about the host environment, value is always 1",
+            const_labels: {
+                "allocator" => &host_environment.allocator,
+                "transparent_hugepages" => &host_environment.transparent_hugepages,
+                "data_directory_filesystem" => &host_environment.data_directory_filesystem,
+                "clocksource" => &host_environment.clocksource,
+                "nofile_soft_limit" => &host_environment.nofile_soft_limit
+            },
+        ),
+        &metadata_labels,
+    ));
+    host_environment_info.set(1);
 
-    // Initialize network listener.
-    let listener = TcpListener::bind(&config.listen_addr).await?;
-    let local_addr = listener.local_addr()?;
-
-    // Initialize coordinator.
-    let (coord_handle, coord_client) = coord::serve(coord::Config {
-        workers,
-        timely_worker: config.timely_worker,
-        symbiosis_url: config.symbiosis_url.as_deref(),
-        logging: config.logging,
-        data_directory: &config.data_directory,
-        timestamp_frequency: config.timestamp_frequency,
-        logical_compaction_window: config.logical_compaction_window,
-        experimental_mode: config.experimental_mode,
-        safe_mode: config.safe_mode,
-        build_info: &BUILD_INFO,
-        metrics_registry: metrics_registry.clone(),
-    })
-    .await?;
+    // Determine the memory limit that the server runs against, so that
+    // dashboards can show memory usage against it rather than against the
+    // memory on the system.
+    let memory_limit = match config.memory_watchdog.as_ref().and_then(|w| w.limit) {
+        Some(limit) => Some(limit.as_u64()),
+        None => watchdog::cgroup_memory_limit(),
+    };
+    if let Some(memory_limit) = memory_limit {
+        metrics.memory_limit.set(memory_limit);
+    }
+    if let Some(worker_cores) = &worker_cores {
+        for (worker, core) in worker_cores.iter().enumerate() {
+            info!("timely worker {} assigned to core {}", worker, core);
+            metrics
+                .worker_affinity
+                .with_label_values(&[&worker.to_string(), &core.to_string()])
+                .set(1);
+        }
+    }
+    tokio::spawn({
+        let metrics = metrics.clone();
+        let metrics_registry = metrics_registry.clone();
+        let metadata_labels = metadata_labels.clone();
+        let log_format = config.manage_logging.then(|| config.log.format);
+        async move {
+            if let Ok(system_info) = system_info.await {
+                if let Some(log_format) = log_format {
+                    log_system_info(log_format, &system_info);
+                }
+                metrics.register_metadata(
+                    &metrics_registry,
+                    &metadata_labels,
+                    &system_info,
+                    memory_limit,
+                );
+            }
+        }
+    });
 
-    // Launch task to serve connections.
+    // Initialize network listener, and begin serving connections right away.
+    // Until the coordinator has booted, connections are answered with an
+    // error that reports startup progress, so that clients can tell a
+    // starting server from an unreachable one.
     //
     // The lifetime of this task is controlled by a trigger that activates on
     // drop. Draining marks the beginning of the server shutdown process and
-    // indicates that new user connections (i.e., pgwire and HTTP connections)
-    // should be rejected. Once all existing user connections have gracefully
-    // terminated, this task exits.
-    let (drain_trigger, drain_tripwire) = oneshot::channel();
+    // indicates that new sessions should be refused. The listener keeps
+    // accepting connections while the server drains, so that clients can
+    // still cancel the queries of existing sessions, and stops only when the
+    // server is dropped. If startup fails, the trigger is dropped on return,
+    // which stops the task.
+    //
+    // A server that takes over the listener of another server process does
+    // not bind a listener of its own, so that some process is listening on
+    // the address throughout. Unless the data directory must not be
+    // modified, every server's listener may in turn be taken over, through
+    // a control socket in the data directory.
+    let startup = StartupProgress::new(start_instant);
+    let (listener, predecessor) = match config.takeover_from {
+        None => (TcpListener::bind(&config.listen_addr).await?, None),
+        Some(addr) => {
+            let data_directory = config.data_directory.clone();
+            let (listener, predecessor) =
+                tokio::task::spawn_blocking(move || handoff::take_over(&data_directory, addr))
+                    .await??;
+            info!("took over listener on {}", addr);
+            (TcpListener::from_std(listener)?, Some(predecessor))
+        }
+    };
+    let local_addr = listener.local_addr()?;
+    let handoff = match config.read_only {
+        true => None,
+        false => match handoff::serve(&config.data_directory, &listener) {
+            Ok(handoff) => Some(handoff),
+            Err(e) => {
+                warn!("listener cannot be handed off to a new server: {:#}", e);
+                None
+            }
+        },
+    };
+    let (listener_trigger, listener_tripwire) = oneshot::channel();
+    let (handoff_trigger, handoff_tripwire) = oneshot::channel::<()>();
+    let draining = pgwire::Draining::new();
+    let connection_tracker = ConnectionTracker::new();
+    let runtime_flags = http::RuntimeFlags {
+        experimental_mode: config.experimental_mode,
+        safe_mode: config.safe_mode,
+        read_only: config.read_only,
+    };
+    let mux_handle = {
+        let mut mux = Mux::new();
+        mux.detect_within(
+            config.protocol_detect_timeout,
+            metrics.protocol_detect_failures.clone(),
+        );
+        mux.enforce(
+            config.protocol_policy,
+            metrics.connections_rejected_protocol.clone(),
+        );
+        mux.time_connections(metrics.connection_durations.clone());
+        if let Some(connection_rate_limit) = config.connection_rate_limit.clone() {
+            mux.rate_limit(rate_limit::ConnectionRateLimiter::new(
+                connection_rate_limit,
+                metrics.connections_rate_limited.clone(),
+            ));
+        }
+        mux.add_handler(pgwire::StartingServer::new(
+            pgwire_tls.clone(),
+            config.server_name.clone(),
+        ));
+        mux.add_handler(http::StartingServer::new(
+            http_tls.clone(),
+            config.http2_cleartext,
+            startup.clone(),
+            runtime_flags,
+            config.server_name.clone(),
+        ));
+        let handle = mux.handle();
+        let connection_tracker = connection_tracker.clone();
+        tokio::spawn(async move {
+            // TODO(benesch): replace with `listener.incoming()` if that is
+            // restored when the `Stream` trait stabilizes.
+            let mut incoming = TcpListenerStream::new(listener);
+            // Once the listener is handed off, the new server accepts every
+            // connection.
+            let handed_off = async {
+                if handoff_tripwire.await.is_err() {
+                    future::pending::<()>().await;
+                }
+            };
+            mux.serve(
+                incoming
+                    .by_ref()
+                    .take_until(future::select(listener_tripwire, Box::pin(handed_off))),
+                connection_tracker,
+            )
+            .await;
+        });
+        handle
+    };
+    lifecycle::listening(local_addr, config.server_name.as_deref());
+
+    // Report when the server begins draining, however the drain is
+    // triggered, and how many connections remain open from then on, so that
+    // whoever is waiting to shut the server down can tell when it is safe to.
+    for protocol in &["pgwire", "http"] {
+        metrics
+            .draining_connections
+            .with_label_values(&[protocol])
+            .set(0);
+        metrics
+            .connections_rejected_draining
+            .with_label_values(&[protocol]);
+    }
     tokio::spawn({
+        let metrics = metrics.clone();
+        let draining = draining.clone();
+        let connection_tracker = connection_tracker.clone();
+        async move {
+            draining.wait().await;
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            metrics.drain_started.set(now.as_secs_f64());
+            connection_tracker.report_draining(metrics.draining_connections.clone());
+            metrics.draining.set(1);
+        }
+    });
+
+    // Once a successor has taken over the listener, stop accepting
+    // connections and drain, and report the reason to whoever awaits
+    // `Server::handed_off`. The control connection is held open until the
+    // server is dropped or the process exits, which is when the successor
+    // may open the catalog.
+    let handed_off_to = Arc::new(Mutex::new(None));
+    let handed_off = handoff.map(|handoff| {
+        let (mut handed_off_tx, handed_off_rx) = oneshot::channel();
+        let draining = draining.clone();
+        let handed_off_to = Arc::clone(&handed_off_to);
+        let server_name = config.server_name.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                conn = handoff => {
+                    let _ = handoff_trigger.send(());
+                    draining.start();
+                    *handed_off_to.lock().expect("lock poisoned") = Some(conn);
+                    lifecycle::handed_off(local_addr, server_name.as_deref());
+                    let _ = handed_off_tx.send("listener handed off to a new server".into());
+                }
+                _ = handed_off_tx.closed() => (),
+            }
+        });
+        handed_off_rx
+    });
+
+    // A server that took over the listener of another server process must
+    // not touch the data directory until that process, which holds the
+    // catalog until it exits, has drained its sessions and exited. The wait is
+    // bounded by the predecessor's drain timeout rather than by the startup
+    // timeout, as the predecessor no longer accepts connections and the
+    // successor could not give the listener back if it gave up.
+    if let Some(predecessor) = predecessor {
+        startup.set(Phase::AwaitingPredecessor);
+        info!("waiting for the server that handed off its listener to exit");
+        startup.excluding(predecessor.exited()).await?;
+        startup.set(Phase::Preparing);
+    }
+
+    // Initialize slow query log.
+    let slow_query_log = coord::SlowQueryLog::new(coord::SlowQueryLogConfig {
+        threshold: config.slow_query_threshold,
+        redaction: config.log.redaction,
+        file: config.slow_query_log_file.clone(),
+    })?;
+
+    // Initialize maintenance mode, which is restored from the data directory
+    // only if it is sticky.
+    let maintenance_mode = coord::MaintenanceMode::register_into(
+        &metrics_registry,
+        match config.sticky_maintenance_mode {
+            true => Some(config.data_directory.join(MAINTENANCE_MODE_FILE)),
+            false => None,
+        },
+    )?;
+    if let Some(maintenance) = maintenance_mode.get() {
+        warn!(
+            "server is in maintenance mode{}",
+            match &maintenance.message {
+                Some(message) => format!(": {}", message),
+                None => "".into(),
+            }
+        );
+    }
+
+    let clock = coord::ClockMonitor::register_into(&metrics_registry, config.clock.clone());
+    let session_quotas = coord::Quotas::new(config.session_quotas);
+    let transport_monitor =
+        coord::TransportMonitor::new(metrics.connections_active.clone(), config.warn_on_plaintext);
+
+    // The catalog and the data of the objects in the catalog may be split
+    // across two directories, which default to the data directory.
+    let catalog_directory = config
+        .catalog_directory
+        .clone()
+        .unwrap_or_else(|| config.data_directory.clone());
+    let storage_directory = config
+        .storage_directory
+        .clone()
+        .unwrap_or_else(|| config.data_directory.clone());
+
+    // Prepare the scratch directory. In read-only mode, the default scratch
+    // directory within the data directory is replaced with a temporary
+    // directory, so that the data directory is not modified. Preparing the
+    // scratch directory can be slow, so it proceeds concurrently with the
+    // preparation of the catalog below.
+    let (scratch_directory, scratch_tempdir) = match config.scratch_directory {
+        Some(scratch_directory) => (scratch_directory, None),
+        None if config.read_only => {
+            let tempdir = tempfile::tempdir().context("creating scratch directory")?;
+            (tempdir.path().to_path_buf(), Some(tempdir))
+        }
+        None => (config.data_directory.join("scratch"), None),
+    };
+    // Heap profiles and flight recorder snapshots are written to the
+    // diagnostics directory. In read-only mode, the data directory must not
+    // be modified, so they are written to the scratch directory instead.
+    let diagnostics_directory = if config.read_only {
+        scratch_directory.join("diagnostics")
+    } else {
+        config.data_directory.join("diagnostics")
+    };
+    let prepare_scratch_directory = {
+        let scratch_directory = scratch_directory.clone();
+        let data_directory = config.data_directory.clone();
+        let catalog_directory = catalog_directory.clone();
+        let storage_directory = storage_directory.clone();
+        tokio::task::spawn_blocking(move || {
+            disk::prepare_scratch_directory(
+                &scratch_directory,
+                &[
+                    ("data", &data_directory),
+                    ("catalog", &catalog_directory),
+                    ("storage", &storage_directory),
+                ],
+            )
+        })
+    };
+
+    // Determine which files in the data directory belong to the server, for
+    // the purposes of the orphan scan.
+    let mut reserved_paths = vec![scratch_directory.clone()];
+    if let LogTarget::File { path, .. } = &config.log.target {
+        reserved_paths.push(path.clone());
+    }
+    reserved_paths.extend(config.slow_query_log_file.clone());
+    reserved_paths.push(config.data_directory.join("diagnostics"));
+    reserved_paths.push(catalog_directory.clone());
+    reserved_paths.push(storage_directory.clone());
+    if let Some(ReplicaTarget::Directory(path)) = &config.catalog_replica {
+        reserved_paths.push(path.clone());
+    }
+
+    // Restore the catalog from a backup, if requested. This must happen
+    // before the coordinator opens the catalog.
+    #[cfg(feature = "failpoints")]
+    {
+        if let Some(fault_injection) = &config.fault_injection {
+            for failpoint in &fault_injection.failpoints {
+                failpoint.activate()?;
+            }
+        }
+    }
+    if let Some(restore) = &config.restore {
+        let restore = restore.clone();
+        let catalog_directory = catalog_directory.clone();
+        startup
+            .within(
+                config.startup_timeout,
+                tokio::task::spawn_blocking(move || backup::restore(&restore, &catalog_directory)),
+            )
+            .await???;
+    }
+    if let Some(from_replica) = &config.from_replica {
+        let manifest = startup
+            .within(
+                config.startup_timeout,
+                replication::restore(from_replica, &catalog_directory),
+            )
+            .await??;
+        info!(
+            "booting from catalog replica {} taken at {}",
+            from_replica, manifest.created_at
+        );
+    }
+
+    // Refuse catalogs that this version of the server cannot open, and
+    // prepare to migrate the catalog, if necessary and permitted. This must
+    // happen before the coordinator opens the catalog.
+    startup.set(Phase::CheckingCatalog);
+    let migration = {
+        let catalog_directory = catalog_directory.clone();
+        let allow_catalog_migration = config.allow_catalog_migration;
+        let read_only = config.read_only;
+        startup
+            .within(
+                config.startup_timeout,
+                tokio::task::spawn_blocking(move || {
+                    migration::prepare(&catalog_directory, allow_catalog_migration, read_only)
+                }),
+            )
+            .await???
+    };
+
+    // Load the catalog's encryption key, if any.
+    let encryption_key = match &config.data_encryption {
+        None => None,
+        Some(data_encryption) => {
+            let key_source = data_encryption.key_source.clone();
+            Some(
+                startup
+                    .within(
+                        config.startup_timeout,
+                        tokio::task::spawn_blocking(move || key_source.load()),
+                    )
+                    .await???,
+            )
+        }
+    };
+
+    startup
+        .within(config.startup_timeout, prepare_scratch_directory)
+        .await???;
+
+    // Initialize coordinator. Opening the catalog and bootstrapping the
+    // coordinator block, so they happen on a thread of their own, which
+    // startup can abandon if it times out. An abandoned coordinator shuts
+    // down once it finishes booting, when its handle and client are dropped.
+    // A migration that was in progress is left in place, as the coordinator
+    // may still be writing the catalog, and is rolled back when the server
+    // next starts.
+    startup.set(Phase::BootingCoordinator);
+    let introspection_enabled = config.logging.is_some();
+    let (boot_tx, boot_rx) = oneshot::channel();
+    {
+        let executor = tokio::runtime::Handle::current();
+        let timely_worker = config.timely_worker;
+        let worker_start_hook = worker_cores.map(affinity::hook);
+        let worker_stack_size = config.worker_stack_size;
+        let symbiosis_url = config.symbiosis_url.clone();
+        let logging = config.logging;
+        let catalog_directory = catalog_directory.clone();
+        let storage_directory = storage_directory.clone();
+        let scratch_directory = scratch_directory.clone();
+        let timestamp_frequency = config.timestamp_frequency;
+        let logical_compaction_window = config.logical_compaction_window;
+        let experimental_mode = config.experimental_mode;
+        let safe_mode = config.safe_mode;
+        let metrics_registry = metrics_registry.clone();
+        let session_defaults = coord::session::SessionDefaults {
+            server_version: config.pgwire_server_version.clone(),
+            statement_timeout: config.statement_timeout,
+            idle_in_transaction_session_timeout: config.idle_in_transaction_session_timeout,
+            lock_timeouts: config.lock_timeouts,
+            max_result_rows: config.max_result_rows,
+            max_result_bytes: config.max_result_bytes.map(u64::cast_from),
+        };
+        let durability = config.durability;
+        let read_only = config.read_only;
+        let maintenance_mode = maintenance_mode.clone();
+        let clock = clock.clone();
+        let tail_buffer_size = config.tail_buffer_size;
+        let tail_buffer_policy = config.tail_buffer_policy;
+        let outbound_bind_addr = config.outbound_bind_addr;
+        let user_stats_max_users = config.user_stats_max_users;
+        let dataflow_memory_budget = config.dataflow_memory_budget.map(|b| b.as_u64());
+        let dataflow_memory_budget_enforce = config.dataflow_memory_budget_enforce;
+        thread::Builder::new()
+            .name("coord-boot".into())
+            .spawn(move || {
+                let _executor_guard = executor.enter();
+                let res = futures::executor::block_on(coord::serve(coord::Config {
+                    workers,
+                    timely_worker,
+                    worker_start_hook,
+                    worker_stack_size,
+                    symbiosis_url: symbiosis_url.as_deref(),
+                    logging,
+                    catalog_directory: &catalog_directory,
+                    storage_directory: &storage_directory,
+                    scratch_directory: &scratch_directory,
+                    timestamp_frequency,
+                    logical_compaction_window,
+                    experimental_mode,
+                    safe_mode,
+                    build_info: &BUILD_INFO,
+                    metrics_registry,
+                    slow_query_log,
+                    maintenance_mode,
+                    clock,
+                    session_defaults,
+                    tail_buffer_size,
+                    tail_buffer_policy,
+                    durability,
+                    read_only,
+                    encryption_key,
+                    outbound_bind_addr,
+                    user_stats_max_users,
+                    dataflow_memory_budget,
+                    dataflow_memory_budget_enforce,
+                }));
+                let _ = boot_tx.send(res);
+            })
+            .context("spawning coordinator boot thread")?;
+    }
+    let res = startup
+        .within(config.startup_timeout, boot_rx)
+        .await?
+        .expect("coordinator boot thread unexpectedly panicked");
+
+    // Opening the catalog performed the migration, if any. If opening the
+    // catalog failed, the coordinator has closed it, and so the catalog from
+    // before the migration can be reinstalled.
+    if let Some(migration) = migration {
+        match &res {
+            Ok(_) => tokio::task::spawn_blocking(move || migration.finish()).await??,
+            Err(e) => {
+                warn!("catalog migration failed; rolling it back: {}", e);
+                tokio::task::spawn_blocking(move || migration.roll_back()).await??;
+            }
+        }
+    }
+    let (coord_handle, coord_client) = res?;
+    metrics
+        .logical_compaction_window
+        .set(match coord_client.logical_compaction_window() {
+            Some(window) => window.as_millis() as f64,
+            None => -1.0,
+        });
+
+    // Determine whether telemetry is enabled. A runtime toggle is available
+    // whenever telemetry is configured, even if the persisted preference
+    // currently disables it.
+    let telemetry = match config.telemetry {
+        None => None,
+        Some(telemetry) => {
+            let decision = telemetry::Decision::resolve(
+                telemetry::env_opt_out(),
+                telemetry::load_preference(&config.data_directory)?,
+                true,
+            );
+            info!("{}", decision);
+            // In read-only mode, the preference cannot be persisted, and so
+            // cannot be changed.
+            let data_directory = if config.read_only {
+                None
+            } else {
+                Some(config.data_directory.clone())
+            };
+            let (toggle, enabled) = telemetry::Toggle::new(decision, data_directory);
+            let spool = match &telemetry.spool_dir {
+                None => None,
+                Some(spool_dir) => Some(
+                    telemetry::Spool::open(
+                        config.data_directory.join(spool_dir),
+                        telemetry.spool_max_size,
+                    )
+                    .await?,
+                ),
+            };
+            Some((telemetry, toggle, enabled, decision, spool))
+        }
+    };
+
+    // Now that the coordinator has booted, serve connections in earnest.
+    // Connections that were accepted while the server was starting up are
+    // unaffected.
+    let connection_gate = pgwire::ConnectionGate::new();
+    let coord_health = CoordHealth::new();
+    let version_status = VersionStatus::new();
+    let source_lag = SourceLagMetrics::register_into(&metrics_registry);
+    let connection_notice = pgwire::ConnectionNotice::new();
+    if let Some(source) = config.connection_notice.clone() {
+        let cluster_id = coord_handle.cluster_id();
+        let notice = {
+            let source = source.clone();
+            tokio::task::spawn_blocking(move || source.load(cluster_id)).await??
+        };
+        connection_notice.set(notice);
+        if source.is_reloadable() {
+            let connection_notice = connection_notice.clone();
+            let mut sighup = signal(SignalKind::hangup()).context("installing SIGHUP handler")?;
+            tokio::spawn(async move {
+                while sighup.recv().await.is_some() {
+                    info!("received SIGHUP; reloading connection notice");
+                    let source = source.clone();
+                    match tokio::task::spawn_blocking(move || source.load(cluster_id)).await {
+                        Ok(Ok(notice)) => connection_notice.set(notice),
+                        Ok(Err(e)) => warn!("failed to reload connection notice: {:#}", e),
+                        Err(e) => warn!("failed to reload connection notice: {}", e),
+                    }
+                }
+            });
+        }
+    }
+    {
         let mut mux = Mux::new();
         mux.add_handler(pgwire::Server::new(pgwire::Config {
             tls: pgwire_tls,
             coord_client: coord_client.clone(),
             metrics_registry: &metrics_registry,
+            log_redaction: config.log.redaction,
+            buffers: pgwire::BufferConfig {
+                read_size: config.pgwire_read_buffer_size,
+                write_size: config.pgwire_buffer_size,
+                idle_shrink_after: config.pgwire_buffer_shrink_after,
+            },
+            max_message_size: config.pgwire_max_message_size,
+            gate: connection_gate.clone(),
+            session_limit: config.max_connections.map(|max_connections| {
+                pgwire::SessionLimit::new(
+                    max_connections,
+                    config.reserved_superuser_connections,
+                    config.superusers.clone(),
+                )
+            }),
+            draining: draining.clone(),
+            draining_rejections: metrics
+                .connections_rejected_draining
+                .with_label_values(&["pgwire"]),
+            statement_durations: coord::StatementDurations::new(
+                metrics.statement_durations.clone(),
+                config.statement_label_keys.clone(),
+            ),
+            authorizer: config.authz.clone(),
+            drain_notices: config.drain_notices,
+            connection_notice: connection_notice.clone(),
+            maintenance_mode: maintenance_mode.clone(),
+            session_quotas: session_quotas.clone(),
+            transport_monitor: transport_monitor.clone(),
+            server_name: config.server_name.clone(),
         }));
         mux.add_handler(http::Server::new(http::Config {
             tls: http_tls,
             coord_client: coord_client.clone(),
             start_time: coord_handle.start_instant(),
             metrics_registry: metrics_registry.clone(),
+            metrics_guard,
             global_metrics: metrics.clone(),
+            telemetry_toggle: telemetry
+                .as_ref()
+                .map(|(_, toggle, _, _, _)| toggle.clone()),
+            telemetry_spool: telemetry
+                .as_ref()
+                .and_then(|(_, _, _, _, spool)| spool.clone()),
+            log_filter,
+            log_file,
+            backup_metrics: backup_metrics.clone(),
+            compaction_metrics: compaction_metrics.clone(),
+            read_only: config.read_only,
+            runtime_flags,
+            scratch_directory: scratch_directory.clone(),
+            data_encryption: config.data_encryption.clone(),
+            allocator_metrics: allocator_metrics.clone(),
+            startup: startup.clone(),
+            draining: draining.clone(),
+            drain_notices: config.drain_notices,
+            coord_health: coord_health.clone(),
+            version_status: version_status.clone(),
+            source_lag: source_lag.clone(),
+            statement_durations: coord::StatementDurations::new(
+                metrics.statement_durations.clone(),
+                config.statement_label_keys.clone(),
+            ),
+            authorizer: config.authz.clone(),
+            max_concurrent_requests: config.http_max_concurrent_requests,
+            request_queue_size: config.http_request_queue_size,
+            request_queue_timeout: config.http_request_queue_timeout,
+            result_memory_limit: config.http_result_memory_limit,
+            arrow_max_batch_size: config.http_arrow_max_batch_size,
+            http2_cleartext: config.http2_cleartext,
+            http1_keep_alive_timeout: config.http1_keep_alive_timeout,
+            http1_max_requests_per_connection: config.http1_max_requests_per_connection,
+            connection_notice,
+            maintenance_mode,
+            clock,
+            session_quotas,
+            transport_monitor,
+            host_environment: Arc::new(host_environment),
+            server_name: config.server_name.clone(),
+            diagnostics: http::DiagnosticsConfig {
+                config: config_description.into(),
+                crash_directory: config.data_directory.join("crash"),
+                flight_recorder_directory: config
+                    .flight_recorder
+                    .as_ref()
+                    .map(|_| diagnostics_directory.join(flight_recorder::DIRECTORY)),
+                exclude_sql: config.diagnostics_exclude_sql,
+            },
+            connection_tracker: connection_tracker.clone(),
+            #[cfg(feature = "failpoints")]
+            fault_injection: config.fault_injection.is_some(),
         }));
-        async move {
-            // TODO(benesch): replace with `listener.incoming()` if that is
-            // restored when the `Stream` trait stabilizes.
-            let mut incoming = TcpListenerStream::new(listener);
-            mux.serve(incoming.by_ref().take_until(drain_tripwire))
-                .await;
+        mux_handle.replace(mux);
+    }
+    let self_test_client = coord_client.clone();
+
+    // Periodic background jobs share a single scheduler, so that an idle
+    // server wakes up once per scheduler tick rather than once per job. The
+    // scheduler stops when the server drains, so that its jobs do not hold
+    // the coordinator open.
+    let mut scheduler = Scheduler::new(
+        scheduler::GRANULARITY,
+        scheduler::Metrics::register_into(&metrics_registry),
+    );
+
+    let host_metrics = if config.host_metrics {
+        Some(HostMetrics::register_into(&metrics_registry))
+    } else {
+        None
+    };
+    scheduler.add_job(Job::every(
+        "introspection",
+        config.introspection_frequency,
+        {
+            let data_directory = config.data_directory.clone();
+            let catalog_directory = catalog_directory.clone();
+            let storage_directory = storage_directory.clone();
+            let scratch_directory = scratch_directory.clone();
+            move || {
+                allocator_metrics.update();
+                let metrics = metrics.clone();
+                let host_metrics = host_metrics.clone();
+                let data_directory = data_directory.clone();
+                let catalog_directory = catalog_directory.clone();
+                let storage_directory = storage_directory.clone();
+                let scratch_directory = scratch_directory.clone();
+                async move {
+                    let _ = tokio::task::spawn_blocking(move || {
+                        metrics.update_disk_usage(
+                            &data_directory,
+                            &catalog_directory,
+                            &storage_directory,
+                            &scratch_directory,
+                        );
+                        if let Some(host_metrics) = host_metrics {
+                            host_metrics.update();
+                        }
+                    })
+                    .await;
+                }
+            }
+        },
+    ));
+
+    // Measure how far source timestamping trails the wall clock on the
+    // introspection cadence.
+    scheduler.add_job(Job::every("source_lag", config.introspection_frequency, {
+        let coord_client = coord_client.clone();
+        move || {
+            let coord_client = coord_client.clone();
+            let source_lag = source_lag.clone();
+            async move {
+                source_lag.update(coord_client.source_timestamp_lags().await);
+            }
         }
-    });
+    }));
 
-    tokio::spawn({
-        let start_time = coord_handle.start_instant();
-        let frequency = config.introspection_frequency;
-        async move {
-            loop {
-                metrics.update_uptime(start_time);
-                tokio::time::sleep(frequency).await;
+    // Republish introspection data as Prometheus metrics, if requested.
+    if config.introspection_metrics {
+        if introspection_enabled {
+            let bridge = introspection_metrics::Bridge::new(
+                coord_client.clone(),
+                introspection_metrics::Metrics::register_into(&metrics_registry),
+            );
+            scheduler.add_job(bridge.into_job(config.introspection_frequency));
+        } else {
+            warn!("introspection metrics disabled: introspection is disabled");
+        }
+    }
+
+    // Compact the catalog periodically, if requested.
+    if let Some(interval) = config.catalog_compact_interval {
+        scheduler.add_job(compaction::job(
+            coord_client.clone(),
+            backup_metrics.clone(),
+            compaction_metrics,
+            interval,
+        ));
+    }
+
+    // Scan the data directory for orphaned files now that the catalog has
+    // booted, and then periodically, if requested.
+    let orphan_scan = {
+        let scanner = orphans::Scanner {
+            data_directory: config.data_directory.clone(),
+            reserved_paths,
+            retention: if config.cleanup_orphans {
+                Some(config.orphan_retention)
+            } else {
+                None
+            },
+            start_time: start_system_time,
+            metrics: orphans::Metrics::register_into(&metrics_registry),
+        };
+        let cleanup = config.cleanup_orphans;
+        let data_directory = config.data_directory.clone();
+        let coord_client = coord_client.clone();
+        move || {
+            let scanner = scanner.clone();
+            let data_directory = data_directory.clone();
+            let coord_client = coord_client.clone();
+            async move {
+                let catalog_files = coord_client.catalog_files().await;
+                let res = tokio::task::spawn_blocking(move || scanner.scan(&catalog_files)).await;
+                match res {
+                    Ok(Ok(report)) => report.log(&data_directory, cleanup),
+                    Ok(Err(e)) => warn!("scanning data directory for orphaned files: {:#}", e),
+                    Err(e) => warn!("scanning data directory for orphaned files: {}", e),
+                }
             }
         }
+    };
+    scheduler.add_job(match config.orphan_scan_interval {
+        None => Job::once("orphan_scan", orphan_scan),
+        Some(interval) => Job::every("orphan_scan", interval, orphan_scan),
     });
 
-    // Start telemetry reporting loop.
-    if let Some(telemetry) = config.telemetry {
-        let config = telemetry::Config {
-            domain: telemetry.domain,
-            interval: telemetry.interval,
-            cluster_id: coord_handle.cluster_id(),
-            coord_client,
-        };
-        tokio::spawn(async move { telemetry::report_loop(config).await });
+    // Watch memory usage on the introspection cadence, if requested.
+    if let Some(memory_watchdog) = config.memory_watchdog {
+        match memory_limit {
+            None => warn!(
+                "memory watchdog disabled: no memory limit was configured, \
+                 and the server's cgroup has no memory limit"
+            ),
+            Some(limit) => {
+                info!(
+                    "memory watchdog enabled with a limit of {}",
+                    watchdog::ByteSize(limit)
+                );
+                let watchdog = watchdog::Watchdog::new(
+                    memory_watchdog,
+                    limit,
+                    coord_client.clone(),
+                    connection_gate,
+                    diagnostics_directory.clone(),
+                    watchdog::Metrics::register_into(&metrics_registry),
+                );
+                scheduler.add_job(watchdog.into_job(config.introspection_frequency));
+            }
+        }
+    }
+
+    // Record snapshots on the introspection cadence, if requested.
+    if let Some(flight_recorder) = config.flight_recorder {
+        let directory = diagnostics_directory.join(flight_recorder::DIRECTORY);
+        info!(
+            "flight recorder enabled: recording snapshots every {:?} to {}",
+            flight_recorder.interval,
+            directory.display()
+        );
+        let recorder = flight_recorder::FlightRecorder::new(
+            flight_recorder,
+            coord_client.clone(),
+            directory,
+            introspection_enabled,
+            flight_recorder::Metrics::register_into(&metrics_registry),
+        );
+        scheduler.add_job(recorder.into_job(config.introspection_frequency));
+    }
+
+    // Return unused memory to the OS once the server goes idle, if requested.
+    if let Some(interval) = config.memory_trim_interval {
+        if cfg!(target_os = "macos") {
+            info!("memory trimming disabled: jemalloc is not used on macOS");
+        } else {
+            scheduler.add_job(allocator::idle_trim_job(
+                interval,
+                coord_client.statement_activity().clone(),
+                allocator_metrics.clone(),
+            ));
+        }
+    }
+
+    // Log TAIL statements that have been streaming for a long time, if
+    // requested.
+    if let Some(threshold) = config.long_running_tail_threshold {
+        let active_tails = coord_client.active_tails().clone();
+        scheduler.add_job(Job::every("long_running_tails", threshold / 4, move || {
+            active_tails.log_long_running(threshold);
+            future::ready(())
+        }));
+    }
+
+    // Probe the coordinator, so that a coordinator that has stopped
+    // responding fails health checks rather than silently hanging every
+    // query. If requested, report the reason to whoever awaits
+    // `Server::unresponsive_exit`, which is expected to shut the server down.
+    let (unresponsive_exit_tx, unresponsive_exit_rx) = match config.coord_liveness.exit {
+        true => {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        }
+        false => (None, None),
+    };
+    scheduler.add_job(
+        liveness::Prober::new(
+            config.coord_liveness,
+            {
+                let coord_client = coord_client.clone();
+                move || coord_client.ping().boxed()
+            },
+            coord_health,
+            liveness::Metrics::register_into(&metrics_registry),
+            unresponsive_exit_tx,
+        )
+        .into_job(),
+    );
+
+    // Replicate the catalog to the warm standby target, if requested. Like the
+    // scheduler, the replicator stops once the server begins draining, so
+    // that it does not keep the coordinator alive.
+    let replication_trigger = match config.catalog_replica {
+        Some(target) => {
+            let replicator = replication::Replicator::new(
+                target,
+                coord_client.clone(),
+                scratch_directory.clone(),
+                &metrics_registry,
+            )?;
+            let (trigger, tripwire) = oneshot::channel();
+            tokio::spawn(replicator.run(tripwire));
+            Some(trigger)
+        }
+        None => None,
+    };
+
+    // Start checking for new releases, if requested, unless the environment
+    // opted out of telemetry entirely. Checks are skipped while telemetry is
+    // disabled by preference.
+    if let Some(version_check) = config.version_check {
+        if let Some(var) = telemetry::env_opt_out() {
+            info!("version check disabled by the {} environment variable", var);
+        } else {
+            let enabled = match &telemetry {
+                Some((_, _, enabled, _, _)) => Some(enabled.clone()),
+                None if telemetry::load_preference(&config.data_directory)? == Some(false) => {
+                    info!(
+                        "version check disabled by telemetry preference stored in data directory"
+                    );
+                    None
+                }
+                // Without telemetry, there is no toggle, and so the check
+                // remains enabled.
+                None => Some(watch::channel(true).1),
+            };
+            if let Some(enabled) = enabled {
+                scheduler.add_job(version_check::check_job(
+                    version_check,
+                    version_status,
+                    version_check::Metrics::register_into(&metrics_registry),
+                    enabled,
+                ));
+            }
+        }
     }
 
+    // Start telemetry reporting, unless the environment opted out of
+    // telemetry entirely.
+    if let Some((telemetry, _, enabled, decision, spool)) = telemetry {
+        if !matches!(decision, telemetry::Decision::DisabledByEnvironment(_)) {
+            let config = telemetry::Config {
+                domain: telemetry.domain,
+                interval: telemetry.interval,
+                mode: telemetry.mode,
+                connect_timeout: telemetry.connect_timeout,
+                request_timeout: telemetry.request_timeout,
+                deterministic_splay: telemetry.deterministic_splay,
+                proxy: telemetry.proxy,
+                tags: config.instance_labels,
+                log_file: telemetry.log_file,
+                cluster_id: coord_handle.cluster_id(),
+                coord_client,
+                enabled,
+                spool,
+                metrics: telemetry::Metrics::register_into(&metrics_registry),
+            };
+            scheduler.add_job(telemetry::report_job(config));
+        }
+    }
+
+    let (scheduler_trigger, scheduler_tripwire) = oneshot::channel();
+    tokio::spawn(scheduler.run(scheduler_tripwire));
+
+    // If requested, drain the server once it has had no clients for long
+    // enough, and report the reason to whoever awaits `Server::idle_exit`,
+    // which is expected to finish shutting the server down. The task stops if
+    // the server is dropped first.
+    let idle_exit = config.idle_exit_after.map(|idle_exit_after| {
+        let (mut idle_exit_tx, idle_exit_rx) = oneshot::channel();
+        let draining = draining.clone();
+        let connection_tracker = connection_tracker.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = connection_tracker.drain_when_idle(idle_exit_after, &draining) => {
+                    let reason = format!(
+                        "idle exit: no client connections for {:?}",
+                        idle_exit_after
+                    );
+                    info!("{}", reason);
+                    let _ = idle_exit_tx.send(reason);
+                }
+                _ = idle_exit_tx.closed() => (),
+            }
+        });
+        idle_exit_rx
+    });
+
+    startup.set(Phase::Ready);
+    lifecycle::ready(config.server_name.as_deref());
+
+    let self_test_report = match config.self_test {
+        true => Some(self_test::run(self_test_client, local_addr).await),
+        false => None,
+    };
+
     Ok(Server {
         local_addr,
-        _drain_trigger: drain_trigger,
-        _coord_handle: coord_handle,
+        start_instant,
+        backup_metrics,
+        scratch_directory,
+        stop_reason: None,
+        listener_trigger: Some(listener_trigger),
+        draining,
+        scheduler_trigger: Some(scheduler_trigger),
+        replication_trigger,
+        idle_exit,
+        unresponsive_exit: unresponsive_exit_rx,
+        handed_off,
+        connection_tracker,
+        self_test_report,
+        server_name: config.server_name,
+        coord_handle: Some(coord_handle),
+        handed_off_to,
+        _scratch_tempdir: scratch_tempdir,
     })
 }
 
+/// Waits for any buffered log messages to be written to the log file.
+///
+/// Call this before exiting the process abruptly, e.g. from a panic handler.
+/// Has no effect if logging is not managed by [`serve`] or if logs are not
+/// written to a file.
+pub fn flush_logs() {
+    logging::flush()
+}
+
+/// Returns version information for selected dependencies.
+pub fn dependency_versions() -> Vec<String> {
+    let openssl_version =
+        unsafe { CStr::from_ptr(openssl_sys::OpenSSL_version(openssl_sys::OPENSSL_VERSION)) };
+    let rdkafka_version = unsafe { CStr::from_ptr(rdkafka_sys::bindings::rd_kafka_version_str()) };
+    vec![
+        openssl_version.to_string_lossy().into_owned(),
+        format!("librdkafka v{}", rdkafka_version.to_string_lossy()),
+    ]
+}
+
+/// Logs information about the build and the system.
+///
+/// In the JSON log format, each piece of information is emitted as a separate
+/// field rather than as a line of a multi-line message.
+///
+/// Information that requires scanning the system is logged separately by
+/// [`log_system_info`], as scanning the system can be slow.
+fn log_boot_banner(format: LogFormat) {
+    let mz_version = BUILD_INFO.human_version();
+    let dep_versions = dependency_versions();
+    let invocation = {
+        use shell_words::quote as escape;
+        env::vars_os()
+            .map(|(name, value)| {
+                (
+                    name.to_string_lossy().into_owned(),
+                    value.to_string_lossy().into_owned(),
+                )
+            })
+            .filter(|(name, _value)| name.starts_with("MZ_"))
+            .map(|(name, value)| format!("{}={}", escape(&name), escape(&value)))
+            .chain(env::args().into_iter().map(|arg| escape(&arg).into_owned()))
+            .join(" ")
+    };
+    let ncpus_logical = num_cpus::get();
+    let ncpus_physical = num_cpus::get_physical();
+    match format {
+        LogFormat::Text => info!(
+            "booting server
+materialized {mz_version}
+{dep_versions}
+invoked as: {invocation}
+cpus: {ncpus_logical} logical, {ncpus_physical} physical",
+            mz_version = mz_version,
+            dep_versions = dep_versions.join("\n"),
+            invocation = invocation,
+            ncpus_logical = ncpus_logical,
+            ncpus_physical = ncpus_physical,
+        ),
+        LogFormat::Json => tracing::info!(
+            version = %mz_version,
+            dependency_versions = ?dep_versions,
+            invocation = %invocation,
+            ncpus_logical,
+            ncpus_physical,
+            "booting server"
+        ),
+    }
+}
+
+/// Logs information about the system, once it has been collected.
+fn log_system_info(format: LogFormat, system: &SystemInfo) {
+    match format {
+        LogFormat::Text => info!(
+            "system information
+os: {os}
+cpu0: {cpu0}
+memory: {memory_total}KB total, {memory_used}KB used
+swap: {swap_total}KB total, {swap_used}KB used",
+            os = system.os,
+            cpu0 = system.cpu0,
+            memory_total = system.memory_total,
+            memory_used = system.memory_used,
+            swap_total = system.swap_total,
+            swap_used = system.swap_used,
+        ),
+        LogFormat::Json => tracing::info!(
+            os = %system.os,
+            cpu0 = %system.cpu0,
+            memory_total_kb = system.memory_total,
+            memory_used_kb = system.memory_used,
+            swap_total_kb = system.swap_total,
+            swap_used_kb = system.swap_used,
+            "system information"
+        ),
+    }
+}
+
 /// A running `materialized` server.
+///
+/// Dropping the server drains it and then waits for the coordinator to shut
+/// down.
 pub struct Server {
     local_addr: SocketAddr,
+    start_instant: Instant,
+    backup_metrics: backup::Metrics,
+    scratch_directory: PathBuf,
+    stop_reason: Option<String>,
+    draining: pgwire::Draining,
+    idle_exit: Option<oneshot::Receiver<String>>,
+    unresponsive_exit: Option<oneshot::Receiver<String>>,
+    handed_off: Option<oneshot::Receiver<String>>,
+    connection_tracker: ConnectionTracker,
+    self_test_report: Option<SelfTestReport>,
+    server_name: Option<String>,
     // Drop order matters for these fields.
-    _drain_trigger: oneshot::Sender<()>,
-    _coord_handle: coord::Handle,
+    listener_trigger: Option<oneshot::Sender<()>>,
+    scheduler_trigger: Option<oneshot::Sender<()>>,
+    replication_trigger: Option<oneshot::Sender<()>>,
+    coord_handle: Option<coord::Handle>,
+    // Tells the successor that took over the listener, if any, that the
+    // catalog is free once the coordinator has shut down.
+    handed_off_to: Arc<Mutex<Option<UnixStream>>>,
+    // Removes the temporary scratch directory, if any, once the coordinator
+    // has shut down.
+    _scratch_tempdir: Option<TempDir>,
 }
 
 impl Server {
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
+
+    /// Writes a backup of the catalog to `w` as a tarball, returning `w`.
+    ///
+    /// The backup is consistent, even if the catalog is concurrently
+    /// modified. Queries are blocked only while the catalog is copied, not
+    /// while the tarball is written.
+    pub async fn backup_catalog<W>(&self, w: W) -> Result<W, anyhow::Error>
+    where
+        W: Write + Send + 'static,
+    {
+        let coord_handle = self
+            .coord_handle
+            .as_ref()
+            .expect("coordinator handle present until drop");
+        let snapshot_catalog = |path| async move { coord_handle.snapshot_catalog(path).await };
+        let snapshot = backup::Snapshot::take(
+            snapshot_catalog,
+            &self.scratch_directory,
+            &self.backup_metrics,
+        )
+        .await?;
+        tokio::task::spawn_blocking(move || snapshot.write_tarball(w)).await?
+    }
+
+    /// Dumps the catalog as a sequence of SQL statements that recreates its
+    /// objects when executed against a fresh server.
+    pub async fn dump_catalog_sql(&self, options: coord::catalog::SqlDumpOptions) -> String {
+        self.coord_handle
+            .as_ref()
+            .expect("coordinator handle present until drop")
+            .dump_catalog_sql(options)
+            .await
+    }
+
+    /// Stops accepting new sessions for the specified reason.
+    ///
+    /// Existing sessions are unaffected, and clients may still cancel their
+    /// queries, whether by pgwire cancellation request or through the HTTP
+    /// API. Has no effect if the server has already been drained.
+    pub fn drain(&mut self, reason: &str) {
+        // The server may already be draining, without having been drained,
+        // if it began draining because it was idle.
+        if self.stop_reason.is_none() {
+            self.draining.start();
+            lifecycle::draining(reason, self.server_name.as_deref());
+            self.stop_reason = Some(reason.into());
+            drop(self.scheduler_trigger.take());
+            drop(self.replication_trigger.take());
+        }
+    }
+
+    /// Returns the outcome of the self test, if the server was configured to
+    /// run it.
+    pub fn self_test_report(&self) -> Option<&SelfTestReport> {
+        self.self_test_report.as_ref()
+    }
+
+    /// Waits for the server to begin draining because no client has been
+    /// connected for [`Config::idle_exit_after`], and returns the reason.
+    ///
+    /// The server is already refusing new sessions when this returns. The
+    /// caller should finish shutting the server down, e.g. via
+    /// [`Server::halt`], with the returned reason, so that the reason is
+    /// reported in the server's lifecycle events. Never returns if the
+    /// server is not configured to exit when idle.
+    pub async fn idle_exit(&mut self) -> String {
+        match &mut self.idle_exit {
+            Some(idle_exit) => match idle_exit.await {
+                Ok(reason) => reason,
+                Err(_) => future::pending().await,
+            },
+            None => future::pending().await,
+        }
+    }
+
+    /// Waits for the coordinator to stop responding, if the server is
+    /// configured to exit when it does, and returns the reason.
+    ///
+    /// See [`LivenessConfig::exit`]. The caller should shut the server down,
+    /// e.g. via [`Server::halt`], with the returned reason. Never returns if
+    /// the server is not configured to exit when the coordinator stops
+    /// responding.
+    pub async fn unresponsive_exit(&mut self) -> String {
+        match &mut self.unresponsive_exit {
+            Some(unresponsive_exit) => match unresponsive_exit.await {
+                Ok(reason) => reason,
+                Err(_) => future::pending().await,
+            },
+            None => future::pending().await,
+        }
+    }
+
+    /// Waits for a new server process to take over the server's listener,
+    /// and returns the reason.
+    ///
+    /// See [`Config::takeover_from`]. The server has stopped accepting
+    /// connections and is already refusing new sessions when this returns.
+    /// The caller should wait for the existing connections to close, via
+    /// [`Server::connections_closed`], and then shut the server down, e.g.
+    /// via [`Server::halt`], with the returned reason. Never returns if the
+    /// listener cannot be handed off.
+    pub async fn handed_off(&mut self) -> String {
+        match &mut self.handed_off {
+            Some(handed_off) => match handed_off.await {
+                Ok(reason) => reason,
+                Err(_) => future::pending().await,
+            },
+            None => future::pending().await,
+        }
+    }
+
+    /// Waits until no client connections are open.
+    pub async fn connections_closed(&self) {
+        self.connection_tracker.closed().await
+    }
+
+    /// Drains the server for the specified reason and reports that it has
+    /// stopped, without waiting for the coordinator to shut down.
+    ///
+    /// This is meant to be called just before the process exits. The
+    /// coordinator does not shut down until every client of the coordinator,
+    /// including those held by long-lived background tasks, has been dropped,
+    /// so waiting for it to shut down could block indefinitely.
+    pub fn halt(mut self, reason: &str) {
+        self.drain(reason);
+        // The coordinator's thread is abandoned rather than joined; see
+        // above.
+        mem::forget(self.coord_handle.take());
+        // Likewise the control connection of the successor that took over
+        // the listener, if any, which must not open the catalog until the
+        // abandoned coordinator is gone with the process.
+        mem::forget(Arc::clone(&self.handed_off_to));
+        lifecycle::stopped(
+            reason,
+            self.start_instant.elapsed(),
+            self.server_name.as_deref(),
+        );
+        flush_logs();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.drain("server dropped");
+        // The listener holds coordinator clients, so it must stop before the
+        // coordinator can shut down.
+        drop(self.listener_trigger.take());
+        if let Some(coord_handle) = self.coord_handle.take() {
+            drop(coord_handle);
+            let reason = self.stop_reason.as_deref().unwrap_or("server dropped");
+            lifecycle::stopped(
+                reason,
+                self.start_instant.elapsed(),
+                self.server_name.as_deref(),
+            );
+        }
+    }
 }
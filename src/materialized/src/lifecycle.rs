@@ -0,0 +1,147 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Server lifecycle events.
+//!
+//! Lifecycle events are log messages that mark the transitions of the server
+//! from starting up through shutting down. Unlike other log messages, they are
+//! a compatibility surface: they are always emitted at the `INFO` level under
+//! the `lifecycle` target, regardless of the log filter, and each carries an
+//! `event` field that names the event along with fields whose names are
+//! stable across releases. Tools that orchestrate `materialized` can watch for
+//! these events rather than parsing free-form log messages.
+//!
+//! The `server.*` events carry a `server_name` field that names the server,
+//! if it was configured with a name, so that the events of a fleet of servers
+//! can be told apart once aggregated.
+//!
+//! Adding a field to an event is a compatible change. Renaming or removing an
+//! event or a field is not.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::system::HostEnvironment;
+use crate::BUILD_INFO;
+
+/// Emits the `server.starting` event, which indicates that the server has
+/// begun to start up, in read-only mode if `read_only` is true. The event
+/// describes the host environment in which the server starts.
+pub fn starting(read_only: bool, environment: &HostEnvironment, server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.starting",
+        server_name,
+        version = BUILD_INFO.version,
+        sha = BUILD_INFO.sha,
+        read_only,
+        nofile_soft_limit = %environment.nofile_soft_limit,
+        nofile_hard_limit = %environment.nofile_hard_limit,
+        address_space_soft_limit = %environment.address_space_soft_limit,
+        address_space_hard_limit = %environment.address_space_hard_limit,
+        allocator = %environment.allocator,
+        transparent_hugepages = %environment.transparent_hugepages,
+        data_directory_filesystem = %environment.data_directory_filesystem,
+        clocksource = %environment.clocksource,
+        "server starting"
+    );
+}
+
+/// Emits the `server.listening` event, which indicates that the server has
+/// bound its listener to `addr`.
+///
+/// Connections to `addr` are accepted, but until the server is ready, they
+/// are answered with an error that indicates that the server is starting up:
+/// pgwire clients receive SQLSTATE `57P03`, and HTTP clients receive a `503
+/// Service Unavailable` response that reports startup progress.
+pub fn listening(addr: SocketAddr, server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.listening",
+        server_name,
+        addr = %addr,
+        "server listening"
+    );
+}
+
+/// Emits the `server.ready` event, which indicates that the coordinator has
+/// booted and that the server is servicing connections.
+pub fn ready(server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.ready",
+        server_name,
+        "server ready"
+    );
+}
+
+/// Emits the `server.draining` event, which indicates that the server has
+/// stopped accepting new sessions for the specified reason.
+pub fn draining(reason: &str, server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.draining",
+        server_name,
+        reason,
+        "server draining"
+    );
+}
+
+/// Emits the `server.handed_off` event, which indicates that a new server
+/// process has taken over the server's listener on `addr`. The server no
+/// longer accepts connections and is draining.
+pub fn handed_off(addr: SocketAddr, server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.handed_off",
+        server_name,
+        addr = %addr,
+        "server handed off listener"
+    );
+}
+
+/// Emits the `self_test.step` event, which reports the outcome of the named
+/// step of the self test, along with how long the step took. `error` is the
+/// reason the step failed, if it did.
+pub fn self_test_step(step: &str, error: Option<&str>, duration: Duration) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "self_test.step",
+        step,
+        passed = error.is_none(),
+        error,
+        duration_ms = duration.as_millis() as u64,
+        "self test step finished"
+    );
+}
+
+/// Emits the `self_test.finished` event, which indicates that the self test
+/// has finished. `failed_step` names the step that failed, if any.
+pub fn self_test_finished(failed_step: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "self_test.finished",
+        passed = failed_step.is_none(),
+        failed_step,
+        "self test finished"
+    );
+}
+
+/// Emits the `server.stopped` event, which indicates that the server has
+/// stopped for the specified reason after running for `uptime`.
+pub fn stopped(reason: &str, uptime: Duration, server_name: Option<&str>) {
+    tracing::info!(
+        target: "lifecycle",
+        event = "server.stopped",
+        server_name,
+        reason,
+        uptime_ms = uptime.as_millis() as u64,
+        "server stopped"
+    );
+}
@@ -0,0 +1,367 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Detection of an unresponsive coordinator.
+//!
+//! A coordinator that deadlocks leaves the server looking healthy: the
+//! listener accepts connections and the metrics endpoint serves metrics, but
+//! every query hangs. To detect this, the server periodically probes the
+//! coordinator with a command that does nothing and waits a bounded time for
+//! the coordinator to process it. After enough consecutive probes time out,
+//! the coordinator is considered unresponsive: the `/api/status` endpoint
+//! reports the server as unhealthy, the threads of the process are logged,
+//! and, if so configured, the server exits so that its supervisor can
+//! restart it.
+//!
+//! Only one probe is outstanding at a time. A probe that times out stays in
+//! the coordinator's queue, and the next run waits on it rather than queueing
+//! another behind the stuck work.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::bail;
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+use tokio::sync::oneshot;
+use tokio::time;
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounter, UIntCounterVec, UIntGauge};
+
+use crate::scheduler::Job;
+
+/// Configures the probing of the coordinator.
+#[derive(Debug, Clone)]
+pub struct LivenessConfig {
+    /// How often to probe the coordinator.
+    pub probe_interval: Duration,
+    /// How long to wait for the coordinator to answer a probe.
+    pub probe_timeout: Duration,
+    /// The number of consecutive probes that must time out before the
+    /// coordinator is considered unresponsive.
+    pub max_failures: usize,
+    /// Whether to exit once the coordinator is unresponsive.
+    pub exit: bool,
+}
+
+impl LivenessConfig {
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.probe_interval == Duration::from_secs(0) {
+            bail!("coordinator probe interval must be positive");
+        }
+        if self.probe_timeout == Duration::from_secs(0) {
+            bail!("coordinator probe timeout must be positive");
+        }
+        if self.max_failures == 0 {
+            bail!("coordinator probe failures must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+impl Default for LivenessConfig {
+    fn default() -> LivenessConfig {
+        LivenessConfig {
+            probe_interval: Duration::from_secs(5),
+            probe_timeout: Duration::from_secs(10),
+            max_failures: 3,
+            exit: false,
+        }
+    }
+}
+
+/// Whether the coordinator is responsive.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CoordHealth {
+    unresponsive: Arc<AtomicBool>,
+}
+
+impl CoordHealth {
+    /// Constructs a new health state, in which the coordinator is
+    /// responsive.
+    pub fn new() -> CoordHealth {
+        CoordHealth::default()
+    }
+
+    /// Reports whether the coordinator is unresponsive.
+    pub fn is_unresponsive(&self) -> bool {
+        self.unresponsive.load(Ordering::SeqCst)
+    }
+
+    /// Records whether the coordinator is unresponsive, and returns whether
+    /// it was before.
+    fn set_unresponsive(&self, unresponsive: bool) -> bool {
+        self.unresponsive.swap(unresponsive, Ordering::SeqCst)
+    }
+}
+
+/// Coordinator probe metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    probes: UIntCounterVec,
+    unresponsive: UIntGauge,
+    unresponsive_total: UIntCounter,
+}
+
+impl Metrics {
+    /// Registers the coordinator probe metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            probes: registry.register(metric!(
+                name: "mz_coord_probes_total",
+                help: "the number of probes of the coordinator, by outcome",
+                var_labels: ["outcome"],
+            )),
+            unresponsive: registry.register(metric!(
+                name: "mz_coord_unresponsive",
+                help: "whether the coordinator is unresponsive (1) or not (0)",
+            )),
+            unresponsive_total: registry.register(metric!(
+                name: "mz_coord_unresponsive_total",
+                help: "the number of times the coordinator became unresponsive",
+            )),
+        }
+    }
+}
+
+/// Probes the coordinator on a schedule.
+pub struct Prober {
+    config: LivenessConfig,
+    probe: Box<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>,
+    health: CoordHealth,
+    metrics: Metrics,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The probe that timed out on the previous run, if any.
+    outstanding: Option<BoxFuture<'static, ()>>,
+    /// The number of consecutive probes that have timed out.
+    failures: usize,
+    /// Where to report the reason for exiting, if the server should exit
+    /// once the coordinator is unresponsive.
+    exit_tx: Option<oneshot::Sender<String>>,
+}
+
+impl Prober {
+    /// Constructs a prober that probes the coordinator by calling `probe`,
+    /// which must return a future that resolves once the coordinator has
+    /// answered.
+    ///
+    /// If `exit_tx` is present and the configuration calls for exiting, the
+    /// reason for exiting is sent to it once the coordinator is unresponsive.
+    pub fn new<F>(
+        config: LivenessConfig,
+        probe: F,
+        health: CoordHealth,
+        metrics: Metrics,
+        exit_tx: Option<oneshot::Sender<String>>,
+    ) -> Prober
+    where
+        F: Fn() -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        Prober {
+            config,
+            probe: Box::new(probe),
+            health,
+            metrics,
+            state: Mutex::new(State {
+                outstanding: None,
+                failures: 0,
+                exit_tx,
+            }),
+        }
+    }
+
+    /// Converts the prober into a job that probes the coordinator at the
+    /// configured interval.
+    pub fn into_job(self) -> Job {
+        let interval = self.config.probe_interval;
+        let prober = Arc::new(self);
+        Job::every("coord_probe", interval, move || {
+            let prober = Arc::clone(&prober);
+            async move { prober.probe().await }
+        })
+    }
+
+    /// Probes the coordinator once.
+    ///
+    /// The scheduler never runs two probes at once, so the state is not
+    /// locked while the probe is outstanding.
+    pub async fn probe(&self) {
+        let outstanding = self.state.lock().expect("lock poisoned").outstanding.take();
+        let mut probe = outstanding.unwrap_or_else(|| (self.probe)());
+        let answered = time::timeout(self.config.probe_timeout, &mut probe)
+            .await
+            .is_ok();
+
+        let mut state = self.state.lock().expect("lock poisoned");
+        if answered {
+            self.metrics.probes.with_label_values(&["ok"]).inc();
+            state.failures = 0;
+            if self.health.set_unresponsive(false) {
+                info!("coordinator is responsive again");
+                self.metrics.unresponsive.set(0);
+            }
+            return;
+        }
+
+        self.metrics.probes.with_label_values(&["timeout"]).inc();
+        state.outstanding = Some(probe);
+        state.failures += 1;
+        if state.failures < self.config.max_failures || self.health.is_unresponsive() {
+            if !self.health.is_unresponsive() {
+                warn!(
+                    "coordinator did not answer probe within {:?} ({} of {} consecutive timeouts)",
+                    self.config.probe_timeout, state.failures, self.config.max_failures
+                );
+            }
+            return;
+        }
+
+        self.health.set_unresponsive(true);
+        self.metrics.unresponsive.set(1);
+        self.metrics.unresponsive_total.inc();
+        let reason = format!(
+            "coordinator unresponsive: {} consecutive probes timed out after {:?}",
+            state.failures, self.config.probe_timeout
+        );
+        match thread_diagnostics() {
+            Some(threads) => error!("{}; threads:\n{}", reason, threads),
+            None => error!("{}", reason),
+        }
+        if self.config.exit {
+            if let Some(exit_tx) = state.exit_tx.take() {
+                let _ = exit_tx.send(reason);
+            }
+        }
+    }
+}
+
+/// Describes the state of each thread in the process, as best the platform
+/// allows.
+#[cfg(target_os = "linux")]
+fn thread_diagnostics() -> Option<String> {
+    use std::fmt::Write;
+    use std::fs;
+
+    let mut out = String::new();
+    for task in fs::read_dir("/proc/self/task").ok()?.filter_map(Result::ok) {
+        let path = task.path();
+        let read = |name: &str| fs::read_to_string(path.join(name)).ok();
+        let tid = task.file_name().to_string_lossy().into_owned();
+        let name = read("comm").unwrap_or_default();
+        // The state follows the parenthesized thread name, which may itself
+        // contain spaces and parentheses.
+        let state = read("stat")
+            .and_then(|stat| {
+                let (_, rest) = stat.rsplit_once(')')?;
+                rest.split_whitespace().next().map(str::to_owned)
+            })
+            .unwrap_or_default();
+        let wchan = read("wchan").unwrap_or_default();
+        let wchan = match wchan.trim() {
+            "" | "0" => "-",
+            wchan => wchan,
+        };
+        let _ = writeln!(
+            out,
+            "  {} {}: state {}, waiting in {}",
+            tid,
+            name.trim(),
+            state,
+            wchan
+        );
+        // The kernel stack is readable only with elevated privileges.
+        if let Some(stack) = read("stack") {
+            for frame in stack.lines() {
+                let _ = writeln!(out, "    {}", frame);
+            }
+        }
+    }
+    Some(out)
+}
+
+/// Describes the state of each thread in the process, as best the platform
+/// allows.
+///
+/// Threads cannot be inspected on this platform.
+#[cfg(not(target_os = "linux"))]
+fn thread_diagnostics() -> Option<String> {
+    log::debug!("thread diagnostics are only supported on Linux");
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::future::{self, FutureExt};
+    use tokio::runtime::Runtime;
+    use tokio::sync::oneshot;
+
+    use ore::metrics::MetricsRegistry;
+
+    use super::{CoordHealth, LivenessConfig, Metrics, Prober};
+
+    #[test]
+    fn test_unresponsive() {
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let config = LivenessConfig {
+                probe_interval: Duration::from_millis(10),
+                probe_timeout: Duration::from_millis(10),
+                max_failures: 3,
+                exit: true,
+            };
+            let health = CoordHealth::new();
+            let metrics = Metrics::register_into(&MetricsRegistry::new());
+            let (exit_tx, mut exit_rx) = oneshot::channel();
+
+            // Probes never resolve, as if the coordinator were stuck.
+            let probes = Arc::new(AtomicUsize::new(0));
+            let prober = Prober::new(
+                config,
+                {
+                    let probes = Arc::clone(&probes);
+                    move || {
+                        probes.fetch_add(1, Ordering::SeqCst);
+                        future::pending().boxed()
+                    }
+                },
+                health.clone(),
+                metrics,
+                Some(exit_tx),
+            );
+
+            // The coordinator is unresponsive only after the configured
+            // number of consecutive timeouts, and only one probe is sent
+            // while it is stuck.
+            for _ in 0..2 {
+                prober.probe().await;
+                assert!(!health.is_unresponsive());
+            }
+            assert!(exit_rx.try_recv().is_err());
+            prober.probe().await;
+            assert!(health.is_unresponsive());
+            assert_eq!(probes.load(Ordering::SeqCst), 1);
+            assert!(exit_rx
+                .try_recv()
+                .unwrap()
+                .starts_with("coordinator unresponsive"));
+        });
+    }
+}
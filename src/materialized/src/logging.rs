@@ -0,0 +1,677 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Installation of the server's tracing subscriber.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context as _};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use ore::metric;
+use ore::metrics::MetricsRegistry;
+use prometheus::{IntCounter, IntCounterVec};
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::{LogConfig, LogFormat, LogTarget};
+
+mod file;
+mod otlp;
+mod recent;
+mod syslog;
+mod throttle;
+
+pub use file::LogFileHandle;
+pub use recent::query as recent_events;
+pub use recent::snapshot as recent_messages;
+pub use recent::{RecentLogQuery, DEFAULT_CAPACITY as DEFAULT_BUFFER_SIZE};
+
+use recent::RecentLayer;
+use throttle::ThrottleLayer;
+
+lazy_static! {
+    /// The handle to the log file writer, if logging to a file, so that
+    /// buffered messages can be flushed before the process exits.
+    static ref LOG_FILE: Mutex<Option<LogFileHandle>> = Mutex::new(None);
+}
+
+/// Installs a global tracing subscriber as described by `config`.
+///
+/// Returns a handle that can change the log filter while the server is
+/// running and, if logging to a file, a handle to the log file writer.
+pub fn init(
+    config: &LogConfig,
+    metrics_registry: &MetricsRegistry,
+) -> Result<(LogFilterHandle, Option<LogFileHandle>), anyhow::Error> {
+    let (env_filter, reload_handle) = reload::Layer::new(parse_filter(&config.filter)?);
+    let handle = LogFilterHandle {
+        current: Arc::new(Mutex::new(config.filter.clone())),
+        reload: Arc::new(move |filter| reload_handle.reload(filter).map_err(|e| anyhow!(e))),
+    };
+
+    let log_message_counter: IntCounterVec = metrics_registry.register(metric!(
+        name: "mz_log_message_total",
+        help: "The number of log messages produced by this materialized instance",
+        var_labels: ["severity"],
+    ));
+
+    let otlp_layer = match &config.trace_export {
+        None => None,
+        Some(trace_export) => {
+            let dropped: IntCounterVec = metrics_registry.register(metric!(
+                name: "mz_trace_spans_dropped_total",
+                help: "The number of sampled trace spans that could not be exported",
+                var_labels: ["reason"],
+            ));
+            Some(otlp::layer(trace_export, dropped)?)
+        }
+    };
+
+    let span_events = if config.span_timings {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+
+    let text = config.format == LogFormat::Text;
+    let json = config.format == LogFormat::Json;
+
+    // Repetitive messages are throttled before they reach the output layers,
+    // but not before they are counted or recorded in the ring buffer of
+    // recent events.
+    let file_handle = match &config.target {
+        LogTarget::Stderr => {
+            // Note that `Option` has an inherent `and_then` method, so
+            // `Layer::and_then` must be called explicitly.
+            let outputs = Layer::and_then(
+                text.then(|| {
+                    tracing_subscriber::fmt::layer()
+                        .with_writer(io::stderr)
+                        .with_ansi(atty::is(atty::Stream::Stderr))
+                        .with_span_events(span_events)
+                }),
+                json.then(|| JsonLayer::new(io::stderr)),
+            );
+            tracing_subscriber::registry()
+                .with(MetricsRecorderLayer::new(log_message_counter))
+                .with(env_filter)
+                .with(ThrottleLayer::new(outputs, config.throttle.clone())?)
+                .with(RecentLayer::new(config.buffer_size, config.redaction))
+                .with(otlp_layer)
+                .try_init()?;
+            None
+        }
+        LogTarget::File {
+            path,
+            stderr_level,
+            rotation,
+        } => {
+            // Logging to a file. Optionally bubble up messages of a certain
+            // severity to stderr.
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).with_context(|| {
+                    format!("creating log file directory: {}", parent.display())
+                })?;
+            }
+            let (file_writer, file_handle) = file::spawn(path.clone(), rotation.clone())?;
+            let stderr_filter = stderr_level
+                .map(LevelFilter::from)
+                .unwrap_or(LevelFilter::OFF);
+            let outputs = Layer::and_then(
+                text.then(|| {
+                    tracing_subscriber::fmt::layer()
+                        .with_ansi(false)
+                        .with_writer(file_writer.clone())
+                        .with_span_events(span_events)
+                }),
+                json.then(|| JsonLayer::new(file_writer)),
+            )
+            .and_then(FilterLayer::new(
+                text.then(|| {
+                    tracing_subscriber::fmt::layer()
+                        .with_writer(io::stderr)
+                        .with_ansi(atty::is(atty::Stream::Stderr))
+                }),
+                stderr_filter,
+            ))
+            .and_then(FilterLayer::new(
+                json.then(|| JsonLayer::new(io::stderr)),
+                stderr_filter,
+            ));
+            tracing_subscriber::registry()
+                .with(MetricsRecorderLayer::new(log_message_counter))
+                .with(env_filter)
+                .with(ThrottleLayer::new(outputs, config.throttle.clone())?)
+                .with(RecentLayer::new(config.buffer_size, config.redaction))
+                .with(otlp_layer)
+                .try_init()?;
+            *LOG_FILE.lock().expect("lock poisoned") = Some(file_handle.clone());
+            Some(file_handle)
+        }
+        LogTarget::Syslog(syslog_config) => {
+            // Logging to a syslog server. Optionally bubble up messages of a
+            // certain severity to stderr.
+            let dropped: IntCounter = metrics_registry.register(metric!(
+                name: "mz_syslog_messages_dropped_total",
+                help: "The number of log messages that could not be sent to the syslog server",
+            ));
+            let stderr_filter = syslog_config
+                .stderr_level
+                .map(LevelFilter::from)
+                .unwrap_or(LevelFilter::OFF);
+            let outputs = syslog::layer(syslog_config, dropped)?
+                .and_then(FilterLayer::new(
+                    text.then(|| {
+                        tracing_subscriber::fmt::layer()
+                            .with_writer(io::stderr)
+                            .with_ansi(atty::is(atty::Stream::Stderr))
+                    }),
+                    stderr_filter,
+                ))
+                .and_then(FilterLayer::new(
+                    json.then(|| JsonLayer::new(io::stderr)),
+                    stderr_filter,
+                ));
+            tracing_subscriber::registry()
+                .with(MetricsRecorderLayer::new(log_message_counter))
+                .with(env_filter)
+                .with(ThrottleLayer::new(outputs, config.throttle.clone())?)
+                .with(RecentLayer::new(config.buffer_size, config.redaction))
+                .with(otlp_layer)
+                .try_init()?;
+            None
+        }
+    };
+
+    Ok((handle, file_handle))
+}
+
+/// Waits for any buffered log messages to be written to the log file.
+pub fn flush() {
+    if let Ok(log_file) = LOG_FILE.lock() {
+        if let Some(log_file) = &*log_file {
+            log_file.flush();
+        }
+    }
+}
+
+/// Parses a log filter, as accepted by [`LogConfig::filter`].
+fn parse_filter(filter: &str) -> Result<EnvFilter, anyhow::Error> {
+    Ok(EnvFilter::try_new(filter)
+        .with_context(|| format!("parsing log filter {:?}", filter))?
+        // Ensure panics and lifecycle events are logged, even if the user has
+        // specified otherwise.
+        .add_directive("panic=error".parse().unwrap())
+        .add_directive("lifecycle=info".parse().unwrap()))
+}
+
+/// A handle to change the filter of the installed tracing subscriber.
+#[derive(Clone)]
+pub struct LogFilterHandle {
+    current: Arc<Mutex<String>>,
+    reload: Arc<dyn Fn(EnvFilter) -> Result<(), anyhow::Error> + Send + Sync>,
+}
+
+impl LogFilterHandle {
+    /// Returns the current log filter.
+    pub fn current(&self) -> String {
+        self.current.lock().expect("lock poisoned").clone()
+    }
+
+    /// Replaces the log filter with `filter`.
+    ///
+    /// The filter uses the same syntax as [`LogConfig::filter`].
+    pub fn set(&self, filter: &str) -> Result<(), anyhow::Error> {
+        let mut current = self.current.lock().expect("lock poisoned");
+        (self.reload)(parse_filter(filter)?)?;
+        *current = filter.into();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LogFilterHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LogFilterHandle")
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+/// A tracing [`Layer`] that applies a [`LevelFilter`] to one layer only.
+///
+/// By default, tracing filter layers apply *globally*. See the ["Filtering with
+/// `Layers`"][layer-filtering] section of the tracing-subscriber docs for
+/// details. This means that you can't apply different filters to different
+/// output streams—e.g., logging all messages to a file but only warnings to
+/// stderr—as the most restrictive filter will win. This behavior is
+/// unintuitive and generally not what anyone wants.
+///
+/// A `FilterLayer` is a workaround for the desired behavior until per-layer
+/// filters are supported upstream (see [tokio-rs/tracing#508]). The idea is
+/// to wrap an inner layer and apply a level filter manually, only notifying the
+/// inner layer if the event matches the filter.
+///
+/// [layer-filtering]: https://docs.rs/tracing-subscriber/0.2.14/tracing_subscriber/layer/trait.Layer.html#filtering-with-layers
+/// [tokio-rs/tracing#508]: https://github.com/tokio-rs/tracing/pull/508
+pub struct FilterLayer<L, S> {
+    layer: L,
+    filter: LevelFilter,
+    _inner: PhantomData<S>,
+}
+
+impl<L, S> FilterLayer<L, S>
+where
+    L: Layer<S> + 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    /// Constructs a new filter layer.
+    ///
+    /// The wrapped `layer` will be notified of only those events that are
+    /// enabled by `filter`.
+    pub fn new(layer: L, filter: LevelFilter) -> FilterLayer<L, S> {
+        FilterLayer {
+            layer,
+            filter,
+            _inner: PhantomData,
+        }
+    }
+}
+
+impl<S, L> Layer<S> for FilterLayer<L, S>
+where
+    L: Layer<S> + 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.layer.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.layer.enabled(metadata, ctx)
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(id).unwrap(), ctx.clone()) {
+            self.layer.new_span(attrs, id, ctx)
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(id).unwrap(), ctx.clone()) {
+            self.layer.on_record(id, values, ctx)
+        }
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(id).unwrap(), ctx.clone()) {
+            self.layer.on_follows_from(id, follows, ctx)
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if self.filter.enabled(event.metadata(), ctx.clone()) {
+            self.layer.on_event(event, ctx)
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(id).unwrap(), ctx.clone()) {
+            self.layer.on_enter(id, ctx)
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(id).unwrap(), ctx.clone()) {
+            self.layer.on_exit(id, ctx)
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(&id).unwrap(), ctx.clone()) {
+            self.layer.on_close(id, ctx)
+        }
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        if self.filter.enabled(ctx.metadata(old).unwrap(), ctx.clone()) {
+            self.layer.on_id_change(old, new, ctx)
+        }
+    }
+}
+
+/// A tracing `Layer` that allows hooking into the reporting/filtering chain for log messages,
+/// incrementing a counter for the severity of messages reported.
+pub struct MetricsRecorderLayer<S> {
+    counter: IntCounterVec,
+    _inner: PhantomData<S>,
+}
+
+impl<S> MetricsRecorderLayer<S> {
+    /// Construct a metrics-recording layer.
+    pub fn new(counter: IntCounterVec) -> Self {
+        Self {
+            counter,
+            _inner: PhantomData,
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsRecorderLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, ev: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = ev.metadata();
+        self.counter
+            .with_label_values(&[&metadata.level().to_string()])
+            .inc();
+    }
+}
+
+/// A tracing [`Layer`] that emits each event as a single line of JSON.
+///
+/// Each line is an object with the following keys, whose names are stable
+/// across releases:
+///
+///   * `timestamp`: the time at which the event occurred, in RFC 3339 format
+///     with nanosecond precision.
+///   * `level`: the severity of the event, e.g. `INFO`.
+///   * `target`: the module path from which the event was emitted.
+///   * `message`: the event's message. Multi-line messages, like backtraces,
+///     are escaped within the string.
+///   * `fields`: the structured key–value pairs attached to the event and to
+///     each of its enclosing spans. The event's own fields take precedence,
+///     followed by those of the innermost span.
+///   * `spans`: the names of the enclosing spans, from outermost to innermost.
+pub struct JsonLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W>
+where
+    W: MakeWriter + 'static,
+{
+    /// Constructs a new JSON layer that writes to the writers produced by
+    /// `make_writer`.
+    pub fn new(make_writer: W) -> JsonLayer<W> {
+        JsonLayer { make_writer }
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: MakeWriter + 'static,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        record_span_fields(attrs, id, &ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        record_span_values(id, values, &ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut line = serde_json::to_vec(&event_json(event, &ctx, SystemTime::now()))
+            .expect("JSON serialization cannot fail");
+        line.push(b'\n');
+        // There is nowhere to report a failure to write a log message.
+        let _ = self.make_writer.make_writer().write_all(&line);
+    }
+}
+
+/// Records the fields of a new span, for inclusion in the JSON rendering of
+/// the events within it.
+///
+/// Multiple layers may record the same span's fields. Only the first to see
+/// the span attaches the fields to it.
+fn record_span_fields<S>(attrs: &Attributes<'_>, id: &Id, ctx: &Context<'_, S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let span = ctx.span(id).expect("span must exist");
+    let mut extensions = span.extensions_mut();
+    if extensions.get_mut::<JsonFields>().is_none() {
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        extensions.insert(fields);
+    }
+}
+
+/// Records values that were added to an existing span.
+fn record_span_values<S>(id: &Id, values: &Record<'_>, ctx: &Context<'_, S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let span = ctx.span(id).expect("span must exist");
+    let mut extensions = span.extensions_mut();
+    if let Some(fields) = extensions.get_mut::<JsonFields>() {
+        values.record(fields);
+    }
+}
+
+/// Renders `event`, which occurred at `time`, as a JSON object with the keys
+/// described by [`JsonLayer`].
+fn event_json<S>(event: &Event<'_>, ctx: &Context<'_, S>, time: SystemTime) -> Value
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut ancestry = vec![];
+    let mut span = if event.is_root() {
+        None
+    } else if let Some(id) = event.parent() {
+        ctx.span(id)
+    } else {
+        ctx.lookup_current()
+    };
+    while let Some(s) = span {
+        span = s.parent();
+        ancestry.push(s);
+    }
+
+    let mut fields = Map::new();
+    let mut spans = vec![];
+    for span in ancestry.iter().rev() {
+        spans.push(Value::from(span.name()));
+        if let Some(span_fields) = span.extensions().get::<JsonFields>() {
+            fields.extend(span_fields.fields.clone());
+        }
+    }
+    let mut event_fields = JsonFields::default();
+    event.record(&mut event_fields);
+    fields.extend(event_fields.fields);
+
+    let metadata = event.metadata();
+    json!({
+        "timestamp": DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Nanos, true),
+        "level": metadata.level().to_string(),
+        "target": event_fields.log_target.as_deref().unwrap_or_else(|| metadata.target()),
+        "message": event_fields.message.unwrap_or_default(),
+        "fields": fields,
+        "spans": spans,
+    })
+}
+
+/// The fields recorded on a span or event, as collected by [`JsonLayer`].
+#[derive(Default)]
+struct JsonFields {
+    message: Option<String>,
+    /// The target of an event forwarded from the `log` crate, whose metadata
+    /// target is otherwise always "log".
+    log_target: Option<String>,
+    fields: Map<String, Value>,
+}
+
+impl JsonFields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        match field.name() {
+            "message" => {
+                self.message = Some(match value {
+                    Value::String(s) => s,
+                    value => value.to_string(),
+                })
+            }
+            "log.target" => {
+                if let Value::String(s) = value {
+                    self.log_target = Some(s);
+                }
+            }
+            // Skip the remaining metadata fields forwarded from the `log`
+            // crate, like `log.file` and `log.line`.
+            name if name.starts_with("log.") => (),
+            name => {
+                self.fields.insert(name.into(), value);
+            }
+        }
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::{parse_filter, JsonLayer, MetricsRecorderLayer};
+    use log::{error, info, warn};
+    use prometheus::{IntCounterVec, Opts, Registry};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    #[test]
+    fn increments_per_sev_counter() {
+        let counter_opts = Opts::new("test_counter", "test counter help");
+        let counter = IntCounterVec::new(counter_opts, &["severity"]).unwrap();
+        let r = Registry::new();
+        r.register(Box::new(counter.clone())).unwrap();
+        tracing_subscriber::registry()
+            .with(MetricsRecorderLayer::new(counter))
+            .init();
+
+        info!("test message");
+        (0..5).for_each(|_| warn!("a warning"));
+        error!("test error");
+        error!("test error");
+
+        let metric = r
+            .gather()
+            .into_iter()
+            .find(|fam| fam.get_name() == "test_counter")
+            .expect("Didn't find the counter we set up");
+        let mut sevs: HashMap<&str, u32> = HashMap::new();
+        for counter in metric.get_metric() {
+            let sev = counter.get_label()[0].get_value();
+            sevs.insert(sev, counter.get_counter().get_value() as u32);
+        }
+        let mut sevs: Vec<(&str, u32)> = sevs.into_iter().collect();
+        sevs.sort_by_key(|(name, _)| name.to_string());
+        assert_eq!(&[("ERROR", 2), ("INFO", 1), ("WARN", 5)][..], &sevs[..]);
+    }
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_layer_emits_stable_fields() {
+        let writer = TestWriter::default();
+        let subscriber = tracing_subscriber::registry().with(JsonLayer::new({
+            let writer = writer.clone();
+            move || writer.clone()
+        }));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("connection", conn_id = 7);
+            let _guard = span.enter();
+            tracing::warn!(user = "materialize", "first line\nsecond line");
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let event: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["target"], module_path!());
+        assert_eq!(event["message"], "first line\nsecond line");
+        assert_eq!(event["fields"]["conn_id"], 7);
+        assert_eq!(event["fields"]["user"], "materialize");
+        assert_eq!(event["spans"], serde_json::json!(["connection"]));
+        assert!(chrono::DateTime::parse_from_rfc3339(event["timestamp"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn lifecycle_events_ignore_filter() {
+        let writer = TestWriter::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(parse_filter("warn").unwrap())
+            .with(JsonLayer::new({
+                let writer = writer.clone();
+                move || writer.clone()
+            }));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("filtered");
+            crate::lifecycle::listening(([127, 0, 0, 1], 6875).into(), Some("mz-test"));
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let event: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["target"], "lifecycle");
+        assert_eq!(event["fields"]["event"], "server.listening");
+        assert_eq!(event["fields"]["addr"], "127.0.0.1:6875");
+        assert_eq!(event["fields"]["server_name"], "mz-test");
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A non-blocking log file writer with size-based rotation.
+//!
+//! Log messages are handed off to a dedicated thread over a bounded channel,
+//! so that a slow disk can never stall the threads that emit log messages. If
+//! the channel fills up, messages are dropped and counted; the count is
+//! reported in-band once the writer thread catches up. As all writes to the
+//! file happen on the writer thread, rotation never races with a concurrent
+//! write.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::LogRotationConfig;
+
+/// The number of log messages that may be buffered before new messages are
+/// dropped.
+const BUFFER_CAPACITY: usize = 8192;
+
+/// The maximum amount of time to wait for buffered log messages to be written
+/// when flushing.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The minimum interval between reports of errors writing the log file, so
+/// that a full disk does not produce a report for every log message.
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+enum Message {
+    Data(Vec<u8>),
+    Reopen,
+    Flush(Sender<()>),
+}
+
+/// Opens the log file at `path` and starts a thread that writes to it.
+///
+/// Returns a [`MakeWriter`] that enqueues log messages for the writer thread
+/// and a handle that controls the writer thread.
+pub fn spawn(
+    path: PathBuf,
+    rotation: Option<LogRotationConfig>,
+) -> Result<(LogFileWriter, LogFileHandle), anyhow::Error> {
+    let file = LogFile::open(path, rotation)?;
+    let (tx, rx) = crossbeam_channel::bounded(BUFFER_CAPACITY);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let reopen_pending = Arc::new(AtomicBool::new(false));
+    thread::Builder::new()
+        .name("log-writer".into())
+        .spawn({
+            let dropped = Arc::clone(&dropped);
+            let reopen_pending = Arc::clone(&reopen_pending);
+            move || write_loop(file, rx, dropped, reopen_pending)
+        })
+        .context("spawning log writer thread")?;
+    let writer = LogFileWriter {
+        tx: tx.clone(),
+        dropped,
+    };
+    let handle = LogFileHandle { tx, reopen_pending };
+    Ok((writer, handle))
+}
+
+fn write_loop(
+    mut file: LogFile,
+    rx: Receiver<Message>,
+    dropped: Arc<AtomicU64>,
+    reopen_pending: Arc<AtomicBool>,
+) {
+    for msg in rx.iter() {
+        match msg {
+            Message::Data(buf) => file.write(&buf),
+            Message::Reopen => file.reopen(),
+            Message::Flush(ack) => {
+                file.flush();
+                let _ = ack.send(());
+            }
+        }
+        if reopen_pending.swap(false, Ordering::SeqCst) {
+            file.reopen();
+        }
+        if rx.is_empty() {
+            file.flush();
+            // Now that the buffer has drained, report any messages that were
+            // dropped while it was full. The report itself travels through
+            // the buffer like any other message, so it respects the
+            // configured format.
+            let n = dropped.swap(0, Ordering::Relaxed);
+            if n > 0 {
+                tracing::warn!(
+                    dropped = n,
+                    "dropped {} log messages because the log buffer was full",
+                    n
+                );
+            }
+        }
+    }
+    file.flush();
+}
+
+/// A [`MakeWriter`] for the log file.
+///
+/// Writes never block. If the writer thread has fallen too far behind, the
+/// message is dropped.
+#[derive(Clone)]
+pub struct LogFileWriter {
+    tx: Sender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MakeWriter for LogFileWriter {
+    type Writer = LogFileWriter;
+
+    fn make_writer(&self) -> LogFileWriter {
+        self.clone()
+    }
+}
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Each call carries exactly one formatted log message, so messages
+        // are dropped whole rather than torn.
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(Message::Data(buf.to_vec())) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A handle to the log file writer thread.
+#[derive(Debug, Clone)]
+pub struct LogFileHandle {
+    tx: Sender<Message>,
+    /// Set if a reopen request could not be enqueued because the buffer was
+    /// full.
+    reopen_pending: Arc<AtomicBool>,
+}
+
+impl LogFileHandle {
+    /// Asks the writer thread to close and reopen the log file.
+    ///
+    /// This allows external tools like logrotate to move the log file out of
+    /// the way.
+    ///
+    /// Messages enqueued before the request are written to the old file.
+    /// Never blocks.
+    pub fn reopen(&self) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(Message::Reopen) {
+            self.reopen_pending.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Waits for all log messages buffered so far to be written to the log
+    /// file.
+    ///
+    /// Gives up after a few seconds if the writer thread is not making
+    /// progress.
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        if self
+            .tx
+            .send_timeout(Message::Flush(ack_tx), FLUSH_TIMEOUT)
+            .is_ok()
+        {
+            let _ = ack_rx.recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+/// The state of the log file, as owned by the writer thread.
+struct LogFile {
+    path: PathBuf,
+    rotation: Option<LogRotationConfig>,
+    file: BufWriter<File>,
+    size: u64,
+    /// When an error was last reported, if ever.
+    last_error_report: Option<Instant>,
+    /// The number of errors that occurred since then, but were not reported.
+    suppressed_errors: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, rotation: Option<LogRotationConfig>) -> Result<LogFile, anyhow::Error> {
+        let (file, size) = open_append(&path)?;
+        Ok(LogFile {
+            path,
+            rotation,
+            file,
+            size,
+            last_error_report: None,
+            suppressed_errors: 0,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        if let Some(rotation) = &self.rotation {
+            if self.size > 0 && self.size + buf.len() as u64 > rotation.max_size {
+                if let Err(e) = self.rotate() {
+                    self.report_error(format_args!("rotating log file: {:#}", e));
+                }
+            }
+        }
+        match self.file.write_all(buf) {
+            Ok(()) => self.size += buf.len() as u64,
+            Err(e) => self.report_error(format_args!("writing log file: {}", e)),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.file.flush() {
+            self.report_error(format_args!("writing log file: {}", e));
+        }
+    }
+
+    fn reopen(&mut self) {
+        self.flush();
+        match open_append(&self.path) {
+            Ok((file, size)) => {
+                self.file = file;
+                self.size = size;
+            }
+            Err(e) => self.report_error(format_args!("{:#}", e)),
+        }
+    }
+
+    /// Reports an error to stderr, as there is no log to report it to, at
+    /// most once per [`ERROR_REPORT_INTERVAL`].
+    ///
+    /// The report is written with `writeln!` rather than `eprintln!`, which
+    /// panics if stderr is closed, as it often is when the server runs as a
+    /// daemon.
+    fn report_error(&mut self, error: fmt::Arguments) {
+        let now = Instant::now();
+        if let Some(last) = self.last_error_report {
+            if now.duration_since(last) < ERROR_REPORT_INTERVAL {
+                self.suppressed_errors += 1;
+                return;
+            }
+        }
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "materialized: {}", error);
+        if self.suppressed_errors > 0 {
+            let _ = write!(
+                stderr,
+                " ({} more errors suppressed since the last report)",
+                self.suppressed_errors
+            );
+        }
+        let _ = writeln!(stderr);
+        self.last_error_report = Some(now);
+        self.suppressed_errors = 0;
+    }
+
+    /// Moves the current log file to `PATH.1`, shifting any existing rotated
+    /// files up by one and discarding the oldest, and then opens a fresh log
+    /// file.
+    fn rotate(&mut self) -> Result<(), anyhow::Error> {
+        self.flush();
+        let rotation = self.rotation.as_ref().expect("rotation configured");
+        let rotated_path = |i| {
+            let mut path = OsString::from(&self.path);
+            path.push(format!(".{}", i));
+            if rotation.compress {
+                path.push(".gz");
+            }
+            PathBuf::from(path)
+        };
+
+        if rotation.max_files == 0 {
+            fs::remove_file(&self.path)
+                .with_context(|| format!("removing {}", self.path.display()))?;
+        } else {
+            let oldest = rotated_path(rotation.max_files);
+            if oldest.exists() {
+                fs::remove_file(&oldest)
+                    .with_context(|| format!("removing {}", oldest.display()))?;
+            }
+            for i in (1..rotation.max_files).rev() {
+                let from = rotated_path(i);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(i + 1))
+                        .with_context(|| format!("renaming {}", from.display()))?;
+                }
+            }
+            if rotation.compress {
+                compress(&self.path, &rotated_path(1))?;
+                fs::remove_file(&self.path)
+                    .with_context(|| format!("removing {}", self.path.display()))?;
+            } else {
+                fs::rename(&self.path, rotated_path(1))
+                    .with_context(|| format!("renaming {}", self.path.display()))?;
+            }
+        }
+
+        let (file, size) = open_append(&self.path)?;
+        self.file = file;
+        self.size = size;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<(BufWriter<File>, u64), anyhow::Error> {
+    let file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("opening log file: {}", path.display()))?;
+    let size = file
+        .metadata()
+        .with_context(|| format!("opening log file: {}", path.display()))?
+        .len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn compress(from: &Path, to: &Path) -> Result<(), anyhow::Error> {
+    let mut input = File::open(from).with_context(|| format!("opening {}", from.display()))?;
+    let output = File::create(to).with_context(|| format!("creating {}", to.display()))?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)
+        .with_context(|| format!("compressing {}", from.display()))?;
+    encoder
+        .finish()
+        .with_context(|| format!("compressing {}", from.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("materialized.log");
+        let (mut writer, handle) = spawn(
+            path.clone(),
+            Some(LogRotationConfig {
+                max_size: 10,
+                max_files: 2,
+                compress: false,
+            }),
+        )?;
+        for line in &["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            writer.write_all(line.as_bytes())?;
+        }
+        handle.flush();
+
+        assert_eq!(fs::read_to_string(&path)?, "dddddddd\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("materialized.log.1"))?,
+            "cccccccc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("materialized.log.2"))?,
+            "bbbbbbbb\n"
+        );
+        assert!(!dir.path().join("materialized.log.3").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("materialized.log");
+        let moved = dir.path().join("moved.log");
+        let (mut writer, handle) = spawn(path.clone(), None)?;
+        writer.write_all(b"before\n")?;
+        handle.flush();
+        fs::rename(&path, &moved)?;
+        handle.reopen();
+        handle.flush();
+        writer.write_all(b"after\n")?;
+        handle.flush();
+
+        assert_eq!(fs::read_to_string(&moved)?, "before\n");
+        assert_eq!(fs::read_to_string(&path)?, "after\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_reports_are_rate_limited() -> Result<(), anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        let mut file = LogFile::open(dir.path().join("materialized.log"), None)?;
+        file.report_error(format_args!("first"));
+        let first_report = file.last_error_report;
+        assert!(first_report.is_some());
+        for _ in 0..3 {
+            file.report_error(format_args!("suppressed"));
+        }
+        assert_eq!(file.last_error_report, first_report);
+        assert_eq!(file.suppressed_errors, 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Export of tracing spans via the OpenTelemetry protocol (OTLP).
+//!
+//! Spans are encoded using the JSON encoding of OTLP and posted to the
+//! `/v1/traces` path of the configured endpoint by a background task. The
+//! sampling decision is made once per trace, when its root span is created:
+//! traces that originate in this process are sampled according to the
+//! configured ratio, while traces that originate with a client, as indicated
+//! by a `traceparent` field on a span, follow the client's decision.
+//!
+//! Export is best effort. If the export queue is full or the endpoint cannot
+//! be reached, spans are dropped and counted rather than allowed to slow down
+//! the operations they describe.
+
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context as _};
+use futures::FutureExt;
+use prometheus::IntCounterVec;
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tokio::time;
+use tracing::span::{Attributes, Record};
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+use ore::trace::TraceParent;
+
+use super::JsonFields;
+use crate::{TraceExportConfig, BUILD_INFO};
+
+/// The number of finished spans that may be queued for export before new
+/// spans are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// The maximum number of spans to export in one request.
+const MAX_BATCH_SIZE: usize = 512;
+
+/// How long to wait for more spans to arrive before exporting a batch.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum amount of time to wait for the endpoint to accept a batch.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The targets whose spans are exported.
+///
+/// Spans from dependencies, like the HTTP client that performs the export, are
+/// deliberately excluded.
+const EXPORTED_TARGETS: &[&str] = &["coord", "materialized", "pgwire"];
+
+/// The OTLP span kind for spans that handle a request from a remote client.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// The OTLP span kind for spans that describe an internal operation.
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// Constructs a layer that exports spans as described by `config`, and starts
+/// the task that performs the export.
+///
+/// Spans that cannot be exported are counted in `dropped`, labeled by reason.
+///
+/// Must be called from within a Tokio runtime.
+pub fn layer(
+    config: &TraceExportConfig,
+    dropped: IntCounterVec,
+) -> Result<OtlpLayer, anyhow::Error> {
+    if !(0.0..=1.0).contains(&config.sample_ratio) {
+        bail!(
+            "trace sample ratio must be between 0 and 1, got {}",
+            config.sample_ratio
+        );
+    }
+    let url = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+    let url = reqwest::Url::parse(&url)
+        .with_context(|| format!("invalid trace export endpoint: {}", config.endpoint))?;
+    let client = http_util::reqwest::client_builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()?;
+
+    let mut resource_attributes = vec![
+        attribute("service.name", "materialized".into()),
+        attribute("service.version", BUILD_INFO.version.into()),
+    ];
+    for (key, value) in &config.resource_attributes {
+        resource_attributes.push(attribute(key, value.clone().into()));
+    }
+    let resource = json!({ "attributes": resource_attributes });
+
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(export(rx, client, url, resource, dropped.clone()));
+    Ok(OtlpLayer {
+        tx,
+        sample_ratio: config.sample_ratio,
+        dropped,
+    })
+}
+
+async fn export(
+    mut rx: mpsc::Receiver<Value>,
+    client: reqwest::Client,
+    url: reqwest::Url,
+    resource: Value,
+    dropped: IntCounterVec,
+) {
+    while let Some(span) = rx.recv().await {
+        time::sleep(BATCH_DELAY).await;
+        let mut spans = vec![span];
+        while spans.len() < MAX_BATCH_SIZE {
+            match rx.recv().now_or_never() {
+                Some(Some(span)) => spans.push(span),
+                _ => break,
+            }
+        }
+        let n = spans.len();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{
+                    "scope": {
+                        "name": "materialized",
+                        "version": BUILD_INFO.version,
+                    },
+                    "spans": spans,
+                }],
+            }],
+        });
+        let res = client
+            .post(url.clone())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        // Failures are deliberately not logged, as a misconfigured or
+        // unavailable endpoint would otherwise flood the log.
+        if res.is_err() {
+            dropped
+                .with_label_values(&["export_failed"])
+                .inc_by(u64::try_from(n).unwrap_or(u64::MAX));
+        }
+    }
+}
+
+/// A tracing [`Layer`] that exports sampled spans via OTLP.
+pub struct OtlpLayer {
+    tx: mpsc::Sender<Value>,
+    sample_ratio: f64,
+    dropped: IntCounterVec,
+}
+
+/// The trace context of a span, as stored in the span's extensions.
+struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    /// Whether the parent span belongs to another process.
+    remote_parent: bool,
+    sampled: bool,
+    start: SystemTime,
+    attributes: JsonFields,
+}
+
+impl SpanContext {
+    /// Adopts the trace context described by a `traceparent` field, if
+    /// present and valid.
+    fn adopt_traceparent(&mut self) {
+        let traceparent = match self.attributes.fields.get("traceparent") {
+            Some(Value::String(traceparent)) => traceparent.parse::<TraceParent>().ok(),
+            _ => None,
+        };
+        if let Some(traceparent) = traceparent {
+            self.trace_id = traceparent.trace_id;
+            self.parent_id = Some(traceparent.parent_id);
+            self.remote_parent = true;
+            self.sampled = traceparent.sampled;
+        }
+    }
+}
+
+impl OtlpLayer {
+    /// Finds the trace context of the nearest exported ancestor of a span.
+    fn parent_context<S>(span: Option<SpanRef<S>>) -> Option<(u128, u64, bool)>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut span = span;
+        while let Some(s) = span {
+            if let Some(cx) = s.extensions().get::<SpanContext>() {
+                return Some((cx.trace_id, cx.span_id, cx.sampled));
+            }
+            span = s.parent();
+        }
+        None
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        let exported = EXPORTED_TARGETS.iter().any(|target| {
+            metadata.target() == *target
+                || metadata
+                    .target()
+                    .strip_prefix(target)
+                    .map_or(false, |rest| rest.starts_with("::"))
+        });
+        if !exported {
+            return;
+        }
+
+        let parent = if attrs.is_root() {
+            None
+        } else if let Some(parent) = attrs.parent() {
+            ctx.span(parent)
+        } else {
+            ctx.lookup_current()
+        };
+        let mut cx = match Self::parent_context(parent) {
+            Some((trace_id, parent_id, sampled)) => SpanContext {
+                trace_id,
+                span_id: random_id(),
+                parent_id: Some(parent_id),
+                remote_parent: false,
+                sampled,
+                start: SystemTime::now(),
+                attributes: JsonFields::default(),
+            },
+            None => SpanContext {
+                trace_id: random_id(),
+                span_id: random_id(),
+                parent_id: None,
+                remote_parent: false,
+                sampled: rand::random::<f64>() < self.sample_ratio,
+                start: SystemTime::now(),
+                attributes: JsonFields::default(),
+            },
+        };
+        attrs.record(&mut cx.attributes);
+        cx.adopt_traceparent();
+
+        let span = ctx.span(id).expect("span must exist");
+        span.extensions_mut().insert(cx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist");
+        // A `traceparent` field may be recorded after the span is created,
+        // once it has been extracted from the request.
+        let traceparent_recorded = span
+            .metadata()
+            .fields()
+            .field("traceparent")
+            .map_or(false, |field| values.contains(&field));
+        let mut extensions = span.extensions_mut();
+        if let Some(cx) = extensions.get_mut::<SpanContext>() {
+            values.record(&mut cx.attributes);
+            if traceparent_recorded {
+                cx.adopt_traceparent();
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).expect("span must exist");
+        let extensions = span.extensions();
+        let cx = match extensions.get::<SpanContext>() {
+            Some(cx) if cx.sampled => cx,
+            _ => return,
+        };
+        let kind = if cx.parent_id.is_none() || cx.remote_parent {
+            SPAN_KIND_SERVER
+        } else {
+            SPAN_KIND_INTERNAL
+        };
+        let attributes: Vec<_> = cx
+            .attributes
+            .fields
+            .iter()
+            .map(|(key, value)| attribute(key, value.clone()))
+            .collect();
+        let mut otlp_span = Map::new();
+        otlp_span.insert("traceId".into(), format!("{:032x}", cx.trace_id).into());
+        otlp_span.insert("spanId".into(), format!("{:016x}", cx.span_id).into());
+        if let Some(parent_id) = cx.parent_id {
+            otlp_span.insert("parentSpanId".into(), format!("{:016x}", parent_id).into());
+        }
+        otlp_span.insert("name".into(), span.name().into());
+        otlp_span.insert("kind".into(), kind.into());
+        otlp_span.insert("startTimeUnixNano".into(), unix_nanos(cx.start).into());
+        otlp_span.insert(
+            "endTimeUnixNano".into(),
+            unix_nanos(SystemTime::now()).into(),
+        );
+        otlp_span.insert("attributes".into(), attributes.into());
+        if self.tx.try_send(Value::Object(otlp_span)).is_err() {
+            self.dropped.with_label_values(&["queue_full"]).inc();
+        }
+    }
+}
+
+/// Encodes a key–value pair as an OTLP attribute.
+fn attribute(key: &str, value: Value) -> Value {
+    // OTLP encodes 64-bit integers as strings in JSON.
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+        Value::String(s) => json!({ "stringValue": s }),
+        value => json!({ "stringValue": value.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Generates a random, nonzero trace or span ID.
+fn random_id<T>() -> T
+where
+    T: Default + PartialEq,
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    loop {
+        let id = rand::random();
+        if id != T::default() {
+            return id;
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
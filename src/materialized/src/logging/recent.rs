@@ -0,0 +1,327 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! An in-memory ring buffer of recent log events.
+//!
+//! The buffer exists so that the most recent log events can be served over
+//! HTTP, and included in crash reports and diagnostics bundles, even if the
+//! log file itself is unavailable or the log pipeline lags behind. Events are
+//! recorded whatever the log target, and before repetitive messages are
+//! throttled.
+//!
+//! The buffer is bounded by the total size of the events that it holds, not
+//! by their number. Once it is full, the oldest events are evicted to make
+//! room for new ones.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use sql::parse::RedactionPolicy;
+
+use super::{event_json, record_span_fields, record_span_values};
+
+/// The default capacity of the buffer, in bytes.
+pub const DEFAULT_CAPACITY: usize = 4 << 20;
+
+/// The maximum size of the message of an event, in bytes. Longer messages are
+/// truncated, so that a single event cannot evict every other event.
+const MAX_MESSAGE_SIZE: usize = 16 << 10;
+
+/// The approximate size of the bookkeeping for each event, in bytes, in
+/// addition to the size of the event's rendering.
+const EVENT_OVERHEAD: usize = 64;
+
+/// The names of the fields that may hold the text of SQL statements, which
+/// are redacted before the event is recorded.
+const SQL_FIELDS: &[&str] = &["sql", "statement", "query"];
+
+lazy_static! {
+    static ref RECENT: Mutex<Buffer> = Mutex::new(Buffer {
+        events: VecDeque::new(),
+        size: 0,
+        capacity: DEFAULT_CAPACITY,
+    });
+}
+
+/// An event in the buffer.
+struct RecentEvent {
+    time: SystemTime,
+    level: Level,
+    target: String,
+    /// The event, rendered as a line of JSON in the format of
+    /// [`JsonLayer`](super::JsonLayer), without a trailing newline.
+    line: String,
+}
+
+impl RecentEvent {
+    fn size(&self) -> usize {
+        self.target.len() + self.line.len() + EVENT_OVERHEAD
+    }
+}
+
+struct Buffer {
+    events: VecDeque<RecentEvent>,
+    size: usize,
+    capacity: usize,
+}
+
+impl Buffer {
+    fn push(&mut self, event: RecentEvent) {
+        let size = event.size();
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            self.evict();
+        }
+        self.size += size;
+        self.events.push_back(event);
+    }
+
+    fn evict(&mut self) {
+        if let Some(event) = self.events.pop_front() {
+            self.size -= event.size();
+        }
+    }
+}
+
+/// A tracing [`Layer`] that records each event in the ring buffer.
+pub struct RecentLayer {
+    redaction: RedactionPolicy,
+}
+
+impl RecentLayer {
+    /// Constructs a layer that records events in a buffer of at most
+    /// `capacity` bytes, redacting the SQL text in their fields according to
+    /// `redaction`.
+    ///
+    /// Events that are already in the buffer are kept, as far as they fit.
+    pub fn new(capacity: usize, redaction: RedactionPolicy) -> RecentLayer {
+        if let Ok(mut recent) = RECENT.lock() {
+            recent.capacity = capacity;
+            while recent.size > capacity {
+                recent.evict();
+            }
+        }
+        RecentLayer { redaction }
+    }
+}
+
+impl<S> Layer<S> for RecentLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        record_span_fields(attrs, id, &ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        record_span_values(id, values, &ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let time = SystemTime::now();
+        let mut value = event_json(event, &ctx, time);
+        if let Some(Value::String(message)) = value.get_mut("message") {
+            truncate(message, MAX_MESSAGE_SIZE);
+        }
+        if let Some(Value::Object(fields)) = value.get_mut("fields") {
+            for name in SQL_FIELDS {
+                if let Some(Value::String(sql)) = fields.get_mut(*name) {
+                    *sql = self.redaction.redact(sql);
+                }
+            }
+        }
+        let target = value["target"].as_str().unwrap_or_default().to_owned();
+        let event = RecentEvent {
+            time,
+            level: *event.metadata().level(),
+            target,
+            line: value.to_string(),
+        };
+        if let Ok(mut recent) = RECENT.lock() {
+            recent.push(event);
+        }
+    }
+}
+
+/// Truncates `s` to at most `max` bytes, on a character boundary.
+fn truncate(s: &mut String, max: usize) {
+    if s.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str("...");
+}
+
+/// Selects events from the ring buffer.
+#[derive(Debug, Clone, Default)]
+pub struct RecentLogQuery {
+    /// If present, only events that occurred at or after this time are
+    /// selected.
+    pub since: Option<SystemTime>,
+    /// If present, only events that are at least as severe as this level are
+    /// selected.
+    pub level: Option<Level>,
+    /// If present, only events whose target is this module path, or is
+    /// within it, are selected.
+    pub target: Option<String>,
+}
+
+impl RecentLogQuery {
+    fn matches(&self, event: &RecentEvent) -> bool {
+        if matches!(self.since, Some(since) if event.time < since) {
+            return false;
+        }
+        // More verbose levels compare greater than less verbose levels.
+        if matches!(self.level, Some(level) if event.level > level) {
+            return false;
+        }
+        match &self.target {
+            None => true,
+            Some(target) => match event.target.strip_prefix(target.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Returns the events in the ring buffer that match `query`, oldest first,
+/// each rendered as a line of JSON with a trailing newline.
+pub fn query(query: &RecentLogQuery) -> Vec<String> {
+    match RECENT.lock() {
+        Ok(recent) => recent
+            .events
+            .iter()
+            .filter(|event| query.matches(event))
+            .map(|event| format!("{}\n", event.line))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Returns every event in the ring buffer, oldest first, each rendered as a
+/// line of text with a trailing newline.
+///
+/// Returns `None` rather than waiting if the buffer is in use, as the caller
+/// may be a panicking thread that was itself in the middle of logging.
+pub fn snapshot() -> Option<Vec<String>> {
+    let recent = RECENT.try_lock().ok()?;
+    Some(recent.events.iter().map(render_text).collect())
+}
+
+/// Renders `event` as a line of text, in the style of the text log format.
+fn render_text(event: &RecentEvent) -> String {
+    let value: Value = serde_json::from_str(&event.line).unwrap_or_default();
+    let mut text = format!(
+        "{} {:>5} {}: {}",
+        value["timestamp"].as_str().unwrap_or_default(),
+        event.level,
+        event.target,
+        value["message"].as_str().unwrap_or_default(),
+    );
+    if let Some(fields) = value["fields"].as_object() {
+        for (name, value) in fields {
+            match value {
+                Value::String(s) => write!(text, " {}={}", name, s),
+                value => write!(text, " {}={}", name, value),
+            }
+            .expect("writing to a string cannot fail");
+        }
+    }
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    fn event(target: &str, level: Level, line_size: usize) -> RecentEvent {
+        RecentEvent {
+            time: SystemTime::now(),
+            level,
+            target: target.into(),
+            line: "x".repeat(line_size),
+        }
+    }
+
+    #[test]
+    fn test_buffer_evicts_oldest() {
+        let mut buffer = Buffer {
+            events: VecDeque::new(),
+            size: 0,
+            capacity: 3 * (100 + 1 + EVENT_OVERHEAD),
+        };
+        for target in &["a", "b", "c", "d"] {
+            buffer.push(event(target, Level::INFO, 100));
+        }
+        let targets: Vec<_> = buffer.events.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, vec!["b", "c", "d"]);
+        assert!(buffer.size <= buffer.capacity);
+
+        // An event that is larger than the buffer is dropped, rather than
+        // evicting every other event.
+        buffer.push(event("e", Level::INFO, buffer.capacity));
+        assert_eq!(buffer.events.len(), 3);
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let query = RecentLogQuery {
+            since: None,
+            level: Some(Level::WARN),
+            target: Some("coord".into()),
+        };
+        assert!(query.matches(&event("coord", Level::WARN, 0)));
+        assert!(query.matches(&event("coord::sink", Level::ERROR, 0)));
+        assert!(!query.matches(&event("coord::sink", Level::INFO, 0)));
+        assert!(!query.matches(&event("coordinator", Level::ERROR, 0)));
+        assert!(!query.matches(&event("pgwire", Level::ERROR, 0)));
+    }
+
+    #[test]
+    fn test_layer_redacts_sql() {
+        let subscriber = tracing_subscriber::registry()
+            .with(RecentLayer::new(DEFAULT_CAPACITY, RedactionPolicy::Full));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(
+                target: "recent_log_test",
+                sql = "SELECT 'secret'",
+                "{}",
+                "x".repeat(MAX_MESSAGE_SIZE * 2)
+            );
+        });
+        let lines = query(&RecentLogQuery {
+            target: Some("recent_log_test".into()),
+            ..Default::default()
+        });
+        assert_eq!(lines.len(), 1);
+        let event: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["fields"]["sql"], "<redacted>");
+        assert!(event["message"].as_str().unwrap().len() <= MAX_MESSAGE_SIZE + 3);
+    }
+}
@@ -0,0 +1,495 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Emission of log messages to a syslog server.
+//!
+//! Log messages are formatted according to [RFC 5424], with the fields of the
+//! event and its enclosing spans as structured data, and handed off to a
+//! dedicated thread over a bounded channel. The thread owns the connection to
+//! the syslog server and transparently reconnects if the connection is lost.
+//! While the server is unreachable, messages accumulate in the channel; once
+//! the channel is full, new messages are dropped and counted rather than
+//! allowed to block the threads that emit them.
+//!
+//! Over TCP and TLS, messages are framed using octet counting, as described
+//! in [RFC 6587] and [RFC 5425]. Over UDP, each message is sent in its own
+//! datagram, as described in [RFC 5426].
+//!
+//! [RFC 5424]: https://datatracker.ietf.org/doc/html/rfc5424
+//! [RFC 5425]: https://datatracker.ietf.org/doc/html/rfc5425
+//! [RFC 5426]: https://datatracker.ietf.org/doc/html/rfc5426
+//! [RFC 6587]: https://datatracker.ietf.org/doc/html/rfc6587#section-3.4.1
+
+use std::cmp;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context as _};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use prometheus::IntCounter;
+use serde_json::{Map, Value};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use super::JsonFields;
+use crate::{SyslogConfig, SyslogProtocol};
+
+/// The number of log messages that may be buffered before new messages are
+/// dropped.
+const BUFFER_CAPACITY: usize = 8192;
+
+/// The maximum amount of time to wait for a connection to the syslog server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum amount of time to wait for the syslog server to accept a
+/// message before considering the connection lost.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The initial and maximum delays between attempts to connect to an
+/// unreachable syslog server.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The maximum size of a message sent over UDP. Longer messages are
+/// truncated.
+const MAX_DATAGRAM_SIZE: usize = 65_000;
+
+/// The ID of the structured data element that carries an event's fields.
+///
+/// RFC 5424 requires that custom SD-IDs be qualified with a private enterprise
+/// number. 32473 is the number reserved for use in documentation, which syslog
+/// servers accept like any other.
+const STRUCTURED_DATA_ID: &str = "fields@32473";
+
+/// The maximum length of the APP-NAME header field.
+const MAX_APP_NAME_LEN: usize = 48;
+
+/// The maximum length of a structured data parameter name.
+const MAX_PARAM_NAME_LEN: usize = 32;
+
+/// Constructs a layer that emits log messages to the syslog server described
+/// by `config`, and starts the thread that sends them.
+///
+/// Messages that are dropped, either because the buffer is full or because
+/// the server rejects them, are counted in `dropped`. Connecting to the server
+/// happens in the background, so an unreachable server is not an error.
+pub fn layer(config: &SyslogConfig, dropped: IntCounter) -> Result<SyslogLayer, anyhow::Error> {
+    if config.facility > 23 {
+        bail!("invalid syslog facility: {}", config.facility);
+    }
+    if config.app_name.is_empty()
+        || config.app_name.len() > MAX_APP_NAME_LEN
+        || !config.app_name.bytes().all(is_printable)
+    {
+        bail!(
+            "syslog app name must consist of 1 to {} printable ASCII characters",
+            MAX_APP_NAME_LEN
+        );
+    }
+    let connector = Connector::new(config)?;
+    let (tx, rx) = crossbeam_channel::bounded(BUFFER_CAPACITY);
+    thread::Builder::new()
+        .name("syslog-writer".into())
+        .spawn({
+            let dropped = dropped.clone();
+            move || write_loop(connector, rx, dropped)
+        })
+        .context("spawning syslog writer thread")?;
+    Ok(SyslogLayer {
+        tx,
+        facility: config.facility,
+        hostname: hostname(),
+        app_name: config.app_name.clone(),
+        dropped,
+    })
+}
+
+fn write_loop(connector: Connector, rx: Receiver<Vec<u8>>, dropped: IntCounter) {
+    let mut conn = None;
+    let mut backoff = MIN_BACKOFF;
+    for msg in rx.iter() {
+        // A message is sent at most twice: once on the existing connection,
+        // if any, and once on a fresh connection. A message that cannot be
+        // sent even on a fresh connection is dropped, so that one bad message
+        // cannot wedge the writer.
+        let mut attempts = 0;
+        let mut sent = false;
+        while attempts < 2 && !sent {
+            if conn.is_none() {
+                match connector.connect() {
+                    Ok(c) => {
+                        conn = Some(c);
+                        backoff = MIN_BACKOFF;
+                        attempts = 1;
+                    }
+                    Err(_) => {
+                        // There is nowhere to report the error, so just wait
+                        // before trying again. Meanwhile, messages accumulate
+                        // in the channel.
+                        thread::sleep(backoff);
+                        backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+                        continue;
+                    }
+                }
+            }
+            let c = conn.as_mut().expect("connection established");
+            match c.send(&msg) {
+                Ok(()) => sent = true,
+                Err(_) => {
+                    conn = None;
+                    attempts += 1;
+                }
+            }
+        }
+        if !sent {
+            dropped.inc();
+        }
+    }
+}
+
+/// Establishes connections to the syslog server.
+struct Connector {
+    addr: String,
+    protocol: SyslogProtocol,
+    tls: Option<SslConnector>,
+}
+
+impl Connector {
+    fn new(config: &SyslogConfig) -> Result<Connector, anyhow::Error> {
+        let tls = match config.protocol {
+            SyslogProtocol::Tls => Some(SslConnector::builder(SslMethod::tls())?.build()),
+            SyslogProtocol::Udp | SyslogProtocol::Tcp => None,
+        };
+        Ok(Connector {
+            addr: config.addr.clone(),
+            protocol: config.protocol,
+            tls,
+        })
+    }
+
+    fn connect(&self) -> Result<Connection, anyhow::Error> {
+        // The address is resolved anew on each connection attempt, in case
+        // the server has moved.
+        let addrs: Vec<SocketAddr> = self.addr.to_socket_addrs()?.collect();
+        match self.protocol {
+            SyslogProtocol::Udp => {
+                let addr = match addrs.first() {
+                    Some(addr) => addr,
+                    None => bail!("syslog address {} did not resolve", self.addr),
+                };
+                let local_addr = match addr {
+                    SocketAddr::V4(_) => "0.0.0.0:0",
+                    SocketAddr::V6(_) => "[::]:0",
+                };
+                let socket = UdpSocket::bind(local_addr)?;
+                socket.connect(addr)?;
+                Ok(Connection::Udp(socket))
+            }
+            SyslogProtocol::Tcp => Ok(Connection::Tcp(self.connect_tcp(&addrs)?)),
+            SyslogProtocol::Tls => {
+                let stream = self.connect_tcp(&addrs)?;
+                let tls = self.tls.as_ref().expect("TLS connector exists");
+                let domain = self
+                    .addr
+                    .rsplit_once(':')
+                    .map_or(&*self.addr, |(host, _port)| host)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']');
+                Ok(Connection::Tls(tls.connect(domain, stream)?))
+            }
+        }
+    }
+
+    fn connect_tcp(&self, addrs: &[SocketAddr]) -> Result<TcpStream, anyhow::Error> {
+        let mut last_err = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e.into()),
+            None => bail!("syslog address {} did not resolve", self.addr),
+        }
+    }
+}
+
+/// A connection to the syslog server.
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(SslStream<TcpStream>),
+}
+
+impl Connection {
+    fn send(&mut self, msg: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Connection::Udp(socket) => {
+                let len = cmp::min(msg.len(), MAX_DATAGRAM_SIZE);
+                socket.send(&msg[..len])?;
+                Ok(())
+            }
+            Connection::Tcp(stream) => stream.write_all(&frame(msg)),
+            Connection::Tls(stream) => stream.write_all(&frame(msg)),
+        }
+    }
+}
+
+/// Frames a message for a stream transport using octet counting.
+fn frame(msg: &[u8]) -> Vec<u8> {
+    let mut buf = format!("{} ", msg.len()).into_bytes();
+    buf.extend(msg);
+    buf
+}
+
+/// A tracing [`Layer`] that emits each event to a syslog server.
+pub struct SyslogLayer {
+    tx: Sender<Vec<u8>>,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    dropped: IntCounter,
+}
+
+/// The fields recorded on a span, as collected by [`SyslogLayer`].
+///
+/// This is a distinct type from the [`JsonFields`] stored by other layers so
+/// that the layers do not clobber one another's span extensions.
+struct SyslogFields(JsonFields);
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist");
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(SyslogFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist");
+        let mut extensions = span.extensions_mut();
+        if let Some(SyslogFields(fields)) = extensions.get_mut::<SyslogFields>() {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut ancestry = vec![];
+        let mut span = if event.is_root() {
+            None
+        } else if let Some(id) = event.parent() {
+            ctx.span(id)
+        } else {
+            ctx.lookup_current()
+        };
+        while let Some(s) = span {
+            span = s.parent();
+            ancestry.push(s);
+        }
+
+        let mut fields = Map::new();
+        for span in ancestry.iter().rev() {
+            if let Some(SyslogFields(span_fields)) = span.extensions().get::<SyslogFields>() {
+                fields.extend(span_fields.fields.clone());
+            }
+        }
+        let mut event_fields = JsonFields::default();
+        event.record(&mut event_fields);
+        fields.extend(event_fields.fields);
+
+        let metadata = event.metadata();
+        let target = event_fields
+            .log_target
+            .as_deref()
+            .unwrap_or_else(|| metadata.target());
+        fields.insert("target".into(), target.into());
+
+        let msg = format_message(
+            self.facility,
+            *metadata.level(),
+            SystemTime::now(),
+            &self.hostname,
+            &self.app_name,
+            &fields,
+            event_fields.message.as_deref().unwrap_or_default(),
+        );
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(msg) {
+            self.dropped.inc();
+        }
+    }
+}
+
+/// Maps a tracing level to a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3, // Error
+        Level::WARN => 4,  // Warning
+        Level::INFO => 6,  // Informational
+        _ => 7,            // Debug
+    }
+}
+
+/// Formats an RFC 5424 syslog message.
+fn format_message(
+    facility: u8,
+    level: Level,
+    time: SystemTime,
+    hostname: &str,
+    app_name: &str,
+    fields: &Map<String, Value>,
+    message: &str,
+) -> Vec<u8> {
+    let mut msg = format!(
+        "<{}>1 {} {} {} {} - ",
+        u16::from(facility) * 8 + u16::from(severity(level)),
+        DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Micros, true),
+        hostname,
+        app_name,
+        process::id(),
+    );
+    if fields.is_empty() {
+        msg.push('-');
+    } else {
+        msg.push('[');
+        msg.push_str(STRUCTURED_DATA_ID);
+        for (name, value) in fields {
+            msg.push(' ');
+            msg.extend(
+                name.chars()
+                    .map(|c| match c {
+                        '=' | ']' | '"' => '_',
+                        c if c.is_ascii() && is_printable(c as u8) => c,
+                        _ => '_',
+                    })
+                    .take(MAX_PARAM_NAME_LEN),
+            );
+            msg.push_str("=\"");
+            let value = match value {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            for c in value.chars() {
+                if let '"' | '\\' | ']' = c {
+                    msg.push('\\');
+                }
+                msg.push(c);
+            }
+            msg.push('"');
+        }
+        msg.push(']');
+    }
+    let mut msg = msg.into_bytes();
+    if !message.is_empty() {
+        // RFC 5424 requires that a UTF-8 message begin with a byte order
+        // mark.
+        msg.extend(b" \xEF\xBB\xBF");
+        msg.extend(message.as_bytes());
+    }
+    msg
+}
+
+/// Reports whether `b` is a printable ASCII character, as required of the
+/// header fields of a syslog message.
+fn is_printable(b: u8) -> bool {
+    (33..=126).contains(&b)
+}
+
+/// Returns the name of this host, or the nil value if it is unknown.
+fn hostname() -> String {
+    let mut buf = [0; 256];
+    match nix::unistd::gethostname(&mut buf) {
+        Ok(name) => match name.to_str() {
+            Ok(name) if !name.is_empty() && name.bytes().all(is_printable) => name.into(),
+            _ => "-".into(),
+        },
+        Err(_) => "-".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    use prometheus::IntCounter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    /// Reads one octet-counted frame from `reader`.
+    fn read_frame<R>(reader: &mut R) -> String
+    where
+        R: BufRead,
+    {
+        let mut len = vec![];
+        reader.read_until(b' ', &mut len).unwrap();
+        let len: usize = String::from_utf8(len).unwrap().trim_end().parse().unwrap();
+        let mut frame = vec![0; len];
+        reader.read_exact(&mut frame).unwrap();
+        String::from_utf8(frame).unwrap()
+    }
+
+    #[test]
+    fn test_tcp_framing_and_severity() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = SyslogConfig {
+            addr: listener.local_addr().unwrap().to_string(),
+            protocol: SyslogProtocol::Tcp,
+            facility: 16, // local0
+            app_name: "materialized".into(),
+            stderr_level: None,
+        };
+        let dropped = IntCounter::new("dropped", "dropped").unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(layer(&config, dropped.clone()).unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("connection", conn_id = 7);
+            let _guard = span.enter();
+            tracing::warn!(user = "ma\"terialize", "first line\nsecond line");
+            tracing::error!("second message");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let first = read_frame(&mut reader);
+        // local0 (16) * 8 + warning (4) = 132.
+        assert!(first.starts_with("<132>1 "), "{}", first);
+        assert!(first.contains(&format!(" materialized {} - [", process::id())));
+        assert!(first.contains("conn_id=\"7\""), "{}", first);
+        assert!(first.contains("user=\"ma\\\"terialize\""), "{}", first);
+        assert!(
+            first.ends_with("] \u{feff}first line\nsecond line"),
+            "{}",
+            first
+        );
+
+        let second = read_frame(&mut reader);
+        // local0 (16) * 8 + error (3) = 131.
+        assert!(second.starts_with("<131>1 "), "{}", second);
+        assert!(second.ends_with("\u{feff}second message"), "{}", second);
+
+        assert_eq!(dropped.get(), 0);
+    }
+}
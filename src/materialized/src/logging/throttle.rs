@@ -0,0 +1,448 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Throttling of repetitive log messages.
+//!
+//! Two log messages are considered repetitions of one another if they have the
+//! same level and were emitted from the same line of source code, regardless
+//! of the values interpolated into the message. Within each window, the first
+//! few repetitions of a message are emitted and the remainder are suppressed.
+//! A background thread periodically emits a summary of the repetitions that
+//! were suppressed.
+//!
+//! Messages are tracked in a fixed-size table, so the memory used by the
+//! throttle is bounded regardless of how many distinct messages are emitted.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context as _};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::LogThrottleConfig;
+
+/// The number of distinct messages that are tracked at once.
+const SLOTS: usize = 1024;
+
+/// The maximum length of the sample message retained for each tracked message.
+const MAX_SAMPLE_LEN: usize = 256;
+
+/// A tracing [`Layer`] that suppresses repetitive events before they reach an
+/// inner layer.
+///
+/// All other notifications, like the creation of spans, are passed through to
+/// the inner layer unchanged.
+pub struct ThrottleLayer<L, S> {
+    layer: L,
+    throttle: Option<Arc<Mutex<Throttle>>>,
+    _inner: PhantomData<S>,
+}
+
+impl<L, S> ThrottleLayer<L, S>
+where
+    L: Layer<S> + 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    /// Constructs a new throttle layer.
+    ///
+    /// If `config` is present, starts the thread that emits summaries of
+    /// suppressed events. Otherwise, all events are passed through to the
+    /// wrapped `layer`.
+    pub fn new(
+        layer: L,
+        config: Option<LogThrottleConfig>,
+    ) -> Result<ThrottleLayer<L, S>, anyhow::Error> {
+        let throttle = match config {
+            None => None,
+            Some(config) => {
+                if config.window == Duration::from_secs(0) {
+                    bail!("log throttle window must be nonzero");
+                }
+                let window = config.window;
+                let throttle = Arc::new(Mutex::new(Throttle::new(config)));
+                thread::Builder::new()
+                    .name("log-throttle".into())
+                    .spawn({
+                        let throttle = Arc::clone(&throttle);
+                        move || summarize_loop(throttle, window)
+                    })
+                    .context("spawning log throttle thread")?;
+                Some(throttle)
+            }
+        };
+        Ok(ThrottleLayer {
+            layer,
+            throttle,
+            _inner: PhantomData,
+        })
+    }
+}
+
+impl<S, L> Layer<S> for ThrottleLayer<L, S>
+where
+    L: Layer<S> + 'static,
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.layer.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.layer.enabled(metadata, ctx)
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.layer.new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.layer.on_record(id, values, ctx)
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.layer.on_follows_from(id, follows, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let throttle = match &self.throttle {
+            // Summaries are never themselves throttled.
+            Some(throttle) if event.metadata().target() != module_path!() => throttle,
+            _ => return self.layer.on_event(event, ctx),
+        };
+        let mut callsite = Callsite::default();
+        event.record(&mut callsite);
+        let metadata = event.metadata();
+        let level = *metadata.level();
+        let target = callsite
+            .log_target
+            .unwrap_or_else(|| metadata.target().into());
+        let mut hasher = DefaultHasher::new();
+        level.hash(&mut hasher);
+        target.hash(&mut hasher);
+        callsite
+            .log_file
+            .as_deref()
+            .or(metadata.file())
+            .hash(&mut hasher);
+        callsite
+            .log_line
+            .or(metadata.line().map(u64::from))
+            .hash(&mut hasher);
+        let key = hasher.finish();
+
+        let emit = match throttle.lock() {
+            Ok(mut throttle) => throttle.check(key, level, Instant::now(), || {
+                let mut sample = Sample::default();
+                event.record(&mut sample);
+                (target, sample.message)
+            }),
+            Err(_) => true,
+        };
+        if emit {
+            self.layer.on_event(event, ctx)
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.layer.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.layer.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.layer.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.layer.on_id_change(old, new, ctx)
+    }
+}
+
+fn summarize_loop(throttle: Arc<Mutex<Throttle>>, window: Duration) {
+    loop {
+        thread::sleep(window);
+        let (summaries, evicted) = match throttle.lock() {
+            Ok(mut throttle) => throttle.drain(),
+            Err(_) => return,
+        };
+        // The summaries are emitted after the lock is released, as emitting
+        // them reenters the throttle.
+        for summary in summaries {
+            let Summary {
+                level,
+                target,
+                message,
+                suppressed,
+            } = summary;
+            macro_rules! emit {
+                ($macro:ident) => {
+                    tracing::$macro!(
+                        repeated = suppressed,
+                        repeated_target = %target,
+                        "message repeated {} more times in the last {:?}: {}",
+                        suppressed,
+                        window,
+                        message
+                    )
+                };
+            }
+            match level {
+                Level::ERROR => emit!(error),
+                Level::WARN => emit!(warn),
+                Level::INFO => emit!(info),
+                Level::DEBUG => emit!(debug),
+                _ => emit!(trace),
+            }
+        }
+        if evicted > 0 {
+            tracing::warn!(
+                repeated = evicted,
+                "other messages repeated {} more times in the last {:?}",
+                evicted,
+                window
+            );
+        }
+    }
+}
+
+/// The state of the throttle.
+struct Throttle {
+    config: LogThrottleConfig,
+    /// The tracked messages, indexed by the hash of their keys.
+    slots: Vec<Option<Slot>>,
+    /// The number of suppressed repetitions of messages whose slots were
+    /// taken over by other messages before they could be summarized.
+    evicted: u64,
+}
+
+/// A tracked message.
+struct Slot {
+    key: u64,
+    level: Level,
+    target: String,
+    /// The text of the first occurrence of the message.
+    message: String,
+    window_start: Instant,
+    /// The number of repetitions of the message in the current window.
+    count: u64,
+    /// The number of repetitions suppressed since the last summary.
+    suppressed: u64,
+}
+
+/// A summary of the suppressed repetitions of a message.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    level: Level,
+    target: String,
+    message: String,
+    suppressed: u64,
+}
+
+impl Throttle {
+    fn new(config: LogThrottleConfig) -> Throttle {
+        Throttle {
+            config,
+            slots: (0..SLOTS).map(|_| None).collect(),
+            evicted: 0,
+        }
+    }
+
+    /// Records an occurrence of the message identified by `key` at `now`, and
+    /// reports whether the occurrence should be emitted.
+    ///
+    /// The `describe` function is called to produce the target and text of
+    /// the message when it is not already tracked.
+    fn check<F>(&mut self, key: u64, level: Level, now: Instant, describe: F) -> bool
+    where
+        F: FnOnce() -> (String, String),
+    {
+        let budget = if level == Level::ERROR {
+            self.config.error_burst
+        } else {
+            self.config.burst
+        };
+        let i = (key % SLOTS as u64) as usize;
+        match &self.slots[i] {
+            Some(slot) if slot.key == key => (),
+            old => {
+                // Either the slot is empty or it is tracking another message,
+                // in which case that message is forgotten. Its suppressed
+                // repetitions are still reported, in aggregate.
+                if let Some(old) = old {
+                    self.evicted += old.suppressed;
+                }
+                let (target, mut message) = describe();
+                if message.len() > MAX_SAMPLE_LEN {
+                    let mut end = MAX_SAMPLE_LEN;
+                    while !message.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    message.truncate(end);
+                    message.push('…');
+                }
+                self.slots[i] = Some(Slot {
+                    key,
+                    level,
+                    target,
+                    message,
+                    window_start: now,
+                    count: 0,
+                    suppressed: 0,
+                });
+            }
+        }
+        let slot = self.slots[i].as_mut().expect("slot populated");
+        if now.duration_since(slot.window_start) >= self.config.window {
+            slot.window_start = now;
+            slot.count = 0;
+        }
+        slot.count += 1;
+        if slot.count > u64::from(budget) {
+            slot.suppressed += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Returns summaries of the messages with suppressed repetitions, along
+    /// with the number of suppressed repetitions of forgotten messages, and
+    /// resets the counts of suppressed repetitions.
+    fn drain(&mut self) -> (Vec<Summary>, u64) {
+        let mut summaries = vec![];
+        for slot in self.slots.iter_mut().flatten() {
+            if slot.suppressed > 0 {
+                summaries.push(Summary {
+                    level: slot.level,
+                    target: slot.target.clone(),
+                    message: slot.message.clone(),
+                    suppressed: slot.suppressed,
+                });
+                slot.suppressed = 0;
+            }
+        }
+        let evicted = self.evicted;
+        self.evicted = 0;
+        (summaries, evicted)
+    }
+}
+
+/// Extracts the location of an event forwarded from the `log` crate, whose
+/// metadata otherwise describes the location of the forwarding code.
+#[derive(Default)]
+struct Callsite {
+    log_target: Option<String>,
+    log_file: Option<String>,
+    log_line: Option<u64>,
+}
+
+impl Visit for Callsite {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "log.line" {
+            self.log_line = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "log.target" => self.log_target = Some(value.into()),
+            "log.file" => self.log_file = Some(value.into()),
+            _ => (),
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// Extracts the text of an event.
+#[derive(Default)]
+struct Sample {
+    message: String,
+}
+
+impl Visit for Sample {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(LogThrottleConfig {
+            window: Duration::from_secs(60),
+            burst: 2,
+            error_burst: 3,
+        });
+        let describe = || ("target".to_string(), "connection reset".to_string());
+        let start = Instant::now();
+
+        // Only the first two warnings and three errors in a window are
+        // emitted.
+        let warnings: Vec<_> = (0..5)
+            .map(|_| throttle.check(1, Level::WARN, start, describe))
+            .collect();
+        assert_eq!(warnings, vec![true, true, false, false, false]);
+        let errors: Vec<_> = (0..5)
+            .map(|_| throttle.check(2, Level::ERROR, start, describe))
+            .collect();
+        assert_eq!(errors, vec![true, true, true, false, false]);
+
+        let (mut summaries, evicted) = throttle.drain();
+        summaries.sort_by_key(|s| s.suppressed);
+        assert_eq!(
+            summaries,
+            vec![
+                Summary {
+                    level: Level::ERROR,
+                    target: "target".into(),
+                    message: "connection reset".into(),
+                    suppressed: 2,
+                },
+                Summary {
+                    level: Level::WARN,
+                    target: "target".into(),
+                    message: "connection reset".into(),
+                    suppressed: 3,
+                },
+            ]
+        );
+        assert_eq!(evicted, 0);
+        assert_eq!(throttle.drain(), (vec![], 0));
+
+        // The budget is restored in the next window.
+        let later = start + Duration::from_secs(60);
+        assert!(throttle.check(1, Level::WARN, later, describe));
+
+        // A message whose key collides with a tracked message takes over its
+        // slot, and the suppressed repetitions of the old message are
+        // reported in aggregate.
+        throttle.check(1, Level::WARN, later, describe);
+        throttle.check(1, Level::WARN, later, describe);
+        assert!(throttle.check(1 + SLOTS as u64, Level::WARN, later, describe));
+        assert_eq!(throttle.drain(), (vec![], 1));
+    }
+}
@@ -0,0 +1,399 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Guardrails on the metrics that the server exports.
+//!
+//! A metric whose labels take an unbounded number of values, e.g., because of
+//! a bug that labels a per-source metric with a value that changes over time,
+//! can grow the scrape payload until Prometheus fails the scrape, taking every
+//! other metric down with it. The guardrails are applied to the metrics as
+//! they are gathered for export:
+//!
+//!   * An optional [`MetricsFilter`] drops labels from every series. Series
+//!     whose remaining labels are identical are aggregated into one.
+//!   * An optional limit caps the number of series in each metric family.
+//!     Label sets that are seen once the limit is reached are aggregated into
+//!     a single series labeled `overflow="true"`, and a warning is logged.
+//!
+//! To keep the guardrails' own memory bounded, at most the limit's worth of
+//! label sets are tracked per family, and label sets that disappear from a
+//! family, e.g., because their series were deleted, stop being tracked.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+
+use ore::cast::CastFrom;
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntGaugeVec};
+
+/// The name of the label that marks the series into which a family's excess
+/// series are aggregated.
+pub const OVERFLOW_LABEL: &str = "overflow";
+
+/// Filters the labels of the metrics that the server exports.
+///
+/// Series whose remaining labels are identical are aggregated into one: the
+/// values of counters, gauges, and untyped metrics are summed, as are the
+/// buckets, counts, and sums of histograms, and the counts and sums of
+/// summaries. Quantiles of aggregated summaries are dropped, as they cannot
+/// be aggregated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsFilter {
+    /// Removes the named labels from every series.
+    Drop(BTreeSet<String>),
+    /// Removes every label but the named labels from every series.
+    Keep(BTreeSet<String>),
+}
+
+impl MetricsFilter {
+    fn retains(&self, label: &str) -> bool {
+        match self {
+            _ if label == OVERFLOW_LABEL => true,
+            MetricsFilter::Drop(labels) => !labels.contains(label),
+            MetricsFilter::Keep(labels) => labels.contains(label),
+        }
+    }
+}
+
+/// The labels of a series, as name–value pairs in order.
+type LabelSet = Vec<(String, String)>;
+
+#[derive(Debug, Default)]
+struct GuardState {
+    /// The label sets that have been admitted to each family, in order to
+    /// decide which label sets are new once a family reaches the limit.
+    admitted: HashMap<String, HashSet<LabelSet>>,
+    /// The families that overflowed as of the last gather.
+    overflowing: HashSet<String>,
+}
+
+/// Applies the guardrails to gathered metrics.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct MetricsGuard {
+    filter: Option<MetricsFilter>,
+    max_series: Option<usize>,
+    state: Arc<Mutex<GuardState>>,
+    /// The number of series aggregated into the overflow series of each
+    /// family, as of the last gather.
+    overflowed: UIntGaugeVec,
+}
+
+impl MetricsGuard {
+    /// Constructs guardrails that apply `filter` and limit each family to
+    /// `max_series` series, and registers their metrics into `registry`.
+    pub fn register_into(
+        registry: &MetricsRegistry,
+        filter: Option<MetricsFilter>,
+        max_series: Option<usize>,
+    ) -> MetricsGuard {
+        MetricsGuard {
+            filter,
+            max_series,
+            state: Arc::new(Mutex::new(GuardState::default())),
+            overflowed: registry.register(metric!(
+                name: "mz_metrics_overflowed_series",
+                help: "The number of series of a metric family that were aggregated into its overflow series as of the last scrape",
+                var_labels: ["family"],
+            )),
+        }
+    }
+
+    /// Applies the guardrails to `families`.
+    pub fn apply(&self, mut families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+        if let Some(filter) = &self.filter {
+            for family in &mut families {
+                filter_family(family, filter);
+            }
+        }
+        if let Some(max_series) = self.max_series {
+            let mut state = self.state.lock().expect("lock poisoned");
+            let GuardState {
+                admitted,
+                overflowing,
+            } = &mut *state;
+            // Stop tracking families that are no longer exported.
+            let names: HashSet<String> = families.iter().map(|f| f.get_name().to_owned()).collect();
+            admitted.retain(|name, _| names.contains(name));
+            overflowing.retain(|name| {
+                let exported = names.contains(name);
+                if !exported {
+                    let _ = self.overflowed.remove_label_values(&[name.as_str()]);
+                }
+                exported
+            });
+            for family in &mut families {
+                let name = family.get_name().to_owned();
+                let admitted = admitted.entry(name.clone()).or_default();
+                let overflowed = limit_family(family, admitted, max_series);
+                if overflowed == 0 {
+                    if overflowing.remove(&name) {
+                        let _ = self.overflowed.remove_label_values(&[&name]);
+                        info!(
+                            "metric {} no longer exceeds the limit of {} series",
+                            name, max_series
+                        );
+                    }
+                    continue;
+                }
+                if overflowing.insert(name.clone()) {
+                    warn!(
+                        "metric {} exceeds the limit of {} series; \
+                         aggregating its new series into an overflow series",
+                        name, max_series
+                    );
+                }
+                self.overflowed
+                    .with_label_values(&[&name])
+                    .set(u64::cast_from(overflowed));
+            }
+        }
+        families
+    }
+}
+
+/// Removes the labels of the series in `family` that `filter` does not
+/// retain, and aggregates series whose remaining labels are identical.
+fn filter_family(family: &mut MetricFamily, filter: &MetricsFilter) {
+    let kind = family.get_field_type();
+    let mut series: Vec<Metric> = vec![];
+    let mut index: HashMap<LabelSet, usize> = HashMap::new();
+    for mut metric in family.take_metric().into_vec() {
+        let labels: Vec<LabelPair> = metric
+            .take_label()
+            .into_vec()
+            .into_iter()
+            .filter(|label| filter.retains(label.get_name()))
+            .collect();
+        let key = label_set(&labels);
+        metric.set_label(labels.into());
+        match index.get(&key) {
+            Some(i) => merge(&mut series[*i], &metric, kind),
+            None => {
+                index.insert(key, series.len());
+                series.push(metric);
+            }
+        }
+    }
+    family.set_metric(series.into());
+}
+
+/// Limits `family` to `max_series` series, given the label sets that were
+/// previously `admitted` to it, and returns the number of series that were
+/// aggregated into the overflow series.
+fn limit_family(
+    family: &mut MetricFamily,
+    admitted: &mut HashSet<LabelSet>,
+    max_series: usize,
+) -> usize {
+    let kind = family.get_field_type();
+    let metrics = family.take_metric().into_vec();
+    let keys: Vec<LabelSet> = metrics.iter().map(|m| label_set(m.get_label())).collect();
+
+    // Forget label sets whose series no longer exist, so that they make room
+    // for new ones.
+    let present: HashSet<&LabelSet> = keys.iter().collect();
+    admitted.retain(|key| present.contains(key));
+
+    let mut series = vec![];
+    let mut overflow: Option<Metric> = None;
+    let mut overflowed = 0;
+    for (metric, key) in metrics.into_iter().zip(keys) {
+        if admitted.contains(&key) {
+            series.push(metric);
+        } else if admitted.len() < max_series {
+            admitted.insert(key);
+            series.push(metric);
+        } else {
+            overflowed += 1;
+            match &mut overflow {
+                None => overflow = Some(metric),
+                Some(overflow) => {
+                    // Retain only the labels that all overflowed series have
+                    // in common, like instance labels.
+                    let common: Vec<LabelPair> = overflow
+                        .get_label()
+                        .iter()
+                        .filter(|label| metric.get_label().contains(label))
+                        .cloned()
+                        .collect();
+                    overflow.set_label(common.into());
+                    merge(overflow, &metric, kind);
+                }
+            }
+        }
+    }
+    if let Some(mut overflow) = overflow {
+        let mut label = LabelPair::default();
+        label.set_name(OVERFLOW_LABEL.into());
+        label.set_value("true".into());
+        overflow.mut_label().push(label);
+        series.push(overflow);
+    }
+    family.set_metric(series.into());
+    overflowed
+}
+
+fn label_set(labels: &[LabelPair]) -> LabelSet {
+    labels
+        .iter()
+        .map(|label| (label.get_name().to_owned(), label.get_value().to_owned()))
+        .collect()
+}
+
+/// Adds the value of `from` into `into`, both of which are of type `kind`.
+fn merge(into: &mut Metric, from: &Metric, kind: MetricType) {
+    match kind {
+        MetricType::COUNTER => {
+            let value = into.get_counter().get_value() + from.get_counter().get_value();
+            into.mut_counter().set_value(value);
+        }
+        MetricType::GAUGE => {
+            let value = into.get_gauge().get_value() + from.get_gauge().get_value();
+            into.mut_gauge().set_value(value);
+        }
+        MetricType::UNTYPED => {
+            let value = into.get_untyped().get_value() + from.get_untyped().get_value();
+            into.mut_untyped().set_value(value);
+        }
+        MetricType::HISTOGRAM => {
+            let from = from.get_histogram();
+            let into = into.mut_histogram();
+            into.set_sample_count(into.get_sample_count() + from.get_sample_count());
+            into.set_sample_sum(into.get_sample_sum() + from.get_sample_sum());
+            for bucket in into.mut_bucket().iter_mut() {
+                let count: u64 = from
+                    .get_bucket()
+                    .iter()
+                    .filter(|b| b.get_upper_bound() == bucket.get_upper_bound())
+                    .map(|b| b.get_cumulative_count())
+                    .sum();
+                bucket.set_cumulative_count(bucket.get_cumulative_count() + count);
+            }
+        }
+        MetricType::SUMMARY => {
+            let from = from.get_summary();
+            let into = into.mut_summary();
+            into.set_sample_count(into.get_sample_count() + from.get_sample_count());
+            into.set_sample_sum(into.get_sample_sum() + from.get_sample_sum());
+            into.mut_quantile().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ore::metrics::UIntCounterVec;
+
+    use super::*;
+
+    fn series(families: &[MetricFamily], name: &str) -> Vec<(LabelSet, f64)> {
+        let family = families
+            .iter()
+            .find(|f| f.get_name() == name)
+            .expect("family present");
+        family
+            .get_metric()
+            .iter()
+            .map(|m| (label_set(m.get_label()), m.get_counter().get_value()))
+            .collect()
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> LabelSet {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_series_limit() {
+        let registry = MetricsRegistry::new();
+        let counter: UIntCounterVec = registry.register(metric!(
+            name: "test_requests",
+            help: "test",
+            const_labels: { "instance" => "a" },
+            var_labels: ["source"],
+        ));
+        let guard = MetricsGuard::register_into(&registry, None, Some(2));
+        for (source, n) in &[("s1", 1), ("s2", 2)] {
+            counter.with_label_values(&[source]).inc_by(*n);
+        }
+        guard.apply(registry.gather());
+
+        // Series that appear once the limit is reached are aggregated, while
+        // the series that were admitted first remain.
+        for (source, n) in &[("s0", 4), ("s3", 8)] {
+            counter.with_label_values(&[source]).inc_by(*n);
+        }
+        let families = guard.apply(registry.gather());
+        assert_eq!(
+            series(&families, "test_requests"),
+            vec![
+                (labels(&[("instance", "a"), ("source", "s1")]), 1.0),
+                (labels(&[("instance", "a"), ("source", "s2")]), 2.0),
+                (labels(&[("instance", "a"), ("overflow", "true")]), 12.0),
+            ]
+        );
+        assert_eq!(
+            guard.overflowed.with_label_values(&["test_requests"]).get(),
+            2
+        );
+
+        // Deleted series make room for new ones.
+        counter.remove_label_values(&["s1"]).unwrap();
+        counter.remove_label_values(&["s2"]).unwrap();
+        let families = guard.apply(registry.gather());
+        assert_eq!(
+            series(&families, "test_requests"),
+            vec![
+                (labels(&[("instance", "a"), ("source", "s0")]), 4.0),
+                (labels(&[("instance", "a"), ("source", "s3")]), 8.0),
+            ]
+        );
+        assert!(guard.state.lock().unwrap().overflowing.is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        let registry = MetricsRegistry::new();
+        let counter: UIntCounterVec = registry.register(metric!(
+            name: "test_bytes",
+            help: "test",
+            var_labels: ["source", "worker"],
+        ));
+        for (source, worker, n) in &[("s1", "0", 1), ("s1", "1", 2), ("s2", "0", 4)] {
+            counter.with_label_values(&[source, worker]).inc_by(*n);
+        }
+
+        let drop = MetricsFilter::Drop(vec!["worker".to_owned()].into_iter().collect());
+        let guard = MetricsGuard::register_into(&MetricsRegistry::new(), Some(drop), None);
+        assert_eq!(
+            series(&guard.apply(registry.gather()), "test_bytes"),
+            vec![
+                (labels(&[("source", "s1")]), 3.0),
+                (labels(&[("source", "s2")]), 4.0),
+            ]
+        );
+
+        let keep = MetricsFilter::Keep(vec!["worker".to_owned()].into_iter().collect());
+        let guard = MetricsGuard::register_into(&MetricsRegistry::new(), Some(keep), None);
+        assert_eq!(
+            series(&guard.apply(registry.gather()), "test_bytes"),
+            vec![
+                (labels(&[("worker", "0")]), 5.0),
+                (labels(&[("worker", "1")]), 2.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Gated, all-or-nothing migrations of the catalog in the data directory.
+//!
+//! Before the coordinator opens the catalog, [`prepare`] determines the
+//! catalog's version. A catalog written by a newer version of the server is
+//! refused outright. A catalog that requires migrations is refused unless
+//! migrations are explicitly allowed, in which case a copy of the catalog is
+//! first written to the data directory and kept there.
+//!
+//! While a migration is in progress, a marker file in the data directory names
+//! the pre-migration copy. If the coordinator fails to open the catalog, the
+//! copy is reinstalled before the error is reported. If the server crashes
+//! instead, the marker remains, and the copy is reinstalled by [`prepare`]
+//! when the server next starts. Either way, the catalog is never left
+//! partially migrated.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+use tempfile::NamedTempFile;
+
+use coord::catalog;
+
+use crate::backup::{self, CATALOG_FILE};
+use crate::BUILD_INFO;
+
+/// The name of the marker file that names the pre-migration copy of the
+/// catalog while a migration is in progress.
+const MARKER_FILE: &str = "catalog-migration-in-progress";
+
+/// A migration of the catalog that is in progress.
+#[derive(Debug)]
+pub struct Migration {
+    data_directory: PathBuf,
+    /// The copy of the catalog from before the migration.
+    backup_path: PathBuf,
+}
+
+/// Prepares the catalog in `data_directory` to be opened by the coordinator.
+///
+/// Reinstalls the pre-migration copy of the catalog if a previous migration
+/// was interrupted. Then refuses catalogs that are newer than this version of
+/// the server supports, as well as catalogs that require migration, unless
+/// `allow_migration` is true. In read-only mode, the data directory is never
+/// modified, and whether migrations are required is left to the coordinator
+/// to report.
+///
+/// Returns the migration that opening the catalog will perform, if any. The
+/// caller must report the outcome of opening the catalog to the migration
+/// with [`Migration::finish`] or [`Migration::roll_back`].
+///
+/// This function performs blocking I/O.
+pub fn prepare(
+    data_directory: &Path,
+    allow_migration: bool,
+    read_only: bool,
+) -> Result<Option<Migration>, anyhow::Error> {
+    let marker_path = data_directory.join(MARKER_FILE);
+    if marker_path.exists() {
+        if read_only {
+            bail!(
+                "a migration of the catalog in {} was interrupted; start materialized \
+                 without --read-only to roll it back",
+                data_directory.display()
+            );
+        }
+        let backup_name = fs::read_to_string(&marker_path)
+            .with_context(|| format!("reading {}", marker_path.display()))?;
+        let migration = Migration {
+            data_directory: data_directory.to_path_buf(),
+            backup_path: data_directory.join(backup_name),
+        };
+        warn!(
+            "a previous migration of the catalog was interrupted; rolling it back by \
+             reinstalling {}",
+            migration.backup_path.display()
+        );
+        migration.roll_back()?;
+    }
+
+    let catalog_path = data_directory.join(CATALOG_FILE);
+    let version = match catalog::storage::read_version(&catalog_path) {
+        Ok(Some(version)) => version,
+        // A new catalog is created at the current version.
+        Ok(None) => return Ok(None),
+        // A damaged catalog is reported by the coordinator's integrity check,
+        // which describes how to repair it.
+        Err(_) => return Ok(None),
+    };
+    version.check_compatible(BUILD_INFO.version, allow_migration || read_only)?;
+    let migrations = version.pending_migrations();
+    if read_only || migrations.is_empty() {
+        return Ok(None);
+    }
+
+    info!(
+        "migrating catalog ({}), last opened by {}",
+        version,
+        version.describe_build()
+    );
+    for migration in &migrations {
+        info!("pending catalog migration: {}", migration);
+    }
+
+    // Copy the catalog to a temporary file first, so that an interrupted
+    // copy never masquerades as a complete one.
+    let backup_name = format!(
+        "catalog-pre-migration-{}-{}",
+        version.schema_version, version.content_version
+    );
+    let backup_path = data_directory.join(&backup_name);
+    let tmp = NamedTempFile::new_in(data_directory)?;
+    catalog::storage::copy(&catalog_path, tmp.path())
+        .with_context(|| format!("copying catalog {}", catalog_path.display()))?;
+    tmp.persist(&backup_path)
+        .with_context(|| format!("writing catalog backup {}", backup_path.display()))?;
+
+    let mut marker = NamedTempFile::new_in(data_directory)?;
+    marker.write_all(backup_name.as_bytes())?;
+    marker.as_file().sync_all()?;
+    marker
+        .persist(&marker_path)
+        .with_context(|| format!("writing {}", marker_path.display()))?;
+
+    Ok(Some(Migration {
+        data_directory: data_directory.to_path_buf(),
+        backup_path,
+    }))
+}
+
+impl Migration {
+    /// Records that the migration succeeded.
+    ///
+    /// The pre-migration copy of the catalog is kept.
+    ///
+    /// This function performs blocking I/O.
+    pub fn finish(self) -> Result<(), anyhow::Error> {
+        let marker_path = self.data_directory.join(MARKER_FILE);
+        fs::remove_file(&marker_path)
+            .with_context(|| format!("removing {}", marker_path.display()))?;
+        info!(
+            "migrated catalog; the catalog from before the migration is kept at {}",
+            self.backup_path.display()
+        );
+        Ok(())
+    }
+
+    /// Undoes the migration by reinstalling the pre-migration copy of the
+    /// catalog.
+    ///
+    /// The coordinator must not have the catalog open.
+    ///
+    /// This function performs blocking I/O.
+    pub fn roll_back(self) -> Result<(), anyhow::Error> {
+        // Reinstall a copy, so that the pre-migration copy is kept.
+        let tmp = NamedTempFile::new_in(&self.data_directory)?;
+        fs::copy(&self.backup_path, tmp.path()).with_context(|| {
+            format!(
+                "copying pre-migration catalog {}",
+                self.backup_path.display()
+            )
+        })?;
+        backup::install_catalog(tmp, &self.data_directory)?;
+        let marker_path = self.data_directory.join(MARKER_FILE);
+        fs::remove_file(&marker_path)
+            .with_context(|| format!("removing {}", marker_path.display()))?;
+        Ok(())
+    }
+}
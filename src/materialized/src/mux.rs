@@ -7,34 +7,215 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
-use std::sync::Arc;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use anyhow::bail;
 use async_trait::async_trait;
 use futures::stream::{Stream, StreamExt};
-use log::{debug, error};
+use log::{debug, error, warn};
 use tokio::io::{self, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::time;
+use tracing::{field, Instrument};
 
+use ore::metrics::{HistogramVec, UIntCounterVec};
 use ore::netio::{self, SniffedStream, SniffingStream};
 
 use crate::http;
+use crate::idle::ConnectionTracker;
+use crate::rate_limit::ConnectionRateLimiter;
 
 type Handlers = Vec<Box<dyn ConnectionHandler + Send + Sync>>;
 
+/// The number of bytes sniffed to determine the protocol of a connection.
+///
+/// Choosing how many bytes to sniff is a delicate business. Read too many
+/// bytes and you'll stall out protocols with small handshakes, like pgwire.
+/// Read too few bytes and you won't be able to tell what protocol you have.
+/// For now, eight bytes is the magic number, but this may need to change if
+/// we learn to speak new protocols. Connections whose protocol cannot be
+/// determined from this many bytes are closed.
+const SNIFF_LEN: usize = 8;
+
+/// The minimum interval between log messages about connections closed
+/// because of the [`ProtocolPolicy`].
+const POLICY_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Which protocols a [`Mux`] serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolPolicy {
+    /// Serves every protocol for which the mux has a handler.
+    Multiplexed,
+    /// Serves only the PostgreSQL wire protocol.
+    PgwireOnly,
+    /// Serves only HTTP.
+    HttpOnly,
+}
+
+impl ProtocolPolicy {
+    /// Reports whether the policy permits the named protocol, as reported by
+    /// [`ConnectionHandler::protocol`], or a protocol that no handler
+    /// recognizes, if `protocol` is `None`.
+    fn permits(&self, protocol: Option<&str>) -> bool {
+        match self {
+            ProtocolPolicy::Multiplexed => true,
+            ProtocolPolicy::PgwireOnly => protocol == Some("pgwire"),
+            ProtocolPolicy::HttpOnly => protocol == Some("http"),
+        }
+    }
+}
+
+impl FromStr for ProtocolPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ProtocolPolicy, anyhow::Error> {
+        match s {
+            "multiplexed" => Ok(ProtocolPolicy::Multiplexed),
+            "pgwire-only" => Ok(ProtocolPolicy::PgwireOnly),
+            "http-only" => Ok(ProtocolPolicy::HttpOnly),
+            _ => bail!(
+                "invalid protocol policy {:?}: expected multiplexed, pgwire-only, or http-only",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolPolicy::Multiplexed => f.write_str("multiplexed"),
+            ProtocolPolicy::PgwireOnly => f.write_str("pgwire-only"),
+            ProtocolPolicy::HttpOnly => f.write_str("http-only"),
+        }
+    }
+}
+
+/// Bounds the time that a connection may take to reveal its protocol.
+struct Detection {
+    timeout: Duration,
+    failures: UIntCounterVec,
+}
+
+/// Closes the connections that a [`ProtocolPolicy`] does not permit.
+struct PolicyEnforcer {
+    policy: ProtocolPolicy,
+    rejected: UIntCounterVec,
+    /// When a closed connection was last logged, and how many have been
+    /// closed since without being logged.
+    log_state: Mutex<(Option<Instant>, u64)>,
+}
+
+impl PolicyEnforcer {
+    /// Counts a connection closed because it speaks `protocol`, and logs it,
+    /// unless another closed connection was logged recently.
+    fn reject(&self, protocol: &str) {
+        self.rejected.with_label_values(&[protocol]).inc();
+        let mut log_state = self.log_state.lock().expect("lock poisoned");
+        let (last_logged, unlogged) = &mut *log_state;
+        let now = Instant::now();
+        match last_logged {
+            Some(last_logged) if now.duration_since(*last_logged) < POLICY_LOG_INTERVAL => {
+                *unlogged += 1;
+            }
+            _ => {
+                warn!(
+                    "closed {} connection because the protocol policy is {}; \
+                     {} other connections were closed since this was last reported",
+                    protocol, self.policy, unlogged
+                );
+                *last_logged = Some(now);
+                *unlogged = 0;
+            }
+        }
+    }
+}
+
 /// A mux routes incoming TCP connections to a dynamic set of connection
 /// handlers. It enables serving multiple protocols over the same port.
 ///
 /// Connections are routed by sniffing the first several bytes sent over the
 /// wire and matching them against each handler, in order. The first handler
 /// to match the connection will be invoked.
+///
+/// The handlers of a mux that is serving can be replaced through a
+/// [`MuxHandle`].
 pub struct Mux {
     handlers: Handlers,
+    current: Arc<RwLock<Option<Arc<Handlers>>>>,
+    detection: Option<Arc<Detection>>,
+    enforcer: Option<Arc<PolicyEnforcer>>,
+    durations: Option<HistogramVec>,
+    rate_limiter: Option<Arc<ConnectionRateLimiter>>,
 }
 
 impl Mux {
     /// Constructs a new `Mux`.
     pub fn new() -> Mux {
-        Mux { handlers: vec![] }
+        Mux {
+            handlers: vec![],
+            current: Arc::new(RwLock::new(None)),
+            detection: None,
+            enforcer: None,
+            durations: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Rejects new connections that exceed the rate limit enforced by
+    /// `rate_limiter` as soon as they are accepted, before their protocol is
+    /// detected or any TLS handshake begins.
+    ///
+    /// Like the protocol policy, this applies for as long as the mux serves.
+    pub fn rate_limit(&mut self, rate_limiter: ConnectionRateLimiter) {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+    }
+
+    /// Observes how long each connection that is handed to a handler stays
+    /// open in `durations`, by the protocol that the connection speaks, when
+    /// the connection closes.
+    ///
+    /// Like the protocol policy, this applies for as long as the mux serves.
+    pub fn time_connections(&mut self, durations: HistogramVec) {
+        self.durations = Some(durations);
+    }
+
+    /// Closes connections that do not send enough bytes to determine their
+    /// protocol within `timeout`, or whose protocol is not recognized, and
+    /// counts them in `failures` by reason: `timeout` or `unrecognized`.
+    ///
+    /// Clients that are slow, but not too slow, to send their first bytes
+    /// are served as usual.
+    pub fn detect_within(&mut self, timeout: Duration, failures: UIntCounterVec) {
+        self.detection = Some(Arc::new(Detection { timeout, failures }));
+    }
+
+    /// Closes connections that speak a protocol that `policy` does not
+    /// permit, as soon as the protocol is sniffed, and counts them in
+    /// `rejected` by protocol.
+    ///
+    /// The policy applies for as long as the mux serves, even once its
+    /// handlers are replaced.
+    pub fn enforce(&mut self, policy: ProtocolPolicy, rejected: UIntCounterVec) {
+        self.enforcer = match policy {
+            ProtocolPolicy::Multiplexed => None,
+            _ => Some(Arc::new(PolicyEnforcer {
+                policy,
+                rejected,
+                log_state: Mutex::new((None, 0)),
+            })),
+        };
+    }
+
+    /// Returns a handle that can replace the handlers of this mux once it is
+    /// serving.
+    pub fn handle(&self) -> MuxHandle {
+        MuxHandle {
+            current: self.current.clone(),
+        }
     }
 
     /// Adds a new connection handler to this mux.
@@ -46,12 +227,30 @@ impl Mux {
     }
 
     /// Serves incoming TCP traffic from `listener`.
-    pub async fn serve<S>(self, mut incoming: S)
+    ///
+    /// Every connection is recorded in `tracker` from the moment it is
+    /// accepted until it closes.
+    pub async fn serve<S>(self, mut incoming: S, tracker: ConnectionTracker)
     where
         S: Stream<Item = io::Result<TcpStream>> + Unpin,
     {
+        // The handlers may already have been replaced, if the mux was
+        // replaced before it started serving.
         let handlers = Arc::new(self.handlers);
+        self.current
+            .write()
+            .expect("lock poisoned")
+            .get_or_insert(handlers);
         while let Some(conn) = incoming.next().await {
+            // Fails the accept as if the process had run out of file
+            // descriptors. The accepted connection is dropped.
+            #[cfg(feature = "failpoints")]
+            let conn = conn.and_then(|conn| {
+                fail::fail_point!("mux_accept", |_| Err(io::Error::from_raw_os_error(
+                    libc::EMFILE
+                )));
+                Ok(conn)
+            });
             let conn = match conn {
                 Ok(conn) => conn,
                 Err(err) => {
@@ -59,6 +258,13 @@ impl Mux {
                     continue;
                 }
             };
+            let conn = match &self.rate_limiter {
+                None => conn,
+                Some(rate_limiter) => match rate_limiter.admit(conn) {
+                    Some(conn) => conn,
+                    None => continue,
+                },
+            };
             // Set TCP_NODELAY to disable tinygram prevention (Nagle's
             // algorithm), which forces a 40ms delay between each query
             // on linux. According to John Nagle [0], the true problem
@@ -71,21 +277,73 @@ impl Mux {
             //
             // [0]: https://news.ycombinator.com/item?id=10608356
             conn.set_nodelay(true).expect("set_nodelay failed");
-            tokio::spawn(handle_connection(handlers.clone(), conn));
+            let handlers = self.current.read().expect("lock poisoned").clone();
+            let handlers = handlers.expect("handlers installed");
+            let guard = tracker.open();
+            let tracker = tracker.clone();
+            let detection = self.detection.clone();
+            let enforcer = self.enforcer.clone();
+            let durations = self.durations.clone();
+            tokio::spawn(async move {
+                handle_connection(
+                    handlers,
+                    detection.as_deref(),
+                    enforcer.as_deref(),
+                    durations.as_ref(),
+                    conn,
+                    &tracker,
+                )
+                .await;
+                drop(guard);
+            });
         }
     }
 }
 
-async fn handle_connection(handlers: Arc<Handlers>, conn: TcpStream) {
-    // Sniff out what protocol we've received. Choosing how many bytes to
-    // sniff is a delicate business. Read too many bytes and you'll stall
-    // out protocols with small handshakes, like pgwire. Read too few bytes
-    // and you won't be able to tell what protocol you have. For now, eight
-    // bytes is the magic number, but this may need to change if we learn to
-    // speak new protocols.
+/// A handle to a [`Mux`] that is serving.
+#[derive(Clone)]
+pub struct MuxHandle {
+    current: Arc<RwLock<Option<Arc<Handlers>>>>,
+}
+
+impl MuxHandle {
+    /// Replaces the handlers of the mux with the handlers of `mux`.
+    ///
+    /// The replacement is atomic: every connection accepted after this method
+    /// returns is routed to the new handlers, while connections that were
+    /// already routed to the old handlers continue to be served by them.
+    pub fn replace(&self, mux: Mux) {
+        *self.current.write().expect("lock poisoned") = Some(Arc::new(mux.handlers));
+    }
+}
+
+async fn handle_connection(
+    handlers: Arc<Handlers>,
+    detection: Option<&Detection>,
+    enforcer: Option<&PolicyEnforcer>,
+    durations: Option<&HistogramVec>,
+    conn: TcpStream,
+    tracker: &ConnectionTracker,
+) {
+    let start = Instant::now();
+    // Sniff out what protocol we've received. The sniffed bytes are replayed
+    // to the handler that speaks the protocol.
+    let peer = conn.peer_addr().ok();
     let mut ss = SniffingStream::new(conn);
-    let mut buf = [0; 8];
-    let nread = match netio::read_exact_or_eof(&mut ss, &mut buf).await {
+    let mut buf = [0; SNIFF_LEN];
+    let read = netio::read_exact_or_eof(&mut ss, &mut buf);
+    let read = match detection {
+        None => read.await,
+        Some(detection) => match time::timeout(detection.timeout, read).await {
+            Ok(read) => read,
+            Err(_) => {
+                debug!("dropped connection that did not reveal its protocol in time");
+                detection.failures.with_label_values(&["timeout"]).inc();
+                return;
+            }
+        },
+    };
+    let nread = match read {
         Ok(nread) => nread,
         Err(err) => {
             error!("error handling request: {}", err);
@@ -94,15 +352,60 @@ async fn handle_connection(handlers: Arc<Handlers>, conn: TcpStream) {
     };
     let buf = &buf[..nread];
 
-    for handler in &*handlers {
-        if handler.match_handshake(buf) {
-            if let Err(e) = handler.handle_connection(ss.into_sniffed()).await {
-                error!("error handling connection in {}: {:#}", handler.name(), e);
-            }
+    let handler = handlers.iter().find(|handler| handler.match_handshake(buf));
+    // Connections that close without sending anything, like those of TCP
+    // health checks, are not failures.
+    if handler.is_none() && nread > 0 {
+        if let Some(detection) = detection {
+            detection
+                .failures
+                .with_label_values(&["unrecognized"])
+                .inc();
+        }
+    }
+    if let Some(enforcer) = enforcer {
+        let protocol = handler.map(|handler| handler.protocol());
+        if !enforcer.policy.permits(protocol) {
+            enforcer.reject(protocol.unwrap_or("unknown"));
             return;
         }
     }
 
+    if let Some(handler) = handler {
+        // Every log message emitted while handling the connection is
+        // tagged with the connection's correlation ID, which is also
+        // reported to the client, so that a client's report of a problem
+        // can be matched with the server's logs.
+        let correlation_id = new_correlation_id();
+        let span = tracing::info_span!(
+            "connection",
+            correlation_id = %correlation_id,
+            peer = field::Empty,
+            user = field::Empty,
+            conn_id = field::Empty,
+            transport = field::Empty,
+        );
+        if let Some(peer) = peer {
+            span.record("peer", &field::display(peer));
+        }
+        let conn = ss.into_sniffed();
+        let _protocol_guard = tracker.identify(handler.protocol());
+        if let Err(e) = handler
+            .handle_connection(conn, correlation_id)
+            .instrument(span.clone())
+            .await
+        {
+            let _guard = span.enter();
+            error!("error handling connection in {}: {:#}", handler.name(), e);
+        }
+        if let Some(durations) = durations {
+            durations
+                .with_label_values(&[handler.protocol()])
+                .observe(start.elapsed().as_secs_f64());
+        }
+        return;
+    }
+
     debug!(
         "dropped connection using unknown protocol (sniffed: 0x{})",
         hex::encode(buf)
@@ -110,18 +413,36 @@ async fn handle_connection(handlers: Arc<Handlers>, conn: TcpStream) {
     let _ = ss.into_sniffed().write_all(b"unknown protocol\n").await;
 }
 
+/// Generates a short, random ID for a connection.
+fn new_correlation_id() -> String {
+    format!("{:012x}", rand::random::<u64>() >> 16)
+}
+
 /// A connection handler manages an incoming network connection.
 #[async_trait]
 pub trait ConnectionHandler {
     /// Returns the name of the connection handler for use in e.g. log messages.
     fn name(&self) -> &str;
 
+    /// Returns the name of the protocol that the connection handler speaks,
+    /// for use in e.g. metric labels.
+    fn protocol(&self) -> &'static str;
+
     /// Determines whether this handler can accept the connection based on the
     /// first several bytes in the stream.
     fn match_handshake(&self, buf: &[u8]) -> bool;
 
     /// Handles the connection.
-    async fn handle_connection(&self, conn: SniffedStream<TcpStream>) -> Result<(), anyhow::Error>;
+    ///
+    /// The `correlation_id` identifies the connection to both the server's
+    /// logs and the client. The connection is handled within a span that has
+    /// `user`, `conn_id`, and `transport` fields, which the handler should
+    /// record once they are known.
+    async fn handle_connection(
+        &self,
+        conn: SniffedStream<TcpStream>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error>;
 }
 
 #[async_trait]
@@ -130,15 +451,24 @@ impl ConnectionHandler for pgwire::Server {
         "pgwire server"
     }
 
+    fn protocol(&self) -> &'static str {
+        "pgwire"
+    }
+
     fn match_handshake(&self, buf: &[u8]) -> bool {
         pgwire::match_handshake(buf)
     }
 
-    async fn handle_connection(&self, conn: SniffedStream<TcpStream>) -> Result<(), anyhow::Error> {
+    async fn handle_connection(
+        &self,
+        conn: SniffedStream<TcpStream>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error> {
         // Using fully-qualified syntax means we won't accidentally call
         // ourselves (i.e., silently infinitely recurse) if the name or type of
         // `pgwire::Server::handle_connection` changes.
-        pgwire::Server::handle_connection(self, conn).await
+        let peer = conn.get_ref().peer_addr().ok();
+        pgwire::Server::handle_connection(self, conn, correlation_id, peer).await
     }
 }
 
@@ -148,14 +478,68 @@ impl ConnectionHandler for http::Server {
         "http server"
     }
 
+    fn protocol(&self) -> &'static str {
+        "http"
+    }
+
     fn match_handshake(&self, buf: &[u8]) -> bool {
         self.match_handshake(buf)
     }
 
-    async fn handle_connection(&self, conn: SniffedStream<TcpStream>) -> Result<(), anyhow::Error> {
+    async fn handle_connection(
+        &self,
+        conn: SniffedStream<TcpStream>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error> {
         // Using fully-qualified syntax means we won't accidentally call
         // ourselves (i.e., silently infinitely recurse) if the name or type of
         // `http::Server::handle_connection` changes.
-        http::Server::handle_connection(self, conn).await
+        http::Server::handle_connection(self, conn, correlation_id).await
+    }
+}
+
+#[async_trait]
+impl ConnectionHandler for pgwire::StartingServer {
+    fn name(&self) -> &str {
+        "pgwire starting server"
+    }
+
+    fn protocol(&self) -> &'static str {
+        "pgwire"
+    }
+
+    fn match_handshake(&self, buf: &[u8]) -> bool {
+        pgwire::match_handshake(buf)
+    }
+
+    async fn handle_connection(
+        &self,
+        conn: SniffedStream<TcpStream>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error> {
+        pgwire::StartingServer::handle_connection(self, conn, correlation_id).await
+    }
+}
+
+#[async_trait]
+impl ConnectionHandler for http::StartingServer {
+    fn name(&self) -> &str {
+        "http starting server"
+    }
+
+    fn protocol(&self) -> &'static str {
+        "http"
+    }
+
+    fn match_handshake(&self, buf: &[u8]) -> bool {
+        self.match_handshake(buf)
+    }
+
+    async fn handle_connection(
+        &self,
+        conn: SniffedStream<TcpStream>,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error> {
+        http::StartingServer::handle_connection(self, conn, correlation_id).await
     }
 }
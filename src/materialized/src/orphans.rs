@@ -0,0 +1,309 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Detection and cleanup of orphaned files in the data directory.
+//!
+//! An orphaned file is an entry in the data directory that belongs neither to
+//! the server itself, like the catalog and the log files, nor to any object in
+//! the catalog, like the file that an Avro OCF sink writes. Orphaned files are
+//! typically left behind by dropped sinks or by crashes.
+//!
+//! Only the top level of the data directory is scanned. An entry that contains
+//! a file that belongs to the server or to the catalog is never an orphan, nor
+//! is an entry that was modified after the server started, as it may belong to
+//! an object that was created after the catalog was consulted.
+//!
+//! When cleanup is enabled, orphans are moved to the [`QUARANTINE_DIR`] and
+//! deleted once they have been quarantined for the retention period. Each
+//! step is a single rename or removal, so a scan may be interrupted at any
+//! point; the next scan picks up where it left off.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use log::{info, warn};
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntGauge};
+
+use crate::disk;
+use crate::telemetry;
+
+/// The name of the directory within the data directory to which orphans are
+/// moved when cleanup is enabled.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// The names of the entries in the data directory that always belong to the
+/// server.
+///
+/// Entries whose names extend one of these names, like the catalog's SQLite
+/// journal or rotated log files, also belong to the server.
+const RESERVED_ENTRIES: &[&str] = &[
+    "catalog",
+    "crash",
+    "diagnostics",
+    "materialized.log",
+    QUARANTINE_DIR,
+    telemetry::PREFERENCE_FILE,
+    crate::MAINTENANCE_MODE_FILE,
+    crate::handoff::CONTROL_SOCKET,
+    coord::catalog::storage_directory::MARKER_FILE,
+];
+
+/// The maximum number of orphans to name in the log summary.
+const MAX_LOGGED_ORPHANS: usize = 10;
+
+/// Orphan scan metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// The number of orphans found by the most recent scan.
+    orphans: UIntGauge,
+    /// The size of the orphans found by the most recent scan.
+    orphaned_bytes: UIntGauge,
+    /// The number of orphans in quarantine after the most recent scan.
+    quarantined: UIntGauge,
+}
+
+impl Metrics {
+    /// Registers the orphan scan metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            orphans: registry.register(metric!(
+                name: "mz_data_directory_orphans",
+                help: "number of orphaned files found in the data directory by the most recent scan",
+            )),
+            orphaned_bytes: registry.register(metric!(
+                name: "mz_data_directory_orphaned_bytes",
+                help: "size of the orphaned files found in the data directory by the most recent scan",
+            )),
+            quarantined: registry.register(metric!(
+                name: "mz_data_directory_quarantined_orphans",
+                help: "number of orphaned files awaiting deletion in the quarantine directory",
+            )),
+        }
+    }
+}
+
+/// Scans the data directory for orphans.
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    /// The data directory to scan.
+    pub data_directory: PathBuf,
+    /// Additional paths that belong to the server, like the scratch directory
+    /// or a log file.
+    ///
+    /// Entries whose names extend the name of a reserved path, like rotated
+    /// log files, are also considered reserved.
+    pub reserved_paths: Vec<PathBuf>,
+    /// If present, orphans are quarantined, and deleted once they have been
+    /// quarantined for the specified duration.
+    pub retention: Option<Duration>,
+    /// Entries modified at or after this time are never orphans.
+    pub start_time: SystemTime,
+    /// Metrics about the scans.
+    pub metrics: Metrics,
+}
+
+/// The outcome of a scan of the data directory.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The orphans that were found.
+    pub orphans: Vec<PathBuf>,
+    /// The total size of the orphans, in bytes.
+    pub orphaned_bytes: u64,
+    /// The orphans that were moved into quarantine.
+    pub quarantined: usize,
+    /// The quarantined orphans that were deleted because their retention
+    /// period expired.
+    pub deleted: usize,
+}
+
+impl Scanner {
+    /// Scans the data directory for orphans, cleaning them up if configured
+    /// to do so.
+    ///
+    /// `catalog_files` are the files that the objects in the catalog refer
+    /// to, as returned by [`coord::Client::catalog_files`].
+    ///
+    /// This function performs blocking I/O.
+    pub fn scan(&self, catalog_files: &[PathBuf]) -> Result<Report, anyhow::Error> {
+        let data_directory = self.data_directory.canonicalize().with_context(|| {
+            format!("resolving data directory {}", self.data_directory.display())
+        })?;
+        let reserved_paths: Vec<_> = self.reserved_paths.iter().map(|p| absolute(p)).collect();
+        let catalog_files: Vec<_> = catalog_files.iter().map(|p| absolute(p)).collect();
+
+        let mut report = Report::default();
+        for entry in fs::read_dir(&data_directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // The entry was removed after it was listed.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let reserved = RESERVED_ENTRIES
+                .iter()
+                .any(|reserved| name.starts_with(reserved))
+                || reserved_paths
+                    .iter()
+                    .any(|reserved| extends(&path, reserved))
+                || catalog_files.iter().any(|file| file.starts_with(&path));
+            let recently_modified = match metadata.modified() {
+                Ok(modified) => modified >= self.start_time,
+                Err(_) => true,
+            };
+            if reserved || recently_modified {
+                continue;
+            }
+            report.orphaned_bytes += if metadata.is_dir() {
+                disk::usage(&path, &[])
+            } else {
+                metadata.len()
+            };
+            report.orphans.push(path);
+        }
+
+        if let Some(retention) = self.retention {
+            let quarantine_dir = data_directory.join(QUARANTINE_DIR);
+            fs::create_dir_all(&quarantine_dir).with_context(|| {
+                format!("creating quarantine directory {}", quarantine_dir.display())
+            })?;
+            let now = SystemTime::now();
+            let now_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            for orphan in &report.orphans {
+                let name = orphan.file_name().expect("directory entries have names");
+                let mut target = OsString::from(format!("{}-", now_secs));
+                target.push(name);
+                let target = quarantine_dir.join(target);
+                if target.exists() {
+                    // An orphan of the same name was quarantined in the same
+                    // second. Try again during the next scan.
+                    continue;
+                }
+                fs::rename(orphan, &target)
+                    .with_context(|| format!("quarantining orphan {}", orphan.display()))?;
+                report.quarantined += 1;
+            }
+            let mut remaining = 0;
+            for entry in fs::read_dir(&quarantine_dir)? {
+                let entry = entry?;
+                let quarantined_at = match quarantine_time(&entry.file_name().to_string_lossy()) {
+                    Some(quarantined_at) => quarantined_at,
+                    // Not placed here by a scan. Leave it be.
+                    None => continue,
+                };
+                if now.duration_since(quarantined_at).unwrap_or_default() < retention {
+                    remaining += 1;
+                    continue;
+                }
+                let path = entry.path();
+                let res = if entry.file_type()?.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+                res.with_context(|| format!("deleting quarantined orphan {}", path.display()))?;
+                report.deleted += 1;
+            }
+            self.metrics.quarantined.set(remaining);
+        }
+
+        self.metrics.orphans.set(report.orphans.len() as u64);
+        self.metrics.orphaned_bytes.set(report.orphaned_bytes);
+        Ok(report)
+    }
+}
+
+impl Report {
+    /// Logs a summary of the report.
+    pub fn log(&self, data_directory: &Path, cleanup: bool) {
+        if self.orphans.is_empty() {
+            info!(
+                "found no orphaned files in data directory {}",
+                data_directory.display()
+            );
+        } else {
+            let mut names = self
+                .orphans
+                .iter()
+                .take(MAX_LOGGED_ORPHANS)
+                .map(|p| p.file_name().unwrap_or_default().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ");
+            if self.orphans.len() > MAX_LOGGED_ORPHANS {
+                names += &format!(", and {} more", self.orphans.len() - MAX_LOGGED_ORPHANS);
+            }
+            let action = if cleanup {
+                format!("quarantined {}", self.quarantined)
+            } else {
+                "enable orphan cleanup to quarantine them".into()
+            };
+            warn!(
+                "found {} orphaned files ({} bytes) in data directory {}: {}; {}",
+                self.orphans.len(),
+                self.orphaned_bytes,
+                data_directory.display(),
+                names,
+                action,
+            );
+        }
+        if self.deleted > 0 {
+            info!(
+                "deleted {} quarantined orphans whose retention period expired",
+                self.deleted
+            );
+        }
+    }
+}
+
+/// Reports whether `path` is `reserved`, contains `reserved`, or extends the
+/// name of `reserved` within the same directory.
+fn extends(path: &Path, reserved: &Path) -> bool {
+    if reserved.starts_with(path) {
+        return true;
+    }
+    match (path.file_name(), reserved.file_name()) {
+        (Some(name), Some(reserved_name)) => {
+            path.parent() == reserved.parent()
+                && name
+                    .to_string_lossy()
+                    .starts_with(&*reserved_name.to_string_lossy())
+        }
+        _ => false,
+    }
+}
+
+/// Parses the time at which a quarantined orphan was quarantined from its
+/// name.
+fn quarantine_time(name: &str) -> Option<SystemTime> {
+    let (secs, _) = name.split_once('-')?;
+    let secs = secs.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Resolves `path` to an absolute path, following symbolic links if the path
+/// exists.
+fn absolute(path: &Path) -> PathBuf {
+    match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => match env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => path.to_path_buf(),
+        },
+    }
+}
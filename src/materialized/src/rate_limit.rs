@@ -0,0 +1,402 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Rate limiting of new connections by source address.
+//!
+//! Each source address is allotted a token bucket, and each connection
+//! attempt takes a token from the bucket of its source address and from a
+//! global bucket. Attempts that find either bucket empty are rejected as soon
+//! as they are accepted, before any TLS handshake or protocol detection.
+//!
+//! Only the most recently seen source addresses are tracked, so that a scan
+//! from many addresses cannot exhaust memory. An address whose bucket is
+//! evicted starts over with a full bucket.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use log::warn;
+use tokio::net::TcpStream;
+
+use ore::metrics::UIntCounterVec;
+
+/// How long a tarpitted connection is held open before it is closed.
+const TARPIT_DURATION: Duration = Duration::from_secs(10);
+
+/// The maximum number of connections that are tarpitted at once. Beyond
+/// this, rejected connections are dropped instead, so that tarpitting cannot
+/// exhaust file descriptors.
+const MAX_TARPITTED: usize = 1024;
+
+/// The minimum interval between log messages about rejected connection
+/// attempts from any one source address.
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The default value of [`RateLimitConfig::max_addresses`].
+pub const DEFAULT_RATE_LIMIT_ADDRESSES: usize = 10_000;
+
+/// What to do with a connection attempt that exceeds the rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// Closes the connection immediately.
+    Drop,
+    /// Holds the connection open, without reading from it, for a while
+    /// before closing it, to slow down clients that wait for a response.
+    Tarpit,
+}
+
+impl RateLimitAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitAction::Drop => "drop",
+            RateLimitAction::Tarpit => "tarpit",
+        }
+    }
+}
+
+impl FromStr for RateLimitAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<RateLimitAction, anyhow::Error> {
+        match s {
+            "drop" => Ok(RateLimitAction::Drop),
+            "tarpit" => Ok(RateLimitAction::Tarpit),
+            _ => bail!("invalid rate limit action {:?}: expected drop or tarpit", s),
+        }
+    }
+}
+
+impl fmt::Display for RateLimitAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Configures the rate limit on new connections.
+///
+/// Connections from loopback addresses are exempt.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// The sustained number of connection attempts per second permitted from
+    /// each source address.
+    pub per_address_rate: f64,
+    /// The number of connection attempts that each source address may make
+    /// in a burst.
+    pub burst: u32,
+    /// The sustained number of connection attempts per second permitted
+    /// across all source addresses, if any. Bursts of up to one second's
+    /// worth of attempts are permitted.
+    pub global_rate: Option<f64>,
+    /// What to do with attempts that exceed the limit.
+    pub action: RateLimitAction,
+    /// The number of source addresses that are tracked at once.
+    pub max_addresses: usize,
+}
+
+impl RateLimitConfig {
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(self.per_address_rate.is_finite() && self.per_address_rate > 0.0) {
+            bail!(
+                "connection rate limit must be positive, got {}",
+                self.per_address_rate
+            );
+        }
+        if self.burst == 0 {
+            bail!("connection rate limit burst must be positive");
+        }
+        if let Some(global_rate) = self.global_rate {
+            if !(global_rate.is_finite() && global_rate >= 1.0) {
+                bail!(
+                    "global connection rate limit must be at least 1, got {}",
+                    global_rate
+                );
+            }
+        }
+        if self.max_addresses == 0 {
+            bail!("the number of rate-limited addresses must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// A bucket of tokens that refills continuously up to its capacity.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    /// Takes a token from the bucket, if one is available.
+    fn take(&mut self, rate: f64, capacity: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    bucket: TokenBucket,
+    /// The position of the entry in [`State::recency`].
+    tick: u64,
+    last_logged: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct State {
+    entries: HashMap<IpAddr, Entry>,
+    /// The tracked addresses, from least to most recently seen.
+    recency: BTreeMap<u64, IpAddr>,
+    next_tick: u64,
+    global: Option<TokenBucket>,
+    global_last_logged: Option<Instant>,
+}
+
+/// Enforces a [`RateLimitConfig`] on accepted connections.
+#[derive(Debug)]
+pub struct ConnectionRateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+    limited: UIntCounterVec,
+    tarpitted: Arc<AtomicUsize>,
+}
+
+impl ConnectionRateLimiter {
+    /// Constructs a rate limiter that counts the connections it rejects in
+    /// `limited`, by action: `drop` or `tarpit`.
+    pub fn new(config: RateLimitConfig, limited: UIntCounterVec) -> ConnectionRateLimiter {
+        let now = Instant::now();
+        ConnectionRateLimiter {
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_tick: 0,
+                global: config.global_rate.map(|rate| TokenBucket::full(rate, now)),
+                global_last_logged: None,
+            }),
+            config,
+            limited,
+            tarpitted: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Admits `conn`, or rejects it if its source address, or the server as
+    /// a whole, has exceeded the rate limit.
+    ///
+    /// A rejected connection is closed, either immediately or after being
+    /// tarpitted, and `None` is returned.
+    pub fn admit(&self, conn: TcpStream) -> Option<TcpStream> {
+        let addr = match conn.peer_addr() {
+            Ok(peer) => peer.ip(),
+            Err(_) => return Some(conn),
+        };
+        if is_loopback(addr) || self.permits(addr, Instant::now()) {
+            return Some(conn);
+        }
+        let mut action = self.config.action;
+        if action == RateLimitAction::Tarpit {
+            if self.tarpitted.fetch_add(1, Ordering::SeqCst) < MAX_TARPITTED {
+                let tarpitted = Arc::clone(&self.tarpitted);
+                tokio::spawn(async move {
+                    tokio::time::sleep(TARPIT_DURATION).await;
+                    drop(conn);
+                    tarpitted.fetch_sub(1, Ordering::SeqCst);
+                });
+            } else {
+                self.tarpitted.fetch_sub(1, Ordering::SeqCst);
+                action = RateLimitAction::Drop;
+            }
+        }
+        self.limited.with_label_values(&[action.as_str()]).inc();
+        None
+    }
+
+    /// Takes a token for a connection attempt from `addr` at `now`, and
+    /// reports whether one was available.
+    fn permits(&self, addr: IpAddr, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let state = &mut *state;
+        let burst = f64::from(self.config.burst);
+
+        // Move the address to the back of the recency queue, evicting the
+        // least recently seen address if the queue is full.
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        match state.entries.get_mut(&addr) {
+            Some(entry) => {
+                state.recency.remove(&entry.tick);
+                entry.tick = tick;
+            }
+            None => {
+                if state.entries.len() >= self.config.max_addresses {
+                    if let Some(oldest) = state.recency.keys().next().copied() {
+                        let evicted = state.recency.remove(&oldest).expect("key exists");
+                        state.entries.remove(&evicted);
+                    }
+                }
+                state.entries.insert(
+                    addr,
+                    Entry {
+                        bucket: TokenBucket::full(burst, now),
+                        tick,
+                        last_logged: None,
+                    },
+                );
+            }
+        }
+        state.recency.insert(tick, addr);
+
+        let entry = state.entries.get_mut(&addr).expect("entry just inserted");
+        if !entry.bucket.take(self.config.per_address_rate, burst, now) {
+            if should_log(&mut entry.last_logged, now) {
+                warn!(
+                    "rejecting connection attempts from {}: more than {} per second; \
+                     further rejections from this address will not be logged for {:?}",
+                    addr, self.config.per_address_rate, LOG_INTERVAL
+                );
+            }
+            return false;
+        }
+
+        if let (Some(global), Some(rate)) = (&mut state.global, self.config.global_rate) {
+            if !global.take(rate, rate, now) {
+                if should_log(&mut state.global_last_logged, now) {
+                    warn!(
+                        "rejecting connection attempts: more than {} per second across all \
+                         addresses; further rejections will not be logged for {:?}",
+                        rate, LOG_INTERVAL
+                    );
+                }
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Reports whether a rejection last logged at `last_logged` should be
+/// logged again at `now`, and if so, records that it was.
+fn should_log(last_logged: &mut Option<Instant>, now: Instant) -> bool {
+    match last_logged {
+        Some(last) if now.saturating_duration_since(*last) < LOG_INTERVAL => false,
+        _ => {
+            *last_logged = Some(now);
+            true
+        }
+    }
+}
+
+/// Reports whether `addr` is a loopback address, including an IPv4 loopback
+/// address mapped into IPv6.
+fn is_loopback(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_loopback(),
+        IpAddr::V6(addr) => match addr.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, _] => hi >> 8 == 127,
+            _ => addr.is_loopback(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use ore::metric;
+    use ore::metrics::MetricsRegistry;
+
+    use super::*;
+
+    fn limiter(global_rate: Option<f64>, max_addresses: usize) -> ConnectionRateLimiter {
+        let limited = MetricsRegistry::new().register(metric!(
+            name: "test_limited",
+            help: "test",
+            var_labels: ["action"],
+        ));
+        ConnectionRateLimiter::new(
+            RateLimitConfig {
+                per_address_rate: 1.0,
+                burst: 2,
+                global_rate,
+                action: RateLimitAction::Drop,
+                max_addresses,
+            },
+            limited,
+        )
+    }
+
+    #[test]
+    fn test_per_address_limit() {
+        let limiter = limiter(None, 2);
+        let (a, b, c) = (
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            "10.0.0.3".parse().unwrap(),
+        );
+        let start = Instant::now();
+
+        // Each address may make a burst of two attempts, and is then limited
+        // to one attempt per second.
+        assert!(limiter.permits(a, start));
+        assert!(limiter.permits(a, start));
+        assert!(!limiter.permits(a, start));
+        assert!(limiter.permits(b, start));
+        assert!(limiter.permits(a, start + Duration::from_secs(1)));
+        assert!(!limiter.permits(a, start + Duration::from_secs(1)));
+
+        // Tracking a third address evicts the least recently seen address,
+        // which then starts over with a full bucket.
+        assert!(limiter.permits(c, start + Duration::from_secs(1)));
+        assert_eq!(limiter.state.lock().unwrap().entries.len(), 2);
+        assert!(limiter.permits(b, start + Duration::from_secs(1)));
+        assert!(limiter.permits(b, start + Duration::from_secs(1)));
+        assert!(!limiter.permits(b, start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let limiter = limiter(Some(3.0), 100);
+        let start = Instant::now();
+        let permitted = (0..10)
+            .filter(|i| limiter.permits(format!("10.0.0.{}", i).parse().unwrap(), start))
+            .count();
+        assert_eq!(permitted, 3);
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("127.0.0.1".parse().unwrap()));
+        assert!(is_loopback("127.1.2.3".parse().unwrap()));
+        assert!(is_loopback(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(is_loopback("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!is_loopback("10.0.0.1".parse().unwrap()));
+        assert!(!is_loopback("::ffff:10.0.0.1".parse().unwrap()));
+    }
+}
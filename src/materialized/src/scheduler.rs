@@ -0,0 +1,315 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Scheduling of periodic background jobs.
+//!
+//! Rather than each periodic job sleeping in a loop of its own, which wakes
+//! an otherwise idle server once per job, a single [`Scheduler`] wakes only
+//! on ticks that are aligned to a coarse granularity and starts every job
+//! that is due on that tick. Jobs therefore run up to one granularity later
+//! than they are due.
+//!
+//! Each run of a job is spawned onto its own task, so that a slow job does not
+//! delay unrelated jobs. If a job is still running when it is next due, that
+//! run is skipped.
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::debug;
+use tokio::sync::oneshot;
+use tokio::time::{self, Instant};
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounter, UIntCounterVec};
+
+/// The granularity to which the scheduler's ticks are aligned.
+pub const GRANULARITY: Duration = Duration::from_millis(250);
+
+/// Scheduler metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// The number of times the scheduler woke up to run jobs.
+    wakeups: UIntCounter,
+    /// The number of runs of each job that were skipped because the previous
+    /// run was still in progress.
+    skipped_runs: UIntCounterVec,
+}
+
+impl Metrics {
+    /// Registers the scheduler metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            wakeups: registry.register(metric!(
+                name: "mz_server_scheduler_wakeups_total",
+                help: "the number of times the background job scheduler woke up",
+            )),
+            skipped_runs: registry.register(metric!(
+                name: "mz_server_scheduler_skipped_runs_total",
+                help: "the number of runs of a background job that were skipped \
+                       because the previous run was still in progress",
+                var_labels: ["job"],
+            )),
+        }
+    }
+}
+
+/// A periodic background job.
+pub struct Job {
+    name: &'static str,
+    next_run: Instant,
+    /// Returns the delay from one run to the next. If absent, the job runs
+    /// only once.
+    period: Option<Box<dyn FnMut() -> Duration + Send>>,
+    run: Box<dyn FnMut() -> BoxFuture<'static, ()> + Send>,
+    running: Arc<AtomicBool>,
+}
+
+impl Job {
+    /// Constructs a job named `name` that runs `run` every `interval`,
+    /// starting immediately.
+    pub fn every<F, Fut>(name: &'static str, interval: Duration, run: F) -> Job
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Job::once(name, run).with_period(move || interval)
+    }
+
+    /// Constructs a job named `name` that runs `run` once, immediately.
+    pub fn once<F, Fut>(name: &'static str, mut run: F) -> Job
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Job {
+            name,
+            next_run: Instant::now(),
+            period: None,
+            run: Box::new(move || run().boxed()),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Delays the first run of the job by `delay`.
+    pub fn first_run_after(mut self, delay: Duration) -> Job {
+        self.next_run = Instant::now() + delay;
+        self
+    }
+
+    /// Runs the job repeatedly, calling `period` as each run starts to
+    /// determine the delay until the next run.
+    pub fn with_period<P>(mut self, period: P) -> Job
+    where
+        P: FnMut() -> Duration + Send + 'static,
+    {
+        self.period = Some(Box::new(period));
+        self
+    }
+
+    /// Starts a run of the job, unless the previous run is still in progress.
+    fn start(&mut self, metrics: &Metrics) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            debug!(
+                "skipping run of background job {}: previous run still in progress",
+                self.name
+            );
+            metrics.skipped_runs.with_label_values(&[self.name]).inc();
+            return;
+        }
+        let guard = RunningGuard(Arc::clone(&self.running));
+        let run = (self.run)();
+        tokio::spawn(async move {
+            run.await;
+            drop(guard);
+        });
+    }
+}
+
+/// Marks a job as no longer running when dropped, even if the run panics.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Runs periodic background jobs on coarse, aligned ticks.
+pub struct Scheduler {
+    granularity: Duration,
+    jobs: Vec<Job>,
+    metrics: Metrics,
+}
+
+impl Scheduler {
+    /// Constructs a scheduler whose ticks are aligned to `granularity`.
+    pub fn new(granularity: Duration, metrics: Metrics) -> Scheduler {
+        Scheduler {
+            granularity,
+            jobs: vec![],
+            metrics,
+        }
+    }
+
+    /// Adds a job to the scheduler.
+    pub fn add_job(&mut self, job: Job) {
+        self.jobs.push(job);
+    }
+
+    /// Runs jobs as they come due, until `tripwire` fires or no jobs remain.
+    ///
+    /// Runs that are in progress when the tripwire fires are not canceled.
+    pub async fn run(mut self, mut tripwire: oneshot::Receiver<()>) {
+        let start = Instant::now();
+        loop {
+            let next_run = match self.jobs.iter().map(|job| job.next_run).min() {
+                None => return,
+                Some(next_run) => next_run,
+            };
+            tokio::select! {
+                _ = time::sleep_until(self.align(start, next_run)) => (),
+                _ = &mut tripwire => return,
+            }
+            self.metrics.wakeups.inc();
+
+            let now = Instant::now();
+            for job in &mut self.jobs {
+                if job.next_run > now {
+                    continue;
+                }
+                job.start(&self.metrics);
+                if let Some(period) = &mut job.period {
+                    let period = period();
+                    job.next_run += period;
+                    // If the scheduler has fallen behind, skip the missed runs
+                    // rather than running the job repeatedly to catch up.
+                    if job.next_run <= now {
+                        job.next_run = now + period;
+                    }
+                }
+            }
+            self.jobs
+                .retain(|job| job.period.is_some() || job.next_run > now);
+        }
+    }
+
+    /// Returns the first tick, counting in steps of the granularity from
+    /// `start`, that is no earlier than `instant`.
+    fn align(&self, start: Instant, instant: Instant) -> Instant {
+        let granularity = self.granularity.as_nanos();
+        let offset = instant.saturating_duration_since(start).as_nanos();
+        let ticks = (offset + granularity - 1) / granularity;
+        start + Duration::from_nanos(u64::try_from(ticks * granularity).unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::future::{self, Ready};
+    use tokio::runtime::{self, Runtime};
+    use tokio::sync::oneshot;
+    use tokio::time;
+
+    use ore::metrics::MetricsRegistry;
+
+    use super::{Job, Metrics, Scheduler};
+
+    /// Returns a job body that counts its runs, along with the count.
+    fn counter() -> (impl FnMut() -> Ready<()> + Send + 'static, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let run = {
+            let count = Arc::clone(&count);
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                future::ready(())
+            }
+        };
+        (run, count)
+    }
+
+    /// Returns a runtime whose clock is paused, so that time advances only
+    /// when every task is idle, and jumps straight to the next timer.
+    fn paused_runtime() -> Runtime {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_frequencies_and_coalescing() {
+        let metrics = Metrics::register_into(&MetricsRegistry::new());
+        let (fast_runs, slow_runs, once_runs) = paused_runtime().block_on(async {
+            let mut scheduler = Scheduler::new(Duration::from_millis(100), metrics.clone());
+            let (fast, fast_count) = counter();
+            let (slow, slow_count) = counter();
+            let (once, once_count) = counter();
+            scheduler.add_job(Job::every("fast", Duration::from_millis(100), fast));
+            scheduler.add_job(Job::every("slow", Duration::from_millis(200), slow));
+            scheduler.add_job(Job::once("once", once));
+
+            let (trigger, tripwire) = oneshot::channel();
+            tokio::spawn(scheduler.run(tripwire));
+            time::sleep(Duration::from_millis(1050)).await;
+            drop(trigger);
+            (
+                fast_count.load(Ordering::SeqCst),
+                slow_count.load(Ordering::SeqCst),
+                once_count.load(Ordering::SeqCst),
+            )
+        });
+
+        // Each job runs immediately and then at its own frequency: at 0ms,
+        // 100ms, ..., 1000ms for the fast job, and at 0ms, 200ms, ..., 1000ms
+        // for the slow job.
+        assert_eq!(fast_runs, 11);
+        assert_eq!(slow_runs, 6);
+        assert_eq!(once_runs, 1);
+
+        // Every run of the slow job coincided with a run of the fast job, so
+        // the scheduler woke once per run of the fast job.
+        assert_eq!(metrics.wakeups.get(), u64::try_from(fast_runs).unwrap());
+    }
+
+    #[test]
+    fn test_slow_job_does_not_delay_others() {
+        let metrics = Metrics::register_into(&MetricsRegistry::new());
+        let fast_runs = paused_runtime().block_on(async {
+            let mut scheduler = Scheduler::new(Duration::from_millis(50), metrics.clone());
+            scheduler.add_job(Job::every("stuck", Duration::from_millis(50), || {
+                time::sleep(Duration::from_secs(3600))
+            }));
+            let (fast, fast_count) = counter();
+            scheduler.add_job(Job::every("fast", Duration::from_millis(50), fast));
+
+            let (trigger, tripwire) = oneshot::channel();
+            tokio::spawn(scheduler.run(tripwire));
+            time::sleep(Duration::from_millis(475)).await;
+            drop(trigger);
+            fast_count.load(Ordering::SeqCst)
+        });
+
+        // The fast job ran at 0ms, 50ms, ..., 450ms, while the stuck job ran
+        // once and skipped each later run.
+        assert_eq!(fast_runs, 10);
+        assert_eq!(metrics.skipped_runs.with_label_values(&["stuck"]).get(), 9);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! A self test that verifies the query path of a freshly started server.
+//!
+//! The self test creates a table, inserts into it, queries it back through a
+//! materialized view, and scrapes the server's own metrics endpoint. The
+//! objects it creates are temporary, so they vanish when the self test's
+//! session ends and leave no trace in the catalog.
+
+use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use serde::Serialize;
+
+use coord::session::Session;
+
+use crate::lifecycle;
+
+/// The statements that the self test executes, each of which is a step
+/// named by the first element of its tuple.
+const STATEMENTS: &[(&str, &str)] = &[
+    (
+        "create_table",
+        "CREATE TEMPORARY TABLE mz_self_test (a int)",
+    ),
+    (
+        "create_view",
+        "CREATE TEMPORARY MATERIALIZED VIEW mz_self_test_sum AS SELECT sum(a)::text FROM mz_self_test",
+    ),
+    ("insert", "INSERT INTO mz_self_test VALUES (1), (2), (3)"),
+];
+
+/// The outcome of a self test.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestReport {
+    /// Whether every step passed.
+    pub passed: bool,
+    /// The steps that ran, in order. The self test stops at the first step
+    /// that fails.
+    pub steps: Vec<SelfTestStep>,
+}
+
+impl SelfTestReport {
+    /// Returns the step that failed, if any.
+    pub fn failed_step(&self) -> Option<&SelfTestStep> {
+        self.steps.iter().find(|step| step.error.is_some())
+    }
+}
+
+/// The outcome of one step of a self test.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    /// The name of the step.
+    pub name: &'static str,
+    /// How long the step took, in milliseconds.
+    pub duration_ms: u128,
+    /// The reason the step failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Runs the self test against the server listening on `local_addr`, which is
+/// served by the coordinator behind `coord_client`.
+pub async fn run(coord_client: coord::Client, local_addr: SocketAddr) -> SelfTestReport {
+    let mut report = SelfTestReport {
+        passed: true,
+        steps: vec![],
+    };
+
+    let mut conn_client = match step(&mut report, "connect", async {
+        let conn_client = coord_client.new_conn()?;
+        let session = Session::new(conn_client.conn_id(), "mz_system".into());
+        Ok(conn_client.startup(session).await?.0)
+    })
+    .await
+    {
+        Some(conn_client) => conn_client,
+        None => return finish(report),
+    };
+
+    let mut passed = true;
+    for &(name, sql) in STATEMENTS {
+        let res = step(&mut report, name, async {
+            conn_client.simple_execute(sql).await?;
+            Ok(())
+        })
+        .await;
+        if res.is_none() {
+            passed = false;
+            break;
+        }
+    }
+    if passed {
+        passed = step(&mut report, "query", async {
+            // The view may take a moment to reflect the insert.
+            let deadline = Instant::now() + Duration::from_secs(30);
+            loop {
+                let res = conn_client
+                    .simple_execute("SELECT * FROM mz_self_test_sum")
+                    .await?;
+                let rows = &res.results[0].rows;
+                if rows.len() == 1 && rows[0].len() == 1 && rows[0][0] == "6" {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    bail!("unexpected result from view: {:?}", rows);
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .is_some();
+    }
+    conn_client.terminate().await;
+
+    if passed {
+        step(&mut report, "scrape_metrics", scrape_metrics(local_addr)).await;
+    }
+    finish(report)
+}
+
+/// Runs one step of the self test, recording its outcome in `report`.
+/// Returns the step's output, if it passed.
+async fn step<F, T>(report: &mut SelfTestReport, name: &'static str, f: F) -> Option<T>
+where
+    F: Future<Output = Result<T, anyhow::Error>>,
+{
+    let start = Instant::now();
+    let res = f.await;
+    let duration = start.elapsed();
+    let error = res.as_ref().err().map(|e| format!("{:#}", e));
+    lifecycle::self_test_step(name, error.as_deref(), duration);
+    report.passed &= error.is_none();
+    report.steps.push(SelfTestStep {
+        name,
+        duration_ms: duration.as_millis(),
+        error,
+    });
+    res.ok()
+}
+
+fn finish(report: SelfTestReport) -> SelfTestReport {
+    lifecycle::self_test_finished(report.failed_step().map(|step| step.name));
+    report
+}
+
+async fn scrape_metrics(mut addr: SocketAddr) -> Result<(), anyhow::Error> {
+    // The server may be listening on all interfaces, in which case it is
+    // reachable via the loopback interface.
+    if addr.ip().is_unspecified() {
+        match addr {
+            SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    let client = reqwest::Client::builder().no_proxy().build()?;
+    let res = client
+        .get(&format!("http://{}/metrics", addr))
+        .send()
+        .await?
+        .error_for_status()?;
+    let body = res.text().await?;
+    if !body.contains("# TYPE mz_") {
+        bail!("metrics endpoint reported no metrics");
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Metrics that describe how far the timestamps of sources trail the wall
+//! clock.
+//!
+//! A source whose timestamper falls behind, because its upstream system is
+//! unhealthy or because decoding is slow, otherwise shows up only as queries
+//! that return stale data.
+
+use std::sync::{Arc, Mutex};
+
+use coord::SourceTimestampLag;
+use ore::metric;
+use ore::metrics::{Gauge, GaugeVec, MetricsRegistry};
+
+/// Metrics that describe the timestamping lag of each source in the catalog.
+///
+/// Clones share the same metrics.
+#[derive(Debug, Clone)]
+pub struct SourceLagMetrics {
+    lag: GaugeVec,
+    max_lag: Gauge,
+    latest: Arc<Mutex<Vec<SourceTimestampLag>>>,
+}
+
+impl SourceLagMetrics {
+    /// Registers the source lag metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> SourceLagMetrics {
+        SourceLagMetrics {
+            lag: registry.register(metric!(
+                name: "mz_source_timestamp_lag_seconds",
+                help: "the time since the source's most recently closed timestamp",
+                var_labels: ["source"],
+            )),
+            max_lag: registry.register(metric!(
+                name: "mz_source_timestamp_lag_seconds_max",
+                help: "the greatest timestamping lag of any source",
+            )),
+            latest: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Records the lags most recently reported by the coordinator.
+    ///
+    /// Sources that have been dropped, or whose lag is not currently
+    /// meaningful, are removed from the per-source metric, so that its labels
+    /// are bounded by the sources in the catalog.
+    pub fn update(&self, lags: Vec<SourceTimestampLag>) {
+        let mut latest = self.latest.lock().expect("lock poisoned");
+        for old in latest.iter() {
+            let still_lagging = lags
+                .iter()
+                .any(|new| new.name == old.name && new.lag_ms.is_some());
+            if old.lag_ms.is_some() && !still_lagging {
+                let _ = self.lag.remove_label_values(&[&old.name]);
+            }
+        }
+        let mut max_lag_ms = 0;
+        for lag in &lags {
+            if let Some(lag_ms) = lag.lag_ms {
+                self.lag
+                    .with_label_values(&[&lag.name])
+                    .set(lag_ms as f64 / 1000.0);
+                max_lag_ms = max_lag_ms.max(lag_ms);
+            }
+        }
+        self.max_lag.set(max_lag_ms as f64 / 1000.0);
+        *latest = lags;
+    }
+
+    /// Returns the lags most recently recorded by [`SourceLagMetrics::update`].
+    pub fn latest(&self) -> Vec<SourceTimestampLag> {
+        self.latest.lock().expect("lock poisoned").clone()
+    }
+
+    /// Returns the greatest lag most recently recorded, in milliseconds, or
+    /// `None` if no source has a meaningful lag.
+    pub fn max_lag_ms(&self) -> Option<u64> {
+        self.latest
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter_map(|lag| lag.lag_ms)
+            .max()
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Startup progress.
+//!
+//! The server accepts connections as soon as its listener is bound, well
+//! before the coordinator has booted. Until then, connections are answered
+//! with an error that reports how far startup has progressed.
+//!
+//! If startup does not complete within the startup timeout, the server gives
+//! up and reports the phase that was in progress, as recorded by the same
+//! [`StartupProgress`] that answers those connections.
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::time;
+
+/// A step in starting the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for the server process whose listener this server took over
+    /// to exit.
+    AwaitingPredecessor,
+    /// Restoring a catalog backup and preparing the scratch directory.
+    Preparing,
+    /// Checking whether the catalog needs to be migrated.
+    CheckingCatalog,
+    /// Booting the coordinator, which opens and, if necessary, migrates the
+    /// catalog.
+    BootingCoordinator,
+    /// The server is ready.
+    Ready,
+}
+
+impl Phase {
+    /// Returns the name of the phase, as reported to clients.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::AwaitingPredecessor => "awaiting_predecessor",
+            Phase::Preparing => "preparing",
+            Phase::CheckingCatalog => "checking_catalog",
+            Phase::BootingCoordinator => "booting_coordinator",
+            Phase::Ready => "ready",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tracks the progress of starting the server.
+///
+/// Clones share the same progress.
+#[derive(Debug, Clone)]
+pub struct StartupProgress {
+    start_instant: Instant,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    phase: Phase,
+    /// When the current phase began.
+    phase_instant: Instant,
+    /// When the server became ready, if it has.
+    ready_instant: Option<Instant>,
+    /// The time spent in [`StartupProgress::excluding`], which does not count
+    /// against the startup timeout.
+    excluded: Duration,
+}
+
+impl StartupProgress {
+    /// Begins tracking startup progress from `start_instant`.
+    pub fn new(start_instant: Instant) -> StartupProgress {
+        StartupProgress {
+            start_instant,
+            inner: Arc::new(Mutex::new(Inner {
+                phase: Phase::Preparing,
+                phase_instant: start_instant,
+                ready_instant: None,
+                excluded: Duration::from_secs(0),
+            })),
+        }
+    }
+
+    /// Records that startup has reached `phase`.
+    pub fn set(&self, phase: Phase) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        if inner.phase != phase {
+            inner.phase = phase;
+            inner.phase_instant = Instant::now();
+        }
+        if phase == Phase::Ready {
+            inner.ready_instant.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Returns the current phase.
+    pub fn phase(&self) -> Phase {
+        self.inner.lock().expect("lock poisoned").phase
+    }
+
+    /// Returns how long startup has taken so far, or how long it took in
+    /// total, if the server is ready.
+    pub fn elapsed(&self) -> Duration {
+        match self.inner.lock().expect("lock poisoned").ready_instant {
+            Some(ready_instant) => ready_instant - self.start_instant,
+            None => self.start_instant.elapsed(),
+        }
+    }
+
+    /// Awaits `future` without counting the time it takes against the startup
+    /// timeout.
+    pub async fn excluding<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        let start = Instant::now();
+        let output = future.await;
+        self.inner.lock().expect("lock poisoned").excluded += start.elapsed();
+        output
+    }
+
+    /// Awaits `future`, unless `timeout` elapses, measured from the start of
+    /// startup but not counting the time spent in
+    /// [`StartupProgress::excluding`], before it completes. If `timeout` is
+    /// `None`, waits indefinitely.
+    ///
+    /// If the timeout elapses, `future` is dropped, and the returned error
+    /// reports the phase that was in progress.
+    pub async fn within<F>(
+        &self,
+        timeout: Option<Duration>,
+        future: F,
+    ) -> Result<F::Output, StartupTimeoutError>
+    where
+        F: Future,
+    {
+        let timeout = match timeout {
+            None => return Ok(future.await),
+            Some(timeout) => timeout,
+        };
+        let excluded = self.inner.lock().expect("lock poisoned").excluded;
+        let deadline = time::Instant::from_std(self.start_instant + excluded + timeout);
+        match time::timeout_at(deadline, future).await {
+            Ok(output) => Ok(output),
+            Err(_) => {
+                let inner = self.inner.lock().expect("lock poisoned");
+                Err(StartupTimeoutError {
+                    timeout,
+                    phase: inner.phase,
+                    phase_elapsed: inner.phase_instant.elapsed(),
+                })
+            }
+        }
+    }
+}
+
+/// The error returned when the server does not start within its startup
+/// timeout.
+#[derive(Debug, Clone)]
+pub struct StartupTimeoutError {
+    /// The startup timeout.
+    pub timeout: Duration,
+    /// The phase of startup that was in progress when the timeout elapsed.
+    pub phase: Phase,
+    /// How long that phase had been in progress.
+    pub phase_elapsed: Duration,
+}
+
+impl fmt::Display for StartupTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "server did not start within {:?}: phase {} still in progress after {:?}",
+            self.timeout, self.phase, self.phase_elapsed
+        )
+    }
+}
+
+impl Error for StartupTimeoutError {}
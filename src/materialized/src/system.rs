@@ -0,0 +1,329 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Information about the system on which the server runs.
+//!
+//! Collecting the information can take hundreds of milliseconds on machines
+//! with many CPUs, so the server collects it in the background rather than
+//! before it starts accepting connections. The [`HostEnvironment`], which
+//! consists of a few cheap probes, is instead collected at startup.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use sysinfo::{ProcessorExt, SystemExt};
+
+use ore::metric;
+use ore::metrics::{Gauge, MetricsRegistry, UIntGauge};
+
+/// Information about the system on which the server runs.
+#[derive(Debug, Clone)]
+pub struct SystemInfo {
+    /// The operating system.
+    pub os: String,
+    /// A description of the first CPU.
+    pub cpu0: String,
+    /// The total amount of memory, in kilobytes.
+    pub memory_total: u64,
+    /// The amount of memory in use, in kilobytes.
+    pub memory_used: u64,
+    /// The total amount of swap, in kilobytes.
+    pub swap_total: u64,
+    /// The amount of swap in use, in kilobytes.
+    pub swap_used: u64,
+}
+
+impl SystemInfo {
+    /// Collects information about the system.
+    ///
+    /// This function blocks while it scans the system.
+    pub fn collect() -> SystemInfo {
+        let mut system = sysinfo::System::new();
+        system.refresh_system();
+        let cpu0 = match &system.processors().get(0) {
+            None => "<unknown>".to_string(),
+            Some(cpu0) => format!("{} {}MHz", cpu0.brand(), cpu0.frequency()),
+        };
+        SystemInfo {
+            os: os_info::get().to_string(),
+            cpu0,
+            memory_total: system.total_memory(),
+            memory_used: system.used_memory(),
+            swap_total: system.total_swap(),
+            swap_used: system.used_swap(),
+        }
+    }
+}
+
+/// The value reported for a fact about the host environment that cannot be
+/// determined.
+const UNKNOWN: &str = "unknown";
+
+/// Facts about the environment in which the server runs that commonly
+/// explain its behavior, like its resource limits and kernel settings.
+///
+/// Each fact is reported as a string. Facts that cannot be determined,
+/// including those that do not apply to the platform, are reported as
+/// `"unknown"`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostEnvironment {
+    /// The soft limit on the number of open file descriptors.
+    pub nofile_soft_limit: String,
+    /// The hard limit on the number of open file descriptors.
+    pub nofile_hard_limit: String,
+    /// The soft limit on the size of the address space, in bytes.
+    pub address_space_soft_limit: String,
+    /// The hard limit on the size of the address space, in bytes.
+    pub address_space_hard_limit: String,
+    /// The memory allocator, either `jemalloc` or `system`.
+    pub allocator: String,
+    /// The mode of transparent huge pages, like `madvise`.
+    pub transparent_hugepages: String,
+    /// The type of the filesystem that hosts the data directory, like `ext4`.
+    pub data_directory_filesystem: String,
+    /// The kernel's clock source, like `tsc`.
+    pub clocksource: String,
+}
+
+impl HostEnvironment {
+    /// Collects facts about the environment, including the filesystem that
+    /// hosts `data_directory`.
+    ///
+    /// This function performs blocking I/O, but does not scan the system.
+    pub fn collect(data_directory: &Path) -> HostEnvironment {
+        let (nofile_soft_limit, nofile_hard_limit) = rlimits(Rlimit::Nofile);
+        let (address_space_soft_limit, address_space_hard_limit) = rlimits(Rlimit::AddressSpace);
+        HostEnvironment {
+            nofile_soft_limit,
+            nofile_hard_limit,
+            address_space_soft_limit,
+            address_space_hard_limit,
+            allocator: if cfg!(target_os = "macos") {
+                "system"
+            } else {
+                "jemalloc"
+            }
+            .into(),
+            transparent_hugepages: read_sys("/sys/kernel/mm/transparent_hugepage/enabled")
+                .map(|modes| selected_mode(&modes))
+                .unwrap_or_else(|| UNKNOWN.into()),
+            data_directory_filesystem: fs::read_to_string("/proc/mounts")
+                .ok()
+                .and_then(|mounts| {
+                    let path = fs::canonicalize(data_directory)
+                        .unwrap_or_else(|_| data_directory.to_owned());
+                    filesystem_type(&mounts, &path)
+                })
+                .unwrap_or_else(|| UNKNOWN.into()),
+            clocksource: read_sys(
+                "/sys/devices/system/clocksource/clocksource0/current_clocksource",
+            )
+            .unwrap_or_else(|| UNKNOWN.into()),
+        }
+    }
+}
+
+/// A resource limit reported in the [`HostEnvironment`].
+enum Rlimit {
+    Nofile,
+    AddressSpace,
+}
+
+/// Returns the soft and hard values of the specified resource limit.
+#[cfg(any(target_os = "macos", target_os = "linux", target_os = "ios"))]
+fn rlimits(which: Rlimit) -> (String, String) {
+    use rlimit::Resource;
+
+    let resource = match which {
+        Rlimit::Nofile => Resource::NOFILE,
+        Rlimit::AddressSpace => Resource::AS,
+    };
+    let describe = |limit: u64| -> String {
+        match limit {
+            rlimit::INFINITY => "unlimited".into(),
+            limit => limit.to_string(),
+        }
+    };
+    match resource.get() {
+        Ok((soft, hard)) => (describe(soft), describe(hard)),
+        Err(_) => (UNKNOWN.into(), UNKNOWN.into()),
+    }
+}
+
+/// Returns the soft and hard values of the specified resource limit.
+///
+/// The rlimit crate does not support this platform, so the limits are
+/// unknown.
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "ios")))]
+fn rlimits(_: Rlimit) -> (String, String) {
+    (UNKNOWN.into(), UNKNOWN.into())
+}
+
+/// Reads a single-line kernel setting from `/sys`, if it exists.
+fn read_sys(path: &str) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    Some(value.trim().to_owned()).filter(|v| !v.is_empty())
+}
+
+/// Returns the selected mode from a list of modes in which the selected mode
+/// is bracketed, like `always [madvise] never`.
+fn selected_mode(modes: &str) -> String {
+    modes
+        .split_whitespace()
+        .find_map(|mode| mode.strip_prefix('[')?.strip_suffix(']'))
+        .unwrap_or(modes)
+        .to_owned()
+}
+
+/// Returns the type of the filesystem that hosts `path`, according to
+/// `mounts`, which is in the format of `/proc/mounts`.
+///
+/// The filesystem is the one mounted at the longest mount point that
+/// contains `path`.
+fn filesystem_type(mounts: &str, path: &Path) -> Option<String> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = unescape_mount_point(fields.nth(1)?);
+            let fs_type = fields.next()?;
+            Some((mount_point, fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, fs_type)| fs_type.to_owned())
+}
+
+/// Decodes the octal escapes, like `\040` for a space, with which
+/// `/proc/mounts` encodes whitespace in mount points.
+fn unescape_mount_point(s: &str) -> PathBuf {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let escape = rest.get(i + 1..i + 4);
+        match escape.and_then(|e| u8::from_str_radix(e, 8).ok()) {
+            Some(b) => {
+                out.push(char::from(b));
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    PathBuf::from(out)
+}
+
+/// Metrics that describe the memory and load of the host on which the server
+/// runs.
+///
+/// Unlike [`SystemInfo`], which is collected once, the metrics are refreshed
+/// by [`HostMetrics::update`]. Only the memory and load figures are
+/// refreshed, not the process table, so updating is cheap.
+#[derive(Debug, Clone)]
+pub struct HostMetrics {
+    system: Arc<Mutex<sysinfo::System>>,
+    memory_free: UIntGauge,
+    memory_available: UIntGauge,
+    swap_total: UIntGauge,
+    swap_used: UIntGauge,
+    load1: Gauge,
+    load5: Gauge,
+    load15: Gauge,
+}
+
+impl HostMetrics {
+    /// Registers the host metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> HostMetrics {
+        HostMetrics {
+            system: Arc::new(Mutex::new(sysinfo::System::new())),
+            memory_free: registry.register(metric!(
+                name: "mz_host_memory_free_bytes",
+                help: "the amount of memory on the host that is unused",
+            )),
+            memory_available: registry.register(metric!(
+                name: "mz_host_memory_available_bytes",
+                help: "the amount of memory on the host that is available for new \
+                       allocations, including reclaimable caches",
+            )),
+            swap_total: registry.register(metric!(
+                name: "mz_host_swap_total_bytes",
+                help: "the total amount of swap on the host",
+            )),
+            swap_used: registry.register(metric!(
+                name: "mz_host_swap_used_bytes",
+                help: "the amount of swap on the host that is in use",
+            )),
+            load1: registry.register(metric!(
+                name: "mz_host_load1",
+                help: "the host's load average over the last minute",
+            )),
+            load5: registry.register(metric!(
+                name: "mz_host_load5",
+                help: "the host's load average over the last five minutes",
+            )),
+            load15: registry.register(metric!(
+                name: "mz_host_load15",
+                help: "the host's load average over the last fifteen minutes",
+            )),
+        }
+    }
+
+    /// Refreshes the host's memory and load figures.
+    ///
+    /// This function performs blocking I/O.
+    pub fn update(&self) {
+        let mut system = self.system.lock().expect("lock poisoned");
+        system.refresh_memory();
+        // sysinfo reports memory in kilobytes.
+        self.memory_free.set(system.free_memory() * 1024);
+        self.memory_available.set(system.available_memory() * 1024);
+        self.swap_total.set(system.total_swap() * 1024);
+        self.swap_used.set(system.used_swap() * 1024);
+        let load = system.load_average();
+        self.load1.set(load.one);
+        self.load5.set(load.five);
+        self.load15.set(load.fifteen);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{filesystem_type, selected_mode};
+
+    #[test]
+    fn test_selected_mode() {
+        assert_eq!(selected_mode("always [madvise] never"), "madvise");
+        assert_eq!(selected_mode("[always] madvise never"), "always");
+        assert_eq!(selected_mode("never"), "never");
+    }
+
+    #[test]
+    fn test_filesystem_type() {
+        let mounts = "\
+/dev/root / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+/dev/nvme1n1 /mnt/data xfs rw,noatime 0 0
+/dev/nvme2n1 /mnt/data\\040disk zfs rw 0 0
+";
+        let fs = |path| filesystem_type(mounts, Path::new(path));
+        assert_eq!(fs("/mnt/data/mzdata").as_deref(), Some("xfs"));
+        assert_eq!(fs("/mnt/data disk/mzdata").as_deref(), Some("zfs"));
+        assert_eq!(fs("/mnt/database").as_deref(), Some("ext4"));
+        assert_eq!(fs("/home/mz").as_deref(), Some("ext4"));
+        assert_eq!(fs("mzdata"), None);
+    }
+}
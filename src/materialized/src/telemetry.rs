@@ -8,18 +8,52 @@
 // by the Apache License, Version 2.0.
 
 //! Telemetry collection.
+//!
+//! To keep reports small, each report is sent as a delta against the last
+//! report that the telemetry server acknowledged, rather than as a full
+//! snapshot of the data. Reports are numbered consecutively within each
+//! session of the server, which is identified by its session ID, and each
+//! delta names the report against which it was computed, so that the
+//! telemetry server can detect a gap and ask for a full snapshot in its
+//! response. A full snapshot is also sent at least every
+//! [`FULL_SNAPSHOT_INTERVAL`], and whenever the previous attempt to send a
+//! report failed, as the telemetry server may or may not have received it.
+//!
+//! Reports that cannot be sent may instead be kept in a [`Spool`] on disk,
+//! from which they are sent once the telemetry server is reachable again, or
+//! downloaded via the HTTP API for offline export.
 //
 // WARNING: The code in this module must be tested manually. Please see
 // misc/python/cli/mock_telemetry_server.py for details.
 
-use log::{debug, log, Level};
-use serde::Deserialize;
-use tokio::time::{self, Duration};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context};
+use log::{debug, info, log, warn, Level};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tokio::task;
+use tokio::time::{self, Duration, Instant};
 use uuid::Uuid;
 
-use ore::retry::Retry;
+use ore::cast::CastFrom;
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounter, UIntCounterVec, UIntGauge};
 
-use crate::BUILD_INFO;
+use crate::scheduler::Job;
+use crate::{TelemetryMode, TelemetryProxyConfig, BUILD_INFO};
 
 /// Telemetry configuration.
 pub struct Config {
@@ -27,46 +61,700 @@ pub struct Config {
     pub domain: String,
     /// How often to report telemetry data.
     pub interval: Duration,
+    /// What to do with each telemetry report.
+    pub mode: TelemetryMode,
+    /// The maximum amount of time to wait to establish a connection to the
+    /// telemetry server.
+    pub connect_timeout: Duration,
+    /// The maximum amount of time to wait for the telemetry server to respond
+    /// to a report.
+    pub request_timeout: Duration,
+    /// An explicit proxy through which to send reports.
+    ///
+    /// If absent, the system proxy configuration is used. See the
+    /// [`http_util`] crate for details.
+    pub proxy: Option<TelemetryProxyConfig>,
+    /// Whether to derive the random delays between reports from the cluster
+    /// ID, rather than from a source of entropy.
+    ///
+    /// Intended for use in tests, which require reproducible behavior.
+    pub deterministic_splay: bool,
+    /// Static tags that describe the instance, included in each report.
+    pub tags: BTreeMap<String, String>,
+    /// The file to which logged reports are appended, if any.
+    ///
+    /// Only consulted when `mode` logs reports. If absent, reports are
+    /// written to the server log instead.
+    pub log_file: Option<PathBuf>,
     /// The ID of the Materialize cluster.
     pub cluster_id: Uuid,
     /// A client for the coordinator to introspect.
     pub coord_client: coord::Client,
+    /// Whether reporting is currently enabled, as controlled by a [`Toggle`].
+    pub enabled: watch::Receiver<bool>,
+    /// The spool in which to keep reports that cannot be sent, if any.
+    ///
+    /// If `mode` is [`TelemetryMode::Spool`], every report is spooled.
+    pub spool: Option<Spool>,
+    /// Metrics about the reporting loop.
+    pub metrics: Metrics,
+}
+
+/// Metrics about the telemetry reporting loop.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    /// The number of reporting intervals, by outcome.
+    ///
+    /// Failures caused by the proxy rejecting our credentials are counted
+    /// separately from other failures, to help direct debugging efforts.
+    reports: UIntCounterVec,
+    /// The number of retried attempts to send a report.
+    retries: UIntCounter,
+    /// The number of consecutive reporting intervals that failed.
+    missed_reports: UIntGauge,
+    /// The number of reports dropped from the spool to bound its size.
+    spool_dropped: UIntCounter,
+}
+
+impl Metrics {
+    /// Registers the telemetry metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            reports: registry.register(metric!(
+                name: "mz_telemetry_reports_total",
+                help: "number of telemetry reporting intervals, by outcome",
+                var_labels: ["status"],
+            )),
+            retries: registry.register(metric!(
+                name: "mz_telemetry_retries_total",
+                help: "number of retried attempts to send a telemetry report",
+            )),
+            missed_reports: registry.register(metric!(
+                name: "mz_telemetry_missed_reports",
+                help: "number of consecutive telemetry reporting intervals that failed",
+            )),
+            spool_dropped: registry.register(metric!(
+                name: "mz_telemetry_spool_dropped_total",
+                help: "number of telemetry reports dropped from the spool to bound its size",
+            )),
+        }
+    }
+}
+
+/// The maximum fraction of the reporting interval by which each tick of the
+/// reporting loop is randomly advanced or delayed.
+const TICK_JITTER: f64 = 0.1;
+
+/// The initial backoff after a failed attempt to send a report.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum amount of time between full snapshots.
+const FULL_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The name of the file in the data directory that stores the persisted
+/// telemetry preference.
+pub const PREFERENCE_FILE: &str = "telemetry_preference";
+
+/// Describes why telemetry reporting is enabled or disabled.
+///
+/// The sources of truth are consulted in the following order of precedence:
+///
+///   1. The environment. Setting `DO_NOT_TRACK` to a truthy value or
+///      `MZ_TELEMETRY` to `off` disables telemetry, and the reporting loop is
+///      never started.
+///   2. The preference persisted in the data directory by a [`Toggle`].
+///   3. The server configuration.
+///
+/// A persisted preference to enable telemetry only takes effect if telemetry
+/// is configured, as the configuration supplies the telemetry endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Disabled by the named environment variable.
+    DisabledByEnvironment(&'static str),
+    /// Disabled by the persisted preference.
+    DisabledByPreference,
+    /// Enabled by the persisted preference.
+    EnabledByPreference,
+    /// Disabled by the server configuration.
+    DisabledByConfig,
+    /// Enabled by the server configuration.
+    EnabledByConfig,
+}
+
+impl Decision {
+    /// Determines whether telemetry is enabled from the environment opt-out
+    /// variable, if any, the persisted preference, if any, and whether
+    /// telemetry is configured.
+    pub fn resolve(
+        env_opt_out: Option<&'static str>,
+        preference: Option<bool>,
+        configured: bool,
+    ) -> Decision {
+        match (env_opt_out, preference, configured) {
+            (Some(var), _, _) => Decision::DisabledByEnvironment(var),
+            (None, Some(false), _) => Decision::DisabledByPreference,
+            (None, Some(true), true) => Decision::EnabledByPreference,
+            (None, _, true) => Decision::EnabledByConfig,
+            (None, _, false) => Decision::DisabledByConfig,
+        }
+    }
+
+    /// Reports whether telemetry reporting is enabled.
+    pub fn enabled(&self) -> bool {
+        matches!(
+            self,
+            Decision::EnabledByPreference | Decision::EnabledByConfig
+        )
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decision::DisabledByEnvironment(var) => {
+                write!(f, "telemetry disabled by the {} environment variable", var)
+            }
+            Decision::DisabledByPreference => {
+                f.write_str("telemetry disabled by preference stored in data directory")
+            }
+            Decision::EnabledByPreference => {
+                f.write_str("telemetry enabled by preference stored in data directory")
+            }
+            Decision::DisabledByConfig => f.write_str("telemetry disabled by configuration"),
+            Decision::EnabledByConfig => f.write_str("telemetry enabled by configuration"),
+        }
+    }
+}
+
+/// Returns the name of the environment variable that opts out of telemetry,
+/// if any such variable is set.
+pub fn env_opt_out() -> Option<&'static str> {
+    if ore::env::is_var_truthy("DO_NOT_TRACK") {
+        Some("DO_NOT_TRACK")
+    } else if matches!(
+        env::var("MZ_TELEMETRY").as_deref(),
+        Ok("off") | Ok("false") | Ok("0")
+    ) {
+        Some("MZ_TELEMETRY")
+    } else {
+        None
+    }
+}
+
+/// Loads the telemetry preference persisted in `data_directory`, if any.
+pub fn load_preference(data_directory: &Path) -> Result<Option<bool>, anyhow::Error> {
+    let path = data_directory.join(PREFERENCE_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => match contents.trim() {
+            "enabled" => Ok(Some(true)),
+            "disabled" => Ok(Some(false)),
+            other => bail!(
+                "invalid telemetry preference {:?} in {}",
+                other,
+                path.display()
+            ),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            Err(e).with_context(|| format!("reading telemetry preference: {}", path.display()))
+        }
+    }
+}
+
+/// A runtime switch for the telemetry reporting loop.
+///
+/// Changes made through the switch take effect immediately and are persisted
+/// to the data directory, so that they survive restarts.
+#[derive(Debug, Clone)]
+pub struct Toggle {
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+    data_directory: Option<PathBuf>,
+    env_opt_out: Option<&'static str>,
+}
+
+impl Toggle {
+    /// Constructs a new toggle whose initial state is described by
+    /// `decision`.
+    ///
+    /// If `data_directory` is `None`, as in read-only mode, the preference
+    /// cannot be persisted, and so cannot be changed.
+    ///
+    /// Returns the toggle and a receiver that observes its state.
+    pub fn new(
+        decision: Decision,
+        data_directory: Option<PathBuf>,
+    ) -> (Toggle, watch::Receiver<bool>) {
+        let (tx, rx) = watch::channel(decision.enabled());
+        let env_opt_out = match decision {
+            Decision::DisabledByEnvironment(var) => Some(var),
+            _ => None,
+        };
+        let toggle = Toggle {
+            tx: Arc::new(tx),
+            rx: rx.clone(),
+            data_directory,
+            env_opt_out,
+        };
+        (toggle, rx)
+    }
+
+    /// Reports whether telemetry reporting is currently enabled.
+    pub fn enabled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Enables or disables telemetry reporting and persists the preference.
+    ///
+    /// Fails if telemetry was disabled via the environment, as the
+    /// environment takes precedence over the preference, or if the server is
+    /// in read-only mode.
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), anyhow::Error> {
+        if let Some(var) = self.env_opt_out {
+            bail!("telemetry is disabled by the {} environment variable", var);
+        }
+        let data_directory = match &self.data_directory {
+            Some(data_directory) => data_directory,
+            None => bail!("the telemetry preference cannot be changed in read-only mode"),
+        };
+        let path = data_directory.join(PREFERENCE_FILE);
+        let contents = if enabled { "enabled" } else { "disabled" };
+        fs::write(&path, contents)
+            .with_context(|| format!("writing telemetry preference: {}", path.display()))?;
+        // The toggle holds a receiver, so sending cannot fail.
+        let _ = self.tx.send(enabled);
+        Ok(())
+    }
+}
+
+/// An on-disk queue of telemetry reports.
+///
+/// Each report is stored as JSON in a file of its own, named by an ID that
+/// orders the reports from oldest to newest. The reports survive restarts.
+/// Whenever the reports would exceed the spool's maximum size, the oldest
+/// reports are dropped.
+///
+/// Clones share the same spool. The spool's I/O is performed on the blocking
+/// thread pool.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    inner: Arc<Mutex<SpoolInner>>,
+}
+
+#[derive(Debug)]
+struct SpoolInner {
+    dir: PathBuf,
+    max_size: u64,
+    /// The ID and size of each report in the spool, oldest first.
+    entries: VecDeque<(u64, u64)>,
+    /// The total size of the reports in the spool.
+    size: u64,
+}
+
+impl SpoolInner {
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.json", id))
+    }
+
+    fn remove_oldest(&mut self) -> Result<(), anyhow::Error> {
+        if let Some((id, size)) = self.entries.pop_front() {
+            let path = self.path(id);
+            match fs::remove_file(&path) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("removing telemetry spool file: {}", path.display())
+                    })
+                }
+            }
+            self.size -= size;
+        }
+        Ok(())
+    }
+
+    fn read(&self, id: u64) -> Result<Vec<u8>, anyhow::Error> {
+        let path = self.path(id);
+        fs::read(&path).with_context(|| format!("reading telemetry spool file: {}", path.display()))
+    }
+
+    fn open(dir: PathBuf, max_size: u64) -> Result<SpoolInner, anyhow::Error> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating telemetry spool: {}", dir.display()))?;
+        let mut entries = vec![];
+        let read_dir = fs::read_dir(&dir)
+            .with_context(|| format!("reading telemetry spool: {}", dir.display()))?;
+        for entry in read_dir {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            match name.strip_suffix(".json").map(|id| id.parse::<u64>()) {
+                Some(Ok(id)) => entries.push((id, entry.metadata()?.len())),
+                // A report that was being written when the server stopped is
+                // incomplete.
+                _ if name.ends_with(".tmp") => {
+                    let _ = fs::remove_file(entry.path());
+                }
+                _ => (),
+            }
+        }
+        entries.sort_unstable();
+        let size = entries.iter().map(|(_, size)| size).sum();
+        Ok(SpoolInner {
+            dir,
+            max_size,
+            entries: entries.into(),
+            size,
+        })
+    }
+
+    fn push(&mut self, contents: Vec<u8>) -> Result<u64, anyhow::Error> {
+        let size = u64::cast_from(contents.len());
+        if size > self.max_size {
+            return Ok(1);
+        }
+        let id = self.entries.back().map_or(0, |(id, _)| id + 1);
+        let path = self.path(id);
+        // Write the report to a temporary file first, so that a crash cannot
+        // leave a partial report in the spool.
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, &contents)
+            .with_context(|| format!("writing telemetry spool file: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("writing telemetry spool file: {}", path.display()))?;
+        self.entries.push_back((id, size));
+        self.size += size;
+        let mut dropped = 0;
+        while self.size > self.max_size {
+            self.remove_oldest()?;
+            dropped += 1;
+        }
+        Ok(dropped)
+    }
+
+    fn oldest(&self) -> Result<Option<(u64, Vec<u8>)>, anyhow::Error> {
+        match self.entries.front() {
+            None => Ok(None),
+            Some((id, _)) => Ok(Some((*id, self.read(*id)?))),
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Result<(), anyhow::Error> {
+        if matches!(self.entries.front(), Some((oldest, _)) if *oldest == id) {
+            self.remove_oldest()?;
+        }
+        Ok(())
+    }
+
+    fn export(&mut self, clear: bool) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+        let reports = self
+            .entries
+            .iter()
+            .map(|(id, _)| self.read(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        if clear {
+            while !self.entries.is_empty() {
+                self.remove_oldest()?;
+            }
+        }
+        Ok(reports)
+    }
+}
+
+impl Spool {
+    /// Opens the spool in `dir`, creating the directory if necessary, and
+    /// recovers the reports that it already contains.
+    ///
+    /// The reports in the spool may total at most `max_size` bytes.
+    pub async fn open(dir: PathBuf, max_size: u64) -> Result<Spool, anyhow::Error> {
+        let inner = task::spawn_blocking(move || SpoolInner::open(dir, max_size)).await??;
+        Ok(Spool {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Appends `report` to the spool, dropping the oldest reports as
+    /// necessary to stay within the spool's maximum size.
+    ///
+    /// Returns the number of reports that were dropped, including `report`
+    /// itself if it alone exceeds the maximum size.
+    pub async fn push(&self, report: &Value) -> Result<u64, anyhow::Error> {
+        let contents = serde_json::to_vec(report)?;
+        self.with_inner(move |inner| inner.push(contents)).await
+    }
+
+    /// Returns the ID and contents of the oldest report in the spool, if any.
+    pub async fn oldest(&self) -> Result<Option<(u64, Vec<u8>)>, anyhow::Error> {
+        self.with_inner(|inner| inner.oldest()).await
+    }
+
+    /// Removes the report with ID `id`, if it is the oldest report in the
+    /// spool.
+    pub async fn remove(&self, id: u64) -> Result<(), anyhow::Error> {
+        self.with_inner(move |inner| inner.remove(id)).await
+    }
+
+    /// Returns the contents of the reports in the spool, oldest first.
+    ///
+    /// If `clear` is true, the returned reports are removed from the spool.
+    pub async fn export(&self, clear: bool) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+        self.with_inner(move |inner| inner.export(clear)).await
+    }
+
+    /// Runs `f` against the spool on a thread where blocking is allowed.
+    ///
+    /// The lock is only ever taken on such a thread, so a slow disk cannot
+    /// stall the async runtime, either directly or through a task waiting for
+    /// the lock.
+    async fn with_inner<F, T>(&self, f: F) -> Result<T, anyhow::Error>
+    where
+        F: FnOnce(&mut SpoolInner) -> Result<T, anyhow::Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        task::spawn_blocking(move || f(&mut inner.lock().expect("lock poisoned"))).await?
+    }
 }
 
-/// Runs the telemetry reporting loop.
+/// Constructs the telemetry reporting job.
 ///
-/// The loop ticks at the interval specified in `config.interval`. On each turn,
+/// The job runs at approximately the interval specified in `config.interval`.
+/// The first run occurs after a random delay of less than one interval, and
+/// each subsequent run is jittered by up to [`TICK_JITTER`] of the interval,
+/// so that many servers started at once do not report in lockstep. On each run,
 /// it reports anonymous metadata about the system to the server running at
 /// `config.domain`. If it learns of a new Materialize release in the process,
 /// it logs a notice.
-pub async fn report_loop(config: Config) {
-    let mut interval = time::interval(config.interval);
-    let mut reported_version = BUILD_INFO.semver_version();
-    loop {
-        interval.tick().await;
+///
+/// Depending on `config.mode`, the report may instead (or additionally) be
+/// logged locally, in which case no network request is made and no new version
+/// can be learned of.
+///
+/// Runs that occur while `config.enabled` is false are skipped. Disabling
+/// reporting abandons any in-progress report immediately.
+///
+/// Failed attempts to send a report are retried with exponential backoff and
+/// full jitter until the next interval begins. If all attempts fail, the
+/// next report records the number of missed reports, so that the telemetry
+/// server can account for the gap.
+///
+/// See the [module documentation](self) for when a report is sent as a delta
+/// rather than as a full snapshot.
+pub fn report_job(config: Config) -> Job {
+    let mut rng = if config.deterministic_splay {
+        let id = config.cluster_id.as_u128();
+        StdRng::seed_from_u64((id >> 64) as u64 ^ id as u64)
+    } else {
+        StdRng::from_entropy()
+    };
+    let splay = config.interval.mul_f64(rng.gen::<f64>());
+    let interval = config.interval;
+    let config = Arc::new(config);
+    let state = Arc::new(Mutex::new(ReportState {
+        reported_version: BUILD_INFO.semver_version(),
+        missed_reports: 0,
+        baseline: None,
+    }));
+    let sequence = Arc::new(AtomicU64::new(0));
+    Job::once("telemetry", move || {
+        report(
+            Arc::clone(&config),
+            Arc::clone(&state),
+            Arc::clone(&sequence),
+        )
+    })
+    .first_run_after(splay)
+    .with_period(move || {
+        let jitter = TICK_JITTER * (2.0 * rng.gen::<f64>() - 1.0);
+        interval.mul_f64(1.0 + jitter)
+    })
+}
 
-        let latest_version = match report_one(&config).await {
-            Ok(latest_version) => latest_version,
-            Err(e) => {
-                debug!("failed to report telemetry: {}", e);
-                continue;
+/// State carried from one run of the telemetry reporting job to the next.
+struct ReportState {
+    /// The newest version of which a notice has been logged.
+    reported_version: semver::Version,
+    /// The number of consecutive reports that failed to send.
+    missed_reports: u64,
+    /// The last report that the telemetry server acknowledged, if the next
+    /// report may be sent as a delta against it.
+    baseline: Option<Baseline>,
+}
+
+/// A report that the telemetry server acknowledged.
+#[derive(Debug, Clone)]
+struct Baseline {
+    /// The sequence number of the report.
+    sequence: u64,
+    /// The data in the report, as it would appear in a full snapshot.
+    data: Value,
+    /// When the last full snapshot was sent.
+    snapshot_at: Instant,
+}
+
+/// The outcome of successfully sending a report.
+struct Acknowledgment {
+    /// The newest release of Materialize, if the telemetry server reported
+    /// it.
+    latest_version: Option<semver::Version>,
+    /// The report against which the next report may be sent as a delta, if
+    /// any.
+    baseline: Option<Baseline>,
+}
+
+/// Performs one run of the telemetry reporting job.
+///
+/// Reports are numbered by `sequence`, which is shared by all runs.
+async fn report(config: Arc<Config>, state: Arc<Mutex<ReportState>>, sequence: Arc<AtomicU64>) {
+    if !*config.enabled.borrow() {
+        return;
+    }
+
+    let (missed_reports, baseline) = {
+        let state = state.lock().expect("lock poisoned");
+        let baseline = state
+            .baseline
+            .clone()
+            .filter(|baseline| baseline.snapshot_at.elapsed() < FULL_SNAPSHOT_INTERVAL);
+        (state.missed_reports, baseline)
+    };
+    let mut snapshot = None;
+    let mut request = None;
+    let report = report_one(
+        &config,
+        missed_reports,
+        &sequence,
+        baseline,
+        &mut snapshot,
+        &mut request,
+    );
+    let res = tokio::select! {
+        res = report => res,
+        _ = disabled(config.enabled.clone()) => {
+            debug!("abandoning telemetry report: reporting disabled");
+            return;
+        }
+    };
+    if let Some(request) = request {
+        log_report(&config, request).await;
+    }
+    if let Some(spool) = &config.spool {
+        match (&res, snapshot) {
+            (Err(_), Some(snapshot)) => spool_report(&config, spool, snapshot).await,
+            (Ok(_), Some(snapshot)) if config.mode == TelemetryMode::Spool => {
+                spool_report(&config, spool, snapshot).await
             }
+            (Ok(_), _) if config.mode.sends() => drain_spool(&config, spool).await,
+            _ => (),
+        }
+    }
+    let mut state = state.lock().expect("lock poisoned");
+    match &res {
+        Ok(ack) => {
+            config.metrics.reports.with_label_values(&["success"]).inc();
+            state.missed_reports = 0;
+            state.baseline = ack.baseline.clone();
+        }
+        Err(e) => {
+            let status = if is_proxy_auth_failure(e) {
+                "proxy_auth_failure"
+            } else {
+                "failure"
+            };
+            config.metrics.reports.with_label_values(&[status]).inc();
+            state.missed_reports += 1;
+            state.baseline = None;
+        }
+    }
+    config.metrics.missed_reports.set(state.missed_reports);
+    let latest_version = match res {
+        Ok(Acknowledgment {
+            latest_version: Some(latest_version),
+            ..
+        }) => latest_version,
+        Ok(_) => return,
+        Err(e) if is_proxy_auth_failure(&e) => {
+            debug!(
+                "failed to report telemetry: proxy authentication failed: {:#}",
+                e
+            );
+            return;
+        }
+        Err(e) => {
+            debug!("failed to report telemetry: {:#}", e);
+            return;
+        }
+    };
+
+    if latest_version > state.reported_version {
+        // We assume users running development builds are sophisticated, and
+        // may be intentionally not running the latest release, so downgrade
+        // the message from warn to info level.
+        let level = match BUILD_INFO.semver_version().pre.as_str() {
+            "dev" => Level::Info,
+            _ => Level::Warn,
         };
+        log!(
+            level,
+            "a new version of materialized is available: {}",
+            latest_version
+        );
+        state.reported_version = latest_version;
+    }
+}
+
+/// Appends the full snapshot `snapshot` to `spool`.
+async fn spool_report(config: &Config, spool: &Spool, mut snapshot: Value) {
+    let spooled_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if let Some(report) = snapshot.get_mut("report").and_then(|r| r.as_object_mut()) {
+        report.insert("spooled_at".into(), json!(spooled_at));
+    }
+    match spool.push(&snapshot).await {
+        Ok(dropped) => config.metrics.spool_dropped.inc_by(dropped),
+        Err(e) => warn!("failed to spool telemetry report: {:#}", e),
+    }
+}
 
-        if latest_version > reported_version {
-            // We assume users running development builds are sophisticated, and
-            // may be intentionally not running the latest release, so downgrade
-            // the message from warn to info level.
-            let level = match BUILD_INFO.semver_version().pre.as_str() {
-                "dev" => Level::Info,
-                _ => Level::Warn,
+/// Sends the reports in `spool` to the telemetry server, oldest first,
+/// removing each report once it is sent.
+///
+/// Stops at the first report that fails to send, or once reporting is
+/// disabled.
+async fn drain_spool(config: &Config, spool: &Spool) {
+    let res = async {
+        let client = build_client(config)?;
+        while *config.enabled.borrow() {
+            let (id, report) = match spool.oldest().await? {
+                Some(oldest) => oldest,
+                None => break,
             };
-            log!(
-                level,
-                "a new version of materialized is available: {}",
-                latest_version
-            );
-            reported_version = latest_version;
+            client
+                .post(report_url(config))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(report)
+                .send()
+                .await?
+                .error_for_status()?;
+            spool.remove(id).await?;
+            debug!("sent spooled telemetry report {}", id);
+        }
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    if let Err(e) = res {
+        debug!("failed to send spooled telemetry reports: {:#}", e);
+    }
+}
+
+/// Completes when `enabled` becomes false.
+async fn disabled(mut enabled: watch::Receiver<bool>) {
+    while *enabled.borrow() {
+        if enabled.changed().await.is_err() {
+            // The toggle is gone, so reporting can never be disabled.
+            futures::future::pending::<()>().await;
         }
     }
 }
@@ -99,29 +787,626 @@ const TELEMETRY_QUERY: &str = "SELECT jsonb_build_object(
 #[derive(Deserialize)]
 struct V1VersionResponse {
     latest_release: String,
+    /// Whether the telemetry server cannot apply a delta to the report it
+    /// just received, e.g. because it missed an earlier report.
+    #[serde(default)]
+    full_snapshot_required: bool,
 }
 
-async fn report_one(config: &Config) -> Result<semver::Version, anyhow::Error> {
-    let response: V1VersionResponse = Retry::default()
-        .initial_backoff(Duration::from_secs(1))
-        .max_duration(config.interval)
-        .retry(|_state| async {
-            let query_result = config
-                .coord_client
-                .system_execute_one(&TELEMETRY_QUERY)
-                .await?;
-            let response = http_util::reqwest::client()
-                .post(format!(
-                    "https://{}/api/telemetry/{}",
-                    config.domain, config.cluster_id
-                ))
-                .timeout(Duration::from_secs(10))
-                .json(&query_result.rows[0][0])
-                .send()
+/// Reports telemetry data, retrying failed attempts until the reporting
+/// interval elapses.
+///
+/// The first attempt is sent as a delta against `baseline`, if present.
+/// Retries are sent as full snapshots, as the telemetry server may or may not
+/// have received the failed attempt. The full snapshot of the last attempt is
+/// stored in `snapshot`, and, if the report is to be logged, the full contents
+/// of its request in `request`.
+async fn report_one(
+    config: &Config,
+    missed_reports: u64,
+    sequence: &AtomicU64,
+    mut baseline: Option<Baseline>,
+    snapshot: &mut Option<Value>,
+    request: &mut Option<String>,
+) -> Result<Acknowledgment, anyhow::Error> {
+    let start = Instant::now();
+    let mut backoff = Backoff::new(config.interval);
+    loop {
+        let seqno = sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        let res = try_report_one(
+            config,
+            missed_reports,
+            seqno,
+            baseline.take(),
+            snapshot,
+            request,
+        )
+        .await;
+        match res {
+            Ok(ack) => return Ok(ack),
+            Err(e) => {
+                let sleep = backoff.next();
+                if start.elapsed() + sleep >= config.interval {
+                    return Err(e);
+                }
+                debug!("retrying telemetry report in {:?}: {:#}", sleep, e);
+                config.metrics.retries.inc();
+                time::sleep(sleep).await;
+            }
+        }
+    }
+}
+
+async fn try_report_one(
+    config: &Config,
+    missed_reports: u64,
+    sequence: u64,
+    baseline: Option<Baseline>,
+    spooled_snapshot: &mut Option<Value>,
+    logged_request: &mut Option<String>,
+) -> Result<Acknowledgment, anyhow::Error> {
+    let query_result = config
+        .coord_client
+        .system_execute_one(&TELEMETRY_QUERY)
+        .await?;
+    let mut data = query_result.rows[0][0].clone();
+    if let Some(status) = data.get_mut("status").and_then(|s| s.as_object_mut()) {
+        status.insert("missed_reports".into(), json!(missed_reports));
+    }
+    if let Some(data) = data.as_object_mut() {
+        data.insert("tags".into(), json!(config.tags));
+    }
+
+    // Sequence numbers restart with each session of the server.
+    let session_id = data.pointer("/status/session_id").cloned();
+    let mut snapshot = data.clone();
+    if let Some(snapshot) = snapshot.as_object_mut() {
+        snapshot.insert(
+            "report".into(),
+            json!({
+                "session_id": session_id,
+                "sequence": sequence,
+                "kind": "snapshot",
+            }),
+        );
+    }
+    let snapshot_len = serde_json::to_vec(&snapshot)?.len();
+    *spooled_snapshot = Some(snapshot.clone());
+    let payload = match &baseline {
+        None => {
+            debug!(
+                "telemetry report {}: {} bytes as a full snapshot",
+                sequence, snapshot_len
+            );
+            snapshot
+        }
+        Some(baseline) => {
+            let delta = json!({
+                "report": {
+                    "session_id": session_id,
+                    "sequence": sequence,
+                    "kind": "delta",
+                    "base_sequence": baseline.sequence,
+                },
+                "delta": Delta::compute(&baseline.data, &data),
+            });
+            debug!(
+                "telemetry report {}: {} bytes as a delta against report {}, \
+                 {} bytes as a full snapshot",
+                sequence,
+                serde_json::to_vec(&delta)?.len(),
+                baseline.sequence,
+                snapshot_len
+            );
+            delta
+        }
+    };
+
+    let client = build_client(config)?;
+    // Build the request up front, so that the logged report is exactly the
+    // request that would be sent.
+    let request = build_request(&client, &report_url(config), &payload)?;
+    if config.mode.logs() {
+        *logged_request = Some(format_request(&request));
+    }
+    if !config.mode.sends() {
+        // Without an acknowledgment, every report is a full snapshot.
+        return Ok(Acknowledgment {
+            latest_version: None,
+            baseline: None,
+        });
+    }
+    let response: V1VersionResponse = client
+        .execute(request)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let baseline = match response.full_snapshot_required {
+        true => None,
+        false => Some(Baseline {
+            sequence,
+            data,
+            snapshot_at: match baseline {
+                Some(baseline) => baseline.snapshot_at,
+                None => Instant::now(),
+            },
+        }),
+    };
+    Ok(Acknowledgment {
+        latest_version: Some(response.latest_release.parse()?),
+        baseline,
+    })
+}
+
+/// Builds the client with which to send reports.
+fn build_client(config: &Config) -> Result<reqwest::Client, anyhow::Error> {
+    Ok(match &config.proxy {
+        None => http_util::reqwest::client_builder(),
+        Some(proxy) => reqwest::ClientBuilder::new().proxy(build_proxy(proxy)?),
+    }
+    .connect_timeout(config.connect_timeout)
+    .timeout(config.request_timeout)
+    .build()?)
+}
+
+/// Builds the request that sends `payload` to `url`.
+///
+/// The client adds its default headers to a request only where the request
+/// does not set them, so every one of them is set here.
+fn build_request(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &Value,
+) -> Result<reqwest::Request, anyhow::Error> {
+    Ok(client
+        .post(url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .json(payload)
+        .build()?)
+}
+
+/// Returns the URL to which reports are sent.
+fn report_url(config: &Config) -> String {
+    format!(
+        "https://{}/api/telemetry/{}",
+        config.domain, config.cluster_id
+    )
+}
+
+/// The changes that transform one report's data into another's.
+///
+/// Each change is keyed by the [JSON Pointer] to the value that changed.
+/// Integers, like the counts of objects, change by the difference in
+/// `deltas`. Other values that are added or that change are replaced by the
+/// values in `set`, and values that are removed are listed in `removed`.
+///
+/// [JSON Pointer]: https://datatracker.ietf.org/doc/html/rfc6901
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Delta {
+    deltas: BTreeMap<String, i64>,
+    set: BTreeMap<String, Value>,
+    removed: Vec<String>,
+}
+
+impl Delta {
+    /// Computes the changes that transform `old` into `new`.
+    fn compute(old: &Value, new: &Value) -> Delta {
+        let mut delta = Delta::default();
+        delta.add(String::new(), old, new);
+        delta
+    }
+
+    fn add(&mut self, path: String, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, old_value) in old {
+                    let path = format!("{}/{}", path, escape_pointer_token(key));
+                    match new.get(key) {
+                        Some(new_value) => self.add(path, old_value, new_value),
+                        None => self.removed.push(path),
+                    }
+                }
+                for (key, new_value) in new {
+                    if !old.contains_key(key) {
+                        let path = format!("{}/{}", path, escape_pointer_token(key));
+                        self.set.insert(path, new_value.clone());
+                    }
+                }
+            }
+            _ if old == new => (),
+            _ => match integer_difference(old, new) {
+                Some(difference) => {
+                    self.deltas.insert(path, difference);
+                }
+                None => {
+                    self.set.insert(path, new.clone());
+                }
+            },
+        }
+    }
+}
+
+/// Returns `new - old` if both values are integers and the difference does
+/// not overflow.
+fn integer_difference(old: &Value, new: &Value) -> Option<i64> {
+    match (old, new) {
+        (Value::Number(old), Value::Number(new)) => new.as_i64()?.checked_sub(old.as_i64()?),
+        _ => None,
+    }
+}
+
+/// Escapes `token` for use in a JSON Pointer.
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Builds a `reqwest` proxy from an explicit proxy configuration.
+///
+/// Both plain HTTP and HTTPS traffic is routed through the proxy, the latter
+/// via `CONNECT` tunneling, except for hosts excluded by the no-proxy list.
+fn build_proxy(config: &TelemetryProxyConfig) -> Result<reqwest::Proxy, anyhow::Error> {
+    let url = reqwest::Url::parse(&config.url)
+        .with_context(|| format!("parsing telemetry proxy URL: {}", config.url))?;
+    let no_proxy = config.no_proxy.clone();
+    let mut proxy = reqwest::Proxy::custom(move |target| match target.host_str() {
+        Some(host) if no_proxy.iter().any(|entry| no_proxy_matches(entry, host)) => None,
+        _ => Some(url.clone()),
+    });
+    if let Some(username) = &config.username {
+        proxy = proxy.basic_auth(username, config.password.as_deref().unwrap_or(""));
+    }
+    Ok(proxy)
+}
+
+/// Reports whether `host` is excluded from proxying by the no-proxy list
+/// entry `entry`.
+///
+/// The special entry `*` matches all hosts. Otherwise, an entry matches the
+/// host with the same name and all of its subdomains. A leading `.` in the
+/// entry is ignored.
+fn no_proxy_matches(entry: &str, host: &str) -> bool {
+    let entry = entry.trim();
+    if entry == "*" {
+        return true;
+    }
+    let entry = entry.strip_prefix('.').unwrap_or(entry);
+    if entry.is_empty() {
+        return false;
+    }
+    let host = host.to_ascii_lowercase();
+    let entry = entry.to_ascii_lowercase();
+    host == entry || host.ends_with(&format!(".{}", entry))
+}
+
+/// Reports whether `e` was caused by the proxy rejecting our credentials.
+///
+/// Plain HTTP requests receive the proxy's 407 response directly, while
+/// HTTPS requests observe it as a failure to establish the `CONNECT` tunnel.
+fn is_proxy_auth_failure(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if e.status() == Some(reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED) {
+                return true;
+            }
+        }
+        cause
+            .to_string()
+            .to_lowercase()
+            .contains("proxy authentication required")
+    })
+}
+
+/// Computes exponentially increasing backoffs with full jitter.
+///
+/// The un-jittered backoff starts at [`INITIAL_BACKOFF`] and doubles after
+/// each attempt, but is capped at a quarter of the reporting interval, so that
+/// several attempts fit within each interval.
+struct Backoff {
+    current: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new(interval: Duration) -> Backoff {
+        let max = cmp::max(interval / 4, Duration::from_millis(1));
+        Backoff {
+            current: cmp::min(INITIAL_BACKOFF, max),
+            max,
+        }
+    }
+
+    /// Returns the duration to sleep before the next attempt, chosen
+    /// uniformly at random from between zero and the current backoff.
+    fn next(&mut self) -> Duration {
+        let sleep = self.current.mul_f64(rand::random::<f64>());
+        self.current = cmp::min(self.current * 2, self.max);
+        sleep
+    }
+}
+
+/// Formats the full contents of `request`: its method, URL, headers, and
+/// body.
+///
+/// Besides the headers set on the request, the `host` and `content-length`
+/// headers are included, which HTTP/1.1 derives from the URL and the body as
+/// the request is sent. No other headers are added when sending.
+fn format_request(request: &reqwest::Request) -> String {
+    let url = request.url();
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or(&[]);
+    let mut out = format!("{} {}\n", request.method(), url);
+    for (name, value) in request.headers() {
+        out += &format!("{}: {}\n", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    let host = url.host_str().unwrap_or("");
+    match url.port() {
+        None => out += &format!("host: {}\n", host),
+        Some(port) => out += &format!("host: {}:{}\n", host, port),
+    }
+    out += &format!("content-length: {}\n\n", body.len());
+    out += &String::from_utf8_lossy(body);
+    out
+}
+
+/// Writes `request`, as formatted by [`format_request`], to the telemetry log
+/// file, if specified, or to the server log otherwise.
+///
+/// A failure to write the log file does not fail the report.
+async fn log_report(config: &Config, request: String) {
+    let path = match &config.log_file {
+        None => {
+            info!("telemetry report:\n{}", request);
+            return;
+        }
+        Some(path) => path.clone(),
+    };
+    let res = task::spawn_blocking(move || {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("opening telemetry log file: {}", path.display()))?;
+        writeln!(file, "{}", request)
+            .with_context(|| format!("writing telemetry log file: {}", path.display()))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|res| res);
+    if let Err(e) = res {
+        warn!("failed to log telemetry report: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::runtime::Runtime;
+
+    use ore::cast::CastFrom;
+
+    use super::{build_request, format_request, no_proxy_matches, Backoff, Decision, Delta, Spool};
+
+    #[test]
+    fn test_no_proxy_matches() {
+        assert!(no_proxy_matches("*", "cloud.materialize.com"));
+        assert!(no_proxy_matches("materialize.com", "materialize.com"));
+        assert!(no_proxy_matches("materialize.com", "cloud.materialize.com"));
+        assert!(no_proxy_matches(
+            ".materialize.com",
+            "cloud.materialize.com"
+        ));
+        assert!(no_proxy_matches(
+            " Materialize.COM ",
+            "cloud.materialize.com"
+        ));
+        assert!(!no_proxy_matches("materialize.com", "notmaterialize.com"));
+        assert!(!no_proxy_matches(
+            "cloud.materialize.com",
+            "materialize.com"
+        ));
+        assert!(!no_proxy_matches("", "materialize.com"));
+        assert!(!no_proxy_matches(".", "materialize.com"));
+    }
+
+    #[test]
+    fn test_delta() {
+        let old = json!({
+            "version": "v0.8.4",
+            "status": {
+                "uptime_seconds": 60.5,
+                "tables": {"count": 3},
+                "sources": {"kafka": {"count": 2}, "file": {"count": 1}},
+            },
+            "tags": {"environment": "prod"},
+        });
+        let new = json!({
+            "version": "v0.8.4",
+            "status": {
+                "uptime_seconds": 120.5,
+                "tables": {"count": 1},
+                "sources": {"kafka": {"count": 5}, "s3": {"count": 1}},
+            },
+            "tags": {"env/name": "prod"},
+        });
+        let delta = Delta::compute(&old, &new);
+        assert_eq!(
+            json!(delta),
+            json!({
+                "deltas": {
+                    "/status/sources/kafka/count": 3,
+                    "/status/tables/count": -2,
+                },
+                "set": {
+                    "/status/sources/s3": {"count": 1},
+                    "/status/uptime_seconds": 120.5,
+                    "/tags/env~1name": "prod",
+                },
+                "removed": ["/status/sources/file", "/tags/environment"],
+            })
+        );
+
+        // Identical data yields an empty delta.
+        assert_eq!(Delta::compute(&new, &new), Delta::default());
+    }
+
+    #[test]
+    fn test_spool() -> Result<(), anyhow::Error> {
+        Runtime::new()?.block_on(async {
+            let dir = tempfile::tempdir()?;
+            let report = |n: u64| json!({ "report": { "sequence": n } });
+            let report_len = u64::cast_from(serde_json::to_vec(&report(1))?.len());
+
+            // The spool holds two reports, and drops the oldest to make room.
+            let spool = Spool::open(dir.path().into(), 2 * report_len).await?;
+            assert_eq!(spool.push(&report(1)).await?, 0);
+            assert_eq!(spool.push(&report(2)).await?, 0);
+            assert_eq!(spool.push(&report(3)).await?, 1);
+            assert_eq!(spool.push(&json!({ "too": "large".repeat(100) })).await?, 1);
+
+            // The reports survive reopening the spool.
+            let spool = Spool::open(dir.path().into(), 2 * report_len).await?;
+            let (id, oldest) = spool.oldest().await?.unwrap();
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&oldest)?,
+                report(2)
+            );
+            spool.remove(id).await?;
+            assert_eq!(spool.push(&report(4)).await?, 0);
+            let reports = spool
+                .export(true)
                 .await?
-                .error_for_status()?;
-            Ok::<_, anyhow::Error>(response.json().await?)
+                .into_iter()
+                .map(|r| serde_json::from_slice(&r))
+                .collect::<Result<Vec<serde_json::Value>, _>>()?;
+            assert_eq!(reports, vec![report(3), report(4)]);
+            assert!(spool.export(false).await?.is_empty());
+            Ok(())
         })
-        .await?;
-    Ok(response.latest_release.parse()?)
+    }
+
+    #[test]
+    fn test_logged_request_matches_sent_request() -> Result<(), anyhow::Error> {
+        // Capture the request line and headers of the request as they arrive.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/api/telemetry/test", listener.local_addr()?);
+        let server = thread::spawn(move || -> Result<Vec<String>, io::Error> {
+            let (conn, _) = listener.accept()?;
+            let mut reader = BufReader::new(&conn);
+            let mut head = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                match line.trim_end() {
+                    "" => break,
+                    line => head.push(line.to_owned()),
+                }
+            }
+            let content_length = head
+                .iter()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map_or(0, |len| len.parse().unwrap());
+            reader.read_exact(&mut vec![0; content_length])?;
+            (&conn).write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")?;
+            Ok(head)
+        });
+
+        let client = reqwest::Client::builder().no_proxy().build()?;
+        let payload = json!({ "report": { "sequence": 1 } });
+        let request = build_request(&client, &url, &payload)?;
+        let logged = format_request(&request);
+        Runtime::new()?
+            .block_on(client.execute(request))?
+            .error_for_status()?;
+        let mut sent_headers = server.join().unwrap()?.split_off(1);
+
+        let mut lines = logged.lines();
+        assert_eq!(lines.next(), Some(&*format!("POST {}", url)));
+        let mut logged_headers: Vec<_> = lines
+            .by_ref()
+            .take_while(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            vec![serde_json::to_string(&payload)?]
+        );
+        let addr = url.trim_start_matches("http://").split('/').next().unwrap();
+        assert_eq!(
+            logged_headers,
+            vec![
+                "accept: application/json".to_owned(),
+                "content-type: application/json".to_owned(),
+                format!("host: {}", addr),
+                format!("content-length: {}", serde_json::to_vec(&payload)?.len()),
+            ]
+        );
+        logged_headers.sort();
+        sent_headers.sort();
+        assert_eq!(logged_headers, sent_headers);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff_capped_below_interval() {
+        let interval = Duration::from_secs(60);
+        let mut backoff = Backoff::new(interval);
+        for _ in 0..100 {
+            assert!(backoff.next() <= interval / 4);
+        }
+        assert_eq!(backoff.current, interval / 4);
+
+        // Tiny intervals still produce a nonzero cap.
+        let mut backoff = Backoff::new(Duration::from_millis(2));
+        assert!(backoff.next() <= Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_decision_precedence() {
+        let cases = vec![
+            // The environment trumps everything.
+            (
+                Some("DO_NOT_TRACK"),
+                Some(true),
+                true,
+                Decision::DisabledByEnvironment("DO_NOT_TRACK"),
+            ),
+            (
+                Some("MZ_TELEMETRY"),
+                None,
+                true,
+                Decision::DisabledByEnvironment("MZ_TELEMETRY"),
+            ),
+            (
+                Some("DO_NOT_TRACK"),
+                None,
+                false,
+                Decision::DisabledByEnvironment("DO_NOT_TRACK"),
+            ),
+            // The persisted preference trumps the configuration.
+            (None, Some(false), true, Decision::DisabledByPreference),
+            (None, Some(true), true, Decision::EnabledByPreference),
+            (None, Some(false), false, Decision::DisabledByPreference),
+            // ...but cannot enable telemetry that isn't configured.
+            (None, Some(true), false, Decision::DisabledByConfig),
+            // Otherwise the configuration decides.
+            (None, None, true, Decision::EnabledByConfig),
+            (None, None, false, Decision::DisabledByConfig),
+        ];
+        for (env_opt_out, preference, configured, expected) in cases {
+            let decision = Decision::resolve(env_opt_out, preference, configured);
+            assert_eq!(
+                decision, expected,
+                "env={:?} preference={:?} configured={}",
+                env_opt_out, preference, configured
+            );
+        }
+    }
 }
@@ -0,0 +1,241 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Consistency checks of TLS certificates and keys.
+//!
+//! OpenSSL reports a misconfigured certificate or key with errors like
+//! `error:0B080074:x509 certificate routines:X509_check_private_key:key values
+//! mismatch`, which name neither the file nor the mistake. The checks here
+//! run before the TLS context is built and describe each problem in terms of
+//! the files that the user supplied. Where OpenSSL itself rejected a file,
+//! its error is attached as the source of the reported error.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use openssl::asn1::Asn1Time;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509Ref, X509StoreContext, X509VerifyResult, X509};
+
+use crate::{TlsConfig, TlsMode};
+
+impl TlsConfig {
+    /// Validates that the certificate, key, and certificate authority are
+    /// consistent with one another.
+    ///
+    /// Specifically, the certificate file must contain the server's own
+    /// certificate followed by the certificates that issued it, in order;
+    /// the key must belong to the server's own certificate; the server's own
+    /// certificate must be currently valid; and, if client certificates are
+    /// verified, the certificate authority must be able to verify a chain.
+    ///
+    /// This function performs blocking I/O.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let chain = load_certs(&self.cert, "certificate")?;
+        let key = load_key(&self.key)?;
+        check_key(&chain, &key, &self.cert, &self.key)?;
+        check_chain_order(&chain, &self.cert)?;
+        check_validity(&chain[0], &self.cert)?;
+        if let TlsMode::VerifyCa { ca } | TlsMode::VerifyFull { ca } = &self.mode {
+            check_ca(ca)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads the PEM-encoded certificates in the file at `path`, which holds a
+/// TLS `what`.
+fn load_certs(path: &Path, what: &str) -> Result<Vec<X509>, anyhow::Error> {
+    let pem = fs::read(path).with_context(|| format!("reading TLS {} {}", what, path.display()))?;
+    let certs = X509::stack_from_pem(&pem).with_context(|| {
+        format!(
+            "TLS {} {} does not contain valid PEM-encoded certificates",
+            what,
+            path.display()
+        )
+    })?;
+    if certs.is_empty() {
+        if PKey::private_key_from_pem(&pem).is_ok() {
+            bail!(
+                "TLS {} {} contains a private key rather than a certificate; \
+                 are the certificate and key files swapped?",
+                what,
+                path.display()
+            );
+        }
+        bail!(
+            "TLS {} {} contains no PEM-encoded certificates",
+            what,
+            path.display()
+        );
+    }
+    Ok(certs)
+}
+
+/// Loads the PEM-encoded private key in the file at `path`.
+fn load_key(path: &Path) -> Result<PKey<Private>, anyhow::Error> {
+    let pem = fs::read(path).with_context(|| format!("reading TLS key {}", path.display()))?;
+    PKey::private_key_from_pem(&pem).map_err(|e| {
+        let message = if X509::from_pem(&pem).is_ok() {
+            format!(
+                "TLS key {} contains a certificate rather than a private key; \
+                 are the certificate and key files swapped?",
+                path.display()
+            )
+        } else if String::from_utf8_lossy(&pem).contains("ENCRYPTED") {
+            format!(
+                "TLS key {} is encrypted with a passphrase, which is not supported",
+                path.display()
+            )
+        } else {
+            format!(
+                "TLS key {} does not contain a valid PEM-encoded private key",
+                path.display()
+            )
+        };
+        anyhow::Error::new(e).context(message)
+    })
+}
+
+/// Checks that `key` belongs to the first certificate in `chain`, which is
+/// the server's own certificate.
+fn check_key(
+    chain: &[X509],
+    key: &PKey<Private>,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(), anyhow::Error> {
+    let matches = |cert: &X509Ref| cert.public_key().map_or(false, |k| k.public_eq(key));
+    if matches(&chain[0]) {
+        return Ok(());
+    }
+    match chain.iter().position(|cert| matches(cert)) {
+        Some(i) => bail!(
+            "TLS key {} belongs to certificate {} ({}) in {}, but the server's own \
+             certificate must come first in the file",
+            key_path.display(),
+            i + 1,
+            describe(&chain[i]),
+            cert_path.display()
+        ),
+        None => bail!(
+            "TLS key {} does not match the certificate ({}) in {}",
+            key_path.display(),
+            describe(&chain[0]),
+            cert_path.display()
+        ),
+    }
+}
+
+/// Checks that each certificate in `chain` is followed by the certificate
+/// that issued it, if any.
+fn check_chain_order(chain: &[X509], path: &Path) -> Result<(), anyhow::Error> {
+    for (i, pair) in chain.windows(2).enumerate() {
+        let (cert, next) = (&pair[0], &pair[1]);
+        if next.issued(cert) == X509VerifyResult::OK {
+            continue;
+        }
+        if cert.issued(next) == X509VerifyResult::OK {
+            bail!(
+                "TLS certificate chain {} is in the wrong order: certificate {} ({}) issued \
+                 certificate {} ({}), and so must follow it",
+                path.display(),
+                i + 1,
+                describe(cert),
+                i + 2,
+                describe(next)
+            );
+        }
+        bail!(
+            "TLS certificate chain {} is broken: certificate {} ({}) was not issued by the \
+             certificate that follows it ({}); each certificate must be followed by the \
+             certificate that issued it",
+            path.display(),
+            i + 1,
+            describe(cert),
+            describe(next)
+        );
+    }
+    Ok(())
+}
+
+/// Checks that `cert`, the server's own certificate, is currently valid.
+fn check_validity(cert: &X509Ref, path: &Path) -> Result<(), anyhow::Error> {
+    let now = Asn1Time::days_from_now(0)?;
+    if cert.not_before().compare(&now)? == Ordering::Greater {
+        bail!(
+            "TLS certificate {} ({}) is not valid until {}",
+            path.display(),
+            describe(cert),
+            cert.not_before()
+        );
+    }
+    if cert.not_after().compare(&now)? == Ordering::Less {
+        bail!(
+            "TLS certificate {} ({}) expired on {}",
+            path.display(),
+            describe(cert),
+            cert.not_after()
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the certificate authority at `path`, against which client
+/// certificates are verified, can verify a chain: each of its certificates
+/// must itself verify against the authority.
+fn check_ca(path: &Path) -> Result<(), anyhow::Error> {
+    let certs = load_certs(path, "CA certificate")?;
+    let mut store = X509StoreBuilder::new()?;
+    for cert in &certs {
+        store
+            .add_cert(cert.clone())
+            .with_context(|| format!("loading TLS CA certificate {}", path.display()))?;
+    }
+    let store = store.build();
+    for (i, cert) in certs.iter().enumerate() {
+        let mut context = X509StoreContext::new()?;
+        let untrusted = Stack::new()?;
+        let error = context.init(&store, cert, &untrusted, |context| {
+            Ok(match context.verify_cert()? {
+                true => None,
+                false => Some(context.error()),
+            })
+        })?;
+        if let Some(error) = error {
+            bail!(
+                "TLS CA certificate {} cannot verify client certificates: \
+                 certificate {} ({}) does not verify: {}",
+                path.display(),
+                i + 1,
+                describe(cert),
+                error.error_string()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Describes `cert` by its subject's Common Name (CN).
+fn describe(cert: &X509Ref) -> String {
+    match cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|cn| cn.data().as_utf8().ok())
+    {
+        Some(cn) => format!("CN={}", cn),
+        None => "no common name".into(),
+    }
+}
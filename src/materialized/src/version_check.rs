@@ -0,0 +1,184 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Checks for new releases of Materialize.
+//!
+//! When configured, the server periodically fetches the version of the latest
+//! release from a URL and reports whether it is newer than the running
+//! version, both as a metric and in the `/api/status` endpoint. Unlike
+//! telemetry reporting, the check sends nothing but the running version, and
+//! so is suitable for fleets that do not report telemetry.
+//!
+//! The check honors the same opt-outs as telemetry: it never runs if the
+//! environment opts out of telemetry, and it is skipped while telemetry is
+//! disabled by preference. Failed checks, e.g. because the server is offline,
+//! are counted but otherwise ignored.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use log::debug;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec, UIntGaugeVec};
+
+use crate::scheduler::Job;
+use crate::BUILD_INFO;
+
+/// The maximum time to wait for the version check to complete.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configures the check for new releases.
+#[derive(Debug, Clone)]
+pub struct VersionCheckConfig {
+    /// The URL from which to fetch the latest release.
+    ///
+    /// The URL must answer a `GET` request with a JSON object whose
+    /// `latest_release` field is the version of the latest release.
+    pub url: String,
+    /// How often to check for new releases.
+    pub interval: Duration,
+}
+
+impl VersionCheckConfig {
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let url = reqwest::Url::parse(&self.url)
+            .with_context(|| format!("parsing version check URL: {}", self.url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("version check URL must use http or https: {}", self.url);
+        }
+        if self.interval == Duration::from_secs(0) {
+            bail!("version check interval must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// The latest release of which the version check has learned.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct VersionStatus {
+    latest: Arc<Mutex<Option<semver::Version>>>,
+}
+
+impl VersionStatus {
+    /// Constructs a new status, in which no release is known.
+    pub fn new() -> VersionStatus {
+        VersionStatus::default()
+    }
+
+    /// Returns the version of the latest release, if a check has succeeded.
+    pub fn latest(&self) -> Option<semver::Version> {
+        self.latest.lock().expect("lock poisoned").clone()
+    }
+
+    /// Reports whether the latest release is newer than the running version,
+    /// if a check has succeeded.
+    pub fn upgrade_available(&self) -> Option<bool> {
+        self.latest()
+            .map(|latest| latest > BUILD_INFO.semver_version())
+    }
+}
+
+/// Version check metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    checks: UIntCounterVec,
+    upgrade_available: UIntGaugeVec,
+}
+
+impl Metrics {
+    /// Registers the version check metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            checks: registry.register(metric!(
+                name: "mz_server_version_checks_total",
+                help: "the number of checks for new releases, by outcome",
+                var_labels: ["outcome"],
+            )),
+            upgrade_available: registry.register(metric!(
+                name: "mz_server_upgrade_available",
+                help: "whether a newer release than the running version is available (1) or not (0)",
+                var_labels: ["latest_version"],
+            )),
+        }
+    }
+}
+
+/// Constructs the version check job, which checks for new releases every
+/// `config.interval`, starting immediately, and records the outcome in
+/// `status` and `metrics`.
+///
+/// Runs that occur while `enabled` is false are skipped.
+pub fn check_job(
+    config: VersionCheckConfig,
+    status: VersionStatus,
+    metrics: Metrics,
+    enabled: watch::Receiver<bool>,
+) -> Job {
+    let interval = config.interval;
+    let config = Arc::new(config);
+    Job::every("version_check", interval, move || {
+        let config = Arc::clone(&config);
+        let status = status.clone();
+        let metrics = metrics.clone();
+        let enabled = enabled.clone();
+        async move {
+            if !*enabled.borrow() {
+                return;
+            }
+            match check(&config).await {
+                Ok(latest) => {
+                    metrics.checks.with_label_values(&["success"]).inc();
+                    let upgrade_available = latest > BUILD_INFO.semver_version();
+                    metrics.upgrade_available.reset();
+                    metrics
+                        .upgrade_available
+                        .with_label_values(&[&latest.to_string()])
+                        .set(u64::from(upgrade_available));
+                    *status.latest.lock().expect("lock poisoned") = Some(latest);
+                }
+                Err(e) => {
+                    metrics.checks.with_label_values(&["failure"]).inc();
+                    debug!("failed to check for new releases: {:#}", e);
+                }
+            }
+        }
+    })
+}
+
+/// The response to a version check.
+#[derive(Deserialize)]
+struct VersionResponse {
+    latest_release: String,
+}
+
+/// Fetches the version of the latest release.
+///
+/// The request carries the running version and nothing else that identifies
+/// the server.
+async fn check(config: &VersionCheckConfig) -> Result<semver::Version, anyhow::Error> {
+    let client = http_util::reqwest::client_builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let response: VersionResponse = client
+        .get(&config.url)
+        .query(&[("version", BUILD_INFO.version)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.latest_release.parse()?)
+}
@@ -0,0 +1,635 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Memory watchdog.
+//!
+//! When the server exceeds its memory limit, the kernel kills it, and any
+//! context about what consumed the memory is lost. The watchdog samples the
+//! server's memory usage and, as usage approaches the limit, records
+//! diagnostics and optionally sheds load while the server is still alive.
+//!
+//! Usage above the warning threshold causes the watchdog to log the dataflows
+//! with the largest arrangements and, optionally, to dump a heap profile.
+//! Usage above the critical threshold additionally causes the watchdog to
+//! refuse new pgwire sessions and to cancel the queries with the largest
+//! dataflows, if so configured. Every action is logged and counted in the
+//! `mz_memory_watchdog_actions_total` metric.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+
+use ore::metric;
+use ore::metrics::{MetricsRegistry, UIntCounterVec, UIntGauge};
+
+use crate::allocator;
+use crate::scheduler::Job;
+
+/// The number of dataflows to describe when memory usage crosses a
+/// threshold.
+const TOP_DATAFLOWS: usize = 10;
+
+/// The maximum number of queries to cancel each time memory usage is sampled
+/// above the critical threshold.
+const MAX_CANCELS_PER_SAMPLE: usize = 3;
+
+/// Configures the memory watchdog.
+#[derive(Debug, Clone)]
+pub struct MemoryWatchdogConfig {
+    /// The amount of memory the server may use.
+    ///
+    /// If `None`, the memory limit of the server's cgroup is used. If the
+    /// server's cgroup has no memory limit, the watchdog does not run.
+    pub limit: Option<ByteSize>,
+    /// The fraction of the limit above which memory usage is considered to
+    /// be high.
+    pub warning_threshold: f64,
+    /// The fraction of the limit above which memory usage is considered to
+    /// be critical.
+    pub critical_threshold: f64,
+    /// Whether to dump a heap profile to the diagnostics directory when
+    /// memory usage crosses either threshold.
+    pub heap_profiles: bool,
+    /// Whether to refuse new pgwire sessions while memory usage is critical.
+    pub refuse_connections: bool,
+    /// Whether to cancel the queries with the largest dataflows while memory
+    /// usage is critical.
+    pub cancel_queries: bool,
+}
+
+impl Default for MemoryWatchdogConfig {
+    fn default() -> MemoryWatchdogConfig {
+        MemoryWatchdogConfig {
+            limit: None,
+            warning_threshold: 0.8,
+            critical_threshold: 0.95,
+            heap_profiles: false,
+            refuse_connections: false,
+            cancel_queries: false,
+        }
+    }
+}
+
+impl MemoryWatchdogConfig {
+    /// Validates the thresholds.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(self.warning_threshold > 0.0 && self.warning_threshold <= 1.0) {
+            bail!(
+                "memory warning threshold must be between 0 and 1, got {}",
+                self.warning_threshold
+            );
+        }
+        if !(self.critical_threshold > 0.0 && self.critical_threshold <= 1.0) {
+            bail!(
+                "memory critical threshold must be between 0 and 1, got {}",
+                self.critical_threshold
+            );
+        }
+        if self.warning_threshold > self.critical_threshold {
+            bail!(
+                "memory warning threshold ({}) must not exceed critical threshold ({})",
+                self.warning_threshold,
+                self.critical_threshold
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A number of bytes.
+///
+/// Parses from an integer followed by an optional unit: `B`, one of the
+/// binary units `KiB`, `MiB`, `GiB`, and `TiB`, or one of the decimal units
+/// `kB`, `MB`, `GB`, and `TB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// Returns the number of bytes.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+const BYTE_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("kB", 1000),
+    ("KiB", 1 << 10),
+    ("MB", 1000 * 1000),
+    ("MiB", 1 << 20),
+    ("GB", 1000 * 1000 * 1000),
+    ("GiB", 1 << 30),
+    ("TB", 1000 * 1000 * 1000 * 1000),
+    ("TiB", 1 << 40),
+];
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ByteSize, anyhow::Error> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (n, unit) = s.split_at(split);
+        let n: u64 = n
+            .parse()
+            .map_err(|_| anyhow!("invalid size {:?}: expected a number of bytes", s))?;
+        let multiplier = match unit.trim() {
+            "" => 1,
+            unit => match BYTE_UNITS.iter().find(|(name, _)| *name == unit) {
+                Some((_, multiplier)) => *multiplier,
+                None => bail!(
+                    "invalid size {:?}: unknown unit {:?}; valid units are {}",
+                    s,
+                    unit,
+                    BYTE_UNITS
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+        };
+        match n.checked_mul(multiplier) {
+            Some(n) => Ok(ByteSize(n)),
+            None => bail!("invalid size {:?}: too large", s),
+        }
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, multiplier) in &[("TiB", 1 << 40), ("GiB", 1 << 30), ("MiB", 1 << 20)] {
+            if self.0 >= *multiplier {
+                return write!(f, "{:.1}{}", self.0 as f64 / *multiplier as f64, name);
+            }
+        }
+        write!(f, "{}B", self.0)
+    }
+}
+
+/// Returns the memory limit of the server's cgroup, if it has one.
+///
+/// Both version 1 and version 2 of the cgroup hierarchy are supported. The
+/// hierarchy is expected to be mounted at `/sys/fs/cgroup`. Under version 2,
+/// the limit is the smallest `memory.max` of the server's cgroup and its
+/// ancestors, so the limit is found whether or not the server's cgroup is at
+/// the root of the hierarchy. Under version 1, the server's cgroup is
+/// expected to be at the root of the hierarchy, as it is within a container.
+#[cfg(target_os = "linux")]
+pub fn cgroup_memory_limit() -> Option<u64> {
+    if let Some(limit) = cgroup_v2_memory_limit() {
+        return limit;
+    }
+    let limit: u64 = fs::read_to_string("/sys/fs/cgroup/memory/memory.limit_in_bytes")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    // Version 1 reports a limit near the maximum value of a 64-bit integer
+    // when memory is unlimited.
+    if limit >= 1 << 62 {
+        None
+    } else {
+        Some(limit)
+    }
+}
+
+/// Returns the memory limit of the server's cgroup, if it has one.
+///
+/// Cgroups exist only on Linux, so there is never a limit on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn cgroup_memory_limit() -> Option<u64> {
+    log::debug!("cgroup memory limits are only supported on Linux");
+    None
+}
+
+/// Returns the memory limit of the server's version 2 cgroup, or `None` if
+/// the server is not in a version 2 cgroup with the memory controller
+/// enabled.
+#[cfg(target_os = "linux")]
+fn cgroup_v2_memory_limit() -> Option<Option<u64>> {
+    const ROOT: &str = "/sys/fs/cgroup";
+    // The server's version 2 cgroup appears in `/proc/self/cgroup` as a line
+    // of the form `0::<path>`, where the path is relative to the root of the
+    // hierarchy.
+    let cgroups = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .unwrap_or("/");
+    let cgroup = PathBuf::from(ROOT).join(path.trim_start_matches('/'));
+    let mut found = false;
+    let mut limit = None;
+    for dir in cgroup.ancestors().take_while(|dir| dir.starts_with(ROOT)) {
+        let max = match fs::read_to_string(dir.join("memory.max")) {
+            Ok(max) => max,
+            Err(_) => continue,
+        };
+        found = true;
+        if let Ok(max) = max.trim().parse::<u64>() {
+            limit = Some(limit.map_or(max, |limit: u64| limit.min(max)));
+        }
+    }
+    if found {
+        Some(limit)
+    } else {
+        None
+    }
+}
+
+/// Returns the amount of memory the server is using, in bytes.
+///
+/// This is the resident set size of the process, if it can be determined, or
+/// else the amount of memory the allocator holds resident.
+pub(crate) fn memory_usage() -> Option<u64> {
+    resident_set_size().or_else(allocator::resident)
+}
+
+/// Returns the resident set size of the process, in bytes, if it can be
+/// determined.
+pub fn resident_set_size() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size as u64)
+}
+
+/// The severity of the server's memory usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Warning => "warning",
+            Level::Critical => "critical",
+        }
+    }
+}
+
+/// Memory watchdog metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    usage_bytes: UIntGauge,
+    limit_bytes: UIntGauge,
+    transitions: UIntCounterVec,
+    actions: UIntCounterVec,
+}
+
+impl Metrics {
+    /// Registers the memory watchdog metrics into `registry`.
+    pub fn register_into(registry: &MetricsRegistry) -> Metrics {
+        Metrics {
+            usage_bytes: registry.register(metric!(
+                name: "mz_memory_watchdog_usage_bytes",
+                help: "the memory usage of the server at the watchdog's last sample",
+            )),
+            limit_bytes: registry.register(metric!(
+                name: "mz_memory_watchdog_limit_bytes",
+                help: "the memory limit the watchdog enforces",
+            )),
+            transitions: registry.register(metric!(
+                name: "mz_memory_watchdog_transitions_total",
+                help: "the number of times memory usage entered each level",
+                var_labels: ["level"],
+            )),
+            actions: registry.register(metric!(
+                name: "mz_memory_watchdog_actions_total",
+                help: "the number of actions the memory watchdog took",
+                var_labels: ["action"],
+            )),
+        }
+    }
+
+    fn record_action(&self, action: &str) {
+        self.actions.with_label_values(&[action]).inc();
+    }
+}
+
+/// Watches the server's memory usage.
+pub struct Watchdog {
+    config: MemoryWatchdogConfig,
+    limit: u64,
+    coord_client: coord::Client,
+    gate: pgwire::ConnectionGate,
+    diagnostics_directory: PathBuf,
+    metrics: Metrics,
+    level: Mutex<Level>,
+}
+
+impl Watchdog {
+    /// Constructs a watchdog that enforces `limit`, in bytes.
+    ///
+    /// Heap profiles are written to `diagnostics_directory`. New pgwire
+    /// sessions are refused by closing `gate`.
+    pub fn new(
+        config: MemoryWatchdogConfig,
+        limit: u64,
+        coord_client: coord::Client,
+        gate: pgwire::ConnectionGate,
+        diagnostics_directory: PathBuf,
+        metrics: Metrics,
+    ) -> Watchdog {
+        metrics.limit_bytes.set(limit);
+        Watchdog {
+            config,
+            limit,
+            coord_client,
+            gate,
+            diagnostics_directory,
+            metrics,
+            level: Mutex::new(Level::Normal),
+        }
+    }
+
+    /// Returns a job that samples memory usage every `frequency`.
+    pub fn into_job(self, frequency: Duration) -> Job {
+        let watchdog = Arc::new(self);
+        Job::every("memory_watchdog", frequency, move || {
+            let watchdog = Arc::clone(&watchdog);
+            async move { watchdog.sample().await }
+        })
+    }
+
+    fn level(&self, usage: u64) -> Level {
+        let fraction = usage as f64 / self.limit as f64;
+        if fraction >= self.config.critical_threshold {
+            Level::Critical
+        } else if fraction >= self.config.warning_threshold {
+            Level::Warning
+        } else {
+            Level::Normal
+        }
+    }
+
+    /// Samples memory usage and takes whatever action the usage calls for.
+    async fn sample(&self) {
+        let usage = match tokio::task::spawn_blocking(memory_usage).await {
+            Ok(Some(usage)) => usage,
+            _ => return,
+        };
+        self.metrics.usage_bytes.set(usage);
+
+        let level = self.level(usage);
+        let prev = std::mem::replace(&mut *self.level.lock().expect("lock poisoned"), level);
+        let usage_desc = format!(
+            "{} of {} ({:.0}%)",
+            ByteSize(usage),
+            ByteSize(self.limit),
+            100.0 * usage as f64 / self.limit as f64
+        );
+        match level.cmp(&prev) {
+            Ordering::Equal => (),
+            Ordering::Greater => {
+                self.metrics
+                    .transitions
+                    .with_label_values(&[level.as_str()])
+                    .inc();
+                match level {
+                    Level::Critical => {
+                        error!("memory watchdog: memory usage is critical: {}", usage_desc)
+                    }
+                    _ => warn!("memory watchdog: memory usage is high: {}", usage_desc),
+                }
+                self.log_top_dataflows().await;
+                if self.config.heap_profiles {
+                    self.dump_heap_profile(level).await;
+                }
+            }
+            Ordering::Less => {
+                self.metrics
+                    .transitions
+                    .with_label_values(&[level.as_str()])
+                    .inc();
+                info!(
+                    "memory watchdog: memory usage fell to {} level: {}",
+                    level.as_str(),
+                    usage_desc
+                );
+            }
+        }
+
+        if level == Level::Critical {
+            if self.config.refuse_connections && self.gate.close() {
+                warn!("memory watchdog: refusing new connections until memory usage falls");
+                self.metrics.record_action("refuse_connections");
+            }
+            if self.config.cancel_queries {
+                self.cancel_largest_queries().await;
+            }
+        } else if self.gate.open() {
+            info!("memory watchdog: accepting new connections again");
+            self.metrics.record_action("accept_connections");
+        }
+    }
+
+    /// Queries the introspection sources for the dataflows whose arrangements
+    /// hold the most records, optionally restricted to the transient
+    /// dataflows built for peeks.
+    async fn top_dataflows(
+        &self,
+        limit: usize,
+        peeks_only: bool,
+    ) -> Result<Vec<(String, i64)>, anyhow::Error> {
+        let res = self
+            .coord_client
+            .system_execute_one(&format!(
+                "SELECT name, records::pg_catalog.int8
+                 FROM mz_catalog.mz_records_per_dataflow_global
+                 {}
+                 ORDER BY records DESC
+                 LIMIT {}",
+                if peeks_only {
+                    "WHERE name LIKE 'temp-view-%'"
+                } else {
+                    ""
+                },
+                limit
+            ))
+            .await?;
+        res.rows
+            .into_iter()
+            .map(|row| match (row.get(0), row.get(1)) {
+                (Some(serde_json::Value::String(name)), Some(records)) => {
+                    Ok((name.clone(), records.as_i64().unwrap_or(0)))
+                }
+                _ => bail!("unexpected row: {:?}", row),
+            })
+            .collect()
+    }
+
+    async fn log_top_dataflows(&self) {
+        match self.top_dataflows(TOP_DATAFLOWS, false).await {
+            Ok(dataflows) if dataflows.is_empty() => {
+                warn!("memory watchdog: no dataflows hold arranged records");
+                self.metrics.record_action("log_dataflows");
+            }
+            Ok(dataflows) => {
+                let desc: Vec<_> = dataflows
+                    .iter()
+                    .map(|(name, records)| format!("{} ({} records)", name, records))
+                    .collect();
+                warn!(
+                    "memory watchdog: dataflows with the largest arrangements: {}",
+                    desc.join(", ")
+                );
+                self.metrics.record_action("log_dataflows");
+            }
+            Err(e) => warn!(
+                "memory watchdog: unable to determine the largest arrangements: {:#}",
+                e
+            ),
+        }
+    }
+
+    async fn cancel_largest_queries(&self) {
+        let dataflows = match self.top_dataflows(MAX_CANCELS_PER_SAMPLE, true).await {
+            Ok(dataflows) => dataflows,
+            Err(e) => {
+                warn!(
+                    "memory watchdog: unable to determine the largest queries: {:#}",
+                    e
+                );
+                return;
+            }
+        };
+        let names = dataflows.iter().map(|(name, _)| name.clone()).collect();
+        for (dataflow, conn_id) in self.coord_client.cancel_dataflow_queries(names).await {
+            let records = dataflows
+                .iter()
+                .find(|(name, _)| *name == dataflow)
+                .map_or(0, |(_, records)| *records);
+            warn!(
+                "memory watchdog: canceled query on connection {}, whose dataflow {} holds {} records",
+                conn_id, dataflow, records
+            );
+            self.metrics.record_action("cancel_query");
+        }
+    }
+
+    async fn dump_heap_profile(&self, level: Level) {
+        let path = self.diagnostics_directory.join(format!(
+            "heap-{}-{}.prof",
+            DateTime::<Utc>::from(SystemTime::now()).format("%Y%m%dT%H%M%SZ"),
+            level.as_str(),
+        ));
+        match heap_profile::dump(&path).await {
+            Ok(true) => {
+                warn!("memory watchdog: wrote heap profile to {}", path.display());
+                self.metrics.record_action("heap_profile");
+            }
+            Ok(false) => {
+                warn!(
+                    "memory watchdog: heap profiling was inactive, so no heap profile was written; \
+                     activated heap profiling so that the next profile records allocations from now on"
+                );
+                self.metrics.record_action("activate_heap_profiling");
+            }
+            Err(e) => warn!("memory watchdog: unable to write heap profile: {:#}", e),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) mod heap_profile {
+    use std::path::Path;
+
+    use anyhow::bail;
+
+    pub async fn dump(_: &Path) -> Result<bool, anyhow::Error> {
+        bail!("jemalloc is not used on macOS")
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) mod heap_profile {
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::Path;
+
+    use anyhow::{bail, Context};
+
+    use prof::jemalloc::PROF_CTL;
+
+    /// Dumps a heap profile to `path`.
+    ///
+    /// If heap profiling is inactive, activates it instead and returns false,
+    /// as a profile would not record any allocations.
+    pub async fn dump(path: &Path) -> Result<bool, anyhow::Error> {
+        let ctl = match PROF_CTL.as_ref() {
+            Some(ctl) => ctl,
+            None => bail!("heap profiling is not enabled"),
+        };
+        let mut ctl = ctl.lock().await;
+        if ctl.get_md().start_time.is_none() {
+            ctl.activate()?;
+            return Ok(false);
+        }
+        let mut profile = ctl.dump()?;
+        drop(ctl);
+        let dir = path.parent().expect("heap profile path has a parent");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("creating diagnostics directory: {}", dir.display()))?;
+        let mut file = File::create(path)?;
+        io::copy(&mut profile, &mut file)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteSize, MemoryWatchdogConfig};
+
+    #[test]
+    fn test_byte_size() {
+        for (input, expected) in &[
+            ("0", 0),
+            ("1024", 1024),
+            ("512B", 512),
+            ("4KiB", 4 << 10),
+            ("2 GiB", 2 << 30),
+            ("3MB", 3_000_000),
+            ("1TiB", 1 << 40),
+        ] {
+            assert_eq!(input.parse::<ByteSize>().unwrap(), ByteSize(*expected));
+        }
+        for input in &["", "GiB", "1.5GiB", "4XB", "-1", "99999999999999TiB"] {
+            assert!(input.parse::<ByteSize>().is_err(), "{} parsed", input);
+        }
+        assert_eq!(ByteSize(1536 << 20).to_string(), "1.5GiB");
+        assert_eq!(ByteSize(100).to_string(), "100B");
+    }
+
+    #[test]
+    fn test_validate_thresholds() {
+        let config = |warning_threshold, critical_threshold| MemoryWatchdogConfig {
+            warning_threshold,
+            critical_threshold,
+            ..Default::default()
+        };
+        assert!(config(0.8, 0.95).validate().is_ok());
+        assert!(config(0.9, 0.9).validate().is_ok());
+        assert!(config(0.95, 0.8).validate().is_err());
+        assert!(config(0.0, 0.8).validate().is_err());
+        assert!(config(0.8, 1.5).validate().is_err());
+    }
+}
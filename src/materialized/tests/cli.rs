@@ -7,11 +7,17 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
+use std::error::Error;
 use std::ffi::OsStr;
+use std::net::TcpListener;
 use std::os::unix::ffi::OsStrExt;
-use std::time::Duration;
+use std::path::Path;
+use std::process::{self, Child, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use assert_cmd::Command;
+use postgres::NoTls;
 use predicates::prelude::*;
 
 fn cmd() -> Command {
@@ -53,3 +59,65 @@ fn test_threads() {
     // has started correctly, since it runs forever. The success code path is
     // well exercised by integration tests, so it's not a big deal.
 }
+
+/// A `materialized` process that is killed when dropped.
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts `materialized` with the specified data directory and durability
+/// level and connects to it.
+fn start_materialized(
+    data_directory: &Path,
+    durability: &str,
+) -> Result<(KillOnDrop, postgres::Client), Box<dyn Error>> {
+    // Reserve a free port for the server. There is a small window in which
+    // another process could claim the port, which is acceptable in tests.
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let child = process::Command::new(assert_cmd::cargo::cargo_bin("materialized"))
+        .env_clear()
+        .env("MZ_DEV", "1")
+        .arg("--data-directory")
+        .arg(data_directory)
+        .arg(format!("--listen-addr=127.0.0.1:{}", port))
+        .arg(format!("--durability={}", durability))
+        .arg("--disable-telemetry")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let child = KillOnDrop(child);
+    let conn_str = format!("host=127.0.0.1 port={} user=materialize", port);
+    let start = Instant::now();
+    loop {
+        match postgres::Client::connect(&conn_str, NoTls) {
+            Ok(client) => return Ok((child, client)),
+            Err(e) if start.elapsed() > Duration::from_secs(30) => return Err(e.into()),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Tests that catalog writes survive `materialized` being killed with SIGKILL
+/// at every durability level.
+#[test]
+fn test_durability_kill() -> Result<(), Box<dyn Error>> {
+    for durability in &["full", "async", "none"] {
+        let data_dir = tempfile::tempdir()?;
+
+        let (mut child, mut client) = start_materialized(data_dir.path(), durability)?;
+        client.batch_execute("CREATE VIEW v AS SELECT 1 AS a")?;
+        // `Child::kill` sends SIGKILL.
+        child.0.kill()?;
+        child.0.wait()?;
+
+        let (_child, mut client) = start_materialized(data_dir.path(), durability)?;
+        let row = client.query_one("SELECT a FROM v", &[])?;
+        assert_eq!(row.get::<_, i32>(0), 1, "durability level {}", durability);
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Integration tests that inject faults into the server.
+//!
+//! Failpoints are global to the process, so these tests run one at a time.
+
+#![cfg(feature = "failpoints")]
+
+use std::error::Error;
+use std::sync::{Mutex, MutexGuard};
+
+use lazy_static::lazy_static;
+use postgres::error::SqlState;
+use reqwest::{blocking::Client, StatusCode};
+use serde_json::json;
+
+use crate::util::PostgresErrorExt;
+
+pub mod util;
+
+lazy_static! {
+    static ref FAILPOINTS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Serializes the tests in this file, even if a previous test panicked.
+fn lock() -> MutexGuard<'static, ()> {
+    FAILPOINTS_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Configures a failpoint through the server's HTTP endpoint, and returns the
+/// endpoint's report of the failpoints.
+fn configure(
+    server: &util::Server,
+    params: &[(&str, &str)],
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    let res = Client::new()
+        .post(&format!(
+            "http://{}/api/failpoints",
+            server.inner.local_addr()
+        ))
+        .form(params)
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    Ok(serde_json::from_str(&res.text()?)?)
+}
+
+#[test]
+fn test_accept_recovers_from_emfile() -> Result<(), Box<dyn Error>> {
+    let _guard = lock();
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().fault_injection(Default::default()))?;
+    let status = configure(
+        &server,
+        &[("name", "mux_accept"), ("fault", "error"), ("times", "2")],
+    )?;
+    assert_eq!(
+        status["active"],
+        json!([{ "name": "mux_accept", "actions": "2*return" }])
+    );
+
+    // The next two connections are dropped as they are accepted, as if the
+    // server had run out of file descriptors, but the accept loop carries on.
+    for _ in 0..2 {
+        assert!(server.connect(postgres::NoTls).is_err());
+    }
+    let mut client = server.connect(postgres::NoTls)?;
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    let status = configure(&server, &[("name", "mux_accept"), ("fault", "off")])?;
+    assert_eq!(status["active"], json!([]));
+
+    Ok(())
+}
+
+#[test]
+fn test_coord_send_timeout() -> Result<(), Box<dyn Error>> {
+    let _guard = lock();
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().fault_injection(Default::default()))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    configure(
+        &server,
+        &[
+            ("name", "coord_client_send"),
+            ("fault", "error"),
+            ("times", "1"),
+        ],
+    )?;
+
+    // A command that times out on its way to the coordinator is reported to
+    // the client as a statement timeout, and the session remains usable.
+    let err = client.batch_execute("SELECT 1").unwrap_err();
+    let err = err.unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::QUERY_CANCELED);
+    assert_eq!(
+        err.message(),
+        "canceling statement due to statement timeout"
+    );
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    configure(&server, &[("name", "coord_client_send"), ("fault", "off")])?;
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_write_failure() -> Result<(), Box<dyn Error>> {
+    let _guard = lock();
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().fault_injection(Default::default()))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    configure(
+        &server,
+        &[
+            ("name", "catalog_write"),
+            ("fault", "error"),
+            ("times", "1"),
+        ],
+    )?;
+
+    // A failed catalog write fails the statement and leaves no trace in the
+    // catalog.
+    assert!(client.batch_execute("CREATE TABLE t (a int)").is_err());
+    assert!(client.batch_execute("SELECT * FROM t").is_err());
+    client.batch_execute("CREATE TABLE t (a int)")?;
+    client.batch_execute("SELECT * FROM t")?;
+
+    // Unknown failpoints are refused.
+    let res = Client::new()
+        .post(&format!(
+            "http://{}/api/failpoints",
+            server.inner.local_addr()
+        ))
+        .form(&[("name", "nonexistent"), ("fault", "error")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    configure(&server, &[("name", "catalog_write"), ("fault", "off")])?;
+
+    Ok(())
+}
@@ -9,6 +9,7 @@
 
 //! Integration tests for pgwire functionality.
 
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::error::Error;
 use std::io::{Read, Write};
@@ -97,6 +98,48 @@ fn test_bind_params() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_cancel_request_outcomes() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let client = server.connect(postgres::NoTls)?;
+
+    // Secret keys whose high bits differ belong to different instances, so at
+    // most one of these requests can be meant for this instance.
+    for secret_key in &[0, u32::MAX] {
+        let mut conn = TcpStream::connect(server.inner.local_addr())?;
+        conn.write_all(&16_u32.to_be_bytes())?;
+        conn.write_all(&80877102_u32.to_be_bytes())?;
+        conn.write_all(&u32::MAX.to_be_bytes())?;
+        conn.write_all(&secret_key.to_be_bytes())?;
+        // The server closes the connection once it has handled the request.
+        conn.read_to_end(&mut vec![])?;
+    }
+    client.cancel_token().cancel_query(postgres::NoTls)?;
+
+    let outcomes: HashMap<_, _> = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_pg_cancel_requests")
+        .expect("cancel request metric present")
+        .get_metric()
+        .iter()
+        .map(|metric| {
+            (
+                metric.get_label()[0].get_value().to_string(),
+                metric.get_counter().get_value(),
+            )
+        })
+        .collect();
+    assert_eq!(outcomes.get("matched"), Some(&1.0));
+    assert!(outcomes.get("foreign").copied().unwrap_or(0.0) >= 1.0);
+    assert_eq!(outcomes.values().sum::<f64>(), 3.0);
+
+    Ok(())
+}
+
 #[test]
 fn test_partial_read() -> Result<(), Box<dyn Error>> {
     ore::test::init_logging();
@@ -145,6 +188,362 @@ fn test_read_many_rows() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_buffer_config() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().pgwire_buffers(
+        Some(64 << 10),
+        Some(1 << 10),
+        Some(Duration::from_millis(100)),
+    ))?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    // Results larger than the write buffer, and queries larger than the read
+    // buffer, arrive intact.
+    let query = format!(
+        "SELECT repeat('x', 100), generate_series FROM generate_series(1, 10000) WHERE '{}' <> ''",
+        "y".repeat(4 << 10)
+    );
+    for _ in 0..2 {
+        let rows = client.query(query.as_str(), &[])?;
+        assert_eq!(rows.len(), 10000);
+        assert_eq!(rows[9999].get::<_, i32>(1), 10000);
+
+        // Let the buffers shrink while the connection is idle. The
+        // connection remains usable afterwards.
+        std::thread::sleep(Duration::from_millis(300));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_drain_notices() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let runtime = Runtime::new()?;
+    // Connects a session whose notices, along with whether the server had
+    // reported that it was draining by the time of each notice, are sent to
+    // the returned channel.
+    let connect = |server: &util::Server| {
+        runtime.block_on(async {
+            let (client, mut conn) = server.pg_config_async().connect(postgres::NoTls).await?;
+            let (notice_tx, notice_rx) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                loop {
+                    let msg = match future::poll_fn(|cx| conn.poll_message(cx)).await {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+                    if let Ok(tokio_postgres::AsyncMessage::Notice(n)) = msg {
+                        let draining = conn.parameter("mz_server_draining").map(String::from);
+                        let _ = notice_tx.send((n, draining));
+                    }
+                }
+            });
+            Ok::<_, Box<dyn Error>>((client, notice_rx))
+        })
+    };
+
+    let mut server = util::start_server(util::Config::default())?;
+    let (_idle_client, mut idle_notices) = connect(&server)?;
+    let (busy_client, mut busy_notices) = connect(&server)?;
+    let http_client = reqwest::blocking::Client::new();
+    let status_url = format!("http://{}/api/status", server.inner.local_addr());
+    assert!(http_client.get(&status_url).send()?.status().is_success());
+
+    let statement = runtime.spawn(async move {
+        busy_client
+            .batch_execute("SELECT mz_internal.mz_sleep(1)")
+            .await
+            .map(|()| busy_client)
+    });
+    std::thread::sleep(Duration::from_millis(300));
+    server.inner.drain("test");
+
+    // Idle sessions are notified right away.
+    let (notice, draining) = runtime
+        .block_on(tokio::time::timeout(
+            Duration::from_secs(5),
+            idle_notices.recv(),
+        ))?
+        .unwrap();
+    assert_eq!(*notice.code(), SqlState::ADMIN_SHUTDOWN);
+    assert_eq!(notice.message(), "the server is shutting down");
+    assert_eq!(draining.as_deref(), Some("on"));
+
+    // Sessions in the middle of a statement are notified once the statement
+    // completes.
+    assert!(busy_notices.try_recv().is_err());
+    let busy_client = runtime.block_on(statement)??;
+    let (notice, _) = runtime
+        .block_on(tokio::time::timeout(
+            Duration::from_secs(5),
+            busy_notices.recv(),
+        ))?
+        .unwrap();
+    assert_eq!(*notice.code(), SqlState::ADMIN_SHUTDOWN);
+    // The session remains usable until the server shuts down.
+    runtime.block_on(busy_client.batch_execute("SELECT 1"))?;
+
+    // HTTP clients are told to take their requests elsewhere.
+    let res = http_client.get(&status_url).send()?;
+    assert_eq!(res.headers()["connection"], "close");
+    assert!(res.headers().contains_key("retry-after"));
+
+    // Embedders can opt out of the notices.
+    let mut server = util::start_server(util::Config::default().drain_notices(false))?;
+    let (_client, mut notices) = connect(&server)?;
+    server.inner.drain("test");
+    let res = runtime.block_on(tokio::time::timeout(
+        Duration::from_millis(500),
+        notices.recv(),
+    ));
+    assert!(res.is_err(), "session unexpectedly notified of drain");
+    let res = http_client
+        .get(&format!("http://{}/api/status", server.inner.local_addr()))
+        .send()?;
+    assert!(!res.headers().contains_key("retry-after"));
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_notice() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let runtime = Runtime::new()?;
+    // Returns the messages of the notices that a new session receives once it
+    // starts.
+    let notices = |config: tokio_postgres::Config| {
+        runtime.block_on(async {
+            let (client, mut conn) = config.connect(postgres::NoTls).await?;
+            let (notice_tx, mut notice_rx) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Some(msg) = future::poll_fn(|cx| conn.poll_message(cx)).await {
+                    if let Ok(tokio_postgres::AsyncMessage::Notice(n)) = msg {
+                        let _ = notice_tx.send(n.message().to_owned());
+                    }
+                }
+            });
+            // Notices that arrive during startup are delivered before the
+            // result of the first statement.
+            client.batch_execute("SELECT 1").await?;
+            drop(client);
+            let mut notices = vec![];
+            while let Some(notice) = notice_rx.recv().await {
+                notices.push(notice);
+            }
+            Ok::<_, Box<dyn Error>>(notices)
+        })
+    };
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("notice");
+    std::fs::write(&path, "this is the staging cluster ({version})\n")?;
+    let server = util::start_server(
+        util::Config::default()
+            .connection_notice(materialized::ConnectionNoticeSource::File(path.clone())),
+    )?;
+    let expected = format!(
+        "this is the staging cluster ({})",
+        materialized::BUILD_INFO.version
+    );
+    assert_eq!(notices(server.pg_config_async())?, vec![expected.clone()]);
+
+    // Clients may opt out of the notice.
+    let mut config = server.pg_config_async();
+    config.options("-c client_min_messages=warning");
+    assert!(notices(config)?.is_empty());
+
+    // The HTTP API reports the notice.
+    let http_client = reqwest::blocking::Client::new();
+    let status: serde_json::Value = serde_json::from_str(
+        &http_client
+            .get(&format!("http://{}/api/status", server.inner.local_addr()))
+            .send()?
+            .text()?,
+    )?;
+    assert_eq!(status["connection_notice"], expected.as_str());
+    let res: serde_json::Value = serde_json::from_str(
+        &http_client
+            .post(&format!("http://{}/sql", server.inner.local_addr()))
+            .form(&[("sql", "SELECT 1")])
+            .send()?
+            .text()?,
+    )?;
+    assert_eq!(res["notice"], expected.as_str());
+
+    // The notice is reloaded on SIGHUP.
+    std::fs::write(&path, "data resets nightly")?;
+    nix::sys::signal::kill(nix::unistd::getpid(), nix::sys::signal::Signal::SIGHUP)?;
+    let start = std::time::Instant::now();
+    while notices(server.pg_config_async())? != vec!["data resets nightly".to_owned()] {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "notice not reloaded"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // No notice is sent by default.
+    let server = util::start_server(util::Config::default())?;
+    assert!(notices(server.pg_config_async())?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_max_message_size() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().pgwire_max_message_size(1 << 20))?;
+
+    // Resident memory, in pages, of the process hosting the server.
+    fn resident_pages() -> Result<u64, Box<dyn Error>> {
+        let statm = std::fs::read_to_string("/proc/self/statm")?;
+        Ok(statm.split_whitespace().nth(1).unwrap().parse()?)
+    }
+
+    let mut conn = TcpStream::connect(server.inner.local_addr())?;
+    let params = b"user\0materialize\0\0";
+    conn.write_all(&(8 + params.len() as u32).to_be_bytes())?;
+    conn.write_all(&196608_u32.to_be_bytes())?;
+    conn.write_all(params)?;
+    // Wait for the server to report that it is ready for a query.
+    let mut message_type = [0; 1];
+    loop {
+        let mut len = [0; 4];
+        conn.read_exact(&mut message_type)?;
+        conn.read_exact(&mut len)?;
+        let mut body = vec![0; u32::from_be_bytes(len) as usize - 4];
+        conn.read_exact(&mut body)?;
+        if message_type[0] == b'Z' {
+            break;
+        }
+    }
+
+    // A Bind message that claims to be enormous is rejected from its header
+    // alone, without the server allocating space for it.
+    let before = resident_pages()?;
+    conn.write_all(b"B")?;
+    conn.write_all(&1_500_000_000_u32.to_be_bytes())?;
+    let mut response = vec![];
+    conn.read_to_end(&mut response)?;
+    assert_eq!(response[0], b'E');
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.contains("C54000\0"));
+    assert!(response.contains("exceeds the maximum message size of 1048576 bytes"));
+    let after = resident_pages()?;
+    assert!(after.saturating_sub(before) * 4096 < 64 << 20);
+
+    let oversized = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_pg_oversized_messages")
+        .expect("oversized message metric present");
+    assert_eq!(oversized.get_metric()[0].get_counter().get_value(), 1.0);
+
+    // Messages within the limit are unaffected, and results larger than the
+    // limit are still sent to clients.
+    let mut client = server.connect(postgres::NoTls)?;
+    let row = client.query_one("SELECT repeat('x', 4 << 20)", &[])?;
+    assert_eq!(row.get::<_, String>(0).len(), 4 << 20);
+
+    Ok(())
+}
+
+#[test]
+fn test_reserved_superuser_connections() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().max_connections(3, 1, &["mz_admin"]);
+    let server = util::start_server(config)?;
+
+    // Ordinary users can take all but the reserved slot.
+    let mut client1 = server.connect(postgres::NoTls)?;
+    let _client2 = server.connect(postgres::NoTls)?;
+    client1.batch_execute("SELECT 1")?;
+    let err = server
+        .pg_config()
+        .connect(postgres::NoTls)
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::TOO_MANY_CONNECTIONS);
+    assert_eq!(
+        err.message(),
+        "remaining connection slots are reserved for superusers"
+    );
+
+    // Superusers can take the reserved slot, but no more.
+    let mut admin = server
+        .pg_config()
+        .user("mz_admin")
+        .connect(postgres::NoTls)?;
+    admin.batch_execute("SELECT 1")?;
+    let err = server
+        .pg_config()
+        .user("mz_admin")
+        .connect(postgres::NoTls)
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::TOO_MANY_CONNECTIONS);
+    assert_eq!(err.message(), "sorry, too many clients already");
+
+    // Closing a session frees its slot, though the server may take a moment
+    // to notice.
+    drop(client1);
+    let mut attempts = 0;
+    while let Err(e) = server.pg_config().connect(postgres::NoTls) {
+        attempts += 1;
+        if attempts == 50 {
+            return Err(e.into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_server_version_override() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().pgwire_server_version("13.4"))?;
+    let runtime = Runtime::new()?;
+    let client = runtime.block_on(async {
+        let (client, conn) = server.pg_config_async().connect(postgres::NoTls).await?;
+        // The override is reported in the startup parameter status messages.
+        assert_eq!(conn.parameter("server_version"), Some("13.4"));
+        runtime.spawn(conn);
+        Ok::<_, Box<dyn Error>>(client)
+    })?;
+
+    let show = |name: &str| -> Result<String, Box<dyn Error>> {
+        let row = runtime.block_on(client.query_one(format!("SHOW {}", name).as_str(), &[]))?;
+        Ok(row.get(0))
+    };
+    assert_eq!(show("server_version")?, "13.4");
+    assert_eq!(show("server_version_num")?, "130004");
+
+    // The real version remains discoverable.
+    let mz_version: String = runtime
+        .block_on(client.query_one("SELECT mz_version()", &[]))?
+        .get(0);
+    let status: serde_json::Value = serde_json::from_str(
+        &reqwest::blocking::get(&format!("http://{}/api/status", server.inner.local_addr()))?
+            .text()?,
+    )?;
+    let version = status["version"].as_str().unwrap();
+    assert_ne!(version, "13.4");
+    assert!(mz_version.contains(version));
+
+    // Versions that do not look like PostgreSQL versions are rejected.
+    assert!(util::start_server(util::Config::default().pgwire_server_version("v13")).is_err());
+
+    Ok(())
+}
+
 #[test]
 fn test_conn_startup() -> Result<(), Box<dyn Error>> {
     ore::test::init_logging();
@@ -362,6 +761,104 @@ fn test_copy() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Drives COPY the way that psql's `\copy` does: `\copy (SELECT ...) TO ...`
+// issues `COPY (SELECT ...) TO STDOUT`, and `\copy t FROM ...` issues
+// `COPY t FROM STDIN`, streaming the file in chunks that need not align with
+// row boundaries.
+#[test]
+fn test_copy_csv() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int, b text)")?;
+
+    let data = "1,plain\n2,\"a,\"\"b\"\"\nc\"\n,\"\"\n3,\n";
+    let mut writer = client.copy_in("COPY t FROM STDIN (FORMAT CSV)")?;
+    for chunk in data.as_bytes().chunks(3) {
+        writer.write_all(chunk)?;
+        writer.flush()?;
+    }
+    assert_eq!(writer.finish()?, 4);
+
+    let rows: Vec<(Option<i32>, Option<String>)> = client
+        .query("SELECT a, b FROM t ORDER BY a", &[])?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    assert_eq!(
+        rows,
+        &[
+            (Some(1), Some("plain".into())),
+            (Some(2), Some("a,\"b\"\nc".into())),
+            (Some(3), None),
+            (None, Some("".into())),
+        ]
+    );
+
+    let mut buf = String::new();
+    client
+        .copy_out("COPY (SELECT a, b FROM t ORDER BY a) TO STDOUT (FORMAT CSV)")?
+        .read_to_string(&mut buf)?;
+    assert_eq!(buf, "1,plain\n2,\"a,\"\"b\"\"\nc\"\n3,\n,\"\"\n");
+
+    // The text format round trips through the same path.
+    client.batch_execute("DELETE FROM t")?;
+    let mut writer = client.copy_in("COPY t FROM STDIN")?;
+    writer.write_all(b"4\ttab\\there\n5\t\\N\n\\.\n")?;
+    assert_eq!(writer.finish()?, 2);
+    let mut buf = String::new();
+    client
+        .copy_out("COPY (SELECT a, b FROM t ORDER BY a) TO STDOUT")?
+        .read_to_string(&mut buf)?;
+    assert_eq!(buf, "4\ttab\\there\n5\t\\N\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_limits() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().max_result_rows(2))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int)")?;
+
+    // Rows copied in count against the result size limits, as they are
+    // buffered until the copy completes.
+    let mut writer = client.copy_in("COPY t FROM STDIN")?;
+    writer.write_all(b"1\n2\n3\n")?;
+    let err = writer.finish().unwrap_db_error();
+    assert!(err
+        .message()
+        .starts_with("result exceeds the limit of 2 rows"));
+    let count: i64 = client.query_one("SELECT count(*) FROM t", &[])?.get(0);
+    assert_eq!(count, 0);
+
+    // As do rows copied out.
+    let mut buf = String::new();
+    let err = client
+        .copy_out("COPY (VALUES (1), (2), (3)) TO STDOUT")?
+        .read_to_string(&mut buf)
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("result exceeds the limit of 2 rows"));
+
+    // A copy that outlives the statement timeout is canceled.
+    client.batch_execute("SET statement_timeout = '500ms'")?;
+    let mut buf = vec![];
+    let err = client
+        .copy_out("COPY (TAIL t) TO STDOUT")?
+        .read_to_end(&mut buf)
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("canceling statement due to statement timeout"));
+
+    Ok(())
+}
+
 #[test]
 fn test_arrays() -> Result<(), Box<dyn Error>> {
     ore::test::init_logging();
@@ -11,9 +11,18 @@
 
 use std::collections::HashMap;
 use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use postgres::error::SqlState;
 use reqwest::{blocking::Client, StatusCode, Url};
 use tempfile::NamedTempFile;
+use tokio::runtime::Runtime;
 
 use crate::util::{PostgresErrorExt, KAFKA_ADDRS};
 
@@ -278,23 +287,3941 @@ fn test_http_sql() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Test that the /sql endpoint streams results in the Arrow IPC format, and
+// that they agree with the JSON results of the same query.
 #[test]
-fn test_metrics_registry_hygiene() -> Result<(), Box<dyn Error>> {
-    // Minor setup chores to ensure the server has done at least a little work:
+fn test_http_sql_arrow() -> Result<(), Box<dyn Error>> {
+    use arrow::array::{
+        Array, BooleanArray, DecimalArray, Float64Array, Int32Array, Int64Array, StringArray,
+        StructArray, TimestampMicrosecondArray,
+    };
+    use arrow::datatypes::{DataType, TimeUnit};
+    use arrow::ipc::reader::StreamReader;
+    use chrono::NaiveDate;
+
+    let server = util::start_server(util::Config::default().http_arrow_max_batch_size(100))?;
+    let url = format!("http://{}/sql", server.inner.local_addr());
+    let query = "SELECT
+            x AS i,
+            x::text AS t,
+            x % 2 = 0 AS b,
+            x::float8 / 4 AS f,
+            (x::numeric / 4)::numeric(10, 2) AS n,
+            NULLIF(x, 3) AS nul,
+            TIMESTAMP '2021-01-02 03:04:05.123456' + x * INTERVAL '1 second' AS ts,
+            x * INTERVAL '1 month 1 second' AS iv
+        FROM generate_series(1, 250) AS g (x)
+        ORDER BY i";
+
+    let res = Client::new().post(&url).form(&[("sql", query)]).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let json: serde_json::Value = res.json()?;
+    let json_rows = json["results"][0]["rows"].as_array().unwrap().clone();
+    assert_eq!(json_rows.len(), 250);
+
+    let res = Client::new()
+        .post(&format!("{}?format=arrow&batch_size=64", url))
+        .form(&[("sql", query)])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()["content-type"],
+        "application/vnd.apache.arrow.stream"
+    );
+    let reader = StreamReader::try_new(io::Cursor::new(res.bytes()?))?;
+    let schema = reader.schema();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["i", "t", "b", "f", "n", "nul", "ts", "iv"]);
+    assert_eq!(schema.field(4).data_type(), &DataType::Decimal(38, 2));
+    assert_eq!(
+        schema.field(6).data_type(),
+        &DataType::Timestamp(TimeUnit::Microsecond, None)
+    );
+
+    let epoch = NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0);
+    let mut batch_sizes = vec![];
+    let mut json_rows = json_rows.iter();
+    for batch in reader {
+        let batch = batch?;
+        batch_sizes.push(batch.num_rows());
+        let column = |i| batch.column(i).as_any();
+        let i = column(0).downcast_ref::<Int32Array>().unwrap();
+        let t = column(1).downcast_ref::<StringArray>().unwrap();
+        let b = column(2).downcast_ref::<BooleanArray>().unwrap();
+        let f = column(3).downcast_ref::<Float64Array>().unwrap();
+        let n = column(4).downcast_ref::<DecimalArray>().unwrap();
+        let nul = column(5).downcast_ref::<Int32Array>().unwrap();
+        let ts = column(6)
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        let iv = column(7).downcast_ref::<StructArray>().unwrap();
+        let iv_months = iv.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+        let iv_nanos = iv.column(1).as_any().downcast_ref::<Int64Array>().unwrap();
+        for row in 0..batch.num_rows() {
+            let expected = json_rows.next().unwrap();
+            assert_eq!(i64::from(i.value(row)), expected[0].as_i64().unwrap());
+            assert_eq!(t.value(row), expected[1].as_str().unwrap());
+            assert_eq!(b.value(row), expected[2].as_bool().unwrap());
+            assert_eq!(f.value(row), expected[3].as_f64().unwrap());
+            assert_eq!(n.value(row) as f64 / 100.0, expected[4].as_f64().unwrap());
+            match expected[5].as_i64() {
+                None => assert!(nul.is_null(row)),
+                Some(expected) => assert_eq!(i64::from(nul.value(row)), expected),
+            }
+            let x = i64::from(i.value(row));
+            let expected_ts = NaiveDate::from_ymd(2021, 1, 2).and_hms_micro(3, 4, 5, 123456)
+                + chrono::Duration::seconds(x);
+            assert_eq!(
+                ts.value(row),
+                (expected_ts - epoch).num_microseconds().unwrap()
+            );
+            assert_eq!(i64::from(iv_months.value(row)), x);
+            assert_eq!(iv_nanos.value(row), x * 1_000_000_000);
+        }
+    }
+    assert!(json_rows.next().is_none());
+    assert_eq!(batch_sizes, [64, 64, 64, 58]);
+
+    // An empty result is a stream with a schema and no batches.
+    let res = Client::new()
+        .post(&format!("{}?format=arrow", url))
+        .form(&[("sql", "SELECT 1 AS a WHERE false")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let reader = StreamReader::try_new(io::Cursor::new(res.bytes()?))?;
+    assert_eq!(reader.schema().field(0).name(), "a");
+    assert_eq!(reader.count(), 0);
+
+    // Invalid requests are refused.
+    for (params, sql, error) in &[
+        (
+            "format=arrow&batch_size=101",
+            "SELECT 1",
+            "exceeds the maximum of 100",
+        ),
+        (
+            "format=arrow&batch_size=0",
+            "SELECT 1",
+            "must be a positive integer",
+        ),
+        (
+            "batch_size=10",
+            "SELECT 1",
+            "batch_size requires format=arrow",
+        ),
+        ("format=csv", "SELECT 1", "unknown format"),
+        (
+            "format=arrow",
+            "SELECT 1; SELECT 2",
+            "expected exactly one statement",
+        ),
+    ] {
+        let res = Client::new()
+            .post(&format!("{}?{}", url, params))
+            .form(&[("sql", sql)])
+            .send()?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = res.text()?;
+        assert!(body.contains(error), "{}: {}", params, body);
+    }
+
+    Ok(())
+}
+
+// Test that an Arrow result larger than a record batch is sent as several
+// batches of the default size.
+#[test]
+fn test_http_sql_arrow_batches() -> Result<(), Box<dyn Error>> {
+    use arrow::array::{Array, Int32Array};
+    use arrow::ipc::reader::StreamReader;
+
     let server = util::start_server(util::Config::default())?;
-    let source_file = NamedTempFile::new()?;
+    let url = format!("http://{}/sql?format=arrow", server.inner.local_addr());
+    let res = Client::new()
+        .post(&url)
+        .form(&[(
+            "sql",
+            format!(
+                "SELECT x FROM generate_series(1, {}) AS g (x) ORDER BY x",
+                2 * 8192 + 100
+            ),
+        )])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let reader = StreamReader::try_new(io::Cursor::new(res.bytes()?))?;
+    let mut batch_sizes = vec![];
+    let mut next = 1;
+    for batch in reader {
+        let batch = batch?;
+        batch_sizes.push(batch.num_rows());
+        let x = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        for row in 0..x.len() {
+            assert_eq!(x.value(row), next);
+            next += 1;
+        }
+    }
+    assert_eq!(batch_sizes, [8192, 8192, 100]);
+
+    Ok(())
+}
+
+// Test that HTTP/2 is served to plaintext clients with prior knowledge, if
+// enabled, and that HTTP/1 connections are closed as configured.
+#[test]
+fn test_http_protocols() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(
+        util::Config::default()
+            .http2_cleartext(true)
+            .http1_keep_alive_timeout(Duration::from_millis(500))
+            .http1_max_requests_per_connection(2),
+    )?;
+    let addr = server.inner.local_addr();
+    let url = Url::parse(&format!("http://{}/sql", addr))?;
+
+    let client = Client::builder().http2_prior_knowledge().build()?;
+    let res = client
+        .post(url.clone())
+        .form(&[("sql", "SELECT 1")])
+        .send()?;
+    assert_eq!(res.version(), reqwest::Version::HTTP_2);
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.text()?,
+        r#"{"results":[{"rows":[[1]],"col_names":[null]}]}"#
+    );
+
+    // HTTP/1 connections are closed after the maximum number of requests.
+    let client = Client::new();
+    let res = client
+        .post(url.clone())
+        .form(&[("sql", "SELECT 1")])
+        .send()?;
+    assert_eq!(res.version(), reqwest::Version::HTTP_11);
+    assert!(res.headers().get("connection").is_none());
+    let res = client.post(url).form(&[("sql", "SELECT 1")]).send()?;
+    assert_eq!(res.headers()["connection"], "close");
+
+    // Idle HTTP/1 connections are closed after the keep-alive timeout.
+    let mut conn = std::net::TcpStream::connect(addr)?;
+    conn.set_read_timeout(Some(Duration::from_secs(10)))?;
+    conn.write_all(b"GET /api/status HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+    let mut buf = [0; 4096];
+    assert!(conn.read(&mut buf)? > 0);
+    let start = Instant::now();
+    while conn.read(&mut buf)? > 0 {}
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    // Requests are counted by protocol.
+    let protocols: HashMap<_, _> = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_server_http_requests_total")
+        .expect("HTTP request metric present")
+        .get_metric()
+        .iter()
+        .filter_map(|metric| {
+            let protocol = metric
+                .get_label()
+                .iter()
+                .find(|l| l.get_name() == "protocol")?
+                .get_value()
+                .to_string();
+            Some((protocol, metric.get_counter().get_value()))
+        })
+        .collect();
+    assert_eq!(protocols["h2"], 1.0);
+    assert!(protocols["http/1.1"] >= 3.0);
+
+    Ok(())
+}
+
+// Test that HTTP requests beyond the concurrency limit are rejected, except
+// for requests to the endpoints that report on the server's health.
+#[test]
+fn test_http_concurrency_limit() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default().http_max_concurrent_requests(
+        1,
+        0,
+        Duration::from_millis(100),
+    ))?;
+    let addr = server.inner.local_addr();
+    let url = Url::parse(&format!("http://{}/sql", addr))?;
+    let gauge = |name: &str| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].get_gauge().get_value())
+            .unwrap_or(0.0)
+    };
+
+    // Occupy the only slot with a slow query.
+    let slow = thread::spawn({
+        let url = url.clone();
+        move || {
+            Client::new()
+                .post(url)
+                .form(&[("sql", "SELECT mz_internal.mz_sleep(2)")])
+                .send()
+                .map(|res| res.status())
+        }
+    });
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while gauge("mz_server_http_requests_in_flight") < 1.0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(gauge("mz_server_http_requests_in_flight"), 1.0);
+
+    // With no room in the queue, further requests are rejected immediately.
+    let res = Client::new()
+        .post(url.clone())
+        .form(&[("sql", "SELECT 1")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "1");
+
+    // Health and metrics endpoints are exempt from the limit.
+    for path in &["/metrics", "/api/status"] {
+        let res = Client::new()
+            .get(&format!("http://{}{}", addr, path))
+            .send()?;
+        assert_eq!(res.status(), StatusCode::OK, "path: {}", path);
+    }
+
+    assert_eq!(slow.join().unwrap()?, StatusCode::OK);
+    let res = Client::new()
+        .post(url)
+        .form(&[("sql", "SELECT 1")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(gauge("mz_server_http_requests_in_flight"), 0.0);
+    let rejected = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_server_http_requests_rejected_total")
+        .map(|family| family.get_metric()[0].get_counter().get_value());
+    assert_eq!(rejected, Some(1.0));
+
+    Ok(())
+}
+
+// Test that requests to the /sql endpoint whose results would exceed the
+// memory limit fail, and that buffered results are released.
+#[test]
+fn test_http_result_memory_limit() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default().http_result_memory_limit(64 << 10))?;
+    let url = Url::parse(&format!("http://{}/sql", server.inner.local_addr()))?;
+    let metric = |name: &str| {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].clone())
+            .unwrap()
+    };
+
+    let res = Client::new()
+        .post(url.clone())
+        .form(&[("sql", "SELECT 1 AS col")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.text()?,
+        r#"{"results":[{"rows":[[1]],"col_names":["col"]}]}"#
+    );
+
+    let res = Client::new()
+        .post(url)
+        .form(&[("sql", "SELECT generate_series(1, 100000)")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(res.headers()["content-type"], "application/json");
+    let body: serde_json::Value = res.json()?;
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("insufficient memory to buffer result"));
+    assert_eq!(
+        metric("mz_server_http_result_rejected_total")
+            .get_counter()
+            .get_value(),
+        1.0
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let buffered = || {
+        metric("mz_server_http_result_buffered_bytes")
+            .get_gauge()
+            .get_value()
+    };
+    while buffered() > 0.0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(buffered(), 0.0);
+
+    Ok(())
+}
+
+// Test that the introspection sources can be deactivated and reactivated via
+// the /api/introspection endpoint of the HTTP server.
+#[test]
+fn test_http_introspection() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default())?;
+    let url = Url::parse(&format!(
+        "http://{}/api/introspection",
+        server.inner.local_addr()
+    ))?;
     let mut client = server.connect(postgres::NoTls)?;
-    client.batch_execute(&format!(
-        "CREATE SOURCE src FROM FILE '{}' FORMAT BYTES",
-        source_file.path().display()
+
+    let res = Client::new().get(url.clone()).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.text()?,
+        r#"{"active":true,"debug":false,"frequency":"1000ms","retain_metrics_for":"1000ms"}"#
+    );
+
+    let res = Client::new()
+        .post(url.clone())
+        .form(&[("active", "false")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let err = client
+        .query("SELECT * FROM mz_dataflow_operators", &[])
+        .unwrap_db_error();
+    assert_eq!(
+        err.message(),
+        "introspection source \"mz_catalog.mz_dataflow_operators\" is not active"
+    );
+
+    let res = Client::new()
+        .post(url.clone())
+        .form(&[("active", "true"), ("frequency", "100ms")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    client.query("SELECT * FROM mz_dataflow_operators", &[])?;
+
+    // Introspection cannot be deactivated while an index depends on it.
+    client.batch_execute(
+        "CREATE MATERIALIZED VIEW ops AS SELECT count(*) FROM mz_dataflow_operators",
+    )?;
+    let res = Client::new()
+        .post(url.clone())
+        .form(&[("active", "false")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    client.batch_execute("DROP VIEW ops")?;
+    let res = Client::new()
+        .post(url)
+        .form(&[("active", "false")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+// Test the /api/catalog/backup endpoint of the HTTP server.
+#[test]
+fn test_http_catalog_backup() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE VIEW v AS SELECT 1")?;
+
+    let url = Url::parse(&format!(
+        "http://{}/api/catalog/backup",
+        server.inner.local_addr()
     ))?;
+    let res = Client::new().get(url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let mut entries = HashMap::new();
+    for entry in tar::Archive::new(res).entries()? {
+        let mut entry = entry?;
+        let mut contents = vec![];
+        entry.read_to_end(&mut contents)?;
+        entries.insert(entry.path()?.display().to_string(), contents);
+    }
+    assert_eq!(entries.len(), 2);
+    let manifest: serde_json::Value = serde_json::from_slice(&entries["manifest.json"])?;
+    assert_eq!(manifest["format_version"], 1);
+    assert_eq!(manifest["build_version"], materialized::BUILD_INFO.version);
+    assert!(manifest["catalog_schema_version"].as_u64().unwrap() > 0);
+    assert!(entries["catalog"].starts_with(b"SQLite format 3\0"));
+
+    // The same backup is available to embedders.
+    let backup = server
+        .runtime
+        .block_on(server.inner.backup_catalog(Vec::new()))?;
+    let names = tar::Archive::new(&backup[..])
+        .entries()?
+        .map(|entry| Ok(entry?.path()?.display().to_string()))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    assert_eq!(names, vec!["manifest.json", "catalog"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_http_catalog_dump() -> Result<(), Box<dyn Error>> {
+    fn dump(server: &util::Server, query: &str) -> Result<String, Box<dyn Error>> {
+        let url = Url::parse(&format!(
+            "http://{}/api/catalog/dump?{}",
+            server.inner.local_addr(),
+            query
+        ))?;
+        let res = Client::new().get(url).send()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        // Strip the header, which identifies the cluster.
+        Ok(res
+            .text()?
+            .lines()
+            .filter(|line| !line.starts_with("-- cluster ID"))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
     client.batch_execute(
-        "CREATE MATERIALIZED VIEW mat AS SELECT 'a', data, 'c' AS c, data FROM src",
+        "CREATE DATABASE d;
+         CREATE SCHEMA d.s;
+         CREATE TABLE d.s.t (a int);
+         CREATE MATERIALIZED VIEW mv AS SELECT a + 1 AS b FROM d.s.t;
+         CREATE INDEX i ON mv (b);
+         CREATE TEMPORARY VIEW tmp AS SELECT 1",
     )?;
+    let sql = dump(&server, "format=sql")?;
+    assert!(sql.contains("CREATE DATABASE IF NOT EXISTS \"materialize\";"));
+    assert!(sql.contains("CREATE SCHEMA \"d\".\"s\";"));
+    assert!(sql.contains("-- index d.s.t_primary_idx is created by the statement"));
+    assert!(!sql.contains("tmp"));
+
+    // The dump recreates the catalog on a fresh server.
+    let server2 = util::start_server(util::Config::default())?;
+    server2.connect(postgres::NoTls)?.batch_execute(&sql)?;
+    assert_eq!(dump(&server2, "format=sql")?, sql);
+
+    // With `IF NOT EXISTS`, the dump can be replayed against the server that
+    // produced it.
+    let sql = dump(&server, "format=sql&if_not_exists=true")?;
+    client.batch_execute(&sql)?;
+
+    let url = Url::parse(&format!(
+        "http://{}/api/catalog/dump?format=json",
+        server.inner.local_addr()
+    ))?;
+    assert_eq!(
+        Client::new().get(url).send()?.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_check_data_directory() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+
+    // A data directory that has not yet been initialized is usable.
+    assert!(materialized::check(data_dir.path(), None)?.is_ok());
+
+    {
+        let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+        let mut client = server.connect(postgres::NoTls)?;
+        client.batch_execute(
+            "CREATE TABLE t (a int);
+             CREATE MATERIALIZED VIEW v AS SELECT a + 1 AS b FROM t",
+        )?;
+    }
+    let report = materialized::check(data_dir.path(), None)?;
+    assert!(report.findings.is_empty(), "{}", report);
+
+    // A damaged catalog is reported by the check and prevents the server from
+    // starting.
+    std::fs::write(data_dir.path().join("catalog"), "garbage")?;
+    let report = materialized::check(data_dir.path(), None)?;
+    assert!(!report.is_ok());
+    assert_eq!(report.findings[0].check, "structure");
+    let err = util::start_server(util::Config::default().data_directory(data_dir.path()))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("catalog failed integrity check"));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_data_directories() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let catalog_dir = tempfile::tempdir()?;
+    let storage_dir = tempfile::tempdir()?;
+    let config = |catalog_dir: &Path, storage_dir: &Path| {
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .catalog_directory(catalog_dir)
+            .storage_directory(storage_dir)
+    };
+
+    // The catalog and the storage marker are written to their own
+    // directories, not to the data directory.
+    {
+        let server = util::start_server(config(catalog_dir.path(), storage_dir.path()))?;
+        let mut client = server.connect(postgres::NoTls)?;
+        client.batch_execute("CREATE TABLE t (a int)")?;
+    }
+    assert!(catalog_dir.path().join("catalog").exists());
+    assert!(!data_dir.path().join("catalog").exists());
+    let marker = storage_dir.path().join("storage_cluster_id");
+    assert!(marker.exists());
+    assert!(materialized::check(catalog_dir.path(), None)?.is_ok());
+
+    // The pair boots again.
+    drop(util::start_server(config(
+        catalog_dir.path(),
+        storage_dir.path(),
+    ))?);
+
+    // The catalog refuses a storage directory that it has not used.
+    let other_storage_dir = tempfile::tempdir()?;
+    let err = util::start_server(config(catalog_dir.path(), other_storage_dir.path()))
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains("does not belong to the catalog"), "{}", err);
+    assert!(err.contains("does not exist"), "{}", err);
+
+    // A new catalog refuses a storage directory that belongs to another
+    // catalog.
+    let other_catalog_dir = tempfile::tempdir()?;
+    let err = util::start_server(config(other_catalog_dir.path(), storage_dir.path()))
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains("belongs to cluster"), "{}", err);
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_catalog_backup() -> Result<(), Box<dyn Error>> {
+    let backup = tempfile::tempdir()?;
+    let backup_path = backup.path().join("backup.tar");
+    let cluster_id = {
+        let server = util::start_server(util::Config::default())?;
+        let mut client = server.connect(postgres::NoTls)?;
+        client.batch_execute("CREATE VIEW v AS SELECT 1")?;
+        let file = std::fs::File::create(&backup_path)?;
+        server.runtime.block_on(server.inner.backup_catalog(file))?;
+        let cluster_id: String = client
+            .query_one("SELECT mz_cluster_id()::text", &[])?
+            .get(0);
+        cluster_id
+    };
+
+    for (policy, same_cluster_id) in &[
+        (materialized::ClusterIdPolicy::Keep, true),
+        (materialized::ClusterIdPolicy::Regenerate, false),
+    ] {
+        let server = util::start_server(util::Config::default().restore(&backup_path, *policy))?;
+        let mut client = server.connect(postgres::NoTls)?;
+        let row = client.query_one("SELECT * FROM v", &[])?;
+        assert_eq!(row.get::<_, i32>(0), 1);
+        let restored_id: String = client
+            .query_one("SELECT mz_cluster_id()::text", &[])?
+            .get(0);
+        assert_eq!(restored_id == cluster_id, *same_cluster_id);
+    }
+
+    // Restoring into a data directory that already contains a catalog is
+    // refused.
+    let data_dir = tempfile::tempdir()?;
+    drop(util::start_server(
+        util::Config::default().data_directory(data_dir.path()),
+    )?);
+    let res = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .restore(&backup_path, materialized::ClusterIdPolicy::Keep),
+    );
+    assert!(res
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("already contains a catalog"));
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_replica() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let replica = tempfile::tempdir()?;
+    let target = materialized::ReplicaTarget::Directory(replica.path().into());
+    let cluster_id = {
+        let server = util::start_server(util::Config::default().catalog_replica(target.clone()))?;
+        let metric = |name: &str| -> f64 {
+            server
+                .metrics_registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == name)
+                .map(|family| {
+                    family
+                        .get_metric()
+                        .iter()
+                        .filter(|m| m.get_label().iter().all(|l| l.get_value() != "error"))
+                        .map(|m| m.get_counter().get_value() + m.get_gauge().get_value())
+                        .sum()
+                })
+                .unwrap_or(0.0)
+        };
+        // Waits until a copy of the catalog has been shipped since `shipped`
+        // copies were, and the replica reflects every committed change.
+        let wait_for_replica = |shipped: f64| -> Result<f64, Box<dyn Error>> {
+            let deadline = Instant::now() + Duration::from_secs(30);
+            loop {
+                let replications = metric("mz_catalog_replications_total");
+                if replications > shipped && metric("mz_catalog_replica_lag_seconds") == 0.0 {
+                    return Ok(replications);
+                } else if Instant::now() > deadline {
+                    return Err("catalog was never replicated".into());
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let shipped = wait_for_replica(0.0)?;
+        let mut client = server.connect(postgres::NoTls)?;
+        client.batch_execute("CREATE VIEW v AS SELECT 1")?;
+        wait_for_replica(shipped)?;
+        let cluster_id: String = client
+            .query_one("SELECT mz_cluster_id()::text", &[])?
+            .get(0);
+        cluster_id
+    };
+    assert!(replica.path().join("catalog-replica.tar").exists());
+
+    // A standby boots from the replica as the server that replicated it.
+    let server = util::start_server(util::Config::default().from_replica(target))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    let row = client.query_one("SELECT * FROM v", &[])?;
+    assert_eq!(row.get::<_, i32>(0), 1);
+    let standby_id: String = client
+        .query_one("SELECT mz_cluster_id()::text", &[])?
+        .get(0);
+    assert_eq!(standby_id, cluster_id);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_only() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let data_dir = tempfile::tempdir()?;
+    let mut source_file = NamedTempFile::new()?;
+    writeln!(source_file, "a")?;
+    {
+        let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+        let mut client = server.connect(postgres::NoTls)?;
+        client.batch_execute(&format!(
+            "CREATE SOURCE src FROM FILE '{}' FORMAT TEXT;
+             CREATE MATERIALIZED VIEW mat AS SELECT text FROM src",
+            source_file.path().display()
+        ))?;
+    }
+    let catalog_path = data_dir.path().join("catalog");
+    let catalog = std::fs::read(&catalog_path)?;
+
+    writeln!(source_file, "b")?;
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .read_only(),
+    )?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    // Sources still ingest data and views are still maintained.
+    let start = Instant::now();
+    loop {
+        let rows = client.query("SELECT * FROM mat", &[])?;
+        if rows.len() == 2 {
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "{} rows",
+            rows.len()
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Statements that modify the catalog or write data are rejected.
+    for (stmt, op) in &[
+        ("CREATE VIEW v AS SELECT 1", "CREATE VIEW"),
+        ("CREATE TABLE t (a int)", "CREATE TABLE"),
+        ("DROP VIEW mat", "DROP VIEW"),
+        ("ALTER VIEW mat RENAME TO mat2", "ALTER VIEW"),
+    ] {
+        let err = client.batch_execute(stmt).unwrap_db_error();
+        assert_eq!(err.code(), &SqlState::READ_ONLY_SQL_TRANSACTION);
+        assert_eq!(
+            err.message(),
+            format!("cannot execute {} in read-only mode", op)
+        );
+    }
+
+    let url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value = serde_json::from_str(&Client::new().get(url).send()?.text()?)?;
+    assert_eq!(status["read_only"], true);
+
+    // Nothing was written back to the catalog, including the timestamp
+    // bindings for the newly ingested data.
+    drop(client);
+    drop(server);
+    assert_eq!(std::fs::read(&catalog_path)?, catalog);
+
+    Ok(())
+}
+
+#[test]
+fn test_maintenance_mode() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let data_dir = tempfile::tempdir()?;
+    let config = util::Config::default()
+        .data_directory(data_dir.path())
+        .sticky_maintenance_mode();
+    let server = util::start_server(config.clone())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1)")?;
+
+    let maintenance_url = Url::parse(&format!(
+        "http://{}/api/admin/maintenance",
+        server.inner.local_addr()
+    ))?;
+    let set_maintenance = |params: &[(&str, &str)]| -> Result<_, Box<dyn Error>> {
+        let res = Client::new()
+            .post(maintenance_url.clone())
+            .form(params)
+            .send()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(serde_json::from_str::<serde_json::Value>(&res.text()?)?)
+    };
+
+    // A transaction that wrote before maintenance mode was enabled may still
+    // commit.
+    let mut txn_client = server.connect(postgres::NoTls)?;
+    txn_client.batch_execute("BEGIN; INSERT INTO t VALUES (2)")?;
+    let state = set_maintenance(&[("enabled", "true"), ("message", "resizing disk")])?;
+    assert_eq!(state["enabled"], true);
+    assert_eq!(state["message"], "resizing disk");
+    txn_client.batch_execute("COMMIT")?;
+
+    // Statements that modify the catalog or write data are rejected, but
+    // reads continue.
+    for (stmt, op) in &[
+        ("INSERT INTO t VALUES (3)", "INSERT"),
+        ("CREATE VIEW v AS SELECT 1", "CREATE VIEW"),
+        ("DROP TABLE t", "DROP TABLE"),
+    ] {
+        let err = client.batch_execute(stmt).unwrap_db_error();
+        assert_eq!(err.code(), &SqlState::READ_ONLY_SQL_TRANSACTION);
+        assert_eq!(
+            err.message(),
+            format!("cannot execute {} in maintenance mode: resizing disk", op)
+        );
+    }
+    let count: i64 = client.query_one("SELECT count(*) FROM t", &[])?.get(0);
+    assert_eq!(count, 2);
+
+    let status_url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value =
+        serde_json::from_str(&Client::new().get(status_url).send()?.text()?)?;
+    assert_eq!(status["maintenance_mode"], true);
+    assert_eq!(status["maintenance_message"], "resizing disk");
+
+    // Sticky maintenance mode survives a restart.
+    drop(client);
+    drop(txn_client);
+    drop(server);
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+    let err = client
+        .batch_execute("INSERT INTO t VALUES (3)")
+        .unwrap_db_error();
+    assert_eq!(err.code(), &SqlState::READ_ONLY_SQL_TRANSACTION);
+    let maintenance_url = Url::parse(&format!(
+        "http://{}/api/admin/maintenance",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new()
+        .post(maintenance_url)
+        .form(&[("enabled", "false")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    client.batch_execute("INSERT INTO t VALUES (3)")?;
+    assert!(!data_dir.path().join("maintenance_mode").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_clock_regression() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // The coordinator reads a mock clock that starts at the wall clock and
+    // trails it by the skew.
+    static SKEW_MS: AtomicU64 = AtomicU64::new(0);
+    fn mock_now() -> u64 {
+        ore::now::system_time() - SKEW_MS.load(Ordering::SeqCst)
+    }
+
+    let server = util::start_server(util::Config::default().clock(materialized::ClockConfig {
+        tolerance: Duration::from_secs(1),
+        fail_readiness: true,
+        now: mock_now,
+    }))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1)")?;
+    let status_url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let metric = |name: &str| {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].clone())
+            .unwrap()
+    };
+
+    // Step the clock back an hour. Timestamps hold, so a write is still
+    // visible to a subsequent read, but readiness fails.
+    SKEW_MS.store(3_600_000, Ordering::SeqCst);
+    client.batch_execute("INSERT INTO t VALUES (2)")?;
+    let count: i64 = client.query_one("SELECT count(*) FROM t", &[])?.get(0);
+    assert_eq!(count, 2);
+    let res = Client::new().get(status_url.clone()).send()?;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let status: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(status["status"], "clock_regressed");
+    assert_eq!(status["clock_regressed"], true);
+    assert_eq!(
+        metric("mz_clock_regressions_total")
+            .get_counter()
+            .get_value(),
+        1.0
+    );
+    assert!(metric("mz_clock_skew_milliseconds").get_gauge().get_value() > 3_000_000.0);
+
+    // Once the clock catches up, the server is ready again.
+    SKEW_MS.store(0, Ordering::SeqCst);
+    let count: i64 = client.query_one("SELECT count(*) FROM t", &[])?.get(0);
+    assert_eq!(count, 2);
+    let res = Client::new().get(status_url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        metric("mz_clock_skew_milliseconds").get_gauge().get_value(),
+        0.0
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_api_versioning() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let base = format!("http://{}", server.inner.local_addr());
+    let client = Client::new();
+
+    // The index lists every route, and the versions the server serves.
+    let res = client.get(&format!("{}/api", base)).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-materialize-api-version"], "1");
+    let index: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    let route = |methods: &[&str], path: &str| serde_json::json!({"methods": methods, "path": path, "version": 1});
+    assert_eq!(
+        index,
+        serde_json::json!({
+            "version": 1,
+            "supported_versions": [1],
+            "routes": [
+                route(&["GET"], "/api"),
+                route(&["GET"], "/api/build-info"),
+                route(&["GET"], "/api/status"),
+                route(&["GET"], "/api/memory"),
+                route(&["POST"], "/api/memory/purge"),
+                route(&["GET"], "/api/sessions"),
+                route(&["POST"], "/api/sessions/{conn_id}/cancel"),
+                route(&["GET"], "/api/users/stats"),
+                route(&["GET"], "/api/sources"),
+                route(&["GET"], "/api/sinks"),
+                route(&["GET", "POST"], "/api/introspection"),
+                route(&["GET", "POST"], "/api/telemetry"),
+                route(&["GET", "PUT"], "/api/log-filter"),
+                route(&["POST"], "/api/log-file/reopen"),
+                route(&["GET"], "/api/logs"),
+                route(&["GET", "PUT"], "/api/slow-query-threshold"),
+                route(&["GET"], "/api/catalog/dump"),
+                route(&["GET"], "/api/catalog/backup"),
+                route(&["POST"], "/api/catalog/reencrypt"),
+                route(&["POST"], "/api/admin/catalog/compact"),
+                route(&["GET"], "/api/admin/diagnostics"),
+                route(&["GET", "POST"], "/api/admin/maintenance"),
+                route(&["GET", "POST"], "/api/admin/session-quotas"),
+                route(&["GET"], "/api/admin/telemetry/spool"),
+                route(&["POST"], "/api/admin/telemetry/spool/drain"),
+                route(&["POST"], "/api/admin/trim-memory"),
+            ],
+        })
+    );
+
+    // The exact JSON of each version of a route is pinned, whether the
+    // version is implied or requested by query parameter or media type.
+    let pinned = &[
+        (
+            "/api/admin/session-quotas",
+            r#"{"max_concurrent_statements":100,"max_prepared_statements":10000,"max_open_portals":1000}"#,
+        ),
+        (
+            "/api/admin/maintenance",
+            r#"{"enabled":false,"message":null}"#,
+        ),
+    ];
+    for (path, body) in pinned {
+        let url = format!("{}{}", base, path);
+        for req in vec![
+            client.get(&url),
+            client.get(&format!("{}?api-version=1", url)),
+            client
+                .get(&url)
+                .header("accept", "application/vnd.materialize.v1+json"),
+        ] {
+            let res = req.send()?;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["x-materialize-api-version"], "1");
+            assert_eq!(&res.text()?, body);
+        }
+    }
+
+    // Versions that are not served are refused.
+    let res = client
+        .get(&format!("{}/api/admin/maintenance?api-version=2", base))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(
+        res.text()?,
+        "API version 2 is not supported; supported versions: 1"
+    );
+    let res = client
+        .get(&format!("{}/api/status", base))
+        .header("accept", "application/vnd.materialize.v0+json")
+        .send()?;
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    let res = client
+        .get(&format!("{}/api/status?api-version=latest", base))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(res.text()?, "invalid `api-version` parameter: latest");
+
+    // Other endpoints are not versioned.
+    let res = client
+        .get(&format!("{}/metrics?api-version=2", base))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-materialize-api-version").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_session_quotas() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().session_quotas(materialized::SessionQuotas {
+        max_concurrent_statements: 1,
+        max_prepared_statements: 2,
+        max_open_portals: 3,
+    });
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    let show = |client: &mut postgres::Client, var: &str| -> Result<String, Box<dyn Error>> {
+        Ok(client.query_one(&*format!("SHOW {}", var), &[])?.get(0))
+    };
+    assert_eq!(show(&mut client, "max_concurrent_statements")?, "1");
+    assert_eq!(show(&mut client, "max_prepared_statements")?, "2");
+    assert_eq!(show(&mut client, "max_open_portals")?, "3");
+    let err = client
+        .batch_execute("SET max_open_portals = 10")
+        .unwrap_db_error();
+    assert_eq!(err.code(), &SqlState::CANT_CHANGE_RUNTIME_PARAM);
+
+    // Named prepared statements count against their quota; the unnamed
+    // statement used by the simple query protocol does not.
+    let query = "VALUES (1), (2), (3)";
+    let stmt1 = client.prepare(query)?;
+    let _stmt2 = client.prepare(query)?;
+    let err = client.prepare(query).unwrap_db_error();
+    assert_eq!(err.code(), &SqlState::CONFIGURATION_LIMIT_EXCEEDED);
+    assert_eq!(
+        err.message(),
+        "session exceeds the limit of 2 prepared statements set by parameter \"max_prepared_statements\""
+    );
+    client.batch_execute("SELECT 1")?;
+    drop(stmt1);
+    let stmt = client.prepare(query)?;
+
+    // Binding portals counts against the portal quota.
+    {
+        let mut txn = client.transaction()?;
+        for _ in 0..3 {
+            txn.bind(&stmt, &[])?;
+        }
+        let err = txn.bind(&stmt, &[]).unwrap_db_error();
+        assert_eq!(err.code(), &SqlState::CONFIGURATION_LIMIT_EXCEEDED);
+        assert_eq!(
+            err.message(),
+            "session exceeds the limit of 3 open portals set by parameter \"max_open_portals\""
+        );
+    }
+
+    // A portal suspended partway through its results is still executing, so
+    // it prevents another statement from starting.
+    {
+        let mut txn = client.transaction()?;
+        let portal1 = txn.bind(&stmt, &[])?;
+        assert_eq!(txn.query_portal(&portal1, 1)?.len(), 1);
+        let portal2 = txn.bind(&stmt, &[])?;
+        let err = txn.query_portal(&portal2, 1).unwrap_db_error();
+        assert_eq!(err.code(), &SqlState::CONFIGURATION_LIMIT_EXCEEDED);
+        assert_eq!(
+            err.message(),
+            "session exceeds the limit of 1 concurrent statements set by parameter \"max_concurrent_statements\""
+        );
+    }
+
+    // The quotas can be adjusted at runtime, and established sessions are
+    // held to the new quotas from their next message.
+    let quotas_url = Url::parse(&format!(
+        "http://{}/api/admin/session-quotas",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new()
+        .post(quotas_url.clone())
+        .form(&[("max_concurrent_statements", "0")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let quotas: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(quotas["max_concurrent_statements"], 0);
+    assert_eq!(quotas["max_open_portals"], 3);
+    assert_eq!(show(&mut client, "max_concurrent_statements")?, "0");
+    {
+        let mut txn = client.transaction()?;
+        let portal1 = txn.bind(&stmt, &[])?;
+        assert_eq!(txn.query_portal(&portal1, 1)?.len(), 1);
+        let portal2 = txn.bind(&stmt, &[])?;
+        assert_eq!(txn.query_portal(&portal2, 1)?.len(), 1);
+        txn.commit()?;
+    }
+
+    let res = Client::new()
+        .post(quotas_url)
+        .form(&[("max_open_portals", "many")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[test]
+fn test_build_info() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default().safe_mode())?;
+    let url = Url::parse(&format!(
+        "http://{}/api/build-info",
+        server.inner.local_addr()
+    ))?;
+
+    let res = Client::new().get(url.clone()).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers()["etag"].to_str()?.to_owned();
+    assert!(etag.contains(materialized::BUILD_INFO.sha), "{}", etag);
+    let info: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(info["build"]["version"], materialized::BUILD_INFO.version);
+    assert_eq!(info["build"]["sha"], materialized::BUILD_INFO.sha);
+    assert!(info["build"]["rustc_version"]
+        .as_str()
+        .unwrap()
+        .starts_with("rustc "));
+    assert!(info["build"]["license_change_date"].is_string());
+    assert_eq!(info["runtime"]["safe_mode"], true);
+    assert_eq!(info["runtime"]["experimental_mode"], false);
+    assert_eq!(info["runtime"]["read_only"], false);
+    assert!(info["runtime"]["allocator"].is_string());
+
+    // A poller that presents the entity tag is told that nothing changed.
+    let res = Client::new()
+        .get(url)
+        .header("if-none-match", &etag)
+        .send()?;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.text()?, "");
+
+    Ok(())
+}
+
+#[test]
+fn test_scratch_directory() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let scratch_dir = tempfile::tempdir()?;
+
+    // Leave behind some stale files, as if from a previous run.
+    std::fs::write(scratch_dir.path().join("stale"), "stale")?;
+    std::fs::create_dir(scratch_dir.path().join("stale-dir"))?;
+    std::fs::write(scratch_dir.path().join("stale-dir").join("stale"), "stale")?;
+
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .scratch_directory(scratch_dir.path()),
+    )?;
+    assert_eq!(std::fs::read_dir(scratch_dir.path())?.count(), 0);
+    assert!(!data_dir.path().join("scratch").exists());
+
+    // Disk usage is reported separately for each directory.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let usage = loop {
+        let usage: HashMap<_, _> = server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_disk_usage_bytes")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter_map(|metric| {
+                let purpose = metric
+                    .get_label()
+                    .iter()
+                    .find(|l| l.get_name() == "purpose")?
+                    .get_value()
+                    .to_string();
+                Some((purpose, metric.get_gauge().get_value()))
+            })
+            .collect();
+        if usage.len() == 2 || Instant::now() > deadline {
+            break usage;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(usage["data"] > 0.0, "data directory usage: {:?}", usage);
+    assert!(usage.contains_key("scratch"), "usage: {:?}", usage);
+    drop(server);
+
+    // By default, the scratch directory lives within the data directory.
+    let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+    assert!(data_dir.path().join("scratch").is_dir());
+    drop(server);
+
+    // A scratch directory that would clean away the data directory is
+    // rejected.
+    match util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .scratch_directory(data_dir.path()),
+    ) {
+        Ok(_) => panic!("server unexpectedly accepted scratch directory"),
+        Err(e) => assert!(
+            e.to_string()
+                .contains("must not contain the data directory"),
+            "unexpected error: {}",
+            e
+        ),
+    }
+    assert!(data_dir.path().join("catalog").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_orphan_cleanup() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let quarantine_dir = data_dir.path().join("quarantine");
+    let source_path = data_dir.path().join("source.csv");
+    std::fs::write(&source_path, "a\n")?;
+
+    let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+    server.connect(postgres::NoTls)?.batch_execute(&format!(
+        "CREATE SOURCE src FROM FILE '{}' FORMAT TEXT",
+        source_path.display()
+    ))?;
+    drop(server);
+
+    // Leave behind some files that belong to nothing in the catalog.
+    std::fs::write(data_dir.path().join("dropped-sink.ocf"), "orphan")?;
+    std::fs::create_dir(data_dir.path().join("old"))?;
+    std::fs::write(data_dir.path().join("old").join("file"), "orphan")?;
+    std::fs::write(data_dir.path().join(".tmp1234"), "orphan")?;
+
+    let wait_for = |description: &str, f: &dyn Fn() -> bool| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !f() {
+            if Instant::now() > deadline {
+                panic!("timed out waiting for {}", description);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    };
+    let quarantined = || match std::fs::read_dir(&quarantine_dir) {
+        Ok(entries) => entries.count(),
+        Err(_) => 0,
+    };
+
+    // Without cleanup, orphans are reported but not touched.
+    let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+    wait_for("orphans to be reported", &|| {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "mz_data_directory_orphans")
+            .map(|family| family.get_metric()[0].get_gauge().get_value())
+            == Some(3.0)
+    });
+    assert!(data_dir.path().join("dropped-sink.ocf").exists());
+    assert!(!quarantine_dir.exists());
+    drop(server);
+
+    // With cleanup, orphans are moved to quarantine.
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .cleanup_orphans(Duration::from_secs(3600)),
+    )?;
+    wait_for("orphans to be quarantined", &|| quarantined() == 3);
+    for name in &["dropped-sink.ocf", "old", ".tmp1234"] {
+        assert!(!data_dir.path().join(name).exists(), "{} exists", name);
+    }
+    for name in &["catalog", "scratch", "source.csv"] {
+        assert!(data_dir.path().join(name).exists(), "{} missing", name);
+    }
+    drop(server);
+
+    // Once the retention period expires, quarantined orphans are deleted.
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .cleanup_orphans(Duration::from_secs(0)),
+    )?;
+    wait_for("orphans to be deleted", &|| quarantined() == 0);
+    assert!(source_path.exists());
+    drop(server);
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_encryption() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let key_dir = tempfile::tempdir()?;
+    let key_path = key_dir.path().join("key");
+    let other_key_path = key_dir.path().join("other-key");
+    std::fs::write(&key_path, "11".repeat(32))?;
+    std::fs::write(&other_key_path, "22".repeat(32))?;
+    let catalog_contains = |data_dir: &std::path::Path, needle: &[u8]| -> bool {
+        let catalog = std::fs::read(data_dir.join("catalog")).unwrap();
+        catalog.windows(needle.len()).any(|w| w == needle)
+    };
+    let start_err = |config: util::Config| match util::start_server(config) {
+        Ok(_) => panic!("server unexpectedly started"),
+        Err(e) => e.to_string(),
+    };
+
+    // A new catalog is encrypted with the configured key.
+    let config = util::Config::default()
+        .data_directory(data_dir.path())
+        .data_encryption_key_file(&key_path);
+    {
+        let server = util::start_server(config.clone())?;
+        server
+            .connect(postgres::NoTls)?
+            .batch_execute("CREATE VIEW v AS SELECT 'hunter2' AS secret")?;
+    }
+    assert!(!catalog_contains(data_dir.path(), b"hunter2"));
+
+    // The catalog cannot be opened without the key or with the wrong key.
+    let err = start_err(util::Config::default().data_directory(data_dir.path()));
+    assert!(err.contains("no encryption key was provided"), "{}", err);
+    let err = start_err(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .data_encryption_key_file(&other_key_path),
+    );
+    assert!(
+        err.contains("not encrypted with the provided encryption key"),
+        "{}",
+        err
+    );
+
+    // The check can only inspect definitions with the key.
+    let key_source = materialized::EncryptionConfig {
+        key_source: materialized::KeySource::File(key_path.clone()),
+    };
+    let report = materialized::check(data_dir.path(), Some(&key_source))?;
+    assert!(report.findings.is_empty(), "{}", report);
+    let report = materialized::check(data_dir.path(), None)?;
+    assert!(report.is_ok());
+    assert_eq!(report.findings[0].check, "encryption");
+
+    // The key is rotated by changing the key that the key source provides and
+    // then reencrypting the catalog.
+    {
+        let server = util::start_server(config.clone())?;
+        std::fs::copy(&other_key_path, &key_path)?;
+        let url = Url::parse(&format!(
+            "http://{}/api/catalog/reencrypt",
+            server.inner.local_addr()
+        ))?;
+        let res = Client::new().post(url).send()?;
+        assert_eq!(res.status(), StatusCode::OK, "{}", res.text()?);
+    }
+    let err = start_err(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .data_encryption_key_file(key_dir.path().join("missing")),
+    );
+    assert!(err.contains("reading data encryption key file"), "{}", err);
+    {
+        let server = util::start_server(
+            util::Config::default()
+                .data_directory(data_dir.path())
+                .data_encryption_key_file(&other_key_path),
+        )?;
+        let row = server
+            .connect(postgres::NoTls)?
+            .query_one("SELECT * FROM v", &[])?;
+        assert_eq!(row.get::<_, String>(0), "hunter2");
+    }
+
+    // An existing unencrypted catalog must be explicitly encrypted.
+    let data_dir = tempfile::tempdir()?;
+    {
+        let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+        server
+            .connect(postgres::NoTls)?
+            .batch_execute("CREATE VIEW v AS SELECT 'hunter2' AS secret")?;
+    }
+    let config = util::Config::default()
+        .data_directory(data_dir.path())
+        .data_encryption_key_file(&key_path);
+    let err = start_err(config.clone());
+    assert!(err.contains("--encrypt-catalog"), "{}", err);
+    materialized::encrypt_catalog(data_dir.path(), &key_source)?;
+    assert!(!catalog_contains(data_dir.path(), b"hunter2"));
+    // Encrypting the catalog again with the same key has no effect.
+    materialized::encrypt_catalog(data_dir.path(), &key_source)?;
+    let server = util::start_server(config)?;
+    let row = server
+        .connect(postgres::NoTls)?
+        .query_one("SELECT * FROM v", &[])?;
+    assert_eq!(row.get::<_, String>(0), "hunter2");
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_compaction() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let compactions = |server: &util::Server, status: &str| {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "mz_catalog_compactions_total")
+            .and_then(|family| {
+                family
+                    .get_metric()
+                    .iter()
+                    .find(|m| m.get_label().iter().any(|l| l.get_value() == status))
+                    .map(|m| m.get_counter().get_value())
+            })
+            .unwrap_or(0.0)
+    };
+
+    // Dropping objects leaves free pages behind, which compaction reclaims.
+    {
+        let server = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+        let mut client = server.connect(postgres::NoTls)?;
+        for i in 0..100 {
+            client.batch_execute(&format!(
+                "CREATE VIEW v{} AS SELECT '{}' AS padding",
+                i,
+                "x".repeat(1024)
+            ))?;
+        }
+        for i in 1..100 {
+            client.batch_execute(&format!("DROP VIEW v{}", i))?;
+        }
+        let url = Url::parse(&format!(
+            "http://{}/api/admin/catalog/compact",
+            server.inner.local_addr()
+        ))?;
+        let res = Client::new().post(url).send()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&res.text()?)?;
+        let size_before = report["size_before_bytes"].as_u64().unwrap();
+        let size_after = report["size_after_bytes"].as_u64().unwrap();
+        assert!(
+            size_after < size_before,
+            "{} >= {}",
+            size_after,
+            size_before
+        );
+        assert_eq!(compactions(&server, "success"), 1.0);
+
+        // The catalog remains writable after compaction.
+        client.batch_execute("CREATE VIEW w AS SELECT 1")?;
+    }
+
+    // The compacted catalog is intact, and compaction is refused in
+    // read-only mode.
+    {
+        let server = util::start_server(
+            util::Config::default()
+                .data_directory(data_dir.path())
+                .read_only(),
+        )?;
+        let mut client = server.connect(postgres::NoTls)?;
+        assert_eq!(client.query("SELECT * FROM v0", &[])?.len(), 1);
+        assert_eq!(client.query("SELECT * FROM w", &[])?.len(), 1);
+        let url = Url::parse(&format!(
+            "http://{}/api/admin/catalog/compact",
+            server.inner.local_addr()
+        ))?;
+        let res = Client::new().post(url).send()?;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    // The catalog can also be compacted periodically.
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .catalog_compact_interval(Duration::from_secs(1)),
+    )?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while compactions(&server, "success") == 0.0 {
+        if Instant::now() > deadline {
+            panic!("timed out waiting for periodic compaction");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_catalog_migration() -> Result<(), Box<dyn Error>> {
+    let data_dir = tempfile::tempdir()?;
+    let catalog_path = data_dir.path().join("catalog");
+    let schema_version = || -> u32 {
+        rusqlite::Connection::open(&catalog_path)
+            .unwrap()
+            .query_row("PRAGMA user_version", rusqlite::params![], |row| row.get(0))
+            .unwrap()
+    };
+    let set_schema_version = |version: u32| {
+        rusqlite::Connection::open(&catalog_path)
+            .unwrap()
+            .execute_batch(&format!("PRAGMA user_version = {}", version))
+            .unwrap();
+    };
+    let start_err = |config: util::Config| match util::start_server(config) {
+        Ok(_) => panic!("server unexpectedly started"),
+        Err(e) => e.to_string(),
+    };
+    let config = util::Config::default().data_directory(data_dir.path());
+
+    {
+        let server = util::start_server(config.clone())?;
+        server
+            .connect(postgres::NoTls)?
+            .batch_execute("CREATE TABLE t (a int)")?;
+    }
+
+    // Pretend that the catalog predates the most recent schema migration,
+    // which is safe to rerun.
+    let current = coord::catalog::storage::SCHEMA_VERSION;
+    let old = current - 1;
+    set_schema_version(old);
+
+    // The server refuses to migrate the catalog unless explicitly allowed,
+    // and reports which migrations would run.
+    let err = start_err(config.clone());
+    assert!(err.contains("must be migrated"), "{}", err);
+    assert!(
+        err.contains(&format!("schema migration {}:", current)),
+        "{}",
+        err
+    );
+    assert_eq!(schema_version(), old);
+
+    // A catalog written by a newer version of the server is always refused.
+    set_schema_version(current + 1);
+    let err = start_err(config.clone().allow_catalog_migration());
+    assert!(err.contains("downgrades are not supported"), "{}", err);
+    set_schema_version(old);
+
+    // When allowed, the catalog is migrated, and the catalog from before the
+    // migration is kept.
+    let backup_path = data_dir.path().join(format!(
+        "catalog-pre-migration-{}-{}",
+        old,
+        coord::catalog::CONTENT_VERSION
+    ));
+    {
+        let server = util::start_server(config.clone().allow_catalog_migration())?;
+        server
+            .connect(postgres::NoTls)?
+            .batch_execute("SELECT * FROM t")?;
+    }
+    assert_eq!(schema_version(), current);
+    assert!(backup_path.exists());
+    assert!(!data_dir
+        .path()
+        .join("catalog-migration-in-progress")
+        .exists());
+
+    // A migration that was interrupted is rolled back when the server next
+    // starts.
+    std::fs::write(
+        data_dir.path().join("catalog-migration-in-progress"),
+        backup_path.file_name().unwrap().to_str().unwrap(),
+    )?;
+    let err = start_err(config.clone());
+    assert!(err.contains("must be migrated"), "{}", err);
+    assert_eq!(schema_version(), old);
+    util::start_server(config.allow_catalog_migration())?;
+    assert_eq!(schema_version(), current);
+
+    Ok(())
+}
+
+#[test]
+fn test_metrics_registry_hygiene() -> Result<(), Box<dyn Error>> {
+    // Minor setup chores to ensure the server has done at least a little work:
+    let server = util::start_server(util::Config::default())?;
+    let source_file = NamedTempFile::new()?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute(&format!(
+        "CREATE SOURCE src FROM FILE '{}' FORMAT BYTES",
+        source_file.path().display()
+    ))?;
+    client.batch_execute(
+        "CREATE MATERIALIZED VIEW mat AS SELECT 'a', data, 'c' AS c, data FROM src",
+    )?;
+
+    // Check that metrics are where we expect them:
+    let default_metrics = prometheus::default_registry().gather();
+    assert_eq!(0, default_metrics.len());
+    assert_ne!(0, server.metrics_registry.gather().len());
+    Ok(())
+}
+
+#[test]
+fn test_instance_labels() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(
+        util::Config::default()
+            .instance_label("environment", "staging")
+            .instance_label("region", "us-east-1"),
+    )?;
+    let family = metadata_metric(&server)?;
+    let labels: HashMap<_, _> = family.get_metric()[0]
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+        .collect();
+    assert_eq!(labels["environment"], "staging");
+    assert_eq!(labels["region"], "us-east-1");
+    assert!(labels.contains_key("version"));
+
+    for name in &["mz_env", "__env", "version", "1env", "env-name"] {
+        match util::start_server(util::Config::default().instance_label(*name, "x")) {
+            Ok(_) => panic!("server unexpectedly accepted instance label {:?}", name),
+            Err(e) => assert!(
+                e.to_string().contains("invalid instance label name"),
+                "unexpected error: {}",
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_server_name() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().server_name("mz-test-1"))?;
+
+    // The name is reported in the startup parameter status messages and in
+    // the context of errors.
+    let runtime = Runtime::new()?;
+    let client = runtime.block_on(async {
+        let (client, conn) = server.pg_config_async().connect(postgres::NoTls).await?;
+        assert_eq!(conn.parameter("mz_server_name"), Some("mz-test-1"));
+        runtime.spawn(conn);
+        Ok::<_, Box<dyn Error>>(client)
+    })?;
+    let err = runtime
+        .block_on(client.batch_execute("SELECT * FROM nonexistent"))
+        .unwrap_db_error();
+    assert_eq!(err.where_(), Some("server mz-test-1"));
+
+    // It is a label on the server metadata metrics.
+    let family = metadata_metric(&server)?;
+    let labels: HashMap<_, _> = family.get_metric()[0]
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+        .collect();
+    assert_eq!(labels["server_name"], "mz-test-1");
+
+    // And it is reported over HTTP, both in the status and in a header on
+    // every response, including errors.
+    let status_url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value = Client::new().get(status_url).send()?.json()?;
+    assert_eq!(status["server_name"], "mz-test-1");
+    let missing_url = Url::parse(&format!("http://{}/nonexistent", server.inner.local_addr()))?;
+    let res = Client::new().get(missing_url).send()?;
+    assert!(!res.status().is_success());
+    assert_eq!(res.headers()["x-materialize-server-name"], "mz-test-1");
+
+    // Names that could smuggle content into logs or headers are rejected.
+    let long_name = "a".repeat(64);
+    for name in &[
+        "",
+        "mz test",
+        "mz\nfake log line",
+        "mz\u{1b}[31m",
+        &long_name,
+    ] {
+        match util::start_server(util::Config::default().server_name(*name)) {
+            Ok(_) => panic!("server unexpectedly accepted server name {:?}", name),
+            Err(e) => assert!(
+                e.to_string().contains("invalid server name"),
+                "unexpected error: {}",
+                e
+            ),
+        }
+    }
+
+    // The name is reserved as a metadata label.
+    assert!(
+        util::start_server(util::Config::default().instance_label("server_name", "x")).is_err()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_user_stats() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().user_stats_max_users(3))?;
+
+    // The statistics of a user accumulate across sessions.
+    for _ in 0..2 {
+        let mut client = server.pg_config().user("alice").connect(postgres::NoTls)?;
+        let rows = client.query("SELECT generate_series(1, 3)", &[])?;
+        assert_eq!(rows.len(), 3);
+    }
+
+    // Users first seen once the limit is reached are counted together.
+    for user in &["bob", "carol", "dave"] {
+        let mut client = server.pg_config().user(user).connect(postgres::NoTls)?;
+        client.batch_execute("SELECT 1")?;
+    }
+
+    let url = Url::parse(&format!(
+        "http://{}/api/users/stats",
+        server.inner.local_addr()
+    ))?;
+    let stats: serde_json::Value = Client::new().get(url).send()?.json()?;
+    assert!(stats["reset_at"].is_string());
+    assert_eq!(stats["max_users"], 3);
+    let users = stats["users"].as_array().unwrap();
+    assert_eq!(users.len(), 3);
+    assert!(users.iter().all(|u| u["user"] != "dave"));
+    let alice = users.iter().find(|u| u["user"] == "alice").unwrap();
+    assert!(alice["statements"].as_u64().unwrap() >= 2);
+    assert_eq!(alice["rows_returned"], 6);
+    assert!(alice["bytes_sent"].as_u64().unwrap() > 0);
+    assert!(alice["statement_seconds"].as_f64().unwrap() > 0.0);
+    assert!(stats["other"]["statements"].as_u64().unwrap() >= 1);
+    let seconds: Vec<_> = users
+        .iter()
+        .map(|u| u["statement_seconds"].as_f64().unwrap())
+        .collect();
+    assert!(seconds.windows(2).all(|w| w[0] >= w[1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_server_metrics() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(
+        util::Config::default()
+            .workers(2)
+            .instance_label("environment", "staging"),
+    )?;
+    // Wait for the metrics that are set once the system has been scanned.
+    metadata_metric(&server)?;
+
+    let families: HashMap<_, _> = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .map(|family| (family.get_name().to_string(), family))
+        .collect();
+    let labels = |metric: &prometheus::proto::Metric| -> Vec<(String, String)> {
+        metric
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+            .collect()
+    };
+    let environment = ("environment".to_string(), "staging".to_string());
+
+    // Values are reported as values, not as labels.
+    let workers = &families["mz_server_timely_worker_threads"].get_metric();
+    assert_eq!(workers.len(), 1);
+    assert_eq!(labels(&workers[0]), vec![environment.clone()]);
+    assert_eq!(workers[0].get_gauge().get_value(), 2.0);
+
+    let cpus = &families["mz_server_cpus"].get_metric();
+    assert_eq!(cpus.len(), 2);
+    for metric in cpus.iter() {
+        assert!(metric.get_gauge().get_value() >= 1.0);
+    }
+
+    let memory = &families["mz_server_memory_total_bytes"].get_metric();
+    assert_eq!(memory.len(), 1);
+    assert!(memory[0].get_gauge().get_value() > 0.0);
+
+    // Without a memory watchdog, the limit is the cgroup's limit or else the
+    // memory on the system, either of which is known.
+    let memory_limit = &families["mz_server_memory_limit_bytes"].get_metric();
+    assert_eq!(memory_limit.len(), 1);
+    assert!(memory_limit[0].get_gauge().get_value() > 0.0);
+
+    // The deprecated series is still reported for one release.
+    let deprecated = &families["mz_server_metadata_timely_worker_threads"].get_metric();
+    assert_eq!(deprecated.len(), 1);
+    assert_eq!(
+        labels(&deprecated[0]),
+        vec![("count".to_string(), "2".to_string()), environment]
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(target_os = "macos"))]
+fn test_allocator_tuning() -> Result<(), Box<dyn Error>> {
+    let server = util::start_server(util::Config::default().allocator(
+        materialized::AllocatorConfig {
+            background_thread: Some(true),
+            dirty_decay: Some(Duration::from_secs(1)),
+            muzzy_decay: Some(Duration::from_secs(0)),
+            narenas: None,
+        },
+    ))?;
+
+    // The applied settings are attached to the allocator's metrics.
+    let family = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_jemalloc_bytes")
+        .expect("allocator metric is registered");
+    let labels: HashMap<_, _> = family.get_metric()[0]
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+        .collect();
+    assert_eq!(labels["background_thread"], "true");
+    assert_eq!(labels["dirty_decay_ms"], "1000");
+    assert_eq!(labels["muzzy_decay_ms"], "0");
+    let narenas = labels["narenas"].parse::<u32>()?;
+
+    let url = Url::parse(&format!(
+        "http://{}/api/memory/purge",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new().post(url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The number of arenas cannot be changed once the process has started.
+    match util::start_server(
+        util::Config::default().allocator(materialized::AllocatorConfig {
+            narenas: Some(narenas + 1),
+            ..Default::default()
+        }),
+    ) {
+        Ok(_) => panic!("server unexpectedly changed the number of arenas"),
+        Err(e) => assert!(
+            e.to_string().contains("MALLOC_CONF"),
+            "unexpected error: {}",
+            e
+        ),
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(target_os = "macos"))]
+fn test_memory_trim() -> Result<(), Box<dyn Error>> {
+    let server =
+        util::start_server(util::Config::default().memory_trim_interval(Duration::from_secs(1)))?;
+    let purges = || -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "mz_jemalloc_purges_total")
+            .map(|family| family.get_metric()[0].get_counter().get_value())
+            .unwrap_or(0.0)
+    };
+    let wait_for_purges = |n: f64| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while purges() < n && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(purges(), n);
+    };
+
+    // The server trims memory once it has been idle for the interval, and
+    // then not again until a statement has executed.
+    wait_for_purges(1.0);
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(purges(), 1.0);
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("SELECT 1")?;
+    wait_for_purges(2.0);
+
+    // Memory can also be trimmed on request, which reports the resident
+    // memory before and after.
+    let url = Url::parse(&format!(
+        "http://{}/api/admin/trim-memory",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new().post(url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert!(report["resident_before_bytes"].as_u64().unwrap() > 0);
+    assert!(report["resident_after_bytes"].is_u64());
+    assert!(report["released_bytes"].is_u64());
+    assert_eq!(purges(), 3.0);
+
+    Ok(())
+}
+
+/// Waits for the server metadata metric, which is registered once the server
+/// has collected information about the system in the background.
+fn metadata_metric(
+    server: &util::Server,
+) -> Result<prometheus::proto::MetricFamily, Box<dyn Error>> {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let family = server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "mz_server_metadata_seconds");
+        match family {
+            Some(family) => return Ok(family),
+            None if Instant::now() > deadline => {
+                return Err("metadata metric was never registered".into())
+            }
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+// Ensures that uptime is computed as metrics are gathered, rather than by a
+// background task, and so advances between every gather.
+#[test]
+fn test_uptime_metrics() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let uptime = |name: &str| -> f64 {
+        let family = server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .expect("uptime metric present");
+        let metric = &family.get_metric()[0];
+        match family.get_field_type() {
+            prometheus::proto::MetricType::COUNTER => metric.get_counter().get_value(),
+            _ => metric.get_gauge().get_value(),
+        }
+    };
+
+    metadata_metric(&server)?;
+    let counter = uptime("mz_server_uptime_seconds_total");
+    let gauge = uptime("mz_server_metadata_seconds");
+    thread::sleep(Duration::from_millis(100));
+    assert!(uptime("mz_server_uptime_seconds_total") >= counter + 0.1);
+    assert!(uptime("mz_server_metadata_seconds") >= gauge + 0.1);
+
+    Ok(())
+}
+
+#[test]
+fn test_logical_compaction_window_reporting() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let window_metric = |server: &util::Server| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "mz_server_logical_compaction_window_ms")
+            .expect("compaction window metric present")
+            .get_metric()[0]
+            .get_gauge()
+            .get_value()
+    };
+    let api_status = |server: &util::Server| -> Result<serde_json::Value, Box<dyn Error>> {
+        let url = format!("http://{}/api/status", server.inner.local_addr());
+        Ok(serde_json::from_str(
+            &Client::new().get(&url).send()?.text()?,
+        )?)
+    };
+
+    let config = util::Config::default().logical_compaction_window(Duration::from_secs(60));
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+    let window: String = client
+        .query_one("SHOW logical_compaction_window", &[])?
+        .get(0);
+    assert_eq!(window, "60s");
+    assert_eq!(window_metric(&server), 60000.0);
+    assert_eq!(api_status(&server)?["logical_compaction_window_ms"], 60000);
+    let err = client
+        .batch_execute("SET logical_compaction_window = '1s'")
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::CANT_CHANGE_RUNTIME_PARAM);
+
+    // Disabled compaction is reported explicitly, rather than as a window of
+    // zero.
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    let window: String = client
+        .query_one("SHOW logical_compaction_window", &[])?
+        .get(0);
+    assert_eq!(window, "off");
+    assert_eq!(window_metric(&server), -1.0);
+    assert!(api_status(&server)?["logical_compaction_window_ms"].is_null());
+
+    Ok(())
+}
+
+#[test]
+fn test_host_metrics() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let gauge = |server: &util::Server, name: &str| -> Option<f64> {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].get_gauge().get_value())
+    };
+
+    // The host metrics are refreshed along with introspection.
+    let server = util::start_server(util::Config::default().host_metrics())?;
+    assert!(gauge(&server, "mz_host_load1").is_some());
+    if cfg!(target_os = "linux") {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while gauge(&server, "mz_host_memory_available_bytes") == Some(0.0) {
+            assert!(Instant::now() < deadline, "host metrics never refreshed");
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(gauge(&server, "mz_host_memory_free_bytes").unwrap() > 0.0);
+    }
+
+    // Embedders can opt out of them.
+    let server = util::start_server(util::Config::default())?;
+    assert_eq!(gauge(&server, "mz_host_memory_available_bytes"), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_introspection_metrics() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let metrics = |server: &util::Server, name: &str| -> Vec<prometheus::proto::Metric> {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric().to_vec())
+            .collect()
+    };
+    let label = |metric: &prometheus::proto::Metric, name: &str| -> String {
+        metric
+            .get_label()
+            .iter()
+            .find(|l| l.get_name() == name)
+            .map(|l| l.get_value().to_string())
+            .unwrap_or_default()
+    };
+
+    let server = util::start_server(util::Config::default().introspection_metrics())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute(
+        "CREATE TABLE t (a int);
+         INSERT INTO t VALUES (1);
+         CREATE MATERIALIZED VIEW v AS SELECT a, count(*) FROM t GROUP BY a;
+         SELECT * FROM v;",
+    )?;
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while metrics(&server, "mz_worker_operator_elapsed_seconds").is_empty()
+        || metrics(&server, "mz_worker_peek_duration_seconds_bucket").is_empty()
+    {
+        assert!(
+            Instant::now() < deadline,
+            "introspection metrics never published"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Operator classes beyond the top few are summed into "other", so the
+    // number of label values is bounded per worker.
+    for name in &[
+        "mz_worker_operator_elapsed_seconds",
+        "mz_worker_arrangement_records",
+    ] {
+        let mut by_worker: HashMap<String, usize> = HashMap::new();
+        for metric in metrics(&server, name) {
+            *by_worker.entry(label(&metric, "worker")).or_default() += 1;
+        }
+        for count in by_worker.values() {
+            assert!(*count <= 11, "{} has {} operator classes", name, count);
+        }
+    }
+    assert!(metrics(&server, "mz_worker_peek_duration_seconds_bucket")
+        .iter()
+        .any(|metric| label(metric, "le") == "+Inf"));
+    assert_eq!(
+        metrics(&server, "mz_introspection_metrics_scrape_duration_seconds").len(),
+        1
+    );
+
+    // Without introspection, the metrics are absent.
+    let server = util::start_server(
+        util::Config::default()
+            .introspection_metrics()
+            .logging_granularity(None),
+    )?;
+    thread::sleep(Duration::from_secs(2));
+    assert!(metrics(&server, "mz_worker_operator_elapsed_seconds").is_empty());
+    assert!(metrics(&server, "mz_introspection_metrics_scrape_duration_seconds").is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_and_statement_durations() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let sample_count = |server: &util::Server, name: &str, label: (&str, &str)| -> u64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == label.0 && l.get_value() == label.1)
+            })
+            .map(|metric| metric.get_histogram().get_sample_count())
+            .sum()
+    };
+    let statements = |server: &util::Server, class| {
+        sample_count(server, "mz_statement_duration_seconds", ("class", class))
+    };
+    let connections = |server: &util::Server, protocol| {
+        sample_count(
+            server,
+            "mz_connection_duration_seconds",
+            ("protocol", protocol),
+        )
+    };
+
+    let server = util::start_server(util::Config::default())?;
+
+    // Statements are observed by coarse class, via both pgwire and HTTP.
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int)")?;
+    client.execute("INSERT INTO t VALUES ($1)", &[&1])?;
+    client.query("SELECT * FROM t", &[])?;
+    client.batch_execute("SET application_name = 'test'")?;
+    assert_eq!(statements(&server, "ddl"), 1);
+    assert_eq!(statements(&server, "insert"), 1);
+    assert_eq!(statements(&server, "select"), 1);
+    assert_eq!(statements(&server, "other"), 1);
+
+    let url = format!("http://{}/sql", server.inner.local_addr());
+    let res = Client::new()
+        .post(&url)
+        .form(&[("sql", "SELECT 1; SELECT 2")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(statements(&server, "select"), 3);
+
+    // Connections are observed when they close.
+    assert_eq!(connections(&server, "pgwire"), 0);
+    drop(client);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while connections(&server, "pgwire") == 0 {
+        assert!(
+            Instant::now() < deadline,
+            "pgwire connection never observed"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(connections(&server, "pgwire"), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_source_timestamp_lag() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let source_lag = |server: &util::Server| -> Option<f64> {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_source_timestamp_lag_seconds")
+            .flat_map(|family| family.get_metric().to_vec())
+            .find(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "source" && l.get_value() == "materialize.public.src")
+            })
+            .map(|metric| metric.get_gauge().get_value())
+    };
+    let wait_for = |description: &str, f: &dyn Fn() -> bool| {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !f() {
+            if Instant::now() > deadline {
+                panic!("timed out waiting for {}", description);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    };
+
+    let data_dir = tempfile::tempdir()?;
+    let source_path = data_dir.path().join("source.csv");
+    std::fs::write(&source_path, "a\n")?;
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute(&format!(
+        "CREATE SOURCE src FROM FILE '{}' WITH (tail = true) FORMAT TEXT",
+        source_path.display()
+    ))?;
+
+    // A healthy source trails the wall clock by about the timestamp
+    // frequency, which the lag reflects once the source closes a timestamp.
+    wait_for("source lag to be reported", &|| {
+        source_lag(&server).is_some()
+    });
+    assert!(source_lag(&server).unwrap() < 30.0);
+
+    let url = format!("http://{}/api/status", server.inner.local_addr());
+    let status: serde_json::Value = serde_json::from_str(&Client::new().get(&url).send()?.text()?)?;
+    let lags = status["source_timestamp_lags"].as_array().unwrap();
+    assert_eq!(lags.len(), 1);
+    assert_eq!(lags[0]["name"], "materialize.public.src");
+    assert!(lags[0]["lag_ms"].is_u64());
+    assert!(status["max_source_timestamp_lag_ms"].is_u64());
+
+    // Dropped sources no longer appear in the metric.
+    client.batch_execute("DROP SOURCE src")?;
+    wait_for("source lag to be removed", &|| {
+        source_lag(&server).is_none()
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_source_health() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let data_dir = tempfile::tempdir()?;
+    let source_path = data_dir.path().join("source.csv");
+    std::fs::write(&source_path, "a\n")?;
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute(&format!(
+        "CREATE SOURCE src FROM FILE '{}' WITH (tail = true) FORMAT TEXT;
+         CREATE SINK snk FROM src INTO AVRO OCF '{}'",
+        source_path.display(),
+        data_dir.path().join("sink.ocf").display(),
+    ))?;
+
+    let fetch = |path: &str| -> Result<serde_json::Value, Box<dyn Error>> {
+        let url = format!("http://{}{}", server.inner.local_addr(), path);
+        Ok(serde_json::from_str(
+            &Client::new().get(&url).send()?.text()?,
+        )?)
+    };
+
+    // The source runs once it closes its first timestamp.
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let source = loop {
+        let sources = fetch("/api/sources")?;
+        let sources = sources.as_array().unwrap();
+        assert_eq!(sources.len(), 1);
+        if sources[0]["status"] == "running" {
+            break sources[0].clone();
+        }
+        assert_eq!(sources[0]["status"], "starting");
+        if Instant::now() > deadline {
+            panic!("timed out waiting for source to run");
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(source["name"], "materialize.public.src");
+    assert_eq!(source["type"], "file");
+    assert!(source["id"].as_str().unwrap().starts_with('u'));
+    assert!(source["upper"].is_u64());
+    assert!(source["last_progress_ms"].is_u64());
+    assert!(source["lag_ms"].is_u64());
+
+    let sinks = fetch("/api/sinks")?;
+    let sinks = sinks.as_array().unwrap();
+    assert_eq!(sinks.len(), 1);
+    assert_eq!(sinks[0]["name"], "materialize.public.snk");
+    assert_eq!(sinks[0]["type"], "avro-ocf");
+    assert_eq!(sinks[0]["status"], "running");
+
+    // Dropped objects are no longer reported.
+    client.batch_execute("DROP SINK snk; DROP SOURCE src")?;
+    assert_eq!(fetch("/api/sources")?, serde_json::json!([]));
+    assert_eq!(fetch("/api/sinks")?, serde_json::json!([]));
+
+    Ok(())
+}
+
+// Test that the HTTP endpoints built on the introspection sources say when
+// introspection is disabled, and report what they can without it.
+#[test]
+fn test_http_without_introspection() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let fetch = |server: &util::Server, path: &str| -> Result<serde_json::Value, Box<dyn Error>> {
+        let url = format!("http://{}{}", server.inner.local_addr(), path);
+        Ok(serde_json::from_str(
+            &Client::new().get(&url).send()?.text()?,
+        )?)
+    };
+
+    let server = util::start_server(util::Config::default().logging_granularity(None))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute(
+        "CREATE TABLE t (a int);
+         CREATE MATERIALIZED VIEW v AS SELECT count(*) FROM t",
+    )?;
+
+    let memory = fetch(&server, "/api/memory")?;
+    assert_eq!(memory["introspection_disabled"], true);
+    assert_eq!(memory["catalog"]["tables"], 1);
+    assert_eq!(memory["catalog"]["views"], 1);
+    assert_eq!(memory["catalog"]["indexes"], 2);
+    assert!(memory["catalog"]["arrangements"].as_u64().unwrap() >= 2);
+    assert!(memory["connections"]["open"].as_u64().unwrap() >= 1);
+    assert!(
+        memory["connections"]["by_protocol"]["pgwire"]
+            .as_u64()
+            .unwrap()
+            >= 1
+    );
+
+    let status = fetch(&server, "/api/status")?;
+    assert_eq!(status["introspection_disabled"], true);
+    assert_eq!(status["catalog"]["views"], 1);
+
+    // Dropping an index stops the dataflow layer from maintaining its
+    // arrangement.
+    let arrangements = memory["catalog"]["arrangements"].as_u64().unwrap();
+    client.batch_execute("DROP VIEW v")?;
+    let memory = fetch(&server, "/api/memory")?;
+    assert_eq!(memory["catalog"]["indexes"], 1);
+    assert_eq!(
+        memory["catalog"]["arrangements"].as_u64().unwrap(),
+        arrangements - 1
+    );
+
+    // With introspection enabled, the endpoints say so.
+    let server = util::start_server(util::Config::default())?;
+    assert_eq!(
+        fetch(&server, "/api/memory")?["introspection_disabled"],
+        false
+    );
+    assert_eq!(
+        fetch(&server, "/api/status")?["introspection_disabled"],
+        false
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_protocol_policy() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let rejected = |server: &util::Server, protocol: &str| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_connections_rejected_protocol_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "protocol" && l.get_value() == protocol)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    };
+    let status_url =
+        |server: &util::Server| format!("http://{}/api/status", server.inner.local_addr());
+
+    // A pgwire-only server closes HTTP connections without responding.
+    let config = util::Config::default().protocol_policy(materialized::ProtocolPolicy::PgwireOnly);
+    let server = util::start_server(config)?;
+    assert!(Client::new().get(&status_url(&server)).send().is_err());
+    assert_eq!(rejected(&server, "http"), 1.0);
+    server.connect(postgres::NoTls)?.batch_execute("SELECT 1")?;
+    assert_eq!(rejected(&server, "pgwire"), 0.0);
+
+    // An HTTP-only server closes pgwire connections.
+    let config = util::Config::default().protocol_policy(materialized::ProtocolPolicy::HttpOnly);
+    let server = util::start_server(config)?;
+    assert!(server.connect(postgres::NoTls).is_err());
+    assert_eq!(rejected(&server, "pgwire"), 1.0);
+    let res = Client::new().get(&status_url(&server)).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(rejected(&server, "http"), 0.0);
+
+    // So is any other protocol, without the usual "unknown protocol" reply.
+    // The client sends only as many bytes as the server sniffs, so that the
+    // server closes the connection cleanly.
+    let mut conn = std::net::TcpStream::connect(server.inner.local_addr())?;
+    conn.write_all(b"SSH-2.0-")?;
+    let mut response = vec![];
+    conn.read_to_end(&mut response)?;
+    assert!(response.is_empty());
+    assert_eq!(rejected(&server, "unknown"), 1.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_rate_limit_exempts_loopback() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().connection_rate_limit(materialized::RateLimitConfig {
+        per_address_rate: 0.001,
+        burst: 1,
+        global_rate: Some(1.0),
+        action: materialized::RateLimitAction::Drop,
+        max_addresses: materialized::DEFAULT_RATE_LIMIT_ADDRESSES,
+    });
+    let server = util::start_server(config)?;
+
+    // Connections from loopback addresses are never limited, however many
+    // are made.
+    for _ in 0..10 {
+        server.connect(postgres::NoTls)?.batch_execute("SELECT 1")?;
+    }
+    let limited: f64 = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "mz_server_connections_rate_limited_total")
+        .flat_map(|family| family.get_metric().to_vec())
+        .map(|metric| metric.get_counter().get_value())
+        .sum();
+    assert_eq!(limited, 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_protocol_detection() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().protocol_detect_timeout(Duration::from_secs(1));
+    let server = util::start_server(config)?;
+    let failures = |reason: &str| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_protocol_detect_failures_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "reason" && l.get_value() == reason)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    };
+
+    // A client that is slow to send its startup message, but not too slow,
+    // is served, as the bytes sniffed while waiting are replayed to pgwire.
+    let mut conn = std::net::TcpStream::connect(server.inner.local_addr())?;
+    let params = b"user\0materialize\0\0";
+    let mut startup = vec![];
+    startup.extend(&(8 + params.len() as u32).to_be_bytes());
+    startup.extend(&196608_u32.to_be_bytes());
+    startup.extend(params);
+    conn.write_all(&startup[..3])?;
+    thread::sleep(Duration::from_millis(300));
+    conn.write_all(&startup[3..])?;
+    let mut message_type = [0; 1];
+    loop {
+        let mut len = [0; 4];
+        conn.read_exact(&mut message_type)?;
+        conn.read_exact(&mut len)?;
+        let mut body = vec![0; u32::from_be_bytes(len) as usize - 4];
+        conn.read_exact(&mut body)?;
+        if message_type[0] == b'Z' {
+            break;
+        }
+    }
+    drop(conn);
+
+    // A client that stalls partway through is disconnected.
+    let start = Instant::now();
+    let mut conn = std::net::TcpStream::connect(server.inner.local_addr())?;
+    conn.write_all(&startup[..3])?;
+    let mut response = vec![];
+    conn.read_to_end(&mut response)?;
+    assert!(response.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(failures("timeout"), 1.0);
+
+    // So is a client whose first bytes match no protocol.
+    let mut conn = std::net::TcpStream::connect(server.inner.local_addr())?;
+    conn.write_all(b"SSH-2.0-")?;
+    let mut response = vec![];
+    conn.read_to_end(&mut response)?;
+    assert_eq!(response, b"unknown protocol\n");
+    assert_eq!(failures("unrecognized"), 1.0);
+
+    Ok(())
+}
+
+// Ensures that starting the server does not wait on slow work, like scanning
+// the system, that can happen in the background.
+#[test]
+fn test_serve_returns_promptly() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let start = Instant::now();
+    let server = util::start_server(util::Config::default())?;
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < Duration::from_secs(20),
+        "server took {:?} to start",
+        elapsed
+    );
+
+    // The information that was collected in the background eventually
+    // appears in the metadata metric.
+    let family = metadata_metric(&server)?;
+    let labels: HashMap<_, _> = family.get_metric()[0]
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+        .collect();
+    assert!(labels.contains_key("cpu0"));
+    assert!(labels.contains_key("memory_total"));
+
+    Ok(())
+}
+
+#[test]
+fn test_startup_status() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+
+    // Once `serve` returns, the starting responder has been replaced, and the
+    // status endpoint reports how long startup took.
+    let url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let res = Client::new().get(url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let status: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(status["status"], "ready");
+    assert_eq!(status["phase"], "ready");
+    assert!(status["startup_ms"].is_u64());
+
+    let mut client = server.connect(postgres::NoTls)?;
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_host_environment() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let expected_allocator = if cfg!(target_os = "macos") {
+        "system"
+    } else {
+        "jemalloc"
+    };
+
+    let url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value = serde_json::from_str(&Client::new().get(url).send()?.text()?)?;
+    let environment = &status["host_environment"];
+    assert_eq!(environment["allocator"], expected_allocator);
+    for field in &[
+        "nofile_soft_limit",
+        "nofile_hard_limit",
+        "address_space_soft_limit",
+        "address_space_hard_limit",
+        "transparent_hugepages",
+        "data_directory_filesystem",
+        "clocksource",
+    ] {
+        assert!(environment[field].is_string(), "missing {}", field);
+    }
+
+    let family = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_host_environment")
+        .expect("host environment metric present");
+    let metric = &family.get_metric()[0];
+    assert_eq!(metric.get_gauge().get_value(), 1.0);
+    let labels: HashMap<_, _> = metric
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+        .collect();
+    assert_eq!(labels["allocator"], expected_allocator);
+    assert_eq!(
+        labels["nofile_soft_limit"],
+        environment["nofile_soft_limit"].as_str().unwrap()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_diagnostics_bundle() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    fn fetch(server: &util::Server) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        let url = Url::parse(&format!(
+            "http://{}/api/admin/diagnostics",
+            server.inner.local_addr()
+        ))?;
+        let res = Client::new().get(url).send()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut entries = HashMap::new();
+        for entry in tar::Archive::new(res).entries()? {
+            let mut entry = entry?;
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents)?;
+            entries.insert(entry.path()?.display().to_string(), contents);
+        }
+        Ok(entries)
+    }
+
+    let server = util::start_server(util::Config::default())?;
+    let entries = fetch(&server)?;
+    for name in &[
+        "manifest.json",
+        "config.txt",
+        "status.json",
+        "metrics.txt",
+        "sessions.json",
+        "dataflows.json",
+        "memory.json",
+        "logs.jsonl",
+    ] {
+        assert!(entries.contains_key(*name), "missing {}", name);
+    }
+    let manifest: serde_json::Value = serde_json::from_slice(&entries["manifest.json"])?;
+    assert_eq!(manifest["version"], materialized::BUILD_INFO.version);
+    assert_eq!(manifest["exclude_sql"], false);
+    let listed: Vec<_> = manifest["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["name"].as_str().unwrap().to_owned())
+        .collect();
+    assert!(listed.contains(&"status.json".to_owned()));
+    let status: serde_json::Value = serde_json::from_slice(&entries["status.json"])?;
+    assert_eq!(status["status"], "ready");
+    assert!(
+        String::from_utf8(entries["metrics.txt"].clone())?.contains("mz_server_metadata_seconds")
+    );
+
+    // Entries that may contain SQL text can be excluded entirely.
+    let server = util::start_server(util::Config::default().diagnostics_exclude_sql(true))?;
+    let entries = fetch(&server)?;
+    assert!(!entries.contains_key("logs.jsonl"));
+    assert!(!entries.keys().any(|name| name.starts_with("crash/")));
+    let manifest: serde_json::Value = serde_json::from_slice(&entries["manifest.json"])?;
+    assert_eq!(manifest["exclude_sql"], true);
+    let missing: Vec<_> = manifest["missing"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["name"].as_str().unwrap().to_owned())
+        .collect();
+    assert!(missing.contains(&"logs.jsonl".to_owned()));
+
+    // The bundle is available only to users who may perform administrative
+    // actions.
+    let policy: materialized::PolicyAuthorizer = r#"
+        default = ["read"]
+    "#
+    .parse()?;
+    let server = util::start_server(util::Config::default().authz(Arc::new(policy)))?;
+    let url = Url::parse(&format!(
+        "http://{}/api/admin/diagnostics",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new().get(url).send()?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_watchdog_refuses_connections() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // The server always uses more than 1MiB, so memory usage is critical from
+    // the watchdog's first sample.
+    let server = util::start_server(util::Config::default().memory_watchdog(
+        materialized::MemoryWatchdogConfig {
+            limit: Some(materialized::ByteSize(1 << 20)),
+            refuse_connections: true,
+            ..Default::default()
+        },
+    ))?;
+
+    // The first sample happens shortly after the server starts.
+    let start = Instant::now();
+    let err = loop {
+        match server.connect(postgres::NoTls) {
+            Ok(_) if start.elapsed() < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            Ok(_) => panic!("memory watchdog never refused a connection"),
+            Err(e) => break e.unwrap_db_error(),
+        }
+    };
+    assert_eq!(*err.code(), SqlState::OUT_OF_MEMORY);
+
+    let actions = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_memory_watchdog_actions_total")
+        .expect("memory watchdog actions metric present");
+    let refusals: f64 = actions
+        .get_metric()
+        .iter()
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "action" && l.get_value() == "refuse_connections")
+        })
+        .map(|metric| metric.get_counter().get_value())
+        .sum();
+    assert_eq!(refusals, 1.0);
+
+    // The configured limit is reported as the server's memory limit.
+    let memory_limit = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_server_memory_limit_bytes")
+        .expect("memory limit metric present");
+    assert_eq!(
+        memory_limit.get_metric()[0].get_gauge().get_value(),
+        (1 << 20) as f64
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_dataflow_memory_budget() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // Waits for the server to estimate that its dataflows occupy some memory,
+    // and returns the budget and the estimate that it reports.
+    fn await_estimate(server: &util::Server) -> Result<serde_json::Value, Box<dyn Error>> {
+        let url = Url::parse(&format!("http://{}/api/status", server.inner.local_addr()))?;
+        let start = Instant::now();
+        loop {
+            let status: serde_json::Value =
+                serde_json::from_str(&Client::new().get(url.clone()).send()?.text()?)?;
+            let dataflow_memory = status["dataflow_memory"].clone();
+            if dataflow_memory["estimated_bytes"].as_u64().unwrap_or(0) > 0 {
+                return Ok(dataflow_memory);
+            }
+            if start.elapsed() > Duration::from_secs(10) {
+                panic!("dataflow memory estimate never grew: {}", status);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    // Any record exhausts a budget of one byte.
+    let server = util::start_server(util::Config::default().dataflow_memory_budget(1, true))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1), (2), (3)")?;
+    let dataflow_memory = await_estimate(&server)?;
+    assert_eq!(dataflow_memory["budget_bytes"], 1);
+    assert_eq!(dataflow_memory["enforce"], true);
+
+    for (op, stmt) in &[
+        ("CREATE INDEX", "CREATE INDEX i ON t (a)"),
+        (
+            "CREATE MATERIALIZED VIEW",
+            "CREATE MATERIALIZED VIEW mv AS SELECT a + 1 FROM t",
+        ),
+    ] {
+        let err = client.batch_execute(stmt).unwrap_db_error();
+        assert_eq!(err.code(), &SqlState::CONFIGURATION_LIMIT_EXCEEDED);
+        assert!(
+            err.message()
+                .starts_with(&format!("cannot execute {}: dataflows are estimated", op)),
+            "unexpected message: {}",
+            err.message()
+        );
+    }
+    // Nothing was created, and views that are not materialized need no
+    // dataflow.
+    assert_eq!(
+        client
+            .query_one("SELECT count(*) FROM mz_indexes WHERE name = 'i'", &[])?
+            .get::<_, i64>(0),
+        0
+    );
+    client.batch_execute("CREATE VIEW v AS SELECT a + 1 FROM t")?;
+
+    // A budget that is not enforced admits the same dataflows.
+    let server = util::start_server(util::Config::default().dataflow_memory_budget(1, false))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1), (2), (3)")?;
+    let dataflow_memory = await_estimate(&server)?;
+    assert_eq!(dataflow_memory["enforce"], false);
+    client.batch_execute("CREATE INDEX i ON t (a)")?;
+    let warnings: f64 = server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == "mz_dataflow_memory_budget_exceeded_total")
+        .expect("dataflow memory budget metric present")
+        .get_metric()
+        .iter()
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "action" && l.get_value() == "warned")
+        })
+        .map(|metric| metric.get_counter().get_value())
+        .sum();
+    assert_eq!(warnings, 1.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_flight_recorder() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let data_dir = tempfile::tempdir()?;
+    let server = util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .flight_recorder(materialized::FlightRecorderConfig {
+                interval: Duration::from_secs(3600),
+                ..Default::default()
+            }),
+    )?;
+    let _client = server.connect(postgres::NoTls)?;
+
+    // The first snapshot is taken on the first sample, shortly after the
+    // server starts.
+    let dir = data_dir.path().join("diagnostics").join("flight-recorder");
+    let start = Instant::now();
+    let path = loop {
+        let snapshot = std::fs::read_dir(&dir).ok().and_then(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .find(|path| path.extension().map_or(false, |ext| ext == "json"))
+        });
+        match snapshot {
+            Some(path) => break path,
+            None if start.elapsed() < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            None => panic!("flight recorder never took a snapshot"),
+        }
+    };
+    let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+    assert!(name.starts_with("snapshot-"), "{}", name);
+    assert!(name.ends_with("-interval.json"), "{}", name);
+    let snapshot: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)?;
+    assert_eq!(snapshot["trigger"], "interval");
+    assert!(snapshot["sessions"].is_array());
+    assert!(snapshot["top_arrangements"].is_array());
+    assert!(snapshot["coord_queue_depth"]["normal"].is_u64());
+
+    // The snapshot is included in the diagnostics bundle.
+    let url = Url::parse(&format!(
+        "http://{}/api/admin/diagnostics",
+        server.inner.local_addr()
+    ))?;
+    let res = Client::new().get(url).send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let mut names = vec![];
+    for entry in tar::Archive::new(res).entries()? {
+        names.push(entry?.path()?.display().to_string());
+    }
+    assert!(
+        names.contains(&format!("flight-recorder/{}", name)),
+        "{:?}",
+        names
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_oom_score_adj() -> Result<(), Box<dyn Error>> {
+    // The server runs in this process, so the adjustment applies to the test
+    // process. Raising the adjustment requires no privileges.
+    let _server = util::start_server(util::Config::default().oom_score_adj(500))?;
+    let adj = std::fs::read_to_string("/proc/self/oom_score_adj")?;
+    assert_eq!(adj.trim(), "500");
+
+    // Adjustments outside the valid range are refused.
+    assert!(util::start_server(util::Config::default().oom_score_adj(1001)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_worker_stack_size() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().worker_stack_size(16 << 20))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE MATERIALIZED VIEW v AS SELECT 1 AS a")?;
+    assert_eq!(
+        client.query_one("SELECT a FROM v", &[])?.get::<_, i32>(0),
+        1
+    );
+
+    // Stacks smaller than the minimum are rejected before any worker starts.
+    match util::start_server(util::Config::default().worker_stack_size(64 << 10)) {
+        Ok(_) => panic!("server unexpectedly accepted a tiny worker stack"),
+        Err(e) => assert!(
+            e.to_string().contains("worker stack size"),
+            "unexpected error: {}",
+            e
+        ),
+    }
+
+    Ok(())
+}
+
+/// Returns the number of pgwire cancellation requests with the specified
+/// outcome.
+fn cancel_requests(server: &util::Server, outcome: &str) -> f64 {
+    server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "mz_pg_cancel_requests")
+        .flat_map(|family| family.get_metric().to_vec())
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "outcome" && l.get_value() == outcome)
+        })
+        .map(|metric| metric.get_counter().get_value())
+        .sum()
+}
+
+#[test]
+fn test_cancel_session_and_drain() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let mut server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    let base = format!("http://{}/api/sessions", server.inner.local_addr());
+
+    // Find the session of the pgwire connection. Each HTTP request has a
+    // session of its own, which belongs to the system user.
+    let sessions: serde_json::Value =
+        serde_json::from_str(&Client::new().get(&base).send()?.text()?)?;
+    let conn_id = sessions
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|session| session["user"] == "materialize")
+        .expect("pgwire session is listed")["conn_id"]
+        .as_u64()
+        .unwrap();
+
+    let cancel = |conn_id| {
+        Client::new()
+            .post(&format!("{}/{}/cancel", base, conn_id))
+            .send()
+    };
+    assert_eq!(cancel(conn_id)?.status(), StatusCode::OK);
+    assert_eq!(cancel(u64::from(u32::MAX))?.status(), StatusCode::NOT_FOUND);
+
+    // A draining server refuses new sessions but continues to serve existing
+    // sessions and to accept cancellation requests.
+    server.inner.drain("test");
+    match server.connect(postgres::NoTls) {
+        Ok(_) => panic!("draining server unexpectedly accepted a session"),
+        Err(e) => assert_eq!(*e.unwrap_db_error().code(), SqlState::ADMIN_SHUTDOWN),
+    }
+    assert_eq!(
+        Client::new().get(&base).send()?.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+    assert_eq!(cancel(conn_id)?.status(), StatusCode::OK);
+    client.cancel_token().cancel_query(postgres::NoTls)?;
+    assert_eq!(cancel_requests(&server, "matched"), 1.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_drain_metrics() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let mut server = util::start_server(util::Config::default())?;
+    let metrics_registry = server.metrics_registry.clone();
+    let gauge = |name: &str, protocol: Option<&str>| -> f64 {
+        metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| match protocol {
+                None => true,
+                Some(protocol) => metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "protocol" && l.get_value() == protocol),
+            })
+            .map(|metric| metric.get_gauge().get_value())
+            .sum()
+    };
+    let wait_for = |cond: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !cond() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+
+    // A serving server reports that it is not draining.
+    let client = server.connect(postgres::NoTls)?;
+    assert_eq!(gauge("mz_server_draining", None), 0.0);
+    assert_eq!(
+        gauge("mz_server_drain_started_timestamp_seconds", None),
+        0.0
+    );
+
+    // Once draining, the server reports when the drain began and the
+    // connections that remain.
+    let before = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+    server.inner.drain("test");
+    wait_for(&|| gauge("mz_server_draining", None) == 1.0);
+    assert!(gauge("mz_server_drain_started_timestamp_seconds", None) >= before.floor());
+    assert_eq!(gauge("mz_server_draining_connections", Some("pgwire")), 1.0);
+    assert_eq!(gauge("mz_server_draining_connections", Some("http")), 0.0);
+
+    // Connections that race the drain are refused with an explanation, and
+    // counted.
+    let rejected = |protocol: &str| -> f64 {
+        metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_connections_rejected_draining_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "protocol" && l.get_value() == protocol)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    };
+    assert_eq!(rejected("pgwire"), 0.0);
+    assert_eq!(rejected("http"), 0.0);
+    let err = server
+        .pg_config()
+        .connect(postgres::NoTls)
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::ADMIN_SHUTDOWN);
+    let res = Client::new()
+        .get(&format!("http://{}/", server.inner.local_addr()))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key("retry-after"));
+    assert_eq!(rejected("pgwire"), 1.0);
+    assert_eq!(rejected("http"), 1.0);
+
+    // The count falls as connections close.
+    drop(client);
+    wait_for(&|| gauge("mz_server_draining_connections", Some("pgwire")) == 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_self_test() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().self_test())?;
+    let report = server.inner.self_test_report().expect("self test ran");
+    assert!(report.passed, "self test failed: {:?}", report);
+    let steps: Vec<_> = report.steps.iter().map(|step| step.name).collect();
+    assert_eq!(
+        steps,
+        &[
+            "connect",
+            "create_table",
+            "create_view",
+            "insert",
+            "query",
+            "scrape_metrics"
+        ]
+    );
+
+    // The self test leaves no objects behind.
+    let mut client = server.connect(postgres::NoTls)?;
+    let count: i64 = client
+        .query_one(
+            "SELECT count(*) FROM mz_catalog.mz_objects WHERE name LIKE 'mz_self_test%'",
+            &[],
+        )?
+        .get(0);
+    assert_eq!(count, 0);
+
+    // The self test cannot run in read-only mode, where it could not create
+    // its objects.
+    match util::start_server(util::Config::default().read_only().self_test()) {
+        Ok(_) => panic!("self test unexpectedly ran in read-only mode"),
+        Err(e) => assert_eq!(e.to_string(), "cannot run the self test in read-only mode"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_coord_probes() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().coord_liveness(
+        materialized::LivenessConfig {
+            probe_interval: Duration::from_millis(250),
+            ..Default::default()
+        },
+    ))?;
+
+    // A responsive coordinator answers every probe.
+    let probes = |outcome: &str| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_coord_probes_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "outcome" && l.get_value() == outcome)
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    };
+    let start = Instant::now();
+    while probes("ok") < 2.0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "coordinator never answered a probe"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(probes("timeout"), 0.0);
+
+    let res = reqwest::blocking::get(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let status: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(status["status"], "ready");
+
+    // Invalid probe configurations are refused.
+    let res = util::start_server(util::Config::default().coord_liveness(
+        materialized::LivenessConfig {
+            max_failures: 0,
+            ..Default::default()
+        },
+    ));
+    match res {
+        Ok(_) => panic!("server unexpectedly started with invalid probe configuration"),
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "coordinator probe failures must be at least 1"
+        ),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_idle_exit() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let mut server =
+        util::start_server(util::Config::default().idle_exit_after(Duration::from_secs(1)))?;
+    let runtime = server.runtime.clone();
+
+    // An open connection keeps the server running.
+    let mut client = server.connect(postgres::NoTls)?;
+    let res = runtime.block_on(tokio::time::timeout(
+        Duration::from_secs(2),
+        server.inner.idle_exit(),
+    ));
+    assert!(res.is_err(), "server exited despite an open connection");
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    // Once the last connection closes, the server drains and reports why.
+    drop(client);
+    let reason = runtime.block_on(tokio::time::timeout(
+        Duration::from_secs(10),
+        server.inner.idle_exit(),
+    ))?;
+    assert_eq!(reason, "idle exit: no client connections for 1s");
+
+    // Connections that arrive once the server is draining are cleanly
+    // refused.
+    match server.connect(postgres::NoTls) {
+        Ok(_) => panic!("idle server unexpectedly accepted a session"),
+        Err(e) => assert_eq!(*e.unwrap_db_error().code(), SqlState::ADMIN_SHUTDOWN),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_listener_handoff() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let data_dir = tempfile::tempdir()?;
+    let mut old = util::start_server(util::Config::default().data_directory(data_dir.path()))?;
+    let runtime = old.runtime.clone();
+    let addr = old.inner.local_addr();
+    let mut client = old.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE VIEW v AS SELECT 1 AS a")?;
+
+    // A server cannot take over a listener on an address other than its
+    // predecessor's.
+    let mut wrong_addr = addr;
+    wrong_addr.set_port(addr.port().wrapping_add(1));
+    match util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .takeover_from(wrong_addr),
+    ) {
+        Ok(_) => panic!("server unexpectedly took over a listener on {}", wrong_addr),
+        Err(e) => assert!(e.to_string().contains("refused handoff"), "{}", e),
+    }
+
+    // A server whose configuration is invalid fails before it takes over the
+    // listener, which the old server continues to serve.
+    match util::start_server(
+        util::Config::default()
+            .data_directory(data_dir.path())
+            .takeover_from(addr)
+            .max_result_rows(0),
+    ) {
+        Ok(_) => panic!("server with an invalid configuration unexpectedly started"),
+        Err(e) => assert!(
+            e.to_string()
+                .contains("maximum result rows must be positive"),
+            "{}",
+            e
+        ),
+    }
+    old.connect(postgres::NoTls)?;
+
+    // The new server does not finish starting until the old server exits.
+    let new = thread::spawn({
+        let data_dir = data_dir.path().to_owned();
+        move || {
+            util::start_server(
+                util::Config::default()
+                    .data_directory(data_dir)
+                    .takeover_from(addr),
+            )
+            .map_err(|e| e.to_string())
+        }
+    });
+    let reason = runtime.block_on(tokio::time::timeout(
+        Duration::from_secs(10),
+        old.inner.handed_off(),
+    ))?;
+    assert_eq!(reason, "listener handed off to a new server");
+
+    // Existing sessions continue on the old server, while new connections
+    // reach the new server, which is still starting up.
+    assert_eq!(
+        client.query_one("SELECT a FROM v", &[])?.get::<_, i32>(0),
+        1
+    );
+    match old.connect(postgres::NoTls) {
+        Ok(_) => panic!("new server unexpectedly accepted a session while starting"),
+        Err(e) => assert_eq!(*e.unwrap_db_error().code(), SqlState::CANNOT_CONNECT_NOW),
+    }
+
+    drop(client);
+    runtime.block_on(tokio::time::timeout(
+        Duration::from_secs(10),
+        old.inner.connections_closed(),
+    ))?;
+    drop(old);
+    let new = new.join().unwrap()?;
+    assert_eq!(new.inner.local_addr(), addr);
+    let mut client = new.connect(postgres::NoTls)?;
+    assert_eq!(
+        client.query_one("SELECT a FROM v", &[])?.get::<_, i32>(0),
+        1
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_startup_timeout() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // A generous timeout does not interfere with startup.
+    let server =
+        util::start_server(util::Config::default().startup_timeout(Duration::from_secs(60)))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    // A server that cannot start in time reports the phase it was stuck in.
+    match util::start_server(util::Config::default().startup_timeout(Duration::from_nanos(1))) {
+        Ok(_) => panic!("server unexpectedly started within 1ns"),
+        Err(e) => {
+            let e = e.to_string();
+            assert!(
+                e.starts_with("server did not start within 1ns: phase "),
+                "{}",
+                e
+            );
+            assert!(e.contains("still in progress after"), "{}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_version_check() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // A fake release server that answers one request and reports what it
+    // was asked.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/latest", listener.local_addr()?);
+    let release_server = thread::spawn(move || -> Result<String, std::io::Error> {
+        let (mut stream, _) = listener.accept()?;
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        let body = r#"{"latest_release": "999.0.0"}"#;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        Ok(String::from_utf8_lossy(&request).into_owned())
+    });
+
+    let server = util::start_server(util::Config::default().version_check(
+        materialized::VersionCheckConfig {
+            url,
+            interval: Duration::from_secs(3600),
+        },
+    ))?;
+
+    // The request identifies the server by its version alone.
+    let request = release_server.join().unwrap()?;
+    let mut lines = request.lines();
+    assert_eq!(
+        lines.next(),
+        Some(&*format!(
+            "GET /latest?version={} HTTP/1.1",
+            materialized::BUILD_INFO.version
+        ))
+    );
+    for header in lines.filter(|l| !l.is_empty()) {
+        let name = header.split(':').next().unwrap().to_lowercase();
+        assert!(
+            ["host", "accept", "accept-encoding"].contains(&name.as_str()),
+            "unexpected header in version check: {}",
+            header
+        );
+    }
+
+    // The newer release is reported by the metric and the status endpoint.
+    let upgrade_available = || -> Option<(String, f64)> {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_upgrade_available")
+            .flat_map(|family| family.get_metric().to_vec())
+            .map(|metric| {
+                (
+                    metric.get_label()[0].get_value().to_owned(),
+                    metric.get_gauge().get_value(),
+                )
+            })
+            .next()
+    };
+    let start = Instant::now();
+    while upgrade_available().is_none() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "version check never completed"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(upgrade_available(), Some(("999.0.0".into(), 1.0)));
+    let res = reqwest::blocking::get(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(status["latest_version"], "999.0.0");
+    assert_eq!(status["upgrade_available"], true);
+    drop(server);
+
+    // An unreachable release server is counted, but otherwise ignored.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let server = util::start_server(util::Config::default().version_check(
+        materialized::VersionCheckConfig {
+            url: format!("http://127.0.0.1:{}/latest", port),
+            interval: Duration::from_secs(3600),
+        },
+    ))?;
+    let failures = || -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_version_checks_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "outcome" && l.get_value() == "failure")
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    };
+    let start = Instant::now();
+    while failures() < 1.0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "failed version check was not counted"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    let res = reqwest::blocking::get(&format!("http://{}/api/status", server.inner.local_addr()))?;
+    let status: serde_json::Value = serde_json::from_str(&res.text()?)?;
+    assert_eq!(status["status"], "ready");
+    assert!(status["upgrade_available"].is_null());
+    let mut client = server.connect(postgres::NoTls)?;
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+
+    Ok(())
+}
+
+fn active_sessions(server: &util::Server, application_name: &str) -> f64 {
+    server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "mz_active_sessions")
+        .flat_map(|family| family.get_metric().to_vec())
+        .filter(|metric| metric.get_label()[0].get_value() == application_name)
+        .map(|metric| metric.get_gauge().get_value())
+        .sum()
+}
+
+#[test]
+fn test_session_application_name() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server
+        .pg_config()
+        .application_name("billing")
+        .options("-c statement_timeout=5s --team=payments")
+        .connect(postgres::NoTls)?;
+    let url = format!("http://{}/api/sessions", server.inner.local_addr());
+    let list_sessions = || -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+        let res = Client::new()
+            .get(&url)
+            .header("x-application-name", "dashboard")
+            .send()?
+            .text()?;
+        Ok(serde_json::from_str(&res)?)
+    };
+
+    // Options that name session variables set them.
+    let timeout: String = client.query_one("SHOW statement_timeout", &[])?.get(0);
+    assert_eq!(timeout, "5s");
+
+    let sessions = list_sessions()?;
+    let session = sessions
+        .iter()
+        .find(|session| session["user"] == "materialize")
+        .expect("pgwire session is listed");
+    assert_eq!(session["application_name"], "billing");
+    assert_eq!(
+        session["options"],
+        serde_json::json!({"statement_timeout": "5s", "team": "payments"})
+    );
+    // The HTTP request's own session is named by its header.
+    assert!(sessions
+        .iter()
+        .any(|session| session["application_name"] == "dashboard"));
+    assert_eq!(active_sessions(&server, "billing"), 1.0);
+
+    // Changing the application name changes what is reported.
+    client.batch_execute("SET application_name = 'reports'")?;
+    let sessions = list_sessions()?;
+    let session = sessions
+        .iter()
+        .find(|session| session["user"] == "materialize")
+        .expect("pgwire session is listed");
+    assert_eq!(session["application_name"], "reports");
+    assert_eq!(active_sessions(&server, "billing"), 0.0);
+    assert_eq!(active_sessions(&server, "reports"), 1.0);
+
+    drop(client);
+    // The coordinator learns of the disconnect asynchronously.
+    let start = Instant::now();
+    while active_sessions(&server, "reports") != 0.0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "session not ended"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_active_tails() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(
+        util::Config::default().long_running_tail_threshold(Duration::from_millis(100)),
+    )?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1)")?;
+    let url = format!("http://{}/api/sessions", server.inner.local_addr());
+    let active_tails = |protocol: &str| -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_active_tails")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| metric.get_label()[0].get_value() == protocol)
+            .map(|metric| metric.get_gauge().get_value())
+            .sum()
+    };
+    let wait_for = |what: &str, f: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(10), "{}", what);
+            thread::sleep(Duration::from_millis(100));
+        }
+    };
+    assert_eq!(active_tails("pgwire"), 0.0);
+    assert_eq!(active_tails("http"), 0.0);
+
+    let tail_thread = thread::spawn(move || -> Result<(), postgres::Error> {
+        let mut reader = client.copy_out("COPY (TAIL t) TO STDOUT")?;
+        let mut buf = vec![];
+        // The TAIL streams until it is canceled.
+        let _ = reader.read_to_end(&mut buf);
+        Ok(())
+    });
+    wait_for("tail not counted", &|| active_tails("pgwire") == 1.0);
+
+    // The session that is streaming is flagged in the session listing.
+    let sessions: Vec<serde_json::Value> =
+        serde_json::from_str(&Client::new().get(&url).send()?.text()?)?;
+    let session = sessions
+        .iter()
+        .find(|session| session["user"] == "materialize")
+        .expect("pgwire session is listed");
+    assert_eq!(session["tailing"], true);
+    assert!(sessions
+        .iter()
+        .filter(|session| session["user"] != "materialize")
+        .all(|session| session["tailing"] == false && session["tail_buffer"].is_null()));
+
+    // As is the occupancy of its buffer, which is limited by the server's
+    // defaults.
+    assert!(session["tail_buffer"]["buffered_bytes"].is_u64());
+    assert_eq!(session["tail_buffer"]["limit"], 64 << 20);
+    assert_eq!(session["tail_buffer"]["policy"], "block");
+
+    // Canceling the session ends the stream, which is no longer counted.
+    let conn_id = session["conn_id"].as_u64().unwrap();
+    let res = Client::new()
+        .post(&format!("{}/{}/cancel", url, conn_id))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    wait_for("tail not uncounted", &|| active_tails("pgwire") == 0.0);
+    tail_thread.join().unwrap()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_coord_command_lanes() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let commands = |lane: &str| -> u64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_coord_commands_total")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| metric.get_label()[0].get_value() == lane)
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    };
+
+    // SQL statements are served on the normal lane.
+    let (normal, priority) = (commands("normal"), commands("priority"));
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int)")?;
+    assert!(commands("normal") > normal);
+
+    // The admin endpoints, and the cancellation of queries, are served on the
+    // priority lane.
+    let url = format!("http://{}/api/sessions", server.inner.local_addr());
+    let sessions: Vec<serde_json::Value> =
+        serde_json::from_str(&Client::new().get(&url).send()?.text()?)?;
+    assert!(commands("priority") > priority);
+    let priority = commands("priority");
+    let session = sessions
+        .iter()
+        .find(|session| session["user"] == "materialize")
+        .expect("pgwire session is listed");
+    let conn_id = session["conn_id"].as_u64().unwrap();
+    let res = Client::new()
+        .post(&format!("{}/{}/cancel", url, conn_id))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    // At least the startup of the HTTP session and the cancellation itself.
+    assert!(commands("priority") >= priority + 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_progress() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default())?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1), (2)")?;
+    let url = format!("http://{}/api/sessions", server.inner.local_addr());
+    let copying_session = || -> Result<Option<serde_json::Value>, Box<dyn Error>> {
+        let sessions: Vec<serde_json::Value> =
+            serde_json::from_str(&Client::new().get(&url).send()?.text()?)?;
+        Ok(sessions
+            .into_iter()
+            .find(|session| !session["copy"].is_null()))
+    };
+
+    let copy_thread = thread::spawn(move || -> Result<(), postgres::Error> {
+        let mut reader = client.copy_out("COPY (TAIL t) TO STDOUT (FORMAT CSV)")?;
+        let mut buf = vec![];
+        // The copy streams until it is canceled.
+        let _ = reader.read_to_end(&mut buf);
+        Ok(())
+    });
+
+    // The copy's progress is reported in the session listing, once the
+    // initial snapshot of the table has been sent.
+    let start = Instant::now();
+    let session = loop {
+        match copying_session()? {
+            Some(session) if session["copy"]["rows"] == 2 => break session,
+            _ => {
+                assert!(start.elapsed() < Duration::from_secs(10), "copy not listed");
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    };
+    assert_eq!(session["copy"]["direction"], "to");
+    assert_eq!(session["copy"]["format"], "csv");
+    assert!(session["copy"]["bytes"].as_u64().unwrap() > 0);
+
+    // Canceling the session ends the copy promptly.
+    let conn_id = session["conn_id"].as_u64().unwrap();
+    let res = Client::new()
+        .post(&format!("{}/{}/cancel", url, conn_id))
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    copy_thread.join().unwrap()?;
+    let start = Instant::now();
+    while copying_session()?.is_some() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "copy still listed"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_authz_policy() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let policy: materialized::PolicyAuthorizer = r#"
+        default = ["read"]
+
+        [users]
+        materialize = ["read", "write"]
+    "#
+    .parse()?;
+    let server = util::start_server(util::Config::default().authz(Arc::new(policy)))?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    // Statements that affect only the session are not subject to the policy.
+    client.batch_execute("SET application_name = 'analyst'")?;
+    assert_eq!(client.query_one("SELECT 1", &[])?.get::<_, i32>(0), 1);
+    let err = client
+        .batch_execute("CREATE TABLE t (a int)")
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::INSUFFICIENT_PRIVILEGE);
+    assert_eq!(
+        err.message(),
+        "permission denied: user materialize may not execute ddl statements"
+    );
+
+    // Cursors are authorized according to the statement that they run, and
+    // prepared statements when they are executed.
+    client.batch_execute("BEGIN; DECLARE c CURSOR FOR SELECT 1; FETCH c; COMMIT")?;
+    let stmt = client.prepare("CREATE VIEW v AS SELECT 1")?;
+    let err = client.execute(&stmt, &[]).unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::INSUFFICIENT_PRIVILEGE);
+
+    // HTTP clients are subject to the same policy.
+    let url = format!("http://{}/sql", server.inner.local_addr());
+    let res = Client::new()
+        .post(&url)
+        .form(&[("sql", "SELECT 1")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = Client::new()
+        .post(&url)
+        .form(&[("sql", "CREATE VIEW v AS SELECT 1")])
+        .send()?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text()?,
+        "permission denied: user mz_system may not execute ddl statements"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_statement_labels() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let server = util::start_server(util::Config::default().statement_label_key("job"))?;
+    let mut client = server.connect(postgres::NoTls)?;
+    client.batch_execute("CREATE TABLE t (a int); INSERT INTO t VALUES (1)")?;
+    let url = format!("http://{}/api/sessions", server.inner.local_addr());
+    let durations = |job: &str| -> u64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_statement_duration_seconds")
+            .flat_map(|family| family.get_metric().to_vec())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "job" && label.get_value() == job)
+            })
+            .map(|metric| metric.get_histogram().get_sample_count())
+            .sum()
+    };
+
+    // Labels are attached to the statement duration metric, whether the
+    // statement arrives via a simple or an extended query, and malformed
+    // comments are ignored.
+    client.batch_execute("/* job=nightly-revenue, team=finance */ SELECT 1")?;
+    client.query("/* job=nightly-revenue */ SELECT $1::int", &[&1])?;
+    client.batch_execute("/* job=bad label */ SELECT 1")?;
+    assert_eq!(durations("nightly-revenue"), 2);
+    assert_eq!(durations("bad"), 0);
+
+    // The labels of an executing statement are reported in the session
+    // listing.
+    let tail_thread = thread::spawn(move || -> Result<(), postgres::Error> {
+        let mut reader = client.copy_out("/* job=stream */ COPY (TAIL t) TO STDOUT")?;
+        let mut buf = vec![];
+        // The TAIL streams until it is canceled.
+        let _ = reader.read_to_end(&mut buf);
+        Ok(())
+    });
+    let start = Instant::now();
+    let session = loop {
+        let sessions: Vec<serde_json::Value> =
+            serde_json::from_str(&Client::new().get(&url).send()?.text()?)?;
+        let session = sessions
+            .into_iter()
+            .find(|session| session["user"] == "materialize")
+            .expect("pgwire session is listed");
+        if session["labels"] == serde_json::json!({"job": "stream"}) {
+            break session;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "labels not listed"
+        );
+        thread::sleep(Duration::from_millis(100));
+    };
+    let conn_id = session["conn_id"].as_u64().unwrap();
+    Client::new()
+        .post(&format!("{}/{}/cancel", url, conn_id))
+        .send()?;
+    tail_thread.join().unwrap()?;
+
+    Ok(())
+}
+
+// Test that a source whose connections cannot be bound to the outbound bind
+// address fails to be created, rather than retrying forever.
+#[test]
+fn test_outbound_bind_addr_unassigned() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    // 192.0.2.1 is reserved for documentation, and so is not assigned to any
+    // interface of the test host.
+    let server = util::start_server(
+        util::Config::default().outbound_bind_addr(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+    )?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    let start = Instant::now();
+    let err = client
+        .batch_execute(
+            "CREATE MATERIALIZED SOURCE s FROM POSTGRES
+             CONNECTION 'host=127.0.0.1 port=5432 user=postgres'
+             PUBLICATION 'p'",
+        )
+        .unwrap_db_error();
+    assert_eq!(
+        err.message(),
+        "binding outbound connection to 192.0.2.1: address is not assigned to any interface"
+    );
+    assert!(start.elapsed() < Duration::from_secs(10));
 
-    // Check that metrics are where we expect them:
-    let default_metrics = prometheus::default_registry().gather();
-    assert_eq!(0, default_metrics.len());
-    assert_ne!(0, server.metrics_registry.gather().len());
     Ok(())
 }
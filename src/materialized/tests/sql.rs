@@ -28,6 +28,7 @@ use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
 use log::info;
+use postgres::error::SqlState;
 use postgres::Row;
 use tempfile::NamedTempFile;
 
@@ -225,6 +226,112 @@ fn test_tail_negative_diffs() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_tail_buffer() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config =
+        util::Config::default().tail_buffer(1 << 10, materialized::TailBufferPolicy::Block);
+    let server = util::start_server(config)?;
+    let mut client_writes = server.connect(postgres::NoTls)?;
+    let mut client_reads = server.connect(postgres::NoTls)?;
+    let buffered_bytes = || -> f64 {
+        server
+            .metrics_registry
+            .gather()
+            .into_iter()
+            .filter(|family| family.get_name() == "mz_server_tail_buffered_bytes")
+            .flat_map(|family| family.get_metric().to_vec())
+            .map(|metric| metric.get_gauge().get_value())
+            .sum()
+    };
+    let wait_for = |what: &str, f: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(10), "{}", what);
+            sleep(Duration::from_millis(100));
+        }
+    };
+
+    client_writes.batch_execute("CREATE TABLE t (data text)")?;
+    let insert_rows = |client: &mut postgres::Client| -> Result<(), postgres::Error> {
+        for i in 0..100 {
+            client.execute(
+                "INSERT INTO t VALUES ($1)",
+                &[&format!("{:03}{}", i, "x".repeat(100))],
+            )?;
+        }
+        Ok(())
+    };
+
+    // A reader that does not keep up holds the tail back at the server's
+    // limit, rather than letting undelivered updates accumulate.
+    client_reads.batch_execute(
+        "BEGIN;
+         DECLARE c CURSOR FOR TAIL t;
+         FETCH ALL c WITH (timeout = '0s');",
+    )?;
+    insert_rows(&mut client_writes)?;
+    wait_for("no updates buffered", &|| buffered_bytes() > 0.0);
+    sleep(Duration::from_millis(500));
+    let buffered = buffered_bytes();
+    assert!(buffered <= 8192.0, "buffered {} bytes", buffered);
+
+    // Every update is delivered, in order, once the reader catches up.
+    let mut received = vec![];
+    let start = Instant::now();
+    while received.len() < 100 {
+        assert!(start.elapsed() < Duration::from_secs(10), "updates lost");
+        for row in client_reads.query("FETCH ALL c WITH (timeout = '100ms')", &[])? {
+            received.push(row.get::<_, String>("data"));
+        }
+    }
+    assert_eq!(received.len(), 100);
+    for (i, data) in received.iter().enumerate() {
+        assert!(
+            data.starts_with(&format!("{:03}", i)),
+            "{} out of order",
+            data
+        );
+    }
+    client_reads.batch_execute("COMMIT")?;
+    wait_for("buffer not released", &|| buffered_bytes() == 0.0);
+
+    // With the error policy, a reader that falls too far behind receives the
+    // updates that were buffered, and then an error.
+    client_reads.batch_execute(
+        "BEGIN;
+         DECLARE c CURSOR FOR TAIL t WITH (snapshot = false, buffer_size = 1024, buffer_policy = 'error');
+         FETCH ALL c WITH (timeout = '0s');",
+    )?;
+    insert_rows(&mut client_writes)?;
+    let start = Instant::now();
+    let err = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "tail not terminated"
+        );
+        match client_reads.query("FETCH ALL c WITH (timeout = '100ms')", &[]) {
+            Ok(_) => (),
+            Err(err) => break err.unwrap_db_error(),
+        }
+    };
+    assert_eq!(*err.code(), SqlState::PROGRAM_LIMIT_EXCEEDED);
+    assert!(err.message().starts_with("tail buffer exceeded"));
+    client_reads.batch_execute("ROLLBACK")?;
+
+    // The policy must be one of the known policies.
+    let err = client_reads
+        .batch_execute("TAIL t WITH (buffer_policy = 'drop')")
+        .unwrap_db_error();
+    assert_eq!(
+        err.message(),
+        "invalid TAIL buffer policy: drop; expected 'block' or 'error'"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_tail_basic() -> Result<(), Box<dyn Error>> {
     ore::test::init_logging();
@@ -874,3 +981,148 @@ fn test_linearizable() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_statement_timeout() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().statement_timeout(Duration::from_secs(2));
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    client.batch_execute("CREATE TABLE t (i INT8)")?;
+
+    // A statement that runs for too long is canceled.
+    client.batch_execute("BEGIN; DECLARE c CURSOR FOR TAIL t")?;
+    let err = client.query("FETCH c", &[]).unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::QUERY_CANCELED);
+    assert_eq!(
+        err.message(),
+        "canceling statement due to statement timeout"
+    );
+    client.batch_execute("ROLLBACK")?;
+
+    // A statement that completes in time disarms its timer. Were the timer of
+    // the quick SELECT still armed, it would fire in the middle of the FETCH,
+    // and cancel it.
+    client.query("SELECT * FROM t", &[])?;
+    sleep(Duration::from_millis(1500));
+    client.batch_execute("BEGIN; DECLARE c CURSOR FOR TAIL t")?;
+    let rows = client.query("FETCH c WITH (TIMEOUT = '1s')", &[])?;
+    assert_eq!(rows.len(), 0);
+    client.batch_execute("ROLLBACK")?;
+
+    // Sessions may choose their own timeout.
+    client.batch_execute("SET statement_timeout = 0")?;
+    client.batch_execute("BEGIN; DECLARE c CURSOR FOR TAIL t")?;
+    let rows = client.query("FETCH c WITH (TIMEOUT = '3s')", &[])?;
+    assert_eq!(rows.len(), 0);
+    client.batch_execute("ROLLBACK")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_lock_timeouts() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default()
+        .statement_timeout(Duration::from_secs(30))
+        .lock_timeouts();
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    let err = client
+        .batch_execute("SET statement_timeout = 0")
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::CANT_CHANGE_RUNTIME_PARAM);
+    let err = client
+        .batch_execute("SET idle_in_transaction_session_timeout = '1h'")
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::CANT_CHANGE_RUNTIME_PARAM);
+
+    let timeout: String = client.query_one("SHOW statement_timeout", &[])?.get(0);
+    assert_eq!(timeout, "30s");
+
+    Ok(())
+}
+
+#[test]
+fn test_max_result_size() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config = util::Config::default().max_result_rows(10);
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    client.batch_execute("CREATE TABLE t (s TEXT)")?;
+    client.batch_execute("INSERT INTO t SELECT repeat('x', 100) FROM generate_series(1, 20)")?;
+
+    // A result with too many rows fails, without sending any rows.
+    let err = client.query("SELECT * FROM t", &[]).unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::PROGRAM_LIMIT_EXCEEDED);
+    assert_eq!(
+        err.message(),
+        "result exceeds the limit of 10 rows set by parameter \"max_result_rows\""
+    );
+    assert_eq!(client.query("SELECT * FROM t LIMIT 10", &[])?.len(), 10);
+
+    // Sessions may lower the limits, but not raise them.
+    client.batch_execute("SET max_result_rows = 5")?;
+    let err = client
+        .query("SELECT * FROM t LIMIT 10", &[])
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::PROGRAM_LIMIT_EXCEEDED);
+    for value in &["11", "0"] {
+        let err = client
+            .batch_execute(&format!("SET max_result_rows = {}", value))
+            .unwrap_db_error();
+        assert_eq!(*err.code(), SqlState::INVALID_PARAMETER_VALUE);
+    }
+    client.batch_execute("SET max_result_bytes = 1000")?;
+    let err = client
+        .query("SELECT * FROM t LIMIT 5", &[])
+        .unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::PROGRAM_LIMIT_EXCEEDED);
+    assert_eq!(
+        err.message(),
+        "result exceeds the limit of 1000 bytes set by parameter \"max_result_bytes\""
+    );
+    assert_eq!(client.query("SELECT * FROM t LIMIT 2", &[])?.len(), 2);
+
+    // The results of a TAIL are limited batch by batch.
+    client.batch_execute("BEGIN; DECLARE c CURSOR FOR TAIL t")?;
+    let err = client.query("FETCH ALL c", &[]).unwrap_db_error();
+    assert_eq!(*err.code(), SqlState::PROGRAM_LIMIT_EXCEEDED);
+    client.batch_execute("ROLLBACK")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_idle_in_transaction_session_timeout() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let config =
+        util::Config::default().idle_in_transaction_session_timeout(Duration::from_secs(1));
+    let server = util::start_server(config)?;
+    let mut client = server.connect(postgres::NoTls)?;
+
+    client.batch_execute("CREATE TABLE t (i INT8)")?;
+
+    // Idling outside of a transaction is fine.
+    sleep(Duration::from_secs(2));
+    client.batch_execute("BEGIN; INSERT INTO t VALUES (1)")?;
+
+    // Idling in a transaction aborts the transaction and terminates the
+    // connection.
+    sleep(Duration::from_secs(2));
+    assert!(client.batch_execute("COMMIT").is_err());
+    assert!(client.is_closed());
+
+    let mut client = server.connect(postgres::NoTls)?;
+    let count: i64 = client.query_one("SELECT count(*) FROM t", &[])?.get(0);
+    assert_eq!(count, 0);
+
+    Ok(())
+}
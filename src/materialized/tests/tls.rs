@@ -695,3 +695,172 @@ fn test_tls() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Returns the number of connections open over `transport`, as reported by the
+/// `mz_server_connections_active` metric.
+fn connections_active(server: &util::Server, transport: &str) -> f64 {
+    server
+        .metrics_registry
+        .gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "mz_server_connections_active")
+        .flat_map(|family| family.get_metric().to_vec())
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|label| label.get_name() == "transport" && label.get_value() == transport)
+        })
+        .map(|metric| metric.get_gauge().get_value())
+        .sum()
+}
+
+#[allow(clippy::unit_arg)]
+#[test]
+fn test_session_transport() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let runtime = Runtime::new()?;
+    let list_sessions =
+        |server: &util::Server, scheme: Scheme| -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+            let uri = Uri::builder()
+                .scheme(scheme)
+                .authority(&*format!(
+                    "{}:{}",
+                    Ipv4Addr::LOCALHOST,
+                    server.inner.local_addr().port()
+                ))
+                .path_and_query("/api/sessions")
+                .build()?;
+            let res = runtime.block_on(
+                hyper::Client::builder()
+                    .build::<_, Body>(make_http_tls(|b| Ok(b.set_verify(SslVerifyMode::NONE))))
+                    .get(uri),
+            )?;
+            let body = runtime.block_on(body::to_bytes(res.into_body()))?;
+            Ok(serde_json::from_slice(&body)?)
+        };
+    let find_session = |sessions: &[serde_json::Value], user: &str| {
+        sessions
+            .iter()
+            .find(|session| session["user"] == user)
+            .cloned()
+            .expect("session is listed")
+    };
+
+    // Sessions on a server without TLS are in plaintext.
+    let server = util::start_server(util::Config::default())?;
+    let _client = server.connect(postgres::NoTls)?;
+    let sessions = list_sessions(&server, Scheme::HTTP)?;
+    assert_eq!(
+        find_session(&sessions, "materialize")["transport"],
+        serde_json::json!({"kind": "plaintext"})
+    );
+    assert_eq!(
+        find_session(&sessions, "mz_system")["transport"],
+        serde_json::json!({"kind": "plaintext"})
+    );
+    assert!(connections_active(&server, "plaintext") >= 1.0);
+    assert_eq!(connections_active(&server, "tls"), 0.0);
+
+    // Sessions that negotiate TLS report the protocol version and cipher.
+    let ca = Ca::new()?;
+    let (server_cert, server_key) =
+        ca.request_cert("server", vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])?;
+    let config = util::Config::default().with_tls(TlsMode::Require, &server_cert, &server_key);
+    let server = util::start_server(config)?;
+    let _client = server
+        .pg_config()
+        .ssl_mode(SslMode::Require)
+        .connect(make_pg_tls(|b| Ok(b.set_verify(SslVerifyMode::NONE))))?;
+    let sessions = list_sessions(&server, Scheme::HTTPS)?;
+    for user in &["materialize", "mz_system"] {
+        let transport = &find_session(&sessions, user)["transport"];
+        assert_eq!(transport["kind"], "tls");
+        // The test clients disable TLS v1.3.
+        assert_eq!(transport["version"], "TLSv1.2");
+        assert!(!transport["cipher"].as_str().unwrap().is_empty());
+    }
+    assert!(connections_active(&server, "tls") >= 1.0);
+    assert_eq!(connections_active(&server, "plaintext"), 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_tls_config_errors() -> Result<(), Box<dyn Error>> {
+    ore::test::init_logging();
+
+    let ca = Ca::new()?;
+    let (server_cert, server_key) =
+        ca.request_cert("server", vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])?;
+    let (other_cert, other_key) = ca.request_client_cert("other")?;
+
+    // Concatenates the PEM files in `parts` into a new file.
+    let dir = tempfile::tempdir()?;
+    let concat = |name: &str, parts: &[&Path]| -> Result<PathBuf, Box<dyn Error>> {
+        let mut contents = vec![];
+        for part in parts {
+            contents.extend(fs::read(part)?);
+        }
+        let path = dir.path().join(name);
+        fs::write(&path, contents)?;
+        Ok(path)
+    };
+    let start_err = |mode: TlsMode, cert: &Path, key: &Path| -> String {
+        match util::start_server(util::Config::default().with_tls(mode, cert, key)) {
+            Ok(_) => panic!("server unexpectedly started"),
+            Err(e) => e.to_string(),
+        }
+    };
+
+    // A certificate followed by its issuer is accepted.
+    let chain = concat("chain.crt", &[&server_cert, &ca.ca_cert_path()])?;
+    util::start_server(util::Config::default().with_tls(TlsMode::Require, &chain, &server_key))?;
+
+    // A key that belongs to another certificate.
+    let message = start_err(TlsMode::Require, &server_cert, &other_key);
+    assert_contains!(message, "does not match the certificate (CN=server)");
+
+    // A certificate and key that are swapped.
+    let message = start_err(TlsMode::Require, &server_key, &server_cert);
+    assert_contains!(message, "contains a private key rather than a certificate");
+
+    // A chain whose first certificate is not the server's own.
+    let chain = concat("reversed.crt", &[&ca.ca_cert_path(), &server_cert])?;
+    let message = start_err(TlsMode::Require, &chain, &server_key);
+    assert_contains!(message, "belongs to certificate 2 (CN=server)");
+
+    // A chain in which a certificate is not followed by its issuer.
+    let chain = concat("broken.crt", &[&server_cert, &other_cert])?;
+    let message = start_err(TlsMode::Require, &chain, &server_key);
+    assert_contains!(message, "was not issued by the certificate that follows it");
+
+    // A certificate that has expired.
+    let expired_cert = dir.path().join("expired.crt");
+    let cert = {
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_pubkey(&PKey::private_key_from_pem(&fs::read(&server_key)?)?)?;
+        builder.set_issuer_name(ca.cert.subject_name())?;
+        builder.set_subject_name(ca.cert.subject_name())?;
+        builder.set_not_before(&*Asn1Time::from_unix(0)?)?;
+        builder.set_not_after(&*Asn1Time::from_unix(86400)?)?;
+        builder.sign(&ca.pkey, MessageDigest::sha256())?;
+        builder.build()
+    };
+    fs::write(&expired_cert, &cert.to_pem()?)?;
+    let message = start_err(TlsMode::Require, &expired_cert, &server_key);
+    assert_contains!(message, "expired on");
+
+    // A certificate authority that cannot verify a chain, because its
+    // issuer is missing.
+    let message = start_err(
+        TlsMode::VerifyCa { ca: other_cert },
+        &server_cert,
+        &server_key,
+    );
+    assert_contains!(message, "cannot verify client certificates");
+
+    Ok(())
+}
@@ -7,6 +7,7 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::env;
 use std::error::Error;
@@ -36,24 +37,166 @@ lazy_static! {
 #[derive(Clone)]
 pub struct Config {
     data_directory: Option<PathBuf>,
+    takeover_from: Option<SocketAddr>,
+    catalog_directory: Option<PathBuf>,
+    storage_directory: Option<PathBuf>,
+    scratch_directory: Option<PathBuf>,
     logging_granularity: Option<Duration>,
     tls: Option<materialized::TlsConfig>,
     experimental_mode: bool,
     safe_mode: bool,
     workers: usize,
+    worker_stack_size: Option<usize>,
     logical_compaction_window: Option<Duration>,
+    instance_labels: BTreeMap<String, String>,
+    server_name: Option<String>,
+    metrics_max_series: Option<usize>,
+    restore: Option<materialized::RestoreConfig>,
+    from_replica: Option<materialized::ReplicaTarget>,
+    allow_catalog_migration: bool,
+    durability: materialized::DurabilityLevel,
+    catalog_compact_interval: Option<Duration>,
+    orphan_scan_interval: Option<Duration>,
+    orphan_retention: Option<Duration>,
+    data_encryption: Option<materialized::EncryptionConfig>,
+    catalog_replica: Option<materialized::ReplicaTarget>,
+    read_only: bool,
+    sticky_maintenance_mode: bool,
+    self_test: bool,
+    host_metrics: bool,
+    introspection_metrics: bool,
+    protocol_detect_timeout: Duration,
+    protocol_policy: materialized::ProtocolPolicy,
+    connection_rate_limit: Option<materialized::RateLimitConfig>,
+    long_running_tail_threshold: Option<Duration>,
+    statement_label_keys: Vec<String>,
+    allocator: Option<materialized::AllocatorConfig>,
+    memory_watchdog: Option<materialized::MemoryWatchdogConfig>,
+    dataflow_memory_budget: Option<materialized::ByteSize>,
+    dataflow_memory_budget_enforce: bool,
+    flight_recorder: Option<materialized::FlightRecorderConfig>,
+    memory_trim_interval: Option<Duration>,
+    oom_score_adj: Option<i32>,
+    pgwire_buffer_size: Option<usize>,
+    pgwire_read_buffer_size: Option<usize>,
+    pgwire_buffer_shrink_after: Option<Duration>,
+    pgwire_server_version: Option<String>,
+    pgwire_max_message_size: usize,
+    max_connections: Option<usize>,
+    reserved_superuser_connections: usize,
+    superusers: Vec<String>,
+    http_max_concurrent_requests: Option<usize>,
+    http_request_queue_size: usize,
+    http_request_queue_timeout: Duration,
+    http_result_memory_limit: Option<usize>,
+    http_arrow_max_batch_size: usize,
+    http2_cleartext: bool,
+    http1_keep_alive_timeout: Option<Duration>,
+    http1_max_requests_per_connection: Option<usize>,
+    drain_notices: bool,
+    connection_notice: Option<materialized::ConnectionNoticeSource>,
+    idle_exit_after: Option<Duration>,
+    coord_liveness: materialized::LivenessConfig,
+    startup_timeout: Option<Duration>,
+    version_check: Option<materialized::VersionCheckConfig>,
+    #[cfg(feature = "failpoints")]
+    fault_injection: Option<materialized::FaultConfig>,
+    statement_timeout: Option<Duration>,
+    idle_in_transaction_session_timeout: Option<Duration>,
+    lock_timeouts: bool,
+    max_result_rows: Option<u64>,
+    max_result_bytes: Option<usize>,
+    tail_buffer_size: usize,
+    tail_buffer_policy: materialized::TailBufferPolicy,
+    user_stats_max_users: usize,
+    clock: materialized::ClockConfig,
+    outbound_bind_addr: Option<IpAddr>,
+    session_quotas: materialized::SessionQuotas,
+    authz: Option<Arc<dyn materialized::Authorizer>>,
+    diagnostics_exclude_sql: bool,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
             data_directory: None,
+            takeover_from: None,
+            catalog_directory: None,
+            storage_directory: None,
+            scratch_directory: None,
             logging_granularity: Some(Duration::from_secs(1)),
             tls: None,
             experimental_mode: false,
             safe_mode: false,
             workers: 1,
+            worker_stack_size: None,
             logical_compaction_window: None,
+            instance_labels: BTreeMap::new(),
+            server_name: None,
+            metrics_max_series: None,
+            restore: None,
+            from_replica: None,
+            allow_catalog_migration: false,
+            durability: materialized::DurabilityLevel::Full,
+            catalog_compact_interval: None,
+            orphan_scan_interval: None,
+            orphan_retention: None,
+            data_encryption: None,
+            catalog_replica: None,
+            read_only: false,
+            sticky_maintenance_mode: false,
+            self_test: false,
+            host_metrics: false,
+            introspection_metrics: false,
+            protocol_detect_timeout: materialized::DEFAULT_PROTOCOL_DETECT_TIMEOUT,
+            protocol_policy: materialized::ProtocolPolicy::Multiplexed,
+            connection_rate_limit: None,
+            long_running_tail_threshold: None,
+            statement_label_keys: vec![],
+            allocator: None,
+            memory_watchdog: None,
+            dataflow_memory_budget: None,
+            dataflow_memory_budget_enforce: true,
+            flight_recorder: None,
+            memory_trim_interval: None,
+            oom_score_adj: None,
+            pgwire_buffer_size: None,
+            pgwire_read_buffer_size: None,
+            pgwire_buffer_shrink_after: None,
+            pgwire_server_version: None,
+            pgwire_max_message_size: pgwire::DEFAULT_MAX_MESSAGE_SIZE,
+            max_connections: None,
+            reserved_superuser_connections: materialized::DEFAULT_RESERVED_SUPERUSER_CONNECTIONS,
+            superusers: vec![],
+            http_max_concurrent_requests: None,
+            http_request_queue_size: materialized::DEFAULT_HTTP_REQUEST_QUEUE_SIZE,
+            http_request_queue_timeout: materialized::DEFAULT_HTTP_REQUEST_QUEUE_TIMEOUT,
+            http_result_memory_limit: None,
+            http_arrow_max_batch_size: materialized::DEFAULT_HTTP_ARROW_MAX_BATCH_SIZE,
+            http2_cleartext: false,
+            http1_keep_alive_timeout: None,
+            http1_max_requests_per_connection: None,
+            drain_notices: true,
+            connection_notice: None,
+            idle_exit_after: None,
+            coord_liveness: materialized::LivenessConfig::default(),
+            startup_timeout: None,
+            version_check: None,
+            #[cfg(feature = "failpoints")]
+            fault_injection: None,
+            statement_timeout: None,
+            idle_in_transaction_session_timeout: None,
+            lock_timeouts: false,
+            max_result_rows: None,
+            max_result_bytes: None,
+            tail_buffer_size: 64 << 20,
+            tail_buffer_policy: materialized::TailBufferPolicy::Block,
+            user_stats_max_users: materialized::DEFAULT_MAX_TRACKED_USERS,
+            clock: materialized::ClockConfig::default(),
+            outbound_bind_addr: None,
+            session_quotas: materialized::SessionQuotas::default(),
+            authz: None,
+            diagnostics_exclude_sql: false,
         }
     }
 }
@@ -69,6 +212,26 @@ impl Config {
         self
     }
 
+    pub fn takeover_from(mut self, takeover_from: SocketAddr) -> Self {
+        self.takeover_from = Some(takeover_from);
+        self
+    }
+
+    pub fn catalog_directory(mut self, catalog_directory: impl Into<PathBuf>) -> Self {
+        self.catalog_directory = Some(catalog_directory.into());
+        self
+    }
+
+    pub fn storage_directory(mut self, storage_directory: impl Into<PathBuf>) -> Self {
+        self.storage_directory = Some(storage_directory.into());
+        self
+    }
+
+    pub fn scratch_directory(mut self, scratch_directory: impl Into<PathBuf>) -> Self {
+        self.scratch_directory = Some(scratch_directory.into());
+        self
+    }
+
     pub fn with_tls(
         mut self,
         mode: TlsMode,
@@ -93,15 +256,339 @@ impl Config {
         self
     }
 
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn sticky_maintenance_mode(mut self) -> Self {
+        self.sticky_maintenance_mode = true;
+        self
+    }
+
+    pub fn self_test(mut self) -> Self {
+        self.self_test = true;
+        self
+    }
+
+    pub fn host_metrics(mut self) -> Self {
+        self.host_metrics = true;
+        self
+    }
+
+    pub fn introspection_metrics(mut self) -> Self {
+        self.introspection_metrics = true;
+        self
+    }
+
+    pub fn protocol_detect_timeout(mut self, protocol_detect_timeout: Duration) -> Self {
+        self.protocol_detect_timeout = protocol_detect_timeout;
+        self
+    }
+
+    pub fn protocol_policy(mut self, protocol_policy: materialized::ProtocolPolicy) -> Self {
+        self.protocol_policy = protocol_policy;
+        self
+    }
+
+    pub fn connection_rate_limit(mut self, limit: materialized::RateLimitConfig) -> Self {
+        self.connection_rate_limit = Some(limit);
+        self
+    }
+
+    pub fn long_running_tail_threshold(mut self, threshold: Duration) -> Self {
+        self.long_running_tail_threshold = Some(threshold);
+        self
+    }
+
+    pub fn statement_label_key(mut self, key: &str) -> Self {
+        self.statement_label_keys.push(key.into());
+        self
+    }
+
     pub fn workers(mut self, workers: usize) -> Self {
         self.workers = workers;
         self
     }
 
+    pub fn worker_stack_size(mut self, worker_stack_size: usize) -> Self {
+        self.worker_stack_size = Some(worker_stack_size);
+        self
+    }
+
+    pub fn pgwire_buffers(
+        mut self,
+        buffer_size: Option<usize>,
+        read_buffer_size: Option<usize>,
+        shrink_after: Option<Duration>,
+    ) -> Self {
+        self.pgwire_buffer_size = buffer_size;
+        self.pgwire_read_buffer_size = read_buffer_size;
+        self.pgwire_buffer_shrink_after = shrink_after;
+        self
+    }
+
+    pub fn pgwire_server_version(mut self, version: &str) -> Self {
+        self.pgwire_server_version = Some(version.into());
+        self
+    }
+
+    pub fn pgwire_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.pgwire_max_message_size = max_message_size;
+        self
+    }
+
+    pub fn max_connections(
+        mut self,
+        max_connections: usize,
+        reserved_superuser_connections: usize,
+        superusers: &[&str],
+    ) -> Self {
+        self.max_connections = Some(max_connections);
+        self.reserved_superuser_connections = reserved_superuser_connections;
+        self.superusers = superusers.iter().map(|u| u.to_string()).collect();
+        self
+    }
+
+    pub fn http_max_concurrent_requests(
+        mut self,
+        max_concurrent_requests: usize,
+        queue_size: usize,
+        queue_timeout: Duration,
+    ) -> Self {
+        self.http_max_concurrent_requests = Some(max_concurrent_requests);
+        self.http_request_queue_size = queue_size;
+        self.http_request_queue_timeout = queue_timeout;
+        self
+    }
+
+    pub fn http_result_memory_limit(mut self, limit: usize) -> Self {
+        self.http_result_memory_limit = Some(limit);
+        self
+    }
+
+    pub fn http_arrow_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.http_arrow_max_batch_size = max_batch_size;
+        self
+    }
+
+    pub fn http2_cleartext(mut self, http2_cleartext: bool) -> Self {
+        self.http2_cleartext = http2_cleartext;
+        self
+    }
+
+    pub fn http1_keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.http1_keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    pub fn http1_max_requests_per_connection(mut self, max_requests: usize) -> Self {
+        self.http1_max_requests_per_connection = Some(max_requests);
+        self
+    }
+
+    pub fn drain_notices(mut self, drain_notices: bool) -> Self {
+        self.drain_notices = drain_notices;
+        self
+    }
+
+    pub fn connection_notice(mut self, source: materialized::ConnectionNoticeSource) -> Self {
+        self.connection_notice = Some(source);
+        self
+    }
+
+    pub fn idle_exit_after(mut self, idle_exit_after: Duration) -> Self {
+        self.idle_exit_after = Some(idle_exit_after);
+        self
+    }
+
+    pub fn coord_liveness(mut self, coord_liveness: materialized::LivenessConfig) -> Self {
+        self.coord_liveness = coord_liveness;
+        self
+    }
+
+    pub fn startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = Some(startup_timeout);
+        self
+    }
+
+    pub fn version_check(mut self, version_check: materialized::VersionCheckConfig) -> Self {
+        self.version_check = Some(version_check);
+        self
+    }
+
+    #[cfg(feature = "failpoints")]
+    pub fn fault_injection(mut self, fault_injection: materialized::FaultConfig) -> Self {
+        self.fault_injection = Some(fault_injection);
+        self
+    }
+
+    pub fn statement_timeout(mut self, statement_timeout: Duration) -> Self {
+        self.statement_timeout = Some(statement_timeout);
+        self
+    }
+
+    pub fn idle_in_transaction_session_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_in_transaction_session_timeout = Some(timeout);
+        self
+    }
+
+    pub fn lock_timeouts(mut self) -> Self {
+        self.lock_timeouts = true;
+        self
+    }
+
+    pub fn max_result_rows(mut self, max_result_rows: u64) -> Self {
+        self.max_result_rows = Some(max_result_rows);
+        self
+    }
+
+    pub fn max_result_bytes(mut self, max_result_bytes: usize) -> Self {
+        self.max_result_bytes = Some(max_result_bytes);
+        self
+    }
+
+    pub fn clock(mut self, clock: materialized::ClockConfig) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn outbound_bind_addr(mut self, addr: IpAddr) -> Self {
+        self.outbound_bind_addr = Some(addr);
+        self
+    }
+
+    pub fn tail_buffer(mut self, size: usize, policy: materialized::TailBufferPolicy) -> Self {
+        self.tail_buffer_size = size;
+        self.tail_buffer_policy = policy;
+        self
+    }
+
+    pub fn user_stats_max_users(mut self, max_users: usize) -> Self {
+        self.user_stats_max_users = max_users;
+        self
+    }
+
+    pub fn session_quotas(mut self, session_quotas: materialized::SessionQuotas) -> Self {
+        self.session_quotas = session_quotas;
+        self
+    }
+
+    pub fn authz(mut self, authz: Arc<dyn materialized::Authorizer>) -> Self {
+        self.authz = Some(authz);
+        self
+    }
+
+    pub fn diagnostics_exclude_sql(mut self, diagnostics_exclude_sql: bool) -> Self {
+        self.diagnostics_exclude_sql = diagnostics_exclude_sql;
+        self
+    }
+
     pub fn logical_compaction_window(mut self, logical_compaction_window: Duration) -> Self {
         self.logical_compaction_window = Some(logical_compaction_window);
         self
     }
+
+    pub fn instance_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.instance_labels.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    pub fn metrics_max_series(mut self, metrics_max_series: usize) -> Self {
+        self.metrics_max_series = Some(metrics_max_series);
+        self
+    }
+
+    pub fn restore(
+        mut self,
+        path: impl Into<PathBuf>,
+        cluster_id: materialized::ClusterIdPolicy,
+    ) -> Self {
+        self.restore = Some(materialized::RestoreConfig {
+            path: path.into(),
+            force: false,
+            cluster_id,
+        });
+        self
+    }
+
+    pub fn from_replica(mut self, target: materialized::ReplicaTarget) -> Self {
+        self.from_replica = Some(target);
+        self
+    }
+
+    pub fn allow_catalog_migration(mut self) -> Self {
+        self.allow_catalog_migration = true;
+        self
+    }
+
+    pub fn durability(mut self, durability: materialized::DurabilityLevel) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn catalog_compact_interval(mut self, interval: Duration) -> Self {
+        self.catalog_compact_interval = Some(interval);
+        self
+    }
+
+    pub fn orphan_scan_interval(mut self, interval: Duration) -> Self {
+        self.orphan_scan_interval = Some(interval);
+        self
+    }
+
+    pub fn cleanup_orphans(mut self, retention: Duration) -> Self {
+        self.orphan_retention = Some(retention);
+        self
+    }
+
+    pub fn data_encryption_key_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.data_encryption = Some(materialized::EncryptionConfig {
+            key_source: materialized::KeySource::File(path.into()),
+        });
+        self
+    }
+
+    pub fn catalog_replica(mut self, target: materialized::ReplicaTarget) -> Self {
+        self.catalog_replica = Some(target);
+        self
+    }
+
+    pub fn allocator(mut self, allocator: materialized::AllocatorConfig) -> Self {
+        self.allocator = Some(allocator);
+        self
+    }
+
+    pub fn memory_watchdog(mut self, memory_watchdog: materialized::MemoryWatchdogConfig) -> Self {
+        self.memory_watchdog = Some(memory_watchdog);
+        self
+    }
+
+    pub fn dataflow_memory_budget(mut self, budget: u64, enforce: bool) -> Self {
+        self.dataflow_memory_budget = Some(materialized::ByteSize(budget));
+        self.dataflow_memory_budget_enforce = enforce;
+        self
+    }
+
+    pub fn flight_recorder(mut self, flight_recorder: materialized::FlightRecorderConfig) -> Self {
+        self.flight_recorder = Some(flight_recorder);
+        self
+    }
+
+    pub fn memory_trim_interval(mut self, memory_trim_interval: Duration) -> Self {
+        self.memory_trim_interval = Some(memory_trim_interval);
+        self
+    }
+
+    pub fn oom_score_adj(mut self, oom_score_adj: i32) -> Self {
+        self.oom_score_adj = Some(oom_score_adj);
+        self
+    }
 }
 
 pub fn start_server(config: Config) -> Result<Server, Box<dyn Error>> {
@@ -119,25 +606,106 @@ pub fn start_server(config: Config) -> Result<Server, Box<dyn Error>> {
     };
     let metrics_registry = MetricsRegistry::new();
     let inner = runtime.block_on(materialized::serve(materialized::Config {
+        manage_logging: false,
+        log: materialized::LogConfig::default(),
+        slow_query_threshold: None,
+        slow_query_log_file: None,
+        long_running_tail_threshold: config.long_running_tail_threshold,
+        statement_label_keys: config.statement_label_keys,
+        statement_timeout: config.statement_timeout,
+        idle_in_transaction_session_timeout: config.idle_in_transaction_session_timeout,
+        lock_timeouts: config.lock_timeouts,
+        max_result_rows: config.max_result_rows,
+        max_result_bytes: config.max_result_bytes,
+        tail_buffer_size: config.tail_buffer_size,
+        tail_buffer_policy: config.tail_buffer_policy,
+        user_stats_max_users: config.user_stats_max_users,
+        session_quotas: config.session_quotas,
+        authz: config.authz,
+        crash_reports: None,
+        diagnostics_exclude_sql: config.diagnostics_exclude_sql,
         logging: config
             .logging_granularity
             .map(|granularity| coord::LoggingConfig {
                 granularity,
                 log_logging: false,
                 retain_readings_for: granularity,
+                active: true,
             }),
         timestamp_frequency: Duration::from_secs(1),
+        clock: config.clock,
+        allocator: config.allocator,
+        memory_watchdog: config.memory_watchdog,
+        dataflow_memory_budget: config.dataflow_memory_budget,
+        dataflow_memory_budget_enforce: config.dataflow_memory_budget_enforce,
+        flight_recorder: config.flight_recorder,
+        memory_trim_interval: config.memory_trim_interval,
+        oom_score_adj: config.oom_score_adj,
         logical_compaction_window: config.logical_compaction_window,
         workers: config.workers,
         timely_worker: timely::WorkerConfig::default(),
+        worker_affinity: None,
+        worker_stack_size: config.worker_stack_size,
         data_directory,
+        catalog_directory: config.catalog_directory,
+        storage_directory: config.storage_directory,
+        scratch_directory: config.scratch_directory,
+        restore: config.restore,
+        from_replica: config.from_replica,
+        allow_catalog_migration: config.allow_catalog_migration,
+        durability: config.durability,
+        catalog_compact_interval: config.catalog_compact_interval,
+        orphan_scan_interval: config.orphan_scan_interval,
+        cleanup_orphans: config.orphan_retention.is_some(),
+        orphan_retention: config.orphan_retention.unwrap_or_default(),
+        data_encryption: config.data_encryption,
+        catalog_replica: config.catalog_replica,
         symbiosis_url: None,
         listen_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+        takeover_from: config.takeover_from,
+        outbound_bind_addr: config.outbound_bind_addr,
+        protocol_detect_timeout: config.protocol_detect_timeout,
+        protocol_policy: config.protocol_policy,
+        connection_rate_limit: config.connection_rate_limit,
         tls: config.tls,
+        warn_on_plaintext: false,
+        pgwire_buffer_size: config.pgwire_buffer_size,
+        pgwire_read_buffer_size: config.pgwire_read_buffer_size,
+        pgwire_buffer_shrink_after: config.pgwire_buffer_shrink_after,
+        pgwire_server_version: config.pgwire_server_version,
+        pgwire_max_message_size: config.pgwire_max_message_size,
+        max_connections: config.max_connections,
+        reserved_superuser_connections: config.reserved_superuser_connections,
+        superusers: config.superusers,
+        http_max_concurrent_requests: config.http_max_concurrent_requests,
+        http_request_queue_size: config.http_request_queue_size,
+        http_request_queue_timeout: config.http_request_queue_timeout,
+        http_result_memory_limit: config.http_result_memory_limit,
+        http_arrow_max_batch_size: config.http_arrow_max_batch_size,
+        http2_cleartext: config.http2_cleartext,
+        http1_keep_alive_timeout: config.http1_keep_alive_timeout,
+        http1_max_requests_per_connection: config.http1_max_requests_per_connection,
+        drain_notices: config.drain_notices,
+        connection_notice: config.connection_notice,
+        idle_exit_after: config.idle_exit_after,
+        coord_liveness: config.coord_liveness,
+        startup_timeout: config.startup_timeout,
         experimental_mode: config.experimental_mode,
         safe_mode: config.safe_mode,
+        read_only: config.read_only,
+        sticky_maintenance_mode: config.sticky_maintenance_mode,
+        self_test: config.self_test,
         telemetry: None,
+        version_check: config.version_check,
+        #[cfg(feature = "failpoints")]
+        fault_injection: config.fault_injection,
+        instance_labels: config.instance_labels,
+        server_name: config.server_name,
+        metrics_max_series: config.metrics_max_series,
+        metrics_filter: None,
         introspection_frequency: Duration::from_secs(1),
+        host_metrics: config.host_metrics,
+        introspection_metrics: config.introspection_metrics,
         metrics_registry: metrics_registry.clone(),
     }))?;
     let server = Server {
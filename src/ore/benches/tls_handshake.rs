@@ -0,0 +1,147 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks the rate at which a server accepts TLS connections when it
+//! performs handshakes inline on its accepting thread versus on a
+//! [`TlsHandshakePool`].
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::future;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslContext, SslMethod, SslVerifyMode};
+use openssl::x509::{X509NameBuilder, X509};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{self, Runtime};
+use tokio_openssl::SslStream;
+
+use ore::netio::TlsHandshakePool;
+
+/// The number of concurrent connections in each iteration.
+const CONNECTIONS: usize = 32;
+
+/// Builds a context that serves a freshly generated self-signed certificate.
+fn server_context() -> SslContext {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.set_private_key(&key).unwrap();
+    builder.build().into_context()
+}
+
+/// Starts a server on `rt` that performs a TLS handshake with every connection
+/// it accepts, on `pool` if specified and inline otherwise.
+fn start_server(rt: &Runtime, context: SslContext, pool: Option<TlsHandshakePool>) -> SocketAddr {
+    let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    rt.spawn(async move {
+        loop {
+            let (conn, _) = listener.accept().await.unwrap();
+            let ssl = Ssl::new(&context).unwrap();
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                match pool {
+                    Some(pool) => {
+                        let _ = pool.accept(ssl, conn).await;
+                    }
+                    None => {
+                        let mut stream = SslStream::new(ssl, conn).unwrap();
+                        let _ = Pin::new(&mut stream).accept().await;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// Opens [`CONNECTIONS`] concurrent TLS connections to `addr`.
+async fn connect_all(connector: &SslConnector, addr: SocketAddr) {
+    future::join_all((0..CONNECTIONS).map(|_| async move {
+        let conn = TcpStream::connect(addr).await.unwrap();
+        let ssl = connector
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let mut stream = SslStream::new(ssl, conn).unwrap();
+        Pin::new(&mut stream).connect().await.unwrap();
+    }))
+    .await;
+}
+
+pub fn bench_tls_handshake(c: &mut Criterion) {
+    let context = server_context();
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    let connector = connector.build();
+
+    // The server accepts connections on a single thread, so that handshakes
+    // performed inline compete with accepting further connections. Clients
+    // run on their own threads, so that they do not compete with the server.
+    let server_rt = || {
+        runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap()
+    };
+    let client_rt = runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("tls_handshake");
+    group.throughput(Throughput::Elements(CONNECTIONS as u64));
+
+    let inline_rt = server_rt();
+    let addr = start_server(&inline_rt, context.clone(), None);
+    group.bench_function("inline", |b| {
+        b.iter(|| client_rt.block_on(connect_all(&connector, addr)))
+    });
+
+    let pool_rt = server_rt();
+    let pool = TlsHandshakePool::new(4).unwrap();
+    let addr = start_server(&pool_rt, context, Some(pool));
+    group.bench_function("pool", |b| {
+        b.iter(|| client_rt.block_on(connect_all(&connector, addr)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_tls_handshake);
+criterion_main!(benches);
@@ -55,4 +55,5 @@ pub mod sync;
 #[cfg(feature = "test")]
 pub mod test;
 pub mod thread;
+pub mod trace;
 pub mod vec;
@@ -43,15 +43,15 @@
 
 use crate::stats::HISTOGRAM_BUCKETS;
 use prometheus::core::{
-    Atomic, Collector, GenericCounter, GenericCounterVec, GenericGauge, GenericGaugeVec, Opts,
+    Atomic, Collector, Desc, GenericCounter, GenericCounterVec, GenericGauge, GenericGaugeVec, Opts,
 };
 use prometheus::proto::MetricFamily;
 use prometheus::{HistogramOpts, Registry};
 
 pub use prometheus::Opts as PrometheusOpts;
 pub use prometheus::{
-    Counter, CounterVec, Gauge, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
-    IntGaugeVec, UIntCounter, UIntCounterVec, UIntGauge, UIntGaugeVec,
+    Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, UIntCounter, UIntCounterVec, UIntGauge, UIntGaugeVec,
 };
 
 mod delete_on_drop;
@@ -106,6 +106,42 @@ impl MetricsRegistry {
         collector
     }
 
+    /// Register a metric defined with the [`metric`] macro whose value is
+    /// computed by `compute` each time the registry is gathered.
+    ///
+    /// Use this for metrics that are derived from state that is cheap to
+    /// read, so that they are correct whenever they are gathered, rather than
+    /// only as of the last time a background task updated them.
+    pub fn register_computed<M, F>(&self, opts: prometheus::Opts, compute: F) -> M
+    where
+        M: MakeCollector,
+        F: Fn(&M) + Send + Sync + 'static,
+    {
+        let metric = M::make_collector(opts);
+        self.register_collector(ComputedCollector {
+            metric: metric.clone(),
+            compute,
+        });
+        metric
+    }
+
+    /// Register a histogram defined with the [`metric`] macro whose buckets
+    /// are `buckets` rather than [`HISTOGRAM_BUCKETS`].
+    pub fn register_histogram_vec(&self, opts: prometheus::Opts, buckets: &[f64]) -> HistogramVec {
+        let labels = opts.variable_labels.clone();
+        let labels = &labels.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        let histogram = HistogramVec::new(
+            HistogramOpts {
+                common_opts: opts,
+                buckets: buckets.to_vec(),
+            },
+            labels,
+        )
+        .expect("defining a histogram vec");
+        self.inner.register(Box::new(histogram.clone())).unwrap();
+        histogram
+    }
+
     /// Register a pre-defined prometheus collector.
     pub fn register_collector<C: 'static + prometheus::core::Collector>(&self, collector: C) {
         self.inner
@@ -121,6 +157,27 @@ impl MetricsRegistry {
     }
 }
 
+/// A collector that computes the value of its metric as it is collected.
+struct ComputedCollector<M, F> {
+    metric: M,
+    compute: F,
+}
+
+impl<M, F> Collector for ComputedCollector<M, F>
+where
+    M: Collector,
+    F: Fn(&M) + Send + Sync,
+{
+    fn desc(&self) -> Vec<&Desc> {
+        self.metric.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        (self.compute)(&self.metric);
+        self.metric.collect()
+    }
+}
+
 /// A wrapper for creating prometheus metrics more conveniently.
 ///
 /// Together with the [`metric`] macro, this trait is mainly used by [`MetricsRegistry`] and should
@@ -19,8 +19,10 @@ mod async_ready;
 mod framed;
 mod read_exact;
 mod stream;
+mod tls;
 
 pub use self::async_ready::AsyncReady;
 pub use self::framed::{FrameTooBig, MAX_FRAME_SIZE};
 pub use self::read_exact::{read_exact_or_eof, ReadExactOrEof};
 pub use self::stream::{SniffedStream, SniffingStream};
+pub use self::tls::TlsHandshakePool;
@@ -0,0 +1,119 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::io;
+use std::panic;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use openssl::ssl::Ssl;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::runtime::{self, Runtime};
+use tokio_openssl::SslStream;
+
+/// A pool of threads dedicated to TLS handshakes.
+///
+/// The server side of a TLS handshake involves expensive cryptographic
+/// computations. Performing handshakes on the pool, rather than on the
+/// threads that accept and serve connections, ensures that a burst of new
+/// TLS connections cannot starve established connections or delay the
+/// acceptance of further connections.
+///
+/// Cloning a pool produces another handle to the same threads. The threads
+/// exit once every handle has been dropped.
+#[derive(Clone)]
+pub struct TlsHandshakePool {
+    runtime: Arc<PoolRuntime>,
+}
+
+impl TlsHandshakePool {
+    /// Starts a pool with the specified number of threads.
+    pub fn new(threads: usize) -> Result<TlsHandshakePool, io::Error> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .thread_name("tls-handshake")
+            .build()?;
+        Ok(TlsHandshakePool {
+            runtime: Arc::new(PoolRuntime(Some(runtime))),
+        })
+    }
+
+    /// Performs the server side of a TLS handshake over `stream` on the
+    /// pool.
+    ///
+    /// If the handshake fails, `stream` is shut down.
+    pub async fn accept<S>(&self, ssl: Ssl, stream: S) -> Result<SslStream<S>, io::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        #[cfg(feature = "failpoints")]
+        fail::fail_point!("tls_accept", |_| Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "injected TLS handshake failure",
+        )));
+        let mut stream =
+            SslStream::new(ssl, stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let handshake = async move {
+            match Pin::new(&mut stream).accept().await {
+                Ok(()) => Ok(stream),
+                Err(e) => {
+                    let _ = stream.get_mut().shutdown().await;
+                    Err(e
+                        .into_io_error()
+                        .unwrap_or_else(|e| io::Error::new(io::ErrorKind::Other, e)))
+                }
+            }
+        };
+        match self.runtime.handle().spawn(handshake).await {
+            Ok(res) => res,
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "TLS handshake pool shut down",
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for TlsHandshakePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsHandshakePool").finish()
+    }
+}
+
+/// Owns the runtime that backs a [`TlsHandshakePool`].
+///
+/// Dropping a runtime blocks until its threads exit, which is not permitted
+/// within an asynchronous context, where the last handle to a pool is
+/// typically dropped. The runtime is instead shut down in the background.
+struct PoolRuntime(Option<Runtime>);
+
+impl PoolRuntime {
+    fn handle(&self) -> &runtime::Handle {
+        self.0
+            .as_ref()
+            .expect("runtime only taken on drop")
+            .handle()
+    }
+}
+
+impl Drop for PoolRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
@@ -15,6 +15,13 @@
 
 //! Statistics utilities.
 
+/// Buckets that can capture durations between 1 millisecond and 10 minutes,
+/// in seconds, spaced roughly logarithmically.
+pub const LONG_DURATION_BUCKETS: [f64; 18] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0,
+    200.0, 600.0,
+];
+
 /// Buckets that can capture data between 1/8th of a millisecond and 8 seconds.
 pub const HISTOGRAM_BUCKETS: [f64; 17] = [
     0.000_128, 0.000_256, 0.000_512, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128, 0.256,
@@ -0,0 +1,153 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Distributed tracing utilities.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A [W3C trace context], as carried by the `traceparent` HTTP header.
+///
+/// A trace context identifies the trace that an operation belongs to and the
+/// operation's parent within that trace.
+///
+/// [W3C trace context]: https://www.w3.org/TR/trace-context/#traceparent-header
+///
+/// # Examples
+///
+/// ```
+/// use ore::trace::TraceParent;
+///
+/// let tp: TraceParent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(tp.trace_id, 0x0af7651916cd43dd8448eb211c80319c);
+/// assert_eq!(tp.parent_id, 0xb7ad6b7169203331);
+/// assert!(tp.sampled);
+/// assert_eq!(
+///     tp.to_string(),
+///     "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    /// The ID of the trace. Never zero.
+    pub trace_id: u128,
+    /// The ID of the parent span. Never zero.
+    pub parent_id: u64,
+    /// Whether the caller may have recorded the trace.
+    pub sampled: bool,
+}
+
+impl FromStr for TraceParent {
+    type Err = ParseTraceParentError;
+
+    fn from_str(s: &str) -> Result<TraceParent, ParseTraceParentError> {
+        let is_lower_hex = |s: &str| s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+
+        let mut parts = s.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(version), Some(trace_id), Some(parent_id), Some(flags)) => {
+                    (version, trace_id, parent_id, flags)
+                }
+                _ => return Err(ParseTraceParentError),
+            };
+        // Version 00 admits no further fields. Future versions may append
+        // fields, which are ignored, but may not change the existing fields.
+        if version.len() != 2 || !is_lower_hex(version) || version == "ff" {
+            return Err(ParseTraceParentError);
+        }
+        if version == "00" && parts.next().is_some() {
+            return Err(ParseTraceParentError);
+        }
+        if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+            return Err(ParseTraceParentError);
+        }
+        if !is_lower_hex(trace_id) || !is_lower_hex(parent_id) || !is_lower_hex(flags) {
+            return Err(ParseTraceParentError);
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).map_err(|_| ParseTraceParentError)?;
+        let parent_id = u64::from_str_radix(parent_id, 16).map_err(|_| ParseTraceParentError)?;
+        let flags = u8::from_str_radix(flags, 16).map_err(|_| ParseTraceParentError)?;
+        if trace_id == 0 || parent_id == 0 {
+            return Err(ParseTraceParentError);
+        }
+        Ok(TraceParent {
+            trace_id,
+            parent_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.parent_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+/// The error returned when parsing a [`TraceParent`] fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseTraceParentError;
+
+impl fmt::Display for ParseTraceParentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid W3C traceparent")
+    }
+}
+
+impl Error for ParseTraceParentError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in &[
+            "",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "00-0af7651916cd43dd8448eb211c80319c-+7ad6b7169203331-01",
+        ] {
+            assert_eq!(
+                s.parse::<TraceParent>(),
+                Err(ParseTraceParentError),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_future_version() {
+        let tp: TraceParent = "cc-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-extra"
+            .parse()
+            .unwrap();
+        assert!(!tp.sampled);
+    }
+}
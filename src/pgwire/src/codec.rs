@@ -14,33 +14,46 @@
 //!
 //! [1]: https://www.postgresql.org/docs/11/protocol-message-formats.html
 
+use std::cmp;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
+use std::pin::Pin;
 use std::str;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use byteorder::{ByteOrder, NetworkEndian};
 use bytes::{Buf, BufMut, BytesMut};
-use futures::{sink, SinkExt, TryStreamExt};
+use futures::{ready, sink, SinkExt, TryStreamExt};
 use log::trace;
-use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, Interest, Ready};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, Interest, ReadBuf, Ready};
+use tokio::time;
 use tokio_util::codec::{Decoder, Encoder, Framed};
 
 use ore::cast::CastFrom;
 use ore::future::OreSinkExt;
 use ore::netio::{self, AsyncReady};
+use sql::parse::RedactionPolicy;
 
 use crate::message::{
-    BackendMessage, ErrorResponse, FrontendMessage, FrontendStartupMessage, TransactionStatus,
-    VERSION_CANCEL, VERSION_GSSENC, VERSION_SSL,
+    BackendMessage, ErrorResponse, FrontendMessage, FrontendStartupMessage, Redacted,
+    TransactionStatus, VERSION_CANCEL, VERSION_GSSENC, VERSION_SSL,
 };
 use crate::server::Conn;
 
 pub const REJECT_ENCRYPTION: u8 = b'N';
 pub const ACCEPT_SSL_ENCRYPTION: u8 = b'S';
 
+/// The field type of the nonstandard field in an `ErrorResponse` message that
+/// carries the connection's correlation ID.
+///
+/// PostgreSQL does not use this field type, and clients are required to
+/// silently ignore field types they do not recognize.
+pub const CORRELATION_ID_FIELD: u8 = b'I';
+
 #[derive(Debug)]
 enum CodecError {
     StringNoTerminator,
@@ -56,10 +69,76 @@ impl fmt::Display for CodecError {
     }
 }
 
+/// The default maximum size of a message from a client, in bytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = netio::MAX_FRAME_SIZE;
+
+/// The most space to reserve in the read buffer for a message before its data
+/// arrives. Larger messages grow the buffer as their data arrives, so that a
+/// client that claims a large message but never sends it ties up no memory.
+const MAX_RESERVE_SIZE: usize = 1 << 20;
+
+/// An error indicating that a client sent a message larger than the maximum
+/// message size.
+#[derive(Debug)]
+pub struct MessageTooLarge {
+    /// The size of the message, as claimed by its header.
+    pub size: usize,
+    /// The maximum message size.
+    pub max_size: usize,
+}
+
+impl MessageTooLarge {
+    /// Returns the `MessageTooLarge` error wrapped by `e`, if any.
+    pub fn from_io(e: &io::Error) -> Option<&MessageTooLarge> {
+        e.get_ref().and_then(|e| e.downcast_ref())
+    }
+}
+
+impl Error for MessageTooLarge {}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "message of {} bytes exceeds the maximum message size of {} bytes",
+            self.size, self.max_size
+        )
+    }
+}
+
+/// The initial size of the read and write buffers of a [`Framed`], which
+/// also determines how much encoded data [`Framed`] accumulates before
+/// writing it to the connection.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Configures the buffers of a pgwire connection.
+///
+/// The default configuration leaves the buffers as [`Framed`] manages them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BufferConfig {
+    /// The initial size of the buffer into which messages from the client are
+    /// read. The buffer grows to fit larger messages.
+    ///
+    /// Defaults to 8KiB.
+    pub read_size: Option<usize>,
+    /// How much encoded data to accumulate before writing it to the
+    /// connection.
+    ///
+    /// Defaults to 8KiB.
+    pub write_size: Option<usize>,
+    /// How long the connection must wait for a message from the client before
+    /// its buffers are shrunk back to their initial sizes.
+    ///
+    /// By default, buffers that grow to fit large messages never shrink.
+    pub idle_shrink_after: Option<Duration>,
+}
+
 /// A connection that manages the encoding and decoding of pgwire frames.
 pub struct FramedConn<A> {
     conn_id: u32,
-    inner: sink::Buffer<Framed<Conn<A>, Codec>, BackendMessage>,
+    redaction: RedactionPolicy,
+    buffers: BufferConfig,
+    inner: sink::Buffer<Framed<BufferedConn<A>, Codec>, BackendMessage>,
 }
 
 impl<A> FramedConn<A>
@@ -73,11 +152,32 @@ where
     /// will do.
     ///
     /// The supplied `conn_id` is used to identify the connection in logging
-    /// messages.
-    pub fn new(conn_id: u32, inner: Conn<A>) -> FramedConn<A> {
+    /// messages, in which messages are redacted according to `redaction`. The
+    /// supplied `correlation_id`, if any, is attached to every error sent to
+    /// the client, as is the `server_name`, if any, in the error's context.
+    /// The connection's buffers are configured by `buffers`.
+    /// Messages from the client larger than `max_message_size` bytes are
+    /// rejected with a [`MessageTooLarge`] error.
+    pub fn new(
+        conn_id: u32,
+        correlation_id: Option<String>,
+        server_name: Option<String>,
+        redaction: RedactionPolicy,
+        buffers: BufferConfig,
+        max_message_size: usize,
+        inner: Conn<A>,
+    ) -> FramedConn<A> {
+        let mut codec = Codec::new();
+        codec.correlation_id = correlation_id;
+        codec.context = server_name.map(|name| format!("server {}", name));
+        codec.max_message_size = max_message_size;
+        let inner = BufferedConn::new(inner, buffers.write_size);
+        let read_size = buffers.read_size.unwrap_or(DEFAULT_BUFFER_SIZE);
         FramedConn {
             conn_id,
-            inner: Framed::new(inner, Codec::new()).buffer(32),
+            redaction,
+            buffers,
+            inner: Framed::with_capacity(inner, codec, read_size).buffer(32),
         }
     }
 
@@ -86,15 +186,44 @@ where
         self.conn_id
     }
 
+    /// Returns the policy by which to redact this connection's messages in
+    /// log messages.
+    pub fn redaction(&self) -> RedactionPolicy {
+        self.redaction
+    }
+
     /// Reads and decodes one frontend message from the client.
     ///
     /// Blocks until the client sends a complete message. If the client
     /// terminates the stream, returns `None`. Returns an error if the client
     /// sends a malformatted message or if the connection underlying is broken.
+    ///
+    /// If the client sends nothing for long enough, the connection's buffers
+    /// are shrunk while waiting, as configured.
     pub async fn recv(&mut self) -> Result<Option<FrontendMessage>, io::Error> {
-        let message = self.inner.try_next().await?;
+        let message = match self.buffers.idle_shrink_after {
+            None => self.inner.try_next().await?,
+            // Waiting for the next message is cancel safe: a partially read
+            // message remains in the read buffer.
+            Some(idle_shrink_after) => {
+                match time::timeout(idle_shrink_after, self.inner.try_next()).await {
+                    Ok(message) => message?,
+                    Err(_) => {
+                        self.shrink_buffers();
+                        self.inner.try_next().await?
+                    }
+                }
+            }
+        };
         match &message {
-            Some(message) => trace!("cid={} recv={:?}", self.conn_id, message),
+            Some(message) => trace!(
+                "cid={} recv={:?}",
+                self.conn_id,
+                Redacted {
+                    message,
+                    policy: self.redaction
+                }
+            ),
             None => trace!("cid={} recv=<eof>", self.conn_id),
         }
         Ok(message)
@@ -110,7 +239,14 @@ where
         M: Into<BackendMessage>,
     {
         let message = message.into();
-        trace!("cid={} send={:?}", self.conn_id, message);
+        trace!(
+            "cid={} send={:?}",
+            self.conn_id,
+            Redacted {
+                message: &message,
+                policy: self.redaction
+            }
+        );
         Ok(self.inner.enqueue(message).await?)
     }
 
@@ -148,6 +284,25 @@ where
     pub fn set_encode_state(&mut self, encode_state: Vec<(pgrepr::Type, pgrepr::Format)>) {
         self.inner.get_mut().codec_mut().encode_state = encode_state;
     }
+
+    /// Shrinks any buffers that have grown beyond their initial sizes and
+    /// that hold no data.
+    fn shrink_buffers(&mut self) {
+        let read_size = self.buffers.read_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        let framed = self.inner.get_mut();
+        shrink_bytes(framed.read_buffer_mut(), read_size);
+        shrink_bytes(framed.write_buffer_mut(), DEFAULT_BUFFER_SIZE);
+        framed.get_mut().shrink();
+        trace!("cid={} shrank idle buffers", self.conn_id);
+    }
+}
+
+/// Replaces `buf` with a buffer of capacity `size` if `buf` is empty and has
+/// grown beyond `size`.
+fn shrink_bytes(buf: &mut BytesMut, size: usize) {
+    if buf.is_empty() && buf.capacity() > size {
+        *buf = BytesMut::with_capacity(size);
+    }
 }
 
 impl<A> FramedConn<A>
@@ -155,7 +310,7 @@ where
     A: AsyncRead + AsyncWrite + Unpin,
 {
     pub fn inner(&self) -> &Conn<A> {
-        self.inner.get_ref().get_ref()
+        &self.inner.get_ref().get_ref().inner
     }
 }
 
@@ -165,13 +320,115 @@ where
     A: AsyncRead + AsyncWrite + AsyncReady + Send + Sync + Unpin,
 {
     async fn ready(&self, interest: Interest) -> io::Result<Ready> {
-        self.inner.get_ref().get_ref().ready(interest).await
+        self.inner().ready(interest).await
+    }
+}
+
+/// A connection that, if configured with a capacity, accumulates writes in a
+/// buffer of that capacity before writing them to the underlying connection.
+///
+/// Without a capacity, writes pass straight through.
+struct BufferedConn<A> {
+    inner: Conn<A>,
+    capacity: Option<usize>,
+    /// The buffered data. Allocated on first use and released by
+    /// [`BufferedConn::shrink`].
+    buf: Vec<u8>,
+    /// The number of bytes at the start of `buf` that have been written.
+    written: usize,
+}
+
+impl<A> BufferedConn<A>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(inner: Conn<A>, capacity: Option<usize>) -> BufferedConn<A> {
+        BufferedConn {
+            inner,
+            capacity,
+            buf: vec![],
+            written: 0,
+        }
+    }
+
+    /// Releases the buffer, if it holds no data.
+    fn shrink(&mut self) {
+        if self.buf.is_empty() {
+            self.buf = vec![];
+        }
+    }
+
+    fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        while self.written < self.buf.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<A> AsyncRead for BufferedConn<A>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<A> AsyncWrite for BufferedConn<A>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, data: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let capacity = match this.capacity {
+            None => return Pin::new(&mut this.inner).poll_write(cx, data),
+            Some(capacity) => capacity,
+        };
+        if this.buf.len() + data.len() > capacity {
+            ready!(this.poll_write_buf(cx))?;
+        }
+        if data.len() >= capacity {
+            Pin::new(&mut this.inner).poll_write(cx, data)
+        } else {
+            if this.buf.capacity() == 0 {
+                this.buf.reserve_exact(capacity);
+            }
+            this.buf.extend_from_slice(data);
+            Poll::Ready(Ok(data.len()))
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
     }
 }
 
 struct Codec {
     decode_state: DecodeState,
     encode_state: Vec<(pgrepr::Type, pgrepr::Format)>,
+    correlation_id: Option<String>,
+    /// The context attached to every error, which names the server.
+    context: Option<String>,
+    max_message_size: usize,
 }
 
 impl Codec {
@@ -180,6 +437,9 @@ impl Codec {
         Codec {
             decode_state: DecodeState::Head,
             encode_state: vec![],
+            correlation_id: None,
+            context: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
         }
     }
 }
@@ -343,6 +603,16 @@ impl Encoder<BackendMessage> for Codec {
                     dst.put_u8(b'P');
                     dst.put_string(&position.to_string());
                 }
+                if severity.is_error() {
+                    if let Some(context) = &self.context {
+                        dst.put_u8(b'W');
+                        dst.put_string(context);
+                    }
+                    if let Some(correlation_id) = &self.correlation_id {
+                        dst.put_u8(CORRELATION_ID_FIELD);
+                        dst.put_string(correlation_id);
+                    }
+                }
                 dst.put_u8(b'\0');
             }
         }
@@ -395,7 +665,14 @@ impl<B: BufMut> Pgbuf for B {
     }
 }
 
-pub async fn decode_startup<A>(mut conn: A) -> Result<Option<FrontendStartupMessage>, io::Error>
+/// Decodes one startup message from `conn`.
+///
+/// Messages larger than `max_message_size` bytes are rejected with a
+/// [`MessageTooLarge`] error.
+pub async fn decode_startup<A>(
+    mut conn: A,
+    max_message_size: usize,
+) -> Result<Option<FrontendStartupMessage>, io::Error>
 where
     A: AsyncRead + Unpin,
 {
@@ -411,11 +688,18 @@ where
         // surface the unexpected EOF.
         _ => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof")),
     };
-    let frame_len = parse_frame_len(&frame_len)?;
-
-    let mut buf = BytesMut::new();
-    buf.resize(frame_len, b'0');
-    conn.read_exact(&mut buf).await?;
+    let frame_len = parse_frame_len(&frame_len, max_message_size)?;
+
+    // Rather than allocating space for the claimed length up front, grow the
+    // buffer as the data arrives.
+    let mut buf = vec![];
+    (&mut conn)
+        .take(u64::cast_from(frame_len))
+        .read_to_end(&mut buf)
+        .await?;
+    if buf.len() < frame_len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof"));
+    }
 
     let mut buf = Cursor::new(&buf);
     let version = buf.read_i32()?;
@@ -445,12 +729,15 @@ enum DecodeState {
     Data(u8, usize),
 }
 
-fn parse_frame_len(src: &[u8]) -> Result<usize, io::Error> {
+fn parse_frame_len(src: &[u8], max_message_size: usize) -> Result<usize, io::Error> {
     let n = usize::cast_from(NetworkEndian::read_u32(src));
-    if n > netio::MAX_FRAME_SIZE {
+    if n > max_message_size {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
-            netio::FrameTooBig,
+            MessageTooLarge {
+                size: n,
+                max_size: max_message_size,
+            },
         ));
     } else if n < 4 {
         return Err(io::Error::new(
@@ -473,9 +760,9 @@ impl Decoder for Codec {
                         return Ok(None);
                     }
                     let msg_type = src[0];
-                    let frame_len = parse_frame_len(&src[1..])?;
+                    let frame_len = parse_frame_len(&src[1..], self.max_message_size)?;
                     src.advance(5);
-                    src.reserve(frame_len);
+                    src.reserve(cmp::min(frame_len, MAX_RESERVE_SIZE));
                     self.decode_state = DecodeState::Data(msg_type, frame_len);
                 }
 
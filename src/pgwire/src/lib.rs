@@ -28,5 +28,9 @@ mod metrics;
 mod protocol;
 mod server;
 
+pub use codec::{BufferConfig, DEFAULT_MAX_MESSAGE_SIZE};
 pub use protocol::match_handshake;
-pub use server::{Config, Server, TlsConfig, TlsMode};
+pub use server::{
+    Config, ConnectionGate, ConnectionNotice, Draining, Server, SessionLimit, SessionSlot,
+    StartingServer, TlsConfig, TlsMode,
+};
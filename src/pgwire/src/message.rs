@@ -9,6 +9,7 @@
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt;
 use std::io;
 
 use bytes::BytesMut;
@@ -19,6 +20,7 @@ use postgres::error::SqlState;
 use coord::session::TransactionStatus as CoordTransactionStatus;
 use repr::adt::numeric::NUMERIC_DATUM_MAX_PRECISION;
 use repr::{ColumnName, Datum, RelationDesc, RelationType, Row, RowArena, ScalarType};
+use sql::parse::{RedactionPolicy, REDACTED_SQL};
 
 // Pgwire protocol versions are represented as 32-bit integers, where the
 // high 16 bits represent the major version and the low 16 bits represent the
@@ -254,6 +256,113 @@ impl From<ErrorResponse> for BackendMessage {
     }
 }
 
+/// Wraps a frontend or backend message for inclusion in a log message.
+///
+/// The [`Debug`](fmt::Debug) implementation of the wrapper redacts the
+/// message according to the policy. SQL text is redacted by the policy
+/// directly. Data that is not SQL but may nonetheless be sensitive, like
+/// parameter values, rows, and error messages, is omitted entirely unless the
+/// policy is [`RedactionPolicy::None`].
+pub struct Redacted<'a, M> {
+    pub message: &'a M,
+    pub policy: RedactionPolicy,
+}
+
+impl fmt::Debug for Redacted<'_, FrontendMessage> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.policy == RedactionPolicy::None {
+            return fmt::Debug::fmt(self.message, f);
+        }
+        match self.message {
+            FrontendMessage::Query { sql } => f
+                .debug_struct("Query")
+                .field("sql", &self.policy.redact(sql))
+                .finish(),
+            FrontendMessage::Parse {
+                name,
+                sql,
+                param_types,
+            } => f
+                .debug_struct("Parse")
+                .field("name", name)
+                .field("sql", &self.policy.redact(sql))
+                .field("param_types", param_types)
+                .finish(),
+            FrontendMessage::Bind {
+                portal_name,
+                statement_name,
+                param_formats,
+                raw_params,
+                result_formats,
+            } => f
+                .debug_struct("Bind")
+                .field("portal_name", portal_name)
+                .field("statement_name", statement_name)
+                .field("param_formats", param_formats)
+                .field("raw_params", &Omitted(raw_params.len()))
+                .field("result_formats", result_formats)
+                .finish(),
+            FrontendMessage::CopyData(data) => f
+                .debug_tuple("CopyData")
+                .field(&Omitted(data.len()))
+                .finish(),
+            message => fmt::Debug::fmt(message, f),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_, BackendMessage> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.policy == RedactionPolicy::None {
+            return fmt::Debug::fmt(self.message, f);
+        }
+        match self.message {
+            BackendMessage::DataRow(values) => f
+                .debug_tuple("DataRow")
+                .field(&Omitted(values.len()))
+                .finish(),
+            BackendMessage::CopyData(data) => f
+                .debug_tuple("CopyData")
+                .field(&Omitted(data.len()))
+                .finish(),
+            BackendMessage::ErrorResponse(err) => f
+                .debug_tuple("ErrorResponse")
+                .field(&Redacted {
+                    message: err,
+                    policy: self.policy,
+                })
+                .finish(),
+            message => fmt::Debug::fmt(message, f),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_, ErrorResponse> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.policy == RedactionPolicy::None {
+            return fmt::Debug::fmt(self.message, f);
+        }
+        // Error messages frequently quote the values that caused the error,
+        // so only the error's code is retained.
+        let err = self.message;
+        f.debug_struct("ErrorResponse")
+            .field("severity", &err.severity)
+            .field("code", &err.code.code())
+            .field("message", &REDACTED_SQL)
+            .field("position", &err.position)
+            .finish()
+    }
+}
+
+/// Stands in for `n` omitted items in a redacted message.
+struct Omitted(usize);
+
+impl fmt::Debug for Omitted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{} omitted>", self.0)
+    }
+}
+
 /// A local representation of [`CoordTransactionStatus`]
 #[derive(Debug, Clone, Copy)]
 pub enum TransactionStatus {
@@ -339,19 +448,35 @@ impl ErrorResponse {
         let code = match e {
             CoordError::Catalog(_) => SqlState::INTERNAL_ERROR,
             CoordError::ConstrainedParameter(_) => SqlState::INVALID_PARAMETER_VALUE,
+            CoordError::DataflowMemoryBudgetExceeded { .. } => {
+                SqlState::CONFIGURATION_LIMIT_EXCEEDED
+            }
             CoordError::DuplicateCursor(_) => SqlState::DUPLICATE_CURSOR,
             CoordError::Eval(_) => SqlState::INTERNAL_ERROR,
             CoordError::IdExhaustionError => SqlState::INTERNAL_ERROR,
+            CoordError::IntrospectionInactive(_) => SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE,
+            CoordError::IntrospectionInUse { .. } => SqlState::OBJECT_IN_USE,
+            CoordError::IntrospectionNotConfigured => SqlState::OBJECT_NOT_IN_PREREQUISITE_STATE,
             CoordError::InvalidParameterType(_) => SqlState::INVALID_PARAMETER_VALUE,
+            CoordError::InvalidParameterValue { .. } => SqlState::INVALID_PARAMETER_VALUE,
+            CoordError::MaintenanceMode { .. } => SqlState::READ_ONLY_SQL_TRANSACTION,
             CoordError::OperationProhibitsTransaction(_) => SqlState::ACTIVE_SQL_TRANSACTION,
             CoordError::OperationRequiresTransaction(_) => SqlState::NO_ACTIVE_SQL_TRANSACTION,
+            CoordError::ParameterExceedsLimit { .. } => SqlState::INVALID_PARAMETER_VALUE,
             CoordError::ReadOnlyTransaction => SqlState::READ_ONLY_SQL_TRANSACTION,
             CoordError::ReadOnlyParameter(_) => SqlState::CANT_CHANGE_RUNTIME_PARAM,
+            CoordError::ReadOnlyServer(_) => SqlState::READ_ONLY_SQL_TRANSACTION,
             CoordError::RelationOutsideTimeDomain { .. } => SqlState::INVALID_TRANSACTION_STATE,
+            CoordError::ResultMemoryExhausted { .. } => SqlState::INSUFFICIENT_RESOURCES,
+            CoordError::ResultSizeLimitExceeded { .. } => SqlState::PROGRAM_LIMIT_EXCEEDED,
             CoordError::SafeModeViolation(_) => SqlState::INTERNAL_ERROR,
+            CoordError::SessionQuotaExceeded { .. } => SqlState::CONFIGURATION_LIMIT_EXCEEDED,
             CoordError::SqlCatalog(_) => SqlState::INTERNAL_ERROR,
+            CoordError::StatementTimeout => SqlState::QUERY_CANCELED,
+            CoordError::TailBufferExceeded { .. } => SqlState::PROGRAM_LIMIT_EXCEEDED,
             CoordError::TailOnlyTransaction => SqlState::INVALID_TRANSACTION_STATE,
             CoordError::Transform(_) => SqlState::INTERNAL_ERROR,
+            CoordError::Unauthorized(_) => SqlState::INSUFFICIENT_PRIVILEGE,
             CoordError::UnknownCursor(_) => SqlState::INVALID_CURSOR_NAME,
             CoordError::UnknownParameter(_) => SqlState::UNDEFINED_OBJECT,
             CoordError::UnknownLoginRole(_) => SqlState::INVALID_AUTHORIZATION_SPECIFICATION,
@@ -514,6 +639,82 @@ pub fn encode_copy_row_text(
     Ok(())
 }
 
+pub fn encode_copy_row_csv(
+    row: Row,
+    typ: &RelationType,
+    out: &mut Vec<u8>,
+) -> Result<(), io::Error> {
+    let delim = b',';
+    let quote = b'"';
+    let mut buf = BytesMut::new();
+    for (idx, field) in pgrepr::values_from_row(row, typ).into_iter().enumerate() {
+        if idx > 0 {
+            out.push(delim);
+        }
+        match field {
+            // NULL is represented by an unquoted empty string.
+            None => (),
+            Some(field) => {
+                buf.clear();
+                field.encode_text(&mut buf);
+                // Quote values that would otherwise be ambiguous: empty
+                // strings, which would read as NULL, values that contain the
+                // delimiter, a quote, or a line break, and values that would
+                // read as the end-of-copy marker.
+                let needs_quotes = buf.is_empty()
+                    || &buf[..] == b"\\."
+                    || buf
+                        .iter()
+                        .any(|b| *b == delim || *b == quote || *b == b'\n' || *b == b'\r');
+                if needs_quotes {
+                    out.push(quote);
+                    for b in &buf {
+                        if *b == quote {
+                            out.push(quote);
+                        }
+                        out.push(*b);
+                    }
+                    out.push(quote);
+                } else {
+                    out.extend(&buf);
+                }
+            }
+        }
+    }
+    out.push(b'\n');
+    Ok(())
+}
+
+/// Returns the length of the longest prefix of `data` that consists of
+/// complete rows of `COPY FROM` data, in the CSV format if `csv` is set or in
+/// the text format otherwise, and whether that prefix is followed by the
+/// end-of-copy marker.
+///
+/// Rows can then be decoded as the data arrives, rather than once all of it
+/// has been received.
+pub fn split_copy_rows(data: &[u8], csv: bool) -> (usize, bool) {
+    let mut line_start = 0;
+    let mut in_quotes = false;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'\\' if !csv => i += 1,
+            b'"' if csv => in_quotes = !in_quotes,
+            b'\n' if !in_quotes => {
+                let line = &data[line_start..i];
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                if line == b"\\." {
+                    return (line_start, true);
+                }
+                line_start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    (line_start, false)
+}
+
 struct CopyTextFormatParser<'a> {
     data: &'a [u8],
     position: usize,
@@ -801,6 +1002,102 @@ pub fn decode_copy_text_format(
     Ok(rows)
 }
 
+pub fn decode_copy_csv_format(
+    data: &[u8],
+    column_types: &[pgrepr::Type],
+    delimiter: &Option<String>,
+    null: &Option<String>,
+) -> Result<Vec<Row>, io::Error> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let delimiter = match delimiter.as_deref() {
+        None => b',',
+        Some(delimiter) if delimiter.len() == 1 => delimiter.as_bytes()[0],
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "COPY delimiter must be a single one-byte character",
+            ))
+        }
+    };
+    let quote = b'"';
+    let null = null.as_deref().unwrap_or("").as_bytes();
+    let mut rows = Vec::new();
+    let mut position = 0;
+    let mut field = Vec::new();
+    while position < data.len() {
+        if data[position..].starts_with(b"\\.") {
+            let rest = &data[position + 2..];
+            if rest.iter().all(|b| *b == b'\r' || *b == b'\n') {
+                break;
+            }
+        }
+        let mut row = Vec::new();
+        let buf = RowArena::new();
+        for (col, typ) in column_types.iter().enumerate() {
+            if col > 0 {
+                if data.get(position) != Some(&delimiter) {
+                    return Err(invalid("missing data for column".into()));
+                }
+                position += 1;
+            }
+            field.clear();
+            let quoted = data.get(position) == Some(&quote);
+            if quoted {
+                position += 1;
+                loop {
+                    match data.get(position) {
+                        None => return Err(invalid("unterminated CSV quoted field".into())),
+                        Some(b) if *b == quote => {
+                            position += 1;
+                            // A doubled quote is a literal quote.
+                            if data.get(position) == Some(&quote) {
+                                field.push(quote);
+                                position += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(b) => {
+                            field.push(*b);
+                            position += 1;
+                        }
+                    }
+                }
+            } else {
+                while let Some(b) = data.get(position) {
+                    if *b == delimiter || *b == b'\n' || *b == b'\r' {
+                        break;
+                    }
+                    field.push(*b);
+                    position += 1;
+                }
+            }
+            // Only unquoted values can represent NULL.
+            if !quoted && field == null {
+                row.push(Datum::Null);
+            } else {
+                match pgrepr::Value::decode_text(&typ, &field) {
+                    Ok(value) => row.push(value.into_datum(&buf, &typ).0),
+                    Err(err) => return Err(invalid(format!("unable to decode column: {}", err))),
+                }
+            }
+        }
+        match data.get(position) {
+            None => (),
+            Some(b'\n') => position += 1,
+            Some(b'\r') => {
+                position += 1;
+                if data.get(position) == Some(&b'\n') {
+                    position += 1;
+                }
+            }
+            Some(_) => return Err(invalid("extra data after last expected column".into())),
+        }
+        rows.push(Row::pack(row));
+    }
+    Ok(rows)
+}
+
 pub fn encode_row_description(
     desc: &RelationDesc,
     formats: &[pgrepr::Format],
@@ -1004,4 +1301,82 @@ mod tests {
             assert!(parser.is_eof());
         }
     }
+
+    #[test]
+    fn test_copy_format_csv_round_trip() {
+        let typ = RelationType::new(vec![
+            ScalarType::Int32.nullable(true),
+            ScalarType::String.nullable(true),
+        ]);
+        let rows = vec![
+            Row::pack(&[Datum::Int32(1), Datum::String("plain")]),
+            Row::pack(&[Datum::Int32(2), Datum::String("a,\"b\"\nc")]),
+            Row::pack(&[Datum::Null, Datum::String("")]),
+            Row::pack(&[Datum::Int32(3), Datum::String("\\.")]),
+            Row::pack(&[Datum::Int32(4), Datum::Null]),
+        ];
+        let mut out = Vec::new();
+        for row in rows.clone() {
+            encode_copy_row_csv(row, &typ, &mut out).expect("encoding failed");
+        }
+        assert_eq!(
+            std::str::from_utf8(&out).unwrap(),
+            "1,plain\n2,\"a,\"\"b\"\"\nc\"\n,\"\"\n3,\"\\.\"\n4,\n"
+        );
+
+        let column_types = vec![pgrepr::Type::Int4, pgrepr::Type::Text];
+        let decoded =
+            decode_copy_csv_format(&out, &column_types, &None, &None).expect("decoding failed");
+        assert_eq!(decoded, rows);
+    }
+
+    #[test]
+    fn test_copy_format_csv_errors() {
+        let column_types = vec![pgrepr::Type::Int4, pgrepr::Type::Text];
+        for (data, delimiter, error) in &[
+            ("1\n", None, "missing data for column"),
+            ("1,a,b\n", None, "extra data after last expected column"),
+            ("1,\"a\n", None, "unterminated CSV quoted field"),
+            ("x,a\n", None, "unable to decode column"),
+            (
+                "1,a\n",
+                Some("::"),
+                "COPY delimiter must be a single one-byte character",
+            ),
+        ] {
+            let delimiter = delimiter.map(|d| d.to_string());
+            let err = decode_copy_csv_format(data.as_bytes(), &column_types, &delimiter, &None)
+                .expect_err("decoding unexpectedly succeeded");
+            assert!(err.to_string().starts_with(error), "{}: {}", data, err);
+        }
+
+        let decoded = decode_copy_csv_format(
+            b"1|NULL\r\n2|\"NULL\"\n\\.\n",
+            &column_types,
+            &Some("|".into()),
+            &Some("NULL".into()),
+        )
+        .expect("decoding failed");
+        assert_eq!(
+            decoded,
+            vec![
+                Row::pack(&[Datum::Int32(1), Datum::Null]),
+                Row::pack(&[Datum::Int32(2), Datum::String("NULL")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_copy_rows() {
+        assert_eq!(split_copy_rows(b"", false), (0, false));
+        assert_eq!(split_copy_rows(b"a\tb\nc", false), (4, false));
+        // Escaped newlines do not end rows in the text format.
+        assert_eq!(split_copy_rows(b"a\\\nb\nc", false), (5, false));
+        // Nor do quoted newlines in the CSV format.
+        assert_eq!(split_copy_rows(b"\"a\nb\"\n\"c\n", true), (6, false));
+        assert_eq!(split_copy_rows(b"a\n\\.\nb\n", false), (2, true));
+        assert_eq!(split_copy_rows(b"a\n\\.\r\n", true), (2, true));
+        // A quoted end-of-copy marker is data.
+        assert_eq!(split_copy_rows(b"\"\\.\"\n", true), (5, false));
+    }
 }
@@ -9,7 +9,7 @@
 
 use ore::{
     metric,
-    metrics::{HistogramVec, MetricsRegistry, UIntCounter},
+    metrics::{HistogramVec, MetricsRegistry, UIntCounter, UIntCounterVec},
 };
 
 #[derive(Clone, Debug)]
@@ -17,6 +17,9 @@ pub struct Metrics {
     pub command_durations: HistogramVec,
     pub bytes_sent: UIntCounter,
     pub rows_returned: UIntCounter,
+    pub refused_connections: UIntCounter,
+    pub cancel_requests: UIntCounterVec,
+    pub oversized_messages: UIntCounter,
 }
 
 impl Metrics {
@@ -37,6 +40,22 @@ impl Metrics {
                 name: "mz_pg_sent_bytes",
                 help: "total number of bytes sent to clients from pgwire",
             )),
+
+            refused_connections: registry.register(metric!(
+                name: "mz_pg_refused_connections",
+                help: "total number of pgwire connections refused because the server was not accepting new sessions, or had no session available to the user",
+            )),
+
+            cancel_requests: registry.register(metric!(
+                name: "mz_pg_cancel_requests",
+                help: "total number of pgwire cancellation requests received, by outcome",
+                var_labels: ["outcome"],
+            )),
+
+            oversized_messages: registry.register(metric!(
+                name: "mz_pg_oversized_messages",
+                help: "total number of pgwire messages rejected for exceeding the maximum message size",
+            )),
         }
     }
 }
@@ -13,24 +13,30 @@ use std::convert::TryFrom;
 use std::future::Future;
 use std::iter;
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use byteorder::{ByteOrder, NetworkEndian};
 use expr::GlobalId;
-use futures::future::{BoxFuture, FutureExt};
+use futures::future::{self, BoxFuture, FutureExt};
 use futures::stream::{self, StreamExt};
 use itertools::izip;
 use log::debug;
-use message::decode_copy_text_format;
+use message::{decode_copy_csv_format, decode_copy_text_format};
 use openssl::nid::Nid;
 use postgres::error::SqlState;
 use tokio::io::{self, AsyncRead, AsyncWrite, Interest};
 use tokio::time::{self, Duration, Instant};
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{field, Instrument, Span};
 
 use coord::session::{
     EndTransactionAction, Portal, PortalState, RowBatchStream, Session, TransactionStatus,
 };
-use coord::ExecuteResponse;
+use coord::{
+    ActiveStatement, Authorizer, CoordError, ExecuteResponse, LabeledStatement, Maintenance,
+    MaintenanceMode, Quotas, StatementDuration, StatementDurations, StatementLabels,
+    StatementTimer, TimedStatement, Transport, UserStatement,
+};
 use dataflow_types::PeekResponse;
 use ore::cast::CastFrom;
 use ore::netio::AsyncReady;
@@ -38,14 +44,15 @@ use ore::str::StrExt;
 use repr::{Datum, RelationDesc, RelationType, Row, RowArena};
 use sql::ast::display::AstDisplay;
 use sql::ast::{FetchDirection, Ident, Raw, Statement};
+use sql::parse::{RedactionPolicy, REDACTED_SQL};
 use sql::plan::{CopyFormat, CopyParams, ExecuteTimeout, StatementDesc};
 
-use crate::codec::FramedConn;
+use crate::codec::{FramedConn, MessageTooLarge};
 use crate::message::{
     self, BackendMessage, ErrorResponse, FrontendMessage, Severity, VERSIONS, VERSION_3,
 };
 use crate::metrics::Metrics;
-use crate::server::{Conn, TlsMode};
+use crate::server::{Conn, Draining, TlsMode};
 
 /// Reports whether the given stream begins with a pgwire handshake.
 ///
@@ -71,6 +78,8 @@ pub fn match_handshake(buf: &[u8]) -> bool {
 pub struct RunParams<'a, A> {
     /// The TLS mode of the pgwire server.
     pub tls_mode: Option<TlsMode>,
+    /// The transport over which the client connected.
+    pub transport: Transport,
     /// A client for the coordinator.
     pub coord_client: coord::ConnClient,
     /// The connection to the client.
@@ -79,8 +88,29 @@ pub struct RunParams<'a, A> {
     pub version: i32,
     /// The parameters that the client provided in the startup message.
     pub params: HashMap<String, String>,
+    /// The ID that correlates the connection's log messages and errors.
+    pub correlation_id: String,
     /// The server's metrics.
     pub metrics: &'a Metrics,
+    /// Records how long the session's statements take.
+    pub statement_durations: StatementDurations,
+    /// Decides which statements the session may execute, if present.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// Reports whether the server is draining, if the session is to be
+    /// notified when it begins to drain.
+    pub draining: Option<Draining>,
+    /// The notice to send to the client once its session starts, if any.
+    pub connection_notice: Option<String>,
+    /// Reports whether the server is in maintenance mode.
+    pub maintenance_mode: MaintenanceMode,
+    /// The resource quotas to which the session is held.
+    pub session_quotas: Quotas,
+    /// The name of the server, if any, to report to the client once its
+    /// session starts.
+    pub server_name: Option<&'a str>,
+    /// The number of bytes sent to the client that have not yet been
+    /// reported toward the statistics of the session's user.
+    pub unreported_bytes: Arc<AtomicU64>,
 }
 
 /// Runs a pgwire connection to completion.
@@ -95,11 +125,21 @@ pub struct RunParams<'a, A> {
 pub async fn run<'a, A>(
     RunParams {
         tls_mode,
+        transport,
         coord_client,
         conn,
         version,
         mut params,
+        correlation_id,
         metrics,
+        statement_durations,
+        authorizer,
+        draining,
+        connection_notice,
+        maintenance_mode,
+        session_quotas,
+        server_name,
+        unreported_bytes,
     }: RunParams<'a, A>,
 ) -> Result<(), io::Error>
 where
@@ -158,9 +198,27 @@ where
 
     // Construct session.
     let mut session = Session::new(conn.id(), user);
+    session.set_correlation_id(correlation_id);
+    session.set_transport(transport);
+    if let Some(options) = params.remove("options") {
+        session.set_options(coord::session::parse_options(&options));
+    }
+
+    // Clients that ask not to be sent notices, as they may not expect them
+    // during startup, are not sent the connection notice, nor told that the
+    // server is in maintenance mode.
+    let min_messages = params
+        .get("client_min_messages")
+        .or_else(|| session.options().get("client_min_messages"));
+    let startup_notices = !matches!(
+        min_messages.map(|level| level.to_lowercase()).as_deref(),
+        Some("warning" | "error" | "fatal" | "panic")
+    );
+
     for (name, value) in params {
         let _ = session.vars_mut().set(&name, &value);
     }
+    session.vars_mut().set_session_quotas(&session_quotas.get());
 
     // Register session with coordinator.
     let (mut coord_client, startup) = match coord_client.startup(session).await {
@@ -174,6 +232,11 @@ where
 
     // From this point forward we must not fail without calling `coord_client.terminate`!
 
+    coord_client.set_statement_durations(statement_durations);
+    if let Some(authorizer) = authorizer {
+        coord_client.set_authorizer(authorizer);
+    }
+
     let res = async {
         let session = coord_client.session();
         let mut buf = vec![BackendMessage::AuthenticationOk];
@@ -184,9 +247,25 @@ where
             conn_id: session.conn_id(),
             secret_key: startup.secret_key,
         });
+        if let Some(server_name) = server_name {
+            buf.push(BackendMessage::ParameterStatus(
+                "mz_server_name",
+                server_name.into(),
+            ));
+        }
         for startup_message in startup.messages {
             buf.push(ErrorResponse::from_startup_message(startup_message).into());
         }
+        let maintenance = maintenance_mode.get();
+        buf.push(maintenance_status(&maintenance));
+        if startup_notices {
+            if let Some(notice) = connection_notice {
+                buf.push(ErrorResponse::notice(SqlState::SUCCESSFUL_COMPLETION, notice).into());
+            }
+            if let Some(maintenance) = &maintenance {
+                buf.push(maintenance_notice(maintenance).into());
+            }
+        }
         buf.push(BackendMessage::ReadyForQuery(session.transaction().into()));
         conn.send_all(buf).await?;
         conn.flush().await?;
@@ -195,10 +274,24 @@ where
             metrics,
             conn,
             coord_client: &mut coord_client,
+            timed_statement: None,
+            statement_duration: None,
+            statement_timer: None,
+            active_statement: None,
+            user_statement: None,
+            labeled_statement: None,
+            draining,
+            maintenance_mode,
+            maintenance,
+            session_quotas,
+            unreported_bytes: Arc::clone(&unreported_bytes),
         };
         machine.run().await
     }
     .await;
+    coord_client
+        .user_session()
+        .add_bytes(unreported_bytes.swap(0, Ordering::Relaxed));
     coord_client.terminate().await;
     res
 }
@@ -214,6 +307,37 @@ struct StateMachine<'a, A> {
     conn: &'a mut FramedConn<A>,
     coord_client: &'a mut coord::SessionClient,
     metrics: &'a Metrics,
+    /// The statement currently being executed, if the slow query log is
+    /// enabled. If the connection is severed mid-statement, dropping the
+    /// state machine logs the statement as cancelled.
+    timed_statement: Option<TimedStatement>,
+    /// Times the statement currently being executed, if any, for the
+    /// statement duration histogram.
+    statement_duration: Option<StatementDuration>,
+    /// The timer that enforces the session's statement timeout on the
+    /// statement currently being executed, if any.
+    statement_timer: Option<StatementTimer>,
+    /// Records that a statement is executing, for as long as one is.
+    active_statement: Option<ActiveStatement>,
+    /// Counts the statement currently being executed, if any, toward the
+    /// statistics of the session's user.
+    user_statement: Option<UserStatement>,
+    /// Attaches the labels of the statement currently being executed, if
+    /// any, to the session.
+    labeled_statement: Option<LabeledStatement>,
+    /// Reports whether the server is draining, until the client has been
+    /// notified that it is.
+    draining: Option<Draining>,
+    /// Reports whether the server is in maintenance mode.
+    maintenance_mode: MaintenanceMode,
+    /// The maintenance state of the server that the client was last told
+    /// about.
+    maintenance: Option<Maintenance>,
+    /// The resource quotas to which the session is held.
+    session_quotas: Quotas,
+    /// The number of bytes sent to the client that have not yet been
+    /// reported toward the statistics of the session's user.
+    unreported_bytes: Arc<AtomicU64>,
 }
 
 impl<'a, A> StateMachine<'a, A>
@@ -228,17 +352,61 @@ where
         async move {
             let mut state = State::Ready;
             loop {
-                state = match state {
-                    State::Ready => self.advance_ready().await?,
-                    State::Drain => self.advance_drain().await?,
+                let res = match state {
+                    State::Ready => self.advance_ready().await,
+                    State::Drain => self.advance_drain().await,
                     State::Done => return Ok(()),
+                };
+                state = match res {
+                    Ok(state) => state,
+                    Err(e) => match MessageTooLarge::from_io(&e) {
+                        Some(e) => {
+                            let message = e.to_string();
+                            return self.message_too_large(message).await;
+                        }
+                        None => return Err(e),
+                    },
                 }
             }
         }
     }
 
     async fn advance_ready(&mut self) -> Result<State, io::Error> {
-        let message = self.conn.recv().await?;
+        let idle_deadline = self
+            .idle_in_transaction_timeout()
+            .map(|timeout| Instant::now() + timeout);
+        let message = loop {
+            let draining = self.draining.clone();
+            let drained = async move {
+                match draining {
+                    Some(draining) => draining.wait().await,
+                    None => future::pending().await,
+                }
+            };
+            let maintenance_mode = self.maintenance_mode.clone();
+            let maintenance = self.maintenance.clone();
+            let maintenance_changed = async move { maintenance_mode.changed(&maintenance).await };
+            // Waiting for the next message is cancel safe, so the wait can be
+            // interrupted to notify the client that the server is draining or
+            // that its maintenance mode changed.
+            let recv = async {
+                match idle_deadline {
+                    None => self.conn.recv().await.map(Some),
+                    Some(deadline) => match time::timeout_at(deadline, self.conn.recv()).await {
+                        Ok(message) => message.map(Some),
+                        Err(_) => Ok(None),
+                    },
+                }
+            };
+            tokio::select! {
+                message = recv => match message? {
+                    Some(message) => break message,
+                    None => return self.idle_in_transaction_timed_out().await,
+                },
+                _ = drained => self.notify_draining().await?,
+                _ = maintenance_changed => self.notify_maintenance().await?,
+            }
+        };
         let timer = Instant::now();
         let name = match &message {
             Some(message) => message.name(),
@@ -247,6 +415,13 @@ where
 
         self.coord_client.reset_canceled();
 
+        // The quotas may have been adjusted since the last message.
+        let session_quotas = self.session_quotas.get();
+        self.coord_client
+            .session()
+            .vars_mut()
+            .set_session_quotas(&session_quotas);
+
         let next_state = match message {
             Some(FrontendMessage::Query { sql }) => self.query(sql).await?,
             Some(FrontendMessage::Parse {
@@ -278,14 +453,34 @@ where
                     Ok(0) | Err(_) => ExecuteCount::All, // If `max_rows < 0`, no limit.
                     Ok(n) => ExecuteCount::Count(n),
                 };
-                self.execute(
-                    portal_name,
-                    max_rows,
-                    portal_exec_message,
-                    None,
-                    ExecuteTimeout::None,
-                )
-                .await?
+                let labels = self
+                    .coord_client
+                    .session()
+                    .get_portal(&portal_name)
+                    .map(|portal| portal.labels.clone())
+                    .unwrap_or_default();
+                self.labeled_statement = Some(self.coord_client.label_statement(labels));
+                self.timed_statement = self.coord_client.start_timing_portal(&portal_name);
+                self.statement_duration = self
+                    .coord_client
+                    .start_statement_duration_portal(&portal_name);
+                self.statement_timer = self.coord_client.start_statement_timer();
+                self.active_statement =
+                    Some(self.coord_client.start_statement_portal(&portal_name));
+                self.user_statement = Some(self.coord_client.user_session().start_statement());
+                let span = self.statement_span();
+                let state = self
+                    .execute(
+                        portal_name,
+                        max_rows,
+                        portal_exec_message,
+                        None,
+                        ExecuteTimeout::None,
+                    )
+                    .instrument(span)
+                    .await?;
+                self.finish_timing();
+                state
             }
             Some(FrontendMessage::DescribeStatement { name }) => {
                 self.describe_statement(name).await?
@@ -297,9 +492,13 @@ where
             Some(FrontendMessage::Sync) => self.sync().await?,
             Some(FrontendMessage::Terminate) => State::Done,
 
+            // A `COPY FROM` may fail before the client has sent all of its
+            // data. The remaining copy messages are dropped, as in Postgres;
+            // draining until a `Sync` would hang clients that issued the
+            // `COPY` via a simple query, as they never send one.
             Some(FrontendMessage::CopyData(_))
             | Some(FrontendMessage::CopyDone)
-            | Some(FrontendMessage::CopyFail(_)) => State::Drain,
+            | Some(FrontendMessage::CopyFail(_)) => State::Ready,
             None => State::Done,
         };
 
@@ -315,6 +514,21 @@ where
         Ok(next_state)
     }
 
+    /// Rejects a message from the client that exceeds the maximum message
+    /// size. The rest of the message is never read, so the connection cannot
+    /// continue.
+    async fn message_too_large(&mut self, message: String) -> Result<(), io::Error> {
+        debug!("cid={} {}", self.conn.id(), message);
+        self.metrics.oversized_messages.inc();
+        self.conn
+            .send(ErrorResponse::fatal(
+                SqlState::PROGRAM_LIMIT_EXCEEDED,
+                message,
+            ))
+            .await?;
+        self.conn.flush().await
+    }
+
     async fn advance_drain(&mut self) -> Result<State, io::Error> {
         match self.conn.recv().await? {
             Some(FrontendMessage::Sync) => self.sync().await,
@@ -338,6 +552,16 @@ where
                 .await;
         }
 
+        if let Err(e) = self
+            .coord_client
+            .session()
+            .check_concurrent_statement_quota()
+        {
+            return self
+                .error(ErrorResponse::from_coord(Severity::Error, e))
+                .await;
+        }
+
         let stmt_desc = self
             .coord_client
             .session()
@@ -401,6 +625,9 @@ where
     // transactions are handled.
     // From https://www.postgresql.org/docs/current/protocol-flow.html
     async fn query(&mut self, sql: String) -> Result<State, io::Error> {
+        // A leading comment labels every statement in the query.
+        let labels = StatementLabels::parse(&sql);
+
         // Parse first before doing any transaction checking.
         let stmts = match parse_sql(&sql) {
             Ok(stmts) => stmts,
@@ -429,7 +656,16 @@ where
             // statement.
             self.start_transaction(Some(num_stmts)).await;
 
-            match self.one_query(stmt).await? {
+            self.labeled_statement = Some(self.coord_client.label_statement(labels.clone()));
+            self.timed_statement = self.coord_client.start_timing(&stmt);
+            self.statement_duration = self.coord_client.start_statement_duration(&stmt);
+            self.statement_timer = self.coord_client.start_statement_timer();
+            self.active_statement = Some(self.coord_client.start_statement(&stmt));
+            self.user_statement = Some(self.coord_client.user_session().start_statement());
+            let span = self.statement_span();
+            let state = self.one_query(stmt).instrument(span).await?;
+            self.finish_timing();
+            match state {
                 State::Ready => (),
                 State::Drain => break,
                 State::Done => return Ok(State::Done),
@@ -497,12 +733,28 @@ where
         if self.is_aborted_txn() && !is_txn_exit_stmt(maybe_stmt.as_ref()) {
             return self.aborted_txn_error().await;
         }
+        if let Err(e) = self
+            .coord_client
+            .session()
+            .check_prepared_statement_quota(&name)
+        {
+            return self
+                .error(ErrorResponse::from_coord(Severity::Error, e))
+                .await;
+        }
         match self
             .coord_client
-            .describe(name, maybe_stmt, param_types)
+            .describe(name.clone(), maybe_stmt, param_types)
             .await
         {
             Ok(()) => {
+                if let Some(stmt) = self
+                    .coord_client
+                    .session()
+                    .get_prepared_statement_mut(&name)
+                {
+                    stmt.set_labels(StatementLabels::parse(&sql));
+                }
                 self.conn.send(BackendMessage::ParseComplete).await?;
                 Ok(State::Ready)
             }
@@ -617,16 +869,19 @@ where
         };
 
         let desc = stmt.desc().clone();
+        let labels = stmt.labels().clone();
         let stmt = stmt.sql().cloned();
+        let session = self.coord_client.session();
         if let Err(err) =
-            self.coord_client
-                .session()
-                .set_portal(portal_name, desc, stmt, params, result_formats)
+            session.set_portal(portal_name.clone(), desc, stmt, params, result_formats)
         {
             return self
                 .error(ErrorResponse::from_coord(Severity::Error, err))
                 .await;
         }
+        if let Some(portal) = session.get_portal_mut(&portal_name) {
+            portal.labels = labels;
+        }
 
         self.conn.send(BackendMessage::BindComplete).await?;
         Ok(State::Ready)
@@ -667,6 +922,16 @@ where
 
             match &mut portal.state {
                 PortalState::NotStarted => {
+                    if let Err(e) = self
+                        .coord_client
+                        .session()
+                        .check_concurrent_statement_quota()
+                    {
+                        return self
+                            .error(ErrorResponse::from_coord(Severity::Error, e))
+                            .await;
+                    }
+
                     // Start a transaction if we aren't in one. Postgres does this both here and
                     // in bind. We don't do it in bind because I'm not sure what purpose it would
                     // serve us (i.e., I'm not aware of a pgtest that would differ between us and
@@ -1000,13 +1265,7 @@ where
                 let row_desc =
                     row_desc.expect("missing row description for ExecuteResponse::SendingRows");
                 match rx.await {
-                    PeekResponse::Canceled => {
-                        self.error(ErrorResponse::error(
-                            SqlState::QUERY_CANCELED,
-                            "canceling statement due to user request",
-                        ))
-                        .await
-                    }
+                    PeekResponse::Canceled => self.error(self.query_canceled()).await,
                     PeekResponse::Error(text) => {
                         self.error(ErrorResponse::error(SqlState::INTERNAL_ERROR, text))
                             .await
@@ -1015,7 +1274,7 @@ where
                         self.send_rows(
                             row_desc,
                             portal_name,
-                            Box::new(stream::iter(vec![rows])),
+                            Box::new(stream::iter(vec![Ok(rows)])),
                             max_rows,
                             get_response,
                             fetch_portal_name,
@@ -1083,10 +1342,11 @@ where
                 }
                 let row_desc =
                     row_desc.expect("missing row description for ExecuteResponse::Tailing");
+                let _tail = self.coord_client.start_tail("pgwire", rx.buffer().clone());
                 self.send_rows(
                     row_desc,
                     portal_name,
-                    Box::new(UnboundedReceiverStream::new(rx)),
+                    Box::new(rx),
                     max_rows,
                     get_response,
                     fetch_portal_name,
@@ -1097,24 +1357,23 @@ where
             ExecuteResponse::CopyTo { format, resp } => {
                 let row_desc =
                     row_desc.expect("missing row description for ExecuteResponse::CopyTo");
+                let mut _tail = None;
                 let rows: RowBatchStream = match *resp {
-                    ExecuteResponse::Tailing { rx } => Box::new(UnboundedReceiverStream::new(rx)),
+                    ExecuteResponse::Tailing { rx } => {
+                        _tail = Some(self.coord_client.start_tail("pgwire", rx.buffer().clone()));
+                        Box::new(rx)
+                    }
                     ExecuteResponse::SendingRows(rx) => match rx.await {
                         // TODO(mjibson): This logic is duplicated from SendingRows. Dedup?
                         PeekResponse::Canceled => {
-                            return self
-                                .error(ErrorResponse::error(
-                                    SqlState::QUERY_CANCELED,
-                                    "canceling statement due to user request",
-                                ))
-                                .await;
+                            return self.error(self.query_canceled()).await;
                         }
                         PeekResponse::Error(text) => {
                             return self
                                 .error(ErrorResponse::error(SqlState::INTERNAL_ERROR, text))
                                 .await;
                         }
-                        PeekResponse::Rows(rows) => Box::new(stream::iter(vec![rows])),
+                        PeekResponse::Rows(rows) => Box::new(stream::iter(vec![Ok(rows)])),
                     },
                     _ => {
                         return self
@@ -1200,12 +1459,18 @@ where
             let batch = tokio::select! {
                 _ = time::sleep_until(deadline.unwrap_or_else(time::Instant::now)), if deadline.is_some() => FetchResult::Rows(None),
                 _ = self.coord_client.canceled() => FetchResult::Cancelled,
+                _ = time::sleep(Duration::from_secs(1)) => FetchResult::Idle,
                 batch = rows.next() => FetchResult::Rows(batch),
             };
 
             match batch {
                 FetchResult::Rows(None) => break,
-                FetchResult::Rows(Some(mut batch_rows)) => {
+                FetchResult::Rows(Some(Err(e))) => {
+                    return self
+                        .error(ErrorResponse::from_coord(Severity::Error, e))
+                        .await;
+                }
+                FetchResult::Rows(Some(Ok(mut batch_rows))) => {
                     // Verify the first row is of the expected type. This is often good enough to
                     // find problems. Notably it failed to find #6304 when "FETCH 2" was used in a
                     // test, instead we had to use "FETCH 1" twice.
@@ -1239,6 +1504,21 @@ where
                         }
                     }
 
+                    // Check the batch against the session's result size
+                    // limits before sending any of it. A peek delivers its
+                    // entire result in one batch, while each batch of a
+                    // TAIL is limited on its own.
+                    if let Err(e) = self
+                        .coord_client
+                        .session()
+                        .vars()
+                        .check_result_size(&batch_rows)
+                    {
+                        return self
+                            .error(ErrorResponse::from_coord(Severity::Error, e))
+                            .await;
+                    }
+
                     // If wait_once is true: the first time this fn is called it blocks (same as
                     // deadline == None). The second time this fn is called it should behave the
                     // same a 0s timeout.
@@ -1261,19 +1541,33 @@ where
                     // (if any) back and stop sending.
                     if want_rows == 0 {
                         if !batch_rows.is_empty() {
-                            rows = Box::new(stream::iter(vec![batch_rows]).chain(rows));
+                            rows = Box::new(stream::iter(vec![Ok(batch_rows)]).chain(rows));
                         }
                         break;
                     }
                     self.conn.flush().await?;
                 }
                 FetchResult::Cancelled => {
-                    return self
-                        .error(ErrorResponse::error(
-                            SqlState::QUERY_CANCELED,
-                            "canceling statement due to user request",
-                        ))
-                        .await;
+                    return self.error(self.query_canceled()).await;
+                }
+                FetchResult::Idle => {
+                    // It's been a while since we've had any rows to send, as
+                    // is typical of a TAIL, and the client may have
+                    // disconnected. Check whether the socket has been closed
+                    // for reading, without waiting for it to become readable,
+                    // as a live client need not send anything while rows
+                    // stream. See the similar check in `copy_rows`.
+                    let ready = self.conn.ready(Interest::READABLE).now_or_never();
+                    if let Some(ready) = ready {
+                        if ready?.is_read_closed() {
+                            return self
+                                .error(ErrorResponse::fatal(
+                                    SqlState::CONNECTION_FAILURE,
+                                    "connection closed",
+                                ))
+                                .await;
+                        }
+                    }
                 }
             }
         }
@@ -1281,6 +1575,10 @@ where
         self.metrics
             .rows_returned
             .inc_by(u64::cast_from(total_sent_rows));
+        self.coord_client.user_session().add_rows(total_sent_rows);
+        if let Some(timed_statement) = &mut self.timed_statement {
+            timed_statement.add_rows(total_sent_rows);
+        }
 
         let portal = self
             .coord_client
@@ -1314,16 +1612,12 @@ where
             pgrepr::Format,
         ) = match format {
             CopyFormat::Text => (message::encode_copy_row_text, pgrepr::Format::Text),
+            CopyFormat::Csv => (message::encode_copy_row_csv, pgrepr::Format::Text),
             CopyFormat::Binary => (message::encode_copy_row_binary, pgrepr::Format::Binary),
-            _ => {
-                return self
-                    .error(ErrorResponse::error(
-                        SqlState::FEATURE_NOT_SUPPORTED,
-                        format!("COPY TO format {:?} not supported", format),
-                    ))
-                    .await
-            }
         };
+        let copy = self
+            .coord_client
+            .start_copy("to", copy_format_name(&format));
 
         let typ = row_desc.typ();
         let column_formats = iter::repeat(encode_format)
@@ -1378,22 +1672,33 @@ where
                 },
                 _ = self.coord_client.canceled() => {
                     return self
-                        .error(ErrorResponse::error(
-                            SqlState::QUERY_CANCELED,
-                            "canceling statement due to user request",
-                        ))
+                        .error(self.query_canceled())
                     .await;
                 },
                 batch = stream.next() => match batch {
                     None => break,
-                    Some(rows) => {
+                    Some(Err(e)) => {
+                        return self
+                            .error(ErrorResponse::from_coord(Severity::Error, e))
+                            .await;
+                    }
+                    Some(Ok(rows)) => {
+                        // See the similar check in `send_rows`.
+                        if let Err(e) = self.coord_client.session().vars().check_result_size(&rows) {
+                            return self
+                                .error(ErrorResponse::from_coord(Severity::Error, e))
+                                .await;
+                        }
                         count += rows.len();
+                        let (batch_rows, mut batch_bytes) = (rows.len(), 0);
                         for row in rows {
                             encode_fn(row, typ, &mut out)?;
+                            batch_bytes += out.len();
                             self.conn
                                 .send(BackendMessage::CopyData(mem::take(&mut out)))
                                 .await?;
                         }
+                        copy.record(batch_rows, batch_bytes);
                     }
                 },
             }
@@ -1427,9 +1732,9 @@ where
         row_desc: RelationDesc,
     ) -> Result<State, io::Error> {
         let encode_format: pgrepr::Format = match params.format {
-            CopyFormat::Text => pgrepr::Format::Text,
+            CopyFormat::Text | CopyFormat::Csv => pgrepr::Format::Text,
             // CopyFormat::Binary => pgrepr::Format::Binary,
-            _ => {
+            CopyFormat::Binary => {
                 return self
                     .error(ErrorResponse::error(
                         SqlState::FEATURE_NOT_SUPPORTED,
@@ -1451,12 +1756,83 @@ where
             .await?;
         self.conn.flush().await?;
 
-        let mut data = Vec::new();
+        let column_types = typ
+            .column_types
+            .iter()
+            .map(|x| &x.scalar_type)
+            .map(pgrepr::Type::from)
+            .collect::<Vec<pgrepr::Type>>();
+        let csv = matches!(params.format, CopyFormat::Csv);
+        let decode = |data: &[u8]| {
+            if csv {
+                decode_copy_csv_format(data, &column_types, &params.delimiter, &params.null)
+            } else {
+                decode_copy_text_format(data, &column_types, &params.delimiter, &params.null)
+            }
+        };
+        let copy = self
+            .coord_client
+            .start_copy("from", copy_format_name(&params.format));
+
+        // Decode rows as soon as they are complete, so that only the
+        // incomplete trailing row, and not all of the raw data, is buffered.
+        let mut pending = Vec::new();
+        let mut rows = Vec::new();
+        let mut buffered_bytes = 0;
+        let mut ended = false;
         let mut next_state = State::Ready;
         loop {
-            let message = self.conn.recv().await?;
+            let message = tokio::select! {
+                message = self.conn.recv() => message?,
+                _ = self.coord_client.canceled() => {
+                    return self.error(self.query_canceled()).await;
+                }
+            };
             match message {
-                Some(FrontendMessage::CopyData(buf)) => data.extend(buf),
+                Some(FrontendMessage::CopyData(buf)) => {
+                    // Data after the end-of-copy marker is ignored.
+                    if ended {
+                        continue;
+                    }
+                    let received = buf.len();
+                    pending.extend(buf);
+                    let (complete, end) = message::split_copy_rows(&pending, csv);
+                    ended = end;
+                    let decoded = match decode(&pending[..complete]) {
+                        Ok(decoded) => decoded,
+                        Err(e) => {
+                            return self
+                                .error(ErrorResponse::error(
+                                    SqlState::BAD_COPY_FILE_FORMAT,
+                                    format!("{}", e),
+                                ))
+                                .await
+                        }
+                    };
+                    if ended {
+                        pending.clear();
+                    } else {
+                        pending.drain(..complete);
+                    }
+                    copy.record(decoded.len(), received);
+                    // The rows are buffered until the copy completes, so the
+                    // result size limits apply to their running total.
+                    buffered_bytes += decoded
+                        .iter()
+                        .map(|row| u64::cast_from(repr::datums_size(row.iter())))
+                        .sum::<u64>();
+                    rows.extend(decoded);
+                    if let Err(e) = self
+                        .coord_client
+                        .session()
+                        .vars()
+                        .check_result_totals(u64::cast_from(rows.len()), buffered_bytes)
+                    {
+                        return self
+                            .error(ErrorResponse::from_coord(Severity::Error, e))
+                            .await;
+                    }
+                }
                 Some(FrontendMessage::CopyDone) => break,
                 Some(FrontendMessage::CopyFail(err)) => {
                     return self
@@ -1482,21 +1858,17 @@ where
             }
         }
 
-        let column_types = typ
-            .column_types
-            .iter()
-            .map(|x| &x.scalar_type)
-            .map(pgrepr::Type::from)
-            .collect::<Vec<pgrepr::Type>>();
-
         if let State::Ready = next_state {
-            let rows = match decode_copy_text_format(
-                &data,
-                &column_types,
-                &params.delimiter,
-                &params.null,
-            ) {
-                Ok(rows) => rows,
+            // The last row need not end with a newline.
+            match decode(&pending) {
+                Ok(decoded) => {
+                    copy.record(decoded.len(), 0);
+                    buffered_bytes += decoded
+                        .iter()
+                        .map(|row| u64::cast_from(repr::datums_size(row.iter())))
+                        .sum::<u64>();
+                    rows.extend(decoded);
+                }
                 Err(e) => {
                     return self
                         .error(ErrorResponse::error(
@@ -1505,7 +1877,17 @@ where
                         ))
                         .await
                 }
-            };
+            }
+            if let Err(e) = self
+                .coord_client
+                .session()
+                .vars()
+                .check_result_totals(u64::cast_from(rows.len()), buffered_bytes)
+            {
+                return self
+                    .error(ErrorResponse::from_coord(Severity::Error, e))
+                    .await;
+            }
             let count = rows.len();
 
             if let Err(e) = self.coord_client.insert_rows(id, columns, rows).await {
@@ -1529,9 +1911,15 @@ where
             "cid={} error code={} message={}",
             self.coord_client.session().conn_id(),
             err.code.code(),
-            err.message
+            match self.conn.redaction() {
+                RedactionPolicy::None => err.message.as_str(),
+                RedactionPolicy::Literals | RedactionPolicy::Full => REDACTED_SQL,
+            }
         );
         let is_fatal = err.severity.is_fatal();
+        if let Some(timed_statement) = &mut self.timed_statement {
+            timed_statement.fail();
+        }
         self.conn.send(BackendMessage::ErrorResponse(err)).await?;
         let txn = self.coord_client.session().transaction();
         match txn {
@@ -1558,6 +1946,119 @@ where
         }
     }
 
+    /// Constructs a span that covers the execution of a statement, from
+    /// receipt of the statement until the last result is sent to the client.
+    ///
+    /// If the client has set the `traceparent` session variable, the span
+    /// joins the specified trace. If the statement carries labels, they are
+    /// recorded on the span.
+    fn statement_span(&mut self) -> Span {
+        let span = tracing::info_span!(
+            "statement",
+            traceparent = field::Empty,
+            labels = field::Empty
+        );
+        let traceparent = self.coord_client.session().vars().traceparent();
+        if !traceparent.is_empty() {
+            span.record("traceparent", &traceparent);
+        }
+        let labels = self.coord_client.statement_labels();
+        if !labels.is_empty() {
+            span.record("labels", &field::display(labels));
+        }
+        span
+    }
+
+    /// Stops timing the current statement, if any, logging it to the slow
+    /// query log if it was slow and recording its duration, disarms its
+    /// statement timer, and records that it is no longer executing, along
+    /// with its labels.
+    fn finish_timing(&mut self) {
+        if let Some(timed_statement) = self.timed_statement.take() {
+            timed_statement.finish();
+        }
+        self.statement_duration = None;
+        self.statement_timer = None;
+        self.active_statement = None;
+        self.user_statement = None;
+        self.labeled_statement = None;
+        self.coord_client
+            .user_session()
+            .add_bytes(self.unreported_bytes.swap(0, Ordering::Relaxed));
+    }
+
+    /// Constructs the error for a canceled statement, which names the
+    /// statement timeout as the cause if it was the statement timer that
+    /// canceled the statement.
+    fn query_canceled(&self) -> ErrorResponse {
+        let message = match &self.statement_timer {
+            Some(timer) if timer.fired() => "canceling statement due to statement timeout",
+            _ => "canceling statement due to user request",
+        };
+        ErrorResponse::error(SqlState::QUERY_CANCELED, message)
+    }
+
+    /// Tells the client that the server is draining, so that it can
+    /// reconnect to another server before this one shuts down. The client is
+    /// notified at most once.
+    async fn notify_draining(&mut self) -> Result<(), io::Error> {
+        self.draining = None;
+        let mut notice =
+            ErrorResponse::notice(SqlState::ADMIN_SHUTDOWN, "the server is shutting down");
+        notice.hint = Some("Reconnect to continue on another server.".into());
+        self.conn
+            .send_all(vec![
+                BackendMessage::ParameterStatus("mz_server_draining", "on".into()),
+                notice.into(),
+            ])
+            .await?;
+        self.conn.flush().await
+    }
+
+    /// Tells the client that the server entered or left maintenance mode.
+    async fn notify_maintenance(&mut self) -> Result<(), io::Error> {
+        let maintenance = self.maintenance_mode.get();
+        let notice = match &maintenance {
+            Some(maintenance) => maintenance_notice(maintenance),
+            None => ErrorResponse::notice(
+                SqlState::SUCCESSFUL_COMPLETION,
+                "the server is no longer in maintenance mode",
+            ),
+        };
+        self.conn
+            .send_all(vec![maintenance_status(&maintenance), notice.into()])
+            .await?;
+        self.maintenance = maintenance;
+        self.conn.flush().await
+    }
+
+    /// Returns how long the session may wait for the next message, if it is
+    /// idle in an explicit transaction and has an idle-in-transaction timeout.
+    fn idle_in_transaction_timeout(&mut self) -> Option<Duration> {
+        match self.coord_client.session().transaction() {
+            TransactionStatus::InTransaction(_) | TransactionStatus::Failed(_) => self
+                .coord_client
+                .session()
+                .vars()
+                .idle_in_transaction_session_timeout(),
+            _ => None,
+        }
+    }
+
+    /// Aborts the open transaction of a session that was idle in it for too
+    /// long and terminates the connection, as PostgreSQL does.
+    async fn idle_in_transaction_timed_out(&mut self) -> Result<State, io::Error> {
+        self.rollback_transaction().await;
+        self.conn
+            .send(ErrorResponse::fatal(
+                SqlState::IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+                "terminating connection due to idle-in-transaction timeout",
+            ))
+            .await?;
+        self.conn.flush().await?;
+        Ok(State::Done)
+    }
+
     async fn aborted_txn_error(&mut self) -> Result<State, io::Error> {
         self.conn
             .send(BackendMessage::ErrorResponse(ErrorResponse::error(
@@ -1576,6 +2077,36 @@ where
     }
 }
 
+/// Reports whether the server is in maintenance mode via the
+/// `mz_maintenance_mode` parameter.
+fn maintenance_status(maintenance: &Option<Maintenance>) -> BackendMessage {
+    let value = match maintenance {
+        Some(_) => "on",
+        None => "off",
+    };
+    BackendMessage::ParameterStatus("mz_maintenance_mode", value.into())
+}
+
+/// Tells the client that the server is in maintenance mode, and why.
+fn maintenance_notice(maintenance: &Maintenance) -> ErrorResponse {
+    let mut notice = ErrorResponse::notice(
+        SqlState::SUCCESSFUL_COMPLETION,
+        "the server is in maintenance mode; statements that modify the catalog or write data \
+         will be rejected",
+    );
+    notice.detail = maintenance.message.clone();
+    notice
+}
+
+/// Returns the name of a `COPY` format, as reported in the session listing.
+fn copy_format_name(format: &CopyFormat) -> &'static str {
+    match format {
+        CopyFormat::Text => "text",
+        CopyFormat::Csv => "csv",
+        CopyFormat::Binary => "binary",
+    }
+}
+
 fn pad_formats(formats: Vec<pgrepr::Format>, n: usize) -> Result<Vec<pgrepr::Format>, String> {
     match (formats.len(), n) {
         (0, e) => Ok(vec![pgrepr::Format::Text; e]),
@@ -1665,6 +2196,7 @@ fn is_txn_exit_stmt(stmt: Option<&Statement<Raw>>) -> bool {
 
 #[derive(Debug)]
 enum FetchResult {
-    Rows(Option<Vec<Row>>),
+    Rows(Option<Result<Vec<Row>, CoordError>>),
     Cancelled,
+    Idle,
 }
@@ -7,22 +7,35 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
-use futures::ready;
+use futures::{future, ready};
+use std::collections::HashMap;
 use std::fmt;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::task::{Context, Poll};
 
 use async_trait::async_trait;
-use log::trace;
+use log::{debug, info, trace};
 use openssl::ssl::{Ssl, SslContext};
+use postgres::error::SqlState;
 use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, Interest, ReadBuf, Ready};
+use tokio::sync::watch;
 use tokio_openssl::SslStream;
+use tracing::{field, Span};
 
+use coord::{Authorizer, MaintenanceMode, Quotas, StatementDurations, Transport, TransportMonitor};
 use ore::cast::CastFrom;
-use ore::netio::AsyncReady;
+use ore::metrics::UIntCounter;
+use ore::netio::{AsyncReady, TlsHandshakePool};
+use sql::parse::RedactionPolicy;
 
-use crate::codec::{self, FramedConn, ACCEPT_SSL_ENCRYPTION, REJECT_ENCRYPTION};
-use crate::message::FrontendStartupMessage;
+use crate::codec::{
+    self, BufferConfig, FramedConn, MessageTooLarge, ACCEPT_SSL_ENCRYPTION,
+    DEFAULT_MAX_MESSAGE_SIZE, REJECT_ENCRYPTION,
+};
+use crate::message::{ErrorResponse, FrontendStartupMessage};
 use crate::metrics::Metrics;
 use crate::protocol;
 
@@ -39,15 +52,248 @@ pub struct Config<'a> {
 
     /// The registry that the pg wire server uses to report metrics.
     pub metrics_registry: &'a ore::metrics::MetricsRegistry,
+    /// How to redact SQL text and other sensitive data in log messages.
+    pub log_redaction: RedactionPolicy,
+    /// How to size the buffers of each connection.
+    pub buffers: BufferConfig,
+    /// The maximum size of a message from a client, in bytes.
+    ///
+    /// A client that sends a larger message receives an error, and its
+    /// connection is closed.
+    pub max_message_size: usize,
+    /// Controls whether the server accepts new sessions.
+    pub gate: ConnectionGate,
+    /// Limits the number of sessions that the server serves at once, if
+    /// present.
+    pub session_limit: Option<SessionLimit>,
+    /// Reports whether the server is draining.
+    pub draining: Draining,
+    /// Counts the connections refused because the server is draining.
+    pub draining_rejections: UIntCounter,
+    /// Records how long the statements executed by each session take.
+    pub statement_durations: StatementDurations,
+    /// Decides which statements each session may execute, if present.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// Whether to notify established sessions when the server begins
+    /// draining, so that clients can reconnect to another server before this
+    /// one shuts down.
+    pub drain_notices: bool,
+    /// The notice to send to clients once they start a session.
+    pub connection_notice: ConnectionNotice,
+    /// Reports whether the server is in maintenance mode, so that sessions
+    /// can be notified when it is enabled or disabled.
+    pub maintenance_mode: MaintenanceMode,
+    /// The resource quotas to which each session is held.
+    pub session_quotas: Quotas,
+    /// Counts the sessions established over each transport.
+    pub transport_monitor: TransportMonitor,
+    /// The name of the server, if any, which is reported to clients once
+    /// they start a session and in the context of every error.
+    pub server_name: Option<String>,
 }
 
-/// Configures a server's TLS encryption and authentication.
+/// Controls whether a [`Server`] accepts new sessions.
+///
+/// A gate is open when it is constructed, and is closed when the server is low
+/// on memory. While the gate is closed, clients that attempt to start a
+/// session receive an `out_of_memory` error. Sessions that are already
+/// established are unaffected, as are cancellation requests, which may help to
+/// resolve whatever condition caused the gate to close.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionGate {
+    closed: Arc<AtomicBool>,
+}
+
+impl ConnectionGate {
+    /// Constructs a new, open gate.
+    pub fn new() -> ConnectionGate {
+        ConnectionGate::default()
+    }
+
+    /// Opens the gate. Returns whether the gate was closed.
+    pub fn open(&self) -> bool {
+        self.closed.swap(false, Ordering::SeqCst)
+    }
+
+    /// Closes the gate. Returns whether the gate was open.
+    pub fn close(&self) -> bool {
+        !self.closed.swap(true, Ordering::SeqCst)
+    }
+
+    /// Reports whether the gate is open.
+    pub fn is_open(&self) -> bool {
+        !self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// Limits the number of sessions that a [`Server`] serves at once.
+///
+/// Some sessions are reserved for superusers, so that an administrator can
+/// always connect to investigate, even when clients have taken every other
+/// session. Superusers may take any session, while other users may take only
+/// the unreserved sessions. Cancellation requests are not limited.
+///
+/// Clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct SessionLimit {
+    max_sessions: usize,
+    reserved_sessions: usize,
+    superusers: Arc<Vec<String>>,
+    active: Arc<AtomicUsize>,
+}
+
+impl SessionLimit {
+    /// Constructs a limit of `max_sessions` sessions, of which
+    /// `reserved_sessions` are reserved for the users named in `superusers`.
+    pub fn new(
+        max_sessions: usize,
+        reserved_sessions: usize,
+        superusers: Vec<String>,
+    ) -> SessionLimit {
+        SessionLimit {
+            max_sessions,
+            reserved_sessions: reserved_sessions.min(max_sessions),
+            superusers: Arc::new(superusers),
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Reports whether `user` is a superuser.
+    pub fn is_superuser(&self, user: &str) -> bool {
+        self.superusers.iter().any(|u| u == user)
+    }
+
+    /// Takes a session for `user`, if one is available to it.
+    ///
+    /// The session counts against the limit until the returned slot is
+    /// dropped.
+    pub fn acquire(&self, user: &str) -> Option<SessionSlot> {
+        let max = if self.is_superuser(user) {
+            self.max_sessions
+        } else {
+            self.max_sessions - self.reserved_sessions
+        };
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < max).then(|| active + 1)
+            })
+            .ok()?;
+        Some(SessionSlot {
+            active: Arc::clone(&self.active),
+        })
+    }
+
+    /// Returns the number of sessions in progress.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// A session counted against a [`SessionLimit`], until dropped.
 #[derive(Debug)]
+pub struct SessionSlot {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reports whether a server is draining.
+///
+/// A draining server still accepts connections, so that clients can cancel
+/// the queries of sessions that are already established, but clients that
+/// attempt to start a session receive an `admin_shutdown` error.
+#[derive(Debug, Clone)]
+pub struct Draining {
+    draining: Arc<AtomicBool>,
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+}
+
+impl Draining {
+    /// Constructs a new signal that reports that the server is not draining.
+    pub fn new() -> Draining {
+        let (tx, rx) = watch::channel(false);
+        Draining {
+            draining: Arc::new(AtomicBool::new(false)),
+            tx: Arc::new(tx),
+            rx,
+        }
+    }
+
+    /// Marks the server as draining. Returns whether the server was not
+    /// already draining.
+    pub fn start(&self) -> bool {
+        let started = !self.draining.swap(true, Ordering::SeqCst);
+        if started {
+            // Sending cannot fail, as `self` holds a receiver.
+            let _ = self.tx.send(true);
+        }
+        started
+    }
+
+    /// Reports whether the server is draining.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the server is draining.
+    pub async fn wait(&self) {
+        let mut rx = self.rx.clone();
+        while !*rx.borrow() {
+            if rx.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Default for Draining {
+    fn default() -> Draining {
+        Draining::new()
+    }
+}
+
+/// The notice that a [`Server`] sends to clients once they start a session,
+/// e.g. to remind them which environment they are connected to.
+///
+/// Clones share the same notice, which may be replaced while the server is
+/// running. Only sessions that start after the notice is replaced see the new
+/// notice.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionNotice {
+    notice: Arc<RwLock<Option<String>>>,
+}
+
+impl ConnectionNotice {
+    /// Constructs a new holder with no notice.
+    pub fn new() -> ConnectionNotice {
+        ConnectionNotice::default()
+    }
+
+    /// Replaces the notice. If `notice` is `None`, clients receive no notice.
+    pub fn set(&self, notice: Option<String>) {
+        *self.notice.write().expect("lock poisoned") = notice;
+    }
+
+    /// Returns the current notice, if any.
+    pub fn get(&self) -> Option<String> {
+        self.notice.read().expect("lock poisoned").clone()
+    }
+}
+
+/// Configures a server's TLS encryption and authentication.
+#[derive(Debug, Clone)]
 pub struct TlsConfig {
     /// The SSL context used to manage incoming TLS negotiations.
     pub context: SslContext,
     /// The TLS mode.
     pub mode: TlsMode,
+    /// The pool on which TLS handshakes are performed.
+    pub handshake_pool: TlsHandshakePool,
 }
 
 /// Specifies how strictly to enforce TLS encryption and authentication.
@@ -65,6 +311,21 @@ pub struct Server {
     tls: Option<TlsConfig>,
     coord_client: coord::Client,
     metrics: Metrics,
+    log_redaction: RedactionPolicy,
+    buffers: BufferConfig,
+    max_message_size: usize,
+    gate: ConnectionGate,
+    session_limit: Option<SessionLimit>,
+    draining: Draining,
+    draining_rejections: UIntCounter,
+    statement_durations: StatementDurations,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    drain_notices: bool,
+    connection_notice: ConnectionNotice,
+    maintenance_mode: MaintenanceMode,
+    session_quotas: Quotas,
+    transport_monitor: TransportMonitor,
+    server_name: Option<String>,
 }
 
 impl Server {
@@ -74,92 +335,351 @@ impl Server {
             metrics: Metrics::register_into(config.metrics_registry),
             tls: config.tls,
             coord_client: config.coord_client,
+            log_redaction: config.log_redaction,
+            buffers: config.buffers,
+            max_message_size: config.max_message_size,
+            gate: config.gate,
+            session_limit: config.session_limit,
+            draining: config.draining,
+            draining_rejections: config.draining_rejections,
+            statement_durations: config.statement_durations,
+            authorizer: config.authorizer,
+            drain_notices: config.drain_notices,
+            connection_notice: config.connection_notice,
+            maintenance_mode: config.maintenance_mode,
+            session_quotas: config.session_quotas,
+            transport_monitor: config.transport_monitor,
+            server_name: config.server_name,
         }
     }
 
-    pub async fn handle_connection<A>(&self, conn: A) -> Result<(), anyhow::Error>
+    /// Handles a connection from a client.
+    ///
+    /// The `correlation_id` identifies the connection in log messages and is
+    /// reported to the client alongside every error. The connection is
+    /// expected to be handled within a span that has `user`, `conn_id`, and
+    /// `transport` fields, which are recorded once known. The `peer` is the
+    /// address of the client, if known.
+    pub async fn handle_connection<A>(
+        &self,
+        conn: A,
+        correlation_id: String,
+        peer: Option<SocketAddr>,
+    ) -> Result<(), anyhow::Error>
     where
         A: AsyncRead + AsyncWrite + AsyncReady + Send + Sync + Unpin + fmt::Debug + 'static,
     {
         let mut coord_client = self.coord_client.new_conn()?;
         let conn_id = coord_client.conn_id();
-        let mut conn = Conn::Unencrypted(MeteredConn {
-            metrics: &self.metrics,
+        Span::current().record("conn_id", &conn_id);
+        let unreported_bytes = Arc::new(AtomicU64::new(0));
+        let conn = Conn::Unencrypted(MeteredConn {
+            metrics: self.metrics.clone(),
+            unreported_bytes: Arc::clone(&unreported_bytes),
             inner: conn,
         });
-        loop {
-            let message = codec::decode_startup(&mut conn).await?;
-
-            match &message {
-                Some(message) => trace!("cid={} recv={:?}", conn_id, message),
-                None => trace!("cid={} recv=<eof>", conn_id),
+        let negotiated = negotiate(conn, self.tls.as_ref(), conn_id, self.max_message_size).await;
+        if let Err(e) = &negotiated {
+            if let Some(e) = e.downcast_ref().and_then(MessageTooLarge::from_io) {
+                debug!("refusing connection: oversized startup message: {}", e);
+                self.metrics.oversized_messages.inc();
             }
+        }
+        match negotiated? {
+            // Clients sometimes hang up during the startup sequence, e.g.
+            // because they receive an unacceptable response to an
+            // `SslRequest`. This is considered a graceful termination.
+            None => Ok(()),
 
-            conn = match message {
-                // Clients sometimes hang up during the startup sequence, e.g.
-                // because they receive an unacceptable response to an
-                // `SslRequest`. This is considered a graceful termination.
-                None => return Ok(()),
-
-                Some(FrontendStartupMessage::Startup { version, params }) => {
-                    let mut conn = FramedConn::new(conn_id, conn);
-                    protocol::run(protocol::RunParams {
-                        tls_mode: self.tls.as_ref().map(|tls| tls.mode),
-                        coord_client,
-                        conn: &mut conn,
-                        version,
-                        params,
-                        metrics: &self.metrics,
-                    })
+            Some((conn, Startup::Startup { version, params })) => {
+                let transport = match &conn {
+                    Conn::Unencrypted(_) => Transport::Plaintext,
+                    Conn::Ssl(conn) => Transport::Tls {
+                        version: conn.ssl().version_str().into(),
+                        cipher: conn
+                            .ssl()
+                            .current_cipher()
+                            .map_or("unknown", |cipher| cipher.name())
+                            .into(),
+                    },
+                };
+                Span::current().record("transport", &field::display(&transport));
+                // The connection counts toward its transport until it closes.
+                let _transport_guard = self.transport_monitor.connected(&transport);
+                let mut conn = FramedConn::new(
+                    conn_id,
+                    Some(correlation_id.clone()),
+                    self.server_name.clone(),
+                    self.log_redaction,
+                    self.buffers,
+                    self.max_message_size,
+                    conn,
+                );
+                if let Some(user) = params.get("user") {
+                    Span::current().record("user", &user.as_str());
+                }
+                if self.draining.is_draining() {
+                    info!("refusing connection: server is draining");
+                    self.metrics.refused_connections.inc();
+                    self.draining_rejections.inc();
+                    conn.send(ErrorResponse::fatal(
+                        SqlState::ADMIN_SHUTDOWN,
+                        "the server is shutting down and is not accepting new connections",
+                    ))
                     .await?;
                     conn.flush().await?;
                     return Ok(());
                 }
-
-                Some(FrontendStartupMessage::CancelRequest {
-                    conn_id,
-                    secret_key,
-                }) => {
-                    coord_client.cancel_request(conn_id, secret_key).await;
-                    // For security, the client is not told whether the cancel
-                    // request succeeds or fails.
+                if !self.gate.is_open() {
+                    debug!("refusing connection: server is not accepting new sessions");
+                    self.metrics.refused_connections.inc();
+                    conn.send(ErrorResponse::fatal(
+                        SqlState::OUT_OF_MEMORY,
+                        "the server is low on memory and is not accepting new connections",
+                    ))
+                    .await?;
+                    conn.flush().await?;
                     return Ok(());
                 }
-
-                Some(FrontendStartupMessage::SslRequest) => match (conn, &self.tls) {
-                    (Conn::Unencrypted(mut conn), Some(tls)) => {
-                        trace!("cid={} send=AcceptSsl", conn_id);
-                        conn.write_all(&[ACCEPT_SSL_ENCRYPTION]).await?;
-                        let mut ssl_stream = SslStream::new(Ssl::new(&tls.context)?, conn)?;
-                        if let Err(e) = Pin::new(&mut ssl_stream).accept().await {
-                            let _ = ssl_stream.get_mut().shutdown().await;
-                            return Err(e.into());
+                // The session holds its slot until it ends.
+                let _session_slot = match &self.session_limit {
+                    None => None,
+                    Some(session_limit) => {
+                        let user = params.get("user").map(|u| u.as_str()).unwrap_or("");
+                        match session_limit.acquire(user) {
+                            Some(slot) => Some(slot),
+                            None => {
+                                debug!("refusing connection: too many sessions");
+                                self.metrics.refused_connections.inc();
+                                // As in PostgreSQL, other users are told if only
+                                // reserved sessions remain.
+                                let message = if session_limit.is_superuser(user)
+                                    || session_limit.active() >= session_limit.max_sessions
+                                {
+                                    "sorry, too many clients already"
+                                } else {
+                                    "remaining connection slots are reserved for superusers"
+                                };
+                                conn.send(ErrorResponse::fatal(
+                                    SqlState::TOO_MANY_CONNECTIONS,
+                                    message,
+                                ))
+                                .await?;
+                                conn.flush().await?;
+                                return Ok(());
+                            }
                         }
-                        Conn::Ssl(ssl_stream)
-                    }
-                    (mut conn, _) => {
-                        trace!("cid={} send=RejectSsl", conn_id);
-                        conn.write_all(&[REJECT_ENCRYPTION]).await?;
-                        conn
                     }
+                };
+                let user = params.get("user").map(|u| u.as_str()).unwrap_or("");
+                self.transport_monitor
+                    .session_established(&transport, peer, user);
+                protocol::run(protocol::RunParams {
+                    tls_mode: self.tls.as_ref().map(|tls| tls.mode),
+                    transport,
+                    coord_client,
+                    conn: &mut conn,
+                    version,
+                    params,
+                    correlation_id,
+                    metrics: &self.metrics,
+                    statement_durations: self.statement_durations.clone(),
+                    authorizer: self.authorizer.clone(),
+                    draining: match self.drain_notices {
+                        true => Some(self.draining.clone()),
+                        false => None,
+                    },
+                    connection_notice: self.connection_notice.get(),
+                    maintenance_mode: self.maintenance_mode.clone(),
+                    session_quotas: self.session_quotas.clone(),
+                    server_name: self.server_name.as_deref(),
+                    unreported_bytes,
+                })
+                .await?;
+                conn.flush().await?;
+                Ok(())
+            }
+
+            Some((
+                _,
+                Startup::CancelRequest {
+                    conn_id,
+                    secret_key,
                 },
+            )) => {
+                let outcome = coord_client.cancel_request(conn_id, secret_key).await;
+                debug!("cancel request for connection {}: {:?}", conn_id, outcome);
+                self.metrics
+                    .cancel_requests
+                    .with_label_values(&[outcome.as_str()])
+                    .inc();
+                // For security, the client is not told whether the cancel
+                // request succeeds or fails.
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A server that speaks just enough of the pgwire protocol to tell clients
+/// that the database system is starting up.
+///
+/// While the coordinator starts up, which can take a while, connections are
+/// handled by this server, so that clients learn why they cannot connect
+/// rather than having their connections refused.
+#[derive(Debug)]
+pub struct StartingServer {
+    tls: Option<TlsConfig>,
+    server_name: Option<String>,
+}
 
-                Some(FrontendStartupMessage::GssEncRequest) => {
-                    trace!("cid={} send=RejectGssEnc", conn_id);
+impl StartingServer {
+    /// Constructs a new starting server.
+    ///
+    /// Clients may negotiate TLS according to `tls`, so that clients that
+    /// require TLS see the error too. The `server_name`, if any, is reported
+    /// in the context of the error.
+    pub fn new(tls: Option<TlsConfig>, server_name: Option<String>) -> StartingServer {
+        StartingServer { tls, server_name }
+    }
+
+    /// Handles a connection from a client by completing the startup sequence
+    /// with an error.
+    ///
+    /// The `correlation_id` is reported to the client alongside the error.
+    pub async fn handle_connection<A>(
+        &self,
+        conn: A,
+        correlation_id: String,
+    ) -> Result<(), anyhow::Error>
+    where
+        A: AsyncRead + AsyncWrite + Send + Sync + Unpin + fmt::Debug + 'static,
+    {
+        // Connections to a starting server are not assigned IDs.
+        let conn_id = 0;
+        match negotiate(
+            Conn::Unencrypted(conn),
+            self.tls.as_ref(),
+            conn_id,
+            DEFAULT_MAX_MESSAGE_SIZE,
+        )
+        .await?
+        {
+            None => Ok(()),
+            Some((conn, Startup::Startup { .. })) => {
+                // The redaction policy is irrelevant, as the only message
+                // sent contains no SQL.
+                let mut conn = FramedConn::new(
+                    conn_id,
+                    Some(correlation_id),
+                    self.server_name.clone(),
+                    RedactionPolicy::Full,
+                    BufferConfig::default(),
+                    DEFAULT_MAX_MESSAGE_SIZE,
+                    conn,
+                );
+                conn.send(ErrorResponse::fatal(
+                    SqlState::CANNOT_CONNECT_NOW,
+                    "the database system is starting up",
+                ))
+                .await?;
+                conn.flush().await?;
+                Ok(())
+            }
+            // There are no queries to cancel yet.
+            Some((_, Startup::CancelRequest { .. })) => Ok(()),
+        }
+    }
+}
+
+/// A message that ends the encryption negotiation at the beginning of a
+/// connection.
+enum Startup {
+    Startup {
+        version: i32,
+        params: HashMap<String, String>,
+    },
+    CancelRequest {
+        conn_id: u32,
+        secret_key: u32,
+    },
+}
+
+/// Negotiates encryption with the client according to `tls`.
+///
+/// Returns the connection, which is encrypted if the client requested
+/// encryption and `tls` is present, along with the message that ended the
+/// negotiation. Returns `None` if the client hangs up first. Startup
+/// messages larger than `max_message_size` bytes are rejected.
+async fn negotiate<A>(
+    mut conn: Conn<A>,
+    tls: Option<&TlsConfig>,
+    conn_id: u32,
+    max_message_size: usize,
+) -> Result<Option<(Conn<A>, Startup)>, anyhow::Error>
+where
+    A: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    loop {
+        let message = codec::decode_startup(&mut conn, max_message_size).await?;
+
+        match &message {
+            Some(message) => trace!("cid={} recv={:?}", conn_id, message),
+            None => trace!("cid={} recv=<eof>", conn_id),
+        }
+
+        conn = match message {
+            None => return Ok(None),
+
+            Some(FrontendStartupMessage::Startup { version, params }) => {
+                return Ok(Some((conn, Startup::Startup { version, params })));
+            }
+
+            Some(FrontendStartupMessage::CancelRequest {
+                conn_id,
+                secret_key,
+            }) => {
+                return Ok(Some((
+                    conn,
+                    Startup::CancelRequest {
+                        conn_id,
+                        secret_key,
+                    },
+                )));
+            }
+
+            Some(FrontendStartupMessage::SslRequest) => match (conn, tls) {
+                (Conn::Unencrypted(mut conn), Some(tls)) => {
+                    trace!("cid={} send=AcceptSsl", conn_id);
+                    conn.write_all(&[ACCEPT_SSL_ENCRYPTION]).await?;
+                    let ssl = Ssl::new(&tls.context)?;
+                    Conn::Ssl(tls.handshake_pool.accept(ssl, conn).await?)
+                }
+                (mut conn, _) => {
+                    trace!("cid={} send=RejectSsl", conn_id);
                     conn.write_all(&[REJECT_ENCRYPTION]).await?;
                     conn
                 }
+            },
+
+            Some(FrontendStartupMessage::GssEncRequest) => {
+                trace!("cid={} send=RejectGssEnc", conn_id);
+                conn.write_all(&[REJECT_ENCRYPTION]).await?;
+                conn
             }
         }
     }
 }
 
-pub struct MeteredConn<'a, A> {
+pub struct MeteredConn<A> {
     inner: A,
-    metrics: &'a Metrics,
+    metrics: Metrics,
+    /// The number of bytes sent that have not yet been reported toward the
+    /// statistics of the session's user.
+    unreported_bytes: Arc<AtomicU64>,
 }
 
-impl<'a, A> AsyncRead for MeteredConn<'a, A>
+impl<A> AsyncRead for MeteredConn<A>
 where
     A: AsyncRead + AsyncWrite + Unpin,
 {
@@ -172,7 +692,7 @@ where
     }
 }
 
-impl<'a, A> AsyncWrite for MeteredConn<'a, A>
+impl<A> AsyncWrite for MeteredConn<A>
 where
     A: AsyncRead + AsyncWrite + Unpin,
 {
@@ -183,6 +703,8 @@ where
     ) -> Poll<io::Result<usize>> {
         let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
         self.metrics.bytes_sent.inc_by(u64::cast_from(n));
+        self.unreported_bytes
+            .fetch_add(u64::cast_from(n), Ordering::Relaxed);
         Poll::Ready(Ok(n))
     }
 
@@ -196,7 +718,7 @@ where
 }
 
 #[async_trait]
-impl<'a, A> AsyncReady for MeteredConn<'a, A>
+impl<A> AsyncReady for MeteredConn<A>
 where
     A: AsyncRead + AsyncWrite + AsyncReady + Sync + Unpin,
 {
@@ -9,14 +9,20 @@
 
 //! Provides convenience functions for working with upstream Postgres sources from the `sql` package.
 
+use std::error::Error;
 use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
 use anyhow::{anyhow, bail};
 use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
 use postgres_openssl::MakeTlsConnector;
-use tokio_postgres::config::{ReplicationMode, SslMode};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_postgres::config::{Host, ReplicationMode, SslMode};
+use tokio_postgres::tls::MakeTlsConnect;
 use tokio_postgres::types::Type as PgType;
-use tokio_postgres::{Client, Config};
+use tokio_postgres::{Client, Config, Socket};
 
 use sql_parser::ast::display::{AstDisplay, AstFormatter};
 use sql_parser::ast::Ident;
@@ -145,21 +151,176 @@ pub fn make_tls(config: &Config) -> Result<MakeTlsConnector, anyhow::Error> {
     Ok(tls_connector)
 }
 
+/// An error binding the local end of an outbound connection to the configured
+/// outbound address.
+///
+/// Retrying the connection will not help, as the address is misconfigured.
+#[derive(Debug)]
+pub struct BindError {
+    /// The address to which the connection was to be bound.
+    pub addr: IpAddr,
+    /// The underlying error.
+    pub error: io::Error,
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "binding outbound connection to {}: ", self.addr)?;
+        match self.error.kind() {
+            io::ErrorKind::AddrNotAvailable => {
+                f.write_str("address is not assigned to any interface")
+            }
+            _ => write!(f, "{}", self.error),
+        }
+    }
+}
+
+// The underlying error is included in the display of the error, and so is
+// not reported as its source.
+impl Error for BindError {}
+
+/// Connects to the database described by `config`, and spawns a task to drive
+/// the connection.
+///
+/// If `bind_addr` is present, the local end of the connection is bound to that
+/// address before connecting. A `bind_addr` that cannot be bound, e.g.,
+/// because it is not assigned to any interface, is reported as a
+/// [`BindError`].
+pub async fn connect<T>(
+    config: &Config,
+    mut tls: T,
+    bind_addr: Option<IpAddr>,
+) -> Result<Client, anyhow::Error>
+where
+    T: MakeTlsConnect<Socket> + MakeTlsConnect<TcpStream>,
+    <T as MakeTlsConnect<Socket>>::Stream: Send + 'static,
+    <T as MakeTlsConnect<TcpStream>>::Stream: Send + 'static,
+    <T as MakeTlsConnect<TcpStream>>::Error: Error + Send + Sync + 'static,
+{
+    let bind_addr = match bind_addr {
+        None => {
+            let (client, connection) = config.connect(tls).await?;
+            tokio::spawn(connection);
+            return Ok(client);
+        }
+        Some(bind_addr) => bind_addr,
+    };
+
+    // Like libpq, try each host in turn, returning the first error if none
+    // can be reached.
+    let ports = config.get_ports();
+    let mut first_error = None;
+    for (i, host) in config.get_hosts().iter().enumerate() {
+        let host = match host {
+            Host::Tcp(host) => host,
+            #[cfg(unix)]
+            Host::Unix(path) => bail!(
+                "cannot bind connection to Unix socket {} to outbound address {}",
+                path.display(),
+                bind_addr
+            ),
+        };
+        let port = ports
+            .get(i)
+            .or_else(|| ports.first())
+            .copied()
+            .unwrap_or(5432);
+        let stream = match connect_tcp(host, port, bind_addr, config.get_connect_timeout()).await {
+            Ok(stream) => stream,
+            Err(e) if e.is::<BindError>() => return Err(e),
+            Err(e) => {
+                first_error.get_or_insert(e);
+                continue;
+            }
+        };
+        let tls = MakeTlsConnect::<TcpStream>::make_tls_connect(&mut tls, host)?;
+        match config.connect_raw(stream, tls).await {
+            Ok((client, connection)) => {
+                tokio::spawn(connection);
+                return Ok(client);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e.into());
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| anyhow!("no host specified")))
+}
+
+/// Opens a TCP connection to `host` on `port` whose local end is bound to
+/// `bind_addr`.
+async fn connect_tcp(
+    host: &str,
+    port: u16,
+    bind_addr: IpAddr,
+    timeout: Option<&Duration>,
+) -> Result<TcpStream, anyhow::Error> {
+    let mut first_error = None;
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        // A socket can only be bound to an address of its own family.
+        if addr.is_ipv4() != bind_addr.is_ipv4() {
+            continue;
+        }
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket
+            .bind(SocketAddr::new(bind_addr, 0))
+            .map_err(|error| BindError {
+                addr: bind_addr,
+                error,
+            })?;
+        let res = match timeout {
+            None => socket.connect(addr).await,
+            Some(timeout) => match tokio::time::timeout(*timeout, socket.connect(addr)).await {
+                Ok(res) => res,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection timed out",
+                )),
+            },
+        };
+        match res {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) => Err(e.into()),
+        None => bail!(
+            "{} has no {} address reachable from outbound address {}",
+            host,
+            if bind_addr.is_ipv4() { "IPv4" } else { "IPv6" },
+            bind_addr
+        ),
+    }
+}
+
 /// Fetches table schema information from an upstream Postgres source for all tables that are part
 /// of a publication, given a connection string and the publication name.
 ///
+/// If `bind_addr` is present, the connection is bound to that local address,
+/// as described in [`connect`].
+///
 /// # Errors
 ///
 /// - Invalid connection string, user information, or user permissions.
 /// - Upstream publication does not exist or contains invalid values.
+/// - `bind_addr` cannot be bound.
 pub async fn publication_info(
     conn: &str,
     publication: &str,
+    bind_addr: Option<IpAddr>,
 ) -> Result<Vec<TableInfo>, anyhow::Error> {
     let config = conn.parse()?;
     let tls = make_tls(&config)?;
-    let (client, connection) = config.connect(tls).await?;
-    tokio::spawn(connection);
+    let client = connect(&config, tls, bind_addr).await?;
 
     let tables = client
         .query(
@@ -244,13 +405,16 @@ pub async fn publication_info(
     Ok(table_infos)
 }
 
-pub async fn drop_replication_slots(conn: &str, slots: &[String]) -> Result<(), anyhow::Error> {
+pub async fn drop_replication_slots(
+    conn: &str,
+    slots: &[String],
+    bind_addr: Option<IpAddr>,
+) -> Result<(), anyhow::Error> {
     let config = conn.parse()?;
     let tls = make_tls(&config)?;
-    let (client, connection) = tokio_postgres::connect(&conn, tls).await?;
-    tokio::spawn(connection);
+    let client = connect(&config, tls, bind_addr).await?;
 
-    let replication_client = connect_replication(conn).await?;
+    let replication_client = connect_replication(conn, bind_addr).await?;
     for slot in slots {
         let rows = client
             .query(
@@ -281,13 +445,15 @@ pub async fn drop_replication_slots(conn: &str, slots: &[String]) -> Result<(),
 }
 
 /// Starts a replication connection to the upstream database
-pub async fn connect_replication(conn: &str) -> Result<Client, anyhow::Error> {
+///
+/// If `bind_addr` is present, the connection is bound to that local address,
+/// as described in [`connect`].
+pub async fn connect_replication(
+    conn: &str,
+    bind_addr: Option<IpAddr>,
+) -> Result<Client, anyhow::Error> {
     let mut config: Config = conn.parse()?;
     let tls = make_tls(&config)?;
-    let (client, conn) = config
-        .replication_mode(ReplicationMode::Logical)
-        .connect(tls)
-        .await?;
-    tokio::spawn(conn);
-    Ok(client)
+    config.replication_mode(ReplicationMode::Logical);
+    connect(&config, tls, bind_addr).await
 }
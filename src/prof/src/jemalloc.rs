@@ -11,8 +11,10 @@
 //!
 //! (1) Turn jemalloc profiling on and off, and dump heap profiles (`PROF_CTL`)
 //! (2) Parse jemalloc heap files and make them into a hierarchical format (`parse_jeheap` and `collate_stacks`)
+//! (3) Tune the allocator and return unused memory to the OS (`AllocatorSettings` and `purge`)
 
 use std::os::unix::ffi::OsStrExt;
+use std::ptr;
 use std::sync::Arc;
 use std::{ffi::CString, io::BufRead, time::Instant};
 use tokio::sync::Mutex;
@@ -20,7 +22,7 @@ use tokio::sync::Mutex;
 use anyhow::bail;
 use lazy_static::lazy_static;
 use tempfile::NamedTempFile;
-use tikv_jemalloc_ctl::{epoch, raw, stats};
+use tikv_jemalloc_ctl::{arenas, background_thread, epoch, raw, stats};
 
 use super::{ProfStartTime, StackProfile, WeightedStack};
 
@@ -120,7 +122,12 @@ pub fn parse_jeheap<R: BufRead>(r: R) -> anyhow::Result<StackProfile> {
     Ok(profile)
 }
 
+/// The index that refers to all arenas at once in `arena.<i>.*` controls:
+/// http://jemalloc.net/jemalloc.3.html#arena.i.purge
+const MALLCTL_ARENAS_ALL: usize = 4096;
+
 // See stats.{allocated, active, ...} in http://jemalloc.net/jemalloc.3.html for details
+#[derive(Debug, Clone, Copy)]
 pub struct JemallocStats {
     pub active: usize,
     pub allocated: usize,
@@ -191,13 +198,103 @@ impl JemallocProfCtl {
     }
 
     pub fn stats(&self) -> anyhow::Result<JemallocStats> {
-        epoch::advance()?;
-        Ok(JemallocStats {
-            active: stats::active::read()?,
-            allocated: stats::allocated::read()?,
-            metadata: stats::metadata::read()?,
-            resident: stats::resident::read()?,
-            retained: stats::retained::read()?,
+        stats()
+    }
+}
+
+/// Reads allocation statistics.
+///
+/// Unlike [`JemallocProfCtl::stats`], does not require memory profiling to be
+/// enabled.
+pub fn stats() -> anyhow::Result<JemallocStats> {
+    epoch::advance()?;
+    Ok(JemallocStats {
+        active: stats::active::read()?,
+        allocated: stats::allocated::read()?,
+        metadata: stats::metadata::read()?,
+        resident: stats::resident::read()?,
+        retained: stats::retained::read()?,
+    })
+}
+
+/// Settings of the allocator that affect how much memory it retains.
+// See opt.background_thread, arenas.dirty_decay_ms, arenas.muzzy_decay_ms and
+// opt.narenas in http://jemalloc.net/jemalloc.3.html for details
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorSettings {
+    /// Whether background threads purge unused dirty and muzzy pages.
+    pub background_thread: bool,
+    /// How long unused dirty pages are retained before they are purged, in
+    /// milliseconds, or -1 to never purge them.
+    pub dirty_decay_ms: isize,
+    /// How long unused muzzy pages are retained before they are purged, in
+    /// milliseconds, or -1 to never purge them.
+    pub muzzy_decay_ms: isize,
+    /// The number of arenas.
+    ///
+    /// Fixed when the allocator initializes, and so cannot be changed with
+    /// [`AllocatorSettings::apply`].
+    pub narenas: u32,
+}
+
+impl AllocatorSettings {
+    /// Reads the allocator's current settings.
+    pub fn read() -> Result<AllocatorSettings, tikv_jemalloc_ctl::Error> {
+        Ok(AllocatorSettings {
+            background_thread: background_thread::read()?,
+            // SAFETY: "arenas.dirty_decay_ms" is documented as being readable and returning a ssize_t:
+            // http://jemalloc.net/jemalloc.3.html#arenas.dirty_decay_ms
+            dirty_decay_ms: unsafe { raw::read(b"arenas.dirty_decay_ms\0") }?,
+            // SAFETY: "arenas.muzzy_decay_ms" is documented as being readable and returning a ssize_t:
+            // http://jemalloc.net/jemalloc.3.html#arenas.muzzy_decay_ms
+            muzzy_decay_ms: unsafe { raw::read(b"arenas.muzzy_decay_ms\0") }?,
+            narenas: arenas::narenas::read()?,
         })
     }
+
+    /// Applies the settings to the allocator, including to arenas that are
+    /// already in use.
+    pub fn apply(&self) -> Result<(), tikv_jemalloc_ctl::Error> {
+        background_thread::write(self.background_thread)?;
+        // SAFETY: "arenas.{dirty,muzzy}_decay_ms" are documented as being writable and taking a ssize_t:
+        // http://jemalloc.net/jemalloc.3.html#arenas.dirty_decay_ms
+        unsafe { raw::write(b"arenas.dirty_decay_ms\0", self.dirty_decay_ms) }?;
+        unsafe { raw::write(b"arenas.muzzy_decay_ms\0", self.muzzy_decay_ms) }?;
+        // The above only applies to arenas that are initialized from now on.
+        for i in 0..self.narenas {
+            let dirty = format!("arena.{}.dirty_decay_ms\0", i);
+            let muzzy = format!("arena.{}.muzzy_decay_ms\0", i);
+            // SAFETY: "arena.<i>.{dirty,muzzy}_decay_ms" are documented as being writable and taking a ssize_t:
+            // http://jemalloc.net/jemalloc.3.html#arena.i.dirty_decay_ms
+            //
+            // Writing fails for arenas that have not been initialized, which
+            // is fine, as they will pick up the new defaults when they are.
+            let _ = unsafe { raw::write(dirty.as_bytes(), self.dirty_decay_ms) };
+            let _ = unsafe { raw::write(muzzy.as_bytes(), self.muzzy_decay_ms) };
+        }
+        Ok(())
+    }
+}
+
+/// Returns all unused dirty and muzzy pages in all arenas to the OS.
+pub fn purge() -> anyhow::Result<()> {
+    let name = CString::new(format!("arena.{}.purge", MALLCTL_ARENAS_ALL)).unwrap();
+    // SAFETY: "arena.<i>.purge" is documented as taking neither input nor
+    // output: http://jemalloc.net/jemalloc.3.html#arena.i.purge
+    //
+    // The raw interface of tikv_jemalloc_ctl always passes an input or an
+    // output, which the control rejects, so the control is invoked directly.
+    let ret = unsafe {
+        tikv_jemalloc_sys::mallctl(
+            name.as_ptr(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 {
+        bail!("purging arenas: {}", std::io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
 }
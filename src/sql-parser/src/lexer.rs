@@ -35,6 +35,7 @@
 use std::char;
 use std::convert::TryFrom;
 use std::fmt;
+use std::ops::Range;
 
 use ore::lex::LexBuf;
 use ore::str::StrExt;
@@ -103,6 +104,18 @@ macro_rules! bail {
 /// See the module documentation for more information about the lexical
 /// structure of SQL.
 pub fn lex(query: &str) -> Result<Vec<(Token, usize)>, ParserError> {
+    Ok(lex_ranges(query)?
+        .into_iter()
+        .map(|(token, range)| (token, range.start))
+        .collect())
+}
+
+/// Like [`lex`], but returns the byte range that each token occupies in the
+/// input string.
+///
+/// The range of a string literal may include trailing whitespace that the
+/// lexer consumed while looking for an adjacent string literal.
+pub fn lex_ranges(query: &str) -> Result<Vec<(Token, Range<usize>)>, ParserError> {
     let buf = &mut LexBuf::new(query);
     let mut tokens = vec![];
     while let Some(ch) = buf.next() {
@@ -139,12 +152,13 @@ pub fn lex(query: &str) -> Result<Vec<(Token, usize)>, ParserError> {
             '+'|'-'|'*'|'/'|'<'|'>'|'='|'~'|'!'|'@'|'#'|'%'|'^'|'&'|'|'|'`'|'?' => lex_op(buf)?,
             _ => bail!(pos, "unexpected character in input: {}", ch),
         };
-        tokens.push((token, pos))
+        tokens.push((token, pos..buf.pos()))
     }
 
     #[cfg(debug_assertions)]
-    for (_token, pos) in &tokens {
-        assert!(query.is_char_boundary(*pos));
+    for (_token, range) in &tokens {
+        assert!(query.is_char_boundary(range.start));
+        assert!(query.is_char_boundary(range.end));
     }
 
     Ok(tokens)
@@ -37,6 +37,7 @@
 
 pub mod ast;
 pub mod parser;
+pub mod redact;
 
 mod keywords;
 mod lexer;
@@ -0,0 +1,226 @@
+// Copyright Materialize, Inc. and contributors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License in the LICENSE file at the
+// root of this repository, or online at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Redaction of SQL text.
+//!
+//! SQL statements frequently contain sensitive data in their literals. Before
+//! SQL text is written anywhere other than back to the client that sent it,
+//! like to a log, it is redacted according to a [`RedactionPolicy`].
+//!
+//! Redaction operates on the output of the lexer rather than on the raw text,
+//! so that literals are identified exactly as they would be by the parser,
+//! even in the presence of escaped quotes, dollar-quoted strings, and
+//! comments. Because the statement need not parse, even statements that
+//! contain syntax errors can be redacted.
+//!
+//! Separately, [`redact_credentials`] removes the credentials from a parsed
+//! statement while leaving the rest of the statement, including its literals,
+//! intact.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::visit_mut::{self, VisitMut};
+use crate::ast::{AstInfo, Connector, SqlOption, Statement, Value};
+use crate::lexer::{self, Token};
+
+/// The text that replaces each redacted literal.
+pub const REDACTED_LITERAL: &str = "'<redacted>'";
+
+/// The text that replaces redacted SQL text in its entirety.
+pub const REDACTED_SQL: &str = "<redacted>";
+
+/// The text that replaces each redacted credential.
+pub const REDACTED_CREDENTIAL: &str = "<redacted>";
+
+/// The names of the options whose values are credentials.
+const CREDENTIAL_OPTIONS: &[&str] = &[
+    "password",
+    "sasl_password",
+    "secret_access_key",
+    "ssl_key_password",
+    "token",
+];
+
+/// Describes how to redact SQL text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionPolicy {
+    /// The SQL text is not redacted.
+    None,
+    /// Each literal in the SQL text is replaced with a placeholder, leaving
+    /// the structure of the text intact. Comments are removed.
+    Literals,
+    /// The SQL text is replaced in its entirety with a placeholder.
+    Full,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> RedactionPolicy {
+        RedactionPolicy::Literals
+    }
+}
+
+impl RedactionPolicy {
+    /// Redacts `sql` according to this policy.
+    ///
+    /// If the policy calls for redacting literals but `sql` cannot be lexed,
+    /// `sql` is redacted in its entirety, as there is no way to reliably
+    /// locate its literals.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sql_parser::redact::RedactionPolicy;
+    ///
+    /// let sql = "SELECT a FROM t WHERE b = 'it''s' -- secret\n AND c > 1";
+    /// assert_eq!(
+    ///     RedactionPolicy::Literals.redact(sql),
+    ///     "SELECT a FROM t WHERE b = '<redacted>' AND c > '<redacted>'",
+    /// );
+    /// assert_eq!(RedactionPolicy::Full.redact(sql), "<redacted>");
+    /// ```
+    pub fn redact(&self, sql: &str) -> String {
+        match self {
+            RedactionPolicy::None => sql.into(),
+            RedactionPolicy::Literals => {
+                redact_literals(sql).unwrap_or_else(|| REDACTED_SQL.into())
+            }
+            RedactionPolicy::Full => REDACTED_SQL.into(),
+        }
+    }
+
+    /// Returns the name of the policy, as accepted by its [`FromStr`]
+    /// implementation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedactionPolicy::None => "none",
+            RedactionPolicy::Literals => "literals",
+            RedactionPolicy::Full => "full",
+        }
+    }
+}
+
+impl fmt::Display for RedactionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RedactionPolicy {
+    type Err = ParseRedactionPolicyError;
+
+    fn from_str(s: &str) -> Result<RedactionPolicy, ParseRedactionPolicyError> {
+        match s {
+            "none" => Ok(RedactionPolicy::None),
+            "literals" => Ok(RedactionPolicy::Literals),
+            "full" => Ok(RedactionPolicy::Full),
+            _ => Err(ParseRedactionPolicyError),
+        }
+    }
+}
+
+/// The error returned when parsing a [`RedactionPolicy`] fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseRedactionPolicyError;
+
+impl fmt::Display for ParseRedactionPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("redaction policy must be one of \"none\", \"literals\", or \"full\"")
+    }
+}
+
+impl Error for ParseRedactionPolicyError {}
+
+/// Replaces the credentials in `stmt` with [`REDACTED_CREDENTIAL`].
+///
+/// Credentials are the values of options like `sasl_password` and
+/// `secret_access_key`, and the connection strings of PostgreSQL sources,
+/// which may embed a password.
+///
+/// # Examples
+///
+/// ```
+/// use sql_parser::parser::parse_statements;
+/// use sql_parser::redact::redact_credentials;
+///
+/// let mut stmt = parse_statements(
+///     "CREATE SOURCE s FROM KAFKA BROKER 'b' TOPIC 't' \
+///      WITH (sasl_password = 'hunter2', group_id_prefix = 'g') FORMAT BYTES",
+/// )
+/// .unwrap()
+/// .remove(0);
+/// redact_credentials(&mut stmt);
+/// assert_eq!(
+///     stmt.to_string(),
+///     "CREATE SOURCE s FROM KAFKA BROKER 'b' TOPIC 't' \
+///      WITH (sasl_password = '<redacted>', group_id_prefix = 'g') FORMAT BYTES",
+/// );
+/// ```
+pub fn redact_credentials<T: AstInfo>(stmt: &mut Statement<T>) {
+    CredentialRedactor.visit_statement_mut(stmt);
+}
+
+struct CredentialRedactor;
+
+impl<'ast, T: AstInfo> VisitMut<'ast, T> for CredentialRedactor {
+    fn visit_sql_option_mut(&mut self, option: &'ast mut SqlOption<T>) {
+        match option {
+            SqlOption::Value { name, value } if CREDENTIAL_OPTIONS.contains(&name.as_str()) => {
+                *value = Value::String(REDACTED_CREDENTIAL.into());
+            }
+            _ => visit_mut::visit_sql_option_mut(self, option),
+        }
+    }
+
+    fn visit_connector_mut(&mut self, connector: &'ast mut Connector) {
+        if let Connector::Postgres { conn, .. } = connector {
+            *conn = REDACTED_CREDENTIAL.into();
+        }
+    }
+}
+
+/// Replaces each literal in `sql` with [`REDACTED_LITERAL`].
+///
+/// Tokens other than literals are reproduced exactly, and are separated by a
+/// single space wherever the original text separated them with whitespace or
+/// comments. Returns `None` if `sql` cannot be lexed.
+fn redact_literals(sql: &str) -> Option<String> {
+    let tokens = lexer::lex_ranges(sql).ok()?;
+    let mut out = String::with_capacity(sql.len());
+    let mut prev_end = None;
+    for (token, range) in tokens {
+        let text = &sql[range.clone()];
+        if let Some(prev_end) = prev_end {
+            if prev_end < range.start {
+                out.push(' ');
+            }
+        }
+        match token {
+            Token::String(_) | Token::HexString(_) | Token::Number(_) => {
+                out.push_str(REDACTED_LITERAL);
+                // A string literal may have consumed trailing whitespace,
+                // which separates it from the next token.
+                let trimmed = text.trim_end();
+                prev_end = Some(range.start + trimmed.len());
+            }
+            _ => {
+                out.push_str(text);
+                prev_end = Some(range.end);
+            }
+        }
+    }
+    Some(out)
+}
@@ -29,6 +29,7 @@ use sql_parser::ast::visit::Visit;
 use sql_parser::ast::visit_mut::{self, VisitMut};
 use sql_parser::ast::{AstInfo, Expr, Ident, Raw, RawName};
 use sql_parser::parser::{self, ParserError};
+use sql_parser::redact::RedactionPolicy;
 
 #[test]
 fn datadriven() {
@@ -87,11 +88,17 @@ fn datadriven() {
         }
     }
 
+    fn redact(tc: &TestCase) -> String {
+        let input = tc.input.strip_suffix('\n').unwrap_or(&tc.input);
+        format!("{}\n", RedactionPolicy::Literals.redact(input))
+    }
+
     walk("tests/testdata", |f| {
         f.run(|test_case| -> String {
             match test_case.directive.as_str() {
                 "parse-statement" => parse_statement(test_case),
                 "parse-scalar" => parse_scalar(test_case),
+                "redact" => redact(test_case),
                 dir => panic!("unhandled directive {}", dir),
             }
         })
@@ -12,6 +12,7 @@
 //! Catalog abstraction layer.
 
 use std::fmt;
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 use std::{error::Error, unimplemented};
 
@@ -185,6 +186,9 @@ pub struct CatalogConfig {
     /// Function that returns a wall clock now time; can safely be mocked to return
     /// 0.
     pub now: NowFn,
+    /// The local address to which outbound connections to external systems
+    /// are bound, if any.
+    pub outbound_bind_addr: Option<IpAddr>,
 }
 
 /// A database in a [`Catalog`].
@@ -372,6 +376,7 @@ lazy_static! {
         num_workers: 0,
         timestamp_frequency: Duration::from_secs(1),
         now: now_zero,
+        outbound_bind_addr: None,
     };
 }
 
@@ -400,6 +400,14 @@ macro_rules! with_option_type {
             _ => ::anyhow::bail!("expected bool"),
         }
     };
+    ($name:ident, usize) => {
+        match $name {
+            Some(crate::ast::WithOptionValue::Value(crate::ast::Value::Number(value))) => {
+                value.parse::<usize>()?
+            }
+            _ => ::anyhow::bail!("expected usize"),
+        }
+    };
     ($name:ident, Interval) => {
         match $name {
             Some(crate::ast::WithOptionValue::Value(Value::String(value))) => {
@@ -428,6 +436,7 @@ macro_rules! with_option_type {
 ///   (`WITH (name = text)`).
 /// - `bool`: expects either a SQL bool (`WITH (name = true)`) or a valueless
 ///   option which will be interpreted as true: (`WITH (name)`.
+/// - `usize`: expects a non-negative SQL integer (`WITH (name = 42)`).
 /// - `Interval`: expects either a SQL interval or string that can be parsed as
 ///   an interval.
 macro_rules! with_options {
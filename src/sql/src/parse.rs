@@ -10,3 +10,7 @@
 //! SQL parsing.
 
 pub use sql_parser::parser::parse_statements as parse;
+pub use sql_parser::redact::{
+    redact_credentials, ParseRedactionPolicyError, RedactionPolicy, REDACTED_CREDENTIAL,
+    REDACTED_LITERAL, REDACTED_SQL,
+};
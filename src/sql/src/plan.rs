@@ -33,7 +33,7 @@ use enum_kinds::EnumKind;
 use serde::{Deserialize, Serialize};
 
 use ::expr::{GlobalId, RowSetFinishing};
-use dataflow_types::{SinkConnectorBuilder, SinkEnvelope, SourceConnector};
+use dataflow_types::{SinkConnectorBuilder, SinkEnvelope, SourceConnector, TailBufferPolicy};
 use repr::{ColumnName, Diff, RelationDesc, Row, ScalarType, Timestamp};
 
 use crate::ast::{ExplainOptions, ExplainStage, Expr, FetchDirection, ObjectType, Raw, Statement};
@@ -228,6 +228,8 @@ pub struct TailPlan {
     pub emit_progress: bool,
     pub object_columns: usize,
     pub desc: RelationDesc,
+    pub buffer_size: Option<usize>,
+    pub buffer_policy: Option<TailBufferPolicy>,
 }
 
 #[derive(Debug)]
@@ -16,6 +16,7 @@ use std::convert::TryFrom;
 
 use anyhow::bail;
 
+use dataflow_types::TailBufferPolicy;
 use expr::MirRelationExpr;
 use ore::collections::CollectionExt;
 use repr::{RelationDesc, ScalarType};
@@ -246,6 +247,8 @@ with_options! {
     struct TailOptions {
         snapshot: bool,
         progress: bool,
+        buffer_size: usize,
+        buffer_policy: String,
      }
 }
 
@@ -287,6 +290,10 @@ pub fn plan_tail(
     let ts = as_of.map(|e| query::eval_as_of(scx, e)).transpose()?;
     let options = TailOptions::try_from(options)?;
     let desc = entry.desc()?.clone();
+    let buffer_policy = options
+        .buffer_policy
+        .map(|policy| policy.parse::<TailBufferPolicy>())
+        .transpose()?;
 
     match entry.item_type() {
         CatalogItemType::Table | CatalogItemType::Source | CatalogItemType::View => {
@@ -298,6 +305,8 @@ pub fn plan_tail(
                 emit_progress: options.progress.unwrap_or(false),
                 object_columns: entry.desc()?.arity(),
                 desc,
+                buffer_size: options.buffer_size,
+                buffer_policy,
             }))
         }
         CatalogItemType::Func
@@ -71,6 +71,7 @@ pub fn purify(
     };
 
     let now = catalog.now();
+    let bind_addr = catalog.config().outbound_bind_addr;
 
     async move {
         if let Statement::CreateSource(CreateSourceStatement {
@@ -185,7 +186,7 @@ pub fn purify(
                     // verify that we can connect upstream
                     // TODO(petrosagg): store this info along with the source for better error
                     // detection
-                    let _ = postgres_util::publication_info(&conn, &publication).await?;
+                    let _ = postgres_util::publication_info(&conn, &publication, bind_addr).await?;
                 }
                 Connector::PubNub { .. } => (),
             }
@@ -216,7 +217,8 @@ pub fn purify(
                             }),
                         ..
                     } => {
-                        let pub_info = postgres_util::publication_info(&conn, &publication).await?;
+                        let pub_info =
+                            postgres_util::publication_info(&conn, &publication, bind_addr).await?;
 
                         // If the user didn't specify targets we'll generate views for all of them
                         let targets = targets.clone().unwrap_or_else(|| {
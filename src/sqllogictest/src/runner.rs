@@ -25,7 +25,7 @@
 //!       compare to expected results
 //!       if wrong, record the error
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt;
 use std::fs::{File, OpenOptions};
@@ -531,19 +531,97 @@ impl Runner {
     pub async fn start(config: &RunConfig<'_>) -> Result<Self, anyhow::Error> {
         let temp_dir = tempfile::tempdir()?;
         let mz_config = materialized::Config {
+            manage_logging: false,
+            log: materialized::LogConfig::default(),
+            slow_query_threshold: None,
+            slow_query_log_file: None,
+            long_running_tail_threshold: None,
+            statement_label_keys: vec![],
+            statement_timeout: None,
+            idle_in_transaction_session_timeout: None,
+            lock_timeouts: false,
+            max_result_rows: None,
+            max_result_bytes: None,
+            tail_buffer_size: 64 << 20,
+            tail_buffer_policy: materialized::TailBufferPolicy::Block,
+            user_stats_max_users: materialized::DEFAULT_MAX_TRACKED_USERS,
+            session_quotas: materialized::SessionQuotas::default(),
+            authz: None,
+            crash_reports: None,
+            diagnostics_exclude_sql: false,
             logging: None,
             timestamp_frequency: Duration::from_secs(1),
+            clock: materialized::ClockConfig::default(),
+            allocator: None,
+            memory_watchdog: None,
+            dataflow_memory_budget: None,
+            dataflow_memory_budget_enforce: true,
+            flight_recorder: None,
+            memory_trim_interval: None,
+            oom_score_adj: None,
             logical_compaction_window: None,
             workers: config.workers,
             timely_worker: timely::WorkerConfig::default(),
+            worker_affinity: None,
+            worker_stack_size: None,
             data_directory: temp_dir.path().to_path_buf(),
+            catalog_directory: None,
+            storage_directory: None,
+            scratch_directory: None,
+            restore: None,
+            from_replica: None,
+            allow_catalog_migration: false,
+            durability: materialized::DurabilityLevel::None,
+            catalog_compact_interval: None,
+            orphan_scan_interval: None,
+            cleanup_orphans: false,
+            orphan_retention: Duration::default(),
+            data_encryption: None,
+            catalog_replica: None,
             symbiosis_url: Some("postgres://".into()),
             listen_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            takeover_from: None,
+            outbound_bind_addr: None,
+            protocol_detect_timeout: materialized::DEFAULT_PROTOCOL_DETECT_TIMEOUT,
+            protocol_policy: materialized::ProtocolPolicy::Multiplexed,
+            connection_rate_limit: None,
+            pgwire_buffer_size: None,
+            pgwire_read_buffer_size: None,
+            pgwire_buffer_shrink_after: None,
+            pgwire_server_version: None,
+            pgwire_max_message_size: pgwire::DEFAULT_MAX_MESSAGE_SIZE,
+            max_connections: None,
+            reserved_superuser_connections: materialized::DEFAULT_RESERVED_SUPERUSER_CONNECTIONS,
+            superusers: vec![],
+            http_max_concurrent_requests: None,
+            http_request_queue_size: materialized::DEFAULT_HTTP_REQUEST_QUEUE_SIZE,
+            http_request_queue_timeout: materialized::DEFAULT_HTTP_REQUEST_QUEUE_TIMEOUT,
+            http_result_memory_limit: None,
+            http_arrow_max_batch_size: materialized::DEFAULT_HTTP_ARROW_MAX_BATCH_SIZE,
+            http2_cleartext: false,
+            http1_keep_alive_timeout: None,
+            http1_max_requests_per_connection: None,
+            drain_notices: true,
+            connection_notice: None,
+            idle_exit_after: None,
+            coord_liveness: materialized::LivenessConfig::default(),
+            startup_timeout: None,
             tls: None,
+            warn_on_plaintext: false,
             experimental_mode: true,
             safe_mode: false,
+            read_only: false,
+            sticky_maintenance_mode: false,
+            self_test: false,
             telemetry: None,
+            version_check: None,
+            instance_labels: BTreeMap::new(),
+            server_name: None,
+            metrics_max_series: None,
+            metrics_filter: None,
             introspection_frequency: Duration::from_secs(1),
+            host_metrics: false,
+            introspection_metrics: false,
             metrics_registry: MetricsRegistry::new(),
         };
         let server = materialized::serve(mz_config).await?;
@@ -27,6 +27,7 @@
 use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::net::IpAddr;
 use std::rc::Rc;
 
 use anyhow::{anyhow, bail};
@@ -56,7 +57,14 @@ pub struct Postgres {
 }
 
 impl Postgres {
-    pub async fn open_and_erase(url: &str) -> Result<Self, anyhow::Error> {
+    /// Connects to the Postgres server at `url` and erases its contents.
+    ///
+    /// If `bind_addr` is present, the local end of the connection is bound to
+    /// that address.
+    pub async fn open_and_erase(
+        url: &str,
+        bind_addr: Option<IpAddr>,
+    ) -> Result<Self, anyhow::Error> {
         let mut config: tokio_postgres::Config = url.parse()?;
         let username = whoami::username();
         if config.get_user().is_none() {
@@ -75,17 +83,10 @@ impl Postgres {
         if config.get_hosts().is_empty() {
             config.host(env::var("PGHOST").ok().as_deref().unwrap_or("localhost"));
         }
-        let (client, conn) = config
-            .connect(tokio_postgres::NoTls)
+        let client = postgres_util::connect(&config, tokio_postgres::NoTls, bind_addr)
             .await
             .map_err(|err| anyhow!("Postgres connection failed: {}", err))?;
 
-        tokio::spawn(async move {
-            if let Err(e) = conn.await {
-                panic!("connection error: {}", e);
-            }
-        });
-
         // Some postgres servers (or clients?) don't default to UTC, which is the
         // only value materialize supports. Enforce that here because otherwise the
         // sqllogictest results can change when dealing with timestamptz types.